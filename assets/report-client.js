@@ -0,0 +1,288 @@
+// Renders the categories of the report in the browser, from the data file generated along with
+// the page. The category shown is the one in the fragment of the URL.
+
+const MAX_ROWS = 1000;
+
+let report = null;
+let state = {
+    category: null,
+    filter: "",
+    result: "",
+    sort: null,
+    ascending: true,
+};
+
+function element(tag, class_name, text) {
+    let elem = document.createElement(tag);
+    if (class_name) {
+        elem.className = class_name;
+    }
+    if (text !== undefined) {
+        elem.textContent = text;
+    }
+    return elem;
+}
+
+function setup_colors(colors) {
+    let style = element("style");
+    let rules = [];
+    for (let i = 0; i < colors.length; i++) {
+        let color = colors[i];
+        let background;
+        if (color.Single) {
+            background = color.Single;
+        } else {
+            let [a, b] = color.Striped;
+            background = "repeating-linear-gradient(-45deg, " + a + ", " + a + " 15px, "
+                + b + " 15px, " + b + " 30px)";
+        }
+        rules.push(".c" + i + " { background: " + background + "; }");
+    }
+    style.textContent = rules.join("\n");
+    document.head.appendChild(style);
+}
+
+// Flatten the sections of a category into rows, remembering the group of each crate.
+function category_rows(category) {
+    let rows = [];
+    for (let [name, color_idx, crates] of report.categories) {
+        if (name !== category) {
+            continue;
+        }
+        if (crates.Plain) {
+            for (let krate of crates.Plain) {
+                rows.push({ krate: krate, group: null });
+            }
+        } else if (crates.RootResults) {
+            for (let [result, subcrates] of Object.entries(crates.RootResults.results)) {
+                for (let krate of subcrates) {
+                    rows.push({ krate: krate, group: "root: " + result });
+                }
+            }
+        } else if (crates.Tree) {
+            for (let [root, subcrates] of Object.entries(crates.Tree.tree)) {
+                for (let krate of subcrates) {
+                    rows.push({ krate: krate, group: "dependency of " + root });
+                }
+            }
+        }
+    }
+    return rows;
+}
+
+function run_name(krate, idx) {
+    let run = krate.runs[idx];
+    return run ? report.result_names[run.name_idx] : krate.res;
+}
+
+function triage_text(krate) {
+    if (!krate.triage) {
+        return "";
+    }
+    let text = krate.triage.status;
+    if (krate.triage.issue) {
+        text += ", " + krate.triage.issue;
+    }
+    return text;
+}
+
+const SORT_KEYS = {
+    crate: row => row.krate.name,
+    previous: row => run_name(row.krate, 0),
+    current: row => run_name(row.krate, 1),
+    group: row => row.group || "",
+    triage: row => triage_text(row.krate),
+};
+
+function matches(row) {
+    let krate = row.krate;
+    if (state.result && run_name(krate, 0) !== state.result && run_name(krate, 1) !== state.result) {
+        return false;
+    }
+    if (!state.filter) {
+        return true;
+    }
+    let haystack = [
+        krate.name, run_name(krate, 0), run_name(krate, 1), row.group || "", triage_text(krate),
+        krate.triage && krate.triage.note ? krate.triage.note : "",
+    ].join(" ").toLowerCase();
+    return haystack.includes(state.filter);
+}
+
+function render_run(krate, idx) {
+    let span = element("span", "run");
+    let run = krate.runs[idx];
+    if (run) {
+        span.appendChild(element("b", "c" + run.color_idx));
+        let link = element("a", null, report.result_names[run.name_idx]);
+        link.href = run.log + "/" + report.log_page;
+        span.appendChild(link);
+    } else {
+        span.appendChild(element("b", "c" + krate.color_idx));
+        span.appendChild(document.createTextNode(krate.res));
+    }
+    return span;
+}
+
+function render_row(row, grouped) {
+    let krate = row.krate;
+    let div = element("div", "crate");
+    let link = element("a", null, krate.name + (krate.status ? " (" + krate.status + ")" : ""));
+    link.href = krate.url;
+    link.target = "_blank";
+    link.rel = "noopener";
+    div.appendChild(link);
+    div.appendChild(render_run(krate, 0));
+    div.appendChild(render_run(krate, 1));
+    if (grouped) {
+        div.appendChild(element("span", "group", row.group || ""));
+    }
+    let triage = element("span", "triage", krate.triage ? "triaged: " + triage_text(krate) : "");
+    if (krate.triage) {
+        triage.title = (krate.triage.note || "") + " (" + krate.triage.author + ")";
+    }
+    div.appendChild(triage);
+    return div;
+}
+
+function render_header(grouped) {
+    let div = element("div", "crate sortable");
+    let columns = [["crate", "Crate", "title"], ["previous", "Previous run", "run"],
+                   ["current", "Current run", "run"]];
+    if (grouped) {
+        columns.push(["group", "Group", "group"]);
+    }
+    columns.push(["triage", "Triage", "triage"]);
+    for (let [key, label, class_name] of columns) {
+        let arrow = state.sort === key ? (state.ascending ? " ▴" : " ▾") : "";
+        let span = element("span", class_name, label + arrow);
+        span.addEventListener("click", function() {
+            if (state.sort === key) {
+                state.ascending = !state.ascending;
+            } else {
+                state.sort = key;
+                state.ascending = true;
+            }
+            render();
+        });
+        div.appendChild(span);
+    }
+    return div;
+}
+
+function render_tabs() {
+    let tabs = document.getElementById("client-tabs");
+    tabs.textContent = "";
+    for (let [name, color_idx] of report.categories) {
+        if (tabs.querySelector("[data-category=\"" + name + "\"]")) {
+            continue;
+        }
+        let link = element("a", "header c" + color_idx + " link", name + " (" + report.info[name] + ")");
+        link.href = "#" + name;
+        link.dataset.category = name;
+        if (name === state.category) {
+            link.classList.add("selected");
+        }
+        tabs.appendChild(link);
+    }
+}
+
+function render() {
+    render_tabs();
+
+    let container = document.getElementById("client-results");
+    container.textContent = "";
+    if (!state.category) {
+        container.appendChild(element("div", "nothing", "Select a category to show its crates."));
+        return;
+    }
+
+    let rows = category_rows(state.category);
+    let grouped = rows.some(row => row.group);
+    let filtered = rows.filter(matches);
+    if (state.sort) {
+        let key = SORT_KEYS[state.sort];
+        let direction = state.ascending ? 1 : -1;
+        filtered.sort((a, b) => direction * key(a).localeCompare(key(b)));
+    }
+
+    let crates = element("div", "crates");
+    crates.appendChild(render_header(grouped));
+    for (let row of filtered.slice(0, MAX_ROWS)) {
+        crates.appendChild(render_row(row, grouped));
+    }
+    container.appendChild(crates);
+
+    let summary = filtered.length + " of " + rows.length + " crates";
+    if (filtered.length > MAX_ROWS) {
+        summary += ", only the first " + MAX_ROWS + " are shown: refine the filters to see the others";
+    }
+    container.appendChild(element("div", "nothing", summary));
+
+    let backports = report.backports;
+    if (state.category === "regressed" && Object.keys(backports).length > 0) {
+        let div = element("div", "backports");
+        for (let [result, prs] of Object.entries(backports)) {
+            let line = element("div", null, result + " likely caused by: ");
+            for (let pr of prs) {
+                let link = element("a", null, "#" + pr.number);
+                link.href = pr.url;
+                link.title = pr.title;
+                line.appendChild(link);
+                line.appendChild(document.createTextNode(" "));
+            }
+            div.appendChild(line);
+        }
+        container.appendChild(div);
+    }
+}
+
+function setup_filters() {
+    let text = document.getElementById("filter-text");
+    text.addEventListener("input", function() {
+        state.filter = text.value.trim().toLowerCase();
+        render();
+    });
+
+    let result = document.getElementById("filter-result");
+    for (let name of report.result_names) {
+        let option = element("option", null, name);
+        option.value = name;
+        result.appendChild(option);
+    }
+    result.addEventListener("change", function() {
+        state.result = result.value;
+        render();
+    });
+}
+
+function select_category() {
+    let category = decodeURIComponent(window.location.hash.substring(1));
+    state.category = category in report.info ? category : null;
+    render();
+}
+
+function load_report() {
+    let root = document.getElementById("client-report");
+    fetch(root.dataset.src)
+        .then(response => {
+            if (!response.ok) {
+                throw new Error(response.status + " " + response.statusText);
+            }
+            return response.json();
+        })
+        .then(data => {
+            report = data;
+            setup_colors(report.colors);
+            setup_filters();
+            window.addEventListener("hashchange", select_category);
+            select_category();
+        })
+        .catch(err => {
+            let container = document.getElementById("client-results");
+            container.textContent = "";
+            container.appendChild(element("div", "nothing", "Failed to load the results: " + err));
+        });
+}
+
+load_report();
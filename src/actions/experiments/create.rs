@@ -1,6 +1,17 @@
-use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::actions::{
+    experiments::{
+        insert_crates, replace_exclusions, skip_and_pin, validate_build_cache, validate_build_std,
+        validate_patches, validate_requirement, validate_tags, validate_target_mode,
+        validate_test_options, ExperimentError, MAX_REPEAT,
+    },
+    Action, ActionsCtx,
+};
+use crate::crates::{CratePattern, DependencyPatch, OverlayCrate};
 use crate::db::QueryUtils;
-use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status};
+use crate::experiments::{
+    Assignee, CapLints, ComparisonPolicy, CrateSelect, Deadline, Experiment, FollowUp, GitHubIssue,
+    Mode, Status, TestOptions,
+};
 use crate::prelude::*;
 use crate::toolchain::Toolchain;
 use chrono::Utc;
@@ -16,9 +27,56 @@ pub struct CreateExperiment {
     pub ignore_blacklist: bool,
     pub assign: Option<Assignee>,
     pub requirement: Option<String>,
+    pub follow_up: Option<FollowUp>,
+    pub parent: Option<String>,
+    pub sandbox_image: Option<String>,
+    pub zulip_stream: Option<String>,
+    pub repeat: Option<u32>,
+    pub test_options: TestOptions,
+    pub deadline: Option<Deadline>,
+    pub comparison_policy: ComparisonPolicy,
+    pub registry_overlay: Vec<OverlayCrate>,
+    pub dependency_patches: Vec<DependencyPatch>,
+    pub exclude: Vec<CratePattern>,
+    pub tags: Vec<String>,
+    pub build_cache: bool,
+    pub track_warnings: bool,
+    pub client_side_report: bool,
 }
 
 impl CreateExperiment {
+    /// Definition of a new experiment with the configuration of `ex`, testing the same crates.
+    /// The assignee and the follow-up relationship are specific to `ex`, and aren't copied.
+    pub fn copy_of(ex: &Experiment, name: &str) -> Self {
+        CreateExperiment {
+            name: name.to_string(),
+            toolchains: ex.toolchains.clone(),
+            mode: ex.mode,
+            crates: CrateSelect::SameAs(ex.name.clone()),
+            cap_lints: ex.cap_lints,
+            priority: ex.priority,
+            github_issue: ex.github_issue.clone(),
+            ignore_blacklist: ex.ignore_blacklist,
+            assign: None,
+            requirement: ex.requirement.clone(),
+            follow_up: ex.follow_up.clone(),
+            parent: None,
+            sandbox_image: ex.sandbox_image.clone(),
+            zulip_stream: ex.zulip_stream.clone(),
+            repeat: ex.repeat,
+            test_options: ex.test_options.clone(),
+            deadline: ex.deadline,
+            comparison_policy: ex.comparison_policy,
+            registry_overlay: ex.registry_overlay.clone(),
+            dependency_patches: ex.dependency_patches.clone(),
+            exclude: ex.exclude.clone(),
+            tags: ex.tags.clone(),
+            build_cache: ex.build_cache,
+            track_warnings: ex.track_warnings,
+            client_side_report: ex.client_side_report,
+        }
+    }
+
     #[cfg(test)]
     pub fn dummy(name: &str) -> Self {
         use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
@@ -34,6 +92,21 @@ impl CreateExperiment {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
     }
 }
@@ -50,15 +123,78 @@ impl Action for CreateExperiment {
             return Err(ExperimentError::DuplicateToolchains.into());
         }
 
+        // Ensure only allowed sandbox images are used
+        if let Some(image) = &self.sandbox_image {
+            if !ctx.config.is_sandbox_image_allowed(image) {
+                return Err(ExperimentError::SandboxImageNotAllowed(image.clone()).into());
+            }
+        }
+
+        if let Some(repeat) = self.repeat {
+            if repeat == 0 || repeat > MAX_REPEAT {
+                return Err(ExperimentError::InvalidRepeat(repeat).into());
+            }
+        }
+        if let Some(requirement) = &self.requirement {
+            validate_requirement(requirement)?;
+        }
+        validate_test_options(&self.test_options)?;
+        validate_target_mode(&self.toolchains, self.mode)?;
+        validate_build_std(&self.toolchains)?;
+        validate_build_cache(ctx.config, self.build_cache)?;
+        validate_patches(&self.dependency_patches, &self.registry_overlay)?;
+        validate_tags(&self.tags)?;
+
+        // Copies of experiments selecting exact versions select them too
+        let exact_versions = match &self.crates {
+            CrateSelect::Versions(_) => true,
+            CrateSelect::SameAs(name) => {
+                Experiment::get(ctx.db, name)?.is_some_and(|ex| ex.exact_versions)
+            }
+            _ => false,
+        };
         let crates = crate::crates::lists::get_crates(&self.crates, ctx.db, ctx.config)?;
+        let (crates, excluded) =
+            crate::crates::filter_crates(crates, &self.crates, &self.exclude, ctx.config)?;
+        // Make both toolchains test the same commit of the repositories
+        let crates = skip_and_pin(ctx, crates, self.ignore_blacklist)?;
+        let test_options = if self.test_options.is_default() {
+            None
+        } else {
+            Some(serde_json::to_string(&self.test_options)?)
+        };
+        let registry_overlay = if self.registry_overlay.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&self.registry_overlay)?)
+        };
+        let dependency_patches = if self.dependency_patches.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&self.dependency_patches)?)
+        };
+        let exclude = if self.exclude.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&self.exclude)?)
+        };
+        let tags = if self.tags.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&self.tags)?)
+        };
 
         ctx.db.transaction(true, |transaction| {
             transaction.execute(
                 "INSERT INTO experiments \
                  (name, mode, cap_lints, toolchain_start, toolchain_end, priority, created_at, \
                  status, github_issue, github_issue_url, github_issue_number, ignore_blacklist, \
-                 assigned_to, requirement) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14);",
+                 assigned_to, requirement, follow_up, parent, sandbox_image, zulip_stream, \
+                 repeat, test_options, deadline, comparison_policy, registry_overlay, \
+                 build_cache, dependency_patches, track_warnings, exclude, tags, \
+                 client_side_report, exact_versions) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, \
+                 ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30);",
                 &[
                     &self.name,
                     &self.mode.to_str(),
@@ -74,16 +210,27 @@ impl Action for CreateExperiment {
                     &self.ignore_blacklist,
                     &self.assign.map(|a| a.to_string()),
                     &self.requirement,
+                    &self.follow_up.as_ref().map(|f| f.to_string()),
+                    &self.parent,
+                    &self.sandbox_image,
+                    &self.zulip_stream,
+                    &self.repeat,
+                    &test_options,
+                    &self.deadline.map(|d| d.to_string()),
+                    &self.comparison_policy.to_str(),
+                    &registry_overlay,
+                    &self.build_cache,
+                    &dependency_patches,
+                    &self.track_warnings,
+                    &exclude,
+                    &tags,
+                    &self.client_side_report,
+                    &exact_versions,
                 ],
             )?;
 
-            for krate in &crates {
-                let skipped = !self.ignore_blacklist && ctx.config.should_skip(krate);
-                transaction.execute(
-                    "INSERT INTO experiment_crates (experiment, crate, skipped, status) VALUES (?1, ?2, ?3, ?4);",
-                    &[&self.name, &krate.id(), &skipped, &Status::Queued.to_string()],
-                )?;
-            }
+            insert_crates(transaction, &self.name, &crates)?;
+            replace_exclusions(transaction, &self.name, &excluded)?;
 
             Ok(())
         })?;
@@ -95,14 +242,17 @@ impl Action for CreateExperiment {
 #[cfg(test)]
 mod tests {
     use super::CreateExperiment;
-    use crate::actions::{Action, ActionsCtx, ExperimentError};
-    use crate::config::{Config, CrateConfig};
-    use crate::crates::Crate;
+    use crate::actions::{Action, ActionsCtx, ExperimentError, MAX_REPEAT};
+    use crate::agent::RequirementParseError;
+    use crate::config::{BuildCacheConfig, Config, CrateConfig};
+    use crate::crates::{Crate, OverlayCrate};
     use crate::db::{Database, QueryUtils};
     use crate::experiments::{
-        Assignee, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status,
+        Assignee, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status, TestOptions,
     };
-    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::results::{DatabaseDB, ReadResults};
+    use crate::toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::utils::size::Size;
 
     #[test]
     fn test_creation() {
@@ -130,6 +280,26 @@ mod tests {
             ignore_blacklist: true,
             assign: None,
             requirement: Some("linux".to_string()),
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::Strict,
+            registry_overlay: vec![OverlayCrate {
+                name: "serde".into(),
+                version: "1.0.999".into(),
+                url: "https://example.com/serde-1.0.999.crate".into(),
+                checksum: "0".repeat(64),
+            }],
+            dependency_patches: vec!["syn=https://github.com/dtolnay/syn=fix".parse().unwrap()],
+            exclude: vec!["*-sys".parse().unwrap()],
+            tags: vec!["beta-1.79".into()],
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
         .apply(&ctx)
         .unwrap();
@@ -157,6 +327,14 @@ mod tests {
         assert!(ex.assigned_to.is_none());
         assert!(ex.ignore_blacklist);
         assert_eq!(ex.requirement, Some("linux".to_string()));
+        assert_eq!(ex.comparison_policy, ComparisonPolicy::Strict);
+        assert_eq!(ex.registry_overlay[0].version, "1.0.999");
+        assert_eq!(
+            ex.dependency_patches[0].to_string(),
+            "syn=https://github.com/dtolnay/syn=fix"
+        );
+        assert_eq!(ex.exclude[0].to_string(), "*-sys");
+        assert_eq!(ex.tags, ["beta-1.79"]);
     }
 
     #[test]
@@ -210,6 +388,8 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                env: Default::default(),
+                result_override: None,
             },
         );
         let ctx = ActionsCtx::new(&db, &config);
@@ -253,6 +433,21 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
         .apply(&ctx)
         .unwrap_err();
@@ -263,6 +458,347 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sandbox_image_allowlist() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        config.sandbox.allowed_images = vec!["rustops/crates-build-env:extra".into()];
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            sandbox_image: Some("rustops/crates-build-env:extra".into()),
+            zulip_stream: None,
+            repeat: None,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(
+            ex.sandbox_image.as_deref(),
+            Some("rustops/crates-build-env:extra")
+        );
+
+        let err = CreateExperiment {
+            sandbox_image: Some("evil/image".into()),
+            zulip_stream: None,
+            repeat: None,
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::SandboxImageNotAllowed(
+                "evil/image".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_repeat_bounds() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            repeat: Some(5),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.repeat, Some(5));
+
+        for repeat in [0, MAX_REPEAT + 1] {
+            let err = CreateExperiment {
+                repeat: Some(repeat),
+                ..CreateExperiment::dummy("bar")
+            }
+            .apply(&ctx)
+            .unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&ExperimentError::InvalidRepeat(repeat))
+            );
+        }
+    }
+
+    #[test]
+    fn test_test_options() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let options = TestOptions {
+            include_ignored: true,
+            test_threads: Some(1),
+            filters: vec!["net::".into()],
+        };
+        CreateExperiment {
+            test_options: options.clone(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.test_options, options);
+
+        let err = CreateExperiment {
+            test_options: TestOptions {
+                test_threads: Some(0),
+                ..TestOptions::default()
+            },
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidTestThreads)
+        );
+
+        let err = CreateExperiment {
+            test_options: TestOptions {
+                filters: vec!["--nocapture".into()],
+                ..TestOptions::default()
+            },
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidTestFilter("--nocapture".into()))
+        );
+    }
+
+    #[test]
+    fn test_requirement() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            requirement: Some("linux && (big-hard-drive || !docker)".into()),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let err = CreateExperiment {
+            requirement: Some("linux &&".into()),
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidRequirement(
+                "linux &&".into(),
+                RequirementParseError::ExpectedCapability(8)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_cross_target() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let wasm = Toolchain {
+            target: Some("wasm32-unknown-unknown".into()),
+            ..TEST_TOOLCHAIN.clone()
+        };
+
+        // The binaries of the target can't be executed by the agents
+        let err = CreateExperiment {
+            toolchains: [MAIN_TOOLCHAIN.clone(), wasm.clone()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::TestsNotSupportedOnTarget(
+                wasm.to_string()
+            ))
+        );
+
+        CreateExperiment {
+            toolchains: [MAIN_TOOLCHAIN.clone(), wasm],
+            mode: Mode::CheckOnly,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        // Binaries built for other Linux x86 targets can still be executed
+        CreateExperiment {
+            toolchains: [
+                MAIN_TOOLCHAIN.clone(),
+                Toolchain {
+                    target: Some("i686-unknown-linux-musl".into()),
+                    ..TEST_TOOLCHAIN.clone()
+                },
+            ],
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap();
+    }
+
+    #[test]
+    fn test_build_std() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // The standard library can only be built by nightly toolchains
+        let beta: Toolchain = "beta+build-std=std".parse().unwrap();
+        let err = CreateExperiment {
+            toolchains: [MAIN_TOOLCHAIN.clone(), beta.clone()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::BuildStdRequiresNightly(beta.to_string()))
+        );
+
+        CreateExperiment {
+            toolchains: [
+                "nightly".parse().unwrap(),
+                "nightly+build-std=std".parse().unwrap(),
+            ],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+    }
+
+    #[test]
+    fn test_build_cache() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // The cache can't be used without being configured
+        let err = CreateExperiment {
+            build_cache: true,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ActionsCtx::new(&db, &config))
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::BuildCacheNotConfigured)
+        );
+
+        config.sandbox.build_cache = Some(BuildCacheConfig {
+            sccache: "/usr/local/bin/sccache".into(),
+            max_size: Size::Gigabytes(20),
+        });
+        CreateExperiment {
+            build_cache: true,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ActionsCtx::new(&db, &config))
+        .unwrap();
+        assert!(Experiment::get(&db, "foo").unwrap().unwrap().build_cache);
+    }
+
+    #[test]
+    fn test_exclude() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            exclude: vec![
+                "beta-*".parse().unwrap(),
+                "/^build-(fail|pass)$/".parse().unwrap(),
+            ],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        let crates = ex.get_crates(&db).unwrap();
+        assert!(crates.contains(&Crate::Local("test-fail".into())));
+        for excluded in ["beta-fixed", "build-fail", "build-pass"] {
+            assert!(!crates.contains(&Crate::Local(excluded.into())));
+        }
+
+        let excluded = DatabaseDB::new(&db).load_excluded_crates(&ex).unwrap();
+        let fixed = excluded
+            .iter()
+            .find(|e| e.krate == Crate::Local("beta-fixed".into()))
+            .unwrap();
+        assert_eq!(fixed.reason.to_string(), "matching `beta-*`");
+    }
+
+    #[test]
+    fn test_duplicate_patches() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let err = CreateExperiment {
+            registry_overlay: vec![OverlayCrate {
+                name: "serde".into(),
+                version: "1.0.999".into(),
+                url: "https://example.com/serde-1.0.999.crate".into(),
+                checksum: "0".repeat(64),
+            }],
+            dependency_patches: vec!["serde=/opt/serde".parse().unwrap()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::DuplicatePatch("serde".into()))
+        );
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        for tag in ["", "beta 1.79", "beta,nightly"] {
+            let err = CreateExperiment {
+                tags: vec!["lint:unsafe_op_in_unsafe_fn".into(), tag.into()],
+                ..CreateExperiment::dummy("foo")
+            }
+            .apply(&ctx)
+            .unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&ExperimentError::InvalidTag(tag.into()))
+            );
+        }
+    }
+
     #[test]
     fn test_duplicate_name() {
         let db = Database::temp().unwrap();
@@ -283,6 +819,21 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
         .apply(&ctx)
         .unwrap();
@@ -299,6 +850,21 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
         .apply(&ctx)
         .unwrap_err();
@@ -308,4 +874,47 @@ mod tests {
             Some(&ExperimentError::AlreadyExists("foo".into()))
         );
     }
+
+    #[test]
+    fn test_copy_of() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            priority: 5,
+            requirement: Some("linux".into()),
+            exclude: vec!["build-fail".parse().unwrap()],
+            tags: vec!["beta-1.79".into()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let foo = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        CreateExperiment {
+            mode: Mode::CheckOnly,
+            ..CreateExperiment::copy_of(&foo, "bar")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let bar = Experiment::get(&db, "bar").unwrap().unwrap();
+
+        assert_eq!(bar.toolchains, foo.toolchains);
+        assert_eq!(bar.mode, Mode::CheckOnly);
+        assert_eq!(bar.priority, 5);
+        assert_eq!(bar.requirement.as_deref(), Some("linux"));
+        assert_eq!(bar.exclude[0].to_string(), "build-fail");
+        assert_eq!(bar.tags, ["beta-1.79"]);
+        assert_eq!(bar.status, Status::Queued);
+        assert_eq!(bar.get_crates(&db).unwrap(), foo.get_crates(&db).unwrap());
+
+        // The crates can't be copied once the experiment is gone
+        crate::actions::DeleteExperiment { name: "foo".into() }
+            .apply(&ctx)
+            .unwrap();
+        assert!(CreateExperiment::copy_of(&foo, "baz").apply(&ctx).is_err());
+    }
 }
@@ -2,6 +2,8 @@ use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
 use crate::db::QueryUtils;
 use crate::experiments::Experiment;
 use crate::prelude::*;
+use crate::results::delete_logs;
+use crate::server::audit;
 
 pub struct DeleteExperiment {
     pub name: String,
@@ -15,8 +17,19 @@ impl Action for DeleteExperiment {
 
         // This will also delete all the data related to this experiment, thanks to the foreign
         // keys in the SQLite database
-        ctx.db
-            .execute("DELETE FROM experiments WHERE name = ?1;", &[&self.name])?;
+        let log_keys = ctx.db.transaction(true, |t| {
+            let keys = t.query(
+                "SELECT log_key FROM results WHERE experiment = ?1 AND log_key IS NOT NULL;",
+                [&self.name],
+                |row| row.get(0),
+            )?;
+            t.execute("DELETE FROM experiments WHERE name = ?1;", &[&self.name])?;
+            if let Some(author) = ctx.audit_author {
+                audit::record(t, author, "delete-experiment", Some(&self.name), None)?;
+            }
+            Ok(keys)
+        })?;
+        delete_logs(ctx.log_store, &log_keys);
 
         Ok(())
     }
@@ -29,6 +42,7 @@ mod tests {
     use crate::config::Config;
     use crate::db::Database;
     use crate::experiments::Experiment;
+    use crate::server::audit;
 
     #[test]
     fn test_delete_missing_experiment() {
@@ -64,8 +78,13 @@ mod tests {
         DeleteExperiment {
             name: "dummy".to_string(),
         }
-        .apply(&ctx)
+        .apply(&ActionsCtx::new(&db, &config).with_audit("alice"))
         .unwrap();
         assert!(!Experiment::exists(&db, "dummy").unwrap());
+
+        let log = audit::recent(&db, 10).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, "delete-experiment");
+        assert_eq!(log[0].experiment.as_deref(), Some("dummy"));
     }
 }
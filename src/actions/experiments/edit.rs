@@ -1,7 +1,18 @@
-use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::actions::{
+    experiments::{
+        insert_crates, replace_exclusions, skip_and_pin, validate_build_cache, validate_build_std,
+        validate_patches, validate_requirement, validate_tags, validate_target_mode,
+        validate_test_options, ExperimentError, MAX_REPEAT,
+    },
+    Action, ActionsCtx,
+};
+use crate::crates::{CratePattern, DependencyPatch, ExclusionReason};
 use crate::db::QueryUtils;
-use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, Mode, Status};
+use crate::experiments::{
+    Assignee, CapLints, ComparisonPolicy, CrateSelect, Deadline, Experiment, FollowUp, Mode, Status,
+};
 use crate::prelude::*;
+use crate::results::{DatabaseDB, ReadResults};
 use crate::toolchain::Toolchain;
 
 pub struct EditExperiment {
@@ -14,6 +25,21 @@ pub struct EditExperiment {
     pub ignore_blacklist: Option<bool>,
     pub assign: Option<Assignee>,
     pub requirement: Option<String>,
+    pub follow_up: Option<FollowUp>,
+    pub sandbox_image: Option<String>,
+    pub zulip_stream: Option<String>,
+    pub repeat: Option<u32>,
+    pub include_ignored: Option<bool>,
+    pub test_threads: Option<u32>,
+    pub test_filters: Option<Vec<String>>,
+    pub deadline: Option<Deadline>,
+    pub comparison_policy: Option<ComparisonPolicy>,
+    pub build_cache: Option<bool>,
+    pub track_warnings: Option<bool>,
+    pub client_side_report: Option<bool>,
+    pub dependency_patches: Option<Vec<DependencyPatch>>,
+    pub exclude: Option<Vec<CratePattern>>,
+    pub tags: Option<Vec<String>>,
 }
 
 impl EditExperiment {
@@ -29,6 +55,21 @@ impl EditExperiment {
             ignore_blacklist: None,
             assign: None,
             requirement: None,
+            follow_up: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            include_ignored: None,
+            test_threads: None,
+            test_filters: None,
+            deadline: None,
+            comparison_policy: None,
+            build_cache: None,
+            track_warnings: None,
+            client_side_report: None,
+            dependency_patches: None,
+            exclude: None,
+            tags: None,
         }
     }
 }
@@ -40,8 +81,9 @@ impl Action for EditExperiment {
             None => return Err(ExperimentError::NotFound(self.name.clone()).into()),
         };
 
-        // Ensure no change is made to running or complete experiments
-        if ex.status != Status::Queued {
+        // Ensure no change is made to running or complete experiments. Experiments whose
+        // toolchains were not available never ran anything, and are queued again once edited.
+        if ex.status != Status::Queued && ex.status != Status::ToolchainUnavailable {
             return Err(ExperimentError::CanOnlyEditQueuedExperiments.into());
         }
 
@@ -55,6 +97,7 @@ impl Action for EditExperiment {
                     if ex.toolchains[0] == ex.toolchains[1] {
                         return Err(ExperimentError::DuplicateToolchains.into());
                     }
+                    validate_build_std(&ex.toolchains)?;
 
                     let changes = t.execute(
                         &format!("UPDATE experiments SET {col} = ?1 WHERE name = ?2;"),
@@ -75,12 +118,57 @@ impl Action for EditExperiment {
                 ex.ignore_blacklist = ignore_blacklist;
             }
 
+            // Try to update the exclusion patterns
+            // The list of crates will be recalculated afterwards
+            if let Some(exclude) = &self.exclude {
+                let serialized = if exclude.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(exclude)?)
+                };
+                let changes = t.execute(
+                    "UPDATE experiments SET exclude = ?1 WHERE name = ?2;",
+                    &[&serialized, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.exclude = exclude.clone();
+            }
+
             // Try to update the list of crates
             // This is also done if ignore_blacklist is changed to recalculate the skipped crates
-            let new_crates = if let Some(crates) = self.crates {
-                Some(crate::crates::lists::get_crates(
-                    &crates, ctx.db, ctx.config,
-                )?)
+            let new_crates = if let Some(select) = self.crates {
+                let crates = crate::crates::lists::get_crates(&select, ctx.db, ctx.config)?;
+                let (crates, excluded) =
+                    crate::crates::filter_crates(crates, &select, &ex.exclude, ctx.config)?;
+                replace_exclusions(t, &self.name, &excluded)?;
+                Some(crates)
+            } else if self.exclude.is_some() {
+                // The selection of the crates is not stored, so the crates excluded by the
+                // previous patterns are added back before applying the new ones. They were
+                // never checked against the filters of the configuration, unlike the others.
+                let mut re_added = Vec::new();
+                let mut excluded = Vec::new();
+                for krate in DatabaseDB::new(ctx.db).load_excluded_crates(&ex)? {
+                    match krate.reason {
+                        ExclusionReason::Pattern { .. } => re_added.push(krate.krate),
+                        _ => excluded.push(krate),
+                    }
+                }
+                let (mut crates, by_pattern) =
+                    crate::crates::exclude_matching(ex.get_crates(ctx.db)?, &ex.exclude);
+                excluded.extend(by_pattern);
+                // The activity thresholds only apply to the `full-active` selection, which is
+                // not known anymore
+                let (re_added, by_filters) = crate::crates::filter_crates(
+                    re_added,
+                    &CrateSelect::Full,
+                    &ex.exclude,
+                    ctx.config,
+                )?;
+                crates.extend(re_added);
+                excluded.extend(by_filters);
+                replace_exclusions(t, &self.name, &excluded)?;
+                Some(crates)
             } else if self.ignore_blacklist.is_some() {
                 Some(ex.get_crates(ctx.db)?)
             } else {
@@ -93,18 +181,9 @@ impl Action for EditExperiment {
                     "DELETE FROM experiment_crates WHERE experiment = ?1;",
                     &[&self.name],
                 )?;
-                for krate in &crates_vec {
-                    t.execute(
-                        "INSERT INTO experiment_crates (experiment, crate, skipped, status) \
-                         VALUES (?1, ?2, ?3, ?4);",
-                        &[
-                            &self.name,
-                            &krate.id(),
-                            &(!ex.ignore_blacklist && ctx.config.should_skip(krate)),
-                            &Status::Queued.to_string(),
-                        ],
-                    )?;
-                }
+                // Make both toolchains test the same commit of the new repositories
+                let crates = skip_and_pin(ctx, crates_vec, ex.ignore_blacklist)?;
+                insert_crates(t, &self.name, &crates)?;
             }
 
             // Try to update the mode
@@ -116,6 +195,7 @@ impl Action for EditExperiment {
                 assert_eq!(changes, 1);
                 ex.mode = mode;
             }
+            validate_target_mode(&ex.toolchains, ex.mode)?;
 
             // Try to update the cap_lints
             if let Some(cap_lints) = self.cap_lints {
@@ -149,6 +229,7 @@ impl Action for EditExperiment {
 
             // Try to update the requirement
             if let Some(requirement) = self.requirement {
+                validate_requirement(&requirement)?;
                 let changes = t.execute(
                     "UPDATE experiments SET requirement = ?1 WHERE name = ?2;",
                     &[&requirement, &self.name],
@@ -157,6 +238,172 @@ impl Action for EditExperiment {
                 ex.requirement = Some(requirement);
             }
 
+            // Try to update the sandbox image
+            if let Some(image) = self.sandbox_image {
+                if !ctx.config.is_sandbox_image_allowed(&image) {
+                    return Err(ExperimentError::SandboxImageNotAllowed(image).into());
+                }
+
+                let changes = t.execute(
+                    "UPDATE experiments SET sandbox_image = ?1 WHERE name = ?2;",
+                    &[&image, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.sandbox_image = Some(image);
+            }
+
+            // Try to update the Zulip stream
+            if let Some(stream) = self.zulip_stream {
+                let changes = t.execute(
+                    "UPDATE experiments SET zulip_stream = ?1 WHERE name = ?2;",
+                    &[&stream, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.zulip_stream = Some(stream);
+            }
+
+            // Try to update the number of test runs
+            if let Some(repeat) = self.repeat {
+                if repeat == 0 || repeat > MAX_REPEAT {
+                    return Err(ExperimentError::InvalidRepeat(repeat).into());
+                }
+
+                let changes = t.execute(
+                    "UPDATE experiments SET repeat = ?1 WHERE name = ?2;",
+                    &[&repeat, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.repeat = Some(repeat);
+            }
+
+            // Try to update the options of the test harness
+            if self.include_ignored.is_some()
+                || self.test_threads.is_some()
+                || self.test_filters.is_some()
+            {
+                let mut options = ex.test_options.clone();
+                if let Some(include_ignored) = self.include_ignored {
+                    options.include_ignored = include_ignored;
+                }
+                if let Some(threads) = self.test_threads {
+                    options.test_threads = Some(threads);
+                }
+                if let Some(filters) = self.test_filters {
+                    options.filters = filters;
+                }
+                validate_test_options(&options)?;
+
+                let changes = t.execute(
+                    "UPDATE experiments SET test_options = ?1 WHERE name = ?2;",
+                    &[&serde_json::to_string(&options)?, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.test_options = options;
+            }
+
+            // Try to update the deadline
+            if let Some(deadline) = self.deadline {
+                let changes = t.execute(
+                    "UPDATE experiments SET deadline = ?1 WHERE name = ?2;",
+                    &[&deadline.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.deadline = Some(deadline);
+            }
+
+            // Try to update the follow-up
+            if let Some(follow_up) = self.follow_up {
+                let changes = t.execute(
+                    "UPDATE experiments SET follow_up = ?1 WHERE name = ?2;",
+                    &[&follow_up.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.follow_up = Some(follow_up);
+            }
+
+            // Try to update the comparison policy
+            if let Some(policy) = self.comparison_policy {
+                let changes = t.execute(
+                    "UPDATE experiments SET comparison_policy = ?1 WHERE name = ?2;",
+                    &[&policy.to_str(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.comparison_policy = policy;
+            }
+
+            // Try to update the build cache
+            if let Some(build_cache) = self.build_cache {
+                validate_build_cache(ctx.config, build_cache)?;
+
+                let changes = t.execute(
+                    "UPDATE experiments SET build_cache = ?1 WHERE name = ?2;",
+                    &[&build_cache, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.build_cache = build_cache;
+            }
+
+            // Try to update the tracking of the warnings
+            if let Some(track_warnings) = self.track_warnings {
+                let changes = t.execute(
+                    "UPDATE experiments SET track_warnings = ?1 WHERE name = ?2;",
+                    &[&track_warnings, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.track_warnings = track_warnings;
+            }
+
+            // Try to update how the report is rendered
+            if let Some(client_side_report) = self.client_side_report {
+                let changes = t.execute(
+                    "UPDATE experiments SET client_side_report = ?1 WHERE name = ?2;",
+                    &[&client_side_report, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.client_side_report = client_side_report;
+            }
+
+            // Try to update the dependency patches
+            if let Some(patches) = self.dependency_patches.take() {
+                validate_patches(&patches, &ex.registry_overlay)?;
+
+                let serialized = if patches.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&patches)?)
+                };
+                let changes = t.execute(
+                    "UPDATE experiments SET dependency_patches = ?1 WHERE name = ?2;",
+                    &[&serialized, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.dependency_patches = patches;
+            }
+
+            // Try to update the tags
+            if let Some(tags) = self.tags.take() {
+                validate_tags(&tags)?;
+
+                let serialized = if tags.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&tags)?)
+                };
+                let changes = t.execute(
+                    "UPDATE experiments SET tags = ?1 WHERE name = ?2;",
+                    &[&serialized, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.tags = tags;
+            }
+
+            if ex.status == Status::ToolchainUnavailable {
+                t.execute(
+                    "UPDATE experiments SET status = ?1 WHERE name = ?2;",
+                    &[&Status::Queued.to_str(), &self.name],
+                )?;
+            }
+
             Ok(())
         })?;
         Ok(())
@@ -167,11 +414,17 @@ impl Action for EditExperiment {
 mod tests {
     use super::EditExperiment;
     use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
-    use crate::config::{Config, CrateConfig};
+    use crate::agent::RequirementParseError;
+    use crate::config::{BuildCacheConfig, Config, CrateConfig};
     use crate::crates::Crate;
     use crate::db::{Database, QueryUtils};
-    use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, Mode, Status};
+    use crate::experiments::{
+        Assignee, CapLints, ComparisonPolicy, CrateSelect, Experiment, FollowUpKind, Mode, Status,
+        TestOptions,
+    };
+    use crate::results::{DatabaseDB, ReadResults};
     use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::utils::size::Size;
 
     #[test]
     fn test_edit_with_no_changes() {
@@ -188,7 +441,12 @@ mod tests {
     #[test]
     fn test_edit_with_every_change() {
         let db = Database::temp().unwrap();
-        let config = Config::default();
+        let mut config = Config::default();
+        config.sandbox.allowed_images = vec!["rustops/crates-build-env:extra".into()];
+        config.sandbox.build_cache = Some(BuildCacheConfig {
+            sccache: "/usr/local/bin/sccache".into(),
+            max_size: Size::Gigabytes(20),
+        });
         let ctx = ActionsCtx::new(&db, &config);
 
         crate::crates::lists::setup_test_lists(&db, &config).unwrap();
@@ -205,6 +463,21 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: TestOptions::default(),
+            deadline: None,
+            comparison_policy: ComparisonPolicy::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
         }
         .apply(&ctx)
         .unwrap();
@@ -223,6 +496,21 @@ mod tests {
             ignore_blacklist: Some(true),
             assign: Some(Assignee::CLI),
             requirement: Some("windows".to_string()),
+            follow_up: Some("retry-regressed:mode=check-only".parse().unwrap()),
+            sandbox_image: Some("rustops/crates-build-env:extra".into()),
+            zulip_stream: Some("t-infra".into()),
+            repeat: Some(3),
+            include_ignored: Some(true),
+            test_threads: Some(2),
+            test_filters: Some(vec!["parser".into()]),
+            deadline: Some("72h".parse().unwrap()),
+            comparison_policy: Some(ComparisonPolicy::LintExperiment),
+            build_cache: Some(true),
+            track_warnings: Some(true),
+            client_side_report: Some(true),
+            dependency_patches: Some(vec!["serde=/opt/serde".parse().unwrap()]),
+            exclude: Some(vec!["beta-*".parse().unwrap()]),
+            tags: Some(vec!["beta-1.79".into()]),
         }
         .apply(&ctx)
         .unwrap();
@@ -238,6 +526,31 @@ mod tests {
         assert!(ex.ignore_blacklist);
         assert_eq!(ex.assigned_to, Some(Assignee::CLI));
         assert_eq!(ex.requirement, Some("windows".to_string()));
+        let follow_up = ex.follow_up.unwrap();
+        assert_eq!(follow_up.kind, FollowUpKind::RetryRegressed);
+        assert_eq!(follow_up.mode, Some(Mode::CheckOnly));
+        assert_eq!(
+            ex.sandbox_image.as_deref(),
+            Some("rustops/crates-build-env:extra")
+        );
+        assert_eq!(ex.zulip_stream.as_deref(), Some("t-infra"));
+        assert_eq!(ex.repeat, Some(3));
+        assert_eq!(ex.deadline, Some("72h".parse().unwrap()));
+        assert_eq!(ex.comparison_policy, ComparisonPolicy::LintExperiment);
+        assert!(ex.build_cache);
+        assert!(ex.track_warnings);
+        assert!(ex.client_side_report);
+        assert_eq!(ex.dependency_patches[0].name, "serde");
+        assert_eq!(ex.exclude[0].to_string(), "beta-*");
+        assert_eq!(ex.tags, ["beta-1.79"]);
+        assert_eq!(
+            ex.test_options,
+            TestOptions {
+                include_ignored: true,
+                test_threads: Some(2),
+                filters: vec!["parser".into()],
+            }
+        );
 
         assert_eq!(
             ex.get_crates(ctx.db).unwrap(),
@@ -276,6 +589,8 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                env: Default::default(),
+                result_override: None,
             },
         );
         let ctx = ActionsCtx::new(&db, &config);
@@ -331,6 +646,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invalid_requirement() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+
+        let mut edit = EditExperiment::dummy("foo");
+        edit.requirement = Some("linux || (windows".into());
+        let err = edit.apply(&ctx).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidRequirement(
+                "linux || (windows".into(),
+                RequirementParseError::UnclosedParenthesis
+            ))
+        );
+        assert_eq!(
+            Experiment::get(&db, "foo").unwrap().unwrap().requirement,
+            None
+        );
+    }
+
     #[test]
     fn test_editing_missing_experiment() {
         let db = Database::temp().unwrap();
@@ -366,4 +706,64 @@ mod tests {
             Some(&ExperimentError::CanOnlyEditQueuedExperiments)
         );
     }
+
+    #[test]
+    fn test_editing_experiment_with_unavailable_toolchain() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        ex.set_status(&db, Status::Running).unwrap();
+        ex.set_status(&db, Status::ToolchainUnavailable).unwrap();
+
+        // Replacing the toolchain queues the experiment again
+        EditExperiment {
+            toolchains: [None, Some("nightly".parse().unwrap())],
+            ..EditExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert_eq!(ex.toolchains[1], "nightly".parse().unwrap());
+        assert!(ex.completed_at.is_none());
+    }
+
+    #[test]
+    fn test_edit_exclude() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            exclude: vec!["beta-*".parse().unwrap()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        // The crates excluded by the previous patterns are selected again
+        EditExperiment {
+            exclude: Some(vec!["build-*".parse().unwrap()]),
+            ..EditExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        let crates = ex.get_crates(&db).unwrap();
+        assert!(crates.contains(&Crate::Local("beta-fixed".into())));
+        assert!(!crates.contains(&Crate::Local("build-pass".into())));
+        let excluded = DatabaseDB::new(&db).load_excluded_crates(&ex).unwrap();
+        assert_eq!(
+            excluded.iter().map(|e| e.krate.id()).collect::<Vec<_>>(),
+            ["local/build-fail", "local/build-pass"]
+        );
+    }
 }
@@ -1,10 +1,32 @@
 mod create;
 mod delete;
 mod edit;
+mod retry_spurious;
+mod verify;
 
 pub use self::create::CreateExperiment;
 pub use self::delete::DeleteExperiment;
 pub use self::edit::EditExperiment;
+pub use self::retry_spurious::{spurious_crates, RetrySpurious};
+pub use self::verify::{Inconsistency, VerifyExperiment};
+
+use crate::actions::ActionsCtx;
+use crate::agent::{Requirement, RequirementParseError};
+use crate::config::Config;
+use crate::crates::{Crate, DependencyPatch, ExcludedCrate, OverlayCrate};
+use crate::db::{QueryUtils, TransactionHandle};
+use crate::experiments::{Mode, Status, TestOptions};
+use crate::prelude::*;
+use crate::toolchain::Toolchain;
+use rusqlite::types::ToSql;
+use std::fmt::Write;
+
+/// Maximum number of times the test suite of each crate can be run by an experiment.
+pub const MAX_REPEAT: u32 = 10;
+
+/// Number of crates inserted by each statement, keeping the number of variables of the statements
+/// below 999, the limit of the SQLite versions older than 3.32.
+const CRATES_PER_INSERT: usize = 450;
 
 #[derive(Debug, thiserror::Error)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
@@ -17,4 +39,221 @@ pub enum ExperimentError {
     DuplicateToolchains,
     #[error("it's only possible to edit queued experiments")]
     CanOnlyEditQueuedExperiments,
+    #[error("it's only possible to retry the crates of experiments not completed yet")]
+    CanOnlyRetryUnfinishedExperiments,
+    #[error("sandbox image '{0}' is not in the allowed images of the configuration")]
+    SandboxImageNotAllowed(String),
+    #[error("the number of test runs must be between 1 and {MAX_REPEAT}, {0} given")]
+    InvalidRepeat(u32),
+    #[error("the number of test threads must be at least 1")]
+    InvalidTestThreads,
+    #[error("invalid test filter '{0}'")]
+    InvalidTestFilter(String),
+    #[error("the tests can't be run on the target of toolchain '{0}', use a check or build mode")]
+    TestsNotSupportedOnTarget(String),
+    #[error("invalid requirement '{0}': {1}")]
+    InvalidRequirement(String, RequirementParseError),
+    #[error("building the standard library requires a nightly toolchain, '{0}' is not")]
+    BuildStdRequiresNightly(String),
+    #[error("the build cache is not configured in the `[sandbox]` section of the configuration")]
+    BuildCacheNotConfigured,
+    #[error("the `{0}` crate is patched multiple times")]
+    DuplicatePatch(String),
+    #[error("invalid tag '{0}'")]
+    InvalidTag(String),
+}
+
+/// Whether each of the crates is skipped by an experiment, because it's on the blacklist or
+/// quarantined, with the repositories the experiment tests pinned to their current commit.
+/// Resolving the commits is slow, so the skipped repositories aren't pinned.
+fn skip_and_pin(
+    ctx: &ActionsCtx,
+    crates: Vec<Crate>,
+    ignore_blacklist: bool,
+) -> Fallible<Vec<(String, bool)>> {
+    let quarantined = crate::crates::quarantine::quarantined_ids(ctx.db)?;
+    let is_skipped = |krate: &Crate| {
+        !ignore_blacklist && (ctx.config.should_skip(krate) || quarantined.contains(&krate.id()))
+    };
+
+    let (mut tested, skipped): (Vec<_>, Vec<_>) =
+        crates.into_iter().partition(|krate| !is_skipped(krate));
+    crate::crates::pin_git_shas(&mut tested);
+    Ok(tested
+        .into_iter()
+        // Pinned repositories can be quarantined at their current commit
+        .map(|krate| (krate.id(), is_skipped(&krate)))
+        .chain(skipped.into_iter().map(|krate| (krate.id(), true)))
+        .collect())
+}
+
+/// Insert the crates of the experiment, with whether each of them is skipped. Inserting them one
+/// by one takes minutes for the whole registry, so each statement inserts many of them.
+fn insert_crates(t: &TransactionHandle, ex: &str, crates: &[(String, bool)]) -> Fallible<()> {
+    let status = Status::Queued.to_string();
+    for (chunk_idx, chunk) in crates.chunks(CRATES_PER_INSERT).enumerate() {
+        let mut sql = String::from(
+            "INSERT INTO experiment_crates (experiment, crate, skipped, status) VALUES ",
+        );
+        let mut params: Vec<&dyn ToSql> = vec![&ex, &status];
+        for (i, (krate, skipped)) in chunk.iter().enumerate() {
+            if i != 0 {
+                sql.push_str(", ");
+            }
+            write!(
+                sql,
+                "(?1, ?{}, ?{}, ?2)",
+                params.len() + 1,
+                params.len() + 2
+            )?;
+            params.push(krate);
+            params.push(skipped);
+        }
+        sql.push(';');
+        t.execute(&sql, &params)?;
+
+        debug!(
+            "inserted {} of the {} crates of {}",
+            chunk_idx * CRATES_PER_INSERT + chunk.len(),
+            crates.len(),
+            ex
+        );
+    }
+    Ok(())
+}
+
+/// Replace the crates excluded from the experiment by the filters of the configuration.
+fn replace_exclusions(t: &TransactionHandle, ex: &str, excluded: &[ExcludedCrate]) -> Fallible<()> {
+    t.execute("DELETE FROM excluded_crates WHERE experiment = ?1;", &[&ex])?;
+    for excluded in excluded {
+        t.execute(
+            "INSERT INTO excluded_crates (experiment, crate, reason) VALUES (?1, ?2, ?3);",
+            &[
+                &ex,
+                &excluded.krate.id(),
+                &serde_json::to_string(&excluded.reason)?,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Ensure the crates' tests are only run when the agents can execute the binaries of the target.
+fn validate_target_mode(toolchains: &[Toolchain], mode: Mode) -> Result<(), ExperimentError> {
+    if let Mode::BuildAndTest | Mode::BuildTestAndDoctest = mode {
+        if let Some(tc) = toolchains.iter().find(|tc| !tc.can_run_binaries()) {
+            return Err(ExperimentError::TestsNotSupportedOnTarget(tc.to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_build_std(toolchains: &[Toolchain]) -> Result<(), ExperimentError> {
+    match toolchains
+        .iter()
+        .find(|tc| tc.build_std.is_some() && !tc.is_nightly())
+    {
+        Some(tc) => Err(ExperimentError::BuildStdRequiresNightly(tc.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn validate_build_cache(config: &Config, build_cache: bool) -> Result<(), ExperimentError> {
+    if build_cache && config.sandbox.build_cache.is_none() {
+        return Err(ExperimentError::BuildCacheNotConfigured);
+    }
+    Ok(())
+}
+
+/// Cargo rejects crates patched more than once, be it by the overlay or by the patches.
+fn validate_patches(
+    patches: &[DependencyPatch],
+    overlay: &[OverlayCrate],
+) -> Result<(), ExperimentError> {
+    let mut names = overlay.iter().map(|krate| &krate.name).collect::<Vec<_>>();
+    for patch in patches {
+        if names.contains(&&patch.name) {
+            return Err(ExperimentError::DuplicatePatch(patch.name.clone()));
+        }
+        names.push(&patch.name);
+    }
+    Ok(())
+}
+
+/// Tags are passed to the bot as comma-separated lists, and can't be empty or contain spaces.
+fn validate_tags(tags: &[String]) -> Result<(), ExperimentError> {
+    if let Some(tag) = tags
+        .iter()
+        .find(|tag| tag.is_empty() || tag.contains(|c: char| c == ',' || c.is_whitespace()))
+    {
+        return Err(ExperimentError::InvalidTag(tag.clone()));
+    }
+    Ok(())
+}
+
+fn validate_requirement(requirement: &str) -> Result<(), ExperimentError> {
+    requirement
+        .parse::<Requirement>()
+        .map_err(|err| ExperimentError::InvalidRequirement(requirement.into(), err))?;
+    Ok(())
+}
+
+fn validate_test_options(options: &TestOptions) -> Result<(), ExperimentError> {
+    if options.test_threads == Some(0) {
+        return Err(ExperimentError::InvalidTestThreads);
+    }
+    // Filters are passed to the test harness as is, make sure they can't be mistaken for flags
+    if let Some(filter) = options
+        .filters
+        .iter()
+        .find(|f| f.is_empty() || f.starts_with('-'))
+    {
+        return Err(ExperimentError::InvalidTestFilter(filter.clone()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_crates, CRATES_PER_INSERT};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+
+    #[test]
+    fn test_insert_crates() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        db.execute("DELETE FROM experiment_crates;", &[]).unwrap();
+
+        // More crates than a single statement inserts
+        let crates = (0..CRATES_PER_INSERT * 2 + 1)
+            .map(|i| (format!("reg/crate-{i}/1.0.0"), i % 2 == 0))
+            .collect::<Vec<_>>();
+        db.transaction(true, |t| insert_crates(t, "dummy", &crates))
+            .unwrap();
+
+        let rows = db
+            .query(
+                "SELECT crate, skipped, status FROM experiment_crates \
+                 WHERE experiment = 'dummy' ORDER BY rowid;",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>("crate")?,
+                        row.get::<_, bool>("skipped")?,
+                        row.get::<_, String>("status")?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(rows.len(), crates.len());
+        for ((krate, skipped, status), expected) in rows.iter().zip(&crates) {
+            assert_eq!((krate, skipped), (&expected.0, &expected.1));
+            assert_eq!(status, "queued");
+        }
+    }
 }
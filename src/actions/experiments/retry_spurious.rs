@@ -0,0 +1,173 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::config::Config;
+use crate::crates::Crate;
+use crate::db::{Database, QueryUtils};
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use crate::report::{compare, Comparison};
+use crate::results::{delete_logs, DatabaseDB};
+
+/// Find the crates of an experiment whose results recorded so far were classified as spurious.
+pub fn spurious_crates(db: &Database, config: &Config, ex: &Experiment) -> Fallible<Vec<Crate>> {
+    let results_db = DatabaseDB::new(db);
+    let results = results_db.load_all_results(ex)?;
+    let overridden = results_db.load_overridden_crates(ex)?;
+
+    let mut crates = Vec::new();
+    for krate in ex.get_crates(db)? {
+        let id = krate.id();
+        let [res1, res2] = [&ex.toolchains[0], &ex.toolchains[1]]
+            .map(|tc| results.get(&(id.clone(), tc.to_string())));
+        if let Comparison::SpuriousRegressed | Comparison::SpuriousFixed = compare(
+            config,
+            ex.comparison_policy,
+            &krate,
+            res1,
+            res2,
+            overridden.contains(&id),
+        ) {
+            crates.push(krate);
+        }
+    }
+    Ok(crates)
+}
+
+pub struct RetrySpurious {
+    pub name: String,
+}
+
+impl RetrySpurious {
+    /// Queue again the crates with spurious results, returning them.
+    pub fn requeue(&self, ctx: &ActionsCtx) -> Fallible<Vec<Crate>> {
+        let mut ex = Experiment::get(ctx.db, &self.name)?
+            .ok_or_else(|| ExperimentError::NotFound(self.name.clone()))?;
+
+        // The results of completed experiments might have already been purged, and changing them
+        // while the report is being generated would make it inconsistent.
+        if let Status::Completed | Status::GeneratingReport = ex.status {
+            return Err(ExperimentError::CanOnlyRetryUnfinishedExperiments.into());
+        }
+
+        let crates = spurious_crates(ctx.db, ctx.config, &ex)?;
+        if crates.is_empty() {
+            return Ok(crates);
+        }
+
+        let log_keys = ctx.db.transaction(true, |t| {
+            let mut log_keys: Vec<String> = Vec::new();
+            for krate in &crates {
+                log_keys.extend(t.query(
+                    "SELECT log_key FROM results \
+                     WHERE experiment = ?1 AND crate = ?2 AND log_key IS NOT NULL;",
+                    [&ex.name, &krate.id()],
+                    |row| row.get(0),
+                )?);
+                t.execute(
+                    "DELETE FROM results WHERE experiment = ?1 AND crate = ?2;",
+                    &[&ex.name, &krate.id()],
+                )?;
+                t.execute(
+                    "UPDATE experiment_crates SET status = 'queued', started_at = NULL \
+                     WHERE experiment = ?1 AND crate = ?2;",
+                    &[&ex.name, &krate.id()],
+                )?;
+            }
+            Ok(log_keys)
+        })?;
+        delete_logs(ctx.log_store, &log_keys);
+
+        if ex.status != Status::Queued && ex.status != Status::Running {
+            ex.set_status(ctx.db, Status::Queued)?;
+        }
+
+        info!(
+            "queued again {} crates with spurious results in {}",
+            crates.len(),
+            ex.name
+        );
+        Ok(crates)
+    }
+}
+
+impl Action for RetrySpurious {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        self.requeue(ctx)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spurious_crates, RetrySpurious};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Experiment, Status};
+    use crate::results::{DatabaseDB, FailureReason, TestResult};
+
+    #[test]
+    fn test_retry_spurious() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().pop().unwrap();
+
+        let insert = |tc: &str, result: TestResult| {
+            db.execute(
+                "INSERT INTO results (experiment, crate, toolchain, result, log, encoding) \
+                 VALUES ('dummy', ?1, ?2, ?3, 'log', 'plain');",
+                &[&krate.id(), &tc, &result.to_string()],
+            )
+            .unwrap();
+        };
+        insert(&ex.toolchains[0].to_string(), TestResult::TestPass);
+        insert(
+            &ex.toolchains[1].to_string(),
+            TestResult::BuildFail(FailureReason::OOM),
+        );
+        db.execute(
+            "UPDATE experiment_crates SET status = 'completed' WHERE crate = ?1;",
+            &[&krate.id()],
+        )
+        .unwrap();
+        ex.set_status(&db, Status::NeedsReport).unwrap();
+
+        assert_eq!(
+            spurious_crates(&db, &config, &ex).unwrap(),
+            vec![krate.clone()]
+        );
+
+        let retried = RetrySpurious {
+            name: "dummy".into(),
+        }
+        .requeue(&ctx)
+        .unwrap();
+        assert_eq!(retried, vec![krate]);
+
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert!(DatabaseDB::new(&db)
+            .load_all_results(&ex)
+            .unwrap()
+            .is_empty());
+        assert!(spurious_crates(&db, &config, &ex).unwrap().is_empty());
+
+        // Completed experiments can't be retried
+        let mut ex = ex;
+        ex.set_status(&db, Status::Completed).unwrap();
+        let err = RetrySpurious {
+            name: "dummy".into(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::CanOnlyRetryUnfinishedExperiments)
+        );
+    }
+}
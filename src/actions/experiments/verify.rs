@@ -0,0 +1,263 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::db::QueryUtils;
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use crate::results::delete_logs;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// Results were recorded for a crate that's not part of the experiment.
+    UnknownCrate { krate: String },
+    /// A result was recorded without its log.
+    MissingLog { krate: String, toolchain: String },
+    /// The crate is marked as completed, but not all of its results were recorded.
+    CompletedWithoutResults { krate: String },
+    /// The crate was handed out to an agent which never reported back.
+    OrphanedAssignment { krate: String },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::UnknownCrate { krate } => {
+                write!(
+                    f,
+                    "results recorded for {krate}, which is not in the experiment"
+                )
+            }
+            Inconsistency::MissingLog { krate, toolchain } => {
+                write!(f, "result of {krate} on {toolchain} has no log")
+            }
+            Inconsistency::CompletedWithoutResults { krate } => {
+                write!(
+                    f,
+                    "{krate} is marked as completed but its results are missing"
+                )
+            }
+            Inconsistency::OrphanedAssignment { krate } => {
+                write!(
+                    f,
+                    "{krate} was assigned to an agent which never reported back"
+                )
+            }
+        }
+    }
+}
+
+pub struct VerifyExperiment {
+    pub name: String,
+    pub fix: bool,
+}
+
+impl VerifyExperiment {
+    fn experiment(&self, ctx: &ActionsCtx) -> Fallible<Experiment> {
+        let ex = Experiment::get(ctx.db, &self.name)?
+            .ok_or_else(|| ExperimentError::NotFound(self.name.clone()))?;
+
+        // Results and crates of completed experiments are purged from the database over time, so
+        // they would always look inconsistent.
+        if ex.status == Status::Completed {
+            bail!("experiment {} is already completed", ex.name);
+        }
+        Ok(ex)
+    }
+
+    pub fn find_inconsistencies(&self, ctx: &ActionsCtx) -> Fallible<Vec<Inconsistency>> {
+        let ex = self.experiment(ctx)?;
+        let mut found = Vec::new();
+
+        found.extend(ctx.db.query(
+            "SELECT DISTINCT crate FROM results WHERE experiment = ?1 \
+             AND crate NOT IN (SELECT crate FROM experiment_crates WHERE experiment = ?1) \
+             ORDER BY crate;",
+            [&ex.name],
+            |row| Ok(Inconsistency::UnknownCrate { krate: row.get(0)? }),
+        )?);
+
+        found.extend(ctx.db.query(
+            "SELECT crate, toolchain FROM results WHERE experiment = ?1 AND length(log) = 0 \
+             AND log_key IS NULL ORDER BY crate, toolchain;",
+            [&ex.name],
+            |row| {
+                Ok(Inconsistency::MissingLog {
+                    krate: row.get(0)?,
+                    toolchain: row.get(1)?,
+                })
+            },
+        )?);
+
+        found.extend(ctx.db.query(
+            "SELECT crate FROM experiment_crates ec WHERE experiment = ?1 \
+             AND status = 'completed' \
+             AND (SELECT COUNT(*) FROM results r \
+                  WHERE r.experiment = ec.experiment AND r.crate = ec.crate) < ?2 \
+             ORDER BY crate;",
+            rusqlite::params![ex.name, ex.toolchains.len()],
+            |row| Ok(Inconsistency::CompletedWithoutResults { krate: row.get(0)? }),
+        )?);
+
+        // Crates are handed out again after the timeout only while the experiment is running.
+        if ex.status != Status::Running {
+            found.extend(ctx.db.query(
+                "SELECT crate FROM experiment_crates WHERE experiment = ?1 \
+                 AND status = 'queued' AND started_at IS NOT NULL ORDER BY crate;",
+                [&ex.name],
+                |row| Ok(Inconsistency::OrphanedAssignment { krate: row.get(0)? }),
+            )?);
+        }
+
+        Ok(found)
+    }
+
+    fn repair(&self, ctx: &ActionsCtx, inconsistencies: &[Inconsistency]) -> Fallible<()> {
+        let log_keys = ctx.db.transaction(true, |t| {
+            let mut log_keys: Vec<String> = Vec::new();
+            for inconsistency in inconsistencies {
+                match inconsistency {
+                    Inconsistency::UnknownCrate { krate } => {
+                        log_keys.extend(t.query(
+                            "SELECT log_key FROM results \
+                             WHERE experiment = ?1 AND crate = ?2 AND log_key IS NOT NULL;",
+                            [&self.name, krate],
+                            |row| row.get(0),
+                        )?);
+                        t.execute(
+                            "DELETE FROM results WHERE experiment = ?1 AND crate = ?2;",
+                            &[&self.name, krate],
+                        )?;
+                    }
+                    Inconsistency::MissingLog { krate, toolchain } => {
+                        log_keys.extend(t.query(
+                            "SELECT log_key FROM results WHERE experiment = ?1 AND crate = ?2 \
+                             AND toolchain = ?3 AND log_key IS NOT NULL;",
+                            [&self.name, krate, toolchain],
+                            |row| row.get(0),
+                        )?);
+                        t.execute(
+                            "DELETE FROM results \
+                             WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                            &[&self.name, krate, toolchain],
+                        )?;
+                        requeue(t, &self.name, krate)?;
+                    }
+                    Inconsistency::CompletedWithoutResults { krate }
+                    | Inconsistency::OrphanedAssignment { krate } => {
+                        requeue(t, &self.name, krate)?;
+                    }
+                }
+            }
+            Ok(log_keys)
+        })?;
+        delete_logs(ctx.log_store, &log_keys);
+        Ok(())
+    }
+}
+
+fn requeue(db: &impl QueryUtils, ex: &str, krate: &str) -> Fallible<()> {
+    db.execute(
+        "UPDATE experiment_crates SET status = 'queued', started_at = NULL \
+         WHERE experiment = ?1 AND crate = ?2;",
+        &[&ex, &krate],
+    )?;
+    Ok(())
+}
+
+impl Action for VerifyExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let inconsistencies = self.find_inconsistencies(ctx)?;
+        for inconsistency in &inconsistencies {
+            warn!("{}", inconsistency);
+        }
+
+        if inconsistencies.is_empty() {
+            info!("no inconsistencies found in experiment {}", self.name);
+        } else if self.fix {
+            self.repair(ctx, &inconsistencies)?;
+            info!(
+                "repaired {} inconsistencies in experiment {}",
+                inconsistencies.len(),
+                self.name
+            );
+        } else {
+            bail!(
+                "found {} inconsistencies in experiment {}, run again with --fix to repair them",
+                inconsistencies.len(),
+                self.name
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inconsistency, VerifyExperiment};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Experiment, Status};
+
+    #[test]
+    fn test_verify_and_repair() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::NeedsReport).unwrap();
+
+        let verify = || VerifyExperiment {
+            name: "dummy".into(),
+            fix: false,
+        };
+        assert_eq!(verify().find_inconsistencies(&ctx).unwrap(), Vec::new());
+        verify().apply(&ctx).unwrap();
+
+        let krate = ex.get_crates(&db).unwrap().pop().unwrap().id();
+        let insert = |krate: &str, tc: &str, log: &[u8]| {
+            db.execute(
+                "INSERT INTO results (experiment, crate, toolchain, result, log, encoding) \
+                 VALUES ('dummy', ?1, ?2, 'test-pass', ?3, 'plain');",
+                &[&krate, &tc, &log],
+            )
+            .unwrap();
+        };
+        insert("reg/missing/1.0.0", "stable", b"log");
+        insert(&krate, "stable", b"");
+        db.execute(
+            "UPDATE experiment_crates SET status = 'completed' WHERE crate = ?1;",
+            &[&krate],
+        )
+        .unwrap();
+
+        assert_eq!(
+            verify().find_inconsistencies(&ctx).unwrap(),
+            vec![
+                Inconsistency::UnknownCrate {
+                    krate: "reg/missing/1.0.0".into()
+                },
+                Inconsistency::MissingLog {
+                    krate: krate.clone(),
+                    toolchain: "stable".into()
+                },
+                Inconsistency::CompletedWithoutResults {
+                    krate: krate.clone()
+                },
+            ]
+        );
+        assert!(verify().apply(&ctx).is_err());
+
+        VerifyExperiment {
+            name: "dummy".into(),
+            fix: true,
+        }
+        .apply(&ctx)
+        .unwrap();
+        assert_eq!(verify().find_inconsistencies(&ctx).unwrap(), Vec::new());
+    }
+}
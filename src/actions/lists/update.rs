@@ -6,6 +6,8 @@ pub struct UpdateLists {
     pub github: bool,
     pub registry: bool,
     pub local: bool,
+    /// Fetch the lists even if their sources didn't change since the last update.
+    pub force: bool,
 }
 
 impl Default for UpdateLists {
@@ -14,6 +16,7 @@ impl Default for UpdateLists {
             github: true,
             registry: true,
             local: true,
+            force: false,
         }
     }
 }
@@ -22,17 +25,24 @@ impl Action for UpdateLists {
     fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
         if self.github {
             info!("updating GitHub repositories list");
-            GitHubList::default().update(ctx.db)?;
+            GitHubList::default().update(ctx.db, self.force)?;
         }
 
         if self.registry {
             info!("updating crates.io crates list");
-            RegistryList.update(ctx.db)?;
+            RegistryList.update(ctx.db, self.force)?;
+
+            // Creating the experiments then reads the cached dump instead of downloading it
+            if crate::crates::use_db_dump(&ctx.config.crate_filters) {
+                if let Err(err) = crate::crates::refresh_db_dump() {
+                    warn!("failed to refresh the crates.io database dump: {:?}", err);
+                }
+            }
         }
 
         if self.local {
             info!("updating local crates list");
-            LocalList::default().update(ctx.db)?;
+            LocalList::default().update(ctx.db, self.force)?;
         }
 
         Ok(())
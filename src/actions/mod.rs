@@ -7,6 +7,7 @@ pub use self::lists::*;
 use crate::config::Config;
 use crate::db::Database;
 use crate::prelude::*;
+use crate::results::LogStore;
 
 pub trait Action {
     fn apply(self, ctx: &ActionsCtx) -> Fallible<()>;
@@ -15,10 +16,30 @@ pub trait Action {
 pub struct ActionsCtx<'ctx> {
     db: &'ctx Database,
     config: &'ctx Config,
+    log_store: Option<&'ctx dyn LogStore>,
+    audit_author: Option<&'ctx str>,
 }
 
 impl<'ctx> ActionsCtx<'ctx> {
     pub fn new(db: &'ctx Database, config: &'ctx Config) -> Self {
-        ActionsCtx { db, config }
+        ActionsCtx {
+            db,
+            config,
+            log_store: None,
+            audit_author: None,
+        }
+    }
+
+    /// Delete the logs of the results removed by the actions from this store.
+    pub fn with_log_store(mut self, log_store: Option<&'ctx dyn LogStore>) -> Self {
+        self.log_store = log_store;
+        self
+    }
+
+    /// Record the actions in the audit log on behalf of this author, in the same transaction
+    /// as their changes.
+    pub fn with_audit(mut self, author: &'ctx str) -> Self {
+        self.audit_author = Some(author);
+        self
     }
 }
@@ -1,20 +1,38 @@
 use std::time::Duration;
 
+use crate::agent::spool::Spool;
 use crate::agent::Capabilities;
 use crate::crates::Crate;
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::TestResult;
-use crate::server::api_types::{AgentConfig, ApiResponse, CraterToken};
-use crate::toolchain::Toolchain;
+use crate::results::{BuildReport, TestResult};
+use crate::server::api_encoding::{self, Encoding, Format};
+use crate::server::api_types::{
+    AgentConfig, ApiResponse, CompleteUpload, CraterToken, IDEMPOTENCY_KEY, UPLOAD_CHUNK_SIZE,
+    UPLOAD_ID, UPLOAD_OFFSET,
+};
+use crate::toolchain::{RustcVersion, Toolchain};
 use crate::utils;
+use crate::utils::hex::to_hex;
 use base64::Engine;
 use rand::Rng;
 use reqwest::blocking::RequestBuilder;
-use reqwest::header::AUTHORIZATION;
+use reqwest::header::{
+    HeaderName, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE,
+};
 use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::RwLock;
+
+/// Number of times a chunked upload is restarted when the server received it corrupted or
+/// abandoned it.
+const CHUNKED_UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Time the heartbeat waits for the server to tell whether the running experiment was aborted.
+const ABORT_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, thiserror::Error)]
 pub enum AgentApiError {
@@ -28,13 +46,28 @@ pub enum AgentApiError {
     InvalidAuthorizationToken,
     #[error("internal server error: {0}")]
     InternalServerError(String),
+    #[error("invalid request: {0}")]
+    BadRequest(String),
 }
 
 trait ResponseExt {
+    fn encoding(&self) -> Fallible<Encoding>;
     fn to_api_response<T: DeserializeOwned>(self) -> Fallible<T>;
 }
 
 impl ResponseExt for ::reqwest::blocking::Response {
+    fn encoding(&self) -> Fallible<Encoding> {
+        let header = |name: HeaderName| {
+            self.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        Ok(Encoding::from_headers(
+            header(CONTENT_TYPE),
+            header(CONTENT_ENCODING),
+        )?)
+    }
+
     fn to_api_response<T: DeserializeOwned>(self) -> Fallible<T> {
         // 404 responses are not JSON, so avoid parsing them
         match self.status() {
@@ -51,16 +84,20 @@ impl ResponseExt for ::reqwest::blocking::Response {
 
         let status = self.status();
         let result: ApiResponse<T> = self
-            .json()
+            .encoding()
+            .and_then(|encoding| encoding.decode(&self.bytes()?))
             .with_context(|| format!("failed to parse API response (status code {status})",))?;
         match result {
             ApiResponse::Success { result } => Ok(result),
-            ApiResponse::SlowDown => Err(AgentApiError::ServerUnavailable.into()),
+            ApiResponse::SlowDown | ApiResponse::ReadOnly => {
+                Err(AgentApiError::ServerUnavailable.into())
+            }
             ApiResponse::InternalError { error } => {
                 Err(AgentApiError::InternalServerError(error).into())
             }
             ApiResponse::Unauthorized => Err(AgentApiError::InvalidAuthorizationToken.into()),
             ApiResponse::NotFound => Err(AgentApiError::InvalidEndpoint.into()),
+            ApiResponse::BadRequest { error } => Err(AgentApiError::BadRequest(error).into()),
         }
     }
 }
@@ -69,6 +106,10 @@ pub struct AgentApi {
     url: String,
     token: String,
     random_id: String,
+    spool: Option<Spool>,
+    /// Encoding of the request bodies, switched to the compact one once the server showed it
+    /// supports it by answering with it.
+    encoding: RwLock<Encoding>,
 }
 
 impl AgentApi {
@@ -77,17 +118,42 @@ impl AgentApi {
             url: url.to_string(),
             token: token.to_string(),
             random_id: format!("{:X}{:X}", rand::random::<u64>(), rand::random::<u64>()),
+            spool: None,
+            encoding: RwLock::new(Encoding::JSON),
         }
     }
 
+    /// Spool the results locally before uploading them, to upload them again if they're lost.
+    pub(crate) fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
     fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
-        utils::http::prepare_sync(method, &format!("{}/agent-api/{url}", self.url)).header(
-            AUTHORIZATION,
-            (CraterToken {
-                token: self.token.clone(),
-            })
-            .to_string(),
-        )
+        utils::http::prepare_sync(method, &format!("{}/agent-api/{url}", self.url))
+            .header(
+                AUTHORIZATION,
+                (CraterToken {
+                    token: self.token.clone(),
+                })
+                .to_string(),
+            )
+            .header(ACCEPT, api_encoding::ACCEPTED_FORMATS)
+            .header(ACCEPT_ENCODING, api_encoding::ACCEPTED_COMPRESSIONS)
+    }
+
+    fn encoding(&self) -> Encoding {
+        *self.encoding.read().unwrap()
+    }
+
+    /// Attach the body to the request, encoded with the encoding negotiated with the server.
+    fn with_body<T: Serialize + ?Sized>(
+        &self,
+        req: RequestBuilder,
+        body: &T,
+    ) -> Fallible<RequestBuilder> {
+        let encoding = self.encoding();
+        Ok(with_encoding(req, encoding).body(encoding.encode(body)?))
     }
 
     fn retry<T, F: Fn(&Self) -> Fallible<T>>(&self, f: F) -> Fallible<T> {
@@ -99,7 +165,8 @@ impl AgentApi {
                     let retry = if let Some(AgentApiError::ServerUnavailable) = err.downcast_ref() {
                         true
                     } else if let Some(err) = err.downcast_ref::<::reqwest::Error>() {
-                        err.is_timeout() || err.is_connect()
+                        // Request and body errors include connections dropped mid-upload
+                        err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
                     } else {
                         // We retry these errors. Ideally it's something the
                         // server would handle, but that's (unfortunately) hard
@@ -130,12 +197,18 @@ impl AgentApi {
         }
     }
 
+    /// Fetch the configuration, negotiating the encoding of the following requests. The request
+    /// itself is JSON, as the server might not support anything else.
     pub fn config(&self, caps: &Capabilities) -> Fallible<AgentConfig> {
         self.retry(|this| {
-            this.build_request(Method::POST, "config")
+            let resp = this
+                .build_request(Method::POST, "config")
                 .json(&json!(caps))
-                .send()?
-                .to_api_response()
+                .send()?;
+            let encoding = resp.encoding().unwrap_or(Encoding::JSON);
+            let config = resp.to_api_response()?;
+            *this.encoding.write().unwrap() = encoding;
+            Ok(config)
         })
     }
 
@@ -161,8 +234,7 @@ impl AgentApi {
     pub fn next_crate(&self, ex: &str) -> Fallible<Option<Crate>> {
         self.retry(|this| {
             let resp: Option<Crate> = this
-                .build_request(Method::POST, "next-crate")
-                .json(&json!(ex))
+                .with_body(this.build_request(Method::POST, "next-crate"), ex)?
                 .send()?
                 .to_api_response()?;
 
@@ -177,51 +249,213 @@ impl AgentApi {
         toolchain: &Toolchain,
         log: &[u8],
         result: &TestResult,
+        report: &BuildReport,
         version: Option<(&Crate, &Crate)>,
     ) -> Fallible<()> {
+        let body = serde_json::to_vec(&json!({
+            "experiment-name": ex.name,
+            "result": {
+                "crate": krate,
+                "toolchain": toolchain,
+                "result": result,
+                "log": base64::engine::general_purpose::STANDARD.encode(log),
+                "report": report,
+            },
+            "version": version
+        }))?;
+        match &self.spool {
+            Some(spool) => {
+                let key = spool.push(&body)?;
+                self.upload_from_spool(spool, &key)
+            }
+            None => self.upload_progress(&body, None),
+        }
+    }
+
+    /// Upload a JSON body, like the spooled ones, compressed if the server supports it.
+    fn upload_progress(&self, body: &[u8], idempotency_key: Option<&str>) -> Fallible<()> {
+        let encoding = Encoding {
+            format: Format::Json,
+            ..self.encoding()
+        };
+        let body = &encoding.compress(body)?;
+        if body.len() > UPLOAD_CHUNK_SIZE {
+            let id = match idempotency_key {
+                Some(key) => key.to_string(),
+                None => format!(
+                    "{:016X}{:016X}",
+                    rand::random::<u64>(),
+                    rand::random::<u64>()
+                ),
+            };
+            return self.upload_chunked(body, encoding, &id);
+        }
+
         self.retry(|this| {
+            let mut req = with_encoding(
+                this.build_request(Method::POST, "record-progress"),
+                encoding,
+            )
+            .body(body.to_vec());
+            if let Some(key) = idempotency_key {
+                req = req.header(IDEMPOTENCY_KEY, key);
+            }
+            let _: bool = req.send()?.to_api_response()?;
+            Ok(())
+        })
+    }
+
+    /// Upload a large body in chunks, each of them retried on its own. The server tells how much
+    /// of the body it received, so interrupted uploads resume where they stopped, even across
+    /// restarts of the agent when the body comes from the spool.
+    fn upload_chunked(&self, body: &[u8], encoding: Encoding, id: &str) -> Fallible<()> {
+        let sha256 = to_hex(&Sha256::digest(body));
+        for _ in 0..CHUNKED_UPLOAD_ATTEMPTS {
+            let mut offset = 0;
+            while offset < body.len() {
+                let chunk = &body[offset..(offset + UPLOAD_CHUNK_SIZE).min(body.len())];
+                let received: u64 = self.retry(|this| {
+                    this.build_request(Method::POST, "upload-chunk")
+                        .header(UPLOAD_ID, id)
+                        .header(UPLOAD_OFFSET, offset.to_string())
+                        .body(chunk.to_vec())
+                        .send()?
+                        .to_api_response()
+                })?;
+                offset = received as usize;
+            }
+
+            let completed: bool = self.retry(|this| {
+                this.build_request(Method::POST, "complete-upload")
+                    .json(&CompleteUpload {
+                        upload_id: id.to_string(),
+                        sha256: sha256.clone(),
+                        content_type: Some(encoding.content_type().into()),
+                        content_encoding: encoding.content_encoding().map(String::from),
+                    })
+                    .send()?
+                    .to_api_response()
+            })?;
+            if completed {
+                return Ok(());
+            }
+            warn!("the server rejected the upload {}, uploading it again", id);
+        }
+        bail!("failed to upload {id}: the server kept rejecting it")
+    }
+
+    fn upload_from_spool(&self, spool: &Spool, key: &str) -> Fallible<()> {
+        let result = self.upload_progress(&spool.read(key)?, Some(key));
+        // Uploads rejected by the server would be rejected again when retried
+        let rejected = result
+            .as_ref()
+            .is_err_and(|err| err.downcast_ref::<AgentApiError>().is_some());
+        if result.is_ok() || rejected {
+            spool.remove(key)?;
+        }
+        result
+    }
+
+    /// Upload the results left in the spool by interrupted uploads, including the ones of
+    /// previous runs of the agent.
+    pub fn upload_spooled(&self) -> Fallible<()> {
+        let Some(spool) = &self.spool else {
+            return Ok(());
+        };
+        let pending = spool.pending()?;
+        if !pending.is_empty() {
+            info!("uploading {} spooled results...", pending.len());
+        }
+        for key in pending {
+            if let Err(err) = self.upload_from_spool(spool, &key) {
+                utils::report_failure(&err.context(format!("failed to upload {key}")));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_rustc_versions(
+        &self,
+        ex: &Experiment,
+        versions: &[RustcVersion; 2],
+    ) -> Fallible<()> {
+        self.retry(|this| {
+            let body = json!({
+                "experiment-name": ex.name,
+                "versions": versions,
+            });
             let _: bool = this
-                .build_request(Method::POST, "record-progress")
-                .json(&json!({
-                    "experiment-name": ex.name,
-                    "result": {
-                        "crate": krate,
-                        "toolchain": toolchain,
-                        "result": result,
-                        "log": base64::engine::general_purpose::STANDARD.encode(log),
-                    },
-                    "version": version
-                }))
+                .with_body(this.build_request(Method::POST, "rustc-versions"), &body)?
                 .send()?
                 .to_api_response()?;
             Ok(())
         })
     }
 
-    pub fn heartbeat(&self) -> Fallible<()> {
+    /// Whether the experiment was aborted since the agent started running it. Experiments are
+    /// deleted when aborted, and can then be created again with the same name.
+    ///
+    /// This is checked by the heartbeat, so a single attempt is made, bounded by a timeout: a
+    /// failed check is retried with the next heartbeat instead of delaying it.
+    pub fn is_aborted(&self, ex: &Experiment) -> Fallible<bool> {
+        let body = json!({
+            "experiment-name": ex.name,
+            "created-at": ex.created_at,
+        });
+        self.with_body(
+            self.build_request(Method::POST, "experiment-aborted")
+                .timeout(ABORT_CHECK_TIMEOUT),
+            &body,
+        )?
+        .send()?
+        .to_api_response()
+    }
+
+    /// Tell the server the agent is alive, along with the number of workers restarted after a
+    /// panic since the previous heartbeat.
+    pub fn heartbeat(&self, worker_restarts: u32) -> Fallible<()> {
         self.retry(|this| {
+            let body = json!({
+                "id": self.random_id,
+                "worker-restarts": worker_restarts,
+            });
             let _: bool = this
-                .build_request(Method::POST, "heartbeat")
-                .json(&json!({
-                    "id": self.random_id,
-                }))
+                .with_body(this.build_request(Method::POST, "heartbeat"), &body)?
                 .send()?
                 .to_api_response()?;
             Ok(())
         })
     }
 
-    pub fn report_error(&self, ex: &Experiment, error: String) -> Fallible<()> {
+    /// Report the experiment failed on this agent. The toolchain is provided when the failure was
+    /// caused by it not being available.
+    pub fn report_error(
+        &self,
+        ex: &Experiment,
+        error: String,
+        toolchain: Option<&Toolchain>,
+    ) -> Fallible<()> {
+        let mut body = json!({
+            "experiment-name": ex.name,
+            "error": error
+        });
+        if let Some(tc) = toolchain {
+            body["toolchain"] = json!(tc.to_string());
+        }
         self.retry(|this| {
             let _: bool = this
-                .build_request(Method::POST, "error")
-                .json(&json!({
-                    "experiment-name": ex.name,
-                    "error": error
-                }))
+                .with_body(this.build_request(Method::POST, "error"), &body)?
                 .send()?
                 .to_api_response()?;
             Ok(())
         })
     }
 }
+
+fn with_encoding(req: RequestBuilder, encoding: Encoding) -> RequestBuilder {
+    let req = req.header(CONTENT_TYPE, encoding.content_type());
+    match encoding.content_encoding() {
+        Some(content_encoding) => req.header(CONTENT_ENCODING, content_encoding),
+        None => req,
+    }
+}
@@ -1,18 +1,25 @@
 mod api;
+mod requirement;
+mod spool;
 
 pub use crate::agent::api::AgentApi;
-use crate::config::Config;
+pub use crate::agent::requirement::{Requirement, RequirementParseError};
+use crate::agent::spool::Spool;
+use crate::config::{Config, CrateCacheConfig};
 use crate::crates::Crate;
 use crate::db::{Database, QueryUtils};
+use crate::dirs::WORK_DIR;
 use crate::experiments::Experiment;
 use crate::prelude::*;
+use crate::runner::{self, container::ContainerRuntime, self_test::SelfTestReport, Threads};
 use crate::utils;
 use crate::utils::disk_usage::DiskUsage;
 use anyhow::{Error, Result};
 use rustwide::Workspace;
 use std::collections::BTreeSet;
 use std::ops;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -72,18 +79,34 @@ pub struct Agent {
 }
 
 impl Agent {
-    fn new(url: &str, token: &str, caps: &Capabilities) -> Fallible<Self> {
+    fn new(
+        url: &str,
+        token: &str,
+        caps: &Capabilities,
+        crate_cache: Option<&str>,
+    ) -> Fallible<Self> {
         info!("connecting to crater server {}...", url);
 
-        let api = AgentApi::new(url, token);
+        let spool = Spool::new(&WORK_DIR.join("results-spool"))?;
+        let api = AgentApi::new(url, token).with_spool(spool);
         let config = api.config(caps)?;
 
         info!("connected to the crater server!");
         info!("assigned agent name: {}", config.agent_name);
 
+        let mut crater_config = config.crater_config;
+        if let Some(url) = crate_cache {
+            crater_config.crate_cache = Some(CrateCacheConfig {
+                url: url.to_string(),
+            });
+        }
+        if let Some(cache) = &crater_config.crate_cache {
+            info!("using the crate cache at {}", cache.url);
+        }
+
         Ok(Agent {
             api,
-            config: config.crater_config,
+            config: crater_config,
         })
     }
 
@@ -99,12 +122,26 @@ impl Agent {
 
 static HEALTH_CHECK: AtomicBool = AtomicBool::new(false);
 
+lazy_static! {
+    /// Experiment being run by the agent, checked by the heartbeat thread.
+    static ref RUNNING_EXPERIMENT: Mutex<Option<Experiment>> = Mutex::new(None);
+}
+/// Set when the running experiment is aborted, interrupting its workers.
+static ABORTED: AtomicBool = AtomicBool::new(false);
+/// Workers restarted after a panic since the last heartbeat, reported to the server.
+static WORKER_RESTARTS: AtomicU32 = AtomicU32::new(0);
+
 // Should be called at least once every 5 minutes, otherwise instance is
 // replaced.
 pub fn set_healthy() {
     HEALTH_CHECK.store(true, Ordering::SeqCst);
 }
 
+/// Count a worker restarted after a panic, sent along with the next heartbeat.
+pub(crate) fn record_worker_restart() {
+    WORKER_RESTARTS.fetch_add(1, Ordering::SeqCst);
+}
+
 fn health_thread() {
     std::thread::spawn(move || {
         let mut last_check = Instant::now();
@@ -137,20 +174,82 @@ fn run_heartbeat(url: &str, token: &str) {
     let api = AgentApi::new(url, token);
 
     thread::spawn(move || loop {
-        if let Err(e) = api.heartbeat().with_context(|| "failed to send heartbeat") {
+        let restarts = WORKER_RESTARTS.swap(0, Ordering::SeqCst);
+        if let Err(e) = api
+            .heartbeat(restarts)
+            .with_context(|| "failed to send heartbeat")
+        {
+            // Sent again with the next heartbeat
+            WORKER_RESTARTS.fetch_add(restarts, Ordering::SeqCst);
+            utils::report_failure(&e);
+        }
+        if let Err(e) = check_aborted(&api).with_context(|| "failed to check for aborts") {
             utils::report_failure(&e);
         }
         thread::sleep(Duration::from_secs(60));
     });
 }
 
+/// Interrupt the running experiment if it was aborted, killing the builds in progress instead of
+/// waiting for them to complete.
+fn check_aborted(api: &AgentApi) -> Fallible<()> {
+    let running = RUNNING_EXPERIMENT.lock().unwrap().clone();
+    let Some(ex) = running else {
+        return Ok(());
+    };
+    if ABORTED.load(Ordering::SeqCst) || !api.is_aborted(&ex)? {
+        return Ok(());
+    }
+
+    warn!("experiment {} was aborted, interrupting it", ex.name);
+    ABORTED.store(true, Ordering::SeqCst);
+    let killed = runner::container::kill_sandboxes()?;
+    info!("killed {} running builds", killed);
+    Ok(())
+}
+
+/// Workspace used by the agent, recreated whenever an experiment requires a different sandbox
+/// image than the previous one.
+struct AgentWorkspace<'a> {
+    build: &'a dyn Fn(Option<&str>) -> Fallible<Workspace>,
+    image: Option<String>,
+    workspace: Workspace,
+}
+
+impl<'a> AgentWorkspace<'a> {
+    fn new(build: &'a dyn Fn(Option<&str>) -> Fallible<Workspace>) -> Fallible<Self> {
+        Ok(AgentWorkspace {
+            build,
+            image: None,
+            workspace: build(None)?,
+        })
+    }
+
+    fn for_experiment(&mut self, ex: &Experiment) -> Fallible<&Workspace> {
+        if self.image != ex.sandbox_image {
+            info!(
+                "switching the sandbox image to {}",
+                ex.sandbox_image.as_deref().unwrap_or("the default one")
+            );
+            self.workspace = (self.build)(ex.sandbox_image.as_deref())?;
+            self.image = ex.sandbox_image.clone();
+        }
+        Ok(&self.workspace)
+    }
+}
+
 fn run_experiment(
     agent: &Agent,
-    workspace: &Workspace,
-    threads_count: usize,
+    workspace: &mut AgentWorkspace,
+    threads: Threads,
+    once: bool,
     past_experiment: &mut Option<String>,
 ) -> Result<(), (Option<Box<Experiment>>, Error)> {
     let ex = agent.experiment().map_err(|e| (None, e))?;
+    let workspace = match workspace.for_experiment(&ex) {
+        Ok(workspace) => workspace,
+        Err(err) => return Err((Some(Box::new(ex)), err)),
+    };
 
     if Some(&ex.name) != past_experiment.as_ref() {
         debug!("purging build directories...");
@@ -170,45 +269,95 @@ fn run_experiment(
         }
     }
 
-    crate::runner::run_ex(
+    ABORTED.store(false, Ordering::SeqCst);
+    *RUNNING_EXPERIMENT.lock().unwrap() = Some(ex.clone());
+    let tested = AtomicBool::new(false);
+    let result = crate::runner::run_ex(
         &ex,
         workspace,
         &agent.api,
-        threads_count,
+        threads,
         &agent.config,
-        &|| agent.next_crate(&ex.name),
-    )
-    .map_err(|err| (Some(Box::new(ex)), err))?;
+        None,
+        &|| {
+            // Only a single crate is requested when running once
+            if once && tested.swap(true, Ordering::SeqCst) {
+                return Ok(None);
+            }
+            agent.next_crate(&ex.name)
+        },
+        &ABORTED,
+    );
+    *RUNNING_EXPERIMENT.lock().unwrap() = None;
+    result.map_err(|err| (Some(Box::new(ex)), err))?;
     Ok(())
 }
 
 pub fn run(
     url: &str,
     token: &str,
-    threads_count: usize,
+    threads: Threads,
     caps: &Capabilities,
-    workspace: &Workspace,
+    container_runtime: ContainerRuntime,
+    workspace: &dyn Fn(Option<&str>) -> Fallible<Workspace>,
+    crate_cache: Option<&str>,
+    once: bool,
 ) -> Fallible<()> {
-    let agent = Agent::new(url, token, caps)?;
+    let agent = Agent::new(url, token, caps, crate_cache)?;
+    container_runtime.check_config(&agent.config)?;
+    let mut workspace = AgentWorkspace::new(workspace)?;
 
     run_heartbeat(url, token);
     health_thread();
 
     let mut past_experiment = None;
     loop {
-        if let Err((ex, err)) =
-            run_experiment(&agent, workspace, threads_count, &mut past_experiment)
-        {
+        if let Err(err) = agent.api.upload_spooled() {
+            utils::report_failure(&err);
+        }
+
+        let result = run_experiment(&agent, &mut workspace, threads, once, &mut past_experiment);
+        if let Err((ex, err)) = result {
             utils::report_failure(&err);
             if let Some(ex) = ex {
+                let toolchain = err
+                    .downcast_ref::<runner::ToolchainUnavailable>()
+                    .map(|unavailable| &unavailable.0);
+                // Failed installations are reported with the attempts made before giving up
+                let message = match err.downcast_ref::<runner::InstallFailed>() {
+                    Some(failed) => format!("{failed}: {}", err.root_cause()),
+                    None => format!("{}", err.root_cause()),
+                };
                 if let Err(e) = agent
                     .api
-                    .report_error(&ex, format!("{}", err.root_cause()))
+                    .report_error(&ex, message, toolchain)
                     .with_context(|| "error encountered")
                 {
                     utils::report_failure(&e);
                 }
             }
+            if once {
+                return Err(err);
+            }
+        }
+
+        if once {
+            info!("tested a single crate, exiting");
+            return Ok(());
         }
     }
 }
+
+/// Check the sandbox of this host works as configured by the server, without running any
+/// experiment.
+pub fn self_test(
+    url: &str,
+    token: &str,
+    caps: &Capabilities,
+    container_runtime: ContainerRuntime,
+    workspace: &dyn Fn(Option<&str>) -> Fallible<Workspace>,
+) -> Fallible<SelfTestReport> {
+    let agent = Agent::new(url, token, caps, None)?;
+    container_runtime.check_config(&agent.config)?;
+    Ok(runner::self_test::run(&agent.config, || workspace(None)))
+}
@@ -0,0 +1,231 @@
+//! Requirements of the experiments, matched against the capabilities of the agents. A requirement
+//! is a boolean expression of capabilities, like `linux && big-hard-drive` or
+//! `windows || (linux && !arm)`, where `&&` binds tighter than `||`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RequirementParseError {
+    #[error("the requirement is empty")]
+    Empty,
+    #[error("expected a capability at position {0}")]
+    ExpectedCapability(usize),
+    #[error("unexpected `{1}` at position {0}")]
+    Unexpected(usize, char),
+    #[error("unclosed parenthesis")]
+    UnclosedParenthesis,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Capability(String),
+    Not(Box<Requirement>),
+    All(Vec<Requirement>),
+    Any(Vec<Requirement>),
+}
+
+impl Requirement {
+    /// Whether an agent with these capabilities can run the experiment.
+    pub fn matches(&self, capabilities: &BTreeSet<String>) -> bool {
+        match self {
+            Requirement::Capability(cap) => capabilities.contains(cap),
+            Requirement::Not(inner) => !inner.matches(capabilities),
+            Requirement::All(all) => all.iter().all(|r| r.matches(capabilities)),
+            Requirement::Any(any) => any.iter().any(|r| r.matches(capabilities)),
+        }
+    }
+}
+
+fn is_capability_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn any(&mut self) -> Result<Requirement, RequirementParseError> {
+        let mut any = vec![self.all()?];
+        while self.eat("||") {
+            any.push(self.all()?);
+        }
+        Ok(if any.len() == 1 {
+            any.pop().unwrap()
+        } else {
+            Requirement::Any(any)
+        })
+    }
+
+    fn all(&mut self) -> Result<Requirement, RequirementParseError> {
+        let mut all = vec![self.unary()?];
+        while self.eat("&&") {
+            all.push(self.unary()?);
+        }
+        Ok(if all.len() == 1 {
+            all.pop().unwrap()
+        } else {
+            Requirement::All(all)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Requirement, RequirementParseError> {
+        if self.eat("!") {
+            return Ok(Requirement::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let inner = self.any()?;
+            if !self.eat(")") {
+                return Err(RequirementParseError::UnclosedParenthesis);
+            }
+            return Ok(inner);
+        }
+
+        self.skip_whitespace();
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c| !is_capability_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(RequirementParseError::ExpectedCapability(self.pos));
+        }
+        self.pos += len;
+        Ok(Requirement::Capability(rest[..len].to_string()))
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = RequirementParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.trim().is_empty() {
+            return Err(RequirementParseError::Empty);
+        }
+        let mut parser = Parser { input, pos: 0 };
+        let requirement = parser.any()?;
+        match parser.peek() {
+            None => Ok(requirement),
+            Some(c) => Err(RequirementParseError::Unexpected(parser.pos, c)),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, items: &[Requirement], sep: &str| {
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(sep)?;
+                }
+                match item {
+                    Requirement::All(_) | Requirement::Any(_) => write!(f, "({item})")?,
+                    _ => write!(f, "{item}")?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Requirement::Capability(cap) => f.write_str(cap),
+            Requirement::Not(inner) => match **inner {
+                Requirement::All(_) | Requirement::Any(_) => write!(f, "!({inner})"),
+                _ => write!(f, "!{inner}"),
+            },
+            Requirement::All(all) => join(f, all, " && "),
+            Requirement::Any(any) => join(f, any, " || "),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Requirement, RequirementParseError};
+    use std::collections::BTreeSet;
+
+    fn caps(caps: &[&str]) -> BTreeSet<String> {
+        caps.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        let matches = |requirement: &str, agent: &[&str]| {
+            requirement
+                .parse::<Requirement>()
+                .unwrap()
+                .matches(&caps(agent))
+        };
+
+        assert!(matches("linux", &["linux"]));
+        assert!(!matches("linux", &["windows"]));
+        assert!(matches(
+            "linux && big-hard-drive",
+            &["linux", "big-hard-drive"]
+        ));
+        assert!(!matches("linux && big-hard-drive", &["linux"]));
+        assert!(matches("windows || mac", &["mac"]));
+        assert!(!matches("windows || mac", &["linux"]));
+        assert!(matches("linux && !arm", &["linux"]));
+        assert!(!matches("linux && !arm", &["linux", "arm"]));
+        // `&&` binds tighter than `||`
+        assert!(matches("windows || linux && arm", &["windows"]));
+        assert!(!matches("(windows || linux) && arm", &["windows"]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |requirement: &str| requirement.parse::<Requirement>().unwrap_err();
+
+        assert_eq!(error(" "), RequirementParseError::Empty);
+        assert_eq!(
+            error("linux &&"),
+            RequirementParseError::ExpectedCapability(8)
+        );
+        assert_eq!(
+            error("linux windows"),
+            RequirementParseError::Unexpected(6, 'w')
+        );
+        assert_eq!(
+            error("linux & arm"),
+            RequirementParseError::Unexpected(6, '&')
+        );
+        assert_eq!(
+            error("(linux || mac"),
+            RequirementParseError::UnclosedParenthesis
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        for requirement in [
+            "linux",
+            "linux && big-hard-drive",
+            "windows || (linux && !arm)",
+            "!(windows || mac) && x86_64",
+        ] {
+            assert_eq!(
+                requirement.parse::<Requirement>().unwrap().to_string(),
+                requirement
+            );
+        }
+    }
+}
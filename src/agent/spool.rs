@@ -0,0 +1,100 @@
+//! Local spool of the results reported by the agent. Each result is written to disk before being
+//! uploaded and removed once the server acknowledges it, so that results whose upload was
+//! interrupted (by a network failure or by the agent being restarted) are uploaded again later
+//! instead of requiring the crates to be rebuilt. Every spooled result has an idempotency key,
+//! allowing the server to ignore the uploads it already received.
+
+use crate::prelude::*;
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXTENSION: &str = "json";
+
+pub(crate) struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub(crate) fn new(dir: &Path) -> Fallible<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create the spool at {}", dir.display()))?;
+        Ok(Spool {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(EXTENSION)
+    }
+
+    /// Store the body of an upload, returning its idempotency key. Keys sort in the order the
+    /// uploads were spooled.
+    pub(crate) fn push(&self, body: &[u8]) -> Fallible<String> {
+        let key = format!(
+            "{:016X}-{:016X}",
+            Utc::now().timestamp_millis(),
+            rand::random::<u64>()
+        );
+
+        // Write to a temporary file first to avoid uploading truncated results
+        let tmp = self.dir.join(format!(".{key}.tmp"));
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, self.path(&key))?;
+        Ok(key)
+    }
+
+    pub(crate) fn read(&self, key: &str) -> Fallible<Vec<u8>> {
+        Ok(fs::read(self.path(key))?)
+    }
+
+    pub(crate) fn remove(&self, key: &str) -> Fallible<()> {
+        fs::remove_file(self.path(key))?;
+        Ok(())
+    }
+
+    /// Keys of the uploads still in the spool, oldest first.
+    pub(crate) fn pending(&self) -> Fallible<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            if let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) {
+                keys.push(key.to_string());
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spool;
+
+    #[test]
+    fn test_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::new(&dir.path().join("spool")).unwrap();
+        assert!(spool.pending().unwrap().is_empty());
+
+        let first = spool.push(b"first").unwrap();
+        let second = spool.push(b"second").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(spool.read(&first).unwrap(), b"first");
+
+        // Leftover temporary files are ignored
+        std::fs::write(dir.path().join("spool").join(".partial.tmp"), "").unwrap();
+        let mut expected = vec![first.clone(), second.clone()];
+        expected.sort();
+        assert_eq!(spool.pending().unwrap(), expected);
+
+        spool.remove(&first).unwrap();
+        assert_eq!(spool.pending().unwrap(), [second.clone()]);
+        // Spooled uploads survive the agent being restarted
+        let spool = Spool::new(&dir.path().join("spool")).unwrap();
+        assert_eq!(spool.read(&second).unwrap(), b"second");
+    }
+}
@@ -0,0 +1,179 @@
+//! Analysis of the results of Crater experiments, for the tools consuming the `results.json` file
+//! of the reports (like rustc-perf or triagebot) instead of reimplementing how Crater compares
+//! and groups the results. The module is enabled by the `analysis-api` feature:
+//!
+//! ```toml
+//! crater = { git = "https://github.com/rust-lang/crater", features = ["analysis-api"] }
+//! ```
+//!
+//! Unlike the rest of the crate, which is internal to Crater, the items of this module only
+//! change in backwards compatible ways, and keep reading the `results.json` files of the past
+//! experiments. New results and categories are added over time, so the enums are
+//! `#[non_exhaustive]`.
+//!
+//! ```no_run
+//! use crater::analysis::{analyze_report, parse_results, Comparison};
+//!
+//! let results = parse_results(&std::fs::read("results.json")?)?;
+//! let analyzed = analyze_report(results);
+//! println!("{} regressions", analyzed.info.get(&Comparison::Regressed).unwrap_or(&0));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::config::Config;
+use crate::prelude::*;
+
+pub use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+pub use crate::experiments::ComparisonPolicy;
+pub use crate::report::{
+    analyze_report, BuildTestResult, Comparison, CrateResult, RawTestResults, ReportCrates,
+    TestResults,
+};
+pub use crate::results::{BrokenReason, FailureReason, TestResult, TriageAnnotation, TriageStatus};
+
+/// Parse the `results.json` file of a report.
+pub fn parse_results(json: &[u8]) -> Fallible<RawTestResults> {
+    serde_json::from_slice(json).context("parsing the results of the report")
+}
+
+/// Categorize the change between the results of the start and end toolchains for a crate, like
+/// the reports do. The results are never considered as forced by Crater's configuration.
+pub fn classify(
+    policy: ComparisonPolicy,
+    krate: &Crate,
+    start: Option<&TestResult>,
+    end: Option<&TestResult>,
+) -> Comparison {
+    crate::report::compare(&Config::default(), policy, krate, start, end, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        analyze_report, classify, parse_results, BrokenReason, Comparison, ComparisonPolicy, Crate,
+        FailureReason, ReportCrates, TestResult,
+    };
+
+    #[test]
+    fn test_analyze_results_json() {
+        let json = br#"{"crates": [
+            {
+                "krate": {"Local": "beta-regression"},
+                "name": "beta-regression (local)",
+                "res": "regressed",
+                "runs": [
+                    {"log": "stable/local/beta-regression", "res": "test-pass"},
+                    {"log": "beta/local/beta-regression", "res": "build-fail:unknown"}
+                ],
+                "url": "https://example.com/beta-regression"
+            },
+            {
+                "krate": {"Local": "build-pass"},
+                "name": "build-pass (local)",
+                "res": "test-pass",
+                "runs": [null, {"log": "beta/local/build-pass", "res": "test-pass"}],
+                "url": "https://example.com/build-pass"
+            }
+        ]}"#;
+
+        let results = parse_results(json).unwrap();
+        let regression = &results.crates[0];
+        assert_eq!(regression.name(), "beta-regression (local)");
+        assert_eq!(regression.krate(), &Crate::Local("beta-regression".into()));
+        let [start, end] = regression.runs();
+        assert_eq!(start.unwrap().result(), &TestResult::TestPass);
+        assert_eq!(end.unwrap().log(), "beta/local/beta-regression");
+        assert!(results.crates[1].runs()[0].is_none());
+
+        let analyzed = analyze_report(results);
+        assert_eq!(analyzed.info[&Comparison::Regressed], 1);
+        assert_eq!(analyzed.info[&Comparison::SameTestPass], 1);
+        let Some(ReportCrates::Complete { results, .. }) =
+            analyzed.categories.get(&Comparison::Regressed)
+        else {
+            panic!("regressions are grouped by result");
+        };
+        assert_eq!(
+            results.keys().collect::<Vec<_>>(),
+            [&TestResult::BuildFail(FailureReason::Unknown)]
+        );
+
+        assert!(parse_results(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_old_results_json() {
+        // Written by the reports before the tags, triage annotations and version statuses
+        let json = br#"{"crates": [
+            {
+                "name": "foo-1.0.0",
+                "url": "https://crates.io/crates/foo/1.0.0",
+                "krate": {"Registry": {"name": "foo", "version": "1.0.0"}},
+                "res": "regressed",
+                "runs": [
+                    {"res": "test-pass", "log": "stable/reg/foo-1.0.0"},
+                    {
+                        "res": "build-fail:compiler-error(E0308, E0599)",
+                        "log": "beta/reg/foo-1.0.0"
+                    }
+                ]
+            },
+            {
+                "name": "bar-0.1.0",
+                "url": "https://crates.io/crates/bar/0.1.0",
+                "krate": {"Registry": {"name": "bar", "version": "0.1.0"}},
+                "res": "spurious-regressed",
+                "runs": [
+                    {"res": "test-pass", "log": "stable/reg/bar-0.1.0"},
+                    {"res": "test-fail:timeout", "log": "beta/reg/bar-0.1.0"}
+                ]
+            },
+            {
+                "name": "rust-lang/baz",
+                "url": "https://github.com/rust-lang/baz",
+                "krate": {"GitHub": {"org": "rust-lang", "name": "baz"}},
+                "res": "broken",
+                "runs": [
+                    {"res": "broken:cargo-toml", "log": "stable/gh/rust-lang.baz"},
+                    {"res": "broken:cargo-toml", "log": "beta/gh/rust-lang.baz"}
+                ]
+            }
+        ]}"#;
+
+        let results = parse_results(json).unwrap();
+        let [_, end] = results.crates[0].runs();
+        let codes = ["E0308", "E0599"]
+            .into_iter()
+            .map(|code| code.parse().unwrap())
+            .collect();
+        assert_eq!(
+            end.unwrap().result(),
+            &TestResult::BuildFail(FailureReason::CompilerError(codes))
+        );
+        let [start, _] = results.crates[2].runs();
+        assert_eq!(
+            start.unwrap().result(),
+            &TestResult::BrokenCrate(BrokenReason::CargoToml)
+        );
+
+        let analyzed = analyze_report(results);
+        assert_eq!(analyzed.info[&Comparison::Regressed], 1);
+        assert_eq!(analyzed.info[&Comparison::SpuriousRegressed], 1);
+        assert_eq!(analyzed.info[&Comparison::Broken], 1);
+    }
+
+    #[test]
+    fn test_classify() {
+        let krate = Crate::Local("foo".into());
+        let fail = TestResult::BuildFail(FailureReason::Unknown);
+        let oom = TestResult::BuildFail(FailureReason::OOM);
+        let compare =
+            |start, end| classify(ComparisonPolicy::Lenient, &krate, Some(start), Some(end));
+        assert_eq!(compare(&TestResult::TestPass, &fail), Comparison::Regressed);
+        assert_eq!(compare(&fail, &TestResult::TestPass), Comparison::Fixed);
+        assert_eq!(
+            compare(&TestResult::TestPass, &oom),
+            Comparison::SpuriousRegressed
+        );
+    }
+}
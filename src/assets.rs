@@ -61,22 +61,36 @@ load_files! {
         "ui/layout.html",
 
         "ui/agents.html",
+        "ui/costs.html",
+        "ui/crate-lists.html",
 
         "ui/queue.html",
         "ui/experiment.html",
+        "ui/triage.html",
+        "ui/results.html",
+        "ui/diff.html",
+        "ui/search.html",
+        "ui/subscription.html",
 
+        "ui/400.html",
         "ui/404.html",
         "ui/500.html",
 
         "report/layout.html",
+        "report/artifact-sizes.html",
+        "report/new-warnings.html",
+        "report/non-reproducible.html",
         "report/downloads.html",
+        "report/index.html",
         "report/results.html",
+        "report/client.html",
     ],
     assets: [
         "ui.css" => mime::TEXT_CSS,
 
         "report.css" => mime::TEXT_CSS,
         "report.js" => mime::TEXT_JAVASCRIPT,
+        "report-client.js" => mime::TEXT_JAVASCRIPT,
 
         "favicon.ico" => "image/x-icon".parse().unwrap(),
     ],
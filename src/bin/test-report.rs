@@ -13,14 +13,7 @@ fn main() {
 
 #[cfg(unix)]
 fn main() {
-    let mut env = env_logger::Builder::new();
-    env.filter_module("test_report", log::LevelFilter::Info);
-    env.filter_module("crater", log::LevelFilter::Info);
-    env.filter_module("rustwide", log::LevelFilter::Info);
-    if let Ok(content) = std::env::var("RUST_LOG") {
-        env.parse_filters(&content);
-    }
-    rustwide::logging::init_with(env.build());
+    crater::utils::logging::init(&["test_report", "crater"]);
     let config: Config = toml::from_str(&std::fs::read_to_string("config.toml").unwrap()).unwrap();
     let db = crater::db::Database::open_at(std::path::Path::new("crater.db")).unwrap();
     let experiments = db
@@ -36,20 +29,20 @@ fn main() {
     let ex = experiments.iter().find(|e| e.name == "pr-118920").unwrap();
     let rdb = crater::results::DatabaseDB::new(&db);
 
-    log::info!("Getting crates...");
+    tracing::info!("Getting crates...");
 
     let crates = ex.get_crates(&db).unwrap();
     let writer = NullWriter;
 
-    log::info!("Starting report generation...");
-    log::info!(
+    tracing::info!("Starting report generation...");
+    tracing::info!(
         "@ {:?}",
         nix::sys::resource::getrusage(nix::sys::resource::UsageWho::RUSAGE_SELF)
             .unwrap()
             .max_rss()
     );
-    crater::report::gen(&rdb, ex, &crates, &writer, &config, false).unwrap();
-    log::info!(
+    crater::report::gen(&rdb, ex, &crates, &writer, &config, false, None, None).unwrap();
+    tracing::info!(
         "@ {:?}",
         nix::sys::resource::getrusage(nix::sys::resource::UsageWho::RUSAGE_SELF)
             .unwrap()
@@ -9,24 +9,29 @@
 //! application state employs ownership techniques to ensure that
 //! parallel access is consistent and race-free.
 
-use anyhow::{bail, Error, Result};
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use crater::actions::{self, Action, ActionsCtx};
 use crater::agent::{self, Capabilities};
 use crater::config::Config;
-use crater::crates::Crate;
+use crater::crates::{Crate, CratePattern, DependencyPatch};
 use crater::db::Database;
-use crater::experiments::{Assignee, CapLints, DeferredCrateSelect, Experiment, Mode, Status};
-use crater::report;
+use crater::experiments::{
+    Assignee, CapLints, ComparisonPolicy, Deadline, DeferredCrateSelect, Experiment, FollowUp,
+    Mode, Status, TestOptions,
+};
+use crater::report::{self, Comparison};
 use crater::results::{DatabaseDB, DeleteResults};
-use crater::runner;
+use crater::runner::{self, container::ContainerRuntime, Threads};
 use crater::server;
 use crater::toolchain::Toolchain;
 use rustwide::{cmd::SandboxImage, Workspace, WorkspaceBuilder};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 
 // An experiment name
@@ -89,6 +94,12 @@ pub enum Crater {
     CreateLists {
         #[clap(name = "lists")]
         lists: Vec<String>,
+        #[clap(
+            name = "force",
+            long = "force",
+            help = "Fetch the lists even if their sources didn't change since the last update."
+        )]
+        force: bool,
     },
 
     #[clap(name = "define-ex", about = "define an experiment")]
@@ -107,8 +118,9 @@ pub enum Crater {
             help = "The set of crates on which the experiment will run.",
             long_help = "The set of crates on which the experiment will run.\n\n\
                          This can be one of (full, demo, random-{d}, top-{d}, local) \
-                         where {d} is a positive integer, or \"list:\" followed \
-                         by a comma-separated list of crates.",
+                         where {d} is a positive integer, \"list:\" followed \
+                         by a comma-separated list of crates, or \"lockfile:\" followed \
+                         by the URL of a Cargo.lock.",
             default_value = "demo"
         )]
         crates: DeferredCrateSelect,
@@ -126,6 +138,106 @@ pub enum Crater {
         assign: Option<Assignee>,
         #[clap(name = "requirement", long = "requirement")]
         requirement: Option<String>,
+        #[clap(
+            name = "follow-up",
+            long = "follow-up",
+            help = "Experiment automatically created once this one completes, with its settings \
+                    (retry-regressed:mode=check-only,priority=10)"
+        )]
+        follow_up: Option<FollowUp>,
+        #[clap(
+            name = "sandbox-image",
+            long = "sandbox-image",
+            help = "Docker image used for the sandbox, from the allowed images of the config."
+        )]
+        sandbox_image: Option<String>,
+        #[clap(
+            name = "zulip-stream",
+            long = "zulip-stream",
+            help = "Zulip stream the experiment events are mirrored to."
+        )]
+        zulip_stream: Option<String>,
+        #[clap(
+            name = "repeat",
+            long = "repeat",
+            help = "Run the test suite of each crate this many times to measure flakiness."
+        )]
+        repeat: Option<u32>,
+        #[clap(
+            name = "include-ignored",
+            long = "include-ignored",
+            help = "Also run the tests marked with #[ignore]."
+        )]
+        include_ignored: bool,
+        #[clap(
+            name = "test-threads",
+            long = "test-threads",
+            help = "Number of threads used by the test harness of each crate."
+        )]
+        test_threads: Option<u32>,
+        #[clap(
+            name = "test-filter",
+            long = "test-filter",
+            help = "Only run the tests whose name contains this filter (can be repeated)."
+        )]
+        test_filters: Vec<String>,
+        #[clap(
+            name = "deadline",
+            long = "deadline",
+            help = "Skip the crates not tested yet once the experiment ran this long (like 72h)."
+        )]
+        deadline: Option<Deadline>,
+        #[clap(
+            name = "comparison-policy",
+            long = "comparison-policy",
+            help = "How the report categorizes corner cases (lenient, strict, lint-experiment).",
+            default_value_t = ComparisonPolicy::Lenient
+        )]
+        comparison_policy: ComparisonPolicy,
+        #[clap(
+            name = "registry-overlay",
+            long = "registry-overlay",
+            help = "URL of a .crate file replacing the crates.io version (can be repeated)."
+        )]
+        registry_overlay: Vec<String>,
+        #[clap(
+            name = "build-cache",
+            long = "build-cache",
+            help = "Compile the crates through the sccache disk cache of the agents."
+        )]
+        build_cache: bool,
+        #[clap(
+            name = "track-warnings",
+            long = "track-warnings",
+            help = "Record the warnings of the builds, to report the new ones."
+        )]
+        track_warnings: bool,
+        #[clap(
+            name = "client-side-report",
+            long = "client-side-report",
+            help = "Render the pages of the report in the browser, from a JSON file of the results."
+        )]
+        client_side_report: bool,
+        #[clap(
+            name = "patch",
+            long = "patch",
+            help = "Replace a dependency in all the builds, as `name=git-url=branch` or \
+                    `name=/absolute/path` (can be repeated)."
+        )]
+        dependency_patches: Vec<DependencyPatch>,
+        #[clap(
+            name = "exclude",
+            long = "exclude",
+            help = "Exclude the crates matching a glob like `win-*`, or a regex like `/^win/` \
+                    (can be repeated)."
+        )]
+        exclude: Vec<CratePattern>,
+        #[clap(
+            name = "tag",
+            long = "tag",
+            help = "Tag the experiment, like `beta-1.79` (can be repeated)."
+        )]
+        tags: Vec<String>,
     },
 
     #[clap(name = "edit", about = "edit an experiment configuration")]
@@ -168,6 +280,186 @@ pub enum Crater {
         assign: Option<Assignee>,
         #[clap(name = "requirement", long = "requirement")]
         requirement: Option<String>,
+        #[clap(
+            name = "follow-up",
+            long = "follow-up",
+            help = "Experiment automatically created once this one completes, with its settings \
+                    (retry-regressed:mode=check-only,priority=10)"
+        )]
+        follow_up: Option<FollowUp>,
+        #[clap(
+            name = "sandbox-image",
+            long = "sandbox-image",
+            help = "Docker image used for the sandbox, from the allowed images of the config."
+        )]
+        sandbox_image: Option<String>,
+        #[clap(
+            name = "zulip-stream",
+            long = "zulip-stream",
+            help = "Zulip stream the experiment events are mirrored to."
+        )]
+        zulip_stream: Option<String>,
+        #[clap(
+            name = "repeat",
+            long = "repeat",
+            help = "Run the test suite of each crate this many times to measure flakiness."
+        )]
+        repeat: Option<u32>,
+        #[clap(
+            name = "include-ignored",
+            long = "include-ignored",
+            help = "Also run the tests marked with #[ignore].",
+            conflicts_with = "no-include-ignored"
+        )]
+        include_ignored: bool,
+        #[clap(
+            name = "no-include-ignored",
+            long = "no-include-ignored",
+            conflicts_with = "include-ignored"
+        )]
+        no_include_ignored: bool,
+        #[clap(
+            name = "test-threads",
+            long = "test-threads",
+            help = "Number of threads used by the test harness of each crate."
+        )]
+        test_threads: Option<u32>,
+        #[clap(
+            name = "test-filter",
+            long = "test-filter",
+            help = "Only run the tests whose name contains this filter (can be repeated)."
+        )]
+        test_filters: Option<Vec<String>>,
+        #[clap(
+            name = "deadline",
+            long = "deadline",
+            help = "Skip the crates not tested yet once the experiment ran this long (like 72h)."
+        )]
+        deadline: Option<Deadline>,
+        #[clap(
+            name = "comparison-policy",
+            long = "comparison-policy",
+            help = "How the report categorizes corner cases (lenient, strict, lint-experiment)."
+        )]
+        comparison_policy: Option<ComparisonPolicy>,
+        #[clap(
+            name = "build-cache",
+            long = "build-cache",
+            help = "Compile the crates through the sccache disk cache of the agents.",
+            conflicts_with = "no-build-cache"
+        )]
+        build_cache: bool,
+        #[clap(
+            name = "no-build-cache",
+            long = "no-build-cache",
+            conflicts_with = "build-cache"
+        )]
+        no_build_cache: bool,
+        #[clap(
+            name = "track-warnings",
+            long = "track-warnings",
+            help = "Record the warnings of the builds, to report the new ones.",
+            conflicts_with = "no-track-warnings"
+        )]
+        track_warnings: bool,
+        #[clap(
+            name = "no-track-warnings",
+            long = "no-track-warnings",
+            conflicts_with = "track-warnings"
+        )]
+        no_track_warnings: bool,
+        #[clap(
+            name = "client-side-report",
+            long = "client-side-report",
+            help = "Render the pages of the report in the browser, from a JSON file of the \
+                    results.",
+            conflicts_with = "no-client-side-report"
+        )]
+        client_side_report: bool,
+        #[clap(
+            name = "no-client-side-report",
+            long = "no-client-side-report",
+            conflicts_with = "client-side-report"
+        )]
+        no_client_side_report: bool,
+        #[clap(
+            name = "patch",
+            long = "patch",
+            help = "Replace a dependency in all the builds, as `name=git-url=branch` or \
+                    `name=/absolute/path` (can be repeated, replaces the current patches).",
+            conflicts_with = "no-patches"
+        )]
+        dependency_patches: Option<Vec<DependencyPatch>>,
+        #[clap(
+            name = "no-patches",
+            long = "no-patches",
+            help = "Remove the dependency patches of the experiment.",
+            conflicts_with = "patch"
+        )]
+        no_patches: bool,
+        #[clap(
+            name = "exclude",
+            long = "exclude",
+            help = "Exclude the crates matching a glob like `win-*`, or a regex like `/^win/` \
+                    (can be repeated, replaces the current patterns).",
+            conflicts_with = "no-exclude"
+        )]
+        exclude: Option<Vec<CratePattern>>,
+        #[clap(
+            name = "no-exclude",
+            long = "no-exclude",
+            help = "Remove the exclusion patterns of the experiment.",
+            conflicts_with = "exclude"
+        )]
+        no_exclude: bool,
+        #[clap(
+            name = "tag",
+            long = "tag",
+            help = "Tag the experiment, like `beta-1.79` (can be repeated, replaces the current \
+                    tags).",
+            conflicts_with = "no-tags"
+        )]
+        tags: Option<Vec<String>>,
+        #[clap(
+            name = "no-tags",
+            long = "no-tags",
+            help = "Remove the tags of the experiment.",
+            conflicts_with = "tag"
+        )]
+        no_tags: bool,
+    },
+
+    #[clap(
+        name = "clone-ex",
+        about = "define an experiment with the configuration and the crates of another one"
+    )]
+    CloneEx {
+        #[clap(name = "source")]
+        source: Ex,
+        #[clap(name = "name")]
+        name: String,
+        #[clap(name = "toolchain-start", long = "start")]
+        tc1: Option<Toolchain>,
+        #[clap(name = "toolchain-end", long = "end")]
+        tc2: Option<Toolchain>,
+        #[clap(name = "mode", long = "mode")]
+        mode: Option<Mode>,
+        #[clap(
+            name = "crates",
+            long = "crates",
+            help = "The set of crates on which the experiment will run, instead of the crates of \
+                    the source experiment."
+        )]
+        crates: Option<DeferredCrateSelect>,
+        #[clap(name = "priority", long = "priority", short = 'p')]
+        priority: Option<i32>,
+        #[clap(
+            name = "tag",
+            long = "tag",
+            help = "Tag the experiment, like `beta-1.79` (can be repeated, replaces the tags of \
+                    the source experiment)."
+        )]
+        tags: Option<Vec<String>>,
     },
 
     #[clap(name = "delete-ex", about = "delete shared data for experiment")]
@@ -176,6 +468,17 @@ pub enum Crater {
         ex: Ex,
     },
 
+    #[clap(
+        name = "verify-ex",
+        about = "check the consistency of the data of an experiment"
+    )]
+    VerifyEx {
+        #[clap(name = "name")]
+        name: String,
+        #[clap(name = "fix", long = "fix")]
+        fix: bool,
+    },
+
     #[clap(
         name = "delete-all-results",
         about = "delete all results for an experiment"
@@ -206,6 +509,13 @@ pub enum Crater {
         threads: usize,
         #[clap(name = "docker-env", long = "docker-env")]
         docker_env: Option<String>,
+        #[clap(
+            name = "container-runtime",
+            long = "container-runtime",
+            default_value_t = ContainerRuntime::Docker,
+            help = "Container runtime used for the sandbox (docker, podman)."
+        )]
+        container_runtime: ContainerRuntime,
         #[clap(name = "fast-workspace-init", long = "fast-workspace-init")]
         fast_workspace_init: bool,
     },
@@ -222,6 +532,37 @@ pub enum Crater {
         output_templates: bool,
     },
 
+    #[clap(
+        name = "diff-reports",
+        about = "list the crates whose outcome changed between two experiments"
+    )]
+    DiffReports {
+        #[clap(name = "before")]
+        before: Ex,
+        #[clap(name = "after")]
+        after: Ex,
+    },
+
+    #[clap(
+        name = "diff-unstable-features",
+        about = "compare the usage of the unstable features between two unstable-features experiments"
+    )]
+    DiffUnstableFeatures {
+        #[clap(name = "before")]
+        before: Ex,
+        #[clap(name = "after")]
+        after: Ex,
+    },
+
+    #[clap(
+        name = "ex",
+        about = "inspect the experiments stored in the local database"
+    )]
+    Ex {
+        #[clap(subcommand)]
+        command: ExCommand,
+    },
+
     #[clap(name = "server")]
     Server {
         #[clap(
@@ -231,6 +572,20 @@ pub enum Crater {
             help = "The address and port to bind to."
         )]
         bind: Option<SocketAddr>,
+        #[clap(
+            name = "read-only",
+            long = "read-only",
+            requires = "db-replica",
+            help = "Serve the UI and the read-only APIs, refusing all the changes."
+        )]
+        read_only: bool,
+        #[clap(
+            name = "db-replica",
+            long = "db-replica",
+            requires = "read-only",
+            help = "Replica of the database to serve in read-only mode."
+        )]
+        db_replica: Option<PathBuf>,
     },
 
     #[clap(name = "agent")]
@@ -241,8 +596,31 @@ pub enum Crater {
         token: String,
         #[clap(name = "threads", short = 't', long = "threads", default_value = "1")]
         threads: usize,
+        #[clap(
+            name = "max-threads",
+            long = "max-threads",
+            help = "Tune the number of threads between --min-threads and this, depending on the \
+                    memory pressure, the load and the OOM kills of the host, starting from \
+                    --threads."
+        )]
+        max_threads: Option<usize>,
+        #[clap(
+            name = "min-threads",
+            long = "min-threads",
+            default_value = "1",
+            requires = "max-threads",
+            help = "Minimum number of threads when tuning them."
+        )]
+        min_threads: usize,
         #[clap(name = "docker-env", long = "docker-env")]
         docker_env: Option<String>,
+        #[clap(
+            name = "container-runtime",
+            long = "container-runtime",
+            default_value_t = ContainerRuntime::Docker,
+            help = "Container runtime used for the sandbox (docker, podman)."
+        )]
+        container_runtime: ContainerRuntime,
         #[clap(name = "fast-workspace-init", long = "fast-workspace-init")]
         fast_workspace_init: bool,
         #[clap(
@@ -260,6 +638,67 @@ pub enum Crater {
             help = "Disables the default capabilities for this platform."
         )]
         no_default_capabilities: bool,
+        #[clap(
+            name = "crate-cache",
+            long = "crate-cache",
+            help = "URL of the crate cache proxy to fetch crates and git repositories from."
+        )]
+        crate_cache: Option<String>,
+        #[clap(
+            name = "once",
+            long = "once",
+            help = "Exit after testing a single crate."
+        )]
+        once: bool,
+        #[clap(
+            name = "self-test",
+            long = "self-test",
+            help = "Check the sandbox works on this host, print a JSON summary and exit."
+        )]
+        self_test: bool,
+    },
+
+    #[clap(
+        name = "run-crate",
+        about = "run a single crate of an experiment locally, printing its logs and results"
+    )]
+    RunCrate {
+        #[clap(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+        #[clap(name = "crate", long = "crate")]
+        krate: Crate,
+        #[clap(
+            name = "toolchain",
+            long = "toolchain",
+            short = 't',
+            help = "Only test this toolchain of the experiment."
+        )]
+        tc: Option<Toolchain>,
+        #[clap(name = "docker-env", long = "docker-env")]
+        docker_env: Option<String>,
+        #[clap(
+            name = "container-runtime",
+            long = "container-runtime",
+            default_value_t = ContainerRuntime::Docker,
+            help = "Container runtime used for the sandbox (docker, podman)."
+        )]
+        container_runtime: ContainerRuntime,
+        #[clap(name = "fast-workspace-init", long = "fast-workspace-init")]
+        fast_workspace_init: bool,
+    },
+
+    #[clap(
+        name = "crate-cache",
+        about = "run a caching proxy for crate tarballs and git repositories"
+    )]
+    CrateCache {
+        #[clap(
+            name = "bind",
+            long = "bind",
+            short = 'b',
+            help = "The address and port to bind to."
+        )]
+        bind: Option<SocketAddr>,
     },
 
     #[clap(
@@ -272,10 +711,256 @@ pub enum Crater {
     },
 }
 
+#[derive(clap::Subcommand)]
+pub enum ExCommand {
+    #[clap(name = "list", about = "list all the experiments")]
+    List {
+        #[clap(name = "json", long = "json", help = "Print JSON instead of a table.")]
+        json: bool,
+        #[clap(
+            name = "tag",
+            long = "tag",
+            help = "Only list the experiments with this tag."
+        )]
+        tag: Option<String>,
+    },
+
+    #[clap(
+        name = "show",
+        about = "show the configuration and progress of an experiment"
+    )]
+    Show {
+        #[clap(name = "experiment")]
+        ex: Ex,
+        #[clap(name = "json", long = "json", help = "Print JSON instead of a table.")]
+        json: bool,
+    },
+
+    #[clap(
+        name = "results",
+        about = "list the results of the crates of an experiment"
+    )]
+    Results {
+        #[clap(name = "experiment")]
+        ex: Ex,
+        #[clap(
+            name = "filter",
+            long = "filter",
+            help = "Only list the crates with this outcome, like `regressed`."
+        )]
+        filter: Option<Comparison>,
+        #[clap(name = "json", long = "json", help = "Print JSON instead of a table.")]
+        json: bool,
+    },
+}
+
+#[derive(serde_derive::Serialize)]
+struct ExperimentSummary {
+    name: String,
+    status: Status,
+    mode: Mode,
+    toolchains: [String; 2],
+    tags: Vec<String>,
+    priority: i32,
+    progress: u8,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde_derive::Serialize)]
+struct ExperimentDetails {
+    #[serde(flatten)]
+    experiment: Experiment,
+    completed_jobs: u32,
+    total_jobs: u32,
+    results: BTreeMap<String, u32>,
+}
+
+/// Print the rows aligned in columns, below the header.
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths = header.iter().map(|cell| cell.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let print_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+impl ExCommand {
+    fn run(&self) -> Result<()> {
+        let db = Database::open()?;
+        let get = |ex: &Ex| {
+            Experiment::get(&db, &ex.0)?.ok_or_else(|| anyhow!("missing experiment: {}", ex.0))
+        };
+
+        match *self {
+            ExCommand::List { json, ref tag } => {
+                let mut summaries = Vec::new();
+                for ex in Experiment::all(&db)? {
+                    if tag.as_ref().is_some_and(|tag| !ex.tags.contains(tag)) {
+                        continue;
+                    }
+                    summaries.push(ExperimentSummary {
+                        progress: ex.progress(&db)?,
+                        toolchains: [ex.toolchains[0].to_string(), ex.toolchains[1].to_string()],
+                        name: ex.name,
+                        tags: ex.tags,
+                        status: ex.status,
+                        mode: ex.mode,
+                        priority: ex.priority,
+                        created_at: ex.created_at,
+                    });
+                }
+
+                if json {
+                    return print_json(&summaries);
+                }
+                let rows = summaries
+                    .iter()
+                    .map(|ex| {
+                        vec![
+                            ex.name.clone(),
+                            ex.status.to_string(),
+                            ex.mode.to_string(),
+                            ex.toolchains[0].clone(),
+                            ex.toolchains[1].clone(),
+                            if ex.tags.is_empty() {
+                                "-".into()
+                            } else {
+                                ex.tags.join(",")
+                            },
+                            ex.priority.to_string(),
+                            format!("{}%", ex.progress),
+                            ex.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                print_table(
+                    &[
+                        "NAME", "STATUS", "MODE", "START", "END", "TAGS", "PRIORITY", "PROGRESS",
+                        "CREATED",
+                    ],
+                    &rows,
+                );
+            }
+            ExCommand::Show { ref ex, json } => {
+                let ex = get(ex)?;
+                let (completed_jobs, total_jobs) = ex.raw_progress(&db)?;
+                let results = ex
+                    .get_result_counts(&db)?
+                    .into_iter()
+                    .map(|(result, count)| (result.to_string(), count))
+                    .collect::<BTreeMap<_, _>>();
+
+                if json {
+                    return print_json(&ExperimentDetails {
+                        experiment: ex,
+                        completed_jobs,
+                        total_jobs,
+                        results,
+                    });
+                }
+                let optional = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+                let time = |time: Option<DateTime<Utc>>| {
+                    optional(time.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()))
+                };
+                let mut rows = vec![
+                    ("name", ex.name.clone()),
+                    ("status", ex.status.to_string()),
+                    ("mode", ex.mode.to_string()),
+                    ("start toolchain", ex.toolchains[0].to_string()),
+                    ("end toolchain", ex.toolchains[1].to_string()),
+                    ("cap lints", ex.cap_lints.to_string()),
+                    ("priority", ex.priority.to_string()),
+                    (
+                        "assigned to",
+                        optional(ex.assigned_to.as_ref().map(|a| a.to_string())),
+                    ),
+                    ("requirement", optional(ex.requirement.clone())),
+                    (
+                        "tags",
+                        optional(Some(ex.tags.join(",")).filter(|tags| !tags.is_empty())),
+                    ),
+                    ("created at", time(Some(ex.created_at))),
+                    ("started at", time(ex.started_at)),
+                    ("completed at", time(ex.completed_at)),
+                    (
+                        "github issue",
+                        optional(ex.github_issue.as_ref().map(|i| i.html_url.clone())),
+                    ),
+                    ("report", optional(ex.report_url.clone())),
+                    (
+                        "progress",
+                        format!(
+                            "{completed_jobs}/{total_jobs} jobs ({}%)",
+                            ex.progress(&db)?
+                        ),
+                    ),
+                ]
+                .into_iter()
+                .map(|(field, value)| vec![field.to_string(), value])
+                .collect::<Vec<_>>();
+                rows.extend(
+                    results.into_iter().map(|(result, count)| {
+                        vec![format!("results: {result}"), count.to_string()]
+                    }),
+                );
+                print_table(&["FIELD", "VALUE"], &rows);
+            }
+            ExCommand::Results {
+                ref ex,
+                filter,
+                json,
+            } => {
+                let config = Config::load()?;
+                let ex = get(ex)?;
+                let mut outcomes = report::crate_outcomes(&db, &config, &ex)?;
+                if let Some(filter) = filter {
+                    outcomes.retain(|outcome| outcome.comparison == filter);
+                }
+
+                if json {
+                    return print_json(&outcomes);
+                }
+                let rows = outcomes
+                    .into_iter()
+                    .map(|outcome| {
+                        vec![
+                            outcome.krate,
+                            outcome.start.unwrap_or_else(|| "-".into()),
+                            outcome.end.unwrap_or_else(|| "-".into()),
+                            outcome.comparison.to_string(),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                print_table(&["CRATE", "START", "END", "OUTCOME"], &rows);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Crater {
     pub fn run(&self) -> Result<()> {
         match *self {
-            Crater::CreateLists { ref lists } => {
+            Crater::CreateLists { ref lists, force } => {
                 let mut lists: HashSet<_> = lists.iter().map(|s| s.as_str()).collect();
 
                 let config = Config::load()?;
@@ -283,12 +968,16 @@ impl Crater {
                 let ctx = ActionsCtx::new(&db, &config);
 
                 let action = if lists.is_empty() {
-                    actions::UpdateLists::default()
+                    actions::UpdateLists {
+                        force,
+                        ..actions::UpdateLists::default()
+                    }
                 } else {
                     actions::UpdateLists {
                         github: lists.remove("github"),
                         registry: lists.remove("registry"),
                         local: lists.remove("local"),
+                        force,
                     }
                 };
 
@@ -315,6 +1004,22 @@ impl Crater {
                 ref ignore_blacklist,
                 ref assign,
                 ref requirement,
+                ref follow_up,
+                ref sandbox_image,
+                ref zulip_stream,
+                repeat,
+                include_ignored,
+                test_threads,
+                ref test_filters,
+                deadline,
+                comparison_policy,
+                ref registry_overlay,
+                build_cache,
+                track_warnings,
+                client_side_report,
+                ref dependency_patches,
+                ref exclude,
+                ref tags,
             } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
@@ -331,6 +1036,25 @@ impl Crater {
                     ignore_blacklist: *ignore_blacklist,
                     assign: assign.clone(),
                     requirement: requirement.clone(),
+                    follow_up: follow_up.clone(),
+                    parent: None,
+                    sandbox_image: sandbox_image.clone(),
+                    zulip_stream: zulip_stream.clone(),
+                    repeat,
+                    test_options: TestOptions {
+                        include_ignored,
+                        test_threads,
+                        filters: test_filters.clone(),
+                    },
+                    deadline,
+                    comparison_policy,
+                    registry_overlay: crater::crates::resolve_overlay(registry_overlay)?,
+                    dependency_patches: dependency_patches.clone(),
+                    exclude: exclude.clone(),
+                    tags: tags.clone(),
+                    build_cache,
+                    track_warnings,
+                    client_side_report,
                 }
                 .apply(&ctx)?;
             }
@@ -346,6 +1070,28 @@ impl Crater {
                 ref no_ignore_blacklist,
                 ref assign,
                 ref requirement,
+                ref follow_up,
+                ref sandbox_image,
+                ref zulip_stream,
+                repeat,
+                include_ignored,
+                no_include_ignored,
+                test_threads,
+                ref test_filters,
+                deadline,
+                comparison_policy,
+                build_cache,
+                no_build_cache,
+                track_warnings,
+                no_track_warnings,
+                client_side_report,
+                no_client_side_report,
+                ref dependency_patches,
+                no_patches,
+                ref exclude,
+                no_exclude,
+                ref tags,
+                no_tags,
             } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
@@ -358,6 +1104,34 @@ impl Crater {
                 } else {
                     None
                 };
+                let include_ignored = if include_ignored {
+                    Some(true)
+                } else if no_include_ignored {
+                    Some(false)
+                } else {
+                    None
+                };
+                let build_cache = if build_cache {
+                    Some(true)
+                } else if no_build_cache {
+                    Some(false)
+                } else {
+                    None
+                };
+                let track_warnings = if track_warnings {
+                    Some(true)
+                } else if no_track_warnings {
+                    Some(false)
+                } else {
+                    None
+                };
+                let client_side_report = if client_side_report {
+                    Some(true)
+                } else if no_client_side_report {
+                    Some(false)
+                } else {
+                    None
+                };
 
                 actions::EditExperiment {
                     name: name.clone(),
@@ -369,19 +1143,93 @@ impl Crater {
                     ignore_blacklist,
                     assign: assign.clone(),
                     requirement: requirement.clone(),
+                    follow_up: follow_up.clone(),
+                    sandbox_image: sandbox_image.clone(),
+                    zulip_stream: zulip_stream.clone(),
+                    repeat,
+                    include_ignored,
+                    test_threads,
+                    test_filters: test_filters.clone(),
+                    deadline,
+                    comparison_policy,
+                    build_cache,
+                    track_warnings,
+                    client_side_report,
+                    dependency_patches: if no_patches {
+                        Some(Vec::new())
+                    } else {
+                        dependency_patches.clone()
+                    },
+                    exclude: if no_exclude {
+                        Some(Vec::new())
+                    } else {
+                        exclude.clone()
+                    },
+                    tags: if no_tags {
+                        Some(Vec::new())
+                    } else {
+                        tags.clone()
+                    },
                 }
                 .apply(&ctx)?;
             }
-            Crater::DeleteEx { ref ex } => {
+            Crater::CloneEx {
+                ref source,
+                ref name,
+                ref tc1,
+                ref tc2,
+                mode,
+                ref crates,
+                priority,
+                ref tags,
+            } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
                 let ctx = ActionsCtx::new(&db, &config);
 
+                let source = Experiment::get(&db, &source.0)?
+                    .ok_or_else(|| anyhow!("missing experiment: {}", source.0))?;
+                let mut create = actions::CreateExperiment::copy_of(&source, name);
+                if let Some(tc) = tc1 {
+                    create.toolchains[0] = tc.clone();
+                }
+                if let Some(tc) = tc2 {
+                    create.toolchains[1] = tc.clone();
+                }
+                if let Some(crates) = crates {
+                    create.crates = crates.clone().resolve()?;
+                }
+                create.mode = mode.unwrap_or(create.mode);
+                create.priority = priority.unwrap_or(create.priority);
+                if let Some(tags) = tags {
+                    create.tags = tags.clone();
+                }
+                create.apply(&ctx)?;
+            }
+            Crater::DeleteEx { ref ex } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let log_store = server::tokens::Tokens::load_log_store()?;
+                let ctx = ActionsCtx::new(&db, &config).with_log_store(log_store.as_deref());
+
                 actions::DeleteExperiment { name: ex.0.clone() }.apply(&ctx)?;
             }
+            Crater::VerifyEx { ref name, fix } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let log_store = server::tokens::Tokens::load_log_store()?;
+                let ctx = ActionsCtx::new(&db, &config).with_log_store(log_store.as_deref());
+
+                actions::VerifyExperiment {
+                    name: name.clone(),
+                    fix,
+                }
+                .apply(&ctx)?;
+            }
             Crater::DeleteAllResults { ref ex } => {
                 let db = Database::open()?;
-                let result_db = DatabaseDB::new(&db);
+                let log_store = server::tokens::Tokens::load_log_store()?;
+                let result_db = DatabaseDB::new(&db).with_log_store(log_store.as_deref());
 
                 if let Some(mut experiment) = Experiment::get(&db, &ex.0)? {
                     result_db.delete_all_results(&experiment)?;
@@ -396,7 +1244,8 @@ impl Crater {
                 ref krate,
             } => {
                 let db = Database::open()?;
-                let result_db = DatabaseDB::new(&db);
+                let log_store = server::tokens::Tokens::load_log_store()?;
+                let result_db = DatabaseDB::new(&db).with_log_store(log_store.as_deref());
 
                 if let Some(mut experiment) = Experiment::get(&db, &ex.0)? {
                     if let Some(tc) = tc {
@@ -416,9 +1265,12 @@ impl Crater {
                 ref ex,
                 threads,
                 ref docker_env,
+                container_runtime,
                 fast_workspace_init,
             } => {
+                container_runtime.configure()?;
                 let config = Config::load()?;
+                container_runtime.check_config(&config)?;
                 let db = Database::open()?;
 
                 if let Some(mut experiment) = Experiment::get(&db, &ex.0)? {
@@ -438,19 +1290,26 @@ impl Crater {
 
                     let result_db = DatabaseDB::new(&db);
 
-                    let workspace = self
-                        .workspace(docker_env.as_ref().map(|s| s.as_str()), fast_workspace_init)?;
+                    // The image of the experiment takes precedence, like on the agents
+                    let image = experiment
+                        .sandbox_image
+                        .as_deref()
+                        .or(docker_env.as_deref());
+                    let workspace = self.workspace(image, fast_workspace_init)?;
                     workspace.purge_all_build_dirs()?;
 
-                    let crates =
-                        std::sync::Mutex::new(experiment.get_uncompleted_crates(&db, None)?);
+                    let crates = std::sync::Mutex::new(
+                        experiment.get_uncompleted_crates(&db, &config, None)?,
+                    );
                     let res = runner::run_ex(
                         &experiment,
                         &workspace,
                         &result_db,
-                        threads,
+                        Threads::fixed(threads),
                         &config,
+                        None,
                         &|| Ok(crates.lock().unwrap().pop()),
+                        &AtomicBool::new(false),
                     );
                     workspace.purge_all_build_dirs()?;
                     res?;
@@ -480,14 +1339,32 @@ impl Crater {
 
                     experiment.set_status(&db, Status::GeneratingReport)?;
 
-                    let result_db = DatabaseDB::new(&db);
-                    let res = report::gen(
+                    // With --force agents might still be recording results, read them from a
+                    // consistent view, released before writing the report
+                    let log_store = server::tokens::Tokens::load_log_store()?;
+                    let (crates, report_data) = {
+                        let snapshot = db.snapshot()?;
+                        let crates = experiment.get_crates(&snapshot)?;
+                        let report_data = report::read_report(
+                            &DatabaseDB::new(&snapshot).with_log_store(log_store.as_deref()),
+                            &experiment,
+                            &crates,
+                            &config,
+                            None,
+                            None,
+                        )?;
+                        (crates, report_data)
+                    };
+                    let result_db = DatabaseDB::new(&db).with_log_store(log_store.as_deref());
+                    let res = report::write_report(
                         &result_db,
+                        report_data,
                         &experiment,
-                        &experiment.get_crates(&db)?,
+                        &crates,
                         &report::FileWriter::create(dest.0.clone())?,
                         &config,
                         output_templates,
+                        None,
                     );
 
                     if let Err(err) = res {
@@ -500,22 +1377,81 @@ impl Crater {
                     bail!("missing experiment: {}", ex.0);
                 }
             }
-            Crater::Server { bind } => {
+            Crater::DiffReports {
+                ref before,
+                ref after,
+            } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+
+                let get = |ex: &Ex| {
+                    Experiment::get(&db, &ex.0)?
+                        .ok_or_else(|| anyhow!("missing experiment: {}", ex.0))
+                };
+                let diff = report::diff_experiments(&db, &config, &get(before)?, &get(after)?)?;
+
+                for krate in &diff.changed {
+                    println!("{}: {} -> {}", krate.krate, krate.before, krate.after);
+                }
+                println!(
+                    "{} crates changed, {} unchanged, {} tested in only one experiment",
+                    diff.changed.len(),
+                    diff.unchanged,
+                    diff.not_shared
+                );
+            }
+            Crater::DiffUnstableFeatures {
+                ref before,
+                ref after,
+            } => {
+                let db = Database::open()?;
+
+                let get = |ex: &Ex| {
+                    Experiment::get(&db, &ex.0)?
+                        .ok_or_else(|| anyhow!("missing experiment: {}", ex.0))
+                };
+                let diff = report::diff_unstable_features(&db, &get(before)?, &get(after)?)?;
+
+                for usage in &diff {
+                    println!(
+                        "{}: {} -> {} crates ({:+})",
+                        usage.feature,
+                        usage.before,
+                        usage.after,
+                        usage.change()
+                    );
+                }
+            }
+            Crater::Ex { ref command } => command.run()?,
+            Crater::Server {
+                bind,
+                read_only,
+                ref db_replica,
+            } => {
                 let config = Config::load()?;
                 server::run(
                     config,
                     bind.unwrap_or_else(|| ([127, 0, 0, 1], 8000).into()),
+                    read_only,
+                    db_replica.as_deref(),
                 )?;
             }
             Crater::Agent {
                 ref url,
                 ref token,
                 threads,
+                max_threads,
+                min_threads,
                 ref docker_env,
+                container_runtime,
                 fast_workspace_init,
                 ref capabilities,
                 no_default_capabilities,
+                ref crate_cache,
+                once,
+                self_test,
             } => {
+                container_runtime.configure()?;
                 let mut caps = if no_default_capabilities {
                     Capabilities::default()
                 } else {
@@ -523,15 +1459,66 @@ impl Crater {
                 };
                 caps.extend(capabilities.clone());
 
+                if self_test {
+                    let report =
+                        agent::self_test(url, token, &caps, container_runtime, &|image| {
+                            self.workspace(image.or(docker_env.as_deref()), fast_workspace_init)
+                        })?;
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                    if !report.ok {
+                        bail!("the self-test of the agent failed");
+                    }
+                    return Ok(());
+                }
+
+                let threads = match max_threads {
+                    Some(max) => Threads::tuned(threads, min_threads, max)?,
+                    None => Threads::fixed(threads),
+                };
                 agent::run(
                     url,
                     token,
                     threads,
                     &caps,
-                    &self
-                        .workspace(docker_env.as_ref().map(|s| s.as_str()), fast_workspace_init)?,
+                    container_runtime,
+                    &|image| self.workspace(image.or(docker_env.as_deref()), fast_workspace_init),
+                    crate_cache.as_deref(),
+                    once,
                 )?;
             }
+            Crater::RunCrate {
+                ref ex,
+                ref krate,
+                ref tc,
+                ref docker_env,
+                container_runtime,
+                fast_workspace_init,
+            } => {
+                container_runtime.configure()?;
+                let config = Config::load()?;
+                container_runtime.check_config(&config)?;
+                let db = Database::open()?;
+
+                if let Some(experiment) = Experiment::get(&db, &ex.0)? {
+                    // The image of the experiment takes precedence, like on the agents
+                    let image = experiment
+                        .sandbox_image
+                        .as_deref()
+                        .or(docker_env.as_deref());
+                    let workspace = self.workspace(image, fast_workspace_init)?;
+                    workspace.purge_all_build_dirs()?;
+
+                    let res =
+                        runner::run_crate(&experiment, &workspace, &config, krate, tc.as_ref());
+                    workspace.purge_all_build_dirs()?;
+                    res?;
+                } else {
+                    bail!("missing experiment {}", ex.0);
+                }
+            }
+            Crater::CrateCache { bind } => {
+                server::crate_cache::run(bind.unwrap_or_else(|| ([127, 0, 0, 1], 8001).into()))?;
+            }
             Crater::CheckConfig { ref filename } => {
                 if let Err(ref e) = Config::check(filename) {
                     bail!("check-config failed: {}", e);
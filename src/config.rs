@@ -1,6 +1,9 @@
 use crate::crates::Crate;
 use crate::prelude::*;
+use crate::results::TestResult;
+use crate::utils::logging::{BuildLog, LogTail};
 use crate::utils::size::Size;
+use chrono::{NaiveDate, Utc};
 use log::LevelFilter;
 use regex::Regex;
 use rustwide::logging::LogStorage;
@@ -32,6 +35,30 @@ pub struct CrateConfig {
     pub quiet: bool,
     #[serde(default = "default_false")]
     pub broken: bool,
+    /// Environment variables set when building and testing the crate.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Result recorded for the crate instead of building it.
+    #[serde(default, rename = "override")]
+    pub result_override: Option<ResultOverride>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResultOverride {
+    pub result: TestResult,
+    /// Why the result is overridden, shown in the logs of the crate.
+    pub reason: String,
+    /// Last day the override applies, after which the crate is built again.
+    #[serde(default)]
+    pub expires: Option<NaiveDate>,
+}
+
+impl ResultOverride {
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires < Utc::now().date_naive())
+    }
 }
 
 fn default_false() -> bool {
@@ -43,6 +70,115 @@ fn default_false() -> bool {
 pub struct ServerConfig {
     pub bot_acl: BotACL,
     pub labels: ServerLabels,
+    /// Minutes after which a crate handed out to an agent without being reported is handed out
+    /// again to the next agent asking for work.
+    #[serde(default = "default_reassign_crates_after")]
+    pub reassign_crates_after: u32,
+    /// Minutes without an heartbeat after which the experiment assigned to an agent is handed
+    /// over to the other agents.
+    #[serde(default = "default_agent_failover_after")]
+    pub agent_failover_after: u32,
+    /// Estimated build time, in minutes, of each batch of crates handed out to the agents.
+    #[serde(default = "default_batch_build_time")]
+    pub batch_build_time: u32,
+    #[serde(default)]
+    pub zulip: ZulipConfig,
+    #[serde(default)]
+    pub issues: IssuesConfig,
+    #[serde(default)]
+    pub shadow_runs: ShadowRunsConfig,
+    /// URL the server is reachable at, used by the links sent outside of its UI.
+    #[serde(default = "default_public_url")]
+    pub public_url: String,
+}
+
+fn default_public_url() -> String {
+    "https://crater.rust-lang.org".into()
+}
+
+fn default_reassign_crates_after() -> u32 {
+    20
+}
+
+fn default_agent_failover_after() -> u32 {
+    30
+}
+
+fn default_batch_build_time() -> u32 {
+    60
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ZulipConfig {
+    /// Stream the experiment events are mirrored to, unless the experiment sets its own.
+    pub stream: Option<String>,
+    /// Topic of the messages, where `{name}` is replaced with the name of the experiment.
+    #[serde(default = "default_zulip_topic")]
+    pub topic: String,
+}
+
+impl Default for ZulipConfig {
+    fn default() -> Self {
+        ZulipConfig {
+            stream: None,
+            topic: default_zulip_topic(),
+        }
+    }
+}
+
+fn default_zulip_topic() -> String {
+    "crater: {name}".into()
+}
+
+/// Re-runs of a sample of the completed crates on a different agent, checking the results are
+/// reproducible across agents.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShadowRunsConfig {
+    /// Fraction of the crates of each experiment to re-run, between 0 (disabled) and 1.
+    #[serde(default)]
+    pub sample_rate: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IssuesConfig {
+    /// Repository the issues about the root regressions are filed in. No issue is filed when
+    /// it's not set.
+    pub repo: Option<String>,
+    /// Only the experiments whose name matches this regex file issues.
+    #[serde(with = "serde_regex", default = "default_issues_experiments")]
+    pub experiments: Regex,
+    /// Post the drafts of the issues on the issue of the experiment instead of filing them.
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Maximum number of issues filed for each experiment, to avoid flooding the repository
+    /// when something went wrong with the experiment.
+    #[serde(default = "default_max_issues")]
+    pub max_issues: usize,
+}
+
+impl Default for IssuesConfig {
+    fn default() -> Self {
+        IssuesConfig {
+            repo: None,
+            experiments: default_issues_experiments(),
+            draft: false,
+            labels: Vec::new(),
+            max_issues: default_max_issues(),
+        }
+    }
+}
+
+fn default_issues_experiments() -> Regex {
+    Regex::new("^beta-").unwrap()
+}
+
+fn default_max_issues() -> usize {
+    20
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -75,6 +211,170 @@ pub struct SandboxConfig {
     pub memory_limit: Size,
     pub build_log_max_size: Size,
     pub build_log_max_lines: usize,
+    /// Amount of the end of the build logs kept when they exceed the limits, out of
+    /// `build_log_max_size`. Without it only the start of the logs is kept.
+    #[serde(default)]
+    pub build_log_tail_size: Option<Size>,
+    #[serde(default)]
+    pub allowed_images: Vec<String>,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Maximum size of the target directories of each worker, after which the least recently
+    /// used ones are purged.
+    #[serde(default)]
+    pub target_dir_quota: Option<Size>,
+    /// Disk cache of the compiled crates, used by the experiments enabling `build-cache`.
+    #[serde(default)]
+    pub build_cache: Option<BuildCacheConfig>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildCacheConfig {
+    /// Path of a statically linked sccache binary on the agents, mounted in the sandbox.
+    pub sccache: PathBuf,
+    /// Maximum size of the cache of each worker thread of the agents.
+    pub max_size: Size,
+}
+
+string_enum!(pub enum NetworkPolicy {
+    Disabled => "disabled",
+    Allowlist => "allowlist",
+});
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// Whether builds can reach the network: never, or only the allowed hosts through a proxy
+    /// run by the agent.
+    pub policy: NetworkPolicy,
+    #[serde(default = "default_allowed_hosts")]
+    pub allowed_hosts: Vec<String>,
+    /// Ports of the allowed hosts the proxy tunnels connections to.
+    #[serde(default = "default_allowed_ports")]
+    pub allowed_ports: Vec<u16>,
+    /// Address of the proxy, which has to be reachable from inside the sandbox.
+    #[serde(default = "default_proxy_host")]
+    pub proxy_host: String,
+    #[serde(default = "default_proxy_port")]
+    pub proxy_port: u16,
+    /// Docker bridge the sandboxes are attached to, which is firewalled so that the proxy is the
+    /// only destination they can reach.
+    #[serde(default = "default_bridge")]
+    pub bridge: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            policy: NetworkPolicy::Disabled,
+            allowed_hosts: default_allowed_hosts(),
+            allowed_ports: default_allowed_ports(),
+            proxy_host: default_proxy_host(),
+            proxy_port: default_proxy_port(),
+            bridge: default_bridge(),
+        }
+    }
+}
+
+fn default_allowed_hosts() -> Vec<String> {
+    vec![
+        "crates.io".into(),
+        "static.crates.io".into(),
+        "github.com".into(),
+    ]
+}
+
+fn default_allowed_ports() -> Vec<u16> {
+    vec![443]
+}
+
+fn default_proxy_host() -> String {
+    // Address of the host on the default Docker bridge network
+    "172.17.0.1".into()
+}
+
+fn default_proxy_port() -> u16 {
+    3128
+}
+
+fn default_bridge() -> String {
+    "docker0".into()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateCacheConfig {
+    pub url: String,
+}
+
+/// Crates excluded from the experiments when they're created. Excluded crates are recorded in the
+/// experiment and listed in its report.
+#[derive(Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateFiltersConfig {
+    /// Exclude the crates whose license expression can't be satisfied without these licenses.
+    #[serde(default)]
+    pub excluded_licenses: Vec<String>,
+    /// Exclude the yanked crate versions.
+    #[serde(default)]
+    pub skip_yanked: bool,
+    /// Exclude from the `full-active` experiments the crates downloaded fewer times than this.
+    #[serde(default)]
+    pub min_downloads: Option<u64>,
+    /// Exclude from the `full-active` experiments the crates without a release in this many
+    /// years.
+    #[serde(default)]
+    pub max_inactive_years: Option<u32>,
+}
+
+/// Installation of the toolchains by the agents when they start running an experiment.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainsConfig {
+    /// Number of times each installation step is attempted, waiting twice as long before each
+    /// retry.
+    #[serde(default = "default_install_attempts")]
+    pub install_attempts: u32,
+    /// Mirror of `https://ci-artifacts.rust-lang.org`, which the CI toolchains are installed
+    /// from when all the attempts with the primary server failed.
+    #[serde(default)]
+    pub ci_mirror: Option<String>,
+}
+
+impl Default for ToolchainsConfig {
+    fn default() -> Self {
+        ToolchainsConfig {
+            install_attempts: default_install_attempts(),
+            ci_mirror: None,
+        }
+    }
+}
+
+fn default_install_attempts() -> u32 {
+    4
+}
+
+/// Generation of the reports of the experiments.
+#[derive(Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportConfig {
+    /// Also upload an HTML version of each log, with the errors highlighted, and link to it from
+    /// the HTML reports instead of the raw log.
+    #[serde(default)]
+    pub html_logs: bool,
+}
+
+/// Share of the crates picked by the `random-N:stratified` crate selection from a range of the
+/// registry list, which is sorted by the number of reverse dependencies.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RandomQuota {
+    /// Rank in the registry list this range ends at (the previous quota's rank is where it starts).
+    /// A quota without a rank covers all the remaining crates, including the GitHub ones.
+    #[serde(default)]
+    pub up_to_rank: Option<u32>,
+    pub share: f64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -86,6 +386,16 @@ pub struct Config {
     pub local_crates: HashMap<String, CrateConfig>,
     pub server: ServerConfig,
     pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub crate_cache: Option<CrateCacheConfig>,
+    #[serde(default)]
+    pub random_quotas: Vec<RandomQuota>,
+    #[serde(default)]
+    pub crate_filters: CrateFiltersConfig,
+    #[serde(default)]
+    pub toolchains: ToolchainsConfig,
+    #[serde(default)]
+    pub report: ReportConfig,
 }
 
 impl Config {
@@ -107,7 +417,7 @@ impl Config {
             Crate::Registry(ref details) => self.crates.get(&details.name),
             Crate::GitHub(ref repo) => self.github_repos.get(&repo.slug()),
             Crate::Local(ref name) => self.local_crates.get(name),
-            Crate::Git(_) | Crate::Path(_) => unimplemented!("unsupported crate"),
+            Crate::Git(_) | Crate::Path(_) => None,
         }
     }
 
@@ -127,6 +437,23 @@ impl Config {
         self.crate_config(c).map(|c| c.broken).unwrap_or(false)
     }
 
+    /// The result the crate is forced to, even if the override expired: reports still need to
+    /// know which results were forced while it applied.
+    pub fn result_override(&self, c: &Crate) -> Option<&ResultOverride> {
+        self.crate_config(c)
+            .and_then(|c| c.result_override.as_ref())
+    }
+
+    pub fn crate_env(&self, c: &Crate) -> HashMap<String, String> {
+        self.crate_config(c)
+            .map(|c| c.env.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn is_sandbox_image_allowed(&self, image: &str) -> bool {
+        self.sandbox.allowed_images.iter().any(|i| i == image)
+    }
+
     pub fn demo_crates(&self) -> &DemoCrates {
         &self.demo_crates
     }
@@ -247,6 +574,11 @@ impl Default for Config {
                 memory_limit: Size::Gigabytes(2),
                 build_log_max_size: Size::Megabytes(1),
                 build_log_max_lines: 1000,
+                build_log_tail_size: None,
+                allowed_images: vec![],
+                network: NetworkConfig::default(),
+                target_dir_quota: None,
+                build_cache: None,
             },
             server: ServerConfig {
                 bot_acl: BotACL {
@@ -258,7 +590,19 @@ impl Default for Config {
                     experiment_queued: "".into(),
                     experiment_completed: "".into(),
                 },
+                reassign_crates_after: 1,
+                agent_failover_after: 30,
+                batch_build_time: 60,
+                zulip: ZulipConfig::default(),
+                issues: IssuesConfig::default(),
+                shadow_runs: ShadowRunsConfig::default(),
+                public_url: default_public_url(),
             },
+            crate_cache: None,
+            random_quotas: vec![],
+            crate_filters: CrateFiltersConfig::default(),
+            toolchains: ToolchainsConfig::default(),
+            report: ReportConfig::default(),
         }
     }
 }
@@ -272,10 +616,35 @@ impl From<&'_ Config> for LogStorage {
     }
 }
 
+impl From<&'_ Config> for BuildLog {
+    fn from(config: &Config) -> BuildLog {
+        let max_size = config.sandbox.build_log_max_size.to_bytes();
+        let max_lines = config.sandbox.build_log_max_lines;
+        let Some(tail_size) = &config.sandbox.build_log_tail_size else {
+            return BuildLog::new(LogStorage::from(config), None);
+        };
+
+        // The lines are shared between the start and the end of the log like the bytes
+        let tail_size = tail_size.to_bytes().min(max_size);
+        let tail_lines = (max_lines as u64 * tail_size as u64 / max_size.max(1) as u64)
+            .max(1)
+            .min(max_lines as u64) as usize;
+        let (head_size, head_lines) = (max_size - tail_size, max_lines - tail_lines);
+        let mut storage = LogStorage::new(LevelFilter::Info);
+        storage.set_max_size(head_size);
+        storage.set_max_lines(head_lines);
+        BuildLog::new(
+            storage,
+            Some(LogTail::new(head_size, head_lines, tail_size, tail_lines)),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Config;
     use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+    use crate::results::{FailureReason, TestResult};
 
     #[test]
     fn test_config() {
@@ -298,6 +667,9 @@ mod tests {
             "build-log-max-lines = 1000\n",
             "[crates]\n",
             "lazy_static = { skip = true }\n",
+            "prost-build = { env = { PROTOC = \"/usr/bin/protoc\" } }\n",
+            "foo = { override = { result = \"build-fail:unknown\", reason = \"nope\" } }\n",
+            "bar = { override = { result = \"test-pass\", reason = \"old\", expires = \"2020-01-01\" } }\n",
             "[github-repos]\n",
             "\"rust-lang/rust\" = { quiet = true }\n", // :(
             "[local-crates]\n"
@@ -314,6 +686,36 @@ mod tests {
             version: "42".into(),
         })));
 
+        let prost_build = Crate::Registry(RegistryCrate {
+            name: "prost-build".into(),
+            version: "0.12.0".into(),
+        });
+        assert_eq!(
+            list.crate_env(&prost_build)
+                .get("PROTOC")
+                .map(String::as_str),
+            Some("/usr/bin/protoc")
+        );
+        assert!(list
+            .crate_env(&Crate::Registry(RegistryCrate {
+                name: "rand".into(),
+                version: "42".into(),
+            }))
+            .is_empty());
+
+        let result_override = |name: &str| {
+            list.result_override(&Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "42".into(),
+            }))
+        };
+        let foo = result_override("foo").unwrap();
+        assert_eq!(foo.result, TestResult::BuildFail(FailureReason::Unknown));
+        assert_eq!(foo.reason, "nope");
+        assert!(!foo.is_expired());
+        assert!(result_override("bar").unwrap().is_expired());
+        assert!(result_override("rand").is_none());
+
         assert!(list.is_quiet(&Crate::GitHub(GitHubRepo {
             org: "rust-lang".into(),
             name: "rust".into(),
@@ -0,0 +1,82 @@
+use crate::config::CrateCacheConfig;
+use crate::crates::{GitHubRepo, RegistryCrate};
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::utils;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+// rustwide doesn't download registry crates that are already present in its cache, so tarballs
+// fetched from the crate cache are stored where rustwide would have put them.
+pub(super) fn rustwide_cache_path(krate: &RegistryCrate) -> PathBuf {
+    WORK_DIR
+        .join("cache")
+        .join("cratesio-sources")
+        .join(&krate.name)
+        .join(format!("{}-{}.crate", krate.name, krate.version))
+}
+
+impl CrateCacheConfig {
+    fn base_url(&self) -> &str {
+        self.url.trim_end_matches('/')
+    }
+
+    /// Downloads the tarball of a registry crate from the crate cache, unless rustwide already
+    /// has it.
+    pub(crate) fn prefetch(&self, krate: &RegistryCrate) -> Fallible<()> {
+        let dest = rustwide_cache_path(krate);
+        if dest.exists() {
+            return Ok(());
+        }
+
+        let url = format!(
+            "{}/crates/{}/{}/download",
+            self.base_url(),
+            krate.name,
+            krate.version
+        );
+        let mut content = Vec::new();
+        utils::http::get_sync(&url)?.read_to_end(&mut content)?;
+
+        // Write to a temporary file first, to avoid rustwide picking up partial downloads
+        let parent = dest.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let tmp = parent.join(format!(".{}-{}.tmp", krate.name, krate.version));
+        fs::write(&tmp, &content)?;
+        fs::rename(&tmp, &dest)?;
+
+        Ok(())
+    }
+
+    /// URL of the mirror of a GitHub repository served by the crate cache.
+    pub(crate) fn git_url(&self, repo: &GitHubRepo) -> String {
+        format!(
+            "{}/git/github.com/{}/{}",
+            self.base_url(),
+            repo.org,
+            repo.name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CrateCacheConfig;
+    use crate::crates::GitHubRepo;
+
+    #[test]
+    fn test_git_url() {
+        let cache = CrateCacheConfig {
+            url: "http://crate-cache:8000/".into(),
+        };
+        assert_eq!(
+            cache.git_url(&GitHubRepo {
+                org: "rust-lang".into(),
+                name: "crater".into(),
+                sha: None,
+            }),
+            "http://crate-cache:8000/git/github.com/rust-lang/crater"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::crates::cache::rustwide_cache_path;
+use crate::crates::RegistryCrate;
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::utils;
+use crate::utils::hex::to_hex;
+use reqwest::header::ETAG;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+const SPARSE_INDEX_URL: &str = "https://index.crates.io";
+
+#[derive(Debug, thiserror::Error)]
+#[error("checksum mismatch for {name} {version}: expected {expected}, found {found}")]
+pub(crate) struct ChecksumMismatch {
+    name: String,
+    version: String,
+    expected: String,
+    found: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("the tarball of {name} {version} is missing after fetching it")]
+pub(crate) struct MissingTarball {
+    name: String,
+    version: String,
+}
+
+/// Path of the file of a crate in the sparse index, like `se/rd/serde`.
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Local copy of the file of a crate in the sparse index, stored next to its ETag.
+fn index_cache_path(name: &str) -> PathBuf {
+    WORK_DIR
+        .join("cache")
+        .join("sparse-index")
+        .join(index_path(name))
+}
+
+fn find_checksum(content: &[u8], version: &str) -> Fallible<Option<[u8; 32]>> {
+    let index_crate = crates_index::Crate::from_slice(content)?;
+    Ok(index_crate
+        .versions()
+        .iter()
+        .find(|v| v.version() == version)
+        .map(|v| *v.checksum()))
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> Fallible<()> {
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent)?;
+    let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+    tmp.write_all(content)?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Fetch the checksum of a crate version from the crates.io index. The files of the index are
+/// cached locally: the checksums of the published versions never change, so the index is only
+/// requested again for the versions missing from the cached file, and only downloaded again
+/// when it changed.
+fn expected_checksum(krate: &RegistryCrate) -> Fallible<Option<[u8; 32]>> {
+    let path = index_cache_path(&krate.name);
+    let etag_path = path.with_extension("etag");
+    let cached = fs::read(&path).ok();
+    if let Some(ref content) = cached {
+        if let Some(checksum) = find_checksum(content, &krate.version)? {
+            return Ok(Some(checksum));
+        }
+    }
+
+    let etag = cached
+        .as_ref()
+        .and_then(|_| fs::read_to_string(&etag_path).ok());
+    let url = format!("{SPARSE_INDEX_URL}/{}", index_path(&krate.name));
+    let Some(mut resp) = utils::http::get_sync_if_changed(&url, etag.as_deref())? else {
+        // The cached file is up to date, and the version isn't in it
+        return Ok(None);
+    };
+    let etag = resp
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(String::from);
+    let mut content = Vec::new();
+    resp.read_to_end(&mut content)?;
+    let checksum = find_checksum(&content, &krate.version)?;
+
+    write_atomically(&path, &content)?;
+    match etag {
+        Some(etag) => write_atomically(&etag_path, etag.as_bytes())?,
+        None => {
+            if let Err(err) = fs::remove_file(&etag_path) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+    Ok(checksum)
+}
+
+fn compare(krate: &RegistryCrate, expected: &[u8; 32], tarball: &[u8]) -> Fallible<()> {
+    let found = Sha256::digest(tarball);
+    if found.as_slice() != expected {
+        return Err(ChecksumMismatch {
+            name: krate.name.clone(),
+            version: krate.version.clone(),
+            expected: to_hex(expected),
+            found: to_hex(&found),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Verify the tarball of a registry crate downloaded by rustwide against the checksum recorded in
+/// the crates.io index. Corrupted tarballs are removed, so that they're downloaded again the next
+/// time the crate is fetched.
+pub(crate) fn verify_checksum(krate: &RegistryCrate) -> Fallible<()> {
+    let path = rustwide_cache_path(krate);
+    let tarball = match fs::read(&path) {
+        Ok(tarball) => tarball,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(MissingTarball {
+                name: krate.name.clone(),
+                version: krate.version.clone(),
+            }
+            .into());
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to read the tarball {}", path.display()))
+        }
+    };
+
+    // The index being unreachable shouldn't prevent the crate from being tested
+    let expected = match expected_checksum(krate) {
+        Ok(Some(expected)) => expected,
+        Ok(None) => {
+            warn!("{} {} not found in the index", krate.name, krate.version);
+            return Ok(());
+        }
+        Err(err) => {
+            warn!(
+                "failed to fetch the checksum of {} {}: {:?}",
+                krate.name, krate.version, err
+            );
+            return Ok(());
+        }
+    };
+
+    let res = compare(krate, &expected, &tarball);
+    if res.is_err() {
+        fs::remove_file(&path)?;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, find_checksum, index_path, ChecksumMismatch};
+    use crate::crates::RegistryCrate;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), "1/a");
+        assert_eq!(index_path("cc"), "2/cc");
+        assert_eq!(index_path("syn"), "3/s/syn");
+        assert_eq!(index_path("Serde"), "se/rd/serde");
+    }
+
+    #[test]
+    fn test_find_checksum() {
+        let checksum = "a".repeat(64);
+        let content = format!(
+            "{{\"name\":\"foo\",\"vers\":\"1.0.0\",\"deps\":[],\"cksum\":\"{checksum}\",\
+             \"features\":{{}},\"yanked\":false}}\n"
+        );
+        assert_eq!(
+            find_checksum(content.as_bytes(), "1.0.0").unwrap(),
+            Some([0xaa; 32])
+        );
+        assert_eq!(find_checksum(content.as_bytes(), "2.0.0").unwrap(), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        let krate = RegistryCrate {
+            name: "foo".into(),
+            version: "1.0.0".into(),
+        };
+        let expected: [u8; 32] = Sha256::digest(b"tarball").into();
+
+        assert!(compare(&krate, &expected, b"tarball").is_ok());
+        let err = compare(&krate, &expected, b"tampered").unwrap_err();
+        assert!(err.downcast_ref::<ChecksumMismatch>().is_some());
+    }
+}
@@ -0,0 +1,547 @@
+//! Filters excluding crates from the experiments when they're created, according to the
+//! `[crate-filters]` section of the configuration and to the exclusion patterns of the
+//! experiment. Unlike skipped crates, excluded crates are not part of the experiment at all:
+//! they're recorded along with the reason of their exclusion, so that the reports can state
+//! exactly what was filtered out.
+
+use crate::config::{Config, CrateFiltersConfig};
+use crate::crates::lists::open_index;
+use crate::crates::{Crate, CratePattern};
+use crate::dirs::WORK_DIR;
+use crate::experiments::CrateSelect;
+use crate::prelude::*;
+use crate::utils;
+use chrono::{Duration as DateDuration, NaiveDate, Utc};
+use flate2::read::GzDecoder;
+use reqwest::Method;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Dump of the crates.io database, the only source of the licenses of all the crates.
+const DB_DUMP_URL: &str = "https://static.crates.io/db-dump.tar.gz";
+/// The dump is hundreds of megabytes, well beyond the default timeout of the HTTP client.
+const DB_DUMP_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// Age after which the cached dump is downloaded again when creating an experiment. crates.io
+/// publishes a new dump every day, and updating the lists also refreshes it.
+const DB_DUMP_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ExclusionReason {
+    /// The license expression of the crate can't be satisfied without an excluded license.
+    License { license: String },
+    /// The tested version of the crate is yanked.
+    Yanked,
+    /// The crate was downloaded fewer times than required by the `full-active` selection.
+    FewDownloads { downloads: u64 },
+    /// The crate wasn't released recently enough for the `full-active` selection.
+    Inactive { last_release: NaiveDate },
+    /// The crate matches one of the exclusion patterns of the experiment.
+    Pattern { pattern: String },
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExclusionReason::License { license } => write!(f, "licensed under `{license}`"),
+            ExclusionReason::Yanked => write!(f, "yanked"),
+            ExclusionReason::FewDownloads { downloads } => {
+                write!(f, "only downloaded {downloads} times")
+            }
+            ExclusionReason::Inactive { last_release } => {
+                write!(f, "last released on {last_release}")
+            }
+            ExclusionReason::Pattern { pattern } => write!(f, "matching `{pattern}`"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExcludedCrate {
+    pub krate: Crate,
+    pub reason: ExclusionReason,
+}
+
+/// Remove the crates matching one of the exclusion patterns of an experiment from the list,
+/// returning the remaining crates and the excluded ones.
+pub(crate) fn exclude_matching(
+    crates: Vec<Crate>,
+    patterns: &[CratePattern],
+) -> (Vec<Crate>, Vec<ExcludedCrate>) {
+    let mut kept = Vec::new();
+    let mut excluded = Vec::new();
+    for krate in crates {
+        match patterns.iter().find(|pattern| pattern.matches(&krate)) {
+            Some(pattern) => excluded.push(ExcludedCrate {
+                krate,
+                reason: ExclusionReason::Pattern {
+                    pattern: pattern.to_string(),
+                },
+            }),
+            None => kept.push(krate),
+        }
+    }
+    (kept, excluded)
+}
+
+/// Remove the crates excluded by the exclusion patterns of the experiment and by the
+/// configuration from the list, returning the remaining crates and the excluded ones. Only
+/// registry crates can be excluded by the configuration.
+pub(crate) fn filter_crates(
+    crates: Vec<Crate>,
+    select: &CrateSelect,
+    exclude: &[CratePattern],
+    config: &Config,
+) -> Fallible<(Vec<Crate>, Vec<ExcludedCrate>)> {
+    let (crates, mut excluded) = exclude_matching(crates, exclude);
+    let filters = &config.crate_filters;
+    let activity = match select {
+        CrateSelect::FullActive => ActivityThresholds {
+            min_downloads: filters.min_downloads,
+            min_last_release: filters
+                .max_inactive_years
+                .map(|years| Utc::now().date_naive() - DateDuration::days(365 * i64::from(years))),
+        },
+        _ => ActivityThresholds::default(),
+    };
+    if filters.excluded_licenses.is_empty() && !filters.skip_yanked && !activity.is_enabled() {
+        return Ok((crates, excluded));
+    }
+
+    let registry = crates
+        .iter()
+        .filter_map(|krate| match krate {
+            Crate::Registry(krate) => Some((krate.name.as_str(), krate.version.as_str())),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let yanked = if filters.skip_yanked {
+        yanked_versions(&registry)?
+    } else {
+        HashSet::new()
+    };
+    let dump = if filters.excluded_licenses.is_empty() && !activity.is_enabled() {
+        DumpData::default()
+    } else {
+        load_dump(&registry, !filters.excluded_licenses.is_empty())?
+    };
+
+    let mut kept = Vec::new();
+    for krate in crates {
+        let reason = match &krate {
+            Crate::Registry(reg) => {
+                let key = (reg.name.clone(), reg.version.clone());
+                match dump.licenses.get(&key) {
+                    Some(license) if is_license_excluded(license, &filters.excluded_licenses) => {
+                        Some(ExclusionReason::License {
+                            license: license.clone(),
+                        })
+                    }
+                    _ if yanked.contains(&key) => Some(ExclusionReason::Yanked),
+                    _ => activity.check(
+                        dump.downloads.get(&reg.name).copied(),
+                        dump.last_release.get(&reg.name).copied(),
+                    ),
+                }
+            }
+            _ => None,
+        };
+        match reason {
+            Some(reason) => excluded.push(ExcludedCrate { krate, reason }),
+            None => kept.push(krate),
+        }
+    }
+
+    if !excluded.is_empty() {
+        info!("excluded {} crates from the experiment", excluded.len());
+    }
+    Ok((kept, excluded))
+}
+
+/// Find which of the versions are yanked, according to the local copy of the crates.io index.
+fn yanked_versions(versions: &HashSet<(&str, &str)>) -> Fallible<HashSet<(String, String)>> {
+    let index = open_index()?;
+    let mut yanked = HashSet::new();
+    for (name, version) in versions {
+        let Some(krate) = index.crate_(name) else {
+            continue;
+        };
+        if krate
+            .versions()
+            .iter()
+            .any(|v| v.version() == *version && v.is_yanked())
+        {
+            yanked.insert((name.to_string(), version.to_string()));
+        }
+    }
+    Ok(yanked)
+}
+
+/// Thresholds of the `full-active` selection, disabled for the other selections.
+#[derive(Default)]
+struct ActivityThresholds {
+    min_downloads: Option<u64>,
+    min_last_release: Option<NaiveDate>,
+}
+
+impl ActivityThresholds {
+    fn is_enabled(&self) -> bool {
+        self.min_downloads.is_some() || self.min_last_release.is_some()
+    }
+
+    /// Reason to exclude a crate with these downloads and last release date. Crates missing from
+    /// the dump (for example because they were published after it) are kept.
+    fn check(
+        &self,
+        downloads: Option<u64>,
+        last_release: Option<NaiveDate>,
+    ) -> Option<ExclusionReason> {
+        match (self.min_downloads, downloads) {
+            (Some(min), Some(downloads)) if downloads < min => {
+                return Some(ExclusionReason::FewDownloads { downloads })
+            }
+            _ => {}
+        }
+        match (self.min_last_release, last_release) {
+            (Some(min), Some(last_release)) if last_release < min => {
+                Some(ExclusionReason::Inactive { last_release })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DumpCrate {
+    id: u64,
+    name: String,
+    /// Older dumps store the downloads in the crates table instead of `crate_downloads`.
+    #[serde(default)]
+    downloads: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct DumpCrateDownloads {
+    crate_id: u64,
+    downloads: u64,
+}
+
+#[derive(Deserialize)]
+struct DumpVersion {
+    crate_id: u64,
+    num: String,
+    license: Option<String>,
+    created_at: String,
+}
+
+/// Data of the crates.io database dump used by the filters.
+#[derive(Default)]
+struct DumpData {
+    /// License expression of the versions, only loaded when licenses are excluded.
+    licenses: HashMap<(String, String), String>,
+    downloads: HashMap<String, u64>,
+    last_release: HashMap<String, NaiveDate>,
+}
+
+/// Whether the filters of the configuration read the crates.io database dump.
+pub(crate) fn use_db_dump(filters: &CrateFiltersConfig) -> bool {
+    !filters.excluded_licenses.is_empty()
+        || filters.min_downloads.is_some()
+        || filters.max_inactive_years.is_some()
+}
+
+fn db_dump_path() -> PathBuf {
+    WORK_DIR.join("db-dump").join("db-dump.tar.gz")
+}
+
+/// Download the crates.io database dump to the cache, replacing the previous one only once the
+/// download completed.
+pub(crate) fn refresh_db_dump() -> Fallible<()> {
+    let path = db_dump_path();
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+
+    info!("downloading the crates.io database dump");
+    let mut resp = utils::http::prepare_sync(Method::GET, DB_DUMP_URL)
+        .timeout(DB_DUMP_TIMEOUT)
+        .send()?
+        .error_for_status()?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    io::copy(&mut resp, &mut file)?;
+    file.persist(&path)?;
+    Ok(())
+}
+
+/// Open the cached crates.io database dump, downloading it first if it's missing or stale.
+fn open_db_dump() -> Fallible<File> {
+    let path = db_dump_path();
+    let is_fresh = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < DB_DUMP_MAX_AGE);
+    if !is_fresh {
+        refresh_db_dump()?;
+    }
+    Ok(File::open(&path)?)
+}
+
+/// Load the data of the versions and of their crates from the crates.io database dump.
+fn load_dump(versions: &HashSet<(&str, &str)>, load_licenses: bool) -> Fallible<DumpData> {
+    let dump = open_db_dump()?;
+
+    let numbers = versions.iter().map(|(_, num)| *num).collect::<HashSet<_>>();
+    let mut names = HashMap::new();
+    let mut downloads = HashMap::new();
+    let mut last_release: HashMap<u64, NaiveDate> = HashMap::new();
+    let mut licensed = Vec::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(dump));
+    for entry in archive.entries()? {
+        let entry = entry?;
+        // All the tables are stored in the `{date}/data` directory
+        let path = entry.path()?.into_owned();
+        if path.ends_with("data/crates.csv") {
+            for row in csv::Reader::from_reader(entry).deserialize() {
+                let row: DumpCrate = row?;
+                if let Some(count) = row.downloads {
+                    downloads.insert(row.id, count);
+                }
+                names.insert(row.id, row.name);
+            }
+        } else if path.ends_with("data/crate_downloads.csv") {
+            for row in csv::Reader::from_reader(entry).deserialize() {
+                let row: DumpCrateDownloads = row?;
+                downloads.insert(row.crate_id, row.downloads);
+            }
+        } else if path.ends_with("data/versions.csv") {
+            for row in csv::Reader::from_reader(entry).deserialize() {
+                let row: DumpVersion = row?;
+                // Timestamps look like `2019-01-01 12:34:56.789`, only the date is relevant
+                if let Some(Ok(date)) = row.created_at.get(..10).map(|date| date.parse()) {
+                    let last = last_release.entry(row.crate_id).or_insert(date);
+                    *last = (*last).max(date);
+                }
+                if !load_licenses {
+                    continue;
+                }
+                if let Some(license) = row.license.filter(|_| numbers.contains(row.num.as_str())) {
+                    licensed.push((row.crate_id, row.num, license));
+                }
+            }
+        }
+    }
+
+    Ok(DumpData {
+        licenses: licensed
+            .into_iter()
+            .filter_map(|(crate_id, num, license)| {
+                let name = names.get(&crate_id)?;
+                versions
+                    .contains(&(name.as_str(), num.as_str()))
+                    .then(|| ((name.clone(), num), license))
+            })
+            .collect(),
+        downloads: by_name(&names, downloads),
+        last_release: by_name(&names, last_release),
+    })
+}
+
+fn by_name<T>(names: &HashMap<u64, String>, by_id: HashMap<u64, T>) -> HashMap<String, T> {
+    by_id
+        .into_iter()
+        .filter_map(|(id, value)| Some((names.get(&id)?.clone(), value)))
+        .collect()
+}
+
+/// Whether a license expression can't be satisfied without one of the excluded licenses, like
+/// `MIT AND GPL-3.0` when `GPL-3.0` is excluded (unlike `MIT OR GPL-3.0`). Both SPDX expressions
+/// and the legacy `MIT/Apache-2.0` syntax are supported.
+fn is_license_excluded(expression: &str, excluded: &[String]) -> bool {
+    let is_excluded = |license: &str| {
+        let license = license.trim_end_matches('+');
+        excluded
+            .iter()
+            .any(|e| e.trim_end_matches('+').eq_ignore_ascii_case(license))
+    };
+
+    let tokens = expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ");
+    let tokens = tokens.split_whitespace().collect::<Vec<_>>();
+    let mut parser = LicenseParser {
+        tokens: &tokens,
+        pos: 0,
+        is_excluded: &is_excluded,
+    };
+    match parser.any() {
+        Some(allowed) if parser.pos == tokens.len() => !allowed,
+        // Exclude malformed expressions mentioning an excluded license, to be on the safe side
+        _ => tokens.iter().any(|token| is_excluded(token)),
+    }
+}
+
+/// Evaluates whether a license expression is allowed, returning `None` if it's malformed.
+struct LicenseParser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    is_excluded: &'a dyn Fn(&str) -> bool,
+}
+
+impl LicenseParser<'_> {
+    fn eat(&mut self, token: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn any(&mut self) -> Option<bool> {
+        let mut allowed = self.all()?;
+        while self.eat("OR") {
+            allowed |= self.all()?;
+        }
+        Some(allowed)
+    }
+
+    fn all(&mut self) -> Option<bool> {
+        let mut allowed = self.license()?;
+        while self.eat("AND") {
+            allowed &= self.license()?;
+        }
+        Some(allowed)
+    }
+
+    fn license(&mut self) -> Option<bool> {
+        if self.eat("(") {
+            let allowed = self.any()?;
+            return self.eat(")").then_some(allowed);
+        }
+        let license = *self.tokens.get(self.pos)?;
+        if matches!(license, "AND" | "OR" | "WITH" | ")") {
+            return None;
+        }
+        self.pos += 1;
+        let mut allowed = !(self.is_excluded)(license);
+        if self.eat("WITH") {
+            let exception = *self.tokens.get(self.pos)?;
+            self.pos += 1;
+            allowed &= !(self.is_excluded)(exception);
+        }
+        Some(allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_crates, is_license_excluded, ActivityThresholds, ExclusionReason};
+    use crate::config::Config;
+    use crate::crates::Crate;
+    use crate::experiments::CrateSelect;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_is_license_excluded() {
+        let excluded = ["GPL-3.0".to_string(), "AGPL-3.0".to_string()];
+        let check = |expression: &str| is_license_excluded(expression, &excluded);
+
+        assert!(!check("MIT"));
+        assert!(!check("MIT OR Apache-2.0"));
+        assert!(check("GPL-3.0"));
+        assert!(check("gpl-3.0+"));
+        assert!(!check("MIT OR GPL-3.0"));
+        assert!(!check("MIT/GPL-3.0"));
+        assert!(check("MIT AND GPL-3.0"));
+        assert!(check("(MIT OR Apache-2.0) AND (GPL-3.0 OR AGPL-3.0)"));
+        assert!(!check("(MIT OR GPL-3.0) AND (Apache-2.0 OR AGPL-3.0)"));
+        assert!(check("GPL-3.0 WITH Classpath-exception-2.0"));
+        assert!(!check("GPL-3.0-only-ish"));
+        // `AND` binds tighter than `OR`
+        assert!(!check("MIT OR Apache-2.0 AND GPL-3.0"));
+        // Malformed expressions are only excluded when they mention excluded licenses
+        assert!(check("MIT AND (GPL-3.0"));
+        assert!(!check("MIT AND (Apache-2.0"));
+    }
+
+    #[test]
+    fn test_filter_crates_disabled() {
+        let crates = vec![Crate::Local("foo".into())];
+        let (kept, excluded) =
+            filter_crates(crates.clone(), &CrateSelect::Full, &[], &Config::default()).unwrap();
+        assert_eq!(kept, crates);
+        assert!(excluded.is_empty());
+
+        // The activity thresholds only apply to the `full-active` selection
+        let mut config = Config::default();
+        config.crate_filters.min_downloads = Some(1000);
+        let (kept, excluded) =
+            filter_crates(crates.clone(), &CrateSelect::Full, &[], &config).unwrap();
+        assert_eq!(kept, crates);
+        assert!(excluded.is_empty());
+    }
+
+    #[test]
+    fn test_filter_crates_patterns() {
+        let crates = ["win-api", "openssl-sys", "serde"].map(|name| Crate::Local(name.into()));
+        let patterns = ["win-*".parse().unwrap(), "*-sys".parse().unwrap()];
+        let (kept, excluded) = filter_crates(
+            crates.to_vec(),
+            &CrateSelect::Full,
+            &patterns,
+            &Config::default(),
+        )
+        .unwrap();
+        assert_eq!(kept, [Crate::Local("serde".into())]);
+        assert_eq!(
+            excluded
+                .iter()
+                .map(|e| (e.krate.id(), e.reason.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                ("local/win-api".to_string(), "matching `win-*`".to_string()),
+                (
+                    "local/openssl-sys".to_string(),
+                    "matching `*-sys`".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_activity_thresholds() {
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let thresholds = ActivityThresholds {
+            min_downloads: Some(1000),
+            min_last_release: Some(date("2020-01-01")),
+        };
+        assert!(thresholds.is_enabled());
+        assert!(!ActivityThresholds::default().is_enabled());
+
+        assert_eq!(thresholds.check(Some(5000), Some(date("2021-06-01"))), None);
+        assert_eq!(
+            thresholds.check(Some(10), Some(date("2021-06-01"))),
+            Some(ExclusionReason::FewDownloads { downloads: 10 })
+        );
+        assert_eq!(
+            thresholds.check(Some(5000), Some(date("2019-06-01"))),
+            Some(ExclusionReason::Inactive {
+                last_release: date("2019-06-01")
+            })
+        );
+        // Crates missing from the dump are kept
+        assert_eq!(thresholds.check(None, None), None);
+        assert_eq!(
+            ExclusionReason::Inactive {
+                last_release: date("2019-06-01")
+            }
+            .to_string(),
+            "last released on 2019-06-01"
+        );
+    }
+}
@@ -1,41 +1,103 @@
-use crate::config::Config;
+use crate::config::{Config, RandomQuota};
 use crate::crates::sources::github::GitHubRepo;
 use crate::crates::{Crate, RegistryCrate};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::CrateSelect;
+use crate::experiments::{CrateSelect, Experiment};
 use crate::prelude::*;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rand::{seq::SliceRandom, thread_rng};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub(crate) use crate::crates::sources::{
-    github::GitHubList, local::LocalList, registry::RegistryList,
+    github::GitHubList,
+    local::LocalList,
+    registry::{crates_owned_by, open_index, reverse_dependencies, RegistryList},
 };
 
+/// Entries of a `list:` selection matching no crate of the lists.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown crates in the list: {}", .0.join(", "))]
+pub struct UnknownCrates(pub Vec<String>);
+
+/// Crates of a list, with the revision of the source they were fetched from.
+pub(crate) struct FetchedList {
+    pub(crate) crates: Vec<Crate>,
+    /// Version of the source of the list (an ETag or a commit), used to avoid fetching the list
+    /// again when it didn't change.
+    pub(crate) revision: Option<String>,
+}
+
 pub(crate) trait List {
     const NAME: &'static str;
 
     fn fetch(&self) -> Fallible<Vec<Crate>>;
 
-    fn update(&self, db: &Database) -> Fallible<()> {
-        let crates = self.fetch()?;
+    /// Fetch the list, unless its source is still at the revision it was last fetched from, in
+    /// which case `None` is returned. Sources without revisions are always fetched.
+    fn fetch_changed(&self, _revision: Option<&str>) -> Fallible<Option<FetchedList>> {
+        Ok(Some(FetchedList {
+            crates: self.fetch()?,
+            revision: None,
+        }))
+    }
+
+    /// Update the list stored in the database. Unless `force` is set, the list is only fetched
+    /// if its source changed since the last update.
+    fn update(&self, db: &Database, force: bool) -> Fallible<()> {
+        let revision = if force {
+            None
+        } else {
+            db.get_row(
+                "SELECT revision FROM list_revisions WHERE list = ?1;",
+                [&Self::NAME],
+                |row| row.get::<_, String>("revision"),
+            )?
+        };
+        let Some(fetched) = self.fetch_changed(revision.as_deref())? else {
+            info!(
+                "the {} list didn't change since its last update",
+                Self::NAME
+            );
+            return Ok(());
+        };
+        let crates = fetched.crates;
+        let ids = crates.iter().map(|krate| krate.id()).collect::<Vec<_>>();
 
         let now = Utc::now();
         db.transaction(true, |t| {
-            // Replace the existing list in the database
-            t.execute("DELETE FROM crates WHERE list = ?1;", &[&Self::NAME])?;
-            for krate in &crates {
-                t.execute(
-                    "INSERT INTO crates (crate, list, loaded_at) VALUES (?1, ?2, ?3);",
-                    &[&krate.id(), &Self::NAME, &now],
-                )
-                .with_context(|| {
-                    format!(
-                        "failed to insert crate {} into the {} list",
-                        krate,
-                        Self::NAME
+            let current = t.query(
+                "SELECT crate FROM crates WHERE list = ?1 ORDER BY rowid;",
+                [&Self::NAME],
+                |row| row.get::<_, String>(0),
+            )?;
+            // Rewriting the whole list takes a while, skip it when the content is the same
+            if current != ids {
+                // Replace the existing list in the database
+                t.execute("DELETE FROM crates WHERE list = ?1;", &[&Self::NAME])?;
+                for (krate, id) in crates.iter().zip(&ids) {
+                    t.execute(
+                        "INSERT INTO crates (crate, list, loaded_at) VALUES (?1, ?2, ?3);",
+                        &[id, &Self::NAME, &now],
                     )
-                })?;
+                    .with_context(|| {
+                        format!(
+                            "failed to insert crate {} into the {} list",
+                            krate,
+                            Self::NAME
+                        )
+                    })?;
+                }
+            }
+
+            t.execute(
+                "DELETE FROM list_revisions WHERE list = ?1;",
+                &[&Self::NAME],
+            )?;
+            if let Some(revision) = &fetched.revision {
+                t.execute(
+                    "INSERT INTO list_revisions (list, revision, fetched_at) VALUES (?1, ?2, ?3);",
+                    &[&Self::NAME, revision, &now],
+                )?;
             }
 
             Ok(())
@@ -57,6 +119,51 @@ pub(crate) trait List {
     }
 }
 
+/// State of a list stored in the database, to tell how stale it is.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListSummary {
+    pub name: &'static str,
+    pub crates: u32,
+    /// When the content of the list last changed.
+    pub loaded_at: Option<DateTime<Utc>>,
+    /// When the list was last fetched from its source, for the sources with revisions.
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub revision: Option<String>,
+}
+
+fn summary(db: &Database, name: &'static str) -> Fallible<ListSummary> {
+    let (crates, loaded_at) = db
+        .get_row(
+            "SELECT COUNT(*), MAX(loaded_at) FROM crates WHERE list = ?1;",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+        .unwrap_or((0, None));
+    let (fetched_at, revision) = db
+        .get_row(
+            "SELECT fetched_at, revision FROM list_revisions WHERE list = ?1;",
+            [name],
+            |row| Ok((Some(row.get(0)?), Some(row.get(1)?))),
+        )?
+        .unwrap_or((None, None));
+
+    Ok(ListSummary {
+        name,
+        crates,
+        loaded_at,
+        fetched_at,
+        revision,
+    })
+}
+
+/// Summaries of all the lists, including the ones that were never loaded.
+pub(crate) fn summaries(db: &Database) -> Fallible<Vec<ListSummary>> {
+    [RegistryList::NAME, GitHubList::NAME, LocalList::NAME]
+        .into_iter()
+        .map(|name| summary(db, name))
+        .collect()
+}
+
 pub(crate) fn get_crates(
     select: &CrateSelect,
     db: &Database,
@@ -65,7 +172,7 @@ pub(crate) fn get_crates(
     let mut crates = Vec::new();
 
     match select {
-        CrateSelect::Full => {
+        CrateSelect::Full | CrateSelect::FullActive => {
             crates.append(&mut RegistryList::get(db)?);
             crates.append(&mut GitHubList::get(db)?);
         }
@@ -113,27 +220,15 @@ pub(crate) fn get_crates(
             }
         }
         CrateSelect::List(list) => {
-            let mut desired = list.clone();
-
             let mut all_crates = Vec::new();
             all_crates.append(&mut RegistryList::get(db)?);
             all_crates.append(&mut GitHubList::get(db)?);
 
-            for krate in all_crates {
-                let is_desired = match krate {
-                    Crate::Registry(RegistryCrate { ref name, .. }) => desired.remove(name),
-                    Crate::GitHub(ref repo) => desired.remove(&repo.slug()),
-                    _ => unreachable!(),
-                };
-
-                if is_desired {
-                    crates.push(krate);
-                }
-            }
+            crates = select_listed(list, all_crates)?;
+        }
 
-            if !desired.is_empty() {
-                bail!("missing desired crates: {:?}", desired);
-            }
+        CrateSelect::Versions(versions) => {
+            crates.extend(versions.iter().cloned().map(Crate::Registry));
         }
 
         CrateSelect::Random(n) => {
@@ -144,20 +239,122 @@ pub(crate) fn get_crates(
             crates.shuffle(&mut rng);
             crates.truncate(*n as usize);
         }
+        CrateSelect::StratifiedRandom(n) => {
+            if config.random_quotas.is_empty() {
+                bail!("no quotas for stratified random selections are configured");
+            }
+
+            crates = stratified_sample(
+                RegistryList::get(db)?,
+                GitHubList::get(db)?,
+                &config.random_quotas,
+                *n,
+            );
+        }
         CrateSelect::Top(n) => {
             crates.append(&mut RegistryList::get(db)?);
             crates.truncate(*n as usize);
         }
+        CrateSelect::OwnedBy(owner) => {
+            let owned = crates_owned_by(owner)?;
+            crates.extend(RegistryList::get(db)?.into_iter().filter(|krate| {
+                matches!(krate, Crate::Registry(RegistryCrate { name, .. }) if owned.contains(name))
+            }));
+        }
+        CrateSelect::ReverseDeps { krate, depth } => {
+            let dependents = reverse_dependencies(krate, *depth)?;
+            crates.extend(RegistryList::get(db)?.into_iter().filter(|krate| {
+                matches!(krate, Crate::Registry(RegistryCrate { name, .. }) if dependents.contains(name))
+            }));
+        }
         CrateSelect::Local => {
             crates.append(&mut LocalList::get(db)?);
         }
         CrateSelect::Dummy => crates.push(Crate::GitHub(GitHubRepo::dummy())),
+        CrateSelect::SameAs(name) => match Experiment::get(db, name)? {
+            Some(ex) => crates = ex.get_crates(db)?,
+            None => bail!("experiment '{}' not found", name),
+        },
     }
 
     crates.sort();
     Ok(crates)
 }
 
+/// Key identifying a crate of a `list:` selection. Registry crates are compared like crates.io
+/// does, ignoring the case and treating `-` and `_` as the same character, while GitHub
+/// repositories only ignore the case.
+fn list_key(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name.contains('/') {
+        name
+    } else {
+        name.replace('_', "-")
+    }
+}
+
+/// Pick the listed crates, failing with all the entries matching no crate so that a typo
+/// doesn't silently leave a crate out of the experiment.
+fn select_listed(
+    list: &HashSet<String>,
+    all_crates: Vec<Crate>,
+) -> Result<Vec<Crate>, UnknownCrates> {
+    let mut desired = list
+        .iter()
+        .map(|name| (list_key(name), name))
+        .collect::<HashMap<_, _>>();
+
+    let crates = all_crates
+        .into_iter()
+        .filter(|krate| {
+            let name = match krate {
+                Crate::Registry(RegistryCrate { name, .. }) => list_key(name),
+                Crate::GitHub(repo) => list_key(&repo.slug()),
+                _ => return false,
+            };
+            desired.remove(&name).is_some()
+        })
+        .collect();
+
+    if !desired.is_empty() {
+        let mut unknown = desired.into_values().cloned().collect::<Vec<_>>();
+        unknown.sort();
+        return Err(UnknownCrates(unknown));
+    }
+    Ok(crates)
+}
+
+/// Randomly pick `n` crates, taking from each range of the registry list (sorted by popularity)
+/// the share of crates defined by its quota.
+fn stratified_sample(
+    registry: Vec<Crate>,
+    mut others: Vec<Crate>,
+    quotas: &[RandomQuota],
+    n: u32,
+) -> Vec<Crate> {
+    let mut rng = thread_rng();
+    let mut crates = Vec::new();
+    let mut start = 0;
+    for quota in quotas {
+        let mut bucket = if let Some(rank) = quota.up_to_rank {
+            let end = (rank as usize).clamp(start, registry.len());
+            let bucket = registry[start..end].to_vec();
+            start = end;
+            bucket
+        } else {
+            let mut bucket = registry[start..].to_vec();
+            start = registry.len();
+            bucket.append(&mut others);
+            bucket
+        };
+
+        bucket.shuffle(&mut rng);
+        bucket.truncate((n as f64 * quota.share).round() as usize);
+        crates.append(&mut bucket);
+    }
+    crates
+}
+
 #[cfg(test)]
 pub(crate) fn setup_test_lists(db: &Database, config: &Config) -> Fallible<()> {
     use crate::actions::{Action, ActionsCtx, UpdateLists};
@@ -166,6 +363,170 @@ pub(crate) fn setup_test_lists(db: &Database, config: &Config) -> Fallible<()> {
         github: false,
         registry: false,
         local: true,
+        force: false,
     }
     .apply(&ActionsCtx::new(db, config))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        select_listed, stratified_sample, summaries, summary, FetchedList, GitHubList, List,
+        LocalList, RegistryList, UnknownCrates,
+    };
+    use crate::config::RandomQuota;
+    use crate::crates::sources::github::GitHubRepo;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::prelude::*;
+    use std::cell::RefCell;
+
+    struct DummyList {
+        revision: &'static str,
+        requested: RefCell<Vec<Option<String>>>,
+    }
+
+    impl List for DummyList {
+        const NAME: &'static str = "dummy";
+
+        fn fetch(&self) -> Fallible<Vec<Crate>> {
+            Ok(vec![Crate::Local("foo".into()), Crate::Local("bar".into())])
+        }
+
+        fn fetch_changed(&self, revision: Option<&str>) -> Fallible<Option<FetchedList>> {
+            self.requested.borrow_mut().push(revision.map(String::from));
+            if revision == Some(self.revision) {
+                return Ok(None);
+            }
+            Ok(Some(FetchedList {
+                crates: self.fetch()?,
+                revision: Some(self.revision.into()),
+            }))
+        }
+    }
+
+    #[test]
+    fn test_update_unchanged() {
+        let db = Database::temp().unwrap();
+        let list = DummyList {
+            revision: "rev1",
+            requested: RefCell::new(Vec::new()),
+        };
+
+        list.update(&db, false).unwrap();
+        list.update(&db, false).unwrap();
+        list.update(&db, true).unwrap();
+        assert_eq!(
+            *list.requested.borrow(),
+            [None, Some("rev1".to_string()), None]
+        );
+        assert_eq!(
+            DummyList::get(&db).unwrap(),
+            [Crate::Local("foo".into()), Crate::Local("bar".into())]
+        );
+    }
+
+    #[test]
+    fn test_summaries() {
+        let db = Database::temp().unwrap();
+        let registry = summary(&db, RegistryList::NAME).unwrap();
+        assert_eq!(registry.crates, 0);
+        assert!(registry.loaded_at.is_none());
+        assert!(registry.fetched_at.is_none());
+
+        DummyList {
+            revision: "rev1",
+            requested: RefCell::new(Vec::new()),
+        }
+        .update(&db, false)
+        .unwrap();
+        let dummy = summary(&db, DummyList::NAME).unwrap();
+        assert_eq!(dummy.crates, 2);
+        assert!(dummy.loaded_at.is_some());
+        assert_eq!(dummy.fetched_at, dummy.loaded_at);
+        assert_eq!(dummy.revision.as_deref(), Some("rev1"));
+
+        // Lists without revisions are never fetched conditionally
+        LocalList::default().update(&db, false).unwrap();
+        let all = summaries(&db).unwrap();
+        assert_eq!(
+            all.iter().map(|s| s.name).collect::<Vec<_>>(),
+            [RegistryList::NAME, GitHubList::NAME, LocalList::NAME]
+        );
+        assert!(all[2].crates > 0);
+        assert!(all[2].fetched_at.is_none());
+    }
+
+    #[test]
+    fn test_select_listed() {
+        let registry = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let github = Crate::GitHub(GitHubRepo {
+            org: "brson".into(),
+            name: "hello_rs".into(),
+            sha: None,
+        });
+        let all = vec![
+            registry("serde_json"),
+            registry("lazy-static"),
+            registry("syn"),
+            github.clone(),
+        ];
+        let list = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        assert_eq!(
+            select_listed(
+                &list(&["serde-json", "Lazy_Static", "Brson/Hello_rs"]),
+                all.clone()
+            ),
+            Ok(vec![
+                registry("serde_json"),
+                registry("lazy-static"),
+                github
+            ])
+        );
+        // Only the names of the registry treat `-` and `_` the same
+        assert_eq!(
+            select_listed(&list(&["syn", "sny", "brson/hello-rs"]), all),
+            Err(UnknownCrates(vec!["brson/hello-rs".into(), "sny".into()]))
+        );
+    }
+
+    #[test]
+    fn test_stratified_sample() {
+        let registry = (0..100)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate{i}"),
+                    version: "1.0.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let quotas = [
+            RandomQuota {
+                up_to_rank: Some(10),
+                share: 0.5,
+            },
+            RandomQuota {
+                up_to_rank: None,
+                share: 0.5,
+            },
+        ];
+
+        let sample = stratified_sample(registry.clone(), Vec::new(), &quotas, 10);
+        assert_eq!(sample.len(), 10);
+        let top = sample
+            .iter()
+            .filter(|krate| registry[..10].contains(krate))
+            .count();
+        assert_eq!(top, 5);
+
+        // Quotas larger than their range take all of it
+        let sample = stratified_sample(registry, Vec::new(), &quotas, 100);
+        assert_eq!(sample.len(), 60);
+    }
+}
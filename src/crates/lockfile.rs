@@ -0,0 +1,164 @@
+//! Crates listed by the lockfile of a project, to run an experiment over the exact dependency
+//! tree of a production application. Both `Cargo.lock` files and JSON SBOMs (like the CycloneDX
+//! or SPDX ones) are supported, and only the crates.io packages are selected: the workspace
+//! members, and the git and path dependencies can't be fetched by the agents.
+
+use crate::crates::RegistryCrate;
+use crate::prelude::*;
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeSet;
+
+const CRATES_IO_SOURCES: &[&str] = &[
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+const CARGO_PURL_PREFIX: &str = "pkg:cargo/";
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+/// Parse a `Cargo.lock` file or a JSON SBOM, returning the crates.io packages it lists.
+pub(crate) fn parse_lockfile(content: &str) -> Fallible<BTreeSet<RegistryCrate>> {
+    let crates = if content.trim_start().starts_with('{') {
+        let sbom: serde_json::Value = serde_json::from_str(content).context("invalid SBOM")?;
+        let mut crates = BTreeSet::new();
+        collect_purls(&sbom, &mut crates);
+        crates
+    } else {
+        let lockfile: Lockfile = toml::from_str(content).context("invalid Cargo.lock")?;
+        lockfile
+            .package
+            .into_iter()
+            .filter(|package| {
+                package
+                    .source
+                    .as_deref()
+                    .is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+            })
+            .map(|package| RegistryCrate {
+                name: package.name,
+                version: package.version,
+            })
+            .collect()
+    };
+
+    if crates.is_empty() {
+        bail!("the lockfile doesn't list any crates.io package");
+    }
+    Ok(crates)
+}
+
+/// SBOMs identify the packages with their package URL, like `pkg:cargo/serde@1.0.197`, wherever
+/// their format stores it. Package URLs with qualifiers point to other registries, to git
+/// repositories or to directories.
+fn collect_purls(value: &serde_json::Value, crates: &mut BTreeSet<RegistryCrate>) {
+    match value {
+        serde_json::Value::String(purl) => {
+            let Some(package) = purl.strip_prefix(CARGO_PURL_PREFIX) else {
+                return;
+            };
+            let package = package.split('#').next().unwrap_or_default();
+            if package.contains('?') {
+                return;
+            }
+            if let Some((name, version)) = package.split_once('@') {
+                crates.insert(RegistryCrate {
+                    name: percent_decode_str(name).decode_utf8_lossy().into_owned(),
+                    version: percent_decode_str(version).decode_utf8_lossy().into_owned(),
+                });
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_purls(value, crates);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for value in fields.values() {
+                collect_purls(value, crates);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_lockfile;
+    use crate::crates::RegistryCrate;
+
+    fn names(crates: impl IntoIterator<Item = RegistryCrate>) -> Vec<String> {
+        crates
+            .into_iter()
+            .map(|krate| format!("{}@{}", krate.name, krate.version))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_cargo_lock() {
+        let lockfile = r#"
+            version = 3
+
+            [[package]]
+            name = "app"
+            version = "0.1.0"
+            dependencies = ["serde", "fork"]
+
+            [[package]]
+            name = "serde"
+            version = "1.0.197"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "3fb1c873e1b9b056a4dc4c0c198b24c3ffa059243875552b2bd0933b1aee4ce2"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.52"
+            source = "sparse+https://index.crates.io/"
+
+            [[package]]
+            name = "fork"
+            version = "0.2.0"
+            source = "git+https://github.com/example/fork#0123456789abcdef"
+        "#;
+        assert_eq!(
+            names(parse_lockfile(lockfile).unwrap()),
+            ["serde@1.0.197", "syn@2.0.52"]
+        );
+
+        assert!(parse_lockfile("version = 3").is_err());
+        assert!(parse_lockfile("[[package]]\nname = 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_sbom() {
+        let sbom = r#"{
+            "bomFormat": "CycloneDX",
+            "components": [
+                {"name": "serde", "version": "1.0.197", "purl": "pkg:cargo/serde@1.0.197"},
+                {"name": "local", "purl": "pkg:cargo/local@0.1.0?download_url=file://../local"},
+                {
+                    "name": "app",
+                    "purl": "pkg:cargo/app@0.1.0",
+                    "components": [{"purl": "pkg:cargo/syn@2.0.52#src/lib.rs"}]
+                },
+                {"name": "left-pad", "purl": "pkg:npm/left-pad@1.3.0"}
+            ]
+        }"#;
+        assert_eq!(
+            names(parse_lockfile(sbom).unwrap()),
+            ["app@0.1.0", "serde@1.0.197", "syn@2.0.52"]
+        );
+
+        assert!(parse_lockfile(r#"{"components": []}"#).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+mod cache;
+mod checksum;
+mod filters;
 pub(crate) mod lists;
+mod lockfile;
+mod overlay;
+mod patch;
+mod pattern;
+mod pin;
+pub mod quarantine;
 mod sources;
 
+use crate::config::Config;
 use crate::dirs::LOCAL_CRATES_DIR;
 use crate::prelude::*;
 use cargo_metadata::PackageId;
@@ -10,8 +20,20 @@ use std::fmt;
 use std::path::Path;
 use std::str::FromStr;
 
-pub(crate) use crate::crates::sources::github::GitHubRepo;
-pub(crate) use crate::crates::sources::registry::RegistryCrate;
+pub(crate) use crate::crates::checksum::{verify_checksum, ChecksumMismatch, MissingTarball};
+pub(crate) use crate::crates::filters::{
+    exclude_matching, filter_crates, refresh_db_dump, use_db_dump,
+};
+pub use crate::crates::filters::{ExcludedCrate, ExclusionReason};
+pub(crate) use crate::crates::lockfile::parse_lockfile;
+pub(crate) use crate::crates::overlay::describe_overlay;
+pub use crate::crates::overlay::{resolve_overlay, OverlayCrate};
+pub(crate) use crate::crates::patch::describe_patches;
+pub use crate::crates::patch::{DependencyPatch, InvalidPatch, PatchSource};
+pub use crate::crates::pattern::{CratePattern, InvalidPattern};
+pub(crate) use crate::crates::pin::pin_git_shas;
+pub use crate::crates::sources::github::GitHubRepo;
+pub use crate::crates::sources::registry::RegistryCrate;
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
 pub struct GitRepo {
@@ -20,6 +42,7 @@ pub struct GitRepo {
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
+#[non_exhaustive]
 pub enum Crate {
     Registry(RegistryCrate),
     GitHub(GitHubRepo),
@@ -57,11 +80,33 @@ impl Crate {
         }
     }
 
-    pub(crate) fn to_rustwide(&self) -> RustwideCrate {
+    /// Identifier of the crate shared by all its versions, used to track its history across
+    /// experiments.
+    pub(crate) fn unversioned_id(&self) -> String {
+        match *self {
+            Crate::Registry(ref details) => format!("reg/{}", details.name),
+            Crate::GitHub(ref repo) => format!("gh/{}/{}", repo.org, repo.name),
+            Crate::Git(ref repo) => {
+                format!("git/{}", utf8_percent_encode(&repo.url, NON_ALPHANUMERIC))
+            }
+            Crate::Local(_) | Crate::Path(_) => self.id(),
+        }
+    }
+
+    pub(crate) fn to_rustwide(&self, config: &Config) -> RustwideCrate {
+        // Pinned repositories are built from the checkout of their commit
+        if self.pinned_commit(config).is_some() {
+            return RustwideCrate::local(&pin::checkout_dir(self));
+        }
+
         match self {
             Self::Registry(krate) => RustwideCrate::crates_io(&krate.name, &krate.version),
             Self::GitHub(repo) => {
-                RustwideCrate::git(&format!("https://github.com/{}/{}", repo.org, repo.name))
+                if let Some(cache) = &config.crate_cache {
+                    RustwideCrate::git(&cache.git_url(repo))
+                } else {
+                    RustwideCrate::git(&format!("https://github.com/{}/{}", repo.org, repo.name))
+                }
             }
             Self::Local(name) => RustwideCrate::local(&LOCAL_CRATES_DIR.join(name)),
             Self::Path(path) => RustwideCrate::local(Path::new(&path)),
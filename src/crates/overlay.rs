@@ -0,0 +1,195 @@
+//! Overlay of unreleased crate versions over crates.io. An experiment can be defined with a set of
+//! `.crate` files (for example a patched version of a widely-used library), which replace the
+//! crates.io versions in the builds of the end toolchain through `[patch.crates-io]`, measuring
+//! the ecosystem impact of the change before it's published.
+
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::utils;
+use crate::utils::hex::to_hex;
+use flate2::read::GzDecoder;
+use remove_dir_all::remove_dir_all;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// A `.crate` file overlaid over crates.io, recorded in the experiment as the provenance of the
+/// patched crate.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OverlayCrate {
+    pub name: String,
+    pub version: String,
+    /// Where the `.crate` file was downloaded from.
+    pub url: String,
+    /// SHA-256 of the `.crate` file, checked by the agents before using it.
+    pub checksum: String,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: ManifestPackage,
+}
+
+#[derive(Deserialize)]
+struct ManifestPackage {
+    name: String,
+    version: String,
+}
+
+fn download(url: &str) -> Fallible<Vec<u8>> {
+    let mut content = Vec::new();
+    utils::http::get_sync(url)
+        .with_context(|| format!("failed to download {url}"))?
+        .read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Read the name and version of the package from the manifest at the root of a `.crate` file.
+fn read_manifest(tarball: &[u8]) -> Fallible<ManifestPackage> {
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?;
+        // Packages are stored in a `{name}-{version}` directory
+        if path.components().count() == 2 && path.ends_with("Cargo.toml") {
+            let mut manifest = String::new();
+            entry.read_to_string(&mut manifest)?;
+            return Ok(toml::from_str::<Manifest>(&manifest)?.package);
+        }
+    }
+    bail!("no Cargo.toml found at the root of the package")
+}
+
+/// Download the `.crate` files of the overlay, recording where they come from and what they
+/// contain.
+pub fn resolve_overlay(urls: &[String]) -> Fallible<Vec<OverlayCrate>> {
+    let mut overlay: Vec<OverlayCrate> = Vec::new();
+    for url in urls {
+        let tarball = download(url)?;
+        let package = read_manifest(&tarball).with_context(|| format!("invalid crate: {url}"))?;
+        if overlay.iter().any(|krate| krate.name == package.name) {
+            bail!("{} is overlaid multiple times", package.name);
+        }
+        overlay.push(OverlayCrate {
+            name: package.name,
+            version: package.version,
+            url: url.clone(),
+            checksum: to_hex(&Sha256::digest(&tarball)),
+        });
+    }
+    Ok(overlay)
+}
+
+impl OverlayCrate {
+    fn unpack_dir(&self) -> PathBuf {
+        WORK_DIR
+            .join("registry-overlay")
+            .join(format!("{}-{}", self.name, &self.checksum[..16]))
+    }
+
+    /// Directory containing the source of the package, once unpacked.
+    pub(crate) fn source_dir(&self) -> PathBuf {
+        self.unpack_dir()
+            .join(format!("{}-{}", self.name, self.version))
+    }
+
+    /// Download, verify and unpack the `.crate` file, unless it was already unpacked.
+    pub(crate) fn unpack(&self) -> Fallible<()> {
+        let dir = self.unpack_dir();
+        if dir.exists() {
+            return Ok(());
+        }
+
+        info!("unpacking {} {} from {}", self.name, self.version, self.url);
+        let tarball = download(&self.url)?;
+        let found = to_hex(&Sha256::digest(&tarball));
+        if found != self.checksum {
+            bail!(
+                "{} changed since the experiment was created: expected checksum {}, found {}",
+                self.url,
+                self.checksum,
+                found
+            );
+        }
+
+        // Unpack in a temporary directory first, to avoid using partially unpacked crates
+        let tmp = dir.with_extension("tmp");
+        if tmp.exists() {
+            remove_dir_all(&tmp)?;
+        }
+        fs::create_dir_all(&tmp)?;
+        tar::Archive::new(GzDecoder::new(&tarball[..])).unpack(&tmp)?;
+        fs::rename(&tmp, &dir)?;
+        Ok(())
+    }
+}
+
+/// Describe the overlay in a single line, like `serde 1.0.999, syn 2.0.999`.
+pub(crate) fn describe_overlay(overlay: &[OverlayCrate]) -> String {
+    overlay
+        .iter()
+        .map(|krate| format!("{} {}", krate.name, krate.version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_overlay, read_manifest, OverlayCrate};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        let krate = tarball(&[
+            ("serde-1.0.999/src/lib.rs", ""),
+            (
+                "serde-1.0.999/Cargo.toml",
+                "[package]\nname = \"serde\"\nversion = \"1.0.999\"\n",
+            ),
+        ]);
+        let package = read_manifest(&krate).unwrap();
+        assert_eq!(package.name, "serde");
+        assert_eq!(package.version, "1.0.999");
+
+        let nested = tarball(&[(
+            "serde-1.0.999/tests/Cargo.toml",
+            "[package]\nname = \"test\"\nversion = \"0.0.0\"\n",
+        )]);
+        assert!(read_manifest(&nested).is_err());
+        assert!(read_manifest(b"not a tarball").is_err());
+    }
+
+    #[test]
+    fn test_describe_overlay() {
+        let krate = |name: &str, version: &str| OverlayCrate {
+            name: name.into(),
+            version: version.into(),
+            url: format!("https://example.com/{name}.crate"),
+            checksum: "0".repeat(64),
+        };
+        assert_eq!(
+            describe_overlay(&[krate("serde", "1.0.999"), krate("syn", "2.0.999")]),
+            "serde 1.0.999, syn 2.0.999"
+        );
+        assert_eq!(
+            krate("serde", "1.0.999").source_dir().file_name().unwrap(),
+            "serde-1.0.999"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Dependencies patched in the builds of the end toolchain of an experiment. Like the patches of
+//! the toolchains, they replace a crate of crates.io with a git branch or a local directory
+//! through `[patch.crates-io]`, while the start toolchain builds the crates.io version, measuring
+//! the ecosystem impact of an unreleased fix to a widely-used library.
+
+use crate::prelude::*;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("invalid dependency patch `{0}`, expected `name=git-url=branch` or `name=/absolute/path`")]
+pub struct InvalidPatch(String);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum PatchSource {
+    Git {
+        repo: String,
+        branch: String,
+    },
+    /// Directory on the agents, only useful for local experiments or provisioned machines.
+    Path {
+        path: String,
+    },
+}
+
+/// A crate of crates.io replaced in the builds of an experiment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DependencyPatch {
+    pub name: String,
+    pub source: PatchSource,
+}
+
+impl FromStr for DependencyPatch {
+    type Err = InvalidPatch;
+
+    fn from_str(input: &str) -> Result<Self, InvalidPatch> {
+        let invalid = || InvalidPatch(input.to_string());
+        let (name, source) = input.split_once('=').ok_or_else(invalid)?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+
+        let source = match source.split_once('=') {
+            Some((repo, branch))
+                if !repo.is_empty() && !branch.is_empty() && !branch.contains('=') =>
+            {
+                PatchSource::Git {
+                    repo: repo.into(),
+                    branch: branch.into(),
+                }
+            }
+            None if Path::new(source).is_absolute() => PatchSource::Path {
+                path: source.into(),
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(DependencyPatch {
+            name: name.into(),
+            source,
+        })
+    }
+}
+
+impl fmt::Display for DependencyPatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.source {
+            PatchSource::Git { repo, branch } => write!(f, "{}={}={}", self.name, repo, branch),
+            PatchSource::Path { path } => write!(f, "{}={}", self.name, path),
+        }
+    }
+}
+
+/// Describe the patches in a single line, like `serde from https://github.com/serde-rs/serde
+/// (branch fix), syn from /opt/syn`.
+pub(crate) fn describe_patches(patches: &[DependencyPatch]) -> String {
+    patches
+        .iter()
+        .map(|patch| match &patch.source {
+            PatchSource::Git { repo, branch } => {
+                format!("{} from {} (branch {})", patch.name, repo, branch)
+            }
+            PatchSource::Path { path } => format!("{} from {}", patch.name, path),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_patches, DependencyPatch, InvalidPatch, PatchSource};
+
+    #[test]
+    fn test_parse() {
+        macro_rules! test_parse {
+            ($input:expr => $patch:expr) => {
+                let patch: DependencyPatch = $input.parse().unwrap();
+                assert_eq!(patch, $patch);
+                assert_eq!(patch.to_string(), $input);
+            };
+        }
+
+        test_parse!("serde=https://github.com/serde-rs/serde=fix" => DependencyPatch {
+            name: "serde".into(),
+            source: PatchSource::Git {
+                repo: "https://github.com/serde-rs/serde".into(),
+                branch: "fix".into(),
+            },
+        });
+        test_parse!("syn=/opt/syn" => DependencyPatch {
+            name: "syn".into(),
+            source: PatchSource::Path {
+                path: "/opt/syn".into(),
+            },
+        });
+
+        for invalid in [
+            "serde",
+            "=/opt/serde",
+            "serde=relative/path",
+            "serde=repo=",
+            "a=b=c=d",
+        ] {
+            assert_eq!(
+                invalid.parse::<DependencyPatch>(),
+                Err(InvalidPatch(invalid.into()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_describe_patches() {
+        let patches = [
+            "serde=https://github.com/serde-rs/serde=fix"
+                .parse()
+                .unwrap(),
+            "syn=/opt/syn".parse().unwrap(),
+        ];
+        assert_eq!(
+            describe_patches(&patches),
+            "serde from https://github.com/serde-rs/serde (branch fix), syn from /opt/syn"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Patterns excluding crates from a single experiment. They're applied after the crates are
+//! selected, so that one-off exclusions don't require editing the configuration or listing all
+//! the other crates with a `list:` selection.
+
+use crate::crates::Crate;
+use crate::prelude::*;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("invalid exclusion pattern `{0}`")]
+pub struct InvalidPattern(String);
+
+/// Pattern matched against the name of the registry and local crates, and against the
+/// `org/repo` of the GitHub repositories. Patterns are globs, where `*` matches any sequence of
+/// characters and `?` any single character, unless they're enclosed in slashes like
+/// `/^win(32|64)-/`, which makes them regular expressions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CratePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl CratePattern {
+    pub fn matches(&self, krate: &Crate) -> bool {
+        match krate {
+            Crate::Registry(krate) => self.regex.is_match(&krate.name),
+            Crate::GitHub(repo) => self.regex.is_match(&format!("{}/{}", repo.org, repo.name)),
+            Crate::Local(name) => self.regex.is_match(name),
+            Crate::Path(path) => self.regex.is_match(path),
+            Crate::Git(repo) => self.regex.is_match(&repo.url),
+        }
+    }
+}
+
+impl FromStr for CratePattern {
+    type Err = InvalidPattern;
+
+    fn from_str(input: &str) -> Result<Self, InvalidPattern> {
+        let invalid = || InvalidPattern(input.to_string());
+        let regex = match input.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some("") => return Err(invalid()),
+            Some(regex) => regex.to_string(),
+            None if input.is_empty() => return Err(invalid()),
+            None => {
+                let mut regex = String::from("^");
+                for c in input.chars() {
+                    match c {
+                        '*' => regex.push_str(".*"),
+                        '?' => regex.push('.'),
+                        c => regex.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                regex.push('$');
+                regex
+            }
+        };
+        Ok(CratePattern {
+            source: input.to_string(),
+            regex: Regex::new(&regex).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for CratePattern {
+    type Error = InvalidPattern;
+
+    fn try_from(input: String) -> Result<Self, InvalidPattern> {
+        input.parse()
+    }
+}
+
+impl From<CratePattern> for String {
+    fn from(pattern: CratePattern) -> String {
+        pattern.source
+    }
+}
+
+impl fmt::Display for CratePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for CratePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for CratePattern {}
+
+#[cfg(test)]
+mod tests {
+    use super::{CratePattern, InvalidPattern};
+    use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+
+    #[test]
+    fn test_matches() {
+        let registry = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let matches =
+            |pattern: &str, krate: &Crate| pattern.parse::<CratePattern>().unwrap().matches(krate);
+
+        assert!(matches("win-*", &registry("win-api")));
+        assert!(!matches("win-*", &registry("twin-peaks")));
+        assert!(matches("*-sys", &registry("openssl-sys")));
+        assert!(!matches("*-sys", &registry("openssl-system")));
+        assert!(matches("serde?json", &registry("serde_json")));
+        assert!(matches("a.b", &registry("a.b")));
+        assert!(!matches("a.b", &registry("axb")));
+        assert!(matches("/^win(32|64)-/", &registry("win32-api")));
+        assert!(!matches("/^win(32|64)-/", &registry("win16-api")));
+
+        let repo = Crate::GitHub(GitHubRepo {
+            org: "rust-lang".into(),
+            name: "crater".into(),
+            sha: None,
+        });
+        assert!(matches("rust-lang/*", &repo));
+        assert!(matches("rust-lang/crater", &repo));
+        assert!(!matches("crater", &repo));
+    }
+
+    #[test]
+    fn test_parse() {
+        let pattern: CratePattern = "win-*".parse().unwrap();
+        assert_eq!(pattern.to_string(), "win-*");
+        assert_eq!(serde_json::to_string(&[pattern]).unwrap(), "[\"win-*\"]");
+
+        for invalid in ["", "//", "/(/"] {
+            assert_eq!(
+                invalid.parse::<CratePattern>(),
+                Err(InvalidPattern(invalid.into()))
+            );
+        }
+    }
+}
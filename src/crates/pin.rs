@@ -0,0 +1,205 @@
+//! Pinning of the git repositories tested by the experiments to a single commit. Repositories can
+//! be pushed to while an experiment runs, so the commit their default branch points to is
+//! resolved when the experiment is created, and every build checks out that commit.
+
+use crate::config::Config;
+use crate::crates::{Crate, GitHubRepo, GitRepo};
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::utils;
+use percent_encoding::utf8_percent_encode;
+use remove_dir_all::remove_dir_all;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Number of repositories resolved in parallel when creating an experiment.
+const RESOLVE_THREADS: usize = 16;
+/// Time after which resolving the commit of a single repository is abandoned.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time after which the repositories not resolved yet are left unpinned, so that creating an
+/// experiment doesn't take forever when the git servers are slow.
+const PIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Run git without ever prompting for credentials, which deleted or private repositories ask.
+fn git() -> Command {
+    let mut command = Command::new("git");
+    command.env("GIT_TERMINAL_PROMPT", "0");
+    command
+}
+
+fn run(command: &mut Command) -> Fallible<String> {
+    let output = command.output()?;
+    check_output(command, output)
+}
+
+/// Run the command, killing it if it didn't exit before the timeout.
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> Fallible<String> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{:?} timed out after {}s", command, timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let output = child.wait_with_output()?;
+    check_output(command, output)
+}
+
+fn check_output(command: &Command, output: Output) -> Fallible<String> {
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Resolve the commit `HEAD` points to in a remote repository.
+fn resolve_head(url: &str) -> Fallible<String> {
+    let output = run_with_timeout(git().args(["ls-remote", url, "HEAD"]), RESOLVE_TIMEOUT)?;
+    parse_ls_remote(&output).ok_or_else(|| anyhow!("no HEAD found in {}", url))
+}
+
+fn parse_ls_remote(output: &str) -> Option<String> {
+    let sha = output.split_whitespace().next()?;
+    if sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(sha.to_string())
+    } else {
+        None
+    }
+}
+
+fn pin(krate: &mut Crate, deadline: Instant) {
+    let (url, sha) = match krate {
+        Crate::GitHub(GitHubRepo { org, name, sha }) if sha.is_none() => {
+            (format!("https://github.com/{org}/{name}"), sha)
+        }
+        Crate::Git(GitRepo { url, sha }) if sha.is_none() => (url.clone(), sha),
+        _ => return,
+    };
+    if Instant::now() > deadline {
+        warn!(
+            "not pinning {}: resolving the repositories took too long",
+            url
+        );
+        return;
+    }
+
+    match resolve_head(&url) {
+        Ok(head) => *sha = Some(head),
+        // The build will fail the same way, with the error recorded in its log
+        Err(err) => warn!("failed to resolve the HEAD of {}: {:?}", url, err),
+    }
+}
+
+/// Pin the GitHub and git repositories without a commit to the current commit of their default
+/// branch. Repositories whose commit can't be resolved in time are left unpinned.
+pub(crate) fn pin_git_shas(crates: &mut [Crate]) {
+    let unpinned = crates
+        .iter()
+        .filter(|krate| {
+            matches!(
+                krate,
+                Crate::GitHub(GitHubRepo { sha: None, .. }) | Crate::Git(GitRepo { sha: None, .. })
+            )
+        })
+        .count();
+    if unpinned == 0 {
+        return;
+    }
+
+    info!("resolving the commits of {} git repositories...", unpinned);
+    let deadline = Instant::now() + PIN_TIMEOUT;
+    let chunk_size = crates.len().div_ceil(RESOLVE_THREADS);
+    std::thread::scope(|scope| {
+        for chunk in crates.chunks_mut(chunk_size) {
+            scope.spawn(move || chunk.iter_mut().for_each(|krate| pin(krate, deadline)));
+        }
+    });
+}
+
+/// Directory the pinned commit of the repository is checked out in.
+pub(super) fn checkout_dir(krate: &Crate) -> PathBuf {
+    WORK_DIR
+        .join("git-checkouts")
+        .join(utf8_percent_encode(&krate.id(), &utils::FILENAME_ENCODE_SET).to_string())
+}
+
+impl Crate {
+    /// The repository and commit of pinned GitHub and git repositories.
+    pub(super) fn pinned_commit(&self, config: &Config) -> Option<(String, &str)> {
+        match self {
+            Crate::GitHub(repo) => {
+                let url = match &config.crate_cache {
+                    Some(cache) => cache.git_url(repo),
+                    None => format!("https://github.com/{}/{}", repo.org, repo.name),
+                };
+                Some((url, repo.sha.as_deref()?))
+            }
+            Crate::Git(repo) => Some((repo.url.clone(), repo.sha.as_deref()?)),
+            Crate::Registry(_) | Crate::Local(_) | Crate::Path(_) => None,
+        }
+    }
+
+    /// Check out the pinned commit of the repository, which is then built from the checkout.
+    /// Does nothing for the other crates.
+    pub(crate) fn checkout_pinned(&self, config: &Config) -> Fallible<()> {
+        let Some((url, sha)) = self.pinned_commit(config) else {
+            return Ok(());
+        };
+        let dir = checkout_dir(self);
+        if dir.exists() {
+            remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        info!("checking out commit {} of {}", sha, url);
+        run(git().arg("init").arg("--quiet").arg(&dir))?;
+        // Fetching a single commit is supported by GitHub, but not by every git server
+        if run(git()
+            .current_dir(&dir)
+            .args(["fetch", "--quiet", "--depth", "1", &url, sha]))
+        .is_err()
+        {
+            run(git().current_dir(&dir).args(["fetch", "--quiet", &url]))?;
+        }
+        run(git()
+            .current_dir(&dir)
+            .args(["checkout", "--quiet", "--detach", sha]))
+        .with_context(|| format!("commit {sha} is not in {url} anymore"))?;
+        Ok(())
+    }
+
+    /// Remove the checkout created by `checkout_pinned`, if any.
+    pub(crate) fn remove_checkout(&self) -> Fallible<()> {
+        let dir = checkout_dir(self);
+        if dir.exists() {
+            remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_ls_remote;
+
+    #[test]
+    fn test_parse_ls_remote() {
+        assert_eq!(
+            parse_ls_remote("0123456789abcdef0123456789abcdef01234567\tHEAD\n"),
+            Some("0123456789abcdef0123456789abcdef01234567".into())
+        );
+        assert_eq!(parse_ls_remote(""), None);
+        assert_eq!(parse_ls_remote("fatal: repository not found"), None);
+    }
+}
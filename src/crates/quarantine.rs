@@ -0,0 +1,231 @@
+use crate::config::Config;
+use crate::crates::Crate;
+use crate::db::{Database, QueryUtils, TransactionHandle};
+use crate::prelude::*;
+use crate::results::{BrokenReason, TestResult};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+/// Crate whose build behaved suspiciously. The crate is quarantined right away, and skipped by
+/// the experiments which don't ignore the blacklist until someone reviews it and either confirms
+/// the quarantine, reporting it as broken, or lifts it.
+#[derive(Debug, Serialize)]
+pub struct QuarantinedCrate {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub experiment: String,
+    pub reason: String,
+    pub quarantined_at: DateTime<Utc>,
+    /// When the quarantine was confirmed, missing while it's waiting for a review.
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// Quarantine the crate after its build in the experiment behaved suspiciously, skipping it in
+/// the experiments which didn't start testing it yet. Its result in the experiment is kept until
+/// the quarantine is confirmed. Returns whether the crate wasn't already quarantined.
+pub(crate) fn quarantine(db: &Database, krate: &Crate, ex: &str, reason: &str) -> Fallible<bool> {
+    let krate = krate.id();
+    let quarantined = db.transaction(true, |t| {
+        let changes = t.execute(
+            "INSERT OR IGNORE INTO quarantined_crates (crate, experiment, reason, quarantined_at) \
+             VALUES (?1, ?2, ?3, ?4);",
+            &[&krate, &ex, &reason, &Utc::now()],
+        )?;
+        if changes == 0 {
+            return Ok(false);
+        }
+
+        t.execute(
+            "UPDATE experiment_crates SET skipped = 1 \
+             WHERE crate = ?1 AND status = 'queued' AND started_at IS NULL \
+             AND experiment IN (SELECT name FROM experiments WHERE ignore_blacklist = 0);",
+            &[&krate],
+        )?;
+        Ok(true)
+    })?;
+    if quarantined {
+        warn!("quarantined crate {}: {}", krate, reason);
+    }
+    Ok(quarantined)
+}
+
+/// Confirm the quarantine of a crate waiting for a review, reporting it as broken in the
+/// experiment which quarantined it. Returns whether the crate was waiting for a review.
+pub fn confirm(t: &TransactionHandle, krate: &str) -> Fallible<bool> {
+    let changes = t.execute(
+        "UPDATE quarantined_crates SET confirmed_at = ?2 \
+         WHERE crate = ?1 AND confirmed_at IS NULL;",
+        &[&krate, &Utc::now()],
+    )?;
+    if changes == 0 {
+        return Ok(false);
+    }
+
+    t.execute(
+        "UPDATE results SET result = ?2 WHERE crate = ?1 AND experiment = \
+         (SELECT experiment FROM quarantined_crates WHERE crate = ?1);",
+        &[
+            &krate,
+            &TestResult::BrokenCrate(BrokenReason::Quarantined).to_string(),
+        ],
+    )?;
+    Ok(true)
+}
+
+/// Lift the quarantine of the crate, either after reviewing it or after confirming it, returning
+/// whether the crate was quarantined. The experiments which didn't start testing it yet test it
+/// again, unless it's on the blacklist.
+pub fn release(t: &TransactionHandle, config: &Config, krate: &str) -> Fallible<bool> {
+    let changes = t.execute(
+        "DELETE FROM quarantined_crates WHERE crate = ?1;",
+        &[&krate],
+    )?;
+    if changes == 0 {
+        return Ok(false);
+    }
+
+    let blacklisted = krate
+        .parse::<Crate>()
+        .is_ok_and(|parsed| config.should_skip(&parsed));
+    t.execute(
+        "UPDATE experiment_crates SET skipped = 0 \
+         WHERE crate = ?1 AND status = 'queued' AND started_at IS NULL AND (?2 = 0 OR \
+         experiment IN (SELECT name FROM experiments WHERE ignore_blacklist = 1));",
+        &[&krate, &blacklisted],
+    )?;
+    Ok(true)
+}
+
+pub fn list(db: &Database) -> Fallible<Vec<QuarantinedCrate>> {
+    db.query(
+        "SELECT * FROM quarantined_crates ORDER BY quarantined_at DESC;",
+        [],
+        |row| {
+            Ok(QuarantinedCrate {
+                krate: row.get("crate")?,
+                experiment: row.get("experiment")?,
+                reason: row.get("reason")?,
+                quarantined_at: row.get("quarantined_at")?,
+                confirmed_at: row.get("confirmed_at")?,
+            })
+        },
+    )
+}
+
+/// The IDs of the quarantined crates, including the ones waiting for a review.
+pub(crate) fn quarantined_ids(db: &Database) -> Fallible<HashSet<String>> {
+    Ok(db
+        .query("SELECT crate FROM quarantined_crates;", [], |row| {
+            row.get::<_, String>("crate")
+        })?
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confirm, list, quarantined_ids, release};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::Crate;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::Experiment;
+    use crate::results::{
+        BrokenReason, BuildReport, DatabaseDB, Quarantine, ReadResults, TestResult,
+    };
+    use crate::runner::RecordProgress;
+    use crate::server::audit;
+    use crate::toolchain::MAIN_TOOLCHAIN;
+
+    fn is_skipped(db: &Database, ex: &str, krate: &Crate) -> bool {
+        db.get_row(
+            "SELECT skipped FROM experiment_crates WHERE experiment = ?1 AND crate = ?2;",
+            [ex, krate.id().as_str()],
+            |row| row.get("skipped"),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        CreateExperiment::dummy("dummy2").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let results = DatabaseDB::new(&db);
+
+        // The suspicious build is quarantined until it's reviewed
+        let krate = Crate::Local("build-pass".into());
+        let report = BuildReport {
+            quarantine: Some(Quarantine {
+                reason: "mining".into(),
+            }),
+            ..BuildReport::default()
+        };
+        results
+            .record_progress(
+                &ex,
+                &krate,
+                &MAIN_TOOLCHAIN,
+                b"log",
+                &TestResult::TestPass,
+                &report,
+                None,
+            )
+            .unwrap();
+
+        let quarantined = list(&db).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].krate, "local/build-pass");
+        assert_eq!(quarantined[0].reason, "mining");
+        assert!(quarantined[0].confirmed_at.is_none());
+        assert_eq!(
+            audit::recent(&db, 10).unwrap()[0].action,
+            "quarantine-crate"
+        );
+        assert!(quarantined_ids(&db).unwrap().contains("local/build-pass"));
+        assert!(is_skipped(&db, "dummy2", &krate));
+        assert_eq!(
+            results
+                .load_test_result(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+
+        // Once confirmed the crate is reported as broken
+        let confirm_crate = || db.transaction(true, |t| confirm(t, "local/build-pass"));
+        assert!(confirm_crate().unwrap());
+        assert!(!confirm_crate().unwrap());
+        assert!(quarantined_ids(&db).unwrap().contains("local/build-pass"));
+        assert_eq!(
+            results
+                .load_test_result(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestResult::BrokenCrate(BrokenReason::Quarantined))
+        );
+        assert!(is_skipped(&db, "dummy2", &krate));
+
+        // Experiments ignoring the blacklist test it anyway
+        CreateExperiment::dummy("dummy3").apply(&ctx).unwrap();
+        CreateExperiment {
+            ignore_blacklist: true,
+            ..CreateExperiment::dummy("dummy4")
+        }
+        .apply(&ctx)
+        .unwrap();
+        assert!(is_skipped(&db, "dummy3", &krate));
+        assert!(!is_skipped(&db, "dummy4", &krate));
+
+        // Lifting the quarantine tests it again
+        let release_crate = || db.transaction(true, |t| release(t, &config, "local/build-pass"));
+        assert!(release_crate().unwrap());
+        assert!(!release_crate().unwrap());
+        assert!(list(&db).unwrap().is_empty());
+        assert!(!is_skipped(&db, "dummy2", &krate));
+        assert!(!is_skipped(&db, "dummy3", &krate));
+    }
+}
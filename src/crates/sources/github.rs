@@ -1,6 +1,9 @@
-use crate::crates::{lists::List, Crate};
+use crate::crates::lists::{FetchedList, List};
+use crate::crates::Crate;
 use crate::prelude::*;
+use reqwest::header::ETAG;
 use std::borrow::Cow;
+use std::io::Read;
 use std::str::FromStr;
 
 static CACHED_LIST: &str =
@@ -33,37 +36,60 @@ impl List for GitHubList {
     fn fetch(&self) -> Fallible<Vec<Crate>> {
         info!("loading cached GitHub list from {}", self.source);
 
-        let mut resp = crate::utils::http::get_sync(&self.source)
+        let resp = crate::utils::http::get_sync(&self.source)
             .with_context(|| format!("failed to fetch GitHub crates list from {}", self.source))?;
-        let mut reader = ::csv::Reader::from_reader(&mut resp);
+        parse_list(resp)
+    }
 
-        let mut list = Vec::new();
-        for line in reader.deserialize() {
-            let line: ListRepo = line?;
+    fn fetch_changed(&self, revision: Option<&str>) -> Fallible<Option<FetchedList>> {
+        info!("loading cached GitHub list from {}", self.source);
 
-            // Only import repos with a Cargo.toml or Cargo.lock
-            if !line.has_cargo_toml || !line.has_cargo_lock {
-                continue;
-            }
+        let resp = crate::utils::http::get_sync_if_changed(&self.source, revision)
+            .with_context(|| format!("failed to fetch GitHub crates list from {}", self.source))?;
+        let Some(resp) = resp else {
+            return Ok(None);
+        };
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+        Ok(Some(FetchedList {
+            crates: parse_list(resp)?,
+            revision: etag,
+        }))
+    }
+}
 
-            let mut name_parts = line.name.split('/');
-            let org = name_parts.next();
-            let name = name_parts.next();
-            let trailing = name_parts.next();
-
-            if let (Some(org), Some(name), None) = (org, name, trailing) {
-                list.push(Crate::GitHub(GitHubRepo {
-                    org: org.to_string(),
-                    name: name.to_string(),
-                    sha: None,
-                }));
-            } else {
-                warn!("skipping malformed repo name: {}", line.name);
-            }
+fn parse_list(resp: impl Read) -> Fallible<Vec<Crate>> {
+    let mut reader = ::csv::Reader::from_reader(resp);
+
+    let mut list = Vec::new();
+    for line in reader.deserialize() {
+        let line: ListRepo = line?;
+
+        // Only import repos with a Cargo.toml or Cargo.lock
+        if !line.has_cargo_toml || !line.has_cargo_lock {
+            continue;
         }
 
-        Ok(list)
+        let mut name_parts = line.name.split('/');
+        let org = name_parts.next();
+        let name = name_parts.next();
+        let trailing = name_parts.next();
+
+        if let (Some(org), Some(name), None) = (org, name, trailing) {
+            list.push(Crate::GitHub(GitHubRepo {
+                org: org.to_string(),
+                name: name.to_string(),
+                sha: None,
+            }));
+        } else {
+            warn!("skipping malformed repo name: {}", line.name);
+        }
     }
+
+    Ok(list)
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
@@ -1,61 +1,201 @@
-use crate::crates::{lists::List, Crate};
+use crate::crates::lists::{FetchedList, List};
+use crate::crates::Crate;
 use crate::dirs::WORK_DIR;
 use crate::prelude::*;
 use crates_index::GitIndex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{self};
+use std::thread;
+use std::time::Duration;
+
+const CRATES_IO_API: &str = "https://crates.io/api/v1";
+// The crawler policy of crates.io allows at most one request per second
+const CRATES_IO_API_INTERVAL: Duration = Duration::from_secs(1);
+const CRATES_IO_PAGE_SIZE: usize = 100;
 
 pub(crate) struct RegistryList;
 
+/// Open the local copy of the crates.io index, cloning it if it doesn't exist yet.
+pub(crate) fn open_index() -> Fallible<GitIndex> {
+    fs::create_dir_all(&*WORK_DIR)?;
+    Ok(GitIndex::with_path(
+        WORK_DIR.join("crates.io-index"),
+        "https://github.com/rust-lang/crates.io-index",
+    )?)
+}
+
 impl List for RegistryList {
     const NAME: &'static str = "registry";
 
     fn fetch(&self) -> Fallible<Vec<Crate>> {
-        let mut list = Vec::new();
-        let mut counts = HashMap::new();
-
-        fs::create_dir_all(&*WORK_DIR)?;
-        let mut index = GitIndex::with_path(
-            WORK_DIR.join("crates.io-index"),
-            "https://github.com/rust-lang/crates.io-index",
-        )?;
+        let mut index = open_index()?;
+        index.update()?;
+        Ok(list_crates(&index))
+    }
+
+    fn fetch_changed(&self, revision: Option<&str>) -> Fallible<Option<FetchedList>> {
+        let mut index = open_index()?;
         index.update()?;
 
-        for krate in index.crates() {
-            // The versions() method returns the list of published versions starting from the
-            // first one, so its output is reversed to check the latest first
-            for version in krate.versions().iter().rev() {
-                // Try every version until we find a non-yanked one. If all the versions are
-                // yanked the crate is automatically skipped
-                if !version.is_yanked() {
-                    // Increment the counters of this crate's dependencies
-                    for dependency in version.dependencies() {
-                        let count = counts.entry(dependency.name().to_string()).or_insert(0);
-                        *count += 1;
-                    }
-
-                    list.push(Crate::Registry(RegistryCrate {
-                        name: krate.name().to_string(),
-                        version: version.version().to_string(),
-                    }));
-                    break;
+        // The list only depends on the content of the index
+        let head = index.head_commit();
+        if head.is_some() && head.as_deref() == revision {
+            return Ok(None);
+        }
+        Ok(Some(FetchedList {
+            crates: list_crates(&index),
+            revision: head,
+        }))
+    }
+}
+
+/// List the latest non-yanked version of every crate of the index, sorted by the number of crates
+/// depending on them.
+fn list_crates(index: &GitIndex) -> Vec<Crate> {
+    let mut list = Vec::new();
+    let mut counts = HashMap::new();
+
+    for krate in index.crates() {
+        // The versions() method returns the list of published versions starting from the
+        // first one, so its output is reversed to check the latest first
+        for version in krate.versions().iter().rev() {
+            // Try every version until we find a non-yanked one. If all the versions are
+            // yanked the crate is automatically skipped
+            if !version.is_yanked() {
+                // Increment the counters of this crate's dependencies
+                for dependency in version.dependencies() {
+                    let count = counts.entry(dependency.name().to_string()).or_insert(0);
+                    *count += 1;
                 }
+
+                list.push(Crate::Registry(RegistryCrate {
+                    name: krate.name().to_string(),
+                    version: version.version().to_string(),
+                }));
+                break;
             }
         }
+    }
+
+    // Ensure the list is sorted by popularity
+    list.sort_by(|a, b| {
+        if let (Crate::Registry(ref a), Crate::Registry(ref b)) = (a, b) {
+            let count_a = counts.get(&a.name).cloned().unwrap_or(0);
+            let count_b = counts.get(&b.name).cloned().unwrap_or(0);
+            count_b.cmp(&count_a)
+        } else {
+            panic!("non-registry crate produced in the registry list");
+        }
+    });
+
+    list
+}
+
+/// Find the names of the crates depending on `krate`, either directly or through at most `depth`
+/// levels of dependencies, according to the latest non-yanked version of each crate in the local
+/// copy of the crates.io index.
+pub(crate) fn reverse_dependencies(krate: &str, depth: u32) -> Fallible<HashSet<String>> {
+    let index = open_index()?;
+    if index.crate_(krate).is_none() {
+        bail!("crate {} not found in the crates.io index", krate);
+    }
+
+    // Map each crate to the crates directly depending on it
+    let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+    for krate in index.crates() {
+        if let Some(version) = krate.versions().iter().rev().find(|v| !v.is_yanked()) {
+            for dependency in version.dependencies() {
+                dependents
+                    .entry(dependency.crate_name().to_string())
+                    .or_default()
+                    .push(krate.name().to_string());
+            }
+        }
+    }
 
-        // Ensure the list is sorted by popularity
-        list.sort_by(|a, b| {
-            if let (Crate::Registry(ref a), Crate::Registry(ref b)) = (a, b) {
-                let count_a = counts.get(&a.name).cloned().unwrap_or(0);
-                let count_b = counts.get(&b.name).cloned().unwrap_or(0);
-                count_b.cmp(&count_a)
-            } else {
-                panic!("non-registry crate produced in the registry list");
+    let found = walk_dependents(&dependents, krate, depth);
+    info!(
+        "found {} crates depending on {} (depth {})",
+        found.len(),
+        krate,
+        depth
+    );
+    Ok(found)
+}
+
+fn walk_dependents(
+    dependents: &HashMap<String, Vec<String>>,
+    krate: &str,
+    depth: u32,
+) -> HashSet<String> {
+    let mut found = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back((krate, 0));
+    while let Some((current, current_depth)) = queue.pop_front() {
+        if current_depth == depth {
+            continue;
+        }
+        for dependent in dependents.get(current).into_iter().flatten() {
+            if dependent != krate && found.insert(dependent.clone()) {
+                queue.push_back((dependent.as_str(), current_depth + 1));
             }
-        });
+        }
+    }
+    found
+}
+
+#[derive(Deserialize)]
+struct OwnerResponse {
+    #[serde(alias = "user", alias = "team")]
+    owner: Owner,
+}
+
+#[derive(Deserialize)]
+struct Owner {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct CratesResponse {
+    crates: Vec<CratesResponseCrate>,
+}
 
-        Ok(list)
+#[derive(Deserialize)]
+struct CratesResponseCrate {
+    name: String,
+}
+
+/// Fetch from the crates.io API the names of the crates owned by a user (like `dtolnay`) or a
+/// team (like `github:rust-lang:libs`).
+pub(crate) fn crates_owned_by(owner: &str) -> Fallible<HashSet<String>> {
+    let (kind, param) = if owner.starts_with("github:") {
+        ("teams", "team_id")
+    } else {
+        ("users", "user_id")
+    };
+    let id = crate::utils::http::get_sync(&format!("{CRATES_IO_API}/{kind}/{owner}"))
+        .with_context(|| format!("failed to find the crates.io owner {owner}"))?
+        .json::<OwnerResponse>()?
+        .owner
+        .id;
+
+    let mut names = HashSet::new();
+    for page in 1.. {
+        thread::sleep(CRATES_IO_API_INTERVAL);
+        let resp: CratesResponse = crate::utils::http::get_sync(&format!(
+            "{CRATES_IO_API}/crates?{param}={id}&per_page={CRATES_IO_PAGE_SIZE}&page={page}"
+        ))?
+        .json()?;
+
+        let count = resp.crates.len();
+        names.extend(resp.crates.into_iter().map(|c| c.name));
+        if count < CRATES_IO_PAGE_SIZE {
+            break;
+        }
     }
+
+    info!("found {} crates owned by {}", names.len(), owner);
+    Ok(names)
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
@@ -63,3 +203,36 @@ pub struct RegistryCrate {
     pub name: String,
     pub version: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::walk_dependents;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn test_walk_dependents() {
+        let mut dependents = HashMap::new();
+        dependents.insert("serde_derive".to_string(), vec!["serde".to_string()]);
+        dependents.insert(
+            "serde".to_string(),
+            vec!["serde_json".to_string(), "toml".to_string()],
+        );
+        dependents.insert("serde_json".to_string(), vec!["serde".to_string()]);
+        dependents.insert("toml".to_string(), vec!["cargo".to_string()]);
+
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<HashSet<_>>();
+        assert_eq!(
+            walk_dependents(&dependents, "serde_derive", 1),
+            names(&["serde"])
+        );
+        assert_eq!(
+            walk_dependents(&dependents, "serde_derive", 2),
+            names(&["serde", "serde_json", "toml"])
+        );
+        assert_eq!(
+            walk_dependents(&dependents, "serde_derive", 10),
+            names(&["serde", "serde_json", "toml", "cargo"])
+        );
+        assert!(walk_dependents(&dependents, "cargo", 10).is_empty());
+    }
+}
@@ -363,9 +363,492 @@ fn migrations() -> Vec<(&'static str, MigrationKind)> {
         MigrationKind::SQL("alter table agents add column latest_work_for text;"),
     ));
 
+    migrations.push((
+        "add_experiment_follow_up",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN follow_up TEXT;
+            ALTER TABLE experiments ADD COLUMN parent TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_field_sandbox_image",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN sandbox_image TEXT;"),
+    ));
+
+    migrations.push((
+        "create_triage_annotations",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE triage_annotations (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                status TEXT NOT NULL,
+                issue TEXT,
+                note TEXT,
+                author TEXT NOT NULL,
+                updated_at DATETIME NOT NULL,
+
+                PRIMARY KEY (experiment, crate) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_audit_log",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                author TEXT NOT NULL,
+                action TEXT NOT NULL,
+                experiment TEXT,
+                details TEXT,
+                performed_at DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_field_zulip_stream",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN zulip_stream TEXT;"),
+    ));
+
+    migrations.push((
+        "add_results_log_key",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN log_key TEXT;
+            ALTER TABLE results ADD COLUMN log_hash TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_build_metrics",
+        MigrationKind::SQL("ALTER TABLE results ADD COLUMN build_metrics TEXT;"),
+    ));
+
+    migrations.push((
+        "add_results_test_runs",
+        MigrationKind::SQL("ALTER TABLE results ADD COLUMN test_runs TEXT;"),
+    ));
+
+    migrations.push((
+        "add_experiment_field_repeat",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN repeat INTEGER;"),
+    ));
+
+    migrations.push((
+        "add_results_crate_index",
+        MigrationKind::SQL("CREATE INDEX results__crate ON results (crate);"),
+    ));
+
+    migrations.push((
+        "create_report_jobs",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE report_jobs (
+                experiment TEXT NOT NULL PRIMARY KEY,
+                phase TEXT NOT NULL,
+                last_crate TEXT,
+                archives TEXT,
+                updated_at DATETIME NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_subscriptions",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE subscriptions (
+                experiment TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                target TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+
+                PRIMARY KEY (experiment, kind, target),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_report_index_versions",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE report_index_versions (
+                experiment TEXT NOT NULL PRIMARY KEY,
+                versions TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_maintenance",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE maintenance (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                message TEXT NOT NULL,
+                enabled_by TEXT NOT NULL,
+                enabled_at DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_rustc_versions",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN toolchain_start_version TEXT;
+            ALTER TABLE experiments ADD COLUMN toolchain_end_version TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_field_test_options",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN test_options TEXT;"),
+    ));
+
+    migrations.push((
+        "add_experiment_field_deadline",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN deadline TEXT;"),
+    ));
+
+    migrations.push((
+        "add_report_jobs_progress",
+        MigrationKind::SQL("ALTER TABLE report_jobs ADD COLUMN progress INTEGER;"),
+    ));
+
+    migrations.push((
+        "create_quarantined_crates",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE quarantined_crates (
+                crate TEXT NOT NULL PRIMARY KEY,
+                experiment TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                quarantined_at DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_results_trend_indexes",
+        MigrationKind::SQL(
+            "
+            CREATE INDEX experiments__created_at ON experiments (created_at);
+
+            CREATE INDEX results__crate_experiment ON results (crate, experiment);
+            DROP INDEX results__crate;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_filed_issues",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE filed_issues (
+                crate TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                experiment TEXT NOT NULL,
+                issue_url TEXT NOT NULL,
+                filed_at DATETIME NOT NULL,
+
+                PRIMARY KEY (crate, reason)
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_list_revisions",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE list_revisions (
+                list TEXT PRIMARY KEY NOT NULL,
+                revision TEXT NOT NULL,
+                fetched_at DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_unstable_features",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE unstable_features (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                feature TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, feature),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_comparison_policy",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN comparison_policy TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_disk_usage",
+        MigrationKind::SQL("ALTER TABLE results ADD COLUMN disk_usage TEXT;"),
+    ));
+
+    migrations.push((
+        "add_experiment_registry_overlay",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN registry_overlay TEXT;"),
+    ));
+
+    migrations.push((
+        "create_shadow_runs",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN agent TEXT;
+
+            CREATE TABLE shadow_runs (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                original_agent TEXT NOT NULL,
+                shadow_agent TEXT,
+                assigned_at DATETIME,
+                created_at DATETIME NOT NULL,
+                completed_at DATETIME,
+
+                PRIMARY KEY (experiment, crate),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+
+            CREATE TABLE shadow_results (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                original_agent TEXT NOT NULL,
+                original_result TEXT NOT NULL,
+                shadow_agent TEXT NOT NULL,
+                shadow_result TEXT NOT NULL,
+                recorded_at DATETIME NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_build_times",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE build_times (
+                crate TEXT PRIMARY KEY NOT NULL,
+                builds INTEGER NOT NULL,
+                average_time REAL NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_excluded_crates",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE excluded_crates (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                reason TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_result_uploads",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE result_uploads (
+                agent TEXT NOT NULL,
+                idempotency_key TEXT NOT NULL,
+                received_at DATETIME NOT NULL,
+
+                PRIMARY KEY (agent, idempotency_key)
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_build_time",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN build_time REAL;
+            ALTER TABLE results ADD COLUMN recorded_at DATETIME;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_build_cache",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN build_cache INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE results ADD COLUMN build_cache_stats TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_dependency_patches",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN dependency_patches TEXT;"),
+    ));
+
+    migrations.push((
+        "add_track_warnings",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN track_warnings INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE results ADD COLUMN build_warnings TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_build_reproducibility",
+        MigrationKind::SQL("ALTER TABLE results ADD COLUMN build_reproducibility TEXT;"),
+    ));
+
+    migrations.push((
+        "add_exclude",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN exclude TEXT;"),
+    ));
+
+    migrations.push((
+        "add_tags",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN tags TEXT;"),
+    ));
+
+    migrations.push((
+        "create_hooks",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE hooks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                experiment TEXT,
+                created_by TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    // The subscriptions created before the email addresses were confirmed are kept as confirmed
+    migrations.push((
+        "add_subscriptions_confirmation",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE subscriptions ADD COLUMN token TEXT;
+            ALTER TABLE subscriptions ADD COLUMN confirmed_at DATETIME;
+            UPDATE subscriptions SET confirmed_at = created_at, token = lower(hex(randomblob(16)));
+            CREATE UNIQUE INDEX subscriptions__token ON subscriptions (token);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_quarantined_crates_confirmed_at",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE quarantined_crates ADD COLUMN confirmed_at DATETIME;
+            UPDATE quarantined_crates SET confirmed_at = quarantined_at;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_client_side_report",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN client_side_report INTEGER NOT NULL DEFAULT 0;",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_exact_versions",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN exact_versions INTEGER NOT NULL DEFAULT 0;",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_overridden",
+        MigrationKind::SQL("ALTER TABLE results ADD COLUMN overridden INTEGER NOT NULL DEFAULT 0;"),
+    ));
+
+    migrations.push((
+        "add_results_recorded_at_index",
+        MigrationKind::SQL("CREATE INDEX results__recorded_at ON results (recorded_at);"),
+    ));
+
     migrations
 }
 
+fn executed_migrations(db: &Connection) -> Fallible<HashSet<String>> {
+    let mut prepared = db.prepare("SELECT name FROM migrations;")?;
+    let mut result = HashSet::new();
+    for value in prepared.query_map([], |row| row.get::<_, String>(0))? {
+        result.insert(value?);
+    }
+
+    Ok(result)
+}
+
+/// Ensure all the migrations were already executed on the database, without changing it.
+pub fn verify(db: &Connection) -> Fallible<()> {
+    let executed_migrations = executed_migrations(db)?;
+
+    for (name, _) in migrations() {
+        if !executed_migrations.contains(name) {
+            bail!("the database is missing the migration {}", name);
+        }
+    }
+
+    Ok(())
+}
+
 pub fn execute(db: &mut Connection) -> Fallible<()> {
     // If the database version is 0, create the migrations table and bump it
     let version: i32 = db.query_row("PRAGMA user_version;", [], |r| r.get(0))?;
@@ -374,15 +857,7 @@ pub fn execute(db: &mut Connection) -> Fallible<()> {
         db.execute("PRAGMA user_version = 1;", [])?;
     }
 
-    let executed_migrations = {
-        let mut prepared = db.prepare("SELECT name FROM migrations;")?;
-        let mut result = HashSet::new();
-        for value in prepared.query_map([], |row| row.get::<_, String>(0))? {
-            result.insert(value?);
-        }
-
-        result
-    };
+    let executed_migrations = executed_migrations(db)?;
 
     for &(name, ref migration) in &migrations() {
         if !executed_migrations.contains(name) {
@@ -1,10 +1,11 @@
 mod migrations;
 
+use crate::crates::Crate;
 use crate::dirs::WORK_DIR;
 use crate::prelude::*;
 use r2d2::Pool;
 use rusqlite::types::ToSql;
-use rusqlite::{Connection, Row, Transaction};
+use rusqlite::{Connection, OpenFlags, Row, Transaction};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -15,6 +16,7 @@ static DATABASE_PATH: &str = "crater.db";
 
 struct SqliteConnectionManager {
     file: PathBuf,
+    read_only: bool,
 }
 
 impl r2d2::ManageConnection for SqliteConnectionManager {
@@ -22,7 +24,16 @@ impl r2d2::ManageConnection for SqliteConnectionManager {
     type Error = rusqlite::Error;
 
     fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let connection = rusqlite::Connection::open(&self.file)?;
+        let connection = if self.read_only {
+            rusqlite::Connection::open_with_flags(
+                &self.file,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                    | OpenFlags::SQLITE_OPEN_URI,
+            )?
+        } else {
+            rusqlite::Connection::open(&self.file)?
+        };
         connection
             .pragma_update(None, "foreign_keys", "ON")
             .unwrap();
@@ -33,12 +44,32 @@ impl r2d2::ManageConnection for SqliteConnectionManager {
             .pragma_update(None, "cache_size", "-307200")
             .unwrap();
 
+        // build times are tracked across the versions of each crate, see `Crate::unversioned_id`
+        connection.create_scalar_function(
+            "unversioned_id",
+            1,
+            rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC
+                | rusqlite::functions::FunctionFlags::SQLITE_UTF8,
+            |ctx| {
+                let id = ctx.get::<String>(0)?;
+                Ok(match id.parse::<Crate>() {
+                    Ok(krate) => krate.unversioned_id(),
+                    Err(_) => id,
+                })
+            },
+        )?;
+
         // per docs, this is recommended for relatively long-lived connections (like what we have
         // due to the r2d2 pooling)
         // https://www.sqlite.org/pragma.html#pragma_optimize
-        connection
-            .pragma_update(None, "optimize", "0x10002")
-            .unwrap();
+        //
+        // optimizing might need to write the statistics it collects, which is not possible on
+        // read-only connections.
+        if !self.read_only {
+            connection
+                .pragma_update(None, "optimize", "0x10002")
+                .unwrap();
+        }
         Ok(connection)
     }
 
@@ -57,14 +88,26 @@ struct ErrorHandler;
 impl<E: std::error::Error> r2d2::HandleError<E> for ErrorHandler {
     fn handle_error(&self, error: E) {
         // ensure that a message gets logged regardless of whether it's enabled.
-        if log::log_enabled!(log::Level::Error) {
-            log::error!("r2d2 error: {:?}", error);
+        if tracing::enabled!(tracing::Level::ERROR) {
+            error!("r2d2 error: {:?}", error);
         } else {
             eprintln!("r2d2 error: {:?}", error);
         }
     }
 }
 
+/// Starts a read transaction on the connections of a snapshot, see [`Database::snapshot`].
+#[derive(Debug)]
+struct BeginSnapshot;
+
+impl r2d2::CustomizeConnection<Connection, rusqlite::Error> for BeginSnapshot {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        conn.execute_batch("BEGIN DEFERRED;")?;
+        // In WAL mode the snapshot is taken by the first read of the transaction
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master;", [], |_| Ok(()))
+    }
+}
+
 #[derive(Clone)]
 pub struct Database {
     pool: Pool<SqliteConnectionManager>,
@@ -94,7 +137,13 @@ impl Database {
 
         let path = WORK_DIR.join(DATABASE_PATH);
         std::fs::create_dir_all(&*WORK_DIR)?;
-        Database::new(SqliteConnectionManager { file: path }, None)
+        Database::new(
+            SqliteConnectionManager {
+                file: path,
+                read_only: false,
+            },
+            None,
+        )
     }
 
     pub fn open_at(path: &Path) -> Fallible<Self> {
@@ -102,6 +151,22 @@ impl Database {
         Database::new(
             SqliteConnectionManager {
                 file: path.to_owned(),
+                read_only: false,
+            },
+            None,
+        )
+    }
+
+    /// Open a replica of the database without ever writing to it, for example a snapshot of the
+    /// primary database served while the primary is being maintained.
+    pub fn open_read_only(path: &Path) -> Fallible<Self> {
+        if !path.exists() {
+            bail!("missing database replica: {}", path.display());
+        }
+        Database::new(
+            SqliteConnectionManager {
+                file: path.to_owned(),
+                read_only: true,
             },
             None,
         )
@@ -113,6 +178,7 @@ impl Database {
         Database::new(
             SqliteConnectionManager {
                 file: tempfile.path().to_owned(),
+                read_only: false,
             },
             Some(tempfile),
         )
@@ -125,6 +191,15 @@ impl Database {
             .build(conn)?;
 
         let mut connection = pool.get()?;
+        if pool.manager().read_only {
+            // The schema of a replica can't be migrated, make sure it's already up to date
+            migrations::verify(&connection)?;
+            return Ok(Database {
+                pool,
+                _tempfile: tempfile.map(Arc::new),
+            });
+        }
+
         if connection.pragma_query_value(None, "journal_mode", |r| {
             let current = r.get_ref(0)?.as_str()?;
             // in memory database is allowed to stay that way
@@ -154,6 +229,30 @@ impl Database {
         })
     }
 
+    /// Open a read-only view of the database as it is now, unaffected by the changes made while
+    /// it's being used, for example to generate the report of an experiment with results still
+    /// being recorded. The view uses a single connection with a long running read transaction,
+    /// which prevents the WAL from being checkpointed until the view is dropped: only keep it
+    /// while reading, and drop it before any slow operation like uploading the report.
+    pub fn snapshot(&self) -> Fallible<Self> {
+        let manager = SqliteConnectionManager {
+            file: self.pool.manager().file.clone(),
+            read_only: true,
+        };
+        let pool = Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connection_timeout(Duration::from_secs(5))
+            .connection_customizer(Box::new(BeginSnapshot))
+            .error_handler(Box::new(ErrorHandler))
+            .build(manager)?;
+        Ok(Database {
+            pool,
+            _tempfile: self._tempfile.clone(),
+        })
+    }
+
     pub fn transaction<T, F: FnOnce(&TransactionHandle) -> Fallible<T>>(
         &self,
         will_write: bool,
@@ -316,3 +415,51 @@ impl QueryUtils for TransactionHandle<'_> {
         f(&self.transaction)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Database, QueryUtils};
+
+    #[test]
+    fn test_open_read_only() {
+        let db = Database::temp().unwrap();
+        let path = db._tempfile.as_ref().unwrap().path().to_owned();
+
+        let replica = Database::open_read_only(&path).unwrap();
+        let migrations: Option<i64> = replica
+            .get_row("SELECT COUNT(*) FROM migrations;", [], |row| row.get(0))
+            .unwrap();
+        assert!(migrations.unwrap() > 0);
+        assert!(replica.execute("DELETE FROM migrations;", &[]).is_err());
+
+        // Replicas missing some migrations are refused
+        db.execute("DELETE FROM migrations WHERE rowid = 1;", &[])
+            .unwrap();
+        assert!(Database::open_read_only(&path).is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let db = Database::temp().unwrap();
+        let count = |db: &Database| -> i64 {
+            db.get_row("SELECT COUNT(*) FROM migrations;", [], |row| row.get(0))
+                .unwrap()
+                .unwrap()
+        };
+        let before = count(&db);
+
+        let snapshot = db.snapshot().unwrap();
+        assert_eq!(count(&snapshot), before);
+        db.execute("DELETE FROM migrations WHERE rowid = 1;", &[])
+            .unwrap();
+
+        // The changes made after the snapshot was taken are not visible through it
+        assert_eq!(count(&db), before - 1);
+        assert_eq!(count(&snapshot), before);
+        assert!(snapshot.execute("DELETE FROM migrations;", &[]).is_err());
+
+        // Dropping the snapshot doesn't affect the database
+        drop(snapshot);
+        assert_eq!(count(&db), before - 1);
+    }
+}
@@ -1,18 +1,24 @@
-use crate::crates::Crate;
+use crate::agent::{Capabilities, Requirement};
+use crate::config::Config;
+use crate::crates::{Crate, CratePattern, DependencyPatch, OverlayCrate, RegistryCrate};
 use crate::db::{Database, QueryUtils};
 use crate::prelude::*;
 use crate::results::TestResult;
-use crate::toolchain::Toolchain;
+use crate::toolchain::{RustcVersion, Toolchain};
 use crate::utils;
 use chrono::{DateTime, Utc};
 use rusqlite::Row;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt;
 use std::str::FromStr;
 use url::Url;
 
 //sqlite limit is ignored if the expression evaluates to a negative value
 static SQL_VARIABLE_LIMIT: usize = 500;
+/// Maximum number of crates in each batch handed out to the agents.
+const MAX_BATCH_SIZE: u32 = 1000;
+/// Build time, in seconds, assumed for the crates when no build time was ever recorded.
+const DEFAULT_BUILD_TIME: f64 = 60.0;
 
 string_enum!(pub enum Status {
     Queued => "queued",
@@ -21,15 +27,18 @@ string_enum!(pub enum Status {
     GeneratingReport => "generating-report",
     ReportFailed => "report-failed",
     Completed => "completed",
+    ToolchainUnavailable => "toolchain-unavailable",
 });
 
 string_enum!(pub enum Mode {
     BuildAndTest => "build-and-test",
+    BuildTestAndDoctest => "build-test-and-doctest",
     BuildOnly => "build-only",
     CheckOnly => "check-only",
     Clippy => "clippy",
     Rustdoc => "rustdoc",
     UnstableFeatures => "unstable-features",
+    Reproducibility => "reproducibility",
 });
 
 string_enum!(pub enum CapLints {
@@ -39,18 +48,208 @@ string_enum!(pub enum CapLints {
     Forbid => "forbid",
 });
 
+string_enum!(pub enum FollowUpKind {
+    RetryRegressed => "retry-regressed",
+});
+
+/// Experiment automatically created once an experiment completes, like `retry-regressed`. The
+/// settings differing from the completed experiment can be appended after a colon, like
+/// `retry-regressed:mode=check-only,cap-lints=warn,priority=10`.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FollowUp {
+    pub kind: FollowUpKind,
+    pub mode: Option<Mode>,
+    pub cap_lints: Option<CapLints>,
+    pub priority: Option<i32>,
+    pub sandbox_image: Option<String>,
+}
+
+from_into_string!(FollowUp);
+
+impl FollowUp {
+    pub fn new(kind: FollowUpKind) -> Self {
+        FollowUp {
+            kind,
+            mode: None,
+            cap_lints: None,
+            priority: None,
+            sandbox_image: None,
+        }
+    }
+}
+
+impl FromStr for FollowUp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let (kind, settings) = match s.split_once(':') {
+            Some((kind, settings)) => (kind, Some(settings)),
+            None => (s, None),
+        };
+        let mut follow_up = FollowUp::new(kind.parse()?);
+        for setting in settings.into_iter().flat_map(|s| s.split(',')) {
+            let (key, value) = setting.split_once('=').ok_or_else(|| {
+                anyhow!(
+                    "invalid follow-up setting (expected key=value): {}",
+                    setting
+                )
+            })?;
+            match key {
+                "mode" => follow_up.mode = Some(value.parse()?),
+                "cap-lints" => follow_up.cap_lints = Some(value.parse()?),
+                "priority" => follow_up.priority = Some(value.parse()?),
+                "sandbox-image" => follow_up.sandbox_image = Some(value.into()),
+                _ => bail!("unknown follow-up setting: {}", key),
+            }
+        }
+        Ok(follow_up)
+    }
+}
+
+impl fmt::Display for FollowUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(mode) = self.mode {
+            settings.push(format!("mode={mode}"));
+        }
+        if let Some(cap_lints) = self.cap_lints {
+            settings.push(format!("cap-lints={cap_lints}"));
+        }
+        if let Some(priority) = self.priority {
+            settings.push(format!("priority={priority}"));
+        }
+        if let Some(image) = &self.sandbox_image {
+            settings.push(format!("sandbox-image={image}"));
+        }
+
+        write!(f, "{}", self.kind)?;
+        if !settings.is_empty() {
+            write!(f, ":{}", settings.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// How the corner cases of the results are categorized when comparing the two toolchains.
+string_enum!(#[non_exhaustive] pub enum ComparisonPolicy {
+    Lenient => "lenient",
+    Strict => "strict",
+    LintExperiment => "lint-experiment",
+});
+
+impl Default for ComparisonPolicy {
+    fn default() -> Self {
+        ComparisonPolicy::Lenient
+    }
+}
+
+/// Options passed to the test harness of the crates, after the `--` of `cargo test`.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TestOptions {
+    /// Run the tests marked with `#[ignore]` too.
+    pub include_ignored: bool,
+    pub test_threads: Option<u32>,
+    /// Only run the tests whose name contains one of these filters.
+    pub filters: Vec<String>,
+}
+
+impl TestOptions {
+    pub fn is_default(&self) -> bool {
+        *self == TestOptions::default()
+    }
+
+    pub fn harness_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.include_ignored {
+            args.push("--include-ignored".into());
+        }
+        if let Some(threads) = self.test_threads {
+            args.push(format!("--test-threads={threads}"));
+        }
+        args.extend(self.filters.iter().cloned());
+        args
+    }
+}
+
+/// Maximum running time of an experiment, like `72h`. Once it's elapsed the crates not tested
+/// yet are skipped, and a partial report is generated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Deadline {
+    minutes: u32,
+}
+
+from_into_string!(Deadline);
+
+impl Deadline {
+    pub fn duration(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.minutes.into())
+    }
+}
+
+impl FromStr for Deadline {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let (number, multiplier) = if let Some(number) = s.strip_suffix('m') {
+            (number, 1)
+        } else if let Some(number) = s.strip_suffix('h') {
+            (number, 60)
+        } else if let Some(number) = s.strip_suffix('d') {
+            (number, 60 * 24)
+        } else {
+            bail!(
+                "invalid deadline (expected minutes, hours or days, like 72h): {}",
+                s
+            );
+        };
+        let minutes = number
+            .parse::<u32>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .filter(|minutes| *minutes > 0)
+            .ok_or_else(|| anyhow!("invalid deadline: {}", s))?;
+        Ok(Deadline { minutes })
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.minutes % (60 * 24) == 0 {
+            write!(f, "{}d", self.minutes / (60 * 24))
+        } else if self.minutes % 60 == 0 {
+            write!(f, "{}h", self.minutes / 60)
+        } else {
+            write!(f, "{}m", self.minutes)
+        }
+    }
+}
+
 const SMALL_RANDOM_COUNT: u32 = 20;
+const DEFAULT_REVERSE_DEPS_DEPTH: u32 = 1;
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[serde(try_from = "String", into = "String")]
 pub enum CrateSelect {
     Full,
+    // All the crates, except the registry crates below the activity thresholds of the
+    // `[crate-filters]` configuration.
+    FullActive,
     Demo,
     Top(u32),
     Local,
     Dummy,
     Random(u32),
+    StratifiedRandom(u32),
     List(HashSet<String>),
+    // Exact versions of registry crates, like the packages of a `Cargo.lock`.
+    Versions(BTreeSet<RegistryCrate>),
+    OwnedBy(String),
+    ReverseDeps { krate: String, depth: u32 },
+    // The crates tested by an existing experiment, like the one an experiment is cloned from.
+    SameAs(String),
 }
 
 from_into_string!(CrateSelect);
@@ -66,6 +265,10 @@ impl FromStr for CrateSelect {
             }
 
             "small-random" => CrateSelect::Random(SMALL_RANDOM_COUNT),
+            s if s.starts_with("random-") && s.ends_with(":stratified") => {
+                let n: u32 = s["random-".len()..s.len() - ":stratified".len()].parse()?;
+                CrateSelect::StratifiedRandom(n)
+            }
             s if s.starts_with("random-") => {
                 let n: u32 = s["random-".len()..].parse()?;
                 CrateSelect::Random(n)
@@ -74,13 +277,77 @@ impl FromStr for CrateSelect {
             s if s.starts_with("list:") => {
                 let list = s["list:".len()..]
                     .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
                     .map(|s| s.to_owned())
-                    .collect();
+                    .collect::<HashSet<_>>();
+                if list.is_empty() {
+                    bail!("empty list in CrateSelect: {}", s);
+                }
 
                 CrateSelect::List(list)
             }
 
+            s if s.starts_with("versions:") => {
+                let versions = s["versions:".len()..]
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| match s.split_once('@') {
+                        Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+                            Ok(RegistryCrate {
+                                name: name.to_string(),
+                                version: version.to_string(),
+                            })
+                        }
+                        _ => bail!("invalid crate version in CrateSelect: {}", s),
+                    })
+                    .collect::<Fallible<BTreeSet<_>>>()?;
+                if versions.is_empty() {
+                    bail!("empty list in CrateSelect: {}", s);
+                }
+
+                CrateSelect::Versions(versions)
+            }
+
+            s if s.starts_with("owned-by:") => {
+                let owner = &s["owned-by:".len()..];
+                if owner.is_empty() {
+                    bail!("missing owner in CrateSelect: {}", s);
+                }
+                CrateSelect::OwnedBy(owner.to_string())
+            }
+
+            s if s.starts_with("same-as:") => {
+                let experiment = &s["same-as:".len()..];
+                if experiment.is_empty() {
+                    bail!("missing experiment in CrateSelect: {}", s);
+                }
+                CrateSelect::SameAs(experiment.to_string())
+            }
+
+            s if s.starts_with("reverse-deps:") => {
+                let (krate, depth) = match s["reverse-deps:".len()..].split_once(':') {
+                    Some((krate, depth)) => (krate, depth.parse()?),
+                    None => (&s["reverse-deps:".len()..], DEFAULT_REVERSE_DEPS_DEPTH),
+                };
+                if krate.is_empty() {
+                    bail!("missing crate in CrateSelect: {}", s);
+                }
+                if depth == 0 {
+                    bail!(
+                        "the depth of the reverse dependencies must be at least 1: {}",
+                        s
+                    );
+                }
+                CrateSelect::ReverseDeps {
+                    krate: krate.to_string(),
+                    depth,
+                }
+            }
+
             "full" => CrateSelect::Full,
+            "full-active" => CrateSelect::FullActive,
             "demo" => CrateSelect::Demo,
             "local" => CrateSelect::Local,
             "dummy" => CrateSelect::Dummy,
@@ -95,11 +362,19 @@ impl fmt::Display for CrateSelect {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CrateSelect::Full => write!(f, "full"),
+            CrateSelect::FullActive => write!(f, "full-active"),
             CrateSelect::Demo => write!(f, "demo"),
             CrateSelect::Dummy => write!(f, "dummy"),
             CrateSelect::Top(n) => write!(f, "top-{n}"),
             CrateSelect::Local => write!(f, "local"),
             CrateSelect::Random(n) => write!(f, "random-{n}"),
+            CrateSelect::StratifiedRandom(n) => write!(f, "random-{n}:stratified"),
+            CrateSelect::OwnedBy(owner) => write!(f, "owned-by:{owner}"),
+            CrateSelect::SameAs(experiment) => write!(f, "same-as:{experiment}"),
+            CrateSelect::ReverseDeps { krate, depth } if *depth == DEFAULT_REVERSE_DEPS_DEPTH => {
+                write!(f, "reverse-deps:{krate}")
+            }
+            CrateSelect::ReverseDeps { krate, depth } => write!(f, "reverse-deps:{krate}:{depth}"),
             CrateSelect::List(list) => {
                 let mut first = true;
                 write!(f, "list:")?;
@@ -115,6 +390,16 @@ impl fmt::Display for CrateSelect {
 
                 Ok(())
             }
+            CrateSelect::Versions(versions) => {
+                write!(f, "versions:")?;
+                for (i, krate) in versions.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}@{}", krate.name, krate.version)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -130,11 +415,13 @@ impl CrateSelect {
     }
 }
 
-/// Either a `CrateSelect` or `Url` pointing to a list of crates.
+/// Either a `CrateSelect`, a `Url` pointing to a list of crates, or a `Url` pointing to a
+/// `Cargo.lock` (or an SBOM) listing the exact crate versions to test.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DeferredCrateSelect {
     Direct(CrateSelect),
     Indirect(Url),
+    Lockfile(Url),
 }
 
 impl From<CrateSelect> for DeferredCrateSelect {
@@ -145,13 +432,19 @@ impl From<CrateSelect> for DeferredCrateSelect {
 
 impl DeferredCrateSelect {
     pub fn resolve(self) -> Fallible<CrateSelect> {
-        let url = match self {
-            DeferredCrateSelect::Direct(v) => return Ok(v),
-            DeferredCrateSelect::Indirect(url) => url,
-        };
-
-        let body = utils::http::get_sync(url.as_str())?.text()?;
-        CrateSelect::from_newline_separated_list(&body)
+        match self {
+            DeferredCrateSelect::Direct(v) => Ok(v),
+            DeferredCrateSelect::Indirect(url) => {
+                let body = utils::http::get_sync(url.as_str())?.text()?;
+                CrateSelect::from_newline_separated_list(&body)
+            }
+            DeferredCrateSelect::Lockfile(url) => {
+                let body = utils::http::get_sync(url.as_str())?.text()?;
+                let versions = crate::crates::parse_lockfile(&body)
+                    .with_context(|| format!("failed to read the lockfile at {url}"))?;
+                Ok(CrateSelect::Versions(versions))
+            }
+        }
     }
 }
 
@@ -159,7 +452,9 @@ impl FromStr for DeferredCrateSelect {
     type Err = anyhow::Error;
 
     fn from_str(input: &str) -> Fallible<Self> {
-        if input.starts_with("https://") || input.starts_with("http://") {
+        if let Some(url) = input.strip_prefix("lockfile:") {
+            Ok(DeferredCrateSelect::Lockfile(url.parse()?))
+        } else if input.starts_with("https://") || input.starts_with("http://") {
             Ok(DeferredCrateSelect::Indirect(input.parse()?))
         } else {
             Ok(DeferredCrateSelect::Direct(input.parse()?))
@@ -258,6 +553,47 @@ pub struct Experiment {
     pub report_url: Option<String>,
     pub ignore_blacklist: bool,
     pub requirement: Option<String>,
+    pub follow_up: Option<FollowUp>,
+    pub parent: Option<String>,
+    pub sandbox_image: Option<String>,
+    pub zulip_stream: Option<String>,
+    pub repeat: Option<u32>,
+    #[serde(default)]
+    pub test_options: TestOptions,
+    #[serde(default)]
+    pub deadline: Option<Deadline>,
+    #[serde(default)]
+    pub comparison_policy: ComparisonPolicy,
+    /// Unreleased crate versions replacing the crates.io ones in the builds.
+    #[serde(default)]
+    pub registry_overlay: Vec<OverlayCrate>,
+    /// Crates replaced with a git branch or a local directory in the builds.
+    #[serde(default)]
+    pub dependency_patches: Vec<DependencyPatch>,
+    /// Patterns of the crates excluded from the experiment after they're selected.
+    #[serde(default)]
+    pub exclude: Vec<CratePattern>,
+    /// Free-form labels grouping related experiments, like `beta-1.79`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Exact versions of the two toolchains, resolved when the experiment starts running.
+    #[serde(default)]
+    pub rustc_versions: [Option<RustcVersion>; 2],
+    /// Whether the builds go through the sccache disk cache of the agents.
+    #[serde(default)]
+    pub build_cache: bool,
+    /// Whether the agents record the warnings of the successful builds, to report the lints
+    /// firing only with the second toolchain.
+    #[serde(default)]
+    pub track_warnings: bool,
+    /// Whether the report only includes the summary, with the pages of the categories rendered
+    /// by the browser from a JSON file of the results.
+    #[serde(default)]
+    pub client_side_report: bool,
+    /// Whether the crates were selected by their exact versions, which the follow-up
+    /// experiments keep testing.
+    #[serde(default)]
+    pub exact_versions: bool,
 }
 
 impl Experiment {
@@ -277,6 +613,23 @@ impl Experiment {
             .collect::<Fallible<_>>()
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// All the experiments, from the most recently created one.
+    pub fn all(db: &Database) -> Fallible<Vec<Experiment>> {
+        let records = db.query(
+            "SELECT * FROM experiments ORDER BY created_at DESC;",
+            [],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+        records
+            .into_iter()
+            .map(|record| record.into_experiment())
+            .collect::<Fallible<_>>()
+    }
+
     pub fn run_by(db: &Database, assignee: &Assignee) -> Fallible<Option<Experiment>> {
         let record = db.get_row(
             "select * from experiments where name = (
@@ -411,16 +764,11 @@ impl Experiment {
                         FROM   experiments ex
                         WHERE (ex.status = "queued" OR status = "running")
                                AND ( ex.assigned_to = ?1 )
-                               AND ( ex.requirement IS NULL
-                               OR ex.requirement IN (SELECT capability
-                                                     FROM   agent_capabilities
-                                                     WHERE  agent_name = ?2) )
                         ORDER  BY ex.priority DESC,
-                                  ex.created_at
-                        LIMIT  1;
+                                  ex.created_at;
                     "#;
 
-                    (AGENT_QUERY, vec![assignee.to_string(), agent_name])
+                    (AGENT_QUERY, vec![assignee.to_string()])
                 }
                 // FIXME: We don't respect experiment requirements when assigning experiments to the
                 // CLI. We need to decide what capabilities the CLI should have first.
@@ -448,22 +796,38 @@ impl Experiment {
                 FROM   experiments ex
                 WHERE  (ex.status = "queued" OR status = "running")
                         AND ( ex.assigned_to IS NULL )
-                        AND ( ex.requirement IS NULL
-                            OR ex.requirement IN (  SELECT capability
-                                                    FROM   agent_capabilities
-                                                    WHERE  agent_name = ?1) )
                 ORDER  BY ex.priority DESC,
-                          ex.created_at
-                LIMIT  1;
+                          ex.created_at;
             "#;
 
-            (AGENT_UNASSIGNED_QUERY, vec![agent_name])
+            (AGENT_UNASSIGNED_QUERY, vec![])
         };
 
-        if let Some(record) = db.get_row(query, rusqlite::params_from_iter(params.iter()), |r| {
+        // Requirements are expressions over the capabilities, which are evaluated here
+        let capabilities = Capabilities::for_agent(db, &agent_name)?;
+        let records = db.query(query, rusqlite::params_from_iter(params.iter()), |r| {
             ExperimentDBRecord::from_row(r)
-        })? {
-            let ex = record.into_experiment()?;
+        })?;
+        let mut candidates = records.into_iter().map(|r| r.into_experiment());
+        let next = candidates.find(|ex| match ex {
+            Ok(Experiment {
+                name,
+                requirement: Some(requirement),
+                ..
+            }) => match requirement.parse::<Requirement>() {
+                Ok(requirement) => requirement.matches(&capabilities),
+                Err(err) => {
+                    // Requirements are validated when the experiments are created or edited,
+                    // this only happens to the ones queued before they were
+                    warn!("invalid requirement `{requirement}` of experiment {name}: {err}");
+                    false
+                }
+            },
+            _ => true,
+        });
+
+        if let Some(ex) = next {
+            let ex = ex?;
             let (completed, all) = ex.raw_progress(db)?;
             // FIXME: in this case, ideally we'd start running the next
             // experiment. In practice, this only happens with artifically short
@@ -492,6 +856,20 @@ impl Experiment {
         }
     }
 
+    /// Returns the experiments automatically created as a follow-up of this one.
+    pub fn follow_ups(&self, db: &Database) -> Fallible<Vec<Experiment>> {
+        let records = db.query(
+            "SELECT * FROM experiments WHERE parent = ?1 ORDER BY created_at;",
+            [&self.name],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        records
+            .into_iter()
+            .map(|record| record.into_experiment())
+            .collect::<Fallible<_>>()
+    }
+
     pub fn set_status(&mut self, db: &Database, status: Status) -> Fallible<()> {
         db.execute(
             "UPDATE experiments SET status = ?1 WHERE name = ?2;",
@@ -509,6 +887,8 @@ impl Experiment {
                 )?;
                 self.started_at = Some(now);
             }
+            // Experiments stopped before running anything can be queued again once edited
+            (Status::Running, Status::ToolchainUnavailable) => (),
             // Check if the old status was "running" and there is no completed date
             (Status::Running, _) if self.completed_at.is_none() => {
                 db.execute(
@@ -537,6 +917,91 @@ impl Experiment {
         Ok(())
     }
 
+    /// Record which agent a crate was handed out to, so that it can be queued again if the agent
+    /// stops responding.
+    pub fn record_crate_assignment(
+        &self,
+        db: &Database,
+        krate: &Crate,
+        agent: &Assignee,
+    ) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiment_crates SET assigned_to = ?1 WHERE experiment = ?2 AND crate = ?3;",
+            &[&agent.to_string(), &self.name, &krate.id()],
+        )?;
+        Ok(())
+    }
+
+    /// Hand the work of an agent which stopped responding over to the other agents. The crates
+    /// it was running are queued again, and if the experiment was explicitly assigned to that
+    /// agent it becomes distributed.
+    pub fn handle_failure(&mut self, db: &Database, agent: &Assignee) -> Fallible<()> {
+        let assigned = self.assigned_to.as_ref().map(|a| a.to_string()) == Some(agent.to_string());
+
+        db.transaction(true, |t| {
+            if let Assignee::Agent(name) = agent {
+                t.execute(
+                    "UPDATE agents SET latest_work_for = NULL \
+                     WHERE name = ?1 AND latest_work_for = ?2;",
+                    &[name, &self.name],
+                )?;
+            }
+
+            if assigned {
+                t.execute(
+                    "UPDATE experiments SET assigned_to = ?1 WHERE name = ?2;",
+                    &[&Assignee::Distributed.to_string(), &self.name],
+                )?;
+                t.execute(
+                    "UPDATE experiment_crates SET started_at = NULL, assigned_to = NULL \
+                     WHERE experiment = ?1 AND status = 'queued' AND started_at IS NOT NULL;",
+                    &[&self.name],
+                )?;
+            } else {
+                // Other agents are working on the distributed experiments, only requeue the
+                // crates handed out to the failed one
+                t.execute(
+                    "UPDATE experiment_crates SET started_at = NULL, assigned_to = NULL \
+                     WHERE experiment = ?1 AND status = 'queued' AND assigned_to = ?2;",
+                    &[&self.name, &agent.to_string()],
+                )?;
+            }
+
+            Ok(())
+        })?;
+
+        if assigned {
+            self.assigned_to = Some(Assignee::Distributed);
+        }
+        Ok(())
+    }
+
+    pub fn set_priority(&mut self, db: &impl QueryUtils, priority: i32) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiments SET priority = ?1 WHERE name = ?2;",
+            &[&priority, &self.name.as_str()],
+        )?;
+        self.priority = priority;
+        Ok(())
+    }
+
+    /// Position of the experiment in the queue, starting from 1, or `None` if it's not queued.
+    /// Queued experiments are started by priority and then by age, like `Experiment::next` does.
+    pub fn queue_position(&self, db: &Database) -> Fallible<Option<u32>> {
+        if self.status != Status::Queued {
+            return Ok(None);
+        }
+        let ahead: u32 = db
+            .get_row(
+                "SELECT COUNT(*) FROM experiments WHERE status = ?1 \
+                 AND (priority > ?2 OR (priority = ?2 AND created_at < ?3));",
+                rusqlite::params![Status::Queued.to_str(), self.priority, self.created_at],
+                |row| row.get(0),
+            )?
+            .unwrap_or(0);
+        Ok(Some(ahead + 1))
+    }
+
     pub fn set_report_url(&mut self, db: &Database, url: &str) -> Fallible<()> {
         db.execute(
             "UPDATE experiments SET report_url = ?1 WHERE name = ?2;",
@@ -546,6 +1011,35 @@ impl Experiment {
         Ok(())
     }
 
+    /// Record the exact versions of the toolchains, unless an agent already did it.
+    pub fn set_rustc_versions(
+        &mut self,
+        db: &Database,
+        versions: &[RustcVersion; 2],
+    ) -> Fallible<()> {
+        let changes = db.execute(
+            "UPDATE experiments SET toolchain_start_version = ?1, toolchain_end_version = ?2 \
+             WHERE name = ?3 AND toolchain_start_version IS NULL;",
+            &[
+                &serde_json::to_string(&versions[0])?,
+                &serde_json::to_string(&versions[1])?,
+                &self.name.as_str(),
+            ],
+        )?;
+        if changes > 0 {
+            self.rustc_versions = [Some(versions[0].clone()), Some(versions[1].clone())];
+        }
+        Ok(())
+    }
+
+    /// Whether the experiment has been running for longer than its deadline.
+    pub fn deadline_passed(&self) -> bool {
+        match (self.deadline, self.started_at) {
+            (Some(deadline), Some(started_at)) => Utc::now() >= started_at + deadline.duration(),
+            _ => false,
+        }
+    }
+
     pub fn raw_progress(&self, db: &Database) -> Fallible<(u32, u32)> {
         let results_len: u32 = db
             .get_row(
@@ -602,33 +1096,94 @@ impl Experiment {
         .collect::<Fallible<Vec<Crate>>>()
     }
 
+    /// Get up to `limit` crates to run, marking them as started.
+    ///
+    /// Crates that were never handed out are returned first. Once none of them are left, crates
+    /// handed out more than `server.reassign-crates-after` minutes ago without their results being
+    /// reported are returned again, so that idle agents can take over crates stuck on a slow or
+    /// dead agent. Whichever agent reports the result last wins.
     pub fn get_uncompleted_crates(
         &self,
         db: &Database,
+        config: &Config,
         limit: Option<u32>,
+    ) -> Fallible<Vec<Crate>> {
+        self.take_uncompleted_crates(db, config, limit, None)
+    }
+
+    /// Get the next batch of crates to hand out to the agents, marking them as started.
+    ///
+    /// Batches contain about `server.batch-build-time` minutes of builds, estimated from the
+    /// previous builds of each crate across experiments. The slowest crates are handed out first,
+    /// so that they don't end up delaying the end of the experiment while most agents are idle.
+    pub fn next_batch(&self, db: &Database, config: &Config) -> Fallible<Vec<Crate>> {
+        let budget = f64::from(config.server.batch_build_time) * 60.0;
+        self.take_uncompleted_crates(db, config, Some(MAX_BATCH_SIZE), Some(budget))
+    }
+
+    fn take_uncompleted_crates(
+        &self,
+        db: &Database,
+        config: &Config,
+        limit: Option<u32>,
+        budget: Option<f64>,
     ) -> Fallible<Vec<Crate>> {
         let limit = limit.map(|l| l as i32).unwrap_or(-1);
-        #[cfg(not(test))]
-        const RUN_TIMEOUT: u32 = 20;
-        #[cfg(test)]
-        const RUN_TIMEOUT: u32 = 1;
+        let timeout = config.server.reassign_crates_after;
 
         db.transaction(true, |transaction| {
-            //get the first 'limit' queued crates from the experiment crates list
-            let mut params: Vec<&dyn rusqlite::types::ToSql> = Vec::new();
-            let crates = transaction
-                .query(
-                    &format!(
-                        "SELECT crate FROM experiment_crates WHERE experiment = ?1
-                            AND skipped = 0
-                            AND status = 'queued'
-                            AND (started_at is null or started_at <= datetime('now', '-{RUN_TIMEOUT} minutes'))
-                        LIMIT ?2;",
-                    ),
-                    rusqlite::params![self.name, limit],
-                    |r| r.get("crate"),
+            // Crates never built are assumed to be as slow as the average crate
+            let fallback = transaction
+                .get_row(
+                    "SELECT AVG(average_time) AS average FROM build_times;",
+                    [],
+                    |r| r.get::<_, Option<f64>>("average"),
                 )?
+                .flatten()
+                .unwrap_or(DEFAULT_BUILD_TIME);
+
+            //get the first 'limit' queued crates from the experiment crates list, slowest first
+            let mut params: Vec<&dyn rusqlite::types::ToSql> = Vec::new();
+            let crates = transaction.query(
+                &format!(
+                    "SELECT ec.crate, ec.started_at IS NOT NULL AS reassigned,
+                        COALESCE(bt.average_time, ?3) AS estimated_time
+                        FROM experiment_crates ec
+                        LEFT JOIN build_times bt ON bt.crate = unversioned_id(ec.crate)
+                        WHERE ec.experiment = ?1
+                        AND ec.skipped = 0
+                        AND ec.status = 'queued'
+                        AND (ec.started_at is null or ec.started_at <= datetime('now', '-{timeout} minutes'))
+                    ORDER BY ec.started_at IS NOT NULL, ec.started_at, estimated_time DESC
+                    LIMIT ?2;",
+                ),
+                rusqlite::params![self.name, limit, fallback],
+                |r| {
+                    Ok((
+                        r.get::<_, String>("crate")?,
+                        r.get::<_, bool>("reassigned")?,
+                        r.get::<_, f64>("estimated_time")?,
+                    ))
+                },
+            )?;
+            let crates = match budget {
+                Some(budget) => self.fit_in_budget(crates, budget),
+                None => crates
+                    .into_iter()
+                    .map(|(krate, reassigned, _)| (krate, reassigned))
+                    .collect(),
+            };
+
+            let reassigned = crates.iter().filter(|(_, reassigned)| *reassigned).count();
+            if reassigned > 0 {
+                info!(
+                    "handing out again {} crates of {} not reported within {} minutes",
+                    reassigned, self.name, timeout
+                );
+            }
+            let crates = crates
                 .into_iter()
+                .map(|(krate, _)| krate)
                 .collect::<Vec<String>>();
 
             crates.iter().for_each(|krate| params.push(krate));
@@ -657,6 +1212,23 @@ impl Experiment {
                 .collect::<Fallible<Vec<Crate>>>()
         })
     }
+
+    /// Pick the crates fitting in the time budget, in the order they were selected (from the
+    /// slowest to the fastest one). The first crate is always picked, even if it doesn't fit
+    /// alone.
+    fn fit_in_budget(&self, crates: Vec<(String, bool, f64)>, budget: f64) -> Vec<(String, bool)> {
+        let mut total = 0.0;
+        let mut batch = Vec::new();
+        for (krate, reassigned, time) in crates {
+            let time = time * self.toolchains.len() as f64;
+            if !batch.is_empty() && total + time > budget {
+                continue;
+            }
+            total += time;
+            batch.push((krate, reassigned));
+        }
+        batch
+    }
 }
 
 pub struct ExperimentDBRecord {
@@ -677,6 +1249,24 @@ pub struct ExperimentDBRecord {
     report_url: Option<String>,
     ignore_blacklist: bool,
     requirement: Option<String>,
+    follow_up: Option<String>,
+    parent: Option<String>,
+    sandbox_image: Option<String>,
+    zulip_stream: Option<String>,
+    repeat: Option<u32>,
+    test_options: Option<String>,
+    deadline: Option<String>,
+    comparison_policy: Option<String>,
+    registry_overlay: Option<String>,
+    dependency_patches: Option<String>,
+    exclude: Option<String>,
+    tags: Option<String>,
+    toolchain_start_version: Option<String>,
+    toolchain_end_version: Option<String>,
+    build_cache: bool,
+    track_warnings: bool,
+    client_side_report: bool,
+    exact_versions: bool,
 }
 
 impl ExperimentDBRecord {
@@ -699,6 +1289,24 @@ impl ExperimentDBRecord {
             report_url: row.get("report_url")?,
             ignore_blacklist: row.get("ignore_blacklist")?,
             requirement: row.get("requirement")?,
+            follow_up: row.get("follow_up")?,
+            parent: row.get("parent")?,
+            sandbox_image: row.get("sandbox_image")?,
+            zulip_stream: row.get("zulip_stream")?,
+            repeat: row.get("repeat")?,
+            test_options: row.get("test_options")?,
+            deadline: row.get("deadline")?,
+            comparison_policy: row.get("comparison_policy")?,
+            registry_overlay: row.get("registry_overlay")?,
+            dependency_patches: row.get("dependency_patches")?,
+            exclude: row.get("exclude")?,
+            tags: row.get("tags")?,
+            toolchain_start_version: row.get("toolchain_start_version")?,
+            toolchain_end_version: row.get("toolchain_end_version")?,
+            build_cache: row.get("build_cache")?,
+            track_warnings: row.get("track_warnings")?,
+            client_side_report: row.get("client_side_report")?,
+            exact_versions: row.get("exact_versions")?,
         })
     }
 
@@ -734,6 +1342,54 @@ impl ExperimentDBRecord {
             report_url: self.report_url,
             ignore_blacklist: self.ignore_blacklist,
             requirement: self.requirement,
+            follow_up: self.follow_up.map(|f| f.parse()).transpose()?,
+            parent: self.parent,
+            sandbox_image: self.sandbox_image,
+            zulip_stream: self.zulip_stream,
+            repeat: self.repeat,
+            test_options: self
+                .test_options
+                .map(|o| serde_json::from_str(&o))
+                .transpose()?
+                .unwrap_or_default(),
+            deadline: self.deadline.map(|d| d.parse()).transpose()?,
+            comparison_policy: self
+                .comparison_policy
+                .map(|p| p.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            registry_overlay: self
+                .registry_overlay
+                .map(|o| serde_json::from_str(&o))
+                .transpose()?
+                .unwrap_or_default(),
+            dependency_patches: self
+                .dependency_patches
+                .map(|p| serde_json::from_str(&p))
+                .transpose()?
+                .unwrap_or_default(),
+            exclude: self
+                .exclude
+                .map(|e| serde_json::from_str(&e))
+                .transpose()?
+                .unwrap_or_default(),
+            tags: self
+                .tags
+                .map(|t| serde_json::from_str(&t))
+                .transpose()?
+                .unwrap_or_default(),
+            rustc_versions: [
+                self.toolchain_start_version
+                    .map(|v| serde_json::from_str(&v))
+                    .transpose()?,
+                self.toolchain_end_version
+                    .map(|v| serde_json::from_str(&v))
+                    .transpose()?,
+            ],
+            build_cache: self.build_cache,
+            track_warnings: self.track_warnings,
+            client_side_report: self.client_side_report,
+            exact_versions: self.exact_versions,
         })
     }
 }
@@ -741,14 +1397,17 @@ impl ExperimentDBRecord {
 #[cfg(test)]
 mod tests {
     use super::{
-        Assignee, AssigneeParseError, CrateSelect, DeferredCrateSelect, Experiment, Status,
+        Assignee, AssigneeParseError, CrateSelect, Deadline, DeferredCrateSelect, Experiment,
+        Status,
     };
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::agent::Capabilities;
     use crate::config::Config;
-    use crate::db::Database;
+    use crate::crates::RegistryCrate;
+    use crate::db::{Database, QueryUtils};
     use crate::server::agents::Agents;
     use crate::server::tokens::Tokens;
+    use crate::toolchain::RustcVersion;
     use std::collections::HashSet;
     use std::str::FromStr;
 
@@ -761,23 +1420,72 @@ mod tests {
 
         let suite = vec![
             ("demo", CrateSelect::Demo),
+            ("full-active", CrateSelect::FullActive),
             ("top-25", CrateSelect::Top(25)),
             ("random-87", CrateSelect::Random(87)),
             ("small-random", CrateSelect::Random(20)),
+            ("random-50:stratified", CrateSelect::StratifiedRandom(50)),
+            ("owned-by:dtolnay", CrateSelect::OwnedBy("dtolnay".into())),
+            (
+                "owned-by:github:rust-lang:libs",
+                CrateSelect::OwnedBy("github:rust-lang:libs".into()),
+            ),
+            ("same-as:pr-1234", CrateSelect::SameAs("pr-1234".into())),
+            (
+                "reverse-deps:serde_derive",
+                CrateSelect::ReverseDeps {
+                    krate: "serde_derive".into(),
+                    depth: 1,
+                },
+            ),
+            (
+                "reverse-deps:serde_derive:3",
+                CrateSelect::ReverseDeps {
+                    krate: "serde_derive".into(),
+                    depth: 3,
+                },
+            ),
             (
                 "list:brson/hello-rs,lazy_static",
                 CrateSelect::List(demo_crates.clone()),
             ),
+            (
+                "versions:lazy_static@1.4.0,serde@1.0.197",
+                CrateSelect::Versions(
+                    [("lazy_static", "1.4.0"), ("serde", "1.0.197")]
+                        .into_iter()
+                        .map(|(name, version)| RegistryCrate {
+                            name: name.into(),
+                            version: version.into(),
+                        })
+                        .collect(),
+                ),
+            ),
         ];
 
         for (s, output) in suite.into_iter() {
             assert_eq!(CrateSelect::from_str(s).unwrap(), output);
+            if !s.starts_with("list:") && s != "small-random" {
+                assert_eq!(output.to_string(), s);
+            }
             assert_eq!(
                 DeferredCrateSelect::from_str(s).unwrap(),
                 DeferredCrateSelect::Direct(output),
             );
         }
 
+        assert_eq!(
+            CrateSelect::from_str("list: brson/hello-rs, lazy_static,").unwrap(),
+            CrateSelect::List(demo_crates.clone()),
+        );
+        assert!(CrateSelect::from_str("list:").is_err());
+        assert!(CrateSelect::from_str("versions:").is_err());
+        assert!(CrateSelect::from_str("versions:serde").is_err());
+        assert!(CrateSelect::from_str("versions:serde@").is_err());
+        assert!(CrateSelect::from_str("reverse-deps:").is_err());
+        assert!(CrateSelect::from_str("reverse-deps:serde_derive:0").is_err());
+        assert!(CrateSelect::from_str("same-as:").is_err());
+
         assert_eq!(
             DeferredCrateSelect::from_str("http://git.io/Jes7o").unwrap(),
             DeferredCrateSelect::Indirect("http://git.io/Jes7o".parse().unwrap()),
@@ -788,6 +1496,12 @@ mod tests {
             DeferredCrateSelect::Indirect("https://git.io/Jes7o".parse().unwrap()),
         );
 
+        assert_eq!(
+            DeferredCrateSelect::from_str("lockfile:https://example.com/Cargo.lock").unwrap(),
+            DeferredCrateSelect::Lockfile("https://example.com/Cargo.lock".parse().unwrap()),
+        );
+        assert!(DeferredCrateSelect::from_str("lockfile:Cargo.lock").is_err());
+
         let list = CrateSelect::from_newline_separated_list(
             r"
             brson/hello-rs
@@ -999,15 +1713,127 @@ mod tests {
         // Create a dummy experiment
         CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
         let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
-        let crates = ex.get_uncompleted_crates(&db, None).unwrap();
+        let crates = ex.get_uncompleted_crates(&db, &config, None).unwrap();
         // Assert the whole list is returned
         assert_eq!(crates.len(), ex.get_crates(&db).unwrap().len());
 
         // Test already completed crates does not show up again
-        let uncompleted_crates = ex.get_uncompleted_crates(&db, None).unwrap();
+        let uncompleted_crates = ex.get_uncompleted_crates(&db, &config, None).unwrap();
         assert_eq!(uncompleted_crates.len(), 0);
     }
 
+    #[test]
+    fn test_next_batch() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        config.server.batch_build_time = 1;
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let crates = ex.get_crates(&db).unwrap();
+        assert!(crates.len() > 3);
+
+        // The other crates are assumed to take the average time, 22.5 seconds per build
+        for (krate, time) in [(&crates[0], 40.0), (&crates[1], 5.0)] {
+            db.execute(
+                "INSERT INTO build_times (crate, builds, average_time) VALUES (?1, 1, ?2);",
+                &[&krate.unversioned_id(), &time],
+            )
+            .unwrap();
+        }
+
+        // The slowest crate is handed out alone, as it doesn't fit in the budget of one minute
+        assert_eq!(ex.next_batch(&db, &config).unwrap(), [crates[0].clone()]);
+        // The fastest crate fills the rest of the budget
+        let batch = ex.next_batch(&db, &config).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1], crates[1]);
+
+        let mut handed_out = 3;
+        loop {
+            let batch = ex.next_batch(&db, &config).unwrap();
+            if batch.is_empty() {
+                break;
+            }
+            assert_eq!(batch.len(), 1);
+            handed_out += 1;
+        }
+        assert_eq!(handed_out, crates.len());
+    }
+
+    #[test]
+    fn test_deadline_parsing() {
+        let deadline: Deadline = "72h".parse().unwrap();
+        assert_eq!(deadline.duration(), chrono::Duration::hours(72));
+        assert_eq!(deadline.to_string(), "3d");
+        assert_eq!("90m".parse::<Deadline>().unwrap().to_string(), "90m");
+        assert_eq!("2d".parse::<Deadline>().unwrap().to_string(), "2d");
+
+        for invalid in ["", "h", "0h", "72", "-1h", "1.5h", "1w"] {
+            assert!(invalid.parse::<Deadline>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_set_rustc_versions() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.rustc_versions, [None, None]);
+
+        let version = |version: &str| RustcVersion {
+            version: version.into(),
+            commit_hash: Some("0123456789abcdef".into()),
+        };
+        let first = [version("rustc 1.0.0"), version("rustc 1.1.0")];
+        ex.set_rustc_versions(&db, &first).unwrap();
+
+        // Only the versions recorded by the first agent are kept
+        ex.set_rustc_versions(&db, &[version("rustc 1.0.0"), version("rustc 1.2.0")])
+            .unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.rustc_versions, first.map(Some));
+    }
+
+    #[test]
+    fn test_queue_position() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        for name in ["first", "second", "third"] {
+            CreateExperiment::dummy(name).apply(&ctx).unwrap();
+        }
+        let position = |name: &str| {
+            Experiment::get(&db, name)
+                .unwrap()
+                .unwrap()
+                .queue_position(&db)
+                .unwrap()
+        };
+        assert_eq!(position("first"), Some(1));
+        assert_eq!(position("third"), Some(3));
+
+        // Bumping the priority moves the experiment to the front of the queue
+        let mut third = Experiment::get(&db, "third").unwrap().unwrap();
+        third.set_priority(&db, 10).unwrap();
+        assert_eq!(position("third"), Some(1));
+        assert_eq!(position("first"), Some(2));
+
+        // Only queued experiments have a position
+        third.set_status(&db, Status::Running).unwrap();
+        assert_eq!(position("third"), None);
+        assert_eq!(position("first"), Some(1));
+    }
+
     // A failure is handled by re-queueing any running crates for a given agent,
     // to be picked up by the next agent to ask for them.
     #[test]
@@ -1022,10 +1848,16 @@ mod tests {
         // Create a dummy experiment
         CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
         let ex = Experiment::next(&db, &agent1).unwrap().unwrap().1;
-        assert!(!ex.get_uncompleted_crates(&db, None).unwrap().is_empty());
+        assert!(!ex
+            .get_uncompleted_crates(&db, &config, None)
+            .unwrap()
+            .is_empty());
         assert!(Experiment::next(&db, &agent1).unwrap().is_some());
         std::thread::sleep(std::time::Duration::from_secs(80)); // need to wait for at least 60 seconds for timeout to fire
         assert_eq!(ex.status, Status::Running);
-        assert!(!ex.get_uncompleted_crates(&db, None).unwrap().is_empty());
+        assert!(!ex
+            .get_uncompleted_crates(&db, &config, None)
+            .unwrap()
+            .is_empty());
     }
 }
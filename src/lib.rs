@@ -14,6 +14,8 @@
 
 pub mod actions;
 pub mod agent;
+#[cfg(feature = "analysis-api")]
+pub mod analysis;
 mod assets;
 #[macro_use]
 pub mod utils;
@@ -1,7 +1,7 @@
 #![allow(clippy::redundant_closure)]
 #![allow(clippy::needless_question_mark)]
 
-use log::info;
+use tracing::info;
 mod cli;
 
 use clap::Parser;
@@ -19,16 +19,7 @@ fn main() {
     })
     .unwrap();
 
-    // Initialize env_logger
-    // This doesn't use from_default_env() because it doesn't allow to override filter_module()
-    // with the RUST_LOG environment variable
-    let mut env = env_logger::Builder::new();
-    env.filter_module("crater", log::LevelFilter::Info);
-    env.filter_module("rustwide", log::LevelFilter::Info);
-    if let Ok(content) = std::env::var("RUST_LOG") {
-        env.parse_filters(&content);
-    }
-    rustwide::logging::init_with(env.build());
+    utils::logging::init(&["crater"]);
 
     let success = match panic::catch_unwind(main_) {
         Ok(Ok(())) => true,
@@ -2,5 +2,5 @@ pub use anyhow::Result as Fallible;
 pub use anyhow::{anyhow, bail, Context, Result};
 
 pub use lazy_static::lazy_static;
-pub use log::{debug, error, info, trace, warn};
 pub use serde_derive::{Deserialize, Serialize};
+pub use tracing::{debug, error, info, info_span, trace, warn};
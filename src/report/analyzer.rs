@@ -17,8 +17,9 @@ pub enum ReportConfig {
     Complete(ToolchainSelect),
 }
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
-#[derive(Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ReportCrates {
     Plain(Vec<CrateResult>),
     Complete {
@@ -27,7 +28,8 @@ pub enum ReportCrates {
     },
 }
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
+#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug)]
 pub struct TestResults {
     pub categories: IndexMap<Comparison, ReportCrates>,
     pub info: IndexMap<Comparison, u32>,
@@ -54,20 +56,28 @@ fn analyze_detailed(toolchain: usize, crates: Vec<CrateResult>) -> ReportCrates
 
     for krate in root {
         // record results only for root crates
-        if let BuildFail(FailureReason::CompilerError(codes)) =
-            krate.runs[toolchain].clone().unwrap().res
-        {
-            for code in codes {
-                results
-                    .entry(BuildFail(FailureReason::CompilerError(btreeset![code])))
-                    .or_insert_with(Vec::new)
-                    .push(krate.clone())
+        match krate.runs[toolchain].clone().unwrap().res {
+            BuildFail(FailureReason::CompilerError(codes)) => {
+                for code in codes {
+                    results
+                        .entry(BuildFail(FailureReason::CompilerError(btreeset![code])))
+                        .or_insert_with(Vec::new)
+                        .push(krate.clone())
+                }
             }
-        } else {
-            results
+            // Group the crates by lint, like compiler errors are grouped by code
+            BuildFail(FailureReason::NewLint(lints)) => {
+                for lint in lints {
+                    results
+                        .entry(BuildFail(FailureReason::NewLint(btreeset![lint])))
+                        .or_insert_with(Vec::new)
+                        .push(krate.clone())
+                }
+            }
+            _ => results
                 .entry(krate.runs[toolchain].as_ref().unwrap().res.clone())
                 .or_insert_with(Vec::new)
-                .push(krate)
+                .push(krate),
         }
     }
 
@@ -108,7 +118,7 @@ mod tests {
     use crate::config::Config;
     use crate::crates::{Crate, RegistryCrate};
     use crate::experiments::{CapLints, Experiment, Mode, Status};
-    use crate::report::{generate_report, Comparison};
+    use crate::report::{generate_report, root_regressions, Comparison};
     use crate::results::{DummyDB, FailureReason::*};
     use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
     use anyhow::Result;
@@ -166,6 +176,23 @@ mod tests {
             report_url: None,
             ignore_blacklist: false,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: Default::default(),
+            deadline: None,
+            comparison_policy: Default::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: Vec::new(),
+            rustc_versions: [None, None],
+            build_cache: false,
+            track_warnings: false,
+            client_side_report: false,
+            exact_versions: false,
         };
 
         let crates = record_crates! {db, ex,
@@ -179,7 +206,7 @@ mod tests {
             "fix-2" => (TestResult::BuildFail(Unknown), TestResult::TestPass)
         };
 
-        let raw = generate_report(&db, &config, &ex, &crates)?;
+        let raw = generate_report(&db, &config, &ex, &crates, None)?;
         let mut crates = raw
             .crates
             .clone()
@@ -256,6 +283,14 @@ mod tests {
         let expected = TestResults { categories, info };
         assert_eq!(expected, analyzed);
 
+        let roots = root_regressions(&analyzed);
+        assert_eq!(
+            roots.iter().map(|root| &root.krate).collect::<Vec<_>>(),
+            vec![&reg!("ce-1"), &reg!("ce-2"), &reg!("unknown")]
+        );
+        assert_eq!(roots[0].dependents, vec![reg!("dep-1"), reg!("dep-2")]);
+        assert_eq!(roots[2].result, Some(TestResult::BuildFail(Unknown)));
+
         Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use std::fs::File;
+use std::io::Write;
 use std::num::NonZeroUsize;
 use std::ptr::NonNull;
 
@@ -6,9 +7,10 @@ use crate::config::Config;
 use crate::crates::Crate;
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::report::{compare, Comparison, ReportWriter};
+use crate::report::{crate_to_name, Comparison, ReportJob, ReportWriter};
 use crate::results::{EncodedLog, EncodingType, ReadResults};
 use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap};
 use tar::{Builder as TarBuilder, Header as TarHeader};
 use tempfile::tempfile;
 
@@ -57,15 +59,15 @@ impl Drop for TempfileBackedBuffer {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Archive {
     name: String,
     path: String,
 }
 
 struct LogEntry {
+    krate: String,
     path: String,
-    comparison: Comparison,
     log_bytes: Vec<u8>,
 }
 
@@ -79,19 +81,26 @@ impl LogEntry {
     }
 }
 
+/// Comparison of the crate in the results the report is generated from.
+fn comparison(comparisons: &HashMap<String, Comparison>, krate: &Crate) -> Fallible<Comparison> {
+    comparisons
+        .get(&krate.id())
+        .copied()
+        .ok_or_else(|| anyhow!("missing result of {krate}"))
+}
+
 fn iterate<'a, DB: ReadResults + 'a>(
     db: &'a DB,
     ex: &'a Experiment,
     crates: &'a [Crate],
+    comparisons: &'a HashMap<String, Comparison>,
     config: &'a Config,
 ) -> impl Iterator<Item = Fallible<LogEntry>> + 'a {
     let mut iter = crates
         .iter()
         .filter(move |krate| !config.should_skip(krate))
         .map(move |krate| -> Fallible<Vec<LogEntry>> {
-            let res1 = db.load_test_result(ex, &ex.toolchains[0], krate)?;
-            let res2 = db.load_test_result(ex, &ex.toolchains[1], krate)?;
-            let comparison = compare(config, krate, res1.as_ref(), res2.as_ref());
+            let comparison = comparison(comparisons, krate)?;
 
             ex.toolchains
                 .iter()
@@ -121,8 +130,8 @@ fn iterate<'a, DB: ReadResults + 'a>(
                         tc.to_path_component(),
                     );
                     Some(Ok(LogEntry {
+                        krate: krate.id(),
                         path,
-                        comparison,
                         log_bytes,
                     }))
                 })
@@ -141,45 +150,28 @@ fn iterate<'a, DB: ReadResults + 'a>(
     })
 }
 
+/// Upload the content of a temporary file to the report, retrying on failures.
 #[allow(unused_mut)]
-fn write_all_archive<DB: ReadResults, W: ReportWriter>(
-    db: &DB,
-    ex: &Experiment,
-    crates: &[Crate],
-    dest: &W,
-    config: &Config,
-) -> Fallible<Archive> {
-    for i in 1..=RETRIES {
-        // We write this large-ish tarball into a tempfile, which moves the I/O to disk operations
-        // rather than keeping it in memory. This avoids complicating the code by doing incremental
-        // writes to S3 (requiring buffer management etc) while avoiding keeping the blob entirely
-        // in memory.
-        let backing = tempfile()?;
-        let mut all = TarBuilder::new(zstd::stream::Encoder::new(backing, 0)?);
-        for entry in iterate(db, ex, crates, config) {
-            let entry = entry?;
-            let mut header = entry.header();
-            all.append_data(&mut header, &entry.path, &entry.log_bytes[..])?;
-        }
+fn upload<W: ReportWriter>(dest: &W, path: &str, mut data: File) -> Fallible<()> {
+    let mut buffer;
+    let view;
+    #[cfg(unix)]
+    {
+        buffer = TempfileBackedBuffer::new(data)?;
+        view = buffer.buffer();
+    }
+    #[cfg(not(unix))]
+    {
+        use std::io::{Read, Seek};
+        data.rewind()?;
+        buffer = Vec::new();
+        data.read_to_end(&mut buffer)?;
+        view = &buffer[..];
+    }
 
-        let mut data = all.into_inner()?.finish()?;
-        let mut buffer;
-        let view;
-        #[cfg(unix)]
-        {
-            buffer = TempfileBackedBuffer::new(data)?;
-            view = buffer.buffer();
-        }
-        #[cfg(not(unix))]
-        {
-            use std::io::{Read, Seek};
-            data.rewind()?;
-            buffer = Vec::new();
-            data.read_to_end(&mut buffer)?;
-            view = &buffer[..];
-        }
+    for i in 1..=RETRIES {
         match dest.write_bytes(
-            "logs-archives/all.tar.zst",
+            path,
             view,
             &"application/zstd".parse().unwrap(),
             EncodingType::Plain,
@@ -191,9 +183,10 @@ fn write_all_archive<DB: ReadResults, W: ReportWriter>(
                 } else {
                     std::thread::sleep(std::time::Duration::from_secs(2));
                     warn!(
-                        "retry ({}/{}) writing logs-archives/all.tar.zst ({} bytes) (error: {:?})",
+                        "retry ({}/{}) writing {} ({} bytes) (error: {:?})",
                         i,
                         RETRIES,
+                        path,
                         view.len(),
                         e,
                     );
@@ -203,50 +196,152 @@ fn write_all_archive<DB: ReadResults, W: ReportWriter>(
         }
     }
 
-    Ok(Archive {
-        name: "All the crates".to_string(),
-        path: "logs-archives/all.tar.zst".to_string(),
-    })
+    Ok(())
+}
+
+/// A `.tar.zst` archive being written to a temporary file. Archives are made of independently
+/// compressed zstd frames, one for each log, which decompress to the concatenation of their
+/// content: a log is thus compressed once and its frame copied to every archive containing it.
+///
+/// We write the archives into a tempfile, which moves the I/O to disk operations rather than
+/// keeping it in memory. This avoids complicating the code by doing incremental writes to S3
+/// (requiring buffer management etc) while avoiding keeping the blob entirely in memory.
+struct ArchiveWriter {
+    path: String,
+    file: File,
+}
+
+impl ArchiveWriter {
+    fn new(path: String) -> Fallible<Self> {
+        Ok(ArchiveWriter {
+            path,
+            file: tempfile()?,
+        })
+    }
+
+    fn append(&mut self, frame: &[u8]) -> Fallible<()> {
+        self.file.write_all(frame)?;
+        Ok(())
+    }
+
+    fn finish<W: ReportWriter>(mut self, dest: &W) -> Fallible<()> {
+        // Two empty blocks mark the end of a tar archive
+        self.append(&zstd::stream::encode_all(&[0; 1024][..], 0)?)?;
+        upload(dest, &self.path, self.file)
+    }
+}
+
+/// Compresses the tar entries of logs into zstd frames.
+struct FrameEncoder {
+    builder: TarBuilder<Vec<u8>>,
+}
+
+impl FrameEncoder {
+    fn new() -> Self {
+        FrameEncoder {
+            builder: TarBuilder::new(Vec::new()),
+        }
+    }
+
+    fn encode(&mut self, entry: &LogEntry) -> Fallible<Vec<u8>> {
+        self.builder
+            .append_data(&mut entry.header(), &entry.path, &entry.log_bytes[..])?;
+        // Take the entry out of the builder without finishing the archive
+        let raw = std::mem::take(self.builder.get_mut());
+        Ok(zstd::stream::encode_all(&raw[..], 0)?)
+    }
+}
+
+/// Name of the archive the logs of a crate are stored in, inside the archives of its category.
+fn shard(krate: &Crate) -> char {
+    match crate_to_name(krate).chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+        _ => '_',
+    }
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    archive: String,
+    path: String,
 }
 
 const RETRIES: usize = 4;
 
+/// Write the archives of the logs: one with all the logs, one for each result category, and one
+/// for each first letter of the crates of a category. An index of the smallest archive containing
+/// each log is written to `logs-archives/index.json`. The crates are grouped by the comparisons
+/// of the results of the report, which might be older than the results stored in `db`.
 pub fn write_logs_archives<DB: ReadResults, W: ReportWriter>(
     db: &DB,
     ex: &Experiment,
     crates: &[Crate],
+    comparisons: &HashMap<String, Comparison>,
     dest: &W,
     config: &Config,
+    job: Option<&ReportJob>,
 ) -> Fallible<Vec<Archive>> {
-    let mut archives = Vec::new();
-    let mut by_comparison = IndexMap::new();
-
-    archives.push(write_all_archive(db, ex, crates, dest, config)?);
-
-    for entry in iterate(db, ex, crates, config) {
-        let entry = entry?;
+    let mut archives = vec![Archive {
+        name: "All the crates".to_string(),
+        path: "logs-archives/all.tar.zst".to_string(),
+    }];
+    let mut index: BTreeMap<String, Vec<IndexEntry>> = BTreeMap::new();
 
+    // Group the crates beforehand, so that every archive is written in one go while the logs are
+    // only read and compressed once
+    let mut by_comparison: IndexMap<Comparison, BTreeMap<char, Vec<Crate>>> = IndexMap::new();
+    for krate in crates.iter().filter(|krate| !config.should_skip(krate)) {
         by_comparison
-            .entry(entry.comparison)
-            .or_insert_with(|| TarBuilder::new(zstd::stream::Encoder::new(Vec::new(), 3).unwrap()))
-            .append_data(&mut entry.header(), &entry.path, &entry.log_bytes[..])?;
+            .entry(comparison(comparisons, krate)?)
+            .or_default()
+            .entry(shard(krate))
+            .or_default()
+            .push(krate.clone());
     }
 
-    for (comparison, archive) in by_comparison.drain(..) {
-        let data = archive.into_inner()?.finish()?;
-        dest.write_bytes(
-            format!("logs-archives/{comparison}.tar.zst"),
-            &data,
-            &"application/zstd".parse().unwrap(),
-            EncodingType::Plain,
-        )?;
-
+    let total = by_comparison.len() + 1;
+    let mut encoder = FrameEncoder::new();
+    let mut all = ArchiveWriter::new("logs-archives/all.tar.zst".into())?;
+    for (i, (comparison, shards)) in by_comparison.into_iter().enumerate() {
+        let path = format!("logs-archives/{comparison}.tar.zst");
+        let mut category = ArchiveWriter::new(path.clone())?;
+        for (shard, shard_crates) in shards {
+            let archive = format!("logs-archives/{comparison}/{shard}.tar.zst");
+            let mut shard = ArchiveWriter::new(archive.clone())?;
+            for entry in iterate(db, ex, &shard_crates, comparisons, config) {
+                let entry = entry?;
+                let frame = encoder.encode(&entry)?;
+                all.append(&frame)?;
+                category.append(&frame)?;
+                shard.append(&frame)?;
+                index.entry(entry.krate).or_default().push(IndexEntry {
+                    archive: archive.clone(),
+                    path: entry.path,
+                });
+            }
+            shard.finish(dest)?;
+        }
+        category.finish(dest)?;
         archives.push(Archive {
             name: format!("{comparison} crates"),
-            path: format!("logs-archives/{comparison}.tar.zst"),
+            path,
         });
+
+        if let Some(job) = job {
+            job.set_progress(i + 1, total)?;
+        }
+    }
+    all.finish(dest)?;
+    if let Some(job) = job {
+        job.set_progress(total, total)?;
     }
 
+    dest.write_string(
+        "logs-archives/index.json",
+        serde_json::to_string(&index)?.into(),
+        &mime::APPLICATION_JSON,
+    )?;
+
     Ok(archives)
 }
 
@@ -258,7 +353,7 @@ mod tests {
     use crate::db::Database;
     use crate::experiments::Experiment;
     use crate::prelude::*;
-    use crate::report::DummyWriter;
+    use crate::report::{comparisons, generate_report, DummyWriter};
     use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult, WriteResults};
     use mime::Mime;
     use rustwide::logging::LogStorage;
@@ -339,12 +434,16 @@ mod tests {
             .unwrap();
 
         // Generate all the archives
+        let crates = ex.get_crates(&db).unwrap();
+        let raw = generate_report(&results, &config, &ex, &crates, None).unwrap();
         let archives = write_logs_archives(
             &results,
             &ex,
-            &ex.get_crates(&db).unwrap(),
+            &crates,
+            &comparisons(&raw),
             &writer,
             &config,
+            None,
         )
         .unwrap();
 
@@ -419,5 +518,29 @@ mod tests {
             format!("test-pass/{}/{}.txt", crate2.id(), ex.toolchains[0]) => "tc1 crate2",
             format!("test-pass/{}/{}.txt", crate2.id(), ex.toolchains[1]) => "tc2 crate2",
         });
+
+        // Check the index points to the per-letter archives
+        let index: serde_json::Value = serde_json::from_slice(
+            &writer.get("logs-archives/index.json", &mime::APPLICATION_JSON),
+        )
+        .unwrap();
+        let entry = &index[crate1.id()][0];
+        let shard_path = entry["archive"].as_str().unwrap();
+        assert_eq!(
+            shard_path,
+            format!("logs-archives/regressed/{}.tar.zst", super::shard(crate1))
+        );
+        assert_eq!(
+            entry["path"],
+            format!("regressed/{}/{}.txt", crate1.id(), ex.toolchains[0])
+        );
+        assert_eq!(index.as_object().unwrap().len(), 2);
+
+        let shard_content = writer.get(shard_path, &mime);
+        let mut shard = Archive::new(Decoder::new(shard_content.as_slice()).unwrap());
+        check_content!(shard: {
+            format!("regressed/{}/{}.txt", crate1.id(), ex.toolchains[0]) => "tc1 crate1",
+            format!("regressed/{}/{}.txt", crate1.id(), ex.toolchains[1]) => "tc2 crate1",
+        });
     }
 }
@@ -0,0 +1,326 @@
+//! Detection of the rust-lang/rust pull requests likely to have caused the regressions found by
+//! an experiment comparing a stable release with the beta, to help finding what to backport.
+//! Each group of regressions sharing the same failure is matched against the pull requests
+//! merged since the stable release branched off, looking for the diagnostic codes, lints and
+//! symbols of the failure in their titles and descriptions.
+
+use super::{analyzer::ReportCrates, Comparison, ResultName, TestResults};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::results::{FailureReason, ReadResults, TestResult};
+use crate::toolchain::RustcVersion;
+use indexmap::IndexMap;
+use std::collections::{BTreeSet, HashMap};
+
+const RUST_PR_URL: &str = "https://github.com/rust-lang/rust/pull";
+/// Number of logs of each group of regressions the symbols are extracted from.
+const MAX_LOGS_PER_CLUSTER: usize = 5;
+/// Number of pull requests listed for each group of regressions.
+const MAX_CANDIDATES: usize = 3;
+/// Symbols too common in pull requests to hint at the cause of a regression.
+const COMMON_SYMBOLS: &[&str] = &[
+    "alloc", "async", "await", "core", "crate", "const", "impl", "main", "self", "Self", "Some",
+    "None", "static", "std", "String", "super", "trait", "type", "where", "Option", "Result",
+    "usize", "isize",
+];
+/// Weight of a mention of the diagnostic code or lint of the regressions, compared to the
+/// mention of a symbol.
+const CODE_SCORE: usize = 3;
+/// Pull requests only mentioning symbols need more than one of them to be listed.
+const MIN_SCORE: usize = 2;
+
+/// A pull request merged into rust-lang/rust.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MergedPr {
+    pub number: u32,
+    pub title: String,
+    pub description: String,
+}
+
+impl MergedPr {
+    /// Parse the message of the merge commits created by bors, like `Auto merge of #123 -
+    /// user:branch, r=reviewer` or `Rollup merge of #123 - ...`, followed by the title and the
+    /// description of the pull request.
+    pub fn from_merge_commit(message: &str) -> Option<Self> {
+        let mut lines = message.lines();
+        let header = lines.next()?;
+        let number = header
+            .strip_prefix("Auto merge of #")
+            .or_else(|| header.strip_prefix("Rollup merge of #"))?;
+        let number = number
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+
+        let mut lines = lines.skip_while(|line| line.trim().is_empty());
+        let title = lines.next()?.trim().to_string();
+        // Rollups only list the pull requests they merge, which are matched on their own
+        if title.starts_with("Rollup of ") {
+            return None;
+        }
+        Some(MergedPr {
+            number,
+            title,
+            description: lines.collect::<Vec<_>>().join("\n"),
+        })
+    }
+}
+
+/// Pull request likely to have caused a group of regressions.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackportCandidate {
+    pub number: u32,
+    pub title: String,
+    pub url: String,
+    /// Codes, lints and symbols of the regressions mentioned by the pull request.
+    pub mentions: Vec<String>,
+}
+
+/// Commits of rust-lang/rust the toolchains of an experiment were built from, when it compares a
+/// stable release with a beta.
+pub fn beta_changeset(versions: &[Option<RustcVersion>; 2]) -> Option<(&str, &str)> {
+    let [Some(start), Some(end)] = versions else {
+        return None;
+    };
+    // The dates in the versions contain dashes too, only the release numbers tell the channels
+    // apart
+    let is_stable = start
+        .release()
+        .is_some_and(|release| !release.contains('-'));
+    let is_beta = end
+        .release()
+        .is_some_and(|release| release.contains("-beta"));
+    if !is_stable || !is_beta {
+        return None;
+    }
+    Some((start.commit_hash.as_deref()?, end.commit_hash.as_deref()?))
+}
+
+/// Find the candidates for each group of root regressions of the report, indexed by the name of
+/// the failure of the group.
+pub(super) fn find_candidates<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    res: &TestResults,
+    changeset: &[MergedPr],
+) -> Fallible<IndexMap<String, Vec<BackportCandidate>>> {
+    let Some(ReportCrates::Complete { results, .. }) = res.categories.get(&Comparison::Regressed)
+    else {
+        return Ok(IndexMap::new());
+    };
+
+    let mut candidates = IndexMap::new();
+    for (result, crates) in results {
+        let codes = failure_codes(result);
+        let mut logs = Vec::new();
+        for krate in crates.iter().take(MAX_LOGS_PER_CLUSTER) {
+            if let Some(log) = db.load_log(ex, &ex.toolchains[1], &krate.krate)? {
+                logs.push(String::from_utf8_lossy(&log.to_plain()?).into_owned());
+            }
+        }
+        let symbols = common_symbols(&logs);
+
+        let found = rank(changeset, &codes, &symbols);
+        if !found.is_empty() {
+            candidates.insert(result.long_name(), found);
+        }
+    }
+    Ok(candidates)
+}
+
+/// Diagnostic codes or lints identifying the failure.
+fn failure_codes(result: &TestResult) -> Vec<String> {
+    match result {
+        TestResult::BuildFail(FailureReason::CompilerError(codes))
+        | TestResult::BuildFail(FailureReason::NewLint(codes)) => {
+            codes.iter().map(|code| code.to_string()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Symbols quoted in the errors of the log, like the `Foo` and `bar` of ``cannot find method
+/// `bar` for `Foo` ``.
+fn error_symbols(log: &str) -> BTreeSet<String> {
+    let mut symbols = BTreeSet::new();
+    for line in log
+        .lines()
+        .filter(|line| line.contains("error[") || line.contains("error:"))
+    {
+        for quoted in line.split('`').skip(1).step_by(2) {
+            let identifiers = quoted.split(|c: char| !(c.is_alphanumeric() || c == '_'));
+            for ident in identifiers {
+                if ident.len() >= 4
+                    && !ident.starts_with(|c: char| c.is_ascii_digit())
+                    && !COMMON_SYMBOLS.contains(&ident)
+                {
+                    symbols.insert(ident.to_string());
+                }
+            }
+        }
+    }
+    symbols
+}
+
+/// Symbols appearing in the errors of at least half of the logs, to ignore the ones specific to
+/// a single crate.
+fn common_symbols(logs: &[String]) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for log in logs {
+        for symbol in error_symbols(log) {
+            *counts.entry(symbol).or_default() += 1;
+        }
+    }
+    let mut symbols = counts
+        .into_iter()
+        .filter(|(_, count)| count * 2 >= logs.len())
+        .map(|(symbol, _)| symbol)
+        .collect::<Vec<_>>();
+    symbols.sort();
+    symbols
+}
+
+/// Whether the text contains the word, not as part of a longer identifier.
+fn mentions(text: &str, word: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).any(|(idx, _)| {
+        let before = text[..idx].chars().next_back();
+        let after = text[idx + word.len()..].chars().next();
+        !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+    })
+}
+
+fn rank(changeset: &[MergedPr], codes: &[String], symbols: &[String]) -> Vec<BackportCandidate> {
+    let mut scored = changeset
+        .iter()
+        .filter_map(|pr| {
+            let text = format!("{}\n{}", pr.title, pr.description);
+            let codes = codes.iter().filter(|code| mentions(&text, code));
+            let symbols = symbols.iter().filter(|symbol| mentions(&text, symbol));
+            let score = codes.clone().count() * CODE_SCORE + symbols.clone().count();
+            (score >= MIN_SCORE).then(|| {
+                let candidate = BackportCandidate {
+                    number: pr.number,
+                    title: pr.title.clone(),
+                    url: format!("{RUST_PR_URL}/{}", pr.number),
+                    mentions: codes.chain(symbols).cloned().collect(),
+                };
+                (score, candidate)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // The most recent pull requests come last in the changeset
+    scored.reverse();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{beta_changeset, common_symbols, error_symbols, mentions, rank, MergedPr};
+    use crate::toolchain::RustcVersion;
+
+    #[test]
+    fn test_from_merge_commit() {
+        let pr = MergedPr::from_merge_commit(
+            "Auto merge of #12345 - user:branch, r=reviewer\n\nStabilize `foo`\n\nThis \
+             stabilizes the feature.\nFixes E0599.",
+        )
+        .unwrap();
+        assert_eq!(pr.number, 12345);
+        assert_eq!(pr.title, "Stabilize `foo`");
+        assert_eq!(pr.description, "This stabilizes the feature.\nFixes E0599.");
+
+        let pr = MergedPr::from_merge_commit(
+            "Rollup merge of #678 - user:fix, r=reviewer\n\nFix the `bar` lint",
+        )
+        .unwrap();
+        assert_eq!(pr.number, 678);
+        assert_eq!(pr.description, "");
+
+        assert!(MergedPr::from_merge_commit(
+            "Auto merge of #9 - bors:rollup-abc, r=x\n\nRollup of 3 pull requests"
+        )
+        .is_none());
+        assert!(MergedPr::from_merge_commit("Update the changelog").is_none());
+    }
+
+    #[test]
+    fn test_beta_changeset() {
+        let version = |version: &str, hash: &str| {
+            Some(RustcVersion {
+                version: version.into(),
+                commit_hash: Some(hash.into()),
+            })
+        };
+        assert_eq!(beta_changeset(&[None, None]), None);
+
+        let mut versions = [
+            version("rustc 1.75.0 (82e1608df 2023-12-21)", "aaa"),
+            version("rustc 1.76.0-beta.1 (0e09125c6 2023-12-21)", "bbb"),
+        ];
+        assert_eq!(beta_changeset(&versions), Some(("aaa", "bbb")));
+
+        versions[1] = version("rustc 1.77.0-nightly (0e09125c6 2024-01-02)", "ccc");
+        assert_eq!(beta_changeset(&versions), None);
+
+        // Comparing two betas isn't a backport
+        versions[0] = version("rustc 1.76.0-beta.1 (0e09125c6 2023-12-21)", "ddd");
+        versions[1] = version("rustc 1.76.0-beta.2 (1e09125c6 2023-12-28)", "eee");
+        assert_eq!(beta_changeset(&versions), None);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let log = "[INFO] [stderr] error[E0599]: no method named `frobnicate` found for struct \
+                   `Widget` in the current scope\n\
+                   [INFO] [stderr] note: `Widget` is defined here\n\
+                   [INFO] [stderr] error: cannot find `Self` in `std::io`";
+        assert_eq!(
+            error_symbols(log).into_iter().collect::<Vec<_>>(),
+            ["Widget", "frobnicate"]
+        );
+
+        let logs = [
+            log.to_string(),
+            "error: `frobnicate` is ambiguous".to_string(),
+            "error: `Gadget` is private".to_string(),
+        ];
+        assert_eq!(common_symbols(&logs), ["frobnicate"]);
+    }
+
+    #[test]
+    fn test_rank() {
+        assert!(mentions("Fix E0599 when", "E0599"));
+        assert!(mentions("`Widget::frobnicate`", "frobnicate"));
+        assert!(!mentions("frobnicate_all", "frobnicate"));
+
+        let pr = |number: u32, title: &str, description: &str| MergedPr {
+            number,
+            title: title.into(),
+            description: description.into(),
+        };
+        let changeset = [
+            pr(1, "Improve the diagnostics of E0599", ""),
+            pr(2, "Refactor `frobnicate`", "Unrelated"),
+            pr(3, "Remove `Widget::frobnicate`", ""),
+            pr(4, "Unrelated change", "Touches Widget"),
+            pr(5, "Tweak E0599 for `frobnicate`", ""),
+        ];
+        let codes = ["E0599".to_string()];
+        let symbols = ["Widget".to_string(), "frobnicate".to_string()];
+        let found = rank(&changeset, &codes, &symbols);
+        assert_eq!(
+            found.iter().map(|c| c.number).collect::<Vec<_>>(),
+            [5, 1, 3]
+        );
+        assert_eq!(found[0].mentions, ["E0599", "frobnicate"]);
+        assert_eq!(found[0].url, "https://github.com/rust-lang/rust/pull/5");
+    }
+}
@@ -0,0 +1,109 @@
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::results::ReadResults;
+
+/// Use of the build cache by the builds of a toolchain. A toolchain hitting the cache much less
+/// than the other one usually means its compilations can't be shared between crates.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BuildCacheSummary {
+    toolchain: String,
+    /// Number of builds whose use of the cache was reported.
+    builds: u32,
+    hits: u64,
+    misses: u64,
+    /// Percentage of the compilations served from the cache.
+    hit_rate: f64,
+}
+
+/// Aggregate the statistics of the build cache of each toolchain of the experiment.
+pub(super) fn summarize_build_cache<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<BuildCacheSummary>> {
+    let mut summaries = Vec::new();
+    for tc in &ex.toolchains {
+        let mut summary = BuildCacheSummary {
+            toolchain: tc.to_string(),
+            builds: 0,
+            hits: 0,
+            misses: 0,
+            hit_rate: 0.0,
+        };
+        for krate in crates {
+            if let Some(stats) = db.load_build_cache_stats(ex, tc, krate)? {
+                summary.builds += 1;
+                summary.hits += u64::from(stats.hits);
+                summary.misses += u64::from(stats.misses);
+            }
+        }
+        let total = summary.hits + summary.misses;
+        if total > 0 {
+            summary.hit_rate = summary.hits as f64 * 100.0 / total as f64;
+        }
+        summaries.push(summary);
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{summarize_build_cache, BuildCacheSummary};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::results::{BuildCacheStats, BuildReport, DummyDB};
+
+    #[test]
+    fn test_summarize_build_cache() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let report = |hits: u32, misses: u32| BuildReport {
+            cache_stats: Some(BuildCacheStats { hits, misses }),
+            ..BuildReport::default()
+        };
+
+        let mut results = DummyDB::default();
+        let (tc1, tc2) = (ex.toolchains[0].clone(), ex.toolchains[1].clone());
+        results.add_dummy_report(&ex, krate("a"), tc1.clone(), report(30, 10));
+        results.add_dummy_report(&ex, krate("b"), tc1.clone(), report(0, 0));
+        results.add_dummy_report(&ex, krate("a"), tc2, BuildReport::default());
+
+        let crates = ["a", "b"].map(krate);
+        let summary = summarize_build_cache(&results, &ex, &crates).unwrap();
+        assert_eq!(
+            summary,
+            [
+                BuildCacheSummary {
+                    toolchain: tc1.to_string(),
+                    builds: 2,
+                    hits: 30,
+                    misses: 10,
+                    hit_rate: 75.0,
+                },
+                BuildCacheSummary {
+                    toolchain: ex.toolchains[1].to_string(),
+                    builds: 0,
+                    hits: 0,
+                    misses: 0,
+                    hit_rate: 0.0,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::db::{Database, QueryUtils};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::archives::Archive;
+use crate::report::versions::IndexVersions;
+use chrono::Utc;
+
+string_enum!(pub enum ReportPhase {
+    Archives => "archives",
+    Html => "html",
+    Markdown => "markdown",
+    Logs => "logs",
+});
+
+impl ReportPhase {
+    fn order(self) -> u8 {
+        match self {
+            ReportPhase::Archives => 0,
+            ReportPhase::Html => 1,
+            ReportPhase::Markdown => 2,
+            ReportPhase::Logs => 3,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ReportPhase::Archives => "building the logs archives",
+            ReportPhase::Html => "writing the HTML report",
+            ReportPhase::Markdown => "writing the markdown report",
+            ReportPhase::Logs => "uploading the logs",
+        }
+    }
+}
+
+/// The phase a report being generated is in, and how much of that phase is done when it's known.
+pub struct ReportProgress {
+    pub phase: ReportPhase,
+    pub percentage: Option<u8>,
+}
+
+impl ReportProgress {
+    pub fn get(db: &Database, ex: &Experiment) -> Fallible<Option<Self>> {
+        db.get_row(
+            "SELECT phase, progress FROM report_jobs WHERE experiment = ?1;",
+            [&ex.name],
+            |row| {
+                Ok((
+                    row.get::<_, String>("phase")?,
+                    row.get::<_, Option<u8>>("progress")?,
+                ))
+            },
+        )?
+        .map(|(phase, percentage)| {
+            Ok(ReportProgress {
+                phase: phase.parse()?,
+                percentage,
+            })
+        })
+        .transpose()
+    }
+}
+
+impl std::fmt::Display for ReportProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (step {} of 4",
+            self.phase.description(),
+            self.phase.order() + 1
+        )?;
+        if let Some(percentage) = self.percentage {
+            write!(f, ", {percentage}% done")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Progress of the generation of a report, persisted in the database so that the generation can
+/// resume from the last checkpoint after a restart of the server.
+pub struct ReportJob<'a> {
+    db: &'a Database,
+    experiment: String,
+    phase: Option<ReportPhase>,
+    last_crate: Option<String>,
+    archives: Option<Vec<Archive>>,
+}
+
+impl<'a> ReportJob<'a> {
+    /// Start generating the report from scratch, discarding any previous progress.
+    pub fn start(db: &'a Database, ex: &Experiment) -> Fallible<Self> {
+        db.execute(
+            "DELETE FROM report_jobs WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        Ok(ReportJob {
+            db,
+            experiment: ex.name.clone(),
+            phase: None,
+            last_crate: None,
+            archives: None,
+        })
+    }
+
+    /// Resume the generation of the report from the last checkpoint, if there is one.
+    pub fn resume(db: &'a Database, ex: &Experiment) -> Fallible<Self> {
+        let row = db.get_row(
+            "SELECT phase, last_crate, archives FROM report_jobs WHERE experiment = ?1;",
+            [&ex.name],
+            |row| {
+                Ok((
+                    row.get::<_, String>("phase")?,
+                    row.get::<_, Option<String>>("last_crate")?,
+                    row.get::<_, Option<String>>("archives")?,
+                ))
+            },
+        )?;
+
+        let mut job = ReportJob {
+            db,
+            experiment: ex.name.clone(),
+            phase: None,
+            last_crate: None,
+            archives: None,
+        };
+        if let Some((phase, last_crate, archives)) = row {
+            job.phase = Some(phase.parse()?);
+            job.last_crate = last_crate;
+            job.archives = archives.map(|a| serde_json::from_str(&a)).transpose()?;
+            info!(
+                "resuming the report of {} from the {} phase",
+                ex.name, phase
+            );
+        }
+        Ok(job)
+    }
+
+    /// Whether the phase was fully completed before the generation was interrupted.
+    pub(super) fn is_done(&self, phase: ReportPhase) -> bool {
+        self.phase
+            .is_some_and(|current| current.order() > phase.order())
+    }
+
+    /// The archives written before the generation was interrupted.
+    pub(super) fn archives(&self) -> Option<&[Archive]> {
+        self.archives.as_deref()
+    }
+
+    /// The last crate whose logs were written, if the generation was interrupted while writing
+    /// the logs.
+    pub(super) fn last_crate(&self) -> Option<&str> {
+        if self.phase == Some(ReportPhase::Logs) {
+            self.last_crate.as_deref()
+        } else {
+            None
+        }
+    }
+
+    /// Record that the generation reached `phase`, after writing the logs of `last_crate`.
+    pub(super) fn checkpoint(&self, phase: ReportPhase, last_crate: Option<&str>) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO report_jobs (experiment, phase, last_crate, updated_at) \
+             VALUES (?1, ?2, ?3, ?4) \
+             ON CONFLICT (experiment) DO UPDATE \
+             SET phase = excluded.phase, last_crate = excluded.last_crate, progress = NULL, \
+             updated_at = excluded.updated_at;",
+            &[&self.experiment, &phase.to_str(), &last_crate, &Utc::now()],
+        )?;
+        Ok(())
+    }
+
+    /// Record that `done` out of `total` items of the current phase were written, to show the
+    /// progress of the generation.
+    pub(super) fn set_progress(&self, done: usize, total: usize) -> Fallible<()> {
+        let percentage = (done * 100 / total.max(1)).min(100) as u8;
+        self.db.execute(
+            "UPDATE report_jobs SET progress = ?1, updated_at = ?2 WHERE experiment = ?3;",
+            &[&percentage, &Utc::now(), &self.experiment],
+        )?;
+        Ok(())
+    }
+
+    /// Record the archives written during the archives phase.
+    pub(super) fn store_archives(&self, archives: &[Archive]) -> Fallible<()> {
+        self.db.execute(
+            "UPDATE report_jobs SET archives = ?1 WHERE experiment = ?2;",
+            &[&serde_json::to_string(archives)?, &self.experiment],
+        )?;
+        Ok(())
+    }
+
+    /// The versions of the crates looked up in the index by a previous attempt at generating the
+    /// report. They're kept along with the experiment, to avoid reading the whole index again when
+    /// the report is retried or generated again.
+    pub(super) fn index_versions(&self) -> Fallible<Option<IndexVersions>> {
+        self.db
+            .get_row(
+                "SELECT versions FROM report_index_versions WHERE experiment = ?1;",
+                [&self.experiment],
+                |row| row.get::<_, String>("versions"),
+            )?
+            .map(|versions| Ok(serde_json::from_str(&versions)?))
+            .transpose()
+    }
+
+    pub(super) fn store_index_versions(&self, versions: &IndexVersions) -> Fallible<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO report_index_versions (experiment, versions, created_at) \
+             VALUES (?1, ?2, ?3);",
+            &[
+                &self.experiment,
+                &serde_json::to_string(versions)?,
+                &Utc::now(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Discard the progress of a report which was fully generated.
+    pub fn finish(self) -> Fallible<()> {
+        self.db.execute(
+            "DELETE FROM report_jobs WHERE experiment = ?1;",
+            &[&self.experiment],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReportJob, ReportPhase, ReportProgress};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, DeleteExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::report::versions::IndexVersions;
+
+    #[test]
+    fn test_resume() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        // Nothing to resume
+        let job = ReportJob::resume(&db, &ex).unwrap();
+        assert!(!job.is_done(ReportPhase::Archives));
+        assert!(job.last_crate().is_none());
+
+        job.checkpoint(ReportPhase::Html, None).unwrap();
+        job.store_archives(&[]).unwrap();
+        job.checkpoint(ReportPhase::Logs, Some("local/foo"))
+            .unwrap();
+
+        let progress = ReportProgress::get(&db, &ex).unwrap().unwrap();
+        assert_eq!(progress.phase, ReportPhase::Logs);
+        assert_eq!(progress.percentage, None);
+        job.set_progress(21, 50).unwrap();
+        assert_eq!(
+            ReportProgress::get(&db, &ex).unwrap().unwrap().to_string(),
+            "uploading the logs (step 4 of 4, 42% done)"
+        );
+
+        let job = ReportJob::resume(&db, &ex).unwrap();
+        assert!(job.is_done(ReportPhase::Archives));
+        assert!(job.is_done(ReportPhase::Markdown));
+        assert!(!job.is_done(ReportPhase::Logs));
+        assert_eq!(job.last_crate(), Some("local/foo"));
+        assert_eq!(job.archives().map(|a| a.len()), Some(0));
+
+        job.finish().unwrap();
+        assert!(ReportProgress::get(&db, &ex).unwrap().is_none());
+        let job = ReportJob::resume(&db, &ex).unwrap();
+        assert!(!job.is_done(ReportPhase::Archives));
+
+        // Starting again discards the progress
+        job.checkpoint(ReportPhase::Logs, None).unwrap();
+        let job = ReportJob::start(&db, &ex).unwrap();
+        assert!(!job.is_done(ReportPhase::Archives));
+        assert!(!ReportJob::resume(&db, &ex)
+            .unwrap()
+            .is_done(ReportPhase::Archives));
+    }
+
+    #[test]
+    fn test_index_versions() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let job = ReportJob::start(&db, &ex).unwrap();
+        assert!(job.index_versions().unwrap().is_none());
+        job.store_index_versions(&IndexVersions::default()).unwrap();
+
+        // The versions are kept when the report is retried from scratch
+        let job = ReportJob::start(&db, &ex).unwrap();
+        assert!(job.index_versions().unwrap().is_some());
+
+        // They're also kept once the report is generated
+        job.finish().unwrap();
+        let job = ReportJob::start(&db, &ex).unwrap();
+        assert!(job.index_versions().unwrap().is_some());
+
+        // and removed with the experiment
+        DeleteExperiment {
+            name: "dummy".into(),
+        }
+        .apply(&ctx)
+        .unwrap();
+        assert!(job.index_versions().unwrap().is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::config::Config;
+use crate::crates::Crate;
+use crate::db::Database;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{compare, Comparison};
+use crate::results::DatabaseDB;
+use std::collections::BTreeMap;
+
+/// A crate whose outcome differs between two experiments. Crates are matched across experiments
+/// by their unversioned id (the name of registry crates and the slug of repositories), as the two
+/// experiments might have tested different versions of them.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CrateDiff {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub before: Comparison,
+    pub after: Comparison,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ReportDiff {
+    pub changed: Vec<CrateDiff>,
+    /// Crates tested in both experiments with the same outcome.
+    pub unchanged: usize,
+    /// Crates tested in only one of the two experiments.
+    pub not_shared: usize,
+}
+
+/// The results of a crate tested by an experiment, and how they compare.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CrateOutcome {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub comparison: Comparison,
+}
+
+/// Returns the outcome of every crate tested in the experiment, sorted by crate ID.
+pub fn crate_outcomes(
+    db: &Database,
+    config: &Config,
+    ex: &Experiment,
+) -> Fallible<Vec<CrateOutcome>> {
+    let mut outcomes = tested_crates(db, config, ex)?
+        .into_iter()
+        .map(|(_, outcome)| outcome)
+        .collect::<Vec<_>>();
+    outcomes.sort_by(|a, b| a.krate.cmp(&b.krate));
+    Ok(outcomes)
+}
+
+/// Returns the crates tested in the experiment along with their outcome.
+fn tested_crates(
+    db: &Database,
+    config: &Config,
+    ex: &Experiment,
+) -> Fallible<Vec<(Crate, CrateOutcome)>> {
+    let results_db = DatabaseDB::new(db);
+    let all_results = results_db.load_all_results(ex)?;
+    let overridden = results_db.load_overridden_crates(ex)?;
+    let mut outcomes = Vec::new();
+    for krate in ex.get_crates(db)? {
+        let id = krate.id();
+        let results = ex
+            .toolchains
+            .iter()
+            .map(|tc| all_results.get(&(id.clone(), tc.to_string())))
+            .collect::<Vec<_>>();
+        if results.iter().all(|r| r.is_none()) {
+            continue;
+        }
+
+        let outcome = CrateOutcome {
+            comparison: compare(
+                config,
+                ex.comparison_policy,
+                &krate,
+                results[0],
+                results[1],
+                overridden.contains(&id),
+            ),
+            krate: id,
+            start: results[0].map(|r| r.to_string()),
+            end: results[1].map(|r| r.to_string()),
+        };
+        outcomes.push((krate, outcome));
+    }
+    Ok(outcomes)
+}
+
+/// Returns the outcome of every crate tested in the experiment, keyed by unversioned crate ID.
+/// When several versions of a crate were tested, the outcome of the highest ID is kept.
+fn outcomes(
+    db: &Database,
+    config: &Config,
+    ex: &Experiment,
+) -> Fallible<BTreeMap<String, Comparison>> {
+    let mut tested = tested_crates(db, config, ex)?;
+    tested.sort_by(|(_, a), (_, b)| a.krate.cmp(&b.krate));
+    Ok(tested
+        .into_iter()
+        .map(|(krate, outcome)| (krate.unversioned_id(), outcome.comparison))
+        .collect())
+}
+
+/// Compares the outcomes of the crates tested by both experiments, for example to check whether
+/// the regressions found by an older run are fixed in a newer one.
+pub fn diff_experiments(
+    db: &Database,
+    config: &Config,
+    before: &Experiment,
+    after: &Experiment,
+) -> Fallible<ReportDiff> {
+    let before = outcomes(db, config, before)?;
+    let mut after = outcomes(db, config, after)?;
+
+    let mut diff = ReportDiff {
+        changed: Vec::new(),
+        unchanged: 0,
+        not_shared: 0,
+    };
+    for (krate, before) in before {
+        match after.remove(&krate) {
+            Some(after) if after == before => diff.unchanged += 1,
+            Some(after) => diff.changed.push(CrateDiff {
+                krate,
+                before,
+                after,
+            }),
+            None => diff.not_shared += 1,
+        }
+    }
+    diff.not_shared += after.len();
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crate_outcomes, diff_experiments, CrateDiff, CrateOutcome};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::Experiment;
+    use crate::report::Comparison;
+
+    #[test]
+    fn test_diff_experiments() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("before").apply(&ctx).unwrap();
+        CreateExperiment::dummy("after").apply(&ctx).unwrap();
+        let before = Experiment::get(&db, "before").unwrap().unwrap();
+        let after = Experiment::get(&db, "after").unwrap().unwrap();
+
+        let mut crates = before.get_crates(&db).unwrap();
+        crates.sort_by_key(|c| c.id());
+        let insert = |ex: &Experiment, krate: &str, results: [&str; 2]| {
+            for (tc, result) in ex.toolchains.iter().zip(results) {
+                db.execute(
+                    "INSERT INTO results (experiment, crate, toolchain, result, log, encoding) \
+                     VALUES (?1, ?2, ?3, ?4, 'log', 'plain');",
+                    &[&ex.name, &krate, &tc.to_string(), &result],
+                )
+                .unwrap();
+            }
+        };
+
+        let fixed = crates[0].id();
+        let unchanged = crates[1].id();
+        let only_before = crates[2].id();
+        insert(&before, &fixed, ["test-pass", "build-fail:unknown"]);
+        insert(&after, &fixed, ["test-pass", "test-pass"]);
+        insert(&before, &unchanged, ["test-pass", "test-pass"]);
+        insert(&after, &unchanged, ["test-pass", "test-pass"]);
+        insert(&before, &only_before, ["test-pass", "test-pass"]);
+
+        let diff = diff_experiments(&db, &config, &before, &after).unwrap();
+        assert_eq!(
+            diff.changed,
+            vec![CrateDiff {
+                krate: fixed,
+                before: Comparison::Regressed,
+                after: Comparison::SameTestPass,
+            }]
+        );
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.not_shared, 1);
+
+        // Different versions of the same crate are compared with each other
+        for (ex, krate) in [(&before, "reg/foo/1.0.0"), (&after, "reg/foo/1.1.0")] {
+            db.execute(
+                "INSERT INTO experiment_crates (experiment, crate, skipped, status) \
+                 VALUES (?1, ?2, 0, 'queued');",
+                &[&ex.name, &krate],
+            )
+            .unwrap();
+        }
+        insert(&before, "reg/foo/1.0.0", ["test-pass", "test-fail:unknown"]);
+        insert(&after, "reg/foo/1.1.0", ["test-pass", "test-pass"]);
+        let diff = diff_experiments(&db, &config, &before, &after).unwrap();
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(
+            diff.changed[1],
+            CrateDiff {
+                krate: "reg/foo".into(),
+                before: Comparison::Regressed,
+                after: Comparison::SameTestPass,
+            }
+        );
+        assert_eq!(diff.not_shared, 1);
+
+        let outcomes = crate_outcomes(&db, &config, &before).unwrap();
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(
+            outcomes[0],
+            CrateOutcome {
+                krate: fixed,
+                start: Some("test-pass".into()),
+                end: Some("build-fail:unknown".into()),
+                comparison: Comparison::Regressed,
+            }
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{crate_to_name, crate_to_url};
+use crate::results::{BuildDiskUsage, ReadResults};
+
+/// Number of crates listed as the heaviest ones in the report.
+const HEAVIEST_CRATES: usize = 50;
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HeavyCrate {
+    name: String,
+    url: String,
+    toolchain: String,
+    usage: BuildDiskUsage,
+}
+
+/// Disk space used by the builds of an experiment.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct DiskUsageSummary {
+    /// Number of builds whose disk usage was reported.
+    builds: u32,
+    total_source_dir: u64,
+    total_target_dir: u64,
+    /// Largest disk usage of a single build, in bytes.
+    peak: u64,
+    /// Builds using the most disk space, sorted by decreasing usage.
+    heaviest: Vec<HeavyCrate>,
+}
+
+/// Aggregate the disk usage of all the builds of the experiment, listing the heaviest crates.
+pub(super) fn summarize_disk_usage<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<DiskUsageSummary> {
+    let mut summary = DiskUsageSummary::default();
+    for krate in crates {
+        for tc in &ex.toolchains {
+            let Some(usage) = db.load_disk_usage(ex, tc, krate)? else {
+                continue;
+            };
+            summary.builds += 1;
+            summary.total_source_dir += usage.source_dir;
+            summary.total_target_dir += usage.target_dir;
+            summary.peak = summary.peak.max(usage.total());
+            summary.heaviest.push(HeavyCrate {
+                name: crate_to_name(krate),
+                url: crate_to_url(krate),
+                toolchain: tc.to_string(),
+                usage,
+            });
+        }
+    }
+
+    summary
+        .heaviest
+        .sort_by_key(|heavy| std::cmp::Reverse(heavy.usage.total()));
+    summary.heaviest.truncate(HEAVIEST_CRATES);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::summarize_disk_usage;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::results::{BuildDiskUsage, BuildReport, DummyDB};
+
+    #[test]
+    fn test_summarize_disk_usage() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let report = |source: u64, target: u64| BuildReport {
+            disk_usage: Some(BuildDiskUsage {
+                source_dir: source,
+                target_dir: target,
+            }),
+            ..BuildReport::default()
+        };
+
+        let mut results = DummyDB::default();
+        let (tc1, tc2) = (ex.toolchains[0].clone(), ex.toolchains[1].clone());
+        results.add_dummy_report(&ex, krate("small"), tc1.clone(), report(10, 100));
+        results.add_dummy_report(&ex, krate("small"), tc2.clone(), report(10, 200));
+        results.add_dummy_report(&ex, krate("big"), tc1, report(1000, 5000));
+        results.add_dummy_report(&ex, krate("missing"), tc2, BuildReport::default());
+
+        let crates = ["small", "big", "missing"].map(krate);
+        let summary = summarize_disk_usage(&results, &ex, &crates).unwrap();
+        assert_eq!(summary.builds, 3);
+        assert_eq!(summary.total_source_dir, 1020);
+        assert_eq!(summary.total_target_dir, 5300);
+        assert_eq!(summary.peak, 6000);
+        let heaviest = summary
+            .heaviest
+            .iter()
+            .map(|heavy| (heavy.name.as_str(), heavy.usage.total()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            heaviest,
+            [
+                ("big-1.0.0", 6000),
+                ("small-1.0.0", 210),
+                ("small-1.0.0", 110)
+            ]
+        );
+    }
+}
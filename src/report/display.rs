@@ -14,25 +14,36 @@ impl ResultName for FailureReason {
             FailureReason::Timeout => "timed out".into(),
             FailureReason::NetworkAccess => "network access".into(),
             FailureReason::Docker => "failed (docker error)".into(),
+            FailureReason::SystemDependency => "missing system dependency".into(),
+            FailureReason::RegistryFailure => "registry failure".into(),
             FailureReason::OOM => "OOM".into(),
             FailureReason::ICE => "ICE".into(),
             FailureReason::NoSpace => "no space left on device".into(),
             FailureReason::CompilerError(_) => "compiler error".into(),
+            FailureReason::NewLint(_) => "new lint".into(),
             FailureReason::DependsOn(_) => "faulty deps".into(),
             FailureReason::CompilerDiagnosticChange => "compiler diagnostic changed".into(),
+            FailureReason::UnsupportedTarget => "unsupported target".into(),
+            FailureReason::NonReproducible => "not reproducible".into(),
         }
     }
 
     fn long_name(&self) -> String {
         match self {
-            FailureReason::CompilerError(_) | FailureReason::DependsOn(_) => self.to_string(),
+            FailureReason::CompilerError(_)
+            | FailureReason::NewLint(_)
+            | FailureReason::DependsOn(_) => self.to_string(),
             FailureReason::Unknown
             | FailureReason::NetworkAccess
             | FailureReason::Docker
+            | FailureReason::SystemDependency
+            | FailureReason::RegistryFailure
             | FailureReason::Timeout
             | FailureReason::OOM
             | FailureReason::NoSpace
             | FailureReason::CompilerDiagnosticChange
+            | FailureReason::UnsupportedTarget
+            | FailureReason::NonReproducible
             | FailureReason::ICE => self.short_name(),
         }
     }
@@ -46,6 +57,8 @@ impl ResultName for BrokenReason {
             BrokenReason::Yanked => "deps yanked".into(),
             BrokenReason::MissingGitRepository => "missing repo".into(),
             BrokenReason::MissingDependencies => "missing deps".into(),
+            BrokenReason::ChecksumMismatch => "checksum mismatch".into(),
+            BrokenReason::Quarantined => "quarantined".into(),
         }
     }
 
@@ -60,10 +73,17 @@ impl ResultName for TestResult {
             TestResult::BrokenCrate(reason) => reason.short_name(),
             TestResult::BuildFail(reason) => format!("build {}", reason.short_name()),
             TestResult::TestFail(reason) => format!("test {}", reason.short_name()),
+            TestResult::DoctestFail(reason) => format!("doctest {}", reason.short_name()),
+            TestResult::TestAndDoctestFail(reasons) => format!(
+                "test {} and doctest {}",
+                reasons.tests.short_name(),
+                reasons.doctests.short_name()
+            ),
             TestResult::TestSkipped => "test skipped".into(),
             TestResult::TestPass => "test passed".into(),
             TestResult::Error => "error".into(),
             TestResult::Skipped => "skipped".into(),
+            TestResult::SkippedDeadline => "skipped past the deadline".into(),
         }
     }
 
@@ -71,11 +91,18 @@ impl ResultName for TestResult {
         match self {
             TestResult::BuildFail(reason) => format!("build {}", reason.long_name()),
             TestResult::TestFail(reason) => format!("test {}", reason.long_name()),
+            TestResult::DoctestFail(reason) => format!("doctest {}", reason.long_name()),
+            TestResult::TestAndDoctestFail(reasons) => format!(
+                "test {} and doctest {}",
+                reasons.tests.long_name(),
+                reasons.doctests.long_name()
+            ),
             TestResult::BrokenCrate(reason) => reason.long_name(),
             TestResult::TestSkipped
             | TestResult::TestPass
             | TestResult::Error
-            | TestResult::Skipped => self.short_name(),
+            | TestResult::Skipped
+            | TestResult::SkippedDeadline => self.short_name(),
         }
     }
 }
@@ -99,10 +126,13 @@ impl ResultColor for Comparison {
             Comparison::Unknown => Color::Single("#494b4a"),
             Comparison::SameBuildFail => Color::Single("#65461e"),
             Comparison::SameTestFail => Color::Single("#788843"),
+            Comparison::SameDoctestFail => Color::Single("#8a9a4f"),
             Comparison::SameTestSkipped => Color::Striped("#72a156", "#80b65f"),
+            Comparison::TestSkipChanged => Color::Striped("#72a156", "#494b4a"),
             Comparison::SameTestPass => Color::Single("#72a156"),
             Comparison::Error => Color::Single("#d77026"),
             Comparison::Broken => Color::Single("#44176e"),
+            Comparison::Overridden => Color::Striped("#44176e", "#494b4a"),
             Comparison::SpuriousRegressed => Color::Striped("#db3026", "#d5433b"),
             Comparison::SpuriousFixed => Color::Striped("#5630db", "#5d3dcf"),
         }
@@ -114,10 +144,11 @@ impl ResultColor for TestResult {
         match self {
             TestResult::BrokenCrate(_) => Color::Single("#44176e"),
             TestResult::BuildFail(_) => Color::Single("#db3026"),
-            TestResult::TestFail(_) => Color::Single("#65461e"),
+            TestResult::TestFail(_) | TestResult::TestAndDoctestFail(_) => Color::Single("#65461e"),
+            TestResult::DoctestFail(_) => Color::Single("#788843"),
             TestResult::TestSkipped | TestResult::TestPass => Color::Single("#62a156"),
             TestResult::Error => Color::Single("#d77026"),
-            TestResult::Skipped => Color::Single("#494b4a"),
+            TestResult::Skipped | TestResult::SkippedDeadline => Color::Single("#494b4a"),
         }
     }
 }
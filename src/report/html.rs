@@ -1,17 +1,27 @@
-use std::collections::HashMap;
-
 use crate::assets;
-use crate::experiments::Experiment;
+use crate::experiments::{Experiment, Mode};
 use crate::prelude::*;
 use crate::report::{
-    analyzer::ReportCrates, archives::Archive, Color, Comparison, CrateResult, ReportWriter,
-    ResultColor, ResultName, TestResults,
+    analyzer::ReportCrates, archives::Archive, BackportCandidate, BuildCacheSummary, Color,
+    Comparison, CrateResult, DiskUsageSummary, ExcludedCrateResult, NewWarnings,
+    NonReproducibleCrate, ReportWriter, ResultColor, ResultName, SizeRegression, TestResults,
 };
-use crate::results::EncodingType;
+use crate::results::{EncodingType, TriageAnnotation};
 use indexmap::{IndexMap, IndexSet};
 
 use super::CrateVersionStatus;
 
+/// Page rendering the results in the browser when the report is generated client-side, and the
+/// file it loads them from.
+const CLIENT_PAGE: &str = "results.html";
+const CLIENT_DATA: &str = "results-data.json";
+
+/// Page redirecting the links to the single page of the older reports to the landing page.
+const FULL_REPORT_REDIRECT: &str = "<!DOCTYPE html><html lang=\"en\"><head>\
+    <meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"0; url=index.html\">\
+    <title>Crater report</title></head><body>\
+    <p>The results are now listed in the <a href=\"index.html\">summary</a>.</p></body></html>";
+
 #[derive(Serialize)]
 struct NavbarItem {
     label: &'static str,
@@ -22,7 +32,9 @@ struct NavbarItem {
 #[derive(PartialEq, Eq)]
 enum CurrentPage {
     Summary,
-    Full,
+    ArtifactSizes,
+    NewWarnings,
+    NonReproducible,
     Downloads,
 }
 
@@ -40,27 +52,65 @@ enum ReportCratesHTML<'a> {
 }
 
 impl CurrentPage {
-    fn navbar(&self) -> Vec<NavbarItem> {
-        vec![
+    fn navbar(&self, ex: &Experiment) -> Vec<NavbarItem> {
+        let mut items = vec![
             NavbarItem {
                 label: "Summary",
                 url: "index.html",
                 active: *self == CurrentPage::Summary,
             },
             NavbarItem {
-                label: "Full report",
-                url: "full.html",
-                active: *self == CurrentPage::Full,
+                label: "Artifact sizes",
+                url: "artifact-sizes.html",
+                active: *self == CurrentPage::ArtifactSizes,
             },
-            NavbarItem {
-                label: "Downloads",
-                url: "downloads.html",
-                active: *self == CurrentPage::Downloads,
-            },
-        ]
+        ];
+        if ex.track_warnings {
+            items.push(NavbarItem {
+                label: "New warnings",
+                url: "new-warnings.html",
+                active: *self == CurrentPage::NewWarnings,
+            });
+        }
+        if ex.mode == Mode::Reproducibility {
+            items.push(NavbarItem {
+                label: "Non-reproducible",
+                url: "non-reproducible.html",
+                active: *self == CurrentPage::NonReproducible,
+            });
+        }
+        items.push(NavbarItem {
+            label: "Downloads",
+            url: "downloads.html",
+            active: *self == CurrentPage::Downloads,
+        });
+        items
     }
 }
 
+/// Link from the landing page to the page of a category.
+#[derive(Serialize)]
+struct CategoryLink {
+    name: Comparison,
+    count: u32,
+    color_idx: usize,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct IndexContext<'a> {
+    ex: &'a Experiment,
+    nav: Vec<NavbarItem>,
+    crates_count: usize,
+    colors: IndexSet<Color>,
+    /// Categories worth looking at when triaging the experiment.
+    summary: Vec<CategoryLink>,
+    others: Vec<CategoryLink>,
+    excluded: usize,
+    disk_usage: &'a DiskUsageSummary,
+    build_cache: &'a [BuildCacheSummary],
+}
+
 #[derive(Serialize)]
 struct ResultsContext<'a> {
     ex: &'a Experiment,
@@ -68,10 +118,34 @@ struct ResultsContext<'a> {
     // (comparison, category color, ...)
     categories: Vec<(Comparison, usize, ReportCratesHTML<'a>)>,
     info: IndexMap<Comparison, u32>,
-    full: bool,
     crates_count: usize,
     colors: IndexSet<Color>,
     result_names: IndexSet<String>,
+    excluded: &'a [ExcludedCrateResult],
+    /// Pull requests likely to have caused each group of root regressions.
+    backports: &'a IndexMap<String, Vec<BackportCandidate>>,
+    /// Page of the log directories the results link to.
+    log_page: &'static str,
+}
+
+#[derive(Serialize)]
+struct ClientContext<'a> {
+    ex: &'a Experiment,
+    nav: Vec<NavbarItem>,
+    crates_count: usize,
+    data_url: &'static str,
+}
+
+/// Everything the client-side page needs to render the categories, in a single file.
+#[derive(Serialize)]
+struct ClientData<'a> {
+    colors: IndexSet<Color>,
+    result_names: IndexSet<String>,
+    // (comparison, category color, ...)
+    categories: Vec<(Comparison, usize, ReportCratesHTML<'a>)>,
+    info: &'a IndexMap<Comparison, u32>,
+    backports: &'a IndexMap<String, Vec<BackportCandidate>>,
+    log_page: &'static str,
 }
 
 #[derive(Serialize)]
@@ -83,6 +157,33 @@ struct DownloadsContext<'a> {
     available_archives: Vec<Archive>,
 }
 
+#[derive(Serialize)]
+struct ArtifactSizesContext<'a> {
+    ex: &'a Experiment,
+    nav: Vec<NavbarItem>,
+    crates_count: usize,
+
+    regressions: &'a [SizeRegression],
+}
+
+#[derive(Serialize)]
+struct NewWarningsContext<'a> {
+    ex: &'a Experiment,
+    nav: Vec<NavbarItem>,
+    crates_count: usize,
+
+    new_warnings: &'a [NewWarnings],
+}
+
+#[derive(Serialize)]
+struct NonReproducibleContext<'a> {
+    ex: &'a Experiment,
+    nav: Vec<NavbarItem>,
+    crates_count: usize,
+
+    non_reproducible: &'a [NonReproducibleCrate],
+}
+
 #[derive(Serialize)]
 struct CrateResultHTML<'a> {
     name: &'a str,
@@ -92,6 +193,8 @@ struct CrateResultHTML<'a> {
     status: Option<CrateVersionStatus>,
     color_idx: usize,
     runs: [Option<BuildTestResultHTML<'a>>; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    triage: Option<&'a TriageAnnotation>,
 }
 
 // Map TestResult to usize to avoid the presence of special characters in html
@@ -129,130 +232,165 @@ fn to_html_crate_result<'a>(
         res: result.res,
         color_idx: category_color,
         runs,
+        triage: result.triage.as_ref(),
     }
 }
 
-fn write_report<W: ReportWriter>(
+/// Page of the log directories the results link to, the HTML version of the log only being
+/// uploaded when enabled.
+fn log_page(html_logs: bool) -> &'static str {
+    if html_logs {
+        "log.html#first-error"
+    } else {
+        "log.txt"
+    }
+}
+
+/// Page of the report listing the crates of a category.
+fn category_url(category: Comparison, client_side: bool) -> String {
+    if client_side {
+        format!("{CLIENT_PAGE}#{}", category.to_str())
+    } else {
+        format!("{}.html", category.to_str())
+    }
+}
+
+fn write_index<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
     res: &TestResults,
-    full: bool,
-    to: &str,
+    excluded: usize,
+    disk_usage: &DiskUsageSummary,
+    build_cache: &[BuildCacheSummary],
     dest: &W,
     output_templates: bool,
 ) -> Fallible<()> {
     let mut colors = IndexSet::new();
-    let mut result_names = IndexSet::new();
+    let (summary, others): (Vec<_>, Vec<_>) = res
+        .info
+        .iter()
+        .map(|(&category, &count)| CategoryLink {
+            name: category,
+            count,
+            color_idx: colors.insert_full(category.color()).0,
+            url: category_url(category, ex.client_side_report),
+        })
+        .partition(|link| link.name.show_in_summary());
 
-    let color_for_category = res
-        .categories
-        .keys()
-        .map(|category| (category.color(), colors.insert_full(category.color()).0))
-        .collect::<HashMap<_, _>>();
+    let context = IndexContext {
+        ex,
+        nav: CurrentPage::Summary.navbar(ex),
+        crates_count,
+        colors,
+        summary,
+        others,
+        excluded,
+        disk_usage,
+        build_cache,
+    };
 
-    let categories = res
-        .categories
-        .iter()
-        .filter(|(category, _)| full || category.show_in_summary())
-        .map(|(&category, crates)| (category, crates))
-        .flat_map(|(category, crates)| {
-            let category_color_idx = *color_for_category.get(&category.color()).unwrap();
-            match crates {
-                ReportCrates::Plain(crates) => vec![(
+    info!("generating index.html");
+    let html = minifier::html::minify(&assets::render_template("report/index.html", &context)?);
+    dest.write_string("index.html", html.into(), &mime::TEXT_HTML)?;
+
+    if output_templates {
+        dest.write_string(
+            "index.html.context.json",
+            serde_json::to_string(&context)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn category_to_html<'a>(
+    colors: &mut IndexSet<Color>,
+    result_names: &mut IndexSet<String>,
+    category: Comparison,
+    crates: &'a ReportCrates,
+) -> Vec<(Comparison, usize, ReportCratesHTML<'a>)> {
+    let category_color_idx = colors.insert_full(category.color()).0;
+    let mut to_html = |crates: &'a [CrateResult]| {
+        crates
+            .iter()
+            .map(|result| to_html_crate_result(colors, result_names, category_color_idx, result))
+            .collect::<Vec<_>>()
+    };
+
+    match crates {
+        ReportCrates::Plain(crates) => vec![(
+            category,
+            category_color_idx,
+            ReportCratesHTML::Plain(to_html(crates)),
+        )],
+        ReportCrates::Complete { tree, results } => {
+            let tree = tree
+                .iter()
+                .map(|(root, deps)| (root.to_string(), to_html(deps)))
+                .collect::<IndexMap<_, _>>();
+            let results = results
+                .iter()
+                .map(|(res, krates)| (res.long_name(), to_html(krates)))
+                .collect::<IndexMap<_, _>>();
+
+            vec![
+                (
                     category,
                     category_color_idx,
-                    ReportCratesHTML::Plain(
-                        crates
-                            .iter()
-                            .map(|result| {
-                                to_html_crate_result(
-                                    &mut colors,
-                                    &mut result_names,
-                                    category_color_idx,
-                                    result,
-                                )
-                            })
-                            .collect::<Vec<_>>(),
-                    ),
-                )]
-                .into_iter(),
-                ReportCrates::Complete { tree, results } => {
-                    let tree = tree
-                        .into_iter()
-                        .map(|(root, deps)| {
-                            (
-                                root.to_string(),
-                                deps.iter()
-                                    .map(|result| {
-                                        to_html_crate_result(
-                                            &mut colors,
-                                            &mut result_names,
-                                            category_color_idx,
-                                            result,
-                                        )
-                                    })
-                                    .collect::<Vec<_>>(),
-                            )
-                        })
-                        .collect::<IndexMap<_, _>>();
-                    let results = results
-                        .into_iter()
-                        .map(|(res, krates)| {
-                            (
-                                res.long_name(),
-                                krates
-                                    .iter()
-                                    .map(|result| {
-                                        to_html_crate_result(
-                                            &mut colors,
-                                            &mut result_names,
-                                            category_color_idx,
-                                            result,
-                                        )
-                                    })
-                                    .collect::<Vec<_>>(),
-                            )
-                        })
-                        .collect::<IndexMap<_, _>>();
-
-                    vec![
-                        (
-                            category,
-                            category_color_idx,
-                            ReportCratesHTML::Tree {
-                                count: tree.keys().len() as u32,
-                                tree,
-                            },
-                        ),
-                        (
-                            category,
-                            category_color_idx,
-                            ReportCratesHTML::RootResults {
-                                count: results.keys().len() as u32,
-                                results,
-                            },
-                        ),
-                    ]
-                    .into_iter()
-                }
-            }
-        })
-        .collect();
+                    ReportCratesHTML::Tree {
+                        count: tree.keys().len() as u32,
+                        tree,
+                    },
+                ),
+                (
+                    category,
+                    category_color_idx,
+                    ReportCratesHTML::RootResults {
+                        count: results.keys().len() as u32,
+                        results,
+                    },
+                ),
+            ]
+        }
+    }
+}
+
+/// Write a page of crates, either the ones of a category or the excluded ones. Only the data of
+/// the page is rendered, so that triaging a category doesn't require loading the whole report.
+fn write_report<W: ReportWriter>(
+    ex: &Experiment,
+    crates_count: usize,
+    res: &TestResults,
+    category: Option<(Comparison, &ReportCrates)>,
+    excluded: &[ExcludedCrateResult],
+    backports: &IndexMap<String, Vec<BackportCandidate>>,
+    to: &str,
+    dest: &W,
+    output_templates: bool,
+    html_logs: bool,
+) -> Fallible<()> {
+    let mut colors = IndexSet::new();
+    let mut result_names = IndexSet::new();
+
+    let categories = match category {
+        Some((category, crates)) => {
+            category_to_html(&mut colors, &mut result_names, category, crates)
+        }
+        None => Vec::new(),
+    };
 
     let context = ResultsContext {
         ex,
-        nav: if full {
-            CurrentPage::Full
-        } else {
-            CurrentPage::Summary
-        }
-        .navbar(),
+        nav: CurrentPage::Summary.navbar(ex),
         categories,
         info: res.info.clone(),
-        full,
         crates_count,
         colors,
         result_names,
+        excluded,
+        backports,
+        log_page: log_page(html_logs),
     };
 
     info!("generating {}", to);
@@ -273,6 +411,65 @@ fn write_report<W: ReportWriter>(
     Ok(())
 }
 
+/// Write the page rendering all the categories in the browser, along with the data it loads. The
+/// data is shared by all the categories, so the report only has a couple of files besides the
+/// logs.
+fn write_client_report<W: ReportWriter>(
+    ex: &Experiment,
+    crates_count: usize,
+    res: &TestResults,
+    backports: &IndexMap<String, Vec<BackportCandidate>>,
+    dest: &W,
+    output_templates: bool,
+    html_logs: bool,
+) -> Fallible<()> {
+    let mut colors = IndexSet::new();
+    let mut result_names = IndexSet::new();
+    let categories = res
+        .categories
+        .iter()
+        .flat_map(|(&category, crates)| {
+            category_to_html(&mut colors, &mut result_names, category, crates)
+        })
+        .collect();
+
+    info!("generating {}", CLIENT_DATA);
+    let data = ClientData {
+        colors,
+        result_names,
+        categories,
+        info: &res.info,
+        backports,
+        log_page: log_page(html_logs),
+    };
+    dest.write_string(
+        CLIENT_DATA,
+        serde_json::to_string(&data)?.into(),
+        &mime::APPLICATION_JSON,
+    )?;
+
+    let context = ClientContext {
+        ex,
+        nav: CurrentPage::Summary.navbar(ex),
+        crates_count,
+        data_url: CLIENT_DATA,
+    };
+
+    info!("generating {}", CLIENT_PAGE);
+    let html = minifier::html::minify(&assets::render_template("report/client.html", &context)?);
+    dest.write_string(CLIENT_PAGE, html.into(), &mime::TEXT_HTML)?;
+
+    if output_templates {
+        dest.write_string(
+            [CLIENT_PAGE, ".context.json"].concat(),
+            serde_json::to_string(&context)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    Ok(())
+}
+
 fn write_downloads<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
@@ -282,7 +479,7 @@ fn write_downloads<W: ReportWriter>(
 ) -> Fallible<()> {
     let context = DownloadsContext {
         ex,
-        nav: CurrentPage::Downloads.navbar(),
+        nav: CurrentPage::Downloads.navbar(ex),
         crates_count,
         available_archives,
     };
@@ -302,35 +499,180 @@ fn write_downloads<W: ReportWriter>(
     Ok(())
 }
 
+fn write_artifact_sizes<W: ReportWriter>(
+    ex: &Experiment,
+    crates_count: usize,
+    regressions: &[SizeRegression],
+    dest: &W,
+    output_templates: bool,
+) -> Fallible<()> {
+    let context = ArtifactSizesContext {
+        ex,
+        nav: CurrentPage::ArtifactSizes.navbar(ex),
+        crates_count,
+        regressions,
+    };
+
+    info!("generating artifact-sizes.html");
+    let html = minifier::html::minify(&assets::render_template(
+        "report/artifact-sizes.html",
+        &context,
+    )?);
+    dest.write_string("artifact-sizes.html", html.into(), &mime::TEXT_HTML)?;
+
+    if output_templates {
+        dest.write_string(
+            "artifact-sizes.html.context.json",
+            serde_json::to_string(&context)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_new_warnings<W: ReportWriter>(
+    ex: &Experiment,
+    crates_count: usize,
+    new_warnings: &[NewWarnings],
+    dest: &W,
+    output_templates: bool,
+) -> Fallible<()> {
+    let context = NewWarningsContext {
+        ex,
+        nav: CurrentPage::NewWarnings.navbar(ex),
+        crates_count,
+        new_warnings,
+    };
+
+    info!("generating new-warnings.html");
+    let html = minifier::html::minify(&assets::render_template(
+        "report/new-warnings.html",
+        &context,
+    )?);
+    dest.write_string("new-warnings.html", html.into(), &mime::TEXT_HTML)?;
+
+    if output_templates {
+        dest.write_string(
+            "new-warnings.html.context.json",
+            serde_json::to_string(&context)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_non_reproducible<W: ReportWriter>(
+    ex: &Experiment,
+    crates_count: usize,
+    non_reproducible: &[NonReproducibleCrate],
+    dest: &W,
+    output_templates: bool,
+) -> Fallible<()> {
+    let context = NonReproducibleContext {
+        ex,
+        nav: CurrentPage::NonReproducible.navbar(ex),
+        crates_count,
+        non_reproducible,
+    };
+
+    info!("generating non-reproducible.html");
+    let html = minifier::html::minify(&assets::render_template(
+        "report/non-reproducible.html",
+        &context,
+    )?);
+    dest.write_string("non-reproducible.html", html.into(), &mime::TEXT_HTML)?;
+
+    if output_templates {
+        dest.write_string(
+            "non-reproducible.html.context.json",
+            serde_json::to_string(&context)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    Ok(())
+}
+
 pub fn write_html_report<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
     res: &TestResults,
     available_archives: Vec<Archive>,
+    size_regressions: &[SizeRegression],
+    new_warnings: &[NewWarnings],
+    non_reproducible: &[NonReproducibleCrate],
+    excluded: &[ExcludedCrateResult],
+    backports: &IndexMap<String, Vec<BackportCandidate>>,
+    disk_usage: &DiskUsageSummary,
+    build_cache: &[BuildCacheSummary],
     dest: &W,
     output_templates: bool,
+    html_logs: bool,
 ) -> Fallible<()> {
     let js_in = assets::load("report.js")?;
     let css_in = assets::load("report.css")?;
-    write_report(
+    write_index(
         ex,
         crates_count,
         res,
-        false,
-        "index.html",
-        dest,
-        output_templates,
-    )?;
-    write_report(
-        ex,
-        crates_count,
-        res,
-        true,
-        "full.html",
+        excluded.len(),
+        disk_usage,
+        build_cache,
         dest,
         output_templates,
     )?;
+    if ex.client_side_report {
+        write_client_report(
+            ex,
+            crates_count,
+            res,
+            backports,
+            dest,
+            output_templates,
+            html_logs,
+        )?;
+    } else {
+        // Each category is rendered on its own, dropping its data before moving to the next one
+        for (&category, crates) in &res.categories {
+            write_report(
+                ex,
+                crates_count,
+                res,
+                Some((category, crates)),
+                &[],
+                backports,
+                &category_url(category, false),
+                dest,
+                output_templates,
+                html_logs,
+            )?;
+        }
+    }
+    if !excluded.is_empty() {
+        write_report(
+            ex,
+            crates_count,
+            res,
+            None,
+            excluded,
+            backports,
+            "excluded.html",
+            dest,
+            output_templates,
+            html_logs,
+        )?;
+    }
+    write_artifact_sizes(ex, crates_count, size_regressions, dest, output_templates)?;
+    if ex.track_warnings {
+        write_new_warnings(ex, crates_count, new_warnings, dest, output_templates)?;
+    }
+    if ex.mode == Mode::Reproducibility {
+        write_non_reproducible(ex, crates_count, non_reproducible, dest, output_templates)?;
+    }
     write_downloads(ex, crates_count, available_archives, dest, output_templates)?;
+    dest.write_string("full.html", FULL_REPORT_REDIRECT.into(), &mime::TEXT_HTML)?;
 
     info!("copying static assets");
     dest.write_bytes(
@@ -345,6 +687,15 @@ pub fn write_html_report<W: ReportWriter>(
         css_in.mime(),
         EncodingType::Plain,
     )?;
+    if ex.client_side_report {
+        let client_js_in = assets::load("report-client.js")?;
+        dest.write_bytes(
+            "report-client.js",
+            &client_js_in.content()?,
+            client_js_in.mime(),
+            EncodingType::Plain,
+        )?;
+    }
 
     Ok(())
 }
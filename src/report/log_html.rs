@@ -0,0 +1,233 @@
+//! HTML version of the build logs included in the reports. The output of each command run during
+//! the build is shown in a collapsible section, errors and warnings are highlighted, and the first
+//! error is marked with a stable anchor, which the links in the HTML reports point to.
+
+use tera::escape_html;
+
+/// Anchor of the first error in the log, linked from the HTML reports.
+const FIRST_ERROR_ANCHOR: &str = "first-error";
+
+const STYLE: &str = "\
+    body { font-family: sans-serif; margin: 0; }\
+    header { padding: 0.5em 1em; background: #333; color: #fff; }\
+    header a { color: #9cf; margin-right: 1em; }\
+    summary { padding: 0.3em 1em; background: #eee; cursor: pointer; font-family: monospace; }\
+    pre { margin: 0; padding: 0.5em 1em; overflow-x: auto; }\
+    pre span { display: block; }\
+    .error { background: #fdd; color: #a00; }\
+    .warning { background: #ffd; color: #850; }";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Normal,
+    Warning,
+    Error,
+}
+
+/// Strip the `[LEVEL] ` prefix added to every line of the log, returning the level and message.
+fn split_level(line: &str) -> (&str, &str) {
+    if let Some(rest) = line.strip_prefix('[') {
+        if let Some((level, message)) = rest.split_once("] ") {
+            return (level, message);
+        }
+    }
+    ("", line)
+}
+
+/// The command a line of the log announces running, if any.
+fn command(line: &str) -> Option<&str> {
+    let (_, message) = split_level(line);
+    message
+        .strip_prefix("running `")
+        .map(|cmd| cmd.strip_suffix('`').unwrap_or(cmd))
+}
+
+fn line_kind(line: &str) -> LineKind {
+    let (level, message) = split_level(line);
+    if level == "ERROR" {
+        return LineKind::Error;
+    }
+    let message = message
+        .strip_prefix("[stdout] ")
+        .or_else(|| message.strip_prefix("[stderr] "))
+        .unwrap_or(message)
+        .trim_start();
+    let is_diagnostic = |kind: &str| {
+        message
+            .strip_prefix(kind)
+            .is_some_and(|rest| rest.starts_with(':') || rest.starts_with('['))
+    };
+    if is_diagnostic("error") {
+        LineKind::Error
+    } else if is_diagnostic("warning") {
+        LineKind::Warning
+    } else {
+        LineKind::Normal
+    }
+}
+
+struct Section<'a> {
+    command: Option<&'a str>,
+    lines: Vec<(usize, &'a str, LineKind)>,
+}
+
+impl Section<'_> {
+    fn has_errors(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|(_, _, kind)| *kind == LineKind::Error)
+    }
+}
+
+/// Split the log in a section for each command run, plus one for the lines before the first
+/// command. Lines are numbered from 1.
+fn sections(log: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        command: None,
+        lines: Vec::new(),
+    }];
+    for (i, line) in log.lines().enumerate() {
+        if let Some(cmd) = command(line) {
+            sections.push(Section {
+                command: Some(cmd),
+                lines: Vec::new(),
+            });
+        }
+        sections
+            .last_mut()
+            .unwrap()
+            .lines
+            .push((i + 1, line, line_kind(line)));
+    }
+    sections.retain(|section| !section.lines.is_empty());
+    sections
+}
+
+/// Render the log as a standalone HTML page. The sections containing errors are expanded, or the
+/// last one if the log has no errors.
+pub(crate) fn render_log(title: &str, log: &str) -> String {
+    let sections = sections(log);
+    let first_error = sections
+        .iter()
+        .flat_map(|section| &section.lines)
+        .find(|(_, _, kind)| *kind == LineKind::Error)
+        .map(|(number, _, _)| *number);
+
+    let title = escape_html(title);
+    let mut html = format!(
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <title>{title}</title><style>{STYLE}</style></head><body>\
+         <header><h1>{title}</h1><a href=\"log.txt\">Raw log</a>"
+    );
+    if first_error.is_some() {
+        html.push_str(&format!(
+            "<a href=\"#{FIRST_ERROR_ANCHOR}\">First error</a>"
+        ));
+    }
+    html.push_str("</header>");
+
+    let last = sections.len().saturating_sub(1);
+    for (i, section) in sections.iter().enumerate() {
+        let open = section.has_errors() || (first_error.is_none() && i == last);
+        html.push_str(if open {
+            "<details open><summary>"
+        } else {
+            "<details><summary>"
+        });
+        match section.command {
+            Some(cmd) => html.push_str(&escape_html(cmd)),
+            None => html.push_str("preparing the build"),
+        }
+        html.push_str("</summary><pre>");
+        for (number, line, kind) in &section.lines {
+            if Some(*number) == first_error {
+                html.push_str(&format!("<a id=\"{FIRST_ERROR_ANCHOR}\"></a>"));
+            }
+            let class = match kind {
+                LineKind::Normal => "",
+                LineKind::Warning => " class=\"warning\"",
+                LineKind::Error => " class=\"error\"",
+            };
+            html.push_str(&format!(
+                "<span id=\"L{number}\"{class}>{}</span>",
+                escape_html(line)
+            ));
+        }
+        html.push_str("</pre></details>");
+    }
+
+    html.push_str("</body></html>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line_kind, render_log, sections, LineKind};
+
+    const LOG: &str = "[INFO] fetching crate foo 1.0.0...\n\
+                       [INFO] running `cargo build`\n\
+                       [INFO] [stderr]    Compiling foo v1.0.0\n\
+                       [INFO] [stderr] warning: unused variable: `x`\n\
+                       [INFO] running `cargo test`\n\
+                       [INFO] [stderr] error[E0308]: mismatched types\n\
+                       [INFO] [stderr]  --> src/lib.rs:1:1 <T>";
+
+    #[test]
+    fn test_line_kind() {
+        assert_eq!(
+            line_kind("[INFO] [stderr] error[E0308]: mismatched types"),
+            LineKind::Error
+        );
+        assert_eq!(
+            line_kind("[INFO] [stderr] error: could not compile `foo`"),
+            LineKind::Error
+        );
+        assert_eq!(line_kind("[ERROR] build failed"), LineKind::Error);
+        assert_eq!(
+            line_kind("[INFO] [stdout] warning: unused import"),
+            LineKind::Warning
+        );
+        assert_eq!(
+            line_kind("[INFO] [stderr]    Compiling error-chain v0.12.4"),
+            LineKind::Normal
+        );
+        assert_eq!(
+            line_kind("[INFO] [stdout] errors are reported below"),
+            LineKind::Normal
+        );
+    }
+
+    #[test]
+    fn test_sections() {
+        let sections = sections(LOG);
+        let commands = sections.iter().map(|s| s.command).collect::<Vec<_>>();
+        assert_eq!(commands, [None, Some("cargo build"), Some("cargo test")]);
+        let numbers = sections
+            .iter()
+            .map(|s| s.lines.iter().map(|l| l.0).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, [vec![1], vec![2, 3, 4], vec![5, 6, 7]]);
+        assert!(!sections[1].has_errors());
+        assert!(sections[2].has_errors());
+    }
+
+    #[test]
+    fn test_render_log() {
+        let html = render_log("foo-1.0.0 on stable", LOG);
+        assert!(html.contains("<a href=\"#first-error\">First error</a>"));
+        assert!(html.contains(
+            "<a id=\"first-error\"></a><span id=\"L6\" class=\"error\">\
+             [INFO] [stderr] error[E0308]: mismatched types</span>"
+        ));
+        assert!(html.contains("<span id=\"L4\" class=\"warning\">"));
+        assert!(html.contains("src&#x2F;lib.rs:1:1 &lt;T&gt;"));
+        // Only the section containing the error is expanded
+        assert_eq!(html.matches("<details open>").count(), 1);
+        assert!(html.contains("<details open><summary>cargo test</summary>"));
+
+        // Without errors the last section is expanded
+        let html = render_log("foo-1.0.0 on stable", "[INFO] running `cargo build`\nok\n");
+        assert!(!html.contains("first-error"));
+        assert!(html.contains("<details open><summary>cargo build</summary>"));
+    }
+}
@@ -3,7 +3,8 @@ use crate::experiments::Experiment;
 use crate::prelude::*;
 use crate::report::analyzer::{ReportConfig, ReportCrates, ToolchainSelect};
 use crate::report::{
-    crate_to_url, BuildTestResult, Comparison, CrateResult, ReportWriter, ResultName, TestResults,
+    crate_to_url, BuildTestResult, Comparison, CrateResult, ExcludedCrateResult, ReportWriter,
+    ResultName, TestResults,
 };
 use crate::utils::serialize::to_vec;
 use indexmap::{IndexMap, IndexSet};
@@ -28,6 +29,7 @@ struct ResultsContext<'a> {
     info: IndexMap<Comparison, u32>,
     full: bool,
     crates_count: usize,
+    excluded: &'a [ExcludedCrateResult],
 }
 
 fn write_crate(
@@ -67,7 +69,12 @@ fn write_crate(
     let status_warning = krate
         .status
         .map(|status| format!(" ({status})"))
-        .unwrap_or_default();
+        .unwrap_or_default()
+        + &krate
+            .triage
+            .as_ref()
+            .map(|triage| format!(" ({triage})"))
+            .unwrap_or_default();
 
     if let ReportConfig::Complete(toolchain) = comparison.report_config() {
         let (conj, run) = match toolchain {
@@ -105,6 +112,37 @@ fn render_markdown(context: &ResultsContext) -> Fallible<String> {
     //add title
     writeln!(rendered, "# Crater report for {}\n\n", context.ex.name)?;
 
+    if let [Some(start), Some(end)] = &context.ex.rustc_versions {
+        writeln!(rendered, "Tested `{start}` against `{end}`.\n")?;
+    }
+    if !context.ex.registry_overlay.is_empty() {
+        writeln!(
+            rendered,
+            "Built with {} instead of the crates.io versions.\n",
+            crate::crates::describe_overlay(&context.ex.registry_overlay)
+        )?;
+    }
+    if !context.ex.dependency_patches.is_empty() {
+        writeln!(
+            rendered,
+            "Built with {} patched in.\n",
+            crate::crates::describe_patches(&context.ex.dependency_patches)
+        )?;
+    }
+    if !context.ex.exclude.is_empty() {
+        let patterns = context
+            .ex
+            .exclude
+            .iter()
+            .map(|pattern| format!("`{pattern}`"))
+            .collect::<Vec<_>>();
+        writeln!(
+            rendered,
+            "Excluded the crates matching {}.\n",
+            patterns.join(", ")
+        )?;
+    }
+
     for (comparison, results) in context.categories.iter() {
         writeln!(rendered, "\n### {comparison}")?;
         match results {
@@ -136,6 +174,17 @@ fn render_markdown(context: &ResultsContext) -> Fallible<String> {
         }
     }
 
+    if !context.excluded.is_empty() {
+        writeln!(rendered, "\n### excluded by the configuration")?;
+        for krate in context.excluded {
+            writeln!(
+                rendered,
+                "* [{}]({}): {}",
+                krate.name, krate.url, krate.reason
+            )?;
+        }
+    }
+
     Ok(rendered)
 }
 
@@ -143,6 +192,7 @@ fn write_report<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
     res: &TestResults,
+    excluded: &[ExcludedCrateResult],
     full: bool,
     to: &str,
     dest: &W,
@@ -182,6 +232,7 @@ fn write_report<W: ReportWriter>(
         info: res.info.clone(),
         full,
         crates_count,
+        excluded,
     };
 
     let markdown = render_markdown(&context)?;
@@ -203,6 +254,7 @@ pub fn write_markdown_report<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
     res: &TestResults,
+    excluded: &[ExcludedCrateResult],
     dest: &W,
     output_templates: bool,
 ) -> Fallible<()> {
@@ -210,6 +262,7 @@ pub fn write_markdown_report<W: ReportWriter>(
         ex,
         crates_count,
         res,
+        excluded,
         false,
         "markdown.md",
         dest,
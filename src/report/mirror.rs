@@ -0,0 +1,159 @@
+use crate::prelude::*;
+use crate::report::{FileWriter, ReportWriter, S3Writer};
+use crate::results::EncodingType;
+use mime::Mime;
+use std::borrow::Cow;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Destination the reports are copied to, in addition to the reports bucket.
+pub enum MirrorWriter {
+    S3(S3Writer),
+    File(FileWriter),
+}
+
+impl ReportWriter for MirrorWriter {
+    fn write_bytes<P: AsRef<Path>>(
+        &self,
+        path: P,
+        b: &[u8],
+        mime: &Mime,
+        encoding_type: EncodingType,
+    ) -> Fallible<()> {
+        match self {
+            MirrorWriter::S3(writer) => writer.write_bytes(path, b, mime, encoding_type),
+            MirrorWriter::File(writer) => writer.write_bytes(path, b, mime, encoding_type),
+        }
+    }
+
+    fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, mime: &Mime) -> Fallible<()> {
+        match self {
+            MirrorWriter::S3(writer) => writer.write_string(path, s, mime),
+            MirrorWriter::File(writer) => writer.write_string(path, s, mime),
+        }
+    }
+}
+
+impl Display for MirrorWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MirrorWriter::S3(writer) => writer.fmt(f),
+            MirrorWriter::File(writer) => writer.fmt(f),
+        }
+    }
+}
+
+/// Writes the report to a primary destination and copies it to the mirrors. Failing to write to a
+/// mirror is not fatal: the mirror is skipped for the rest of the report, which is still
+/// available from the primary destination.
+pub struct MirroredWriter<W> {
+    primary: W,
+    mirrors: Vec<(MirrorWriter, AtomicBool)>,
+}
+
+impl<W: ReportWriter> MirroredWriter<W> {
+    pub fn new(primary: W, mirrors: Vec<MirrorWriter>) -> Self {
+        MirroredWriter {
+            primary,
+            mirrors: mirrors
+                .into_iter()
+                .map(|mirror| (mirror, AtomicBool::new(false)))
+                .collect(),
+        }
+    }
+
+    fn mirror(&self, path: &Path, write: impl Fn(&MirrorWriter) -> Fallible<()>) {
+        for (mirror, failed) in &self.mirrors {
+            if failed.load(Ordering::SeqCst) {
+                continue;
+            }
+            if let Err(err) = write(mirror) {
+                // Only warn about the first failure of each mirror
+                if !failed.swap(true, Ordering::SeqCst) {
+                    warn!(
+                        "failed to write {} to the mirror {}, skipping it: {:?}",
+                        path.display(),
+                        mirror,
+                        err
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<W: ReportWriter> ReportWriter for MirroredWriter<W> {
+    fn write_bytes<P: AsRef<Path>>(
+        &self,
+        path: P,
+        b: &[u8],
+        mime: &Mime,
+        encoding_type: EncodingType,
+    ) -> Fallible<()> {
+        let path = path.as_ref();
+        self.primary.write_bytes(path, b, mime, encoding_type)?;
+        self.mirror(path, |mirror| {
+            mirror.write_bytes(path, b, mime, encoding_type)
+        });
+        Ok(())
+    }
+
+    fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, mime: &Mime) -> Fallible<()> {
+        let path = path.as_ref();
+        self.primary
+            .write_string(path, Cow::Borrowed(s.as_ref()), mime)?;
+        self.mirror(path, |mirror| {
+            mirror.write_string(path, Cow::Borrowed(s.as_ref()), mime)
+        });
+        Ok(())
+    }
+}
+
+impl<W: Display> Display for MirroredWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.primary.fmt(f)?;
+        if !self.mirrors.is_empty() {
+            write!(f, " (and {} mirrors)", self.mirrors.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MirrorWriter, MirroredWriter};
+    use crate::report::{FileWriter, ReportWriter};
+    use std::fs;
+
+    #[test]
+    fn test_failing_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let primary = FileWriter::create(dir.path().join("primary")).unwrap();
+        let mirror = FileWriter::create(dir.path().join("mirror")).unwrap();
+
+        // Replace the directory of the broken mirror with a file, to make the writes fail
+        let broken = FileWriter::create(dir.path().join("broken")).unwrap();
+        fs::remove_dir(dir.path().join("broken")).unwrap();
+        fs::write(dir.path().join("broken"), "").unwrap();
+
+        let writer = MirroredWriter::new(
+            primary,
+            vec![MirrorWriter::File(broken), MirrorWriter::File(mirror)],
+        );
+        writer
+            .write_string("foo/index.html", "report".into(), &mime::TEXT_HTML)
+            .unwrap();
+
+        for dest in &["primary", "mirror"] {
+            let path = dir.path().join(dest).join("foo/index.html");
+            assert_eq!(fs::read_to_string(path).unwrap(), "report");
+        }
+        assert!(writer.mirrors[0]
+            .1
+            .load(std::sync::atomic::Ordering::SeqCst));
+        assert!(!writer.mirrors[1]
+            .1
+            .load(std::sync::atomic::Ordering::SeqCst));
+    }
+}
@@ -1,32 +1,58 @@
 use crate::config::Config;
-use crate::crates::Crate;
-use crate::dirs::WORK_DIR;
-use crate::experiments::Experiment;
+use crate::crates::{Crate, RegistryCrate};
+use crate::experiments::{ComparisonPolicy, Experiment, Mode};
 use crate::prelude::*;
-use crate::report::analyzer::{analyze_report, ReportConfig, ToolchainSelect};
-use crate::results::{EncodedLog, EncodingType, FailureReason, ReadResults, TestResult};
+use crate::report::analyzer::{ReportConfig, ToolchainSelect};
+use crate::results::{
+    EncodedLog, EncodingType, FailureReason, ReadResults, TestResult, TestRuns, TriageAnnotation,
+};
 use crate::toolchain::Toolchain;
 use crate::utils;
-use crates_index::GitIndex;
 use mime::Mime;
 use percent_encoding::{utf8_percent_encode, AsciiSet};
 use std::borrow::Cow;
+use std::collections::HashSet;
 #[cfg(test)]
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::{self, Display};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 mod analyzer;
 mod archives;
+mod backports;
+mod build_cache;
+mod checkpoints;
+mod diff;
+mod disk_usage;
 mod display;
 mod html;
+mod log_html;
 mod markdown;
+mod mirror;
+mod reproducibility;
 mod s3;
-
+mod sizes;
+mod summary;
+mod unstable_features;
+mod versions;
+mod warnings;
+
+pub use self::backports::{beta_changeset, BackportCandidate, MergedPr};
+pub use self::build_cache::BuildCacheSummary;
+pub use self::checkpoints::{ReportJob, ReportProgress};
+pub use self::diff::{crate_outcomes, diff_experiments, CrateDiff, CrateOutcome, ReportDiff};
+pub use self::disk_usage::DiskUsageSummary;
 pub use self::display::{Color, ResultColor, ResultName};
+pub use self::mirror::{MirrorWriter, MirroredWriter};
+pub use self::reproducibility::NonReproducibleCrate;
 pub use self::s3::{S3Prefix, S3Writer};
-pub use analyzer::TestResults;
+pub use self::sizes::SizeRegression;
+pub use self::summary::{summary_sections, summary_tldr};
+pub use self::unstable_features::{diff_unstable_features, FeatureUsageDiff};
+use self::versions::IndexVersions;
+pub use self::warnings::NewWarnings;
+pub use analyzer::{analyze_report, ReportCrates, TestResults};
 
 pub(crate) const REPORT_ENCODE_SET: AsciiSet = percent_encoding::CONTROLS
     .add(b' ')
@@ -40,13 +66,48 @@ pub(crate) const REPORT_ENCODE_SET: AsciiSet = percent_encoding::CONTROLS
     .add(b'}')
     .add(b'+');
 
+/// Characters to encode in each component of the paths of the report in URLs, which must not
+/// contain path separators of any platform. The components are the sanitized names of the files,
+/// whose `%` are part of the name and must be encoded too.
+const URL_COMPONENT_ENCODE_SET: AsciiSet = REPORT_ENCODE_SET.add(b'/').add(b'\\').add(b'%');
+
+/// Flakiness score above which test regressions and fixes of a crate are considered spurious.
+const FLAKINESS_THRESHOLD: f64 = 0.25;
+
 #[derive(Serialize, Deserialize)]
 pub struct RawTestResults {
+    /// Tags of the experiment, to group the results of related experiments.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     pub crates: Vec<CrateResult>,
 }
 
-#[cfg_attr(test, derive(Debug))]
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+/// A crate excluded from the experiment by the filters of the configuration, or by the exclusion
+/// patterns of the experiment.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExcludedCrateResult {
+    name: String,
+    url: String,
+    reason: String,
+}
+
+fn excluded_crates<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+) -> Fallible<Vec<ExcludedCrateResult>> {
+    Ok(db
+        .load_excluded_crates(ex)?
+        .into_iter()
+        .map(|excluded| ExcludedCrateResult {
+            name: crate_to_name(&excluded.krate),
+            url: crate_to_url(&excluded.krate),
+            reason: excluded.reason.to_string(),
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CrateResult {
     name: String,
     url: String,
@@ -55,6 +116,8 @@ pub struct CrateResult {
     status: Option<CrateVersionStatus>,
     pub res: Comparison,
     runs: [Option<BuildTestResult>; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    triage: Option<TriageAnnotation>,
 }
 
 string_enum!(enum CrateVersionStatus {
@@ -64,7 +127,7 @@ string_enum!(enum CrateVersionStatus {
     MissingFromIndex => "missing from the index",
 });
 
-string_enum!(pub enum Comparison {
+string_enum!(#[non_exhaustive] pub enum Comparison {
     Regressed => "regressed",
     Fixed => "fixed",
     Skipped => "skipped",
@@ -73,10 +136,13 @@ string_enum!(pub enum Comparison {
     Broken => "broken",
     SameBuildFail => "build-fail",
     SameTestFail => "test-fail",
+    SameDoctestFail => "doctest-fail",
     SameTestSkipped => "test-skipped",
+    TestSkipChanged => "test-skip-changed",
     SameTestPass => "test-pass",
     SpuriousRegressed => "spurious-regressed",
     SpuriousFixed => "spurious-fixed",
+    Overridden => "overridden",
 });
 
 impl Comparison {
@@ -87,11 +153,14 @@ impl Comparison {
             | Comparison::Unknown
             | Comparison::Error
             | Comparison::SpuriousRegressed
-            | Comparison::SpuriousFixed => true,
+            | Comparison::SpuriousFixed
+            | Comparison::TestSkipChanged => true,
             Comparison::Skipped
             | Comparison::Broken
+            | Comparison::Overridden
             | Comparison::SameBuildFail
             | Comparison::SameTestFail
+            | Comparison::SameDoctestFail
             | Comparison::SameTestSkipped
             | Comparison::SameTestPass => false,
         }
@@ -107,21 +176,59 @@ impl Comparison {
             | Comparison::SpuriousFixed
             | Comparison::Skipped
             | Comparison::Broken
+            | Comparison::Overridden
             | Comparison::SameBuildFail
             | Comparison::SameTestFail
+            | Comparison::SameDoctestFail
             | Comparison::SameTestSkipped
+            | Comparison::TestSkipChanged
             | Comparison::SameTestPass => ReportConfig::Simple,
         }
     }
 }
 
-#[cfg_attr(test, derive(Debug))]
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
-struct BuildTestResult {
+impl CrateResult {
+    /// Name of the crate, including its version or commit.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Page of the crate on crates.io or GitHub.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn krate(&self) -> &Crate {
+        &self.krate
+    }
+
+    /// Results of the start and end toolchains, missing if the crate wasn't built.
+    pub fn runs(&self) -> [Option<&BuildTestResult>; 2] {
+        [self.runs[0].as_ref(), self.runs[1].as_ref()]
+    }
+
+    pub fn triage(&self) -> Option<&TriageAnnotation> {
+        self.triage.as_ref()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BuildTestResult {
     res: TestResult,
     log: String,
 }
 
+impl BuildTestResult {
+    pub fn result(&self) -> &TestResult {
+        &self.res
+    }
+
+    /// Path of the log in the report, relative to its root.
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+}
+
 /// The type of sanitization required for a string.
 #[derive(Debug, Clone, Copy)]
 enum SanitizationContext {
@@ -132,7 +239,13 @@ enum SanitizationContext {
 impl SanitizationContext {
     fn sanitize(self, input: &str) -> Cow<str> {
         match self {
-            SanitizationContext::Url => utf8_percent_encode(input, &REPORT_ENCODE_SET).into(),
+            // The URLs point to the files named by the path sanitization
+            SanitizationContext::Url => {
+                let name = SanitizationContext::Path.sanitize(input);
+                utf8_percent_encode(&name, &URL_COMPONENT_ENCODE_SET)
+                    .to_string()
+                    .into()
+            }
 
             SanitizationContext::Path => {
                 utf8_percent_encode(input, &utils::FILENAME_ENCODE_SET).into()
@@ -141,73 +254,72 @@ impl SanitizationContext {
     }
 }
 
+/// Components of the path of the directory containing the files of a crate in the report.
+fn crate_path_components(
+    toolchain: &Toolchain,
+    krate: &Crate,
+    dest: SanitizationContext,
+) -> [String; 3] {
+    let (kind, name) = match *krate {
+        Crate::Registry(ref details) => ("reg", format!("{}-{}", details.name, details.version)),
+        Crate::GitHub(ref repo) => ("gh", format!("{}.{}", repo.org, repo.name)),
+        Crate::Local(ref name) => ("local", name.clone()),
+        Crate::Path(ref krate_path) => ("path", krate_path.clone()),
+        Crate::Git(ref repo) => ("git", repo.url.clone()),
+    };
+    [
+        dest.sanitize(&toolchain.to_string()).into_owned(),
+        kind.to_string(),
+        dest.sanitize(&name).into_owned(),
+    ]
+}
+
 fn crate_to_path_fragment(
     toolchain: &Toolchain,
     krate: &Crate,
     dest: SanitizationContext,
 ) -> PathBuf {
-    let mut path = PathBuf::new();
-    path.push(dest.sanitize(&toolchain.to_string()).into_owned());
-
-    match *krate {
-        Crate::Registry(ref details) => {
-            path.push("reg");
+    crate_path_components(toolchain, krate, dest)
+        .iter()
+        .collect()
+}
 
-            let name = format!("{}-{}", details.name, details.version);
-            path.push(dest.sanitize(&name).into_owned());
-        }
-        Crate::GitHub(ref repo) => {
-            path.push("gh");
+/// Relative URL of the directory containing the files of a crate in the report. URLs are built
+/// from the components directly, as converting a path would use the separator of the platform
+/// generating the report.
+fn crate_to_url_fragment(toolchain: &Toolchain, krate: &Crate) -> String {
+    crate_path_components(toolchain, krate, SanitizationContext::Url).join("/")
+}
 
-            let name = format!("{}.{}", repo.org, repo.name);
-            path.push(dest.sanitize(&name).into_owned());
-        }
-        Crate::Local(ref name) => {
-            path.push("local");
-            path.push(name);
-        }
-        Crate::Path(ref krate_path) => {
-            path.push("path");
-            path.push(dest.sanitize(krate_path).into_owned());
-        }
-        Crate::Git(ref repo) => {
-            path.push("git");
-            path.push(dest.sanitize(&repo.url).into_owned());
-        }
-    }
+/// Key of a file of the report in object storage, which always uses `/` as the separator.
+pub(crate) fn path_to_key(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
-    path
+/// URL of the log of a crate in the report published at `report_url`.
+pub(crate) fn log_url(report_url: &str, toolchain: &Toolchain, krate: &Crate) -> String {
+    format!(
+        "{}{}/log.txt",
+        report_url.trim_end_matches("index.html"),
+        crate_to_url_fragment(toolchain, krate),
+    )
 }
 
-fn get_crate_version_status(
-    index: &GitIndex,
-    krate: &Crate,
-) -> Fallible<Option<CrateVersionStatus>> {
-    if let Crate::Registry(krate) = krate {
-        let index_krate = index
-            .crate_(&krate.name)
-            .ok_or_else(|| anyhow!("no crate found in index {:?}", &krate))?;
-
-        let outdated = index_krate.most_recent_version().version() != krate.version;
-
-        for version in index_krate.versions().iter().rev() {
-            // Check if the tested version is yanked
-            if version.version() == krate.version {
-                if version.is_yanked() {
-                    return Ok(Some(CrateVersionStatus::Yanked));
-                } else if outdated {
-                    return Ok(Some(CrateVersionStatus::Outdated));
-                } else {
-                    return Ok(Some(CrateVersionStatus::UpToDate));
-                }
-            }
-        }
+/// Look up the versions of the crates in the index, reusing the versions stored by a previous
+/// attempt at generating the report.
+fn index_versions(crates: &[Crate], job: Option<&ReportJob>) -> Fallible<IndexVersions> {
+    if let Some(versions) = job.map(|job| job.index_versions()).transpose()?.flatten() {
+        return Ok(versions);
+    }
 
-        bail!("version not found");
-    } else {
-        // we do not check versions for other crates
-        Ok(None)
+    let versions = IndexVersions::load(crates)?;
+    if let Some(job) = job {
+        job.store_index_versions(&versions)?;
     }
+    Ok(versions)
 }
 
 pub fn generate_report<DB: ReadResults>(
@@ -215,14 +327,13 @@ pub fn generate_report<DB: ReadResults>(
     config: &Config,
     ex: &Experiment,
     crates: &[Crate],
+    job: Option<&ReportJob>,
 ) -> Fallible<RawTestResults> {
     let mut crates = crates.to_vec();
-    let index = GitIndex::with_path(
-        WORK_DIR.join("crates.io-index"),
-        "https://github.com/rust-lang/crates.io-index",
-    )?;
+    let versions = index_versions(&crates, job)?;
     //crate ids are unique so unstable sort is equivalent to stable sort but is generally faster
     crates.sort_unstable_by_key(|a| a.id());
+    let flakiness = db.load_flakiness_scores(ex)?;
     let res = crates
         .iter()
         .map(|krate| {
@@ -233,34 +344,99 @@ pub fn generate_report<DB: ReadResults>(
 
                 Some(BuildTestResult {
                     res,
-                    log: crate_to_path_fragment(tc, krate, SanitizationContext::Url)
-                        .to_str()
-                        .unwrap()
-                        .replace('\'', "/"), // Normalize paths in reports generated on Windows
+                    log: crate_to_url_fragment(tc, krate),
                 })
             });
             let crate1 = crate_results.next().unwrap();
             let crate2 = crate_results.next().unwrap();
+            let overridden = ex
+                .toolchains
+                .iter()
+                .map(|tc| db.load_overridden(ex, tc, krate))
+                .collect::<Fallible<Vec<_>>>()?
+                .into_iter()
+                .all(|overridden| overridden);
             let comp = compare(
                 config,
+                ex.comparison_policy,
                 krate,
                 crate1.as_ref().map(|b| &b.res),
                 crate2.as_ref().map(|b| &b.res),
+                overridden,
+            );
+            let comp = mark_flaky(
+                comp,
+                crate1.as_ref().map(|b| &b.res),
+                crate2.as_ref().map(|b| &b.res),
+                flakiness.get(&krate.id()).copied(),
             );
 
             Ok(CrateResult {
                 name: crate_to_name(krate),
                 url: crate_to_url(krate),
-                status: get_crate_version_status(&index, krate)
-                    .unwrap_or(Some(CrateVersionStatus::MissingFromIndex)),
+                status: versions.status(krate),
                 krate: krate.clone(),
                 res: comp,
                 runs: [crate1, crate2],
+                triage: db.load_triage(ex, krate)?,
             })
         })
         .collect::<Fallible<Vec<_>>>()?;
 
-    Ok(RawTestResults { crates: res })
+    Ok(RawTestResults {
+        tags: ex.tags.clone(),
+        crates: res,
+    })
+}
+
+/// Lower the confidence of test regressions and fixes of crates known to have flaky test suites.
+fn mark_flaky(
+    comp: Comparison,
+    r1: Option<&TestResult>,
+    r2: Option<&TestResult>,
+    score: Option<f64>,
+) -> Comparison {
+    let is_test_result =
+        |r: Option<&TestResult>| matches!(r, Some(TestResult::TestPass | TestResult::TestFail(_)));
+    if score.is_none_or(|s| s < FLAKINESS_THRESHOLD) || !is_test_result(r1) || !is_test_result(r2) {
+        return comp;
+    }
+
+    match comp {
+        Comparison::Regressed => Comparison::SpuriousRegressed,
+        Comparison::Fixed => Comparison::SpuriousFixed,
+        other => other,
+    }
+}
+
+#[derive(Serialize)]
+struct FlakyCrate {
+    #[serde(rename = "crate")]
+    krate: String,
+    toolchain: String,
+    runs: TestRuns,
+}
+
+fn find_flaky_crates<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<FlakyCrate>> {
+    let mut flaky = Vec::new();
+    for krate in crates {
+        for tc in &ex.toolchains {
+            if let Some(runs) = db.load_test_runs(ex, tc, krate)? {
+                if runs.is_flaky() {
+                    flaky.push(FlakyCrate {
+                        krate: krate.id(),
+                        toolchain: tc.to_string(),
+                        runs,
+                    });
+                }
+            }
+        }
+    }
+    Ok(flaky)
 }
 
 const PROGRESS_FRACTION: usize = 50; // write progress every ~1/N crates
@@ -271,10 +447,43 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
     crates: &[Crate],
     dest: &W,
     config: &Config,
+    job: Option<&ReportJob>,
 ) -> Fallible<()> {
     let num_crates = crates.len();
     let progress_every = (num_crates / PROGRESS_FRACTION) + 1;
 
+    // Skip the crates whose logs were written before the generation was interrupted
+    let start = job
+        .and_then(|job| job.last_crate())
+        .and_then(|last| crates.iter().position(|krate| krate.id() == last))
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+
+    for (chunk_idx, chunk) in crates[start..].chunks(progress_every).enumerate() {
+        info!(
+            "wrote logs for {}/{} crates",
+            start + chunk_idx * progress_every,
+            num_crates
+        );
+        write_logs_chunk(db, ex, chunk, dest, config)?;
+
+        // All the uploads of the chunk are done at this point
+        if let (Some(job), Some(last)) = (job, chunk.last()) {
+            job.checkpoint(checkpoints::ReportPhase::Logs, Some(&last.id()))?;
+            job.set_progress(start + chunk_idx * progress_every + chunk.len(), num_crates)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_logs_chunk<DB: ReadResults, W: ReportWriter>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+    dest: &W,
+    config: &Config,
+) -> Fallible<()> {
     let errors = std::sync::Mutex::new(vec![]);
     std::thread::scope(|s| {
         let mut channels = vec![];
@@ -282,14 +491,13 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
         // mostly driving network-related traffic. 8 is a reasonable number to not overwhelm
         // systems while keeping things moving much faster than fully serial uploads.
         for _ in 0..8 {
-            let (tx, rx) = std::sync::mpsc::sync_channel::<(PathBuf, Vec<u8>, EncodingType)>(32);
+            let (tx, rx) =
+                std::sync::mpsc::sync_channel::<(PathBuf, Vec<u8>, &Mime, EncodingType)>(32);
             channels.push(tx);
             let errors = &errors;
             s.spawn(move || {
-                while let Ok((log_path, data, encoding)) = rx.recv() {
-                    if let Err(e) =
-                        dest.write_bytes(log_path, &data, &mime::TEXT_PLAIN_UTF_8, encoding)
-                    {
+                while let Ok((path, data, mime, encoding)) = rx.recv() {
+                    if let Err(e) = dest.write_bytes(path, &data, mime, encoding) {
                         errors.lock().unwrap().push(e);
                     }
                 }
@@ -297,17 +505,12 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
         }
 
         for (i, krate) in crates.iter().enumerate() {
-            if i % progress_every == 0 {
-                info!("wrote logs for {}/{} crates", i, num_crates)
-            }
-
             if config.should_skip(krate) {
                 continue;
             }
 
             for tc in &ex.toolchains {
-                let log_path =
-                    crate_to_path_fragment(tc, krate, SanitizationContext::Path).join("log.txt");
+                let log_dir = crate_to_path_fragment(tc, krate, SanitizationContext::Path);
                 let content = db
                     .load_log(ex, tc, krate)
                     .and_then(|c| c.ok_or_else(|| anyhow!("missing logs")))
@@ -320,17 +523,42 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
                     }
                 };
 
-                match content {
-                    EncodedLog::Plain(data) => {
-                        channels[i % channels.len()]
-                            .send((log_path, data, EncodingType::Plain))
-                            .unwrap();
-                    }
-                    EncodedLog::Gzip(data) => {
-                        channels[i % channels.len()]
-                            .send((log_path, data, EncodingType::Gzip))
-                            .unwrap();
-                    }
+                // The HTML version of the log is stored with the same encoding as the log
+                let encoding = content.get_encoding_type();
+                let html = config.report.html_logs.then(|| {
+                    content
+                        .to_plain()
+                        .map(|plain| {
+                            let title = format!("{} on {tc}", crate_to_name(krate));
+                            log_html::render_log(&title, &String::from_utf8_lossy(&plain))
+                        })
+                        .and_then(|html| EncodedLog::from_plain_slice(html.as_bytes(), encoding))
+                        .with_context(|| format!("failed to render log of {krate} on {tc}"))
+                });
+
+                let channel = &channels[i % channels.len()];
+                let data = match content {
+                    EncodedLog::Plain(data) | EncodedLog::Gzip(data) => data,
+                };
+                channel
+                    .send((
+                        log_dir.join("log.txt"),
+                        data,
+                        &mime::TEXT_PLAIN_UTF_8,
+                        encoding,
+                    ))
+                    .unwrap();
+                match html {
+                    Some(Ok(EncodedLog::Plain(html) | EncodedLog::Gzip(html))) => channel
+                        .send((
+                            log_dir.join("log.html"),
+                            html,
+                            &mime::TEXT_HTML_UTF_8,
+                            encoding,
+                        ))
+                        .unwrap(),
+                    Some(Err(e)) => utils::report_failure(&e),
+                    None => {}
                 }
             }
         }
@@ -347,21 +575,102 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
     Ok(())
 }
 
-pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
+/// The content of a report, read from the results before anything is written. Reading it first
+/// allows releasing a snapshot of the database before the (slow) uploads of the report.
+pub struct ReportData {
+    results_json: String,
+    retry_list: String,
+    comparisons: HashMap<String, Comparison>,
+    res: TestResults,
+    size_regressions: Vec<SizeRegression>,
+    new_warnings: Vec<NewWarnings>,
+    non_reproducible: Vec<NonReproducibleCrate>,
+    disk_usage: DiskUsageSummary,
+    build_cache: Vec<BuildCacheSummary>,
+    excluded: Vec<ExcludedCrateResult>,
+    flaky: Option<Vec<FlakyCrate>>,
+    backports: Option<indexmap::IndexMap<String, Vec<BackportCandidate>>>,
+}
+
+/// Comparison of each crate of the results, keyed by the crate id.
+fn comparisons(raw: &RawTestResults) -> HashMap<String, Comparison> {
+    raw.crates
+        .iter()
+        .map(|krate| (krate.krate.id(), krate.res))
+        .collect()
+}
+
+/// Read everything the report of the experiment is made of, except the logs.
+pub fn read_report<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+    config: &Config,
+    job: Option<&ReportJob>,
+    changeset: Option<&[MergedPr]>,
+) -> Fallible<ReportData> {
+    let raw = generate_report(db, config, ex, crates, job)?;
+    let results_json = serde_json::to_string(&raw)?;
+    let retry_list = gen_retry_list(&raw);
+    let comparisons = comparisons(&raw);
+    let res = analyze_report(raw);
+
+    let new_warnings = if ex.track_warnings {
+        warnings::find_new_warnings(db, ex, crates)?
+    } else {
+        Vec::new()
+    };
+    let non_reproducible = if ex.mode == Mode::Reproducibility {
+        reproducibility::find_non_reproducible(db, ex, crates)?
+    } else {
+        Vec::new()
+    };
+    let build_cache = if ex.build_cache {
+        build_cache::summarize_build_cache(db, ex, crates)?
+    } else {
+        Vec::new()
+    };
+    let flaky = match ex.repeat {
+        Some(_) => Some(find_flaky_crates(db, ex, crates)?),
+        None => None,
+    };
+    let backports = match changeset {
+        Some(changeset) => Some(backports::find_candidates(db, ex, &res, changeset)?),
+        None => None,
+    };
+
+    Ok(ReportData {
+        results_json,
+        retry_list,
+        comparisons,
+        size_regressions: sizes::find_size_regressions(db, ex, crates)?,
+        new_warnings,
+        non_reproducible,
+        disk_usage: disk_usage::summarize_disk_usage(db, ex, crates)?,
+        build_cache,
+        excluded: excluded_crates(db, ex)?,
+        flaky,
+        backports,
+        res,
+    })
+}
+
+/// Write the report read by [`read_report`] to `dest`, loading the logs of the crates from `db`.
+pub fn write_report<DB: ReadResults, W: ReportWriter + Display>(
     db: &DB,
+    data: ReportData,
     ex: &Experiment,
     crates: &[Crate],
     dest: &W,
     config: &Config,
     output_templates: bool,
+    job: Option<&ReportJob>,
 ) -> Fallible<TestResults> {
-    let raw = generate_report(db, config, ex, crates)?;
-
     info!("writing results to {}", dest);
     info!("writing metadata");
     dest.write_string(
         "results.json",
-        serde_json::to_string(&raw)?.into(),
+        data.results_json.into(),
         &mime::APPLICATION_JSON,
     )?;
     dest.write_string(
@@ -371,28 +680,143 @@ pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
     )?;
     dest.write_string(
         "retry-regressed-list.txt",
-        gen_retry_list(&raw).into(),
+        data.retry_list.into(),
         &mime::TEXT_PLAIN_UTF_8,
     )?;
 
-    let res = analyze_report(raw);
-    info!("writing archives");
-    let available_archives = archives::write_logs_archives(db, ex, crates, dest, config)?;
-    info!("writing html files");
-    html::write_html_report(
-        ex,
-        crates.len(),
-        &res,
-        available_archives,
-        dest,
-        output_templates,
+    dest.write_string(
+        "artifact-sizes.json",
+        serde_json::to_string(&data.size_regressions)?.into(),
+        &mime::APPLICATION_JSON,
     )?;
-    info!("writing markdown files");
-    markdown::write_markdown_report(ex, crates.len(), &res, dest, output_templates)?;
+    if ex.track_warnings {
+        dest.write_string(
+            "new-warnings.json",
+            serde_json::to_string(&data.new_warnings)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+    if ex.mode == Mode::Reproducibility {
+        dest.write_string(
+            "non-reproducible.json",
+            serde_json::to_string(&data.non_reproducible)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+    dest.write_string(
+        "disk-usage.json",
+        serde_json::to_string(&data.disk_usage)?.into(),
+        &mime::APPLICATION_JSON,
+    )?;
+
+    if ex.build_cache {
+        dest.write_string(
+            "build-cache.json",
+            serde_json::to_string(&data.build_cache)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    dest.write_string(
+        "excluded-crates.json",
+        serde_json::to_string(&data.excluded)?.into(),
+        &mime::APPLICATION_JSON,
+    )?;
+
+    if let Some(flaky) = &data.flaky {
+        dest.write_string(
+            "flaky-crates.json",
+            serde_json::to_string(flaky)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+
+    use checkpoints::ReportPhase;
+    let is_done = |phase| job.is_some_and(|job| job.is_done(phase));
+    let checkpoint = |phase| job.map_or(Ok(()), |job| job.checkpoint(phase, None));
+
+    if let Some(backports) = &data.backports {
+        dest.write_string(
+            "backport-candidates.json",
+            serde_json::to_string(backports)?.into(),
+            &mime::APPLICATION_JSON,
+        )?;
+    }
+    let backports = data.backports.unwrap_or_default();
+
+    let available_archives = match job.and_then(|job| job.archives()) {
+        Some(archives) if is_done(ReportPhase::Archives) => archives.to_vec(),
+        _ => {
+            checkpoint(ReportPhase::Archives)?;
+            info!("writing archives");
+            let archives = archives::write_logs_archives(
+                db,
+                ex,
+                crates,
+                &data.comparisons,
+                dest,
+                config,
+                job,
+            )?;
+            if let Some(job) = job {
+                job.store_archives(&archives)?;
+            }
+            archives
+        }
+    };
+    if !is_done(ReportPhase::Html) {
+        checkpoint(ReportPhase::Html)?;
+        info!("writing html files");
+        html::write_html_report(
+            ex,
+            crates.len(),
+            &data.res,
+            available_archives,
+            &data.size_regressions,
+            &data.new_warnings,
+            &data.non_reproducible,
+            &data.excluded,
+            &backports,
+            &data.disk_usage,
+            &data.build_cache,
+            dest,
+            output_templates,
+            config.report.html_logs,
+        )?;
+    }
+    if !is_done(ReportPhase::Markdown) {
+        checkpoint(ReportPhase::Markdown)?;
+        info!("writing markdown files");
+        markdown::write_markdown_report(
+            ex,
+            crates.len(),
+            &data.res,
+            &data.excluded,
+            dest,
+            output_templates,
+        )?;
+    }
+    if job.and_then(|job| job.last_crate()).is_none() {
+        checkpoint(ReportPhase::Logs)?;
+    }
     info!("writing logs");
-    write_logs(db, ex, crates, dest, config)?;
+    write_logs(db, ex, crates, dest, config, job)?;
 
-    Ok(res)
+    Ok(data.res)
+}
+
+pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+    dest: &W,
+    config: &Config,
+    output_templates: bool,
+    job: Option<&ReportJob>,
+    changeset: Option<&[MergedPr]>,
+) -> Fallible<TestResults> {
+    let data = read_report(db, ex, crates, config, job, changeset)?;
+    write_report(db, data, ex, crates, dest, config, output_templates, job)
 }
 
 /// Generates a list of regressed crate names that can be passed to crater via
@@ -408,19 +832,103 @@ fn gen_retry_list(res: &RawTestResults) -> String {
         .filter(|crate_res| {
             crate_res.res == Comparison::Regressed || crate_res.res == Comparison::SpuriousRegressed
         })
-        .map(|crate_res| &crate_res.krate);
+        .filter_map(|crate_res| retry_list_name(&crate_res.krate));
 
-    for krate in regressed_crates {
-        match krate {
-            Crate::Registry(details) => writeln!(out, "{}", details.name).unwrap(),
-            Crate::GitHub(repo) => writeln!(out, "{}/{}", repo.org, repo.name).unwrap(),
-            Crate::Local(_) | Crate::Git(_) | Crate::Path(_) => {}
-        }
+    for name in regressed_crates {
+        writeln!(out, "{name}").unwrap();
     }
 
     out
 }
 
+/// Returns the names of the regressed crates in an analyzed report, in the format accepted by
+/// `crates=list:...`.
+pub fn regressed_crate_names(res: &TestResults) -> HashSet<String> {
+    regressed_crates(res)
+        .filter_map(|c| retry_list_name(&c.krate))
+        .collect()
+}
+
+/// Exact versions of the regressed registry crates, to retry them in experiments selecting the
+/// crates by their versions.
+pub fn regressed_crate_versions(res: &TestResults) -> BTreeSet<RegistryCrate> {
+    regressed_crates(res)
+        .filter_map(|c| match &c.krate {
+            Crate::Registry(krate) => Some(krate.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn regressed_crates(res: &TestResults) -> impl Iterator<Item = &CrateResult> {
+    [Comparison::Regressed, Comparison::SpuriousRegressed]
+        .into_iter()
+        .filter_map(|comparison| res.categories.get(&comparison))
+        .flat_map(category_crates)
+}
+
+fn category_crates(crates: &ReportCrates) -> Box<dyn Iterator<Item = &CrateResult> + '_> {
+    match crates {
+        ReportCrates::Plain(crates) => Box::new(crates.iter()),
+        ReportCrates::Complete { tree, results } => {
+            Box::new(tree.values().chain(results.values()).flatten())
+        }
+    }
+}
+
+/// Regression of a crate not caused by one of its dependencies, grouped with the crates failing
+/// because they depend on it.
+pub struct RootRegression {
+    pub krate: Crate,
+    /// Result with the second toolchain, if the crate was tested by the experiment.
+    pub result: Option<TestResult>,
+    pub dependents: Vec<Crate>,
+}
+
+/// Find the root regressions of the experiment, sorted by the number of crates they break.
+pub fn root_regressions(res: &TestResults) -> Vec<RootRegression> {
+    let (tree, results) = match res.categories.get(&Comparison::Regressed) {
+        Some(ReportCrates::Complete { tree, results }) => (tree, results),
+        _ => return Vec::new(),
+    };
+
+    // Crates failing with multiple error codes are listed once for each code
+    let mut roots = indexmap::IndexMap::new();
+    for krate in results.values().flatten() {
+        roots
+            .entry(krate.krate.clone())
+            .or_insert_with(|| RootRegression {
+                krate: krate.krate.clone(),
+                result: krate.runs[1].as_ref().map(|run| run.res.clone()),
+                dependents: Vec::new(),
+            });
+    }
+    for (dep, crates) in tree {
+        roots
+            .entry(dep.clone())
+            .or_insert_with(|| RootRegression {
+                krate: dep.clone(),
+                result: None,
+                dependents: Vec::new(),
+            })
+            .dependents
+            .extend(crates.iter().map(|krate| krate.krate.clone()));
+    }
+
+    let mut roots = roots.into_values().collect::<Vec<_>>();
+    roots.sort_by_key(|root| std::cmp::Reverse(root.dependents.len()));
+    roots
+}
+
+/// Name of the crate used in the crate lists, like `serde` or `rust-lang/crater`.
+pub(crate) fn retry_list_name(krate: &Crate) -> Option<String> {
+    match krate {
+        Crate::Registry(details) => Some(details.name.clone()),
+        Crate::GitHub(repo) => Some(format!("{}/{}", repo.org, repo.name)),
+        Crate::Local(_) | Crate::Git(_) | Crate::Path(_) => None,
+    }
+}
+
 fn crate_to_name(c: &Crate) -> String {
     match *c {
         Crate::Registry(ref details) => format!("{}-{}", details.name, details.version),
@@ -470,16 +978,47 @@ fn crate_to_url(c: &Crate) -> String {
     }
 }
 
-fn compare(
+/// Categorize the change between the results of the two toolchains. The policy of the experiment
+/// decides how the corner cases are categorized:
+///
+/// * `lenient` (the default): crates whose tests were skipped with only one toolchain are put in
+///   their own category, and failures caused only by changed diagnostics are spurious.
+/// * `strict`: like `lenient`, but tests stopping or starting to run are regressions and fixes,
+///   and so are prepare failures (broken crates) happening with only one toolchain. Broken crates
+///   are only fixed when they pass with the other toolchain.
+/// * `lint-experiment`: like `lenient`, but failures caused by changed diagnostics are what the
+///   experiment looks for, and are never spurious.
+///
+/// `overridden` is whether the agent recorded both results as forced by an override of the
+/// configuration, in which case the crate was not built.
+pub(crate) fn compare(
     config: &Config,
+    policy: ComparisonPolicy,
     krate: &Crate,
     r1: Option<&TestResult>,
     r2: Option<&TestResult>,
+    overridden: bool,
 ) -> Comparison {
     use crate::results::TestResult::*;
 
+    let is_spurious = |reason: &FailureReason| match reason {
+        FailureReason::CompilerDiagnosticChange => policy != ComparisonPolicy::LintExperiment,
+        _ => reason.is_spurious(),
+    };
+
+    // Failing doctests are less severe than failing tests, so crates failing both compare like
+    // the ones failing their tests
+    let tests_result = |r: Option<&TestResult>| {
+        r.map(|r| match r {
+            TestAndDoctestFail(reasons) => Cow::Owned(TestFail(reasons.tests.clone())),
+            r => Cow::Borrowed(r),
+        })
+    };
+    let (t1, t2) = (tests_result(r1), tests_result(r2));
+
     match (r1, r2) {
-        (Some(res1), Some(res2)) => match (res1, res2) {
+        (Some(_), Some(_)) if overridden => Comparison::Overridden,
+        (Some(_), Some(_)) => match (t1.as_deref().unwrap(), t2.as_deref().unwrap()) {
             // ICE -> ICE is not a regression, but anything else to an ICE is.
             (BuildFail(FailureReason::ICE), BuildFail(FailureReason::ICE)) => {
                 Comparison::SameBuildFail
@@ -488,18 +1027,19 @@ fn compare(
 
             (BuildFail(_), BuildFail(_)) => Comparison::SameBuildFail,
             (TestFail(_), TestFail(_)) => Comparison::SameTestFail,
+            (DoctestFail(_), DoctestFail(_)) => Comparison::SameDoctestFail,
             (TestSkipped, TestSkipped) => Comparison::SameTestSkipped,
             (TestPass, TestPass) => Comparison::SameTestPass,
 
             (BuildFail(ref reason1), TestFail(ref reason2))
-                if reason1.is_spurious() || reason2.is_spurious() =>
+                if is_spurious(reason1) || is_spurious(reason2) =>
             {
                 Comparison::SpuriousFixed
             }
             (BuildFail(ref reason), TestSkipped)
             | (BuildFail(ref reason), TestPass)
             | (TestFail(ref reason), TestPass)
-                if reason.is_spurious() =>
+                if is_spurious(reason) =>
             {
                 Comparison::SpuriousFixed
             }
@@ -507,9 +1047,9 @@ fn compare(
             | (BuildFail(_), TestSkipped)
             | (BuildFail(_), TestPass)
             | (TestFail(_), TestPass) => Comparison::Fixed,
-            (TestFail(_), BuildFail(reason)) if !reason.is_spurious() => Comparison::Regressed,
+            (TestFail(_), BuildFail(reason)) if !is_spurious(reason) => Comparison::Regressed,
             (TestFail(reason1), BuildFail(reason2))
-                if reason1.is_spurious() || reason2.is_spurious() =>
+                if is_spurious(reason1) || is_spurious(reason2) =>
             {
                 Comparison::SpuriousRegressed
             }
@@ -517,7 +1057,7 @@ fn compare(
             | (TestPass, BuildFail(reason))
             | (TestSkipped, BuildFail(reason))
             | (TestFail(_), BuildFail(reason))
-                if reason.is_spurious() =>
+                if is_spurious(reason) =>
             {
                 Comparison::SpuriousRegressed
             }
@@ -526,15 +1066,73 @@ fn compare(
             | (TestSkipped, BuildFail(_))
             | (TestFail(_), BuildFail(_)) => Comparison::Regressed,
 
+            // Doctest failures are less severe than any other failure.
+            (BuildFail(ref reason1), DoctestFail(ref reason2))
+            | (TestFail(ref reason1), DoctestFail(ref reason2))
+                if is_spurious(reason1) || is_spurious(reason2) =>
+            {
+                Comparison::SpuriousFixed
+            }
+            (DoctestFail(ref reason), TestPass) if is_spurious(reason) => Comparison::SpuriousFixed,
+            (BuildFail(_), DoctestFail(_))
+            | (TestFail(_), DoctestFail(_))
+            | (DoctestFail(_), TestPass) => Comparison::Fixed,
+            (DoctestFail(ref reason1), BuildFail(ref reason2))
+            | (DoctestFail(ref reason1), TestFail(ref reason2))
+                if is_spurious(reason1) || is_spurious(reason2) =>
+            {
+                Comparison::SpuriousRegressed
+            }
+            (TestPass, DoctestFail(ref reason)) if is_spurious(reason) => {
+                Comparison::SpuriousRegressed
+            }
+            (DoctestFail(_), BuildFail(_))
+            | (DoctestFail(_), TestFail(_))
+            | (TestPass, DoctestFail(_)) => Comparison::Regressed,
+
+            // Normalized into test failures above
+            (TestAndDoctestFail(_), _) | (_, TestAndDoctestFail(_)) => unreachable!(),
+
             (Error, _) | (_, Error) => Comparison::Error,
-            (Skipped, _) | (_, Skipped) => Comparison::Skipped,
+            (Skipped, _) | (_, Skipped) | (SkippedDeadline, _) | (_, SkippedDeadline) => {
+                Comparison::Skipped
+            }
+
+            // Crates are prepared with the cargo of each toolchain
+            (BrokenCrate(_), BrokenCrate(_)) => Comparison::Broken,
+            (BrokenCrate(_), TestPass) | (BrokenCrate(_), TestSkipped)
+                if policy == ComparisonPolicy::Strict =>
+            {
+                Comparison::Fixed
+            }
+            (_, BrokenCrate(_)) if policy == ComparisonPolicy::Strict => Comparison::Regressed,
             (BrokenCrate(_), _) | (_, BrokenCrate(_)) => Comparison::Broken,
+
+            // Tests are only skipped by one of the toolchains if the configuration changed while
+            // the experiment was running
+            (TestSkipped, TestPass) if policy == ComparisonPolicy::Strict => Comparison::Fixed,
+            (TestSkipped, TestFail(reason)) | (TestSkipped, DoctestFail(reason))
+                if policy == ComparisonPolicy::Strict =>
+            {
+                if is_spurious(reason) {
+                    Comparison::SpuriousRegressed
+                } else {
+                    Comparison::Regressed
+                }
+            }
+            (TestPass, TestSkipped)
+            | (TestFail(_), TestSkipped)
+            | (DoctestFail(_), TestSkipped)
+                if policy == ComparisonPolicy::Strict =>
+            {
+                Comparison::Regressed
+            }
             (TestFail(_), TestSkipped)
+            | (DoctestFail(_), TestSkipped)
+            | (TestSkipped, DoctestFail(_))
             | (TestPass, TestSkipped)
             | (TestSkipped, TestFail(_))
-            | (TestSkipped, TestPass) => {
-                panic!("can't compare {res1} and {res2}");
-            }
+            | (TestSkipped, TestPass) => Comparison::TestSkipChanged,
         },
         _ if config.should_skip(krate) => Comparison::Skipped,
         _ => Comparison::Unknown,
@@ -609,6 +1207,14 @@ impl DummyWriter {
             .unwrap()
             .clone()
     }
+
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.results
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|(p, _)| p == path.as_ref())
+    }
 }
 
 #[cfg(test)]
@@ -647,12 +1253,13 @@ impl Display for DummyWriter {
 mod tests {
     use super::*;
     use crate::config::{Config, CrateConfig};
-    use crate::crates::{Crate, GitHubRepo, RegistryCrate};
-    use crate::dirs::WORK_DIR;
+    use crate::crates::{Crate, ExcludedCrate, ExclusionReason, GitHubRepo, RegistryCrate};
     use crate::experiments::{CapLints, Experiment, Mode, Status};
-    use crate::results::{BrokenReason, DummyDB, FailureReason, TestResult};
+    use crate::results::{
+        BrokenReason, DummyDB, FailureReason, TestResult, TriageAnnotation, TriageStatus,
+    };
     use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
-    use crates_index::GitIndex;
+    use percent_encoding::percent_decode_str;
 
     #[test]
     fn test_crate_to_path_fragment() {
@@ -684,7 +1291,75 @@ mod tests {
         );
         assert_eq!(
             crate_to_path_fragment(&MAIN_TOOLCHAIN, &gt_plus, SanitizationContext::Url),
-            PathBuf::from("stable/reg/foo-%3E1.0%2Bbar")
+            PathBuf::from("stable/reg/foo-%253E1.0%2Bbar")
+        );
+    }
+
+    #[test]
+    fn test_crate_to_url_fragment() {
+        let reg = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        assert_eq!(
+            crate_to_url_fragment(&MAIN_TOOLCHAIN, &reg),
+            "stable/reg/lazy_static-1.0"
+        );
+
+        // Toolchains used by experiments run on Windows agents can contain both separators
+        let windows: Toolchain = "stable+rustflags=-Clink-arg=C:\\libs/foo.lib"
+            .parse()
+            .unwrap();
+        let fragment = crate_to_url_fragment(&windows, &reg);
+        assert_eq!(fragment.split('/').count(), 3);
+        assert!(!fragment.contains('\\'));
+        assert_eq!(
+            crate_to_path_fragment(&windows, &reg, SanitizationContext::Path)
+                .components()
+                .count(),
+            3
+        );
+
+        // The URLs point to the keys the files are stored at
+        let gt_plus = Crate::Registry(RegistryCrate {
+            name: "foo".into(),
+            version: ">1.0+bar".into(),
+        });
+        for (tc, krate) in [(&windows, &reg), (&*MAIN_TOOLCHAIN, &gt_plus)] {
+            let url = crate_to_url_fragment(tc, krate);
+            let key = path_to_key(&crate_to_path_fragment(
+                tc,
+                krate,
+                SanitizationContext::Path,
+            ));
+            assert_eq!(percent_decode_str(&url).decode_utf8().unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_path_to_key() {
+        let path = crate_to_path_fragment(
+            &MAIN_TOOLCHAIN,
+            &Crate::Local("foo".into()),
+            SanitizationContext::Path,
+        )
+        .join("log.txt");
+        assert_eq!(path_to_key(&path), "stable/local/foo/log.txt");
+    }
+
+    #[test]
+    fn test_log_url() {
+        let reg = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        assert_eq!(
+            log_url(
+                "https://crater-reports.s3.amazonaws.com/pr-1/index.html",
+                &MAIN_TOOLCHAIN,
+                &reg
+            ),
+            "https://crater-reports.s3.amazonaws.com/pr-1/stable/reg/lazy_static-1.0/log.txt"
         );
     }
 
@@ -734,21 +1409,11 @@ mod tests {
         };
         let gh = Crate::GitHub(repo);
 
-        let index = GitIndex::with_path(
-            WORK_DIR.join("crates.io-index"),
-            "https://github.com/rust-lang/crates.io-index",
-        )
-        .unwrap();
+        let versions = IndexVersions::load(&[reg.clone(), yanked.clone(), gh.clone()]).unwrap();
 
-        assert_eq!(
-            get_crate_version_status(&index, &reg).unwrap().unwrap(),
-            CrateVersionStatus::Outdated
-        );
-        assert_eq!(
-            get_crate_version_status(&index, &yanked).unwrap().unwrap(),
-            CrateVersionStatus::Yanked
-        );
-        assert!(get_crate_version_status(&index, &gh).unwrap().is_none());
+        assert_eq!(versions.status(&reg), Some(CrateVersionStatus::Outdated));
+        assert_eq!(versions.status(&yanked), Some(CrateVersionStatus::Yanked));
+        assert!(versions.status(&gh).is_none());
     }
 
     #[test]
@@ -785,17 +1450,22 @@ mod tests {
 
     #[test]
     fn test_compare() {
-        use crate::results::{FailureReason::*, TestResult::*};
+        use crate::results::{FailureReason::*, TestFailures, TestResult::*};
 
         macro_rules! test_compare {
-            ($cmp:ident, $config:expr, $reg:expr, [$($a:expr, $b:expr => $c:ident;)*]) => {
+            (
+                $cmp:ident, $config:expr, $policy:expr, $reg:expr,
+                [$($a:expr, $b:expr => $c:ident;)*]
+            ) => {
                 $(
                     assert_eq!(
                         $cmp(
                             $config,
+                            $policy,
                             $reg,
                             Some(&$a),
                             Some(&$b),
+                            false,
                         ),
                         Comparison::$c
                     );
@@ -812,6 +1482,7 @@ mod tests {
         test_compare!(
             compare,
             &config,
+            ComparisonPolicy::Lenient,
             &reg,
             [
                 BuildFail(Unknown), BuildFail(Unknown) => SameBuildFail;
@@ -829,6 +1500,15 @@ mod tests {
                 TestSkipped, BuildFail(Unknown) => Regressed;
                 TestFail(Unknown), BuildFail(Unknown) => Regressed;
                 TestFail(OOM), BuildFail(Unknown) => Regressed;
+                TestPass, DoctestFail(Unknown) => Regressed;
+                DoctestFail(Unknown), TestFail(Unknown) => Regressed;
+                TestFail(Unknown), DoctestFail(Unknown) => Fixed;
+                DoctestFail(Unknown), DoctestFail(OOM) => SameDoctestFail;
+                TestPass, DoctestFail(OOM) => SpuriousRegressed;
+                TestPass, TestAndDoctestFail(TestFailures::Unknown) => Regressed;
+                DoctestFail(Unknown), TestAndDoctestFail(TestFailures::Unknown) => Regressed;
+                TestAndDoctestFail(TestFailures::Unknown), TestFail(Unknown) => SameTestFail;
+                TestAndDoctestFail(TestFailures::Unknown), TestPass => Fixed;
 
                 // ICE is special
                 BuildFail(Unknown), BuildFail(ICE) => Regressed;
@@ -845,6 +1525,8 @@ mod tests {
                 TestPass, BuildFail(OOM) => SpuriousRegressed;
                 TestSkipped, BuildFail(OOM) => SpuriousRegressed;
                 TestFail(Unknown), BuildFail(OOM) => SpuriousRegressed;
+                TestPass, BuildFail(SystemDependency) => SpuriousRegressed;
+                TestPass, BuildFail(RegistryFailure) => SpuriousRegressed;
 
                 // Errors
                 Error, TestPass => Error;
@@ -866,6 +1548,9 @@ mod tests {
                 TestSkipped, Skipped => Skipped;
                 TestFail(Unknown), Skipped => Skipped;
                 BuildFail(Unknown), Skipped => Skipped;
+                SkippedDeadline, TestPass => Skipped;
+                TestPass, SkippedDeadline => Skipped;
+                SkippedDeadline, SkippedDeadline => Skipped;
 
 
                 // Broken
@@ -877,10 +1562,54 @@ mod tests {
                 TestSkipped, BrokenCrate(BrokenReason::Unknown) => Broken;
                 TestFail(Unknown), BrokenCrate(BrokenReason::Unknown) => Broken;
                 BuildFail(Unknown), BrokenCrate(BrokenReason::Unknown) => Broken;
+
+                // Tests skipped by only one toolchain
+                TestPass, TestSkipped => TestSkipChanged;
+                TestSkipped, TestFail(Unknown) => TestSkipChanged;
             ]
         );
 
-        assert_eq!(compare(&config, &reg, None, None), Comparison::Unknown);
+        test_compare!(
+            compare,
+            &config,
+            ComparisonPolicy::Strict,
+            &reg,
+            [
+                TestPass, TestFail(Unknown) => Regressed;
+                TestPass, BuildFail(CompilerDiagnosticChange) => SpuriousRegressed;
+                TestPass, TestSkipped => Regressed;
+                TestFail(Unknown), TestSkipped => Regressed;
+                TestSkipped, TestPass => Fixed;
+                TestSkipped, TestFail(Unknown) => Regressed;
+                TestSkipped, DoctestFail(OOM) => SpuriousRegressed;
+                TestPass, BrokenCrate(BrokenReason::CargoToml) => Regressed;
+                BrokenCrate(BrokenReason::CargoToml), TestPass => Fixed;
+                BrokenCrate(BrokenReason::CargoToml), TestSkipped => Fixed;
+                BrokenCrate(BrokenReason::CargoToml), BuildFail(Unknown) => Broken;
+                BrokenCrate(BrokenReason::CargoToml), TestFail(Unknown) => Broken;
+                BrokenCrate(BrokenReason::Unknown), BrokenCrate(BrokenReason::Yanked) => Broken;
+                Error, BrokenCrate(BrokenReason::Unknown) => Error;
+            ]
+        );
+
+        test_compare!(
+            compare,
+            &config,
+            ComparisonPolicy::LintExperiment,
+            &reg,
+            [
+                TestPass, BuildFail(CompilerDiagnosticChange) => Regressed;
+                BuildFail(CompilerDiagnosticChange), TestPass => Fixed;
+                TestPass, BuildFail(OOM) => SpuriousRegressed;
+                TestPass, TestSkipped => TestSkipChanged;
+                TestPass, BrokenCrate(BrokenReason::CargoToml) => Broken;
+            ]
+        );
+
+        assert_eq!(
+            compare(&config, ComparisonPolicy::Lenient, &reg, None, None, false),
+            Comparison::Unknown
+        );
 
         config.crates.insert(
             "lazy_static".into(),
@@ -889,29 +1618,76 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                env: Default::default(),
+                result_override: None,
             },
         );
-        assert_eq!(compare(&config, &reg, None, None), Comparison::Skipped);
+        assert_eq!(
+            compare(&config, ComparisonPolicy::Lenient, &reg, None, None, false),
+            Comparison::Skipped
+        );
+
+        // Only the results the agent recorded as overridden are categorized as such
+        let fail = BuildFail(Unknown);
+        assert_eq!(
+            compare(
+                &config,
+                ComparisonPolicy::Lenient,
+                &reg,
+                Some(&fail),
+                Some(&fail),
+                true,
+            ),
+            Comparison::Overridden
+        );
+        assert_eq!(
+            compare(
+                &config,
+                ComparisonPolicy::Lenient,
+                &reg,
+                Some(&fail),
+                Some(&fail),
+                false,
+            ),
+            Comparison::SameBuildFail
+        );
     }
 
     #[test]
-    fn test_report_generation() {
-        let config = Config::default();
+    fn test_mark_flaky() {
+        use crate::results::TestResult::*;
 
-        let repo = GitHubRepo {
-            org: "brson".into(),
-            name: "hello-rs".into(),
-            sha: Some("f00".into()),
-        };
-        let gh = Crate::GitHub(repo);
-        let reg = Crate::Registry(RegistryCrate {
-            name: "syn".into(),
-            version: "1.0.0".into(),
-        });
+        let pass = Some(&TestPass);
+        let fail = TestFail(FailureReason::Unknown);
+        let build_fail = BuildFail(FailureReason::Unknown);
 
-        let ex = Experiment {
+        assert_eq!(
+            mark_flaky(Comparison::Regressed, pass, Some(&fail), Some(0.5)),
+            Comparison::SpuriousRegressed
+        );
+        assert_eq!(
+            mark_flaky(Comparison::Fixed, Some(&fail), pass, Some(0.5)),
+            Comparison::SpuriousFixed
+        );
+        assert_eq!(
+            mark_flaky(Comparison::Regressed, pass, Some(&fail), Some(0.1)),
+            Comparison::Regressed
+        );
+        assert_eq!(
+            mark_flaky(Comparison::Regressed, pass, Some(&fail), None),
+            Comparison::Regressed
+        );
+        // Build failures are not affected by flaky tests
+        assert_eq!(
+            mark_flaky(Comparison::Regressed, pass, Some(&build_fail), Some(1.0)),
+            Comparison::Regressed
+        );
+    }
+
+    fn experiment(toolchains: [Toolchain; 2]) -> Experiment {
+        Experiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            toolchains,
             mode: Mode::BuildAndTest,
             cap_lints: CapLints::Forbid,
             priority: 0,
@@ -924,7 +1700,43 @@ mod tests {
             report_url: None,
             ignore_blacklist: false,
             requirement: None,
+            follow_up: None,
+            parent: None,
+            sandbox_image: None,
+            zulip_stream: None,
+            repeat: None,
+            test_options: Default::default(),
+            deadline: None,
+            comparison_policy: Default::default(),
+            registry_overlay: Vec::new(),
+            dependency_patches: Vec::new(),
+            exclude: Vec::new(),
+            tags: vec!["beta-1.79".into()],
+            rustc_versions: [None, None],
+            build_cache: false,
+            track_warnings: true,
+            client_side_report: false,
+            exact_versions: false,
+        }
+    }
+
+    #[test]
+    fn test_report_generation() {
+        let mut config = Config::default();
+        config.report.html_logs = true;
+
+        let repo = GitHubRepo {
+            org: "brson".into(),
+            name: "hello-rs".into(),
+            sha: Some("f00".into()),
         };
+        let gh = Crate::GitHub(repo);
+        let reg = Crate::Registry(RegistryCrate {
+            name: "syn".into(),
+            version: "1.0.0".into(),
+        });
+
+        let ex = experiment([MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()]);
 
         let mut db = DummyDB::default();
         db.add_dummy_result(
@@ -976,9 +1788,29 @@ mod tests {
             TEST_TOOLCHAIN.clone(),
             EncodedLog::Plain(b"beta log".to_vec()),
         );
+        let triage = TriageAnnotation {
+            status: TriageStatus::Real,
+            issue: Some("rust-lang/rust#12345".into()),
+            note: None,
+            author: "pietroalbini".into(),
+        };
+        db.add_dummy_triage(&ex, reg.clone(), triage.clone());
+        db.add_dummy_exclusion(
+            &ex,
+            ExcludedCrate {
+                krate: Crate::Registry(RegistryCrate {
+                    name: "gpl".into(),
+                    version: "1.0.0".into(),
+                }),
+                reason: ExclusionReason::License {
+                    license: "GPL-3.0".into(),
+                },
+            },
+        );
 
         let writer = DummyWriter::default();
-        gen(&db, &ex, &[gh, reg], &writer, &config, false).unwrap();
+        let crates = [gh, reg];
+        gen(&db, &ex, &crates, &writer, &config, false, None, None).unwrap();
 
         assert_eq!(
             writer.get("config.json", &mime::APPLICATION_JSON),
@@ -993,10 +1825,15 @@ mod tests {
             &writer.get("beta/gh/brson.hello-rs/log.txt", &mime::TEXT_PLAIN_UTF_8),
             b"beta log"
         );
+        let html = writer.get("beta/gh/brson.hello-rs/log.html", &mime::TEXT_HTML_UTF_8);
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .contains("<span id=\"L1\">beta log</span>"));
 
         let result: RawTestResults =
             serde_json::from_slice(&writer.get("results.json", &mime::APPLICATION_JSON)).unwrap();
 
+        assert_eq!(result.tags, ["beta-1.79"]);
         assert_eq!(result.crates.len(), 2);
         let gh_result = &result.crates[0];
         let reg_result = &result.crates[1];
@@ -1016,12 +1853,12 @@ mod tests {
             TestResult::BuildFail(FailureReason::Unknown)
         );
         assert_eq!(
-            Path::new(gh_result.runs[0].as_ref().unwrap().log.as_str()),
-            Path::new("stable/gh/brson.hello-rs")
+            gh_result.runs[0].as_ref().unwrap().log,
+            "stable/gh/brson.hello-rs"
         );
         assert_eq!(
-            Path::new(gh_result.runs[1].as_ref().unwrap().log.as_str()),
-            Path::new("beta/gh/brson.hello-rs")
+            gh_result.runs[1].as_ref().unwrap().log,
+            "beta/gh/brson.hello-rs"
         );
 
         assert_eq!(reg_result.name.as_str(), "syn-1.0.0");
@@ -1039,17 +1876,231 @@ mod tests {
             TestResult::BuildFail(FailureReason::Unknown)
         );
         assert_eq!(
-            Path::new(reg_result.runs[0].as_ref().unwrap().log.as_str()),
-            Path::new("stable/reg/syn-1.0.0")
+            reg_result.runs[0].as_ref().unwrap().log,
+            "stable/reg/syn-1.0.0"
         );
         assert_eq!(
-            Path::new(reg_result.runs[1].as_ref().unwrap().log.as_str()),
-            Path::new("beta/reg/syn-1.0.0")
+            reg_result.runs[1].as_ref().unwrap().log,
+            "beta/reg/syn-1.0.0"
         );
+        assert_eq!(gh_result.triage, None);
+        assert_eq!(reg_result.triage, Some(triage));
 
         assert_eq!(
             writer.get("retry-regressed-list.txt", &mime::TEXT_PLAIN_UTF_8),
             b"brson/hello-rs\nsyn\n",
         );
+
+        let excluded: Vec<ExcludedCrateResult> =
+            serde_json::from_slice(&writer.get("excluded-crates.json", &mime::APPLICATION_JSON))
+                .unwrap();
+        assert_eq!(
+            excluded,
+            [ExcludedCrateResult {
+                name: "gpl-1.0.0".into(),
+                url: "https://crates.io/crates/gpl/1.0.0".into(),
+                reason: "licensed under `GPL-3.0`".into(),
+            }]
+        );
+        let markdown = String::from_utf8(writer.get("markdown.md", &mime::TEXT_PLAIN)).unwrap();
+        assert!(markdown.contains(
+            "* [gpl-1.0.0](https://crates.io/crates/gpl/1.0.0): licensed under `GPL-3.0`"
+        ));
+
+        // The landing page only links to the pages of the categories
+        let index = String::from_utf8(writer.get("index.html", &mime::TEXT_HTML)).unwrap();
+        assert!(index.contains("regressed.html"));
+        assert!(index.contains("regressed (2)"));
+        assert!(index.contains("excluded.html"));
+        assert!(!index.contains("syn-1.0.0"));
+        let regressed = String::from_utf8(writer.get("regressed.html", &mime::TEXT_HTML)).unwrap();
+        assert!(regressed.contains("syn-1.0.0"));
+        assert!(!regressed.contains("gpl-1.0.0"));
+        let excluded = String::from_utf8(writer.get("excluded.html", &mime::TEXT_HTML)).unwrap();
+        assert!(excluded.contains("gpl-1.0.0"));
+        // The links to the single page of the older reports lead to the landing page
+        let full = String::from_utf8(writer.get("full.html", &mime::TEXT_HTML)).unwrap();
+        assert!(full.contains("url=index.html"));
+
+        // No warnings were recorded, but the experiment tracks them
+        assert!(index.contains("new-warnings.html"));
+        assert_eq!(
+            writer.get("new-warnings.json", &mime::APPLICATION_JSON),
+            b"[]"
+        );
+        let warnings =
+            String::from_utf8(writer.get("new-warnings.html", &mime::TEXT_HTML)).unwrap();
+        assert!(warnings.contains("No lint emits more warnings"));
+
+        // The categories of the other reports are rendered on the server
+        assert!(!writer.contains("results-data.json"));
+        assert!(!writer.contains("report-client.js"));
+    }
+
+    #[test]
+    fn test_client_side_report_generation() {
+        let config = Config::default();
+        let ex = Experiment {
+            client_side_report: true,
+            ..experiment([MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()])
+        };
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let depends_on_root = TestResult::BuildFail(FailureReason::DependsOn(
+            vec![krate("root")].into_iter().collect(),
+        ));
+
+        let mut db = DummyDB::default();
+        let cases = [
+            (
+                "root",
+                TestResult::TestPass,
+                TestResult::BuildFail(FailureReason::Unknown),
+            ),
+            ("dep", TestResult::TestPass, depends_on_root),
+            (
+                "fix",
+                TestResult::BuildFail(FailureReason::Unknown),
+                TestResult::TestPass,
+            ),
+            ("pass", TestResult::TestPass, TestResult::TestPass),
+        ];
+        for (name, tc1, tc2) in cases.clone() {
+            for (tc, res) in ex.toolchains.iter().zip([tc1, tc2]) {
+                db.add_dummy_result(&ex, krate(name), tc.clone(), res);
+                db.add_dummy_log(
+                    &ex,
+                    krate(name),
+                    tc.clone(),
+                    EncodedLog::Plain(b"log".to_vec()),
+                );
+            }
+        }
+
+        let writer = DummyWriter::default();
+        let crates = cases
+            .iter()
+            .map(|(name, _, _)| krate(name))
+            .collect::<Vec<_>>();
+        gen(&db, &ex, &crates, &writer, &config, true, None, None).unwrap();
+
+        // The landing page links to the single page rendering the categories in the browser
+        let index = String::from_utf8(writer.get("index.html", &mime::TEXT_HTML)).unwrap();
+        assert!(index.contains("results.html#regressed"));
+        assert!(index.contains("results.html#fixed"));
+        assert!(!writer.contains("regressed.html"));
+        assert!(!writer.contains("test-pass.html"));
+        assert!(writer.contains("report-client.js"));
+        let page = String::from_utf8(writer.get("results.html", &mime::TEXT_HTML)).unwrap();
+        assert!(page.contains("results-data.json"));
+        let context: serde_json::Value = serde_json::from_slice(
+            &writer.get("results.html.context.json", &mime::APPLICATION_JSON),
+        )
+        .unwrap();
+        assert_eq!(context["data_url"], "results-data.json");
+        assert_eq!(context["crates_count"], 4);
+
+        let data: serde_json::Value =
+            serde_json::from_slice(&writer.get("results-data.json", &mime::APPLICATION_JSON))
+                .unwrap();
+        assert_eq!(
+            data["info"],
+            serde_json::json!({"regressed": 2, "fixed": 1, "test-pass": 1})
+        );
+        assert_eq!(data["log_page"], "log.txt");
+        assert_eq!(data["backports"], serde_json::json!({}));
+
+        // The regressions and fixes are split between the tree of the dependencies and the
+        // results of the root crates, while the other categories are listed as is
+        let categories = data["categories"].as_array().unwrap();
+        let kinds = categories
+            .iter()
+            .map(|category| {
+                let kind = category[2].as_object().unwrap().keys().next().unwrap();
+                format!("{}:{kind}", category[0].as_str().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                "regressed:Tree",
+                "regressed:RootResults",
+                "fixed:Tree",
+                "fixed:RootResults",
+                "test-pass:Plain",
+            ]
+        );
+        let tree = &categories[0][2]["Tree"];
+        assert_eq!(tree["count"], 1);
+        assert_eq!(tree["tree"]["root-1.0.0"][0]["name"], "dep-1.0.0");
+        let roots = &categories[1][2]["RootResults"]["results"];
+        let root = &roots["build failed (unknown)"][0];
+        assert_eq!(root["name"], "root-1.0.0");
+        assert_eq!(root["runs"][0]["log"], "stable/reg/root-1.0.0");
+
+        // The colors and the names of the results are deduplicated, and referenced by index
+        let colors = data["colors"].as_array().unwrap();
+        let names = data["result_names"].as_array().unwrap();
+        assert_eq!(
+            colors[categories[0][1].as_u64().unwrap() as usize],
+            serde_json::json!({"Single": "#db3026"})
+        );
+        assert_eq!(root["color_idx"], categories[0][1]);
+        let run_names = root["runs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|run| &names[run["name_idx"].as_u64().unwrap() as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(run_names, ["test passed", "build failed (unknown)"]);
+        assert_eq!(
+            names.iter().filter(|name| *name == "test passed").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_report_mixed_agents() {
+        // The toolchains of the experiments run on Windows agents contain both separators, and
+        // the names of the logs of some crates must be sanitized
+        let windows: Toolchain = "stable+rustflags=-Clink-arg=C:\\libs/foo.lib"
+            .parse()
+            .unwrap();
+        let ex = experiment([windows, TEST_TOOLCHAIN.clone()]);
+        let krate = Crate::Registry(RegistryCrate {
+            name: "foo".into(),
+            version: ">1.0+bar".into(),
+        });
+
+        let mut db = DummyDB::default();
+        for tc in &ex.toolchains {
+            db.add_dummy_result(&ex, krate.clone(), tc.clone(), TestResult::TestPass);
+            db.add_dummy_log(
+                &ex,
+                krate.clone(),
+                tc.clone(),
+                EncodedLog::Plain(format!("log of {tc}").into_bytes()),
+            );
+        }
+
+        let writer = DummyWriter::default();
+        let config = Config::default();
+        gen(&db, &ex, &[krate], &writer, &config, false, None, None).unwrap();
+
+        // The links of the report point to the logs written for each toolchain
+        let result: RawTestResults =
+            serde_json::from_slice(&writer.get("results.json", &mime::APPLICATION_JSON)).unwrap();
+        for (tc, run) in ex.toolchains.iter().zip(&result.crates[0].runs) {
+            let url = &run.as_ref().unwrap().log;
+            let key = percent_decode_str(url).decode_utf8().unwrap();
+            assert_eq!(
+                writer.get(format!("{key}/log.txt"), &mime::TEXT_PLAIN_UTF_8),
+                format!("log of {tc}").into_bytes()
+            );
+        }
     }
 }
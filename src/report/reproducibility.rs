@@ -0,0 +1,126 @@
+//! Crates whose builds are not reproducible, found by the experiments in the `reproducibility`
+//! mode. The agents build each crate twice with the same toolchain and compare the artifacts,
+//! and this lists the artifacts which differed, for the reproducible-builds project.
+
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{crate_to_name, crate_to_url};
+use crate::results::ReadResults;
+use std::collections::BTreeSet;
+
+/// Crate producing different artifacts with at least one of the toolchains. The artifacts are
+/// missing for the toolchains which failed to build the crate twice.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NonReproducibleCrate {
+    name: String,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<BTreeSet<String>>,
+}
+
+pub(super) fn find_non_reproducible<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<NonReproducibleCrate>> {
+    let mut non_reproducible = Vec::new();
+    for krate in crates {
+        let before = db.load_build_reproducibility(ex, &ex.toolchains[0], krate)?;
+        let after = db.load_build_reproducibility(ex, &ex.toolchains[1], krate)?;
+        let (before, after) = (before.map(|r| r.differing), after.map(|r| r.differing));
+        if before.iter().chain(&after).all(BTreeSet::is_empty) {
+            continue;
+        }
+        non_reproducible.push(NonReproducibleCrate {
+            name: crate_to_name(krate),
+            url: crate_to_url(krate),
+            before,
+            after,
+        });
+    }
+    Ok(non_reproducible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_non_reproducible;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Mode};
+    use crate::results::{BuildReport, BuildReproducibility, DummyDB};
+
+    #[test]
+    fn test_find_non_reproducible() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment {
+            mode: Mode::Reproducibility,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let report = |differing: &[&str]| BuildReport {
+            reproducibility: Some(BuildReproducibility {
+                compared: 2,
+                differing: differing.iter().map(|name| name.to_string()).collect(),
+            }),
+            ..BuildReport::default()
+        };
+
+        let mut results = DummyDB::default();
+        let cases: [(&str, &[_], &[_]); 3] = [
+            ("reproducible", &[], &[]),
+            ("regressed", &[], &["foo"]),
+            ("always", &["libfoo.rlib"], &["libfoo.rlib"]),
+        ];
+        for (name, before, after) in cases {
+            let (tc1, tc2) = (ex.toolchains[0].clone(), ex.toolchains[1].clone());
+            results.add_dummy_report(&ex, krate(name), tc1, report(before));
+            results.add_dummy_report(&ex, krate(name), tc2, report(after));
+        }
+        // The crate failed to build with the first toolchain
+        results.add_dummy_report(
+            &ex,
+            krate("failed"),
+            ex.toolchains[1].clone(),
+            report(&["a"]),
+        );
+
+        let crates = ["reproducible", "regressed", "always", "failed"].map(krate);
+        let non_reproducible = find_non_reproducible(&results, &ex, &crates).unwrap();
+        let summary = non_reproducible
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.before.as_ref().map(|b| b.len()),
+                    c.after.as_ref().map(|a| a.len()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("regressed-1.0.0", Some(0), Some(1)),
+                ("always-1.0.0", Some(1), Some(1)),
+                ("failed-1.0.0", None, Some(1)),
+            ]
+        );
+    }
+}
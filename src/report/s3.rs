@@ -1,5 +1,5 @@
 use crate::prelude::*;
-use crate::report::ReportWriter;
+use crate::report::{path_to_key, ReportWriter};
 use crate::results::EncodingType;
 use aws_sdk_s3::Client as S3Client;
 use mime::Mime;
@@ -87,11 +87,7 @@ impl ReportWriter for S3Writer {
                 .client
                 .create_multipart_upload()
                 .acl(aws_sdk_s3::types::ObjectCannedAcl::PublicRead)
-                .key(format!(
-                    "{}/{}",
-                    self.prefix,
-                    path.as_ref().to_str().unwrap()
-                ))
+                .key(format!("{}/{}", self.prefix, path_to_key(path.as_ref())))
                 .content_type(mime.to_string())
                 .bucket(self.bucket.clone());
             match encoding_type {
@@ -164,11 +160,7 @@ impl ReportWriter for S3Writer {
                     bytes::Bytes::copy_from_slice(body),
                 ))
                 .acl(aws_sdk_s3::types::ObjectCannedAcl::PublicRead)
-                .key(format!(
-                    "{}/{}",
-                    self.prefix,
-                    path.as_ref().to_str().unwrap()
-                ))
+                .key(format!("{}/{}", self.prefix, path_to_key(path.as_ref())))
                 .content_type(mime.to_string())
                 .bucket(self.bucket.clone());
             match encoding_type {
@@ -0,0 +1,132 @@
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{crate_to_name, crate_to_url};
+use crate::results::{BuildMetrics, ReadResults};
+
+// Only report crates whose artifacts grew by more than 10%...
+const SIZE_REGRESSION_THRESHOLD: f64 = 0.1;
+// ...and by at least 64KiB, to avoid flagging tiny crates.
+const SIZE_REGRESSION_MIN_BYTES: u64 = 64 * 1024;
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SizeRegression {
+    name: String,
+    url: String,
+    before: BuildMetrics,
+    after: BuildMetrics,
+    /// Relative growth of the artifacts, in percent.
+    growth: f64,
+}
+
+fn size_growth(before: u64, after: u64) -> Option<f64> {
+    if before == 0 || after < before + SIZE_REGRESSION_MIN_BYTES {
+        return None;
+    }
+    let growth = (after - before) as f64 / before as f64;
+    if growth > SIZE_REGRESSION_THRESHOLD {
+        Some(growth * 100.0)
+    } else {
+        None
+    }
+}
+
+/// Find the crates whose artifacts are significantly bigger when built with the second toolchain
+/// of the experiment, sorted by decreasing growth.
+pub(super) fn find_size_regressions<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<SizeRegression>> {
+    let mut regressions = Vec::new();
+    for krate in crates {
+        let before = db.load_build_metrics(ex, &ex.toolchains[0], krate)?;
+        let after = db.load_build_metrics(ex, &ex.toolchains[1], krate)?;
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+
+        if let Some(growth) = size_growth(before.artifact_size, after.artifact_size) {
+            regressions.push(SizeRegression {
+                name: crate_to_name(krate),
+                url: crate_to_url(krate),
+                before,
+                after,
+                growth,
+            });
+        }
+    }
+
+    regressions.sort_by(|a, b| b.growth.total_cmp(&a.growth));
+    Ok(regressions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_size_regressions, size_growth};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::results::{BuildMetrics, BuildReport, DummyDB};
+
+    #[test]
+    fn test_size_growth() {
+        assert_eq!(size_growth(0, 1024 * 1024), None);
+        assert_eq!(size_growth(1024 * 1024, 1024 * 1024), None);
+        assert_eq!(size_growth(1024 * 1024, 1024), None);
+        // More than 10%, but less than 64KiB
+        assert_eq!(size_growth(1024, 2048), None);
+        // More than 64KiB, but less than 10%
+        assert_eq!(size_growth(1024 * 1024, 1024 * 1024 + 65536), None);
+        assert_eq!(size_growth(1024 * 1024, 2 * 1024 * 1024), Some(100.0));
+    }
+
+    #[test]
+    fn test_find_size_regressions() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let report = |size: u64| BuildReport {
+            metrics: Some(BuildMetrics {
+                units: 1,
+                codegen_time: None,
+                artifact_size: size,
+            }),
+            ..BuildReport::default()
+        };
+
+        let mut results = DummyDB::default();
+        for (name, before, after) in [
+            ("small", 1_000_000, 1_500_000),
+            ("big", 1_000_000, 3_000_000),
+            ("same", 1_000_000, 1_000_000),
+        ] {
+            let (tc1, tc2) = (ex.toolchains[0].clone(), ex.toolchains[1].clone());
+            results.add_dummy_report(&ex, krate(name), tc1, report(before));
+            results.add_dummy_report(&ex, krate(name), tc2, report(after));
+        }
+        results.add_dummy_report(&ex, krate("missing"), ex.toolchains[1].clone(), report(1));
+
+        let crates = ["small", "big", "same", "missing"].map(krate);
+        let regressions = find_size_regressions(&results, &ex, &crates).unwrap();
+        let names = regressions
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["big-1.0.0", "small-1.0.0"]);
+        assert_eq!(regressions[0].growth, 200.0);
+    }
+}
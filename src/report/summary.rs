@@ -0,0 +1,148 @@
+//! Summary of the report posted in the GitHub thread of the experiment once it completes: a TL;DR
+//! line added after the lines the comment always started with, followed by a collapsed section
+//! for each category shown in the summary of the report.
+
+use crate::report::analyzer::ReportCrates;
+use crate::report::{Comparison, CrateResult, TestResults};
+use indexmap::IndexSet;
+use std::fmt::Write;
+
+/// Crates listed in each section, the other ones are only listed in the full report.
+const MAX_CRATES_PER_SECTION: usize = 100;
+
+/// One-line summary of the results, like `12 regressed, 3 fixed, 1000 total`.
+pub fn summary_tldr(res: &TestResults) -> String {
+    let count = |comparison| res.info.get(&comparison).copied().unwrap_or(0);
+    format!(
+        "{} regressed, {} fixed, {} total",
+        count(Comparison::Regressed),
+        count(Comparison::Fixed),
+        res.info.values().sum::<u32>(),
+    )
+}
+
+/// Collapsed section listing the crates of each category shown in the summary, with links to
+/// their logs in the report published at `report_url`.
+pub fn summary_sections(res: &TestResults, report_url: &str) -> Vec<String> {
+    let base_url = report_url.trim_end_matches("index.html");
+    res.categories
+        .iter()
+        .filter(|(comparison, _)| comparison.show_in_summary())
+        .filter_map(|(comparison, crates)| {
+            // Only the root regressions are listed, their dependents are in the full report
+            let crates: Vec<&CrateResult> = match crates {
+                ReportCrates::Plain(crates) => crates.iter().collect(),
+                ReportCrates::Complete { results, .. } => results
+                    .values()
+                    .flatten()
+                    .collect::<IndexSet<_>>()
+                    .into_iter()
+                    .collect(),
+            };
+            if crates.is_empty() {
+                return None;
+            }
+
+            let mut section = format!(
+                "<details>\n<summary>{} ({})</summary>\n\n",
+                comparison,
+                res.info.get(comparison).copied().unwrap_or(0)
+            );
+            for krate in crates.iter().take(MAX_CRATES_PER_SECTION) {
+                write!(section, "* [{}]({})", krate.name, krate.url).unwrap();
+                for (label, run) in ["start", "end"].iter().zip(&krate.runs) {
+                    if let Some(run) = run {
+                        write!(section, " [{label}]({base_url}{}/log.txt)", run.log).unwrap();
+                    }
+                }
+                section.push('\n');
+            }
+            if crates.len() > MAX_CRATES_PER_SECTION {
+                writeln!(
+                    section,
+                    "* ...and {} more in the [full report]({report_url})",
+                    crates.len() - MAX_CRATES_PER_SECTION
+                )
+                .unwrap();
+            }
+            section.push_str("</details>");
+            Some(section)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{summary_sections, summary_tldr, MAX_CRATES_PER_SECTION};
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::report::analyzer::ReportCrates;
+    use crate::report::{BuildTestResult, Comparison, CrateResult, TestResults};
+    use crate::results::{FailureReason, TestResult};
+    use indexmap::IndexMap;
+
+    fn result(name: &str, res: Comparison) -> CrateResult {
+        let krate = Crate::Registry(RegistryCrate {
+            name: name.into(),
+            version: "1.0.0".into(),
+        });
+        CrateResult {
+            name: format!("{name}-1.0.0"),
+            url: format!("https://crates.io/crates/{name}/1.0.0"),
+            krate,
+            status: None,
+            res,
+            runs: [
+                Some(BuildTestResult {
+                    res: TestResult::TestPass,
+                    log: format!("stable/reg/{name}-1.0.0"),
+                }),
+                Some(BuildTestResult {
+                    res: TestResult::BuildFail(FailureReason::Unknown),
+                    log: format!("beta/reg/{name}-1.0.0"),
+                }),
+            ],
+            triage: None,
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let regressed = (0..MAX_CRATES_PER_SECTION + 2)
+            .map(|i| result(&format!("r{i}"), Comparison::Regressed))
+            .collect::<Vec<_>>();
+        let mut categories = IndexMap::new();
+        categories.insert(Comparison::Regressed, ReportCrates::Plain(regressed));
+        categories.insert(
+            Comparison::Fixed,
+            ReportCrates::Plain(vec![result("fixed", Comparison::Fixed)]),
+        );
+        categories.insert(
+            Comparison::SameTestPass,
+            ReportCrates::Plain(vec![result("pass", Comparison::SameTestPass)]),
+        );
+        let info = categories
+            .iter()
+            .map(|(&comparison, crates)| match crates {
+                ReportCrates::Plain(crates) => (comparison, crates.len() as u32),
+                ReportCrates::Complete { .. } => unreachable!(),
+            })
+            .collect();
+        let res = TestResults { categories, info };
+
+        assert_eq!(summary_tldr(&res), "102 regressed, 1 fixed, 104 total");
+
+        let sections = summary_sections(&res, "https://example.com/foo/index.html");
+        assert_eq!(sections.len(), 2);
+        assert!(sections[0].starts_with("<details>\n<summary>regressed (102)</summary>\n\n"));
+        assert!(sections[0].contains(
+            "* [r0-1.0.0](https://crates.io/crates/r0/1.0.0) \
+             [start](https://example.com/foo/stable/reg/r0-1.0.0/log.txt) \
+             [end](https://example.com/foo/beta/reg/r0-1.0.0/log.txt)\n"
+        ));
+        assert!(!sections[0].contains("[r100-1.0.0]"));
+        assert!(sections[0].ends_with(
+            "* ...and 2 more in the [full report](https://example.com/foo/index.html)\n</details>"
+        ));
+        assert!(sections[1].starts_with("<details>\n<summary>fixed (1)</summary>"));
+    }
+}
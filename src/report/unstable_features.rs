@@ -0,0 +1,137 @@
+use crate::db::Database;
+use crate::experiments::{Experiment, Mode};
+use crate::prelude::*;
+use crate::results::DatabaseDB;
+use std::collections::BTreeSet;
+
+/// How many crates used an unstable feature in two experiments.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct FeatureUsageDiff {
+    pub feature: String,
+    pub before: u32,
+    pub after: u32,
+}
+
+impl FeatureUsageDiff {
+    pub fn change(&self) -> i64 {
+        i64::from(self.after) - i64::from(self.before)
+    }
+}
+
+/// Compares the usage of the unstable features between two `unstable-features` experiments run
+/// on different snapshots of the registry, to find the features whose usage is growing or
+/// shrinking. Features are sorted from the most growing to the most shrinking.
+pub fn diff_unstable_features(
+    db: &Database,
+    before: &Experiment,
+    after: &Experiment,
+) -> Fallible<Vec<FeatureUsageDiff>> {
+    for ex in [before, after] {
+        if ex.mode != Mode::UnstableFeatures {
+            bail!(
+                "experiment {} doesn't use the {} mode",
+                ex.name,
+                Mode::UnstableFeatures
+            );
+        }
+    }
+
+    let results = DatabaseDB::new(db);
+    let before = results.unstable_feature_usage(before)?;
+    let after = results.unstable_feature_usage(after)?;
+
+    let features = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+    let mut diff = features
+        .into_iter()
+        .map(|feature| FeatureUsageDiff {
+            feature: feature.clone(),
+            before: before.get(feature).copied().unwrap_or(0),
+            after: after.get(feature).copied().unwrap_or(0),
+        })
+        .collect::<Vec<_>>();
+    // The sort is stable, so features with the same change stay sorted by name
+    diff.sort_by_key(|usage| std::cmp::Reverse(usage.change()));
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_unstable_features, FeatureUsageDiff};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Mode};
+    use crate::results::{BuildReport, DatabaseDB, TestResult};
+    use crate::runner::RecordProgress;
+
+    #[test]
+    fn test_diff_unstable_features() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        for name in ["last-month", "today"] {
+            CreateExperiment {
+                mode: Mode::UnstableFeatures,
+                ..CreateExperiment::dummy(name)
+            }
+            .apply(&ctx)
+            .unwrap();
+        }
+        let before = Experiment::get(&db, "last-month").unwrap().unwrap();
+        let after = Experiment::get(&db, "today").unwrap().unwrap();
+
+        let crates = before.get_crates(&db).unwrap();
+        let results = DatabaseDB::new(&db);
+        let record = |ex: &Experiment, krate: usize, features: &[&str]| {
+            let report = BuildReport {
+                unstable_features: Some(features.iter().map(|f| f.to_string()).collect()),
+                ..BuildReport::default()
+            };
+            for tc in &ex.toolchains {
+                results
+                    .record_progress(
+                        ex,
+                        &crates[krate],
+                        tc,
+                        b"log",
+                        &TestResult::TestPass,
+                        &report,
+                        None,
+                    )
+                    .unwrap();
+            }
+        };
+        record(&before, 0, &["never_type", "specialization"]);
+        record(&before, 1, &["specialization"]);
+        record(&after, 0, &["never_type", "let_chains"]);
+        record(&after, 1, &["never_type"]);
+
+        assert_eq!(
+            diff_unstable_features(&db, &before, &after).unwrap(),
+            [
+                FeatureUsageDiff {
+                    feature: "let_chains".into(),
+                    before: 0,
+                    after: 1,
+                },
+                FeatureUsageDiff {
+                    feature: "never_type".into(),
+                    before: 1,
+                    after: 2,
+                },
+                FeatureUsageDiff {
+                    feature: "specialization".into(),
+                    before: 2,
+                    after: 0,
+                },
+            ]
+        );
+
+        // Only the experiments looking for the features can be compared
+        CreateExperiment::dummy("build").apply(&ctx).unwrap();
+        let build = Experiment::get(&db, "build").unwrap().unwrap();
+        assert!(diff_unstable_features(&db, &before, &build).is_err());
+    }
+}
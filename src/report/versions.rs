@@ -0,0 +1,146 @@
+use crate::crates::lists::open_index;
+use crate::crates::Crate;
+use crate::prelude::*;
+use crate::report::CrateVersionStatus;
+use std::collections::{BTreeSet, HashMap};
+use std::thread;
+
+/// Maximum number of threads looking up crates in the index at the same time.
+const MAX_LOOKUP_THREADS: usize = 8;
+
+/// Versions of the tested crates published in the crates.io index, looked up once per report.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct IndexVersions {
+    /// Versions of each crate from the oldest to the newest, with whether they're yanked.
+    crates: HashMap<String, Vec<(String, bool)>>,
+}
+
+impl IndexVersions {
+    /// Look up the versions of the registry crates in the local copy of the index, spreading
+    /// the lookups across multiple threads.
+    pub(super) fn load(crates: &[Crate]) -> Fallible<Self> {
+        let names = crates
+            .iter()
+            .filter_map(|krate| match krate {
+                Crate::Registry(krate) => Some(krate.name.as_str()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Ok(IndexVersions::default());
+        }
+
+        // Make sure the index is cloned before the threads open it
+        open_index()?;
+
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_LOOKUP_THREADS);
+        let chunk_size = names.len().div_ceil(threads);
+        let crates = thread::scope(|scope| {
+            let handles = names
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || -> Fallible<Vec<_>> {
+                        let index = open_index()?;
+                        Ok(chunk
+                            .iter()
+                            .filter_map(|name| {
+                                let krate = index.crate_(name)?;
+                                let versions = krate
+                                    .versions()
+                                    .iter()
+                                    .map(|v| (v.version().to_string(), v.is_yanked()))
+                                    .collect::<Vec<_>>();
+                                Some((name.to_string(), versions))
+                            })
+                            .collect())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let mut crates = HashMap::new();
+            for handle in handles {
+                crates.extend(handle.join().unwrap()?);
+            }
+            Ok::<_, anyhow::Error>(crates)
+        })?;
+
+        Ok(IndexVersions { crates })
+    }
+
+    /// Status of the tested version of the crate. Only registry crates have a status.
+    pub(super) fn status(&self, krate: &Crate) -> Option<CrateVersionStatus> {
+        let Crate::Registry(krate) = krate else {
+            return None;
+        };
+        let Some(versions) = self.crates.get(&krate.name) else {
+            return Some(CrateVersionStatus::MissingFromIndex);
+        };
+
+        let outdated = versions
+            .last()
+            .is_some_and(|(latest, _)| *latest != krate.version);
+        Some(
+            match versions.iter().rev().find(|(v, _)| *v == krate.version) {
+                Some((_, true)) => CrateVersionStatus::Yanked,
+                Some(_) if outdated => CrateVersionStatus::Outdated,
+                Some(_) => CrateVersionStatus::UpToDate,
+                None => CrateVersionStatus::MissingFromIndex,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexVersions;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::report::CrateVersionStatus;
+
+    #[test]
+    fn test_status() {
+        let mut versions = IndexVersions::default();
+        versions.crates.insert(
+            "foo".into(),
+            vec![
+                ("0.1.0".into(), false),
+                ("0.2.0".into(), true),
+                ("0.3.0".into(), false),
+            ],
+        );
+        let foo = |version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: "foo".into(),
+                version: version.into(),
+            })
+        };
+
+        assert_eq!(
+            versions.status(&foo("0.1.0")),
+            Some(CrateVersionStatus::Outdated)
+        );
+        assert_eq!(
+            versions.status(&foo("0.2.0")),
+            Some(CrateVersionStatus::Yanked)
+        );
+        assert_eq!(
+            versions.status(&foo("0.3.0")),
+            Some(CrateVersionStatus::UpToDate)
+        );
+        assert_eq!(
+            versions.status(&foo("0.4.0")),
+            Some(CrateVersionStatus::MissingFromIndex)
+        );
+        assert_eq!(
+            versions.status(&Crate::Registry(RegistryCrate {
+                name: "bar".into(),
+                version: "1.0.0".into(),
+            })),
+            Some(CrateVersionStatus::MissingFromIndex)
+        );
+        assert_eq!(versions.status(&Crate::Local("foo".into())), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Warnings introduced by the second toolchain of the experiments tracking them. Crates keep
+//! building when a lint starts firing, so this is how lint authors preview the impact of a lint
+//! before it's promoted to deny-by-default.
+
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{crate_to_name, crate_to_url};
+use crate::results::ReadResults;
+use std::collections::BTreeMap;
+
+/// Crate emitting more warnings of a lint with the second toolchain.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NewWarningsCrate {
+    name: String,
+    url: String,
+    before: u32,
+    after: u32,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NewWarnings {
+    lint: String,
+    crates: Vec<NewWarningsCrate>,
+}
+
+/// Find the lints emitting more warnings with the second toolchain, sorted by decreasing number
+/// of affected crates. Only the crates successfully built by both toolchains are compared.
+pub(super) fn find_new_warnings<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<NewWarnings>> {
+    let mut lints: BTreeMap<String, Vec<NewWarningsCrate>> = BTreeMap::new();
+    for krate in crates {
+        let before = db.load_build_warnings(ex, &ex.toolchains[0], krate)?;
+        let after = db.load_build_warnings(ex, &ex.toolchains[1], krate)?;
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+
+        for (lint, &count) in &after.lints {
+            let previous = before.lints.get(lint).copied().unwrap_or(0);
+            if count > previous {
+                lints
+                    .entry(lint.clone())
+                    .or_default()
+                    .push(NewWarningsCrate {
+                        name: crate_to_name(krate),
+                        url: crate_to_url(krate),
+                        before: previous,
+                        after: count,
+                    });
+            }
+        }
+    }
+
+    let mut new_warnings = lints
+        .into_iter()
+        .map(|(lint, crates)| NewWarnings { lint, crates })
+        .collect::<Vec<_>>();
+    // The sort is stable, keeping the lints affecting the same number of crates sorted by name
+    new_warnings.sort_by_key(|warnings| std::cmp::Reverse(warnings.crates.len()));
+    Ok(new_warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_new_warnings;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::results::{BuildReport, BuildWarnings, DummyDB};
+
+    #[test]
+    fn test_find_new_warnings() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment {
+            track_warnings: true,
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        let report = |lints: &[(&str, u32)]| BuildReport {
+            warnings: Some(BuildWarnings {
+                count: 0,
+                lints: lints
+                    .iter()
+                    .map(|(lint, count)| (lint.to_string(), *count))
+                    .collect(),
+            }),
+            ..BuildReport::default()
+        };
+
+        let mut results = DummyDB::default();
+        let cases: [(&str, &[_], &[_]); 3] = [
+            ("new-lint", &[], &[("new_lint", 2)]),
+            (
+                "more",
+                &[("dead_code", 1)],
+                &[("dead_code", 3), ("new_lint", 1)],
+            ),
+            ("fewer", &[("dead_code", 2)], &[("dead_code", 1)]),
+        ];
+        for (name, before, after) in cases {
+            let (tc1, tc2) = (ex.toolchains[0].clone(), ex.toolchains[1].clone());
+            results.add_dummy_report(&ex, krate(name), tc1, report(before));
+            results.add_dummy_report(&ex, krate(name), tc2, report(after));
+        }
+        // The first build failed, so the warnings can't be compared
+        results.add_dummy_report(
+            &ex,
+            krate("failed"),
+            ex.toolchains[1].clone(),
+            report(&[("a", 1)]),
+        );
+
+        let crates = ["new-lint", "more", "fewer", "failed"].map(krate);
+        let new_warnings = find_new_warnings(&results, &ex, &crates).unwrap();
+        let summary = new_warnings
+            .iter()
+            .map(|w| {
+                let crates = w.crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+                (w.lint.as_str(), crates)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("new_lint", vec!["new-lint-1.0.0", "more-1.0.0"]),
+                ("dead_code", vec!["more-1.0.0"]),
+            ]
+        );
+        assert_eq!(
+            (
+                new_warnings[1].crates[0].before,
+                new_warnings[1].crates[0].after
+            ),
+            (1, 3)
+        );
+    }
+}
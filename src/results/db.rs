@@ -1,13 +1,23 @@
-use crate::crates::Crate;
+use crate::crates::{Crate, ExcludedCrate};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::{Experiment, Status};
+use crate::experiments::{ComparisonPolicy, Experiment, Status};
 use crate::prelude::*;
+use crate::results::storage::{delete_logs, log_hash, log_key};
 use crate::results::{
-    DeleteResults, EncodedLog, EncodingType, ReadResults, TestResult, WriteResults,
+    flakiness_scores, BuildCacheStats, BuildDiskUsage, BuildMetrics, BuildReport,
+    BuildReproducibility, BuildWarnings, DeleteResults, EncodedLog, EncodingType, LogStore,
+    ReadResults, TestResult, TestRuns, TriageAnnotation, TriageStatus, WriteResults,
 };
-use crate::toolchain::Toolchain;
+use crate::server::idempotency;
+use crate::toolchain::{RustcVersion, Toolchain};
 use base64::Engine;
+use chrono::{DateTime, Utc};
+use rusqlite::Row;
 use rustwide::logging::{self, LogStorage};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Status of the crates skipped because the deadline of their experiment passed.
+const SKIPPED_DEADLINE_STATUS: &str = "skipped-deadline";
 
 #[derive(Deserialize)]
 pub struct TaskResult {
@@ -16,6 +26,88 @@ pub struct TaskResult {
     pub toolchain: Toolchain,
     pub result: TestResult,
     pub log: String,
+    /// Measurements of the build, missing when the crate wasn't built.
+    #[serde(default)]
+    pub report: BuildReport,
+}
+
+/// A result recorded for a crate by any experiment.
+pub struct CrateHistoryEntry {
+    pub experiment: String,
+    pub krate: Crate,
+    pub toolchain: Toolchain,
+    pub result: TestResult,
+    pub report_url: Option<String>,
+}
+
+/// The results of a crate in an experiment comparing release toolchains.
+pub struct CrateTrendEntry {
+    pub experiment: String,
+    pub created_at: DateTime<Utc>,
+    pub krate: Crate,
+    pub toolchains: [Toolchain; 2],
+    pub results: [Option<TestResult>; 2],
+    /// Whether both results were forced by an override of the configuration.
+    pub overridden: bool,
+    pub comparison_policy: ComparisonPolicy,
+}
+
+/// The first toolchain of the ongoing streak of failures of a crate.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FailingSince {
+    pub experiment: String,
+    pub toolchain: Toolchain,
+}
+
+/// Whether the result is a failure to build or test the crate.
+fn is_failure(result: &TestResult) -> bool {
+    matches!(
+        result,
+        TestResult::BuildFail(_)
+            | TestResult::TestFail(_)
+            | TestResult::DoctestFail(_)
+            | TestResult::TestAndDoctestFail(_)
+    )
+}
+
+/// Find since when the crate has been failing, given its trend sorted from the most recent
+/// experiment. Returns `None` if the most recent result is not a failure.
+pub fn failing_since(trend: &[CrateTrendEntry]) -> Option<FailingSince> {
+    // Start toolchains are older than the end toolchains of the same experiment
+    let chronological = trend.iter().rev().flat_map(|entry| {
+        entry
+            .toolchains
+            .iter()
+            .zip(entry.results.iter())
+            .filter_map(move |(tc, res)| res.as_ref().map(|res| (entry, tc, res)))
+    });
+
+    let mut since = None;
+    for (entry, toolchain, result) in chronological {
+        if !is_failure(result) {
+            since = None;
+        } else if since.is_none() {
+            since = Some(FailingSince {
+                experiment: entry.experiment.clone(),
+                toolchain: toolchain.clone(),
+            });
+        }
+    }
+    since
+}
+
+/// Build the conditions matching the IDs of all the versions of a registry crate (like `serde`)
+/// or of a GitHub repository (like `rust-lang/crater`), returning the exact ID and the range.
+fn crate_id_range(name: &str) -> (String, String, String) {
+    let exact = if name.contains('/') {
+        format!("gh/{name}")
+    } else {
+        format!("reg/{name}")
+    };
+    // Match the crate IDs starting with `{exact}/` with a range, as that allows to use the
+    // index on the crate column. `0` is the character following `/`.
+    let (start, end) = (format!("{exact}/"), format!("{exact}0"));
+    (exact, start, end)
 }
 
 #[derive(Deserialize)]
@@ -26,11 +118,39 @@ pub struct ProgressData {
 
 pub struct DatabaseDB<'a> {
     db: &'a Database,
+    log_store: Option<&'a dyn LogStore>,
+    agent: Option<&'a str>,
+    idempotency_key: Option<&'a str>,
 }
 
 impl<'a> DatabaseDB<'a> {
     pub fn new(db: &'a Database) -> Self {
-        DatabaseDB { db }
+        DatabaseDB {
+            db,
+            log_store: None,
+            agent: None,
+            idempotency_key: None,
+        }
+    }
+
+    /// Store the logs in the provided object storage instead of the database, which will only
+    /// contain the key of the object and the hash of its content.
+    pub fn with_log_store(mut self, log_store: Option<&'a dyn LogStore>) -> Self {
+        self.log_store = log_store;
+        self
+    }
+
+    /// Record the agent which produced the results being stored.
+    pub fn with_agent(mut self, agent: &'a str) -> Self {
+        self.agent = Some(agent);
+        self
+    }
+
+    /// Record the idempotency key of the upload along with the result it contains, ignoring the
+    /// result if the key was already recorded for the agent.
+    pub fn with_idempotency_key(mut self, key: Option<&'a str>) -> Self {
+        self.idempotency_key = key;
+        self
     }
 
     pub fn clear_stale_records(&self) -> Fallible<()> {
@@ -57,14 +177,26 @@ impl<'a> DatabaseDB<'a> {
         // The query here would be simpler if rusqlite came with delete .. limit
         // support compiled in, but that's not likely to happen (see
         // https://github.com/rusqlite/rusqlite/issues/1111).
-        self.db.execute(
-            "delete from results where rowid in (
-                select rowid from results where \
+        //
+        // The logs kept outside of the database are removed once the rows referencing them are
+        // gone, so that a failure can't leave results pointing to missing logs.
+        let log_keys = self.db.transaction(true, |t| {
+            let rows = t.query(
+                "select rowid, log_key from results where \
                     experiment in (select name from experiments where status = 'completed') \
-                    limit 100
-                )",
-            &[],
-        )?;
+                    limit 100",
+                [],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?)),
+            )?;
+            for (rowid, _) in &rows {
+                t.execute("delete from results where rowid = ?1", &[rowid])?;
+            }
+            Ok(rows
+                .into_iter()
+                .filter_map(|(_, key)| key)
+                .collect::<Vec<_>>())
+        })?;
+        delete_logs(self.log_store, &log_keys);
         self.db.execute(
             "delete from experiment_crates where rowid in (
                 select rowid from experiment_crates where \
@@ -77,13 +209,48 @@ impl<'a> DatabaseDB<'a> {
         Ok(())
     }
 
+    /// Store the result uploaded by an agent, returning whether it changed the recorded results.
+    /// The retries of an upload which was already recorded don't change them.
     pub fn store(
         &self,
         ex: &Experiment,
         data: &ProgressData,
         encoding_type: EncodingType,
-    ) -> Fallible<()> {
-        let krate = if let Some((old, new)) = &data.version {
+    ) -> Fallible<bool> {
+        let krate = match &data.version {
+            Some((_, new)) => new,
+            None => &data.result.krate,
+        };
+        let log = base64::engine::general_purpose::STANDARD
+            .decode(&data.result.log)
+            .with_context(|| "invalid base64 log provided")?;
+        let log = EncodedLog::from_plain_slice(&log, encoding_type)?;
+
+        // The key is only recorded if the result is, so that the retries of an upload which
+        // failed to be recorded aren't mistaken for duplicates
+        let stored = self.db.transaction(true, |t| {
+            if let (Some(agent), Some(key)) = (self.agent, self.idempotency_key) {
+                if !idempotency::claim(t, agent, key)? {
+                    return Ok(false);
+                }
+            }
+            let changes = self.insert_into_results(
+                t,
+                ex,
+                krate,
+                &data.result.toolchain,
+                &data.result.result,
+                &data.result.report,
+                log,
+            )?;
+            Ok(changes > 0)
+        })?;
+        if !stored {
+            debug!("ignoring duplicate result of {} for {}", krate, ex.name);
+            return Ok(false);
+        }
+
+        if let Some((old, new)) = &data.version {
             // If we're updating the name of the crate (typically changing the hash we found on
             // github) then we ought to also use that new name for marking the crate as complete.
             // Otherwise, we leave behind the old (unversioned) name and end up running this crate
@@ -92,32 +259,41 @@ impl<'a> DatabaseDB<'a> {
 
             // sanity check that the previous name of the crate is the one we intended to run.
             if old.id() != data.result.krate.id() {
-                log::warn!(
+                warn!(
                     "Storing result under {} despite job intended for {} (with wrong name old={})",
                     new.id(),
                     data.result.krate.id(),
                     old.id(),
                 );
             }
+        }
 
-            new
-        } else {
-            &data.result.krate
-        };
-
-        self.store_result(
-            ex,
-            krate,
-            &data.result.toolchain,
-            &data.result.result,
-            &base64::engine::general_purpose::STANDARD
-                .decode(&data.result.log)
-                .with_context(|| "invalid base64 log provided")?,
-            encoding_type,
-        )?;
-
+        self.quarantine_suspicious_build(ex, krate, &data.result.report)?;
         self.mark_crate_as_completed(ex, krate)?;
 
+        Ok(true)
+    }
+
+    /// Quarantine the crate until a human reviews it when the agent detected suspicious behavior
+    /// while building it.
+    fn quarantine_suspicious_build(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        report: &BuildReport,
+    ) -> Fallible<()> {
+        let Some(quarantine) = &report.quarantine else {
+            return Ok(());
+        };
+        if crate::crates::quarantine::quarantine(self.db, krate, &ex.name, &quarantine.reason)? {
+            crate::server::audit::record(
+                self.db,
+                "crater",
+                "quarantine-crate",
+                Some(&ex.name),
+                Some(&format!("{}: {}", krate.id(), quarantine.reason)),
+            )?;
+        }
         Ok(())
     }
 
@@ -135,42 +311,416 @@ impl<'a> DatabaseDB<'a> {
         krate: &Crate,
         toolchain: &Toolchain,
         res: &TestResult,
+        report: &BuildReport,
         log: &[u8],
         desired_encoding_type: EncodingType,
     ) -> Fallible<()> {
         let encoded_log = EncodedLog::from_plain_slice(log, desired_encoding_type)?;
-        self.insert_into_results(ex, krate, toolchain, res, encoded_log)?;
+        self.insert_into_results(self.db, ex, krate, toolchain, res, report, encoded_log)?;
         Ok(())
     }
 
+    /// Insert the result through `db`, which can be a transaction the result is part of.
+    #[allow(clippy::too_many_arguments)]
     fn insert_into_results(
         &self,
+        db: &impl QueryUtils,
         ex: &Experiment,
         krate: &Crate,
         toolchain: &Toolchain,
         res: &TestResult,
+        report: &BuildReport,
         log: EncodedLog,
     ) -> Fallible<usize> {
-        log::info!(
+        info!(
             "insert {krate} for ex={ex:?} with tc={toolchain}; result={res:?}",
             krate = krate.id(),
             ex = &ex.name
         );
-        self.db.execute(
-            "INSERT INTO results (experiment, crate, toolchain, result, log, encoding) \
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        let (log_blob, log_key, log_hash) = if let Some(store) = self.log_store {
+            let key = log_key(ex, toolchain, krate);
+            store.put(&key, log.as_slice())?;
+            (&[][..], Some(key), Some(log_hash(log.as_slice())))
+        } else {
+            (log.as_slice(), None, None)
+        };
+        let build_metrics = report
+            .metrics
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let test_runs = report
+            .test_runs
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let disk_usage = report
+            .disk_usage
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let build_cache_stats = report
+            .cache_stats
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let build_warnings = report
+            .warnings
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let build_reproducibility = report
+            .reproducibility
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        if let Some(features) = &report.unstable_features {
+            store_unstable_features(db, ex, krate, features)?;
+        }
+        let build_time = report.build_time;
+        if let Some(time) = build_time {
+            record_build_time(db, krate, time)?;
+        }
+        db.execute(
+            "INSERT INTO results \
+             (experiment, crate, toolchain, result, log, encoding, log_key, log_hash, \
+             build_metrics, test_runs, disk_usage, agent, build_time, recorded_at, \
+             build_cache_stats, build_warnings, build_reproducibility, overridden) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, \
+             ?17, ?18);",
             &[
                 &ex.name,
                 &krate.id(),
                 &toolchain.to_string(),
                 &res.to_string(),
-                &log.as_slice(),
+                &log_blob,
                 &log.get_encoding_type().to_str(),
+                &log_key,
+                &log_hash,
+                &build_metrics,
+                &test_runs,
+                &disk_usage,
+                &self.agent,
+                &build_time,
+                &Utc::now(),
+                &build_cache_stats,
+                &build_warnings,
+                &build_reproducibility,
+                &report.result_override.is_some(),
             ],
         )
     }
 }
 
+/// Update the average build time of the crate across experiments, used to schedule its next
+/// builds.
+fn record_build_time(db: &impl QueryUtils, krate: &Crate, time: f64) -> Fallible<()> {
+    db.execute(
+        "INSERT INTO build_times (crate, builds, average_time) VALUES (?1, 1, ?2) \
+         ON CONFLICT (crate) DO UPDATE SET builds = builds + 1, \
+         average_time = average_time + (excluded.average_time - average_time) / (builds + 1);",
+        &[&krate.unversioned_id(), &time],
+    )?;
+    Ok(())
+}
+
+/// Persist the unstable features used by the crate. Both toolchains find the same features, as
+/// they only depend on the source of the crate.
+fn store_unstable_features(
+    db: &impl QueryUtils,
+    ex: &Experiment,
+    krate: &Crate,
+    features: &BTreeSet<String>,
+) -> Fallible<()> {
+    for feature in features {
+        db.execute(
+            "INSERT OR IGNORE INTO unstable_features (experiment, crate, feature) \
+             VALUES (?1, ?2, ?3);",
+            &[&ex.name, &krate.id(), feature],
+        )?;
+    }
+    Ok(())
+}
+
+impl DatabaseDB<'_> {
+    /// The number of crates using each unstable feature in the experiment.
+    pub fn unstable_feature_usage(&self, ex: &Experiment) -> Fallible<HashMap<String, u32>> {
+        Ok(self
+            .db
+            .query(
+                "SELECT feature, COUNT(*) AS crates FROM unstable_features \
+                 WHERE experiment = ?1 GROUP BY feature;",
+                [&ex.name],
+                |row| Ok((row.get("feature")?, row.get("crates")?)),
+            )?
+            .into_iter()
+            .collect())
+    }
+
+    /// Store the triage annotation of a crate, removing the existing one if `None` is provided.
+    pub fn store_triage(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        triage: Option<&TriageAnnotation>,
+    ) -> Fallible<()> {
+        if let Some(triage) = triage {
+            self.db.execute(
+                "INSERT INTO triage_annotations \
+                 (experiment, crate, status, issue, note, author, updated_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                &[
+                    &ex.name,
+                    &krate.id(),
+                    &triage.status.to_str(),
+                    &triage.issue,
+                    &triage.note,
+                    &triage.author,
+                    &Utc::now(),
+                ],
+            )?;
+        } else {
+            self.db.execute(
+                "DELETE FROM triage_annotations WHERE experiment = ?1 AND crate = ?2;",
+                &[&ex.name, &krate.id()],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Load all the triage annotations of an experiment, keyed by the crate ID.
+    pub fn load_all_triage(&self, ex: &Experiment) -> Fallible<Vec<(String, TriageAnnotation)>> {
+        self.db.query(
+            "SELECT * FROM triage_annotations WHERE experiment = ?1 ORDER BY crate;",
+            [&ex.name],
+            |row| Ok((row.get("crate")?, triage_from_row(row)?)),
+        )
+    }
+
+    /// Load all the results recorded so far for an experiment, keyed by the crate ID and the
+    /// toolchain.
+    pub fn load_all_results(
+        &self,
+        ex: &Experiment,
+    ) -> Fallible<HashMap<(String, String), TestResult>> {
+        let rows = self.db.query(
+            "SELECT crate, toolchain, result FROM results WHERE experiment = ?1;",
+            [&ex.name],
+            |row| {
+                Ok((
+                    row.get::<_, String>("crate")?,
+                    row.get::<_, String>("toolchain")?,
+                    row.get::<_, String>("result")?,
+                ))
+            },
+        )?;
+
+        rows.into_iter()
+            .map(|(krate, tc, result)| Ok(((krate, tc), result.parse()?)))
+            .collect()
+    }
+
+    /// Load the IDs of the crates of the experiment whose recorded results were all forced by an
+    /// override of the configuration.
+    pub fn load_overridden_crates(&self, ex: &Experiment) -> Fallible<HashSet<String>> {
+        Ok(self
+            .db
+            .query(
+                "SELECT crate FROM results WHERE experiment = ?1 \
+                 GROUP BY crate HAVING MIN(overridden) = 1;",
+                [&ex.name],
+                |row| row.get::<_, String>("crate"),
+            )?
+            .into_iter()
+            .collect())
+    }
+
+    /// Record the crates of the experiment not tested yet as skipped because its deadline passed,
+    /// returning how many crates were skipped. All of them are recorded in a single transaction,
+    /// so that the experiment is never left with part of them skipped.
+    pub fn skip_crates_past_deadline(&self, ex: &Experiment) -> Fallible<usize> {
+        let log = b"crate skipped: the deadline of the experiment passed";
+
+        // Agents can record results while the crates are skipped, so the results which are
+        // missing are only looked for by the statements inserting them
+        self.db.transaction(true, |t| {
+            for tc in &ex.toolchains {
+                t.execute(
+                    "INSERT INTO results (experiment, crate, toolchain, result, log, encoding, \
+                     recorded_at) \
+                     SELECT ?1, crate, ?2, ?3, ?4, ?5, ?6 FROM experiment_crates \
+                     WHERE experiment = ?1 AND skipped = 0 AND status NOT IN (?7, ?8) \
+                     AND NOT EXISTS (SELECT 1 FROM results WHERE results.experiment = ?1 \
+                     AND results.crate = experiment_crates.crate AND results.toolchain = ?2);",
+                    &[
+                        &ex.name,
+                        &tc.to_string(),
+                        &TestResult::SkippedDeadline.to_string(),
+                        &log.as_slice(),
+                        &EncodingType::Plain.to_str(),
+                        &Utc::now(),
+                        &Status::Completed.to_str(),
+                        &SKIPPED_DEADLINE_STATUS,
+                    ],
+                )?;
+            }
+            t.execute(
+                "UPDATE experiment_crates SET status = ?1 \
+                 WHERE experiment = ?2 AND skipped = 0 AND status NOT IN (?3, ?1);",
+                &[
+                    &SKIPPED_DEADLINE_STATUS,
+                    &ex.name,
+                    &Status::Completed.to_str(),
+                ],
+            )
+        })
+    }
+
+    /// Count the crates of the experiment skipped because its deadline passed.
+    pub fn count_skipped_past_deadline(&self, ex: &Experiment) -> Fallible<u32> {
+        Ok(self
+            .db
+            .get_row(
+                "SELECT COUNT(*) FROM experiment_crates WHERE experiment = ?1 AND status = ?2;",
+                [&ex.name.as_str(), &SKIPPED_DEADLINE_STATUS],
+                |row| row.get(0),
+            )?
+            .unwrap_or(0))
+    }
+
+    /// Load the most recent results of all the versions of a registry crate (like `serde`) or of
+    /// a GitHub repository (like `rust-lang/crater`) across every experiment, or across the
+    /// experiments with the tag.
+    pub fn crate_history(
+        &self,
+        name: &str,
+        tag: Option<&str>,
+        limit: u32,
+    ) -> Fallible<Vec<CrateHistoryEntry>> {
+        let (exact, start, end) = crate_id_range(name);
+
+        let rows = self.db.query(
+            "SELECT results.experiment, results.crate, results.toolchain, results.result, \
+             experiments.report_url \
+             FROM results INNER JOIN experiments ON experiments.name = results.experiment \
+             WHERE (results.crate = ?1 OR (results.crate >= ?2 AND results.crate < ?3)) \
+             AND (?5 IS NULL OR EXISTS \
+             (SELECT 1 FROM json_each(experiments.tags) WHERE json_each.value = ?5)) \
+             ORDER BY experiments.created_at DESC, results.crate, results.toolchain \
+             LIMIT ?4;",
+            rusqlite::params![exact, start, end, limit, tag],
+            |row| {
+                Ok((
+                    row.get::<_, String>("experiment")?,
+                    row.get::<_, String>("crate")?,
+                    row.get::<_, String>("toolchain")?,
+                    row.get::<_, String>("result")?,
+                    row.get::<_, Option<String>>("report_url")?,
+                ))
+            },
+        )?;
+
+        rows.into_iter()
+            .map(|(experiment, krate, toolchain, result, report_url)| {
+                Ok(CrateHistoryEntry {
+                    experiment,
+                    krate: krate.parse()?,
+                    toolchain: toolchain.parse()?,
+                    result: result.parse()?,
+                    report_url,
+                })
+            })
+            .collect()
+    }
+
+    /// Load the results of a crate (matched like in `crate_history`) in the last `limit`
+    /// experiments comparing release toolchains, sorted from the most recent one. Experiments
+    /// testing try builds or customizing the toolchains with flags are excluded, as their
+    /// results are not representative of the state of the crate.
+    pub fn crate_trend(
+        &self,
+        name: &str,
+        tag: Option<&str>,
+        limit: u32,
+    ) -> Fallible<Vec<CrateTrendEntry>> {
+        let (exact, start, end) = crate_id_range(name);
+
+        let rows = self.db.query(
+            "SELECT experiments.name, experiments.created_at, experiments.toolchain_start, \
+             experiments.toolchain_end, experiments.comparison_policy, results.crate, \
+             MAX(CASE WHEN results.toolchain = experiments.toolchain_start \
+             THEN results.result END) AS result_start, \
+             MAX(CASE WHEN results.toolchain = experiments.toolchain_end \
+             THEN results.result END) AS result_end, \
+             MIN(results.overridden) AS overridden \
+             FROM results INNER JOIN experiments ON experiments.name = results.experiment \
+             WHERE (results.crate = ?1 OR (results.crate >= ?2 AND results.crate < ?3)) \
+             AND experiments.toolchain_start NOT LIKE '%#%' \
+             AND experiments.toolchain_start NOT LIKE '%+%' \
+             AND experiments.toolchain_end NOT LIKE '%#%' \
+             AND experiments.toolchain_end NOT LIKE '%+%' \
+             AND (?5 IS NULL OR EXISTS \
+             (SELECT 1 FROM json_each(experiments.tags) WHERE json_each.value = ?5)) \
+             GROUP BY experiments.name, results.crate \
+             ORDER BY experiments.created_at DESC, results.crate \
+             LIMIT ?4;",
+            rusqlite::params![exact, start, end, limit, tag],
+            |row| {
+                Ok((
+                    row.get::<_, String>("name")?,
+                    row.get::<_, DateTime<Utc>>("created_at")?,
+                    [
+                        row.get::<_, String>("toolchain_start")?,
+                        row.get::<_, String>("toolchain_end")?,
+                    ],
+                    row.get::<_, String>("crate")?,
+                    [
+                        row.get::<_, Option<String>>("result_start")?,
+                        row.get::<_, Option<String>>("result_end")?,
+                    ],
+                    row.get::<_, Option<String>>("comparison_policy")?,
+                    row.get::<_, bool>("overridden")?,
+                ))
+            },
+        )?;
+
+        rows.into_iter()
+            .map(
+                |(experiment, created_at, [start, end], krate, results, policy, overridden)| {
+                    let [res_start, res_end] = results;
+                    Ok(CrateTrendEntry {
+                        experiment,
+                        created_at,
+                        krate: krate.parse()?,
+                        toolchains: [start.parse()?, end.parse()?],
+                        results: [
+                            res_start.map(|r| r.parse()).transpose()?,
+                            res_end.map(|r| r.parse()).transpose()?,
+                        ],
+                        overridden,
+                        comparison_policy: policy
+                            .map(|p| p.parse())
+                            .transpose()?
+                            .unwrap_or_default(),
+                    })
+                },
+            )
+            .collect()
+    }
+}
+
+fn triage_from_row(row: &Row) -> rusqlite::Result<TriageAnnotation> {
+    let status: String = row.get("status")?;
+    Ok(TriageAnnotation {
+        status: status.parse::<TriageStatus>().map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?,
+        issue: row.get("issue")?,
+        note: row.get("note")?,
+        author: row.get("author")?,
+    })
+}
+
 impl ReadResults for DatabaseDB<'_> {
     fn load_log(
         &self,
@@ -178,22 +728,37 @@ impl ReadResults for DatabaseDB<'_> {
         toolchain: &Toolchain,
         krate: &Crate,
     ) -> Fallible<Option<EncodedLog>> {
-        self.db.get_row(
-            "SELECT log, encoding FROM results \
+        let row = self.db.get_row(
+            "SELECT log, encoding, log_key, log_hash FROM results \
              WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
              LIMIT 1;",
             [&ex.name, &toolchain.to_string(), &krate.id()],
             |row| {
                 let log: Vec<u8> = row.get("log")?;
                 let encoding: String = row.get("encoding")?;
-                let encoding = encoding.parse().unwrap();
-
-                Ok(match encoding {
-                    EncodingType::Plain => EncodedLog::Plain(log),
-                    EncodingType::Gzip => EncodedLog::Gzip(log),
-                })
+                let key: Option<String> = row.get("log_key")?;
+                let hash: Option<String> = row.get("log_hash")?;
+                Ok((log, encoding, key, hash))
             },
-        )
+        )?;
+        let Some((mut log, encoding, key, hash)) = row else {
+            return Ok(None);
+        };
+
+        if let Some(key) = key {
+            let store = self.log_store.ok_or_else(|| {
+                anyhow!("the log {key} is stored outside the database, but no store is configured")
+            })?;
+            log = store.get(&key)?;
+            if hash.as_deref() != Some(log_hash(&log).as_str()) {
+                bail!("the hash of the log {key} doesn't match the one recorded in the database");
+            }
+        }
+
+        Ok(Some(match encoding.parse().unwrap() {
+            EncodingType::Plain => EncodedLog::Plain(log),
+            EncodingType::Gzip => EncodedLog::Gzip(log),
+        }))
     }
 
     fn load_test_result(
@@ -210,6 +775,201 @@ impl ReadResults for DatabaseDB<'_> {
             |row| Ok(row.get_ref("result")?.as_str()?.parse::<TestResult>()?),
         )?)
     }
+
+    fn load_triage(&self, ex: &Experiment, krate: &Crate) -> Fallible<Option<TriageAnnotation>> {
+        self.db.get_row(
+            "SELECT * FROM triage_annotations WHERE experiment = ?1 AND crate = ?2 LIMIT 1;",
+            [&ex.name, &krate.id()],
+            triage_from_row,
+        )
+    }
+
+    fn load_build_metrics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildMetrics>> {
+        let metrics: Option<Option<String>> = self.db.get_row(
+            "SELECT build_metrics FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("build_metrics"),
+        )?;
+        Ok(metrics
+            .flatten()
+            .map(|metrics| serde_json::from_str(&metrics))
+            .transpose()?)
+    }
+
+    fn load_disk_usage(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildDiskUsage>> {
+        let usage: Option<Option<String>> = self.db.get_row(
+            "SELECT disk_usage FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("disk_usage"),
+        )?;
+        Ok(usage
+            .flatten()
+            .map(|usage| serde_json::from_str(&usage))
+            .transpose()?)
+    }
+
+    fn load_build_cache_stats(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildCacheStats>> {
+        let stats: Option<Option<String>> = self.db.get_row(
+            "SELECT build_cache_stats FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("build_cache_stats"),
+        )?;
+        Ok(stats
+            .flatten()
+            .map(|stats| serde_json::from_str(&stats))
+            .transpose()?)
+    }
+
+    fn load_build_warnings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildWarnings>> {
+        let warnings: Option<Option<String>> = self.db.get_row(
+            "SELECT build_warnings FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("build_warnings"),
+        )?;
+        Ok(warnings
+            .flatten()
+            .map(|warnings| serde_json::from_str(&warnings))
+            .transpose()?)
+    }
+
+    fn load_build_reproducibility(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildReproducibility>> {
+        let reproducibility: Option<Option<String>> = self.db.get_row(
+            "SELECT build_reproducibility FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("build_reproducibility"),
+        )?;
+        Ok(reproducibility
+            .flatten()
+            .map(|reproducibility| serde_json::from_str(&reproducibility))
+            .transpose()?)
+    }
+
+    fn load_overridden(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<bool> {
+        let overridden: Option<bool> = self.db.get_row(
+            "SELECT overridden FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("overridden"),
+        )?;
+        Ok(overridden.unwrap_or(false))
+    }
+
+    fn load_build_time(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<f64>> {
+        let time: Option<Option<f64>> = self.db.get_row(
+            "SELECT build_time FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("build_time"),
+        )?;
+        Ok(time.flatten())
+    }
+
+    fn load_test_runs(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<TestRuns>> {
+        let runs: Option<Option<String>> = self.db.get_row(
+            "SELECT test_runs FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            [&ex.name, &toolchain.to_string(), &krate.id()],
+            |row| row.get("test_runs"),
+        )?;
+        Ok(runs
+            .flatten()
+            .map(|runs| serde_json::from_str(&runs))
+            .transpose()?)
+    }
+
+    fn load_flakiness_scores(&self, ex: &Experiment) -> Fallible<HashMap<String, f64>> {
+        let runs = self.db.query(
+            "SELECT results.crate, results.test_runs FROM results \
+             JOIN experiments ON experiments.name = results.experiment \
+             WHERE results.test_runs IS NOT NULL AND experiments.created_at < ?1;",
+            [&ex.created_at],
+            |row| {
+                Ok((
+                    row.get::<_, String>("crate")?,
+                    row.get::<_, String>("test_runs")?,
+                ))
+            },
+        )?;
+        Ok(flakiness_scores(
+            runs.into_iter()
+                .map(|(krate, runs)| Ok((krate, serde_json::from_str(&runs)?)))
+                .collect::<Fallible<Vec<_>>>()?,
+        ))
+    }
+
+    fn load_excluded_crates(&self, ex: &Experiment) -> Fallible<Vec<ExcludedCrate>> {
+        let rows = self.db.query(
+            "SELECT crate, reason FROM excluded_crates WHERE experiment = ?1 ORDER BY crate;",
+            [&ex.name],
+            |row| {
+                Ok((
+                    row.get::<_, String>("crate")?,
+                    row.get::<_, String>("reason")?,
+                ))
+            },
+        )?;
+        rows.into_iter()
+            .map(|(krate, reason)| {
+                Ok(ExcludedCrate {
+                    krate: krate.parse()?,
+                    reason: serde_json::from_str(&reason)?,
+                })
+            })
+            .collect()
+    }
 }
 
 impl WriteResults for DatabaseDB<'_> {
@@ -249,6 +1009,7 @@ impl WriteResults for DatabaseDB<'_> {
             krate,
             toolchain,
             &result,
+            &BuildReport::default(),
             output.as_bytes(),
             encoding_type,
         )?;
@@ -264,28 +1025,60 @@ impl crate::runner::RecordProgress for DatabaseDB<'_> {
         toolchain: &Toolchain,
         log: &[u8],
         result: &TestResult,
+        report: &BuildReport,
         version: Option<(&Crate, &Crate)>,
     ) -> Fallible<()> {
-        self.store_result(ex, krate, toolchain, result, log, EncodingType::Plain)?;
+        self.store_result(
+            ex,
+            krate,
+            toolchain,
+            result,
+            report,
+            log,
+            EncodingType::Plain,
+        )?;
         if let Some((old, new)) = version {
             self.update_crate_version(ex, old, new)?;
         }
+        self.quarantine_suspicious_build(ex, krate, report)?;
         Ok(())
     }
+
+    fn record_rustc_versions(&self, ex: &Experiment, versions: &[RustcVersion; 2]) -> Fallible<()> {
+        ex.clone().set_rustc_versions(self.db, versions)
+    }
 }
 
 impl DeleteResults for DatabaseDB<'_> {
     fn delete_all_results(&self, ex: &Experiment) -> Fallible<()> {
-        self.db
-            .execute("DELETE FROM results WHERE experiment = ?1;", &[&ex.name])?;
+        let log_keys = self.db.transaction(true, |t| {
+            let keys = t.query(
+                "SELECT log_key FROM results WHERE experiment = ?1 AND log_key IS NOT NULL;",
+                [&ex.name],
+                |row| row.get(0),
+            )?;
+            t.execute("DELETE FROM results WHERE experiment = ?1;", &[&ex.name])?;
+            Ok(keys)
+        })?;
+        delete_logs(self.log_store, &log_keys);
         Ok(())
     }
 
     fn delete_result(&self, ex: &Experiment, tc: &Toolchain, krate: &Crate) -> Fallible<()> {
-        self.db.execute(
-            "DELETE FROM results WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
-            &[&ex.name, &tc.to_string(), &krate.id()],
-        )?;
+        let log_keys = self.db.transaction(true, |t| {
+            let keys = t.query(
+                "SELECT log_key FROM results \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 AND log_key IS NOT NULL;",
+                [&ex.name, &tc.to_string(), &krate.id()],
+                |row| row.get(0),
+            )?;
+            t.execute(
+                "DELETE FROM results WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+                &[&ex.name, &tc.to_string(), &krate.id()],
+            )?;
+            Ok(keys)
+        })?;
+        delete_logs(self.log_store, &log_keys);
         Ok(())
     }
 }
@@ -293,20 +1086,22 @@ impl DeleteResults for DatabaseDB<'_> {
 #[cfg(test)]
 mod tests {
     use base64::Engine;
+    use chrono::Utc;
     use rustwide::logging::LogStorage;
 
-    use super::{DatabaseDB, ProgressData, TaskResult};
+    use super::{failing_since, DatabaseDB, FailingSince, ProgressData, TaskResult};
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::config::Config;
-    use crate::crates::{Crate, RegistryCrate};
-    use crate::db::Database;
+    use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+    use crate::db::{Database, QueryUtils};
     use crate::experiments::Experiment;
     use crate::prelude::*;
     use crate::results::{
-        DeleteResults, EncodedLog, EncodingType, FailureReason, ReadResults, TestResult,
-        WriteResults,
+        BuildCacheStats, BuildDiskUsage, BuildMetrics, BuildReport, BuildReproducibility,
+        BuildWarnings, DeleteResults, EncodedLog, EncodingType, FailureReason, LocalLogStore,
+        LogStore, ReadResults, TestResult, TestRuns, TriageAnnotation, TriageStatus, WriteResults,
     };
-    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
     use std::collections::BTreeSet;
 
@@ -449,6 +1244,13 @@ mod tests {
             Some(TestResult::TestFail(FailureReason::Unknown))
         );
 
+        let all = results.load_all_results(&ex).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(
+            all.get(&(krate.id(), TEST_TOOLCHAIN.to_string())),
+            Some(&TestResult::TestFail(FailureReason::Unknown))
+        );
+
         // Test deleting the newly-added result
         results.delete_result(&ex, &TEST_TOOLCHAIN, &krate).unwrap();
         assert!(results
@@ -500,6 +1302,7 @@ mod tests {
                         toolchain: MAIN_TOOLCHAIN.clone(),
                         result: TestResult::TestPass,
                         log: base64::engine::general_purpose::STANDARD.encode("foo"),
+                        report: BuildReport::default(),
                     },
                     version: Some((krate.clone(), updated.clone())),
                 },
@@ -529,4 +1332,623 @@ mod tests {
             None
         );
     }
+
+    #[test]
+    fn test_store_idempotency() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let progress = |result| ProgressData {
+            result: TaskResult {
+                krate: krate.clone(),
+                toolchain: MAIN_TOOLCHAIN.clone(),
+                result,
+                log: base64::engine::general_purpose::STANDARD.encode("foo"),
+                report: BuildReport::default(),
+            },
+            version: None,
+        };
+        let results = DatabaseDB::new(&db)
+            .with_agent("agent")
+            .with_idempotency_key(Some("key"));
+
+        // A result which fails to be recorded doesn't record its key
+        let mut missing = ex.clone();
+        missing.name = "missing".into();
+        assert!(results
+            .store(
+                &missing,
+                &progress(TestResult::TestPass),
+                EncodingType::Plain
+            )
+            .is_err());
+        assert!(!crate::server::idempotency::is_recorded(&db, "agent", "key").unwrap());
+
+        assert!(results
+            .store(&ex, &progress(TestResult::TestPass), EncodingType::Plain)
+            .unwrap());
+        assert!(crate::server::idempotency::is_recorded(&db, "agent", "key").unwrap());
+
+        // Retries of the upload are ignored
+        assert!(!results
+            .store(
+                &ex,
+                &progress(TestResult::TestFail(FailureReason::Unknown)),
+                EncodingType::Plain,
+            )
+            .unwrap());
+        assert_eq!(
+            results
+                .load_test_result(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+    }
+
+    #[test]
+    fn test_log_store() {
+        let db = Database::temp().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalLogStore::new(dir.path().into());
+        let results = DatabaseDB::new(&db).with_log_store(Some(&store));
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        results
+            .store(
+                &ex,
+                &ProgressData {
+                    result: TaskResult {
+                        krate: krate.clone(),
+                        toolchain: MAIN_TOOLCHAIN.clone(),
+                        result: TestResult::TestPass,
+                        log: base64::engine::general_purpose::STANDARD.encode("foo"),
+                        report: BuildReport::default(),
+                    },
+                    version: None,
+                },
+                EncodingType::Plain,
+            )
+            .unwrap();
+
+        // The log is only stored in the log store
+        let key = format!(
+            "dummy/{}/{}",
+            MAIN_TOOLCHAIN.to_path_component(),
+            krate.id()
+        );
+        assert_eq!(store.get(&key).unwrap(), b"foo");
+        assert_eq!(
+            results.load_log(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some(EncodedLog::Plain(b"foo".to_vec()))
+        );
+
+        // Loading the log without the store or with a tampered log fails
+        assert!(DatabaseDB::new(&db)
+            .load_log(&ex, &MAIN_TOOLCHAIN, &krate)
+            .is_err());
+        store.put(&key, b"bar").unwrap();
+        assert!(results.load_log(&ex, &MAIN_TOOLCHAIN, &krate).is_err());
+
+        // Deleting the result also deletes its log
+        results.delete_all_results(&ex).unwrap();
+        assert!(store.get(&key).is_err());
+    }
+
+    #[test]
+    fn test_build_metrics() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let report = BuildReport {
+            metrics: Some(BuildMetrics {
+                units: 3,
+                codegen_time: None,
+                artifact_size: 2048,
+            }),
+            disk_usage: Some(BuildDiskUsage {
+                source_dir: 512,
+                target_dir: 8192,
+            }),
+            cache_stats: Some(BuildCacheStats {
+                hits: 40,
+                misses: 2,
+            }),
+            warnings: Some(BuildWarnings {
+                count: 3,
+                lints: [("dead_code".to_string(), 3)].into(),
+            }),
+            reproducibility: Some(BuildReproducibility {
+                compared: 2,
+                differing: ["foo".to_string()].into(),
+            }),
+            ..BuildReport::default()
+        };
+        // The build can't spoof the measurements by printing them in its log
+        let spoofed = "[INFO] build-metrics: {\"units\":3,\"artifact_size\":2048}\n";
+        for (toolchain, report) in [
+            (&*MAIN_TOOLCHAIN, report),
+            (&*TEST_TOOLCHAIN, BuildReport::default()),
+        ] {
+            results
+                .store(
+                    &ex,
+                    &ProgressData {
+                        result: TaskResult {
+                            krate: krate.clone(),
+                            toolchain: toolchain.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::engine::general_purpose::STANDARD.encode(spoofed),
+                            report,
+                        },
+                        version: None,
+                    },
+                    EncodingType::Gzip,
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            results
+                .load_build_metrics(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(BuildMetrics {
+                units: 3,
+                codegen_time: None,
+                artifact_size: 2048,
+            })
+        );
+        assert_eq!(
+            results
+                .load_build_metrics(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            results
+                .load_disk_usage(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(BuildDiskUsage {
+                source_dir: 512,
+                target_dir: 8192,
+            })
+        );
+        assert_eq!(
+            results
+                .load_disk_usage(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            results
+                .load_build_cache_stats(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(BuildCacheStats {
+                hits: 40,
+                misses: 2,
+            })
+        );
+        assert_eq!(
+            results
+                .load_build_cache_stats(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            results
+                .load_build_warnings(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(BuildWarnings {
+                count: 3,
+                lints: [("dead_code".to_string(), 3)].into(),
+            })
+        );
+        assert_eq!(
+            results
+                .load_build_warnings(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            results
+                .load_build_reproducibility(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(BuildReproducibility {
+                compared: 2,
+                differing: ["foo".to_string()].into(),
+            })
+        );
+        assert_eq!(
+            results
+                .load_build_reproducibility(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_build_time() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        // The history is shared by all the versions of the crate
+        let krate = |version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: version.into(),
+            })
+        };
+        for (version, time) in [("1.0.0", 10.0), ("1.1.0", 20.0), ("1.2.0", 45.0)] {
+            results
+                .record_progress(
+                    &ex,
+                    &krate(version),
+                    &MAIN_TOOLCHAIN,
+                    b"log",
+                    &TestResult::TestPass,
+                    &BuildReport {
+                        build_time: Some(time),
+                        ..BuildReport::default()
+                    },
+                    None,
+                )
+                .unwrap();
+        }
+
+        let history = db
+            .get_row(
+                "SELECT builds, average_time FROM build_times WHERE crate = 'reg/lazy_static';",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, u32>("builds")?,
+                        row.get::<_, f64>("average_time")?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(history, Some((3, 25.0)));
+    }
+
+    #[test]
+    fn test_crate_history() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let crates = [
+            Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: "1".into(),
+            }),
+            Crate::Registry(RegistryCrate {
+                name: "lazy_static_plus".into(),
+                version: "1".into(),
+            }),
+            Crate::GitHub(GitHubRepo {
+                org: "brson".into(),
+                name: "hello-rs".into(),
+                sha: None,
+            }),
+        ];
+        for krate in &crates {
+            results
+                .record_result(
+                    &ex,
+                    &MAIN_TOOLCHAIN,
+                    krate,
+                    &LogStorage::from(&config),
+                    EncodingType::Plain,
+                    || Ok(TestResult::TestPass),
+                )
+                .unwrap();
+        }
+
+        let history = results.crate_history("lazy_static", None, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].experiment, "dummy");
+        assert_eq!(history[0].krate, crates[0]);
+        assert_eq!(history[0].toolchain, *MAIN_TOOLCHAIN);
+        assert_eq!(history[0].result, TestResult::TestPass);
+
+        let history = results.crate_history("brson/hello-rs", None, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].krate, crates[2]);
+
+        assert!(results.crate_history("lazy", None, 10).unwrap().is_empty());
+        // The experiment has no tags
+        assert!(results
+            .crate_history("lazy_static", Some("beta"), 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_crate_trend() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let flags = Toolchain {
+            rustflags: Some("-Zverbose".into()),
+            ..TEST_TOOLCHAIN.clone()
+        };
+        for (i, name) in ["old", "new", "flags"].iter().enumerate() {
+            let mut create = CreateExperiment::dummy(name);
+            if *name == "flags" {
+                create.toolchains[1] = flags.clone();
+            }
+            if *name == "old" {
+                create.tags = vec!["beta".into(), "lint:dead_code".into()];
+            }
+            create.apply(&ctx).unwrap();
+            db.execute(
+                "UPDATE experiments SET created_at = ?1 WHERE name = ?2;",
+                &[&(Utc::now() + chrono::Duration::hours(i as i64)), name],
+            )
+            .unwrap();
+        }
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let record = |ex: &str, tc: &Toolchain, res: TestResult| {
+            let ex = Experiment::get(&db, ex).unwrap().unwrap();
+            results
+                .record_result(
+                    &ex,
+                    tc,
+                    &krate,
+                    &LogStorage::from(&config),
+                    EncodingType::Plain,
+                    || Ok(res),
+                )
+                .unwrap();
+        };
+        let fail = TestResult::BuildFail(FailureReason::Unknown);
+        record("old", &MAIN_TOOLCHAIN, TestResult::TestPass);
+        record("old", &TEST_TOOLCHAIN, TestResult::TestPass);
+        record("new", &MAIN_TOOLCHAIN, TestResult::TestPass);
+        record("new", &TEST_TOOLCHAIN, fail.clone());
+        record("flags", &MAIN_TOOLCHAIN, TestResult::TestPass);
+        record("flags", &flags, TestResult::TestPass);
+
+        // Experiments with custom flags are excluded
+        let trend = results.crate_trend("lazy_static", None, 10).unwrap();
+        assert_eq!(trend.len(), 2);
+        assert_eq!(trend[0].experiment, "new");
+        assert_eq!(trend[0].krate, krate);
+        assert_eq!(
+            trend[0].toolchains,
+            [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()]
+        );
+        assert_eq!(trend[0].results, [Some(TestResult::TestPass), Some(fail)]);
+        assert_eq!(trend[1].experiment, "old");
+
+        assert_eq!(
+            failing_since(&trend),
+            Some(FailingSince {
+                experiment: "new".into(),
+                toolchain: TEST_TOOLCHAIN.clone(),
+            })
+        );
+        // The crate was passing in the last experiment considered
+        assert_eq!(failing_since(&trend[1..]), None);
+
+        assert_eq!(
+            results.crate_trend("lazy_static", None, 1).unwrap().len(),
+            1
+        );
+        assert!(results.crate_trend("lazy", None, 10).unwrap().is_empty());
+
+        let tagged = results
+            .crate_trend("lazy_static", Some("beta"), 10)
+            .unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].experiment, "old");
+        assert!(results
+            .crate_trend("lazy_static", Some("lint"), 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_flakiness_scores() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        CreateExperiment::dummy("later").apply(&ctx).unwrap();
+        db.execute(
+            "UPDATE experiments SET created_at = ?1 WHERE name = 'later';",
+            [&(chrono::Utc::now() + chrono::Duration::days(1))],
+        )
+        .unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let later = Experiment::get(&db, "later").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        for (toolchain, failed) in [(&*MAIN_TOOLCHAIN, 2), (&*TEST_TOOLCHAIN, 0)] {
+            results
+                .store(
+                    &ex,
+                    &ProgressData {
+                        result: TaskResult {
+                            krate: krate.clone(),
+                            toolchain: toolchain.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::engine::general_purpose::STANDARD.encode("log"),
+                            report: BuildReport {
+                                test_runs: Some(TestRuns { total: 5, failed }),
+                                ..BuildReport::default()
+                            },
+                        },
+                        version: None,
+                    },
+                    EncodingType::Gzip,
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            results
+                .load_test_runs(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestRuns {
+                total: 5,
+                failed: 2
+            })
+        );
+        let scores = results.load_flakiness_scores(&later).unwrap();
+        assert_eq!(scores.get(&krate.id()), Some(&0.5));
+        // The runs of an experiment don't count towards its own scores
+        assert!(results.load_flakiness_scores(&ex).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_triage() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        assert!(results.load_triage(&ex, &krate).unwrap().is_none());
+
+        let mut triage = TriageAnnotation {
+            status: TriageStatus::Spurious,
+            issue: None,
+            note: Some("flaky test".into()),
+            author: "pietroalbini".into(),
+        };
+        results.store_triage(&ex, &krate, Some(&triage)).unwrap();
+        assert_eq!(
+            results.load_triage(&ex, &krate).unwrap(),
+            Some(triage.clone())
+        );
+
+        // Storing a new annotation replaces the old one
+        triage.status = TriageStatus::Real;
+        triage.issue = Some("rust-lang/rust#12345".into());
+        results.store_triage(&ex, &krate, Some(&triage)).unwrap();
+        assert_eq!(
+            results.load_all_triage(&ex).unwrap(),
+            vec![(krate.id(), triage)]
+        );
+
+        results.store_triage(&ex, &krate, None).unwrap();
+        assert!(results.load_triage(&ex, &krate).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_skip_crates_past_deadline() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let crates = ex.get_crates(&db).unwrap();
+
+        // A crate tested only with the first toolchain when the deadline passed
+        results
+            .store_result(
+                &ex,
+                &crates[0],
+                &MAIN_TOOLCHAIN,
+                &TestResult::TestPass,
+                &BuildReport::default(),
+                b"log",
+                EncodingType::Plain,
+            )
+            .unwrap();
+
+        let skipped = results.skip_crates_past_deadline(&ex).unwrap();
+        assert_eq!(skipped, crates.len());
+        assert_eq!(
+            results.count_skipped_past_deadline(&ex).unwrap(),
+            crates.len() as u32
+        );
+
+        let (completed, all) = ex.raw_progress(&db).unwrap();
+        assert_eq!(completed, all);
+        assert_eq!(
+            results
+                .load_test_result(&ex, &MAIN_TOOLCHAIN, &crates[0])
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+        assert_eq!(
+            results
+                .load_test_result(&ex, &TEST_TOOLCHAIN, &crates[0])
+                .unwrap(),
+            Some(TestResult::SkippedDeadline)
+        );
+
+        // The crates already skipped are not skipped again
+        assert_eq!(results.skip_crates_past_deadline(&ex).unwrap(), 0);
+    }
 }
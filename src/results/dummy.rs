@@ -1,7 +1,11 @@
-use crate::crates::Crate;
+use crate::crates::{Crate, ExcludedCrate};
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::{EncodedLog, ReadResults, TestResult};
+use crate::results::{
+    flakiness_scores, BuildCacheStats, BuildDiskUsage, BuildMetrics, BuildReport,
+    BuildReproducibility, BuildWarnings, EncodedLog, ReadResults, TestResult, TestRuns,
+    TriageAnnotation,
+};
 use crate::toolchain::Toolchain;
 use std::collections::HashMap;
 
@@ -9,6 +13,9 @@ use std::collections::HashMap;
 struct DummyData {
     logs: HashMap<(Crate, Toolchain), EncodedLog>,
     results: HashMap<(Crate, Toolchain), TestResult>,
+    reports: HashMap<(Crate, Toolchain), BuildReport>,
+    triage: HashMap<Crate, TriageAnnotation>,
+    excluded: Vec<ExcludedCrate>,
 }
 
 #[derive(Default)]
@@ -45,6 +52,48 @@ impl DummyDB {
             .results
             .insert((krate, tc), res);
     }
+
+    pub fn add_dummy_report(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        report: BuildReport,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_default()
+            .reports
+            .insert((krate, tc), report);
+    }
+
+    fn get_report(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<&BuildReport>> {
+        Ok(self
+            .get_data(ex)?
+            .reports
+            .get(&(krate.clone(), toolchain.clone())))
+    }
+
+    pub fn add_dummy_triage(&mut self, ex: &Experiment, krate: Crate, triage: TriageAnnotation) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_default()
+            .triage
+            .insert(krate, triage);
+    }
+
+    pub fn add_dummy_exclusion(&mut self, ex: &Experiment, excluded: ExcludedCrate) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_default()
+            .excluded
+            .push(excluded);
+    }
 }
 
 impl ReadResults for DummyDB {
@@ -73,4 +122,120 @@ impl ReadResults for DummyDB {
             .get(&(krate.clone(), toolchain.clone()))
             .cloned())
     }
+
+    fn load_triage(&self, ex: &Experiment, krate: &Crate) -> Fallible<Option<TriageAnnotation>> {
+        Ok(self.get_data(ex)?.triage.get(krate).cloned())
+    }
+
+    fn load_build_metrics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildMetrics>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.metrics.clone()))
+    }
+
+    fn load_disk_usage(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildDiskUsage>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.disk_usage.clone()))
+    }
+
+    fn load_build_cache_stats(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildCacheStats>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.cache_stats.clone()))
+    }
+
+    fn load_build_warnings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildWarnings>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.warnings.clone()))
+    }
+
+    fn load_build_reproducibility(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildReproducibility>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.reproducibility.clone()))
+    }
+
+    fn load_overridden(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<bool> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .is_some_and(|r| r.result_override.is_some()))
+    }
+
+    fn load_build_time(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<f64>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.build_time))
+    }
+
+    fn load_test_runs(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<TestRuns>> {
+        Ok(self
+            .get_report(ex, toolchain, krate)?
+            .and_then(|r| r.test_runs.clone()))
+    }
+
+    /// The dummy experiments aren't ordered, so the runs of all the other ones are considered.
+    fn load_flakiness_scores(&self, ex: &Experiment) -> Fallible<HashMap<String, f64>> {
+        let mut runs = Vec::new();
+        for (name, data) in &self.experiments {
+            if *name == ex.name {
+                continue;
+            }
+            for ((krate, _), report) in &data.reports {
+                if let Some(r) = &report.test_runs {
+                    runs.push((krate.id(), r.clone()));
+                }
+            }
+        }
+        Ok(flakiness_scores(runs))
+    }
+
+    fn load_excluded_crates(&self, ex: &Experiment) -> Fallible<Vec<ExcludedCrate>> {
+        Ok(self
+            .experiments
+            .get(&ex.name)
+            .map(|data| data.excluded.clone())
+            .unwrap_or_default())
+    }
 }
@@ -1,19 +1,23 @@
 mod db;
 #[cfg(test)]
 mod dummy;
-use crate::crates::Crate;
+mod storage;
+use crate::crates::{Crate, ExcludedCrate};
 use crate::experiments::Experiment;
 use crate::prelude::*;
 
-pub use crate::results::db::{DatabaseDB, ProgressData};
+pub use crate::results::db::{
+    failing_since, CrateHistoryEntry, CrateTrendEntry, DatabaseDB, FailingSince, ProgressData,
+};
 #[cfg(test)]
 pub use crate::results::dummy::DummyDB;
+pub use crate::results::storage::{delete_logs, LocalLogStore, LogStore, S3LogStore};
 use crate::toolchain::Toolchain;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use rustwide::logging::LogStorage;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::{fmt, io::Read, io::Write, str::FromStr};
 
 pub trait ReadResults {
@@ -29,6 +33,67 @@ pub trait ReadResults {
         toolchain: &Toolchain,
         krate: &Crate,
     ) -> Fallible<Option<TestResult>>;
+    fn load_triage(&self, ex: &Experiment, krate: &Crate) -> Fallible<Option<TriageAnnotation>>;
+    fn load_build_metrics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildMetrics>>;
+    fn load_test_runs(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<TestRuns>>;
+    fn load_disk_usage(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildDiskUsage>>;
+    /// Load the statistics of the build cache, if the build used it.
+    fn load_build_cache_stats(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildCacheStats>>;
+    /// Load the warnings of the build, if the experiment tracks them and the build succeeded.
+    fn load_build_warnings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildWarnings>>;
+    /// Load the comparison of the two builds of the crate, if it was built twice in the
+    /// `reproducibility` mode.
+    fn load_build_reproducibility(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<BuildReproducibility>>;
+    /// Load whether the result was forced by an override of the configuration instead of being
+    /// the outcome of a build.
+    fn load_overridden(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<bool>;
+    /// Load the time spent building the crate, in seconds, if it was reported.
+    fn load_build_time(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<f64>>;
+    /// Load the flakiness score of all the crates whose tests were run multiple times by the
+    /// experiments created before `ex`, keyed by the crate ID.
+    fn load_flakiness_scores(&self, ex: &Experiment) -> Fallible<HashMap<String, f64>>;
+    /// Load the crates excluded from the experiment by the filters of the configuration.
+    fn load_excluded_crates(&self, ex: &Experiment) -> Fallible<Vec<ExcludedCrate>>;
 }
 
 pub trait WriteResults {
@@ -57,6 +122,164 @@ pub trait DeleteResults {
     fn delete_result(&self, ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> Fallible<()>;
 }
 
+string_enum!(#[non_exhaustive] pub enum TriageStatus {
+    Spurious => "spurious",
+    Real => "real",
+    Expected => "expected",
+});
+
+/// Annotation left by a triager on the result of a crate, to avoid multiple people looking into
+/// the same regression.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriageAnnotation {
+    pub status: TriageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub author: String,
+}
+
+impl fmt::Display for TriageAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "triaged: {}", self.status)?;
+        if let Some(issue) = &self.issue {
+            write!(f, ", issue {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Summarized numbers about a successful `cargo build` of a crate.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BuildMetrics {
+    /// Number of compilation units built, including the dependencies.
+    pub units: u32,
+    /// Total time spent in codegen by rustc, in seconds. It's only available on nightly
+    /// toolchains, as `cargo build --timings=json` is unstable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codegen_time: Option<f64>,
+    /// Total size of the artifacts of the local packages, in bytes.
+    pub artifact_size: u64,
+}
+
+/// Disk space used by the build of a crate, measured once the build finished, when the
+/// directories are the largest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildDiskUsage {
+    /// Size of the source of the crate, in bytes.
+    pub source_dir: u64,
+    /// Size of the files written to the target directory by the build, in bytes. The target
+    /// directory is shared by the builds of the same agent thread, so the artifacts reused from
+    /// the previous builds are not counted.
+    pub target_dir: u64,
+}
+
+impl BuildDiskUsage {
+    pub fn total(&self) -> u64 {
+        self.source_dir + self.target_dir
+    }
+}
+
+/// Compilations of a build served from the sccache cache of the agent, or added to it. Results
+/// differing between toolchains only when one of the builds hit the cache point to a stale or
+/// corrupted cache rather than to a regression.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildCacheStats {
+    pub hits: u32,
+    pub misses: u32,
+}
+
+/// Warnings emitted for the local packages of a crate by a successful build, when the experiment
+/// tracks them. Warnings without a lint name, like the summaries of rustc, are not counted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildWarnings {
+    pub count: u32,
+    /// Number of warnings emitted by each lint.
+    pub lints: BTreeMap<String, u32>,
+}
+
+/// Comparison of the artifacts of two builds of the local packages of a crate, made from scratch
+/// with the same toolchain.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildReproducibility {
+    /// Number of artifacts compared between the two builds.
+    pub compared: u32,
+    /// Artifacts whose content differed, or which were produced by only one of the builds.
+    pub differing: BTreeSet<String>,
+}
+
+/// Outcome of running the test suite of a crate multiple times on the same toolchain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestRuns {
+    pub total: u32,
+    pub failed: u32,
+}
+
+impl TestRuns {
+    /// Whether the test suite both passed and failed across the runs.
+    pub fn is_flaky(&self) -> bool {
+        self.failed > 0 && self.failed < self.total
+    }
+}
+
+/// Suspicious behavior detected while building a crate, which quarantines it until a human
+/// reviews it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Quarantine {
+    pub reason: String,
+}
+
+/// Measurements of the build of a crate, sent by the agents along with its result. They're kept
+/// out of the build log, as anything the build prints there is controlled by the crate.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildReport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<BuildMetrics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_usage: Option<BuildDiskUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_stats: Option<BuildCacheStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<BuildWarnings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reproducibility: Option<BuildReproducibility>,
+    /// Time spent building the crate, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_time: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_runs: Option<TestRuns>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<Quarantine>,
+    /// Unstable features used by the source of the crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unstable_features: Option<BTreeSet<String>>,
+    /// Reason of the override of the configuration which forced the result, when the crate
+    /// wasn't built because of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_override: Option<String>,
+}
+
+/// Compute the flakiness score of each crate tested multiple times, which is the share of
+/// repeated test runs with non-deterministic outcomes.
+pub fn flakiness_scores(
+    runs: impl IntoIterator<Item = (String, TestRuns)>,
+) -> HashMap<String, f64> {
+    let mut counts: HashMap<String, (u32, u32)> = HashMap::new();
+    for (krate, runs) in runs {
+        let (flaky, total) = counts.entry(krate).or_default();
+        *total += 1;
+        if runs.is_flaky() {
+            *flaky += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(krate, (flaky, total))| (krate, flaky as f64 / total as f64))
+        .collect()
+}
+
 string_enum!(pub enum EncodingType {
     Plain => "plain",
     Gzip => "gzip",
@@ -109,12 +332,13 @@ impl EncodedLog {
 }
 
 macro_rules! test_result_enum {
-    (pub enum $name:ident {
+    ($(#[$meta:meta])* pub enum $name:ident {
         with_reason { $($with_reason_name:ident($reason:ident) => $with_reason_repr:expr,)* }
         without_reason { $($reasonless_name:ident => $reasonless_repr:expr,)* }
     }) => {
         #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
         #[serde(try_from = "String", into = "String")]
+        $(#[$meta])*
         pub enum $name {
             $($with_reason_name($reason),)*
             $($reasonless_name,)*
@@ -193,6 +417,7 @@ impl ::std::str::FromStr for DiagnosticCode {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum FailureReason {
     Unknown,
     OOM,
@@ -201,9 +426,17 @@ pub enum FailureReason {
     ICE,
     NetworkAccess,
     Docker,
+    SystemDependency,
+    RegistryFailure,
     CompilerDiagnosticChange,
+    /// The crate doesn't support the target passed with `+target=`.
+    UnsupportedTarget,
     CompilerError(BTreeSet<DiagnosticCode>),
+    /// Warnings turned into errors by `-D warnings`, identified by the name of their lint.
+    NewLint(BTreeSet<DiagnosticCode>),
     DependsOn(BTreeSet<Crate>),
+    /// Building the crate twice from scratch with the same toolchain produced different artifacts.
+    NonReproducible,
 }
 
 impl std::error::Error for FailureReason {}
@@ -218,6 +451,8 @@ impl ::std::fmt::Display for FailureReason {
             FailureReason::ICE => write!(f, "ice"),
             FailureReason::NetworkAccess => write!(f, "network-access"),
             FailureReason::Docker => write!(f, "docker"),
+            FailureReason::SystemDependency => write!(f, "system-dependency"),
+            FailureReason::RegistryFailure => write!(f, "registry-failure"),
             FailureReason::CompilerError(codes) => write!(
                 f,
                 "compiler-error({})",
@@ -227,6 +462,15 @@ impl ::std::fmt::Display for FailureReason {
                     .collect::<Vec<String>>()
                     .join(", "),
             ),
+            FailureReason::NewLint(lints) => write!(
+                f,
+                "new-lint({})",
+                lints
+                    .iter()
+                    .map(|diag| diag.code.clone())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
             FailureReason::DependsOn(deps) => write!(
                 f,
                 "depends-on({})",
@@ -236,6 +480,8 @@ impl ::std::fmt::Display for FailureReason {
                     .join(", "),
             ),
             FailureReason::CompilerDiagnosticChange => write!(f, "compiler-diagnostic-change"),
+            FailureReason::UnsupportedTarget => write!(f, "unsupported-target"),
+            FailureReason::NonReproducible => write!(f, "non-reproducible"),
         }
     }
 }
@@ -255,6 +501,13 @@ impl ::std::str::FromStr for FailureReason {
                         })
                         .collect(),
                 )),
+                "new-lint" => Ok(FailureReason::NewLint(
+                    contents
+                        .map(|st| DiagnosticCode {
+                            code: st.to_string(),
+                        })
+                        .collect(),
+                )),
                 "depends-on" => {
                     let mut krates: BTreeSet<Crate> = BTreeSet::new();
                     for krate in contents {
@@ -273,6 +526,10 @@ impl ::std::str::FromStr for FailureReason {
                 "ice" => Ok(FailureReason::ICE),
                 "no-space" => Ok(FailureReason::NoSpace),
                 "docker" => Ok(FailureReason::Docker),
+                "system-dependency" => Ok(FailureReason::SystemDependency),
+                "registry-failure" => Ok(FailureReason::RegistryFailure),
+                "unsupported-target" => Ok(FailureReason::UnsupportedTarget),
+                "non-reproducible" => Ok(FailureReason::NonReproducible),
                 _ => bail!("unexpected value: {}", s),
             }
         }
@@ -287,33 +544,101 @@ impl FailureReason {
             | FailureReason::Timeout
             | FailureReason::NetworkAccess
             | FailureReason::Docker
+            | FailureReason::SystemDependency
+            | FailureReason::RegistryFailure
             | FailureReason::CompilerDiagnosticChange => true,
             FailureReason::CompilerError(_)
+            | FailureReason::NewLint(_)
             | FailureReason::DependsOn(_)
             | FailureReason::Unknown
+            | FailureReason::UnsupportedTarget
+            | FailureReason::NonReproducible
             | FailureReason::ICE => false,
         }
     }
+
+    /// Name of the kind of failure, without the diagnostic codes or crates it carries.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            FailureReason::Unknown => "unknown",
+            FailureReason::OOM => "oom",
+            FailureReason::NoSpace => "no-space",
+            FailureReason::Timeout => "timeout",
+            FailureReason::ICE => "ice",
+            FailureReason::NetworkAccess => "network-access",
+            FailureReason::Docker => "docker",
+            FailureReason::SystemDependency => "system-dependency",
+            FailureReason::RegistryFailure => "registry-failure",
+            FailureReason::CompilerDiagnosticChange => "compiler-diagnostic-change",
+            FailureReason::CompilerError(_) => "compiler-error",
+            FailureReason::NewLint(_) => "new-lint",
+            FailureReason::DependsOn(_) => "depends-on",
+            FailureReason::UnsupportedTarget => "unsupported-target",
+            FailureReason::NonReproducible => "non-reproducible",
+        }
+    }
+}
+
+/// Reasons of the failures of both the tests and the doctests of a crate, serialized as
+/// `tests-reason|doctests-reason`.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct TestFailures {
+    pub tests: FailureReason,
+    pub doctests: FailureReason,
 }
 
-string_enum!(pub enum BrokenReason {
+impl TestFailures {
+    /// Failures of the results recorded without their reasons.
+    #[allow(non_upper_case_globals)]
+    pub const Unknown: TestFailures = TestFailures {
+        tests: FailureReason::Unknown,
+        doctests: FailureReason::Unknown,
+    };
+}
+
+impl ::std::fmt::Display for TestFailures {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}|{}", self.tests, self.doctests)
+    }
+}
+
+impl ::std::str::FromStr for TestFailures {
+    type Err = ::anyhow::Error;
+
+    fn from_str(s: &str) -> ::anyhow::Result<TestFailures> {
+        let (tests, doctests) = s
+            .split_once('|')
+            .ok_or_else(|| anyhow!("missing the reason of the doctests failure: {}", s))?;
+        Ok(TestFailures {
+            tests: tests.parse()?,
+            doctests: doctests.parse()?,
+        })
+    }
+}
+
+string_enum!(#[non_exhaustive] pub enum BrokenReason {
     Unknown => "unknown",
     CargoToml => "cargo-toml",
     Yanked => "yanked",
     MissingDependencies => "missing-deps",
     MissingGitRepository => "missing-git-repository",
+    ChecksumMismatch => "checksum-mismatch",
+    Quarantined => "quarantined",
 });
 
-test_result_enum!(pub enum TestResult {
+test_result_enum!(#[non_exhaustive] pub enum TestResult {
     with_reason {
         BrokenCrate(BrokenReason) => "broken",
         BuildFail(FailureReason) => "build-fail",
         TestFail(FailureReason) => "test-fail",
+        DoctestFail(FailureReason) => "doctest-fail",
+        TestAndDoctestFail(TestFailures) => "test-and-doctest-fail",
     }
     without_reason {
         TestSkipped => "test-skipped",
         TestPass => "test-pass",
         Skipped => "skipped",
+        SkippedDeadline => "skipped-deadline",
         Error => "error",
     }
 });
@@ -330,6 +655,7 @@ mod tests {
     fn test_test_result_parsing() {
         use super::{
             FailureReason::*,
+            TestFailures,
             TestResult::{self, *},
         };
 
@@ -360,10 +686,20 @@ mod tests {
             "build-fail:docker" => BuildFail(Docker),
             "build-fail:compiler-error(001, 002)" => BuildFail(CompilerError(btreeset!["001".parse().unwrap(), "002".parse().unwrap()])),
             "build-fail:compiler-error(001)" => BuildFail(CompilerError(btreeset!["001".parse().unwrap()])),
+            "build-fail:new-lint(dead_code, unused_variables)" => BuildFail(NewLint(btreeset!["dead_code".parse().unwrap(), "unused_variables".parse().unwrap()])),
             "build-fail:oom" => BuildFail(OOM),
             "build-fail:ice" => BuildFail(ICE),
             "build-fail:no-space" => BuildFail(NoSpace),
+            "build-fail:system-dependency" => BuildFail(SystemDependency),
+            "build-fail:registry-failure" => BuildFail(RegistryFailure),
+            "build-fail:unsupported-target" => BuildFail(UnsupportedTarget),
             "test-fail:timeout" => TestFail(Timeout),
+            "test-fail:non-reproducible" => TestFail(NonReproducible),
+            "doctest-fail:oom" => DoctestFail(OOM),
+            "test-and-doctest-fail:timeout|compiler-error(E0425)" => TestAndDoctestFail(TestFailures {
+                tests: Timeout,
+                doctests: CompilerError(btreeset!["E0425".parse().unwrap()]),
+            }),
             "test-pass" => TestPass,
             "error" => Error,
             "build-fail:depends-on(reg/clint/0.2.1)" => BuildFail(DependsOn(btreeset![Crate::Registry(RegistryCrate{name: "clint".to_string(), version: "0.2.1".to_string()})])),
@@ -377,4 +713,61 @@ mod tests {
         assert!(TestResult::from_str("error:oom").is_err());
         assert!(TestResult::from_str("build-fail:pleasedonotaddthis").is_err());
     }
+
+    #[test]
+    fn test_build_report_serialization() {
+        use super::{BuildDiskUsage, BuildMetrics, BuildReport};
+
+        let report = BuildReport {
+            metrics: Some(BuildMetrics {
+                units: 42,
+                codegen_time: None,
+                artifact_size: 1024,
+            }),
+            disk_usage: Some(BuildDiskUsage {
+                source_dir: 1024,
+                target_dir: 4096,
+            }),
+            build_time: Some(1.5),
+            ..BuildReport::default()
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            "{\"metrics\":{\"units\":42,\"artifact_size\":1024},\
+             \"disk-usage\":{\"source_dir\":1024,\"target_dir\":4096},\"build-time\":1.5}"
+        );
+        assert_eq!(serde_json::from_str::<BuildReport>(&json).unwrap(), report);
+        assert_eq!(report.disk_usage.unwrap().total(), 5120);
+
+        // Agents without any measurement send an empty report
+        assert_eq!(
+            serde_json::from_str::<BuildReport>("{}").unwrap(),
+            BuildReport::default()
+        );
+    }
+
+    #[test]
+    fn test_flakiness_scores() {
+        use super::{flakiness_scores, TestRuns};
+
+        let flaky = TestRuns {
+            total: 5,
+            failed: 2,
+        };
+        assert!(flaky.is_flaky());
+        let broken = TestRuns {
+            total: 5,
+            failed: 5,
+        };
+        assert!(!broken.is_flaky());
+
+        let scores = flakiness_scores([
+            ("foo".to_string(), flaky),
+            ("foo".to_string(), broken),
+            ("bar".to_string(), broken),
+        ]);
+        assert_eq!(scores["foo"], 0.5);
+        assert_eq!(scores["bar"], 0.0);
+    }
 }
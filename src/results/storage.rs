@@ -0,0 +1,184 @@
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::toolchain::Toolchain;
+use crate::utils::hex::to_hex;
+use aws_sdk_s3::Client as S3Client;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::PathBuf;
+
+/// Object storage the logs are written to instead of the `results` table, which then only keeps
+/// the key of the object and the hash of its content.
+pub trait LogStore: Send + Sync {
+    fn put(&self, key: &str, log: &[u8]) -> Fallible<()>;
+    fn get(&self, key: &str) -> Fallible<Vec<u8>>;
+    /// Remove the log, succeeding if it doesn't exist.
+    fn delete(&self, key: &str) -> Fallible<()>;
+}
+
+/// Remove the logs of results deleted from the database. The results are deleted first, so a
+/// failure only leaves unreferenced objects behind: it's logged instead of failing the deletion.
+pub fn delete_logs(store: Option<&dyn LogStore>, keys: &[String]) {
+    let Some(store) = store else {
+        return;
+    };
+    for key in keys {
+        if let Err(err) = store.delete(key) {
+            warn!("failed to delete the log {}: {:?}", key, err);
+        }
+    }
+}
+
+pub(super) fn log_key(ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> String {
+    format!(
+        "{}/{}/{}",
+        ex.name,
+        toolchain.to_path_component(),
+        krate.id()
+    )
+}
+
+pub(super) fn log_hash(log: &[u8]) -> String {
+    to_hex(&Sha1::digest(log))
+}
+
+pub struct LocalLogStore {
+    root: PathBuf,
+}
+
+impl LocalLogStore {
+    pub fn new(root: PathBuf) -> Self {
+        LocalLogStore { root }
+    }
+
+    fn path(&self, key: &str) -> Fallible<PathBuf> {
+        // Keys are built from experiment names and crate IDs, make sure they can't escape the root
+        if key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            bail!("invalid log key: {}", key);
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl LogStore for LocalLogStore {
+    fn put(&self, key: &str, log: &[u8]) -> Fallible<()> {
+        let path = self.path(key)?;
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+
+        // Write to a temporary file first to avoid leaving truncated logs around
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, log)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Fallible<Vec<u8>> {
+        let path = self.path(key)?;
+        fs::read(&path).with_context(|| format!("failed to read the log {}", path.display()))
+    }
+
+    fn delete(&self, key: &str) -> Fallible<()> {
+        let path = self.path(key)?;
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to delete the log {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct S3LogStore {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl S3LogStore {
+    pub fn create(client: S3Client, bucket: String, prefix: String) -> Fallible<Self> {
+        Ok(S3LogStore {
+            client,
+            bucket,
+            prefix,
+            runtime: tokio::runtime::Runtime::new()?,
+        })
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}/{}", self.prefix, key)
+    }
+}
+
+impl LogStore for S3LogStore {
+    fn put(&self, key: &str, log: &[u8]) -> Fallible<()> {
+        let request = self
+            .client
+            .put_object()
+            .body(aws_sdk_s3::primitives::ByteStream::from(
+                bytes::Bytes::copy_from_slice(log),
+            ))
+            .key(self.object_key(key))
+            .bucket(self.bucket.clone());
+        match self.runtime.block_on(request.send()) {
+            Ok(_) => Ok(()),
+            Err(e) => bail!("Failed to upload the log {}: {:?}", key, e),
+        }
+    }
+
+    fn get(&self, key: &str) -> Fallible<Vec<u8>> {
+        let request = self
+            .client
+            .get_object()
+            .key(self.object_key(key))
+            .bucket(self.bucket.clone());
+        self.runtime.block_on(async {
+            let object = match request.send().await {
+                Ok(object) => object,
+                Err(e) => bail!("Failed to download the log {}: {:?}", key, e),
+            };
+            match object.body.collect().await {
+                Ok(body) => Ok(body.into_bytes().to_vec()),
+                Err(e) => bail!("Failed to download the log {}: {:?}", key, e),
+            }
+        })
+    }
+
+    fn delete(&self, key: &str) -> Fallible<()> {
+        let request = self
+            .client
+            .delete_object()
+            .key(self.object_key(key))
+            .bucket(self.bucket.clone());
+        match self.runtime.block_on(request.send()) {
+            Ok(_) => Ok(()),
+            Err(e) => bail!("Failed to delete the log {}: {:?}", key, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalLogStore, LogStore};
+
+    #[test]
+    fn test_local_log_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalLogStore::new(dir.path().into());
+
+        store.put("ex/stable/reg/foo/1.0.0", b"log").unwrap();
+        assert_eq!(store.get("ex/stable/reg/foo/1.0.0").unwrap(), b"log");
+        assert!(store.get("ex/stable/reg/bar/1.0.0").is_err());
+        assert!(store.put("ex/../../etc/passwd", b"log").is_err());
+
+        store.delete("ex/stable/reg/foo/1.0.0").unwrap();
+        assert!(store.get("ex/stable/reg/foo/1.0.0").is_err());
+        // Deleting a missing log isn't an error
+        store.delete("ex/stable/reg/foo/1.0.0").unwrap();
+    }
+}
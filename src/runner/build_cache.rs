@@ -0,0 +1,155 @@
+//! Build cache of the experiments enabling `build-cache`. Compilations are wrapped with sccache,
+//! whose disk cache lives on the agent and is mounted in the sandbox, so that the dependencies
+//! shared by many crates are only compiled once. Each worker thread has its own cache, to avoid
+//! concurrent sandboxes writing to the same directory.
+//!
+//! The statistics of the cache are kept in memory by the sccache server, which only lives as long
+//! as the sandbox it was started in. Cargo is thus run through a shell script zeroing them before
+//! the build and printing them after it, in the same sandbox.
+
+use crate::config::{BuildCacheConfig, Config};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::results::BuildCacheStats;
+use rustwide::cmd::{Binary, Command, MountKind, Runnable, SandboxBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SANDBOX_SCCACHE: &str = "/opt/crater/sccache";
+const SANDBOX_CACHE_DIR: &str = "/opt/crater/sccache-cache";
+// Where rustwide installs cargo inside the sandbox
+const SANDBOX_CARGO: &str = "/opt/rustwide/cargo-home/bin/cargo";
+/// Prefix of the line printed by the sandbox with the statistics of the sccache server.
+const STATS_PREFIX: &str = "crater-sccache-stats: ";
+
+pub(super) struct BuildCache<'a> {
+    config: &'a BuildCacheConfig,
+    dir: PathBuf,
+}
+
+impl<'a> BuildCache<'a> {
+    /// The cache of the worker, if the experiment uses it.
+    pub(super) fn new(config: &'a Config, ex: &Experiment, worker: &str) -> Option<Self> {
+        if !ex.build_cache {
+            return None;
+        }
+        // Experiments can't enable the cache without it being configured
+        let config = config.sandbox.build_cache.as_ref()?;
+        Some(BuildCache {
+            config,
+            dir: crate::dirs::WORK_DIR.join("sccache").join(worker),
+        })
+    }
+
+    pub(super) fn mount(&self, sandbox: SandboxBuilder) -> Fallible<SandboxBuilder> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(sandbox
+            .mount(
+                &self.config.sccache,
+                Path::new(SANDBOX_SCCACHE),
+                MountKind::ReadOnly,
+            )
+            .mount(
+                &self.dir,
+                Path::new(SANDBOX_CACHE_DIR),
+                MountKind::ReadWrite,
+            ))
+    }
+
+    /// Environment variables making cargo compile the crates through sccache.
+    pub(super) fn env(&self) -> [(&'static str, String); 3] {
+        [
+            ("RUSTC_WRAPPER", SANDBOX_SCCACHE.into()),
+            ("SCCACHE_DIR", SANDBOX_CACHE_DIR.into()),
+            ("SCCACHE_CACHE_SIZE", self.config.max_size.to_string()),
+        ]
+    }
+}
+
+/// Runs the cargo of a toolchain through bash in the sandbox, printing the statistics of the
+/// sccache server once cargo exits. The exit status of cargo is preserved.
+pub(super) struct CargoWithStats<R: Runnable>(pub(super) R);
+
+impl<R: Runnable> Runnable for CargoWithStats<R> {
+    fn name(&self) -> Binary {
+        Binary::Global("bash".into())
+    }
+
+    fn prepare_command<'w, 'pl>(&self, cmd: Command<'w, 'pl>) -> Command<'w, 'pl> {
+        let script = format!(
+            "{SANDBOX_SCCACHE} --zero-stats > /dev/null; \
+             \"$@\"; status=$?; \
+             echo \"{STATS_PREFIX}$({SANDBOX_SCCACHE} --show-stats --stats-format=json)\"; \
+             exit $status"
+        );
+        // The arguments of the toolchain binary (like `+toolchain`) follow the path of cargo
+        self.0
+            .prepare_command(cmd.args(&["-c", &script, "bash", SANDBOX_CARGO]))
+    }
+}
+
+#[derive(Deserialize)]
+struct ServerInfo {
+    stats: ServerStats,
+}
+
+#[derive(Deserialize)]
+struct ServerStats {
+    cache_hits: PerLanguageCount,
+    cache_misses: PerLanguageCount,
+}
+
+#[derive(Deserialize)]
+struct PerLanguageCount {
+    counts: HashMap<String, u64>,
+}
+
+impl PerLanguageCount {
+    fn total(&self) -> u32 {
+        self.counts
+            .values()
+            .sum::<u64>()
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+}
+
+/// Parse the statistics printed by [`CargoWithStats`], if the line contains them.
+pub(super) fn parse_stats(line: &str) -> Option<BuildCacheStats> {
+    let json = line.strip_prefix(STATS_PREFIX)?;
+    match serde_json::from_str::<ServerInfo>(json) {
+        Ok(info) => Some(BuildCacheStats {
+            hits: info.stats.cache_hits.total(),
+            misses: info.stats.cache_misses.total(),
+        }),
+        Err(err) => {
+            warn!("failed to parse the statistics of sccache: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_stats;
+    use crate::results::BuildCacheStats;
+
+    #[test]
+    fn test_parse_stats() {
+        let line = "crater-sccache-stats: {\"stats\":{\"compile_requests\":45,\
+                    \"cache_hits\":{\"counts\":{\"Rust\":40,\"C/C++\":2},\"adv_counts\":{}},\
+                    \"cache_misses\":{\"counts\":{\"Rust\":3},\"adv_counts\":{}}},\
+                    \"cache_location\":\"Local disk\"}";
+        assert_eq!(
+            parse_stats(line),
+            Some(BuildCacheStats {
+                hits: 42,
+                misses: 3,
+            })
+        );
+
+        assert_eq!(parse_stats("   Compiling foo v1.0.0"), None);
+        assert_eq!(parse_stats("crater-sccache-stats: "), None);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::config::{Config, NetworkPolicy};
+use crate::prelude::*;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+string_enum!(pub enum ContainerRuntime {
+    Docker => "docker",
+    Podman => "podman",
+});
+
+impl ContainerRuntime {
+    /// Prepare the environment of the process to run the sandboxes with this runtime.
+    ///
+    /// rustwide always drives the `docker` CLI, which can talk to the Docker-compatible API of a
+    /// rootless podman service through `DOCKER_HOST`. This has to be called before any thread is
+    /// spawned, as it changes the environment of the process.
+    pub fn configure(self) -> Fallible<()> {
+        match self {
+            ContainerRuntime::Docker => Ok(()),
+            ContainerRuntime::Podman => {
+                if env::var_os("DOCKER_HOST").is_none() {
+                    let socket = podman_socket()?;
+                    if !socket.exists() {
+                        bail!(
+                            "the podman API socket {} doesn't exist, start it with \
+                             `systemctl --user enable --now podman.socket`",
+                            socket.display()
+                        );
+                    }
+                    info!("using the podman API socket {}", socket.display());
+                    env::set_var("DOCKER_HOST", format!("unix://{}", socket.display()));
+                }
+
+                // Without the memory controller the memory limit of the sandbox is silently
+                // ignored, so builds running out of memory would take the whole host down
+                // instead of being reported as OOMs.
+                let controllers = fs::read_to_string("/proc/self/cgroup")
+                    .ok()
+                    .and_then(|cgroup| cgroup_v2_path(&cgroup).map(String::from))
+                    .and_then(|path| {
+                        fs::read_to_string(format!("/sys/fs/cgroup{path}/cgroup.controllers")).ok()
+                    })
+                    .unwrap_or_default();
+                if !controllers.split_whitespace().any(|c| c == "memory") {
+                    bail!(
+                        "the cgroup v2 memory controller is not delegated to this user, which is \
+                         required to enforce the memory limits of rootless podman containers"
+                    );
+                }
+
+                // Without it the files written by the builds in the mounted directories are
+                // owned by subordinate ids, and the agent can't clean them up.
+                let configs = containers_conf_paths()
+                    .into_iter()
+                    .filter_map(|path| fs::read_to_string(path).ok())
+                    .collect::<Vec<_>>();
+                if effective_userns(&configs).as_deref() != Some("keep-id") {
+                    bail!(
+                        "rootless podman containers need `userns = \"keep-id\"` in the \
+                         `[containers]` section of containers.conf, so that the files written \
+                         by the builds are owned by this user"
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Check the runtime supports the sandbox configured for the experiments.
+    pub fn check_config(self, config: &Config) -> Fallible<()> {
+        match self {
+            ContainerRuntime::Docker => Ok(()),
+            ContainerRuntime::Podman => {
+                // Rootless containers are not attached to the bridge the proxy listens on, so
+                // builds would fail to reach the allowed hosts
+                if config.sandbox.network.policy == NetworkPolicy::Allowlist {
+                    bail!(
+                        "the `allowlist` network policy is not supported by rootless podman, \
+                         use the `disabled` policy for the experiments run by this agent"
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Kill the builds running in the sandboxes, by killing the containers of the `docker start`
+/// processes rustwide waits on: rustwide then removes the containers and reports the builds as
+/// failed. Only the containers started by this agent are killed. Returns the number of containers
+/// killed.
+#[cfg(target_os = "linux")]
+pub(crate) fn kill_sandboxes() -> Fallible<usize> {
+    use std::process::Command;
+
+    let agent = std::process::id();
+    let mut containers = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        // The process might have exited since the directory was listed
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if parse_stat(&stat) != Some(("docker", agent)) {
+            continue;
+        }
+        let Ok(cmdline) = fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+        if let Some(id) = started_container(&String::from_utf8_lossy(&cmdline)) {
+            containers.push(id.to_string());
+        }
+    }
+
+    let mut killed = 0;
+    for id in containers {
+        match Command::new("docker").args(["kill", &id]).output() {
+            Ok(output) if output.status.success() => killed += 1,
+            // The build might have completed in the meantime
+            Ok(output) => warn!(
+                "failed to kill container {}: {}",
+                id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => warn!("failed to run docker: {}", err),
+        }
+    }
+    Ok(killed)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn kill_sandboxes() -> Fallible<usize> {
+    Ok(0)
+}
+
+/// Parse the command name and the parent of a process from its `/proc/<pid>/stat`, like
+/// `1234 (docker) S 42 ...`. The command name can contain spaces and parentheses.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_stat(stat: &str) -> Option<(&str, u32)> {
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let comm = stat.get(start + 1..end)?;
+    let ppid = stat[end + 1..].split_whitespace().nth(1)?.parse().ok()?;
+    Some((comm, ppid))
+}
+
+/// Extract the id of the container from the NUL-separated command line of a `docker start`
+/// process, like `docker\0start\0-a\0<id>\0`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn started_container(cmdline: &str) -> Option<&str> {
+    let mut args = cmdline.split('\0').filter(|arg| !arg.is_empty());
+    args.next()?;
+    if args.next()? != "start" {
+        return None;
+    }
+    args.rfind(|arg| !arg.starts_with('-'))
+}
+
+fn podman_socket() -> Fallible<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").ok_or_else(|| {
+        anyhow!("XDG_RUNTIME_DIR is not set, set DOCKER_HOST to the podman API socket instead")
+    })?;
+    Ok(PathBuf::from(runtime_dir)
+        .join("podman")
+        .join("podman.sock"))
+}
+
+/// The containers.conf files read by podman, from the lowest to the highest priority.
+fn containers_conf_paths() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("CONTAINERS_CONF") {
+        return vec![path.into()];
+    }
+
+    let mut paths = vec![
+        PathBuf::from("/usr/share/containers/containers.conf"),
+        PathBuf::from("/etc/containers/containers.conf"),
+    ];
+    let user_config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(dir) = user_config {
+        paths.push(dir.join("containers").join("containers.conf"));
+    }
+    paths
+}
+
+/// The user namespace mode of the containers set by the last of the containers.conf files
+/// setting it.
+fn effective_userns(configs: &[String]) -> Option<String> {
+    #[derive(Deserialize)]
+    struct ContainersConf {
+        #[serde(default)]
+        containers: Containers,
+    }
+
+    #[derive(Deserialize, Default)]
+    struct Containers {
+        userns: Option<String>,
+    }
+
+    configs
+        .iter()
+        .filter_map(|config| match toml::from_str::<ContainersConf>(config) {
+            Ok(conf) => conf.containers.userns,
+            Err(err) => {
+                warn!("failed to parse containers.conf: {}", err);
+                None
+            }
+        })
+        .last()
+}
+
+/// Find the path of the cgroup v2 hierarchy of the process in the content of
+/// `/proc/self/cgroup`.
+fn cgroup_v2_path(proc_cgroup: &str) -> Option<&str> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cgroup_v2_path, effective_userns, parse_stat, started_container};
+
+    #[test]
+    fn test_cgroup_v2_path() {
+        assert_eq!(
+            cgroup_v2_path("0::/user.slice/user-1000.slice/user@1000.service/app.slice\n"),
+            Some("/user.slice/user-1000.slice/user@1000.service/app.slice")
+        );
+        // Hybrid hierarchies list the v1 controllers too
+        assert_eq!(
+            cgroup_v2_path("12:memory:/user.slice\n0::/user.slice\n"),
+            Some("/user.slice")
+        );
+        assert_eq!(cgroup_v2_path("12:memory:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn test_effective_userns() {
+        let keep_id = "[containers]\nuserns = \"keep-id\"\n".to_string();
+        let host = "[containers]\nuserns = \"host\"\n".to_string();
+        let unrelated = "[engine]\ncgroup_manager = \"systemd\"\n".to_string();
+
+        assert_eq!(effective_userns(&[]), None);
+        assert_eq!(effective_userns(&[unrelated.clone()]), None);
+        assert_eq!(
+            effective_userns(&[keep_id.clone(), unrelated.clone()]).as_deref(),
+            Some("keep-id")
+        );
+        // The user configuration overrides the system one
+        assert_eq!(
+            effective_userns(&[keep_id.clone(), host.clone()]).as_deref(),
+            Some("host")
+        );
+        assert_eq!(
+            effective_userns(&[host, unrelated, keep_id]).as_deref(),
+            Some("keep-id")
+        );
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        assert_eq!(
+            parse_stat("1234 (docker) S 42 1234 42 0 -1 4194560"),
+            Some(("docker", 42))
+        );
+        assert_eq!(
+            parse_stat("99 (weird (name) x) R 7 99 7 0"),
+            Some(("weird (name) x", 7))
+        );
+        assert_eq!(parse_stat("99 (truncated"), None);
+    }
+
+    #[test]
+    fn test_started_container() {
+        assert_eq!(
+            started_container("docker\0start\0-a\0f00ba5\0"),
+            Some("f00ba5")
+        );
+        assert_eq!(
+            started_container("/usr/bin/docker\0start\0f00ba5\0"),
+            Some("f00ba5")
+        );
+        // Other commands run by the agent aren't builds
+        assert_eq!(started_container("docker\0ps\0--quiet\0"), None);
+        assert_eq!(started_container("docker\0start\0-a\0"), None);
+    }
+}
@@ -1,17 +1,32 @@
+mod build_cache;
+pub mod container;
+mod network;
+mod parallelism;
+mod quarantine;
+pub mod self_test;
 mod tasks;
 mod test;
+mod toolchains;
 mod unstable_features;
 mod worker;
 
-use crate::config::Config;
-use crate::crates::Crate;
-use crate::experiments::{Experiment, Mode};
+use crate::config::{Config, NetworkPolicy};
+use crate::crates::{Crate, PatchSource};
+use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::TestResult;
+use crate::results::{BuildReport, TestResult};
+use crate::runner::network::NetworkProxy;
+use crate::runner::parallelism::Parallelism;
 use crate::runner::worker::{DiskSpaceWatcher, Worker};
+use crate::toolchain::{RustcVersion, Toolchain};
+pub use parallelism::Threads;
 use rustwide::Workspace;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
 use std::thread::scope;
 use std::time::Duration;
+pub use toolchains::InstallFailed;
 pub use worker::RecordProgress;
 
 const DISK_SPACE_WATCHER_INTERVAL: Duration = Duration::from_secs(30);
@@ -21,13 +36,46 @@ const DISK_SPACE_WATCHER_THRESHOLD: f32 = 0.80;
 #[error("overridden task result to {0}")]
 pub struct OverrideResult(TestResult);
 
+/// A toolchain of the experiment doesn't exist, for example because its CI artifacts were garbage
+/// collected. Nothing can be tested until the experiment is edited.
+#[derive(Debug, thiserror::Error)]
+#[error("toolchain {0} is not available")]
+pub struct ToolchainUnavailable(pub Toolchain);
+
+fn rustc_version(workspace: &Workspace, tc: &Toolchain) -> Fallible<RustcVersion> {
+    let output = rustwide::cmd::Command::new(workspace, tc.rustc())
+        .args(&["-vV"])
+        .log_output(false)
+        .run_capture()?;
+    RustcVersion::from_verbose_output(output.stdout_lines())
+        .ok_or_else(|| anyhow!("unexpected output of rustc -vV for {}", tc))
+}
+
+fn record_rustc_versions(
+    ex: &Experiment,
+    workspace: &Workspace,
+    api: &dyn RecordProgress,
+) -> Fallible<()> {
+    let versions = [
+        rustc_version(workspace, &ex.toolchains[0])?,
+        rustc_version(workspace, &ex.toolchains[1])?,
+    ];
+    info!(
+        "recording the toolchain versions: {} and {}",
+        versions[0], versions[1]
+    );
+    api.record_rustc_versions(ex, &versions)
+}
+
 pub fn run_ex(
     ex: &Experiment,
     workspace: &Workspace,
     api: &dyn RecordProgress,
-    threads_count: usize,
+    threads: Threads,
     config: &Config,
+    only_toolchain: Option<&Toolchain>,
     next_crate: &(dyn Fn() -> Fallible<Option<Crate>> + Send + Sync),
+    interrupted: &AtomicBool,
 ) -> Fallible<()> {
     // Attempt to spin indefinitely until docker is up. Ideally, we would
     // decomission this agent until docker is up, instead of leaving the
@@ -47,7 +95,7 @@ pub fn run_ex(
     // jobs won't be completed.
     let mut i = 0;
     while !rustwide::cmd::docker_running(workspace) {
-        log::error!(
+        error!(
             "docker is not currently up, waiting for it to start (tried {} times)",
             i
         );
@@ -69,28 +117,78 @@ pub fn run_ex(
         }
     }
 
+    // The debug runner can restrict the run to one of the toolchains of the experiment
+    let tested_toolchains = ex
+        .toolchains
+        .iter()
+        .filter(|&tc| only_toolchain.is_none_or(|only| tc == only))
+        .collect::<Vec<_>>();
+
     info!("preparing the execution...");
-    for tc in &ex.toolchains {
-        tc.install(workspace)?;
-        if ex.mode == Mode::Clippy {
-            tc.add_component(workspace, "clippy")?;
+    for &tc in &tested_toolchains {
+        // Only a missing toolchain stops the experiment on every agent, other failures are
+        // problems of this agent
+        toolchains::install(workspace, tc, ex.mode, &config.toolchains).map_err(|err| {
+            if toolchains::is_missing(&err) {
+                err.context(ToolchainUnavailable(tc.clone()))
+            } else {
+                err
+            }
+        })?;
+    }
+
+    for krate in &ex.registry_overlay {
+        krate.unpack()?;
+    }
+    for patch in &ex.dependency_patches {
+        if let PatchSource::Path { path } = &patch.source {
+            if !Path::new(path).is_dir() {
+                bail!(
+                    "the {} patch points to {}, which is not a directory",
+                    patch.name,
+                    path
+                );
+            }
         }
-        if let Some(requested_target) = &tc.target {
-            tc.add_target(workspace, requested_target)?;
+    }
+
+    // Runs restricted to a single toolchain by run_crate don't record the versions
+    if only_toolchain.is_none() && ex.rustc_versions.iter().any(Option::is_none) {
+        if let Err(err) = record_rustc_versions(ex, workspace, api) {
+            warn!("failed to record the versions of the toolchains: {:?}", err);
         }
     }
 
-    info!("running tasks in {} threads...", threads_count);
+    // The proxy is stopped when this function returns
+    let _proxy = if config.sandbox.network.policy == NetworkPolicy::Allowlist {
+        Some(NetworkProxy::spawn(&config.sandbox.network)?)
+    } else {
+        None
+    };
 
-    let workers = (0..threads_count)
+    if threads.is_tuned() {
+        info!(
+            "running tasks in {} to {} threads, starting with {}...",
+            threads.min, threads.max, threads.initial
+        );
+    } else {
+        info!("running tasks in {} threads...", threads.initial);
+    }
+
+    let parallelism = Parallelism::new(threads);
+    let workers = (0..threads.max)
         .map(|i| {
             Worker::new(
                 format!("worker-{i}"),
+                i,
                 workspace,
                 ex,
+                &tested_toolchains,
                 config,
                 api,
                 next_crate,
+                &parallelism,
+                interrupted,
             )
         })
         .collect::<Vec<_>>();
@@ -108,16 +206,28 @@ pub fn run_ex(
                 disk_watcher.run();
             })
             .unwrap();
+        if threads.is_tuned() {
+            std::thread::Builder::new()
+                .name("parallelism-tuner".into())
+                .spawn_scoped(scope1, || {
+                    parallelism.run();
+                })
+                .unwrap();
+        }
 
         scope(|scope| {
+            let parallelism = &parallelism;
             for worker in &workers {
                 std::thread::Builder::new()
                     .name(worker.name().into())
                     .spawn_scoped(scope, move || -> Fallible<()> {
-                        match worker.run() {
+                        let result = worker.run();
+                        // The paused workers would otherwise wait for a slot forever
+                        parallelism.finish();
+                        match result {
                             Ok(()) => Ok(()),
                             Err(r) => {
-                                log::warn!("worker {} failed: {:?}", worker.name(), r);
+                                warn!("worker {} failed: {:?}", worker.name(), r);
                                 Err(r)
                             }
                         }
@@ -127,7 +237,71 @@ pub fn run_ex(
         });
 
         disk_watcher.stop();
+        parallelism.stop();
     });
 
     Ok(())
 }
+
+/// Prints the logs and the results instead of recording them.
+struct PrintProgress;
+
+impl RecordProgress for PrintProgress {
+    fn record_progress(
+        &self,
+        _ex: &Experiment,
+        krate: &Crate,
+        toolchain: &Toolchain,
+        log: &[u8],
+        result: &TestResult,
+        report: &BuildReport,
+        _version: Option<(&Crate, &Crate)>,
+    ) -> Fallible<()> {
+        println!("{}", String::from_utf8_lossy(log));
+        println!("result of {krate} on {toolchain}: {result}");
+        if *report != BuildReport::default() {
+            println!(
+                "report of {krate} on {toolchain}: {}",
+                serde_json::to_string(report)?
+            );
+        }
+        Ok(())
+    }
+
+    fn record_rustc_versions(
+        &self,
+        _ex: &Experiment,
+        _versions: &[RustcVersion; 2],
+    ) -> Fallible<()> {
+        Ok(())
+    }
+}
+
+/// Runs a single crate of the experiment exactly like the agents do, printing the logs and the
+/// results they would record instead of storing them. Only `toolchain` is tested if provided.
+pub fn run_crate(
+    ex: &Experiment,
+    workspace: &Workspace,
+    config: &Config,
+    krate: &Crate,
+    toolchain: Option<&Toolchain>,
+) -> Fallible<()> {
+    if let Some(tc) = toolchain {
+        if !ex.toolchains.contains(tc) {
+            bail!("toolchain {} is not part of the experiment {}", tc, ex.name);
+        }
+    }
+
+    let krate = Mutex::new(Some(krate.clone()));
+    let next_crate = || Ok(krate.lock().unwrap().take());
+    run_ex(
+        ex,
+        workspace,
+        &PrintProgress,
+        Threads::fixed(1),
+        config,
+        toolchain,
+        &next_crate,
+        &AtomicBool::new(false),
+    )
+}
@@ -0,0 +1,451 @@
+//! Proxy enforcing the network policy of the sandbox.
+//!
+//! With the `allowlist` policy the sandbox is started on the Docker bridge, which the agent
+//! firewalls so that the proxy is the only destination the sandbox can reach. Every HTTP client
+//! inside it is pointed to the proxy, which only tunnels connections to the allowed hosts.
+//!
+//! The firewall rules are removed when the proxy stops, and only the configured ports of the
+//! allowed hosts can be reached through it.
+//!
+//! The clients authenticate to the proxy with the ID of their build, so that the connections it
+//! refused can be attributed to the build which attempted them.
+
+use crate::config::NetworkConfig;
+use crate::prelude::*;
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Maximum size of the request head sent by the clients, to avoid buffering garbage forever.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of connections tunneled at the same time, each of them using two threads.
+const MAX_CONNECTIONS: usize = 64;
+/// Maximum number of refused hosts recorded for each build.
+const MAX_REFUSED_HOSTS: usize = 16;
+
+lazy_static! {
+    /// Hosts the proxy refused to connect to, for each build being watched.
+    static ref REFUSED_HOSTS: Mutex<HashMap<String, Vec<String>>> = Mutex::default();
+}
+
+/// Start recording the hosts the proxy refuses to connect to on behalf of the build.
+pub(super) fn watch(build_id: &str) {
+    REFUSED_HOSTS
+        .lock()
+        .unwrap()
+        .insert(build_id.to_string(), Vec::new());
+}
+
+/// Stop watching the build, returning the hosts the proxy refused to connect to on its behalf.
+pub(super) fn take_refused_hosts(build_id: &str) -> Vec<String> {
+    REFUSED_HOSTS
+        .lock()
+        .unwrap()
+        .remove(build_id)
+        .unwrap_or_default()
+}
+
+fn record_refused_host(build_id: &str, host: &str) {
+    // Builds not being watched can't be attributed anything, and could grow the map forever
+    if let Some(hosts) = REFUSED_HOSTS.lock().unwrap().get_mut(build_id) {
+        if hosts.len() < MAX_REFUSED_HOSTS && !hosts.iter().any(|h| h == host) {
+            hosts.push(host.to_string());
+        }
+    }
+}
+
+/// Returns whether the host is one of the allowed hosts or one of their subdomains.
+fn is_host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_lowercase();
+        host == allowed
+            || host
+                .strip_suffix(&allowed)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Parses the request line of a `CONNECT host:port HTTP/1.1` request.
+fn parse_connect(request_line: &str) -> Option<(String, u16)> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "CONNECT" {
+        return None;
+    }
+    let (host, port) = parts.next()?.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+/// Parses the build ID out of a `Proxy-Authorization: Basic ...` header, which is the user the
+/// clients found in the URL of the proxy.
+fn parse_proxy_authorization(header: &str) -> Option<String> {
+    let (name, value) = header.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("proxy-authorization") {
+        return None;
+    }
+    let credentials = value.trim().strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let user = decoded.split(':').next()?;
+    Some(user.to_string())
+}
+
+fn respond(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )
+}
+
+fn handle_client(client: TcpStream, config: &NetworkConfig) -> Fallible<()> {
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Only the request line and the build the client authenticated as matter
+    let mut build_id = None;
+    let mut head_size = request_line.len();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line)?;
+        head_size += read;
+        if read == 0 || line == "\r\n" || line == "\n" || head_size > MAX_REQUEST_HEAD {
+            break;
+        }
+        if let Some(id) = parse_proxy_authorization(&line) {
+            build_id = Some(id);
+        }
+    }
+
+    let mut client = client;
+    let Some((host, port)) = parse_connect(&request_line) else {
+        // Only tunnels are supported: all the allowed hosts are served over HTTPS
+        respond(&mut client, "405 Method Not Allowed")?;
+        return Ok(());
+    };
+    if !is_host_allowed(&host, &config.allowed_hosts) || !config.allowed_ports.contains(&port) {
+        info!(
+            "sandbox network policy blocked a connection to {}:{}",
+            host, port
+        );
+        if let Some(build_id) = &build_id {
+            record_refused_host(build_id, &host);
+        }
+        respond(&mut client, "403 Forbidden")?;
+        return Ok(());
+    }
+
+    let upstream = match resolve(&host, port)
+        .and_then(|addr| Ok(TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?))
+    {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            warn!("failed to connect to {}:{}: {}", host, port, err);
+            respond(&mut client, "502 Bad Gateway")?;
+            return Ok(());
+        }
+    };
+    client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+
+    // The reader still holds the bytes the client sent after the request head, which already
+    // belong to the tunnel
+    let mut upstream_writer = upstream.try_clone()?;
+    let mut client_writer = client.try_clone()?;
+    let upload = thread::spawn(move || -> io::Result<()> {
+        io::copy(&mut reader, &mut upstream_writer)?;
+        upstream_writer.shutdown(Shutdown::Write)
+    });
+    let mut upstream_reader = upstream;
+    io::copy(&mut upstream_reader, &mut client_writer)?;
+    let _ = client_writer.shutdown(Shutdown::Write);
+    let _ = upload.join();
+
+    Ok(())
+}
+
+fn resolve(host: &str, port: u16) -> Fallible<SocketAddr> {
+    use std::net::ToSocketAddrs;
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("failed to resolve {}", host))
+}
+
+/// Firewall rules confining the sandboxes on the Docker bridge to the proxy, as the chain they
+/// belong to and the rule itself. Traffic leaving the bridge is rejected, and only the port of the
+/// proxy is reachable on the host. Rules are listed in the order they have in their chain.
+fn isolation_rules(config: &NetworkConfig) -> Vec<(&'static str, Vec<String>)> {
+    let bridge = config.bridge.as_str();
+    let port = config.proxy_port.to_string();
+    [
+        ("DOCKER-USER", vec!["-i", bridge, "-j", "REJECT"]),
+        (
+            "INPUT",
+            vec![
+                "-i",
+                bridge,
+                "-p",
+                "tcp",
+                "-d",
+                config.proxy_host.as_str(),
+                "--dport",
+                port.as_str(),
+                "-j",
+                "ACCEPT",
+            ],
+        ),
+        ("INPUT", vec!["-i", bridge, "-j", "REJECT"]),
+    ]
+    .into_iter()
+    .map(|(chain, rule)| (chain, rule.into_iter().map(String::from).collect()))
+    .collect()
+}
+
+fn iptables(args: &[&str], rule: &[String]) -> Fallible<bool> {
+    let status = Command::new("iptables")
+        .args(args)
+        .args(rule)
+        .status()
+        .context("failed to run iptables")?;
+    Ok(status.success())
+}
+
+/// Install the firewall rules isolating the sandboxes, unless they're already present, returning
+/// the rules which were installed. rustwide can only start the sandbox on the default Docker
+/// network, so the isolation has to be enforced on its bridge.
+fn isolate_sandboxes(config: &NetworkConfig) -> Fallible<Vec<(&'static str, Vec<String>)>> {
+    let mut installed = Vec::new();
+    let mut position = 0;
+    let mut previous_chain = "";
+    for (chain, rule) in isolation_rules(config) {
+        if chain != previous_chain {
+            previous_chain = chain;
+            position = 0;
+        }
+        position += 1;
+        if iptables(&["-C", chain], &rule)? {
+            continue;
+        }
+        if !iptables(&["-I", chain, &position.to_string()], &rule)? {
+            remove_rules(&installed);
+            bail!(
+                "failed to isolate the sandboxes on {} with the rule `{} {}`",
+                config.bridge,
+                chain,
+                rule.join(" ")
+            );
+        }
+        info!(
+            "isolated the sandboxes with the rule `{} {}`",
+            chain,
+            rule.join(" ")
+        );
+        installed.push((chain, rule));
+    }
+    Ok(installed)
+}
+
+/// Remove the firewall rules installed by `isolate_sandboxes`, leaving the bridge as it was.
+fn remove_rules(rules: &[(&'static str, Vec<String>)]) {
+    for (chain, rule) in rules {
+        match iptables(&["-D", chain], rule) {
+            Ok(true) => {}
+            Ok(false) => warn!("failed to remove the rule `{} {}`", chain, rule.join(" ")),
+            Err(err) => warn!(
+                "failed to remove the rule `{} {}`: {}",
+                chain,
+                rule.join(" "),
+                err
+            ),
+        }
+    }
+}
+
+/// Proxy running in the background for as long as this value is alive.
+pub(super) struct NetworkProxy {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    isolation_rules: Vec<(&'static str, Vec<String>)>,
+}
+
+impl NetworkProxy {
+    pub(super) fn spawn(config: &NetworkConfig) -> Fallible<Self> {
+        let isolation_rules = isolate_sandboxes(config)?;
+
+        let listener = match TcpListener::bind((config.proxy_host.as_str(), config.proxy_port)) {
+            Ok(listener) => listener,
+            Err(err) => {
+                remove_rules(&isolation_rules);
+                return Err(err).with_context(|| {
+                    format!(
+                        "failed to bind the network proxy to {}:{}",
+                        config.proxy_host, config.proxy_port
+                    )
+                });
+            }
+        };
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        info!("network proxy for the sandbox listening on {}", addr);
+
+        let config = Arc::new(config.clone());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let thread_stop = stop.clone();
+        thread::Builder::new()
+            .name("network-proxy".into())
+            .spawn(move || {
+                for client in listener.incoming() {
+                    if thread_stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(mut client) = client else { continue };
+                    if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                        warn!("network proxy refused a connection: too many open connections");
+                        let _ = respond(&mut client, "503 Service Unavailable");
+                        continue;
+                    }
+                    let config = config.clone();
+                    let connections = connections.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = handle_client(client, &config) {
+                            debug!("network proxy connection failed: {}", err);
+                        }
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+            })?;
+
+        Ok(NetworkProxy {
+            addr,
+            stop,
+            isolation_rules,
+        })
+    }
+}
+
+impl Drop for NetworkProxy {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the listener thread so that it notices it has to stop
+        let _ = TcpStream::connect(self.addr);
+        remove_rules(&self.isolation_rules);
+    }
+}
+
+/// Environment variables pointing the HTTP clients inside the sandbox to the proxy, which they
+/// authenticate to with the ID of the build.
+pub(super) fn proxy_env(config: &NetworkConfig, build_id: &str) -> Vec<(&'static str, String)> {
+    let url = format!(
+        "http://{}@{}:{}",
+        build_id, config.proxy_host, config.proxy_port
+    );
+    [
+        "CARGO_HTTP_PROXY",
+        "HTTP_PROXY",
+        "HTTPS_PROXY",
+        "http_proxy",
+        "https_proxy",
+    ]
+    .into_iter()
+    .map(|var| (var, url.clone()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_host_allowed, isolation_rules, parse_connect, parse_proxy_authorization,
+        record_refused_host, take_refused_hosts, watch,
+    };
+    use crate::config::NetworkConfig;
+
+    #[test]
+    fn test_is_host_allowed() {
+        let allowed = vec!["crates.io".to_string(), "github.com".to_string()];
+        assert!(is_host_allowed("crates.io", &allowed));
+        assert!(is_host_allowed("static.crates.io", &allowed));
+        assert!(is_host_allowed("GitHub.com.", &allowed));
+        assert!(!is_host_allowed("evilcrates.io", &allowed));
+        assert!(!is_host_allowed("crates.io.example.com", &allowed));
+    }
+
+    #[test]
+    fn test_parse_connect() {
+        assert_eq!(
+            parse_connect("CONNECT static.crates.io:443 HTTP/1.1\r\n"),
+            Some(("static.crates.io".into(), 443))
+        );
+        assert_eq!(
+            parse_connect("CONNECT [::1]:8080 HTTP/1.1\r\n"),
+            Some(("::1".into(), 8080))
+        );
+        assert_eq!(parse_connect("GET http://example.com/ HTTP/1.1\r\n"), None);
+        assert_eq!(parse_connect("CONNECT example.com HTTP/1.1\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_proxy_authorization() {
+        assert_eq!(
+            parse_proxy_authorization("Proxy-Authorization: Basic YnVpbGQtMTI6\r\n"),
+            Some("build-12".into())
+        );
+        assert_eq!(
+            parse_proxy_authorization("proxy-authorization: Basic YnVpbGQtMTI=\r\n"),
+            Some("build-12".into())
+        );
+        assert_eq!(
+            parse_proxy_authorization("Authorization: Basic YnVpbGQtMTI6\r\n"),
+            None
+        );
+        assert_eq!(
+            parse_proxy_authorization("Proxy-Authorization: Bearer foo\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_refused_hosts() {
+        record_refused_host("unwatched", "example.com");
+        assert!(take_refused_hosts("unwatched").is_empty());
+
+        watch("build");
+        record_refused_host("build", "example.com");
+        record_refused_host("build", "example.com");
+        record_refused_host("build", "pool.example.org");
+        assert_eq!(
+            take_refused_hosts("build"),
+            vec!["example.com".to_string(), "pool.example.org".to_string()]
+        );
+        assert!(take_refused_hosts("build").is_empty());
+    }
+
+    #[test]
+    fn test_isolation_rules() {
+        let rules: Vec<_> = isolation_rules(&NetworkConfig::default())
+            .into_iter()
+            .map(|(chain, rule)| format!("{chain} {}", rule.join(" ")))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "DOCKER-USER -i docker0 -j REJECT",
+                "INPUT -i docker0 -p tcp -d 172.17.0.1 --dport 3128 -j ACCEPT",
+                "INPUT -i docker0 -j REJECT",
+            ]
+        );
+    }
+}
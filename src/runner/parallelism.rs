@@ -0,0 +1,316 @@
+//! Auto-tuning of the number of workers of an agent. How many crates can be built at the same
+//! time depends as much on the crates as on the hardware of the agent, so agents started with
+//! bounds on their workers periodically look at the memory pressure, the load average and the OOM
+//! kills of the host, and change how many workers test crates. Workers only stop or resume
+//! between crates: the ones beyond the current limit wait for it to be raised again.
+
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+const TUNING_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the paused workers check whether the experiment was interrupted.
+const PAUSED_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Share of the memory available below which workers are removed.
+const LOW_MEMORY: f64 = 0.1;
+/// Share of the memory that must be available to add a worker.
+const SPARE_MEMORY: f64 = 0.3;
+/// Load average per CPU above which workers are removed. Builds are parallel, so a busy host
+/// commonly has a load higher than its number of CPUs.
+const HIGH_LOAD: f64 = 2.0;
+/// Load average per CPU below which a worker can be added.
+const SPARE_LOAD: f64 = 1.0;
+/// Samples without OOM kills needed before adding workers again.
+const OOM_COOLDOWN: u32 = 5;
+
+/// Number of workers running the crates of an experiment. The number is tuned within the bounds
+/// when they differ, starting from `initial`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threads {
+    pub initial: usize,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Threads {
+    pub fn fixed(count: usize) -> Self {
+        Threads {
+            initial: count,
+            min: count,
+            max: count,
+        }
+    }
+
+    pub fn tuned(initial: usize, min: usize, max: usize) -> Fallible<Self> {
+        if min == 0 || min > max {
+            bail!(
+                "invalid bounds of the number of threads: {} to {}",
+                min,
+                max
+            );
+        }
+        Ok(Threads {
+            initial: initial.clamp(min, max),
+            min,
+            max,
+        })
+    }
+
+    pub(super) fn is_tuned(&self) -> bool {
+        self.min != self.max
+    }
+}
+
+/// Pressure on the resources of the host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HostSample {
+    /// Share of the memory available to new processes.
+    available_memory: f64,
+    /// One-minute load average, divided by the number of CPUs.
+    load_per_cpu: f64,
+    /// Processes killed by the OOM killer since the host booted.
+    oom_kills: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn sample_host() -> Fallible<HostSample> {
+    use std::fs;
+
+    let cpus = std::thread::available_parallelism()?.get();
+    let available_memory = parse_meminfo(&fs::read_to_string("/proc/meminfo")?)
+        .ok_or_else(|| anyhow!("unexpected content of /proc/meminfo"))?;
+    let load = parse_loadavg(&fs::read_to_string("/proc/loadavg")?)
+        .ok_or_else(|| anyhow!("unexpected content of /proc/loadavg"))?;
+    Ok(HostSample {
+        available_memory,
+        load_per_cpu: load / cpus as f64,
+        oom_kills: parse_oom_kills(&fs::read_to_string("/proc/vmstat")?),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sample_host() -> Fallible<HostSample> {
+    bail!("tuning the number of threads is only supported on Linux")
+}
+
+fn parse_meminfo(meminfo: &str) -> Option<f64> {
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(':')?
+                .split_whitespace()
+                .next()?
+                .parse::<f64>()
+                .ok()
+        })
+    };
+    let total = field("MemTotal")?;
+    (total > 0.0).then_some(field("MemAvailable")? / total)
+}
+
+fn parse_loadavg(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Kernels older than 4.13 don't count the OOM kills, which are then never detected.
+fn parse_oom_kills(vmstat: &str) -> u64 {
+    vmstat
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill ")?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Number of workers to run after looking at the host. Workers are removed one at a time as soon
+/// as the host is under pressure, and added one at a time when it has been idle enough.
+fn next_limit(
+    threads: Threads,
+    limit: usize,
+    sample: &HostSample,
+    samples_since_oom: u32,
+) -> usize {
+    if samples_since_oom == 0
+        || sample.available_memory < LOW_MEMORY
+        || sample.load_per_cpu > HIGH_LOAD
+    {
+        limit.saturating_sub(1).max(threads.min)
+    } else if samples_since_oom >= OOM_COOLDOWN
+        && sample.available_memory > SPARE_MEMORY
+        && sample.load_per_cpu < SPARE_LOAD
+    {
+        (limit + 1).min(threads.max)
+    } else {
+        limit
+    }
+}
+
+struct Slots {
+    limit: usize,
+    finished: bool,
+}
+
+pub(super) struct Parallelism {
+    threads: Threads,
+    slots: Mutex<Slots>,
+    slots_changed: Condvar,
+    should_stop: Mutex<bool>,
+    waiter: Condvar,
+}
+
+impl Parallelism {
+    pub(super) fn new(threads: Threads) -> Self {
+        Parallelism {
+            threads,
+            slots: Mutex::new(Slots {
+                limit: threads.initial,
+                finished: false,
+            }),
+            slots_changed: Condvar::new(),
+            should_stop: Mutex::new(false),
+            waiter: Condvar::new(),
+        }
+    }
+
+    /// Wait until the worker with the given slot is allowed to test a crate. Returns false if
+    /// the worker should stop instead, because the other workers stopped or the experiment was
+    /// interrupted.
+    pub(super) fn wait_for_slot(&self, slot: usize, interrupted: &AtomicBool) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        loop {
+            if slot < slots.limit {
+                return true;
+            }
+            if slots.finished || interrupted.load(Ordering::SeqCst) {
+                return false;
+            }
+            slots = self
+                .slots_changed
+                .wait_timeout(slots, PAUSED_CHECK_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Stop the paused workers, called when a worker stops: either no crates are left, or the
+    /// experiment can't be run anymore.
+    pub(super) fn finish(&self) {
+        self.slots.lock().unwrap().finished = true;
+        self.slots_changed.notify_all();
+    }
+
+    pub(super) fn stop(&self) {
+        *self.should_stop.lock().unwrap() = true;
+        self.waiter.notify_all();
+    }
+
+    /// Tune the number of workers until stopped.
+    pub(super) fn run(&self) {
+        let mut last_oom_kills = None;
+        let mut samples_since_oom = OOM_COOLDOWN;
+
+        let mut should_stop = self.should_stop.lock().unwrap();
+        while !*should_stop {
+            match sample_host() {
+                Ok(sample) => {
+                    if last_oom_kills.is_some_and(|last| sample.oom_kills > last) {
+                        warn!("processes were killed by the OOM killer");
+                        samples_since_oom = 0;
+                    } else {
+                        samples_since_oom = samples_since_oom.saturating_add(1);
+                    }
+                    last_oom_kills = Some(sample.oom_kills);
+                    self.tune(&sample, samples_since_oom);
+                }
+                Err(err) => warn!("failed to sample the resources of the host: {}", err),
+            }
+            should_stop = self
+                .waiter
+                .wait_timeout(should_stop, TUNING_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn tune(&self, sample: &HostSample, samples_since_oom: u32) {
+        let mut slots = self.slots.lock().unwrap();
+        let limit = next_limit(self.threads, slots.limit, sample, samples_since_oom);
+        if limit != slots.limit {
+            info!(
+                "running {} workers instead of {} (available memory: {:.0}%, load per cpu: {:.2})",
+                limit,
+                slots.limit,
+                sample.available_memory * 100.0,
+                sample.load_per_cpu
+            );
+            slots.limit = limit;
+            self.slots_changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        next_limit, parse_loadavg, parse_meminfo, parse_oom_kills, HostSample, Parallelism,
+        Threads, OOM_COOLDOWN,
+    };
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_parse_proc() {
+        let meminfo = "MemTotal:       16000000 kB\nMemFree:         1000000 kB\n\
+                       MemAvailable:    4000000 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(0.25));
+        assert_eq!(parse_meminfo("MemTotal: 100 kB\n"), None);
+        assert_eq!(parse_loadavg("3.52 2.10 1.05 4/812 12345\n"), Some(3.52));
+        assert_eq!(parse_oom_kills("pgfault 123\noom_kill 7\n"), 7);
+        assert_eq!(parse_oom_kills("pgfault 123\n"), 0);
+    }
+
+    #[test]
+    fn test_next_limit() {
+        let threads = Threads::tuned(4, 2, 6).unwrap();
+        let sample = |available_memory, load_per_cpu| HostSample {
+            available_memory,
+            load_per_cpu,
+            oom_kills: 0,
+        };
+
+        let idle = sample(0.5, 0.5);
+        assert_eq!(next_limit(threads, 4, &idle, OOM_COOLDOWN), 5);
+        assert_eq!(next_limit(threads, 6, &idle, OOM_COOLDOWN), 6);
+        // Workers are only added again a while after an OOM kill
+        assert_eq!(next_limit(threads, 4, &idle, 0), 3);
+        assert_eq!(next_limit(threads, 4, &idle, 1), 4);
+
+        assert_eq!(next_limit(threads, 4, &sample(0.05, 0.5), OOM_COOLDOWN), 3);
+        assert_eq!(next_limit(threads, 4, &sample(0.5, 2.5), OOM_COOLDOWN), 3);
+        assert_eq!(next_limit(threads, 2, &sample(0.5, 2.5), OOM_COOLDOWN), 2);
+        // Neither idle nor under pressure
+        assert_eq!(next_limit(threads, 4, &sample(0.2, 1.5), OOM_COOLDOWN), 4);
+    }
+
+    #[test]
+    fn test_threads() {
+        assert_eq!(Threads::tuned(8, 1, 4).unwrap().initial, 4);
+        assert!(Threads::tuned(1, 0, 4).is_err());
+        assert!(Threads::tuned(1, 4, 2).is_err());
+        assert!(!Threads::fixed(3).is_tuned());
+    }
+
+    #[test]
+    fn test_wait_for_slot() {
+        let parallelism = Parallelism::new(Threads::tuned(2, 1, 4).unwrap());
+        let interrupted = AtomicBool::new(false);
+        assert!(parallelism.wait_for_slot(1, &interrupted));
+
+        interrupted.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(!parallelism.wait_for_slot(2, &interrupted));
+        interrupted.store(false, std::sync::atomic::Ordering::SeqCst);
+
+        parallelism.finish();
+        assert!(!parallelism.wait_for_slot(3, &interrupted));
+        // Workers within the limit finish their work
+        assert!(parallelism.wait_for_slot(0, &interrupted));
+    }
+}
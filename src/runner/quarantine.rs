@@ -0,0 +1,235 @@
+//! Detection of builds behaving like malware rather than like a crate being compiled.
+//!
+//! Anything the build prints is controlled by the crate, so the detection relies on what the
+//! sandbox observed instead: the connections the network proxy refused on behalf of the build,
+//! and the processes and CPU time used by its container, sampled through the container runtime.
+//! Suspicious builds are quarantined by the server as soon as their result is recorded, and stay
+//! quarantined until a human reviews them and either confirms the quarantine or lifts it.
+
+use crate::prelude::*;
+use crate::results::Quarantine;
+use crate::runner::network;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Interval between the samples of the resources used by the container of the build.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Processes running at the same time in the container above which the build is suspicious.
+const MAX_PIDS: u32 = 1024;
+/// CPU time below which the build is never considered suspicious, in seconds.
+const MIN_SUSPICIOUS_CPU_TIME: f64 = 3600.0;
+/// CPU time used for each compilation unit above which the build is suspicious, in seconds.
+/// Compiling a unit rarely takes more than a few minutes, while cryptocurrency miners keep all
+/// the cores busy for as long as they run.
+const MAX_CPU_TIME_PER_UNIT: f64 = 600.0;
+
+/// What the sandbox observed while the build ran.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct SandboxSignals {
+    /// Hosts outside of the network allowlist the build tried to connect to.
+    pub(super) refused_hosts: Vec<String>,
+    /// Highest number of processes running at the same time in the container.
+    pub(super) max_pids: u32,
+    /// CPU time used by the container, in seconds.
+    pub(super) cpu_time: f64,
+}
+
+/// Watches the sandbox of a build until [`SandboxMonitor::finish`] is called.
+pub(super) struct SandboxMonitor {
+    build_id: String,
+    stop: Arc<AtomicBool>,
+    sampler: Option<JoinHandle<SandboxSignals>>,
+}
+
+impl SandboxMonitor {
+    /// Start watching the build. Its containers are found through the target directory they
+    /// mount, which is only used by the builds of one worker.
+    pub(super) fn start(build_id: &str, target_dir: &Path) -> Self {
+        network::watch(build_id);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let target_dir = target_dir.to_string_lossy().into_owned();
+        let sampler = thread::Builder::new()
+            .name("sandbox-monitor".into())
+            .spawn(move || {
+                let mut signals = SandboxSignals::default();
+                let mut last_sample = Instant::now();
+                while !thread_stop.load(Ordering::SeqCst) {
+                    thread::park_timeout(SAMPLE_INTERVAL);
+                    match sample(&target_dir) {
+                        Ok(Some((cpu_percent, pids))) => {
+                            signals.max_pids = signals.max_pids.max(pids);
+                            signals.cpu_time +=
+                                cpu_percent / 100.0 * last_sample.elapsed().as_secs_f64();
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            debug!("failed to sample the resources of the sandbox: {:?}", err);
+                            break;
+                        }
+                    }
+                    last_sample = Instant::now();
+                }
+                signals
+            });
+        let sampler = match sampler {
+            Ok(sampler) => Some(sampler),
+            Err(err) => {
+                warn!("failed to start monitoring the sandbox: {}", err);
+                None
+            }
+        };
+
+        SandboxMonitor {
+            build_id: build_id.to_string(),
+            stop,
+            sampler,
+        }
+    }
+
+    /// Stop watching the build, returning what the sandbox observed.
+    pub(super) fn finish(mut self) -> SandboxSignals {
+        self.stop.store(true, Ordering::SeqCst);
+        let mut signals = match self.sampler.take() {
+            Some(sampler) => {
+                sampler.thread().unpark();
+                sampler.join().unwrap_or_default()
+            }
+            None => SandboxSignals::default(),
+        };
+        signals.refused_hosts = network::take_refused_hosts(&self.build_id);
+        signals
+    }
+}
+
+/// Sample the CPU usage (in percent of a core) and the number of processes of the container
+/// mounting the target directory, if one is running.
+fn sample(target_dir: &str) -> Fallible<Option<(f64, u32)>> {
+    let containers = docker(&["ps", "--quiet", "--filter", &format!("volume={target_dir}")])?;
+    let Some(container) = containers.lines().next() else {
+        return Ok(None);
+    };
+    let stats = docker(&[
+        "stats",
+        "--no-stream",
+        "--format",
+        "{{.CPUPerc}} {{.PIDs}}",
+        container,
+    ])?;
+    // The container might have exited in the meantime
+    Ok(parse_stats(&stats))
+}
+
+fn docker(args: &[&str]) -> Fallible<String> {
+    let output = Command::new("docker")
+        .args(args)
+        .output()
+        .context("failed to run docker")?;
+    if !output.status.success() {
+        bail!(
+            "docker {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse the output of `docker stats --format "{{.CPUPerc}} {{.PIDs}}"`, like `123.45% 17`.
+fn parse_stats(stats: &str) -> Option<(f64, u32)> {
+    let mut parts = stats.split_whitespace();
+    let cpu_percent = parts.next()?.strip_suffix('%')?.parse().ok()?;
+    let pids = parts.next()?.parse().ok()?;
+    Some((cpu_percent, pids))
+}
+
+/// Flag the build if the sandbox observed suspicious behavior. `units` is the number of
+/// compilation units built, when it was measured.
+pub(super) fn detect(signals: &SandboxSignals, units: Option<u32>) -> Option<Quarantine> {
+    let units = units.unwrap_or(0).max(1);
+    let reason = if !signals.refused_hosts.is_empty() {
+        format!(
+            "connections to hosts outside of the network allowlist: {}",
+            signals.refused_hosts.join(", ")
+        )
+    } else if signals.max_pids > MAX_PIDS {
+        format!(
+            "excessive process spawning: {} processes running at the same time",
+            signals.max_pids
+        )
+    } else if signals.cpu_time > MIN_SUSPICIOUS_CPU_TIME
+        && signals.cpu_time / f64::from(units) > MAX_CPU_TIME_PER_UNIT
+    {
+        format!(
+            "excessive CPU usage, like cryptocurrency mining: {:.0} seconds of CPU time for {} \
+             compilation units",
+            signals.cpu_time, units
+        )
+    } else {
+        return None;
+    };
+    Some(Quarantine { reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, parse_stats, SandboxSignals};
+
+    #[test]
+    fn test_parse_stats() {
+        assert_eq!(parse_stats("123.45% 17\n"), Some((123.45, 17)));
+        assert_eq!(parse_stats("0.00% 0"), Some((0.0, 0)));
+        assert_eq!(parse_stats("--% --"), None);
+        assert_eq!(parse_stats(""), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        // A regular build, using a lot of CPU time to compile many units
+        let build = SandboxSignals {
+            refused_hosts: Vec::new(),
+            max_pids: 200,
+            cpu_time: 20_000.0,
+        };
+        assert!(detect(&build, Some(300)).is_none());
+        assert!(detect(&SandboxSignals::default(), None).is_none());
+
+        let quarantine = detect(
+            &SandboxSignals {
+                refused_hosts: vec!["pool.example.com".into()],
+                ..SandboxSignals::default()
+            },
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(
+            quarantine.reason,
+            "connections to hosts outside of the network allowlist: pool.example.com"
+        );
+
+        let fork_bomb = SandboxSignals {
+            max_pids: 4096,
+            ..SandboxSignals::default()
+        };
+        assert!(detect(&fork_bomb, Some(10))
+            .unwrap()
+            .reason
+            .starts_with("excessive process spawning"));
+
+        // Hours of CPU time spent building a handful of units
+        let miner = SandboxSignals {
+            cpu_time: 7200.0,
+            ..SandboxSignals::default()
+        };
+        assert!(detect(&miner, Some(3))
+            .unwrap()
+            .reason
+            .starts_with("excessive CPU usage"));
+        assert!(detect(&miner, Some(100)).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+//! Self-test of the sandbox of an agent, checking the host is able to run builds correctly before
+//! it starts accepting them. Broken hosts would otherwise only be noticed when their builds fail
+//! in ways no crate would.
+
+use crate::config::{Config, NetworkPolicy};
+use crate::prelude::*;
+use crate::runner::network::NetworkProxy;
+use rustwide::cmd::{Command, CommandError, SandboxBuilder};
+use rustwide::Workspace;
+use std::time::{Duration, Instant};
+
+/// Maximum time each check can run for.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(120);
+/// Maximum time starting a sandbox running nothing can take on a healthy host.
+const MAX_STARTUP_TIME: Duration = Duration::from_secs(30);
+
+/// Host the network checks try to reach. It's in the default allowlist, so builds are allowed to
+/// reach it, but only through the proxy.
+const ALLOWED_PROBE: &str = "static.crates.io";
+const BLOCKED_PROBE: &str = "example.com";
+
+#[derive(Serialize)]
+pub struct SelfTestReport {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Checks(Vec<Check>);
+
+impl Checks {
+    fn run(&mut self, name: &'static str, check: impl FnOnce() -> Fallible<()>) -> bool {
+        info!("self-test: checking {}...", name);
+        let start = Instant::now();
+        let result = check();
+        let duration_ms = start.elapsed().as_millis() as u64;
+        if let Err(err) = &result {
+            warn!("self-test: {} failed: {:?}", name, err);
+        }
+        self.0.push(Check {
+            name,
+            ok: result.is_ok(),
+            duration_ms,
+            error: result.err().map(|err| format!("{err:#}")),
+        });
+        self.0.last().unwrap().ok
+    }
+}
+
+/// Run all the checks, creating the workspace once the container runtime is known to work.
+/// Checks depending on a previous one that failed are skipped.
+pub fn run(config: &Config, workspace: impl FnOnce() -> Fallible<Workspace>) -> SelfTestReport {
+    let mut checks = Checks::default();
+
+    if checks.run("docker", check_docker) {
+        let mut ws = None;
+        let image_runs = checks.run("sandbox-image", || {
+            let ws = ws.insert(workspace().context("failed to create the workspace")?);
+            run_script(ws, sandbox(config, false), "true")?;
+            Ok(())
+        });
+
+        if let (true, Some(workspace)) = (image_runs, &ws) {
+            checks.run("sandbox-startup-time", || {
+                let start = Instant::now();
+                run_script(workspace, sandbox(config, false), "true")?;
+                let elapsed = start.elapsed();
+                if elapsed > MAX_STARTUP_TIME {
+                    bail!(
+                        "starting the sandbox took {:?}, more than {:?}",
+                        elapsed,
+                        MAX_STARTUP_TIME
+                    );
+                }
+                Ok(())
+            });
+            checks.run("memory-limit", || check_memory_limit(workspace, config));
+            checks.run("network-isolation", || {
+                check_network_isolation(workspace, config)
+            });
+        }
+    }
+
+    SelfTestReport {
+        ok: checks.0.iter().all(|check| check.ok),
+        checks: checks.0,
+    }
+}
+
+fn check_docker() -> Fallible<()> {
+    // rustwide drives the docker CLI, which talks to podman too when it's configured
+    let output = std::process::Command::new("docker")
+        .args(["version", "--format", "{{.Server.Version}}"])
+        .output()
+        .context("failed to run docker")?;
+    if !output.status.success() {
+        bail!(
+            "the container runtime is not reachable: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    info!(
+        "self-test: container runtime version {}",
+        String::from_utf8_lossy(&output.stdout).trim()
+    );
+    Ok(())
+}
+
+fn sandbox(config: &Config, enable_networking: bool) -> SandboxBuilder {
+    SandboxBuilder::new()
+        .memory_limit(Some(config.sandbox.memory_limit.to_bytes()))
+        .enable_networking(enable_networking)
+}
+
+fn sandboxed_script<'w>(
+    workspace: &'w Workspace,
+    sandbox: SandboxBuilder,
+    script: &str,
+) -> Command<'w, 'static> {
+    Command::new_sandboxed(workspace, sandbox, "bash")
+        .args(&["-c", script])
+        .timeout(Some(CHECK_TIMEOUT))
+        .log_output(false)
+}
+
+fn run_script(
+    workspace: &Workspace,
+    sandbox: SandboxBuilder,
+    script: &str,
+) -> Result<Vec<String>, CommandError> {
+    sandboxed_script(workspace, sandbox, script)
+        .run_capture()
+        .map(|output| output.stdout_lines().to_vec())
+}
+
+fn check_memory_limit(workspace: &Workspace, config: &Config) -> Fallible<()> {
+    // tail buffers its input until it finds a newline, which /dev/zero never contains
+    match run_script(workspace, sandbox(config, false), "tail /dev/zero") {
+        Err(CommandError::SandboxOOM) => Ok(()),
+        Ok(_) => bail!("allocating memory without bounds succeeded"),
+        Err(err) => bail!("the OOM was not detected, the command failed with: {}", err),
+    }
+}
+
+/// Script trying to open a TCP connection, succeeding only if it could.
+fn connect_script(host: &str, port: u16) -> String {
+    format!("timeout 10 bash -c 'exec 3<>/dev/tcp/{host}/{port}'")
+}
+
+/// Script asking the proxy to tunnel a connection, printing the status line of its response.
+fn proxy_script(config: &Config, host: &str) -> String {
+    let network = &config.sandbox.network;
+    format!(
+        "exec 3<>/dev/tcp/{}/{} && printf 'CONNECT {host}:443 HTTP/1.1\\r\\n\\r\\n' >&3 \
+         && timeout 10 head -n 1 <&3",
+        network.proxy_host, network.proxy_port,
+    )
+}
+
+/// Parse the status code out of an HTTP status line.
+fn parse_status(line: &str) -> Option<u16> {
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+fn proxy_status(workspace: &Workspace, config: &Config, host: &str) -> Fallible<u16> {
+    let lines = run_script(
+        workspace,
+        sandbox(config, true),
+        &proxy_script(config, host),
+    )
+    .with_context(|| format!("failed to reach the network proxy to connect to {host}"))?;
+    lines
+        .first()
+        .and_then(|line| parse_status(line))
+        .ok_or_else(|| anyhow!("unexpected response of the network proxy: {:?}", lines))
+}
+
+fn check_network_isolation(workspace: &Workspace, config: &Config) -> Fallible<()> {
+    let networking = config.sandbox.network.policy == NetworkPolicy::Allowlist;
+    // The proxy is stopped when this function returns. Starting it isolates the sandboxes, which
+    // has to be done before checking that they can't reach the network directly.
+    let _proxy = if networking {
+        Some(NetworkProxy::spawn(&config.sandbox.network)?)
+    } else {
+        None
+    };
+
+    if run_script(
+        workspace,
+        sandbox(config, networking),
+        &connect_script(ALLOWED_PROBE, 443),
+    )
+    .is_ok()
+    {
+        bail!(
+            "the sandbox can connect to {} directly with the {} network policy",
+            ALLOWED_PROBE,
+            config.sandbox.network.policy
+        );
+    }
+
+    if networking {
+        let status = proxy_status(workspace, config, ALLOWED_PROBE)?;
+        if status != 200 {
+            bail!(
+                "the network proxy refused to connect to {} with status {}",
+                ALLOWED_PROBE,
+                status
+            );
+        }
+        let status = proxy_status(workspace, config, BLOCKED_PROBE)?;
+        if status != 403 {
+            bail!(
+                "the network proxy connected to {} outside of the allowlist (status {})",
+                BLOCKED_PROBE,
+                status
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_status;
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            parse_status("HTTP/1.1 200 Connection established\r"),
+            Some(200)
+        );
+        assert_eq!(parse_status("HTTP/1.1 403 Forbidden"), Some(403));
+        assert_eq!(parse_status(""), None);
+        assert_eq!(parse_status("SSH-2.0-OpenSSH"), None);
+    }
+}
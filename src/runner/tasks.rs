@@ -2,14 +2,16 @@ use crate::config::Config;
 use crate::crates::Crate;
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::TestResult;
+use crate::results::{BuildReport, TestResult};
+use crate::runner::build_cache::BuildCache;
 use crate::runner::test;
 use crate::toolchain::Toolchain;
+use crate::utils::logging::BuildLog;
+use rand::distributions::{Alphanumeric, DistString};
 use rustwide::{Build, BuildDirectory};
 use std::collections::HashMap;
 use std::sync::Mutex;
 
-use rustwide::logging::LogStorage;
 use std::fmt;
 
 pub(super) struct TaskCtx<'ctx> {
@@ -19,16 +21,26 @@ pub(super) struct TaskCtx<'ctx> {
     pub(super) toolchain: &'ctx Toolchain,
     pub(super) krate: &'ctx Crate,
     pub(super) quiet: bool,
+    pub(super) build_cache: Option<&'ctx BuildCache<'ctx>>,
+    /// Environment variables configured for this crate in `config.toml`.
+    pub(super) env: HashMap<String, String>,
+    /// Measurements of the build, sent to the server along with its result.
+    pub(super) report: &'ctx Mutex<BuildReport>,
+    /// Random identifier of the task, which the sandbox uses to authenticate to the network
+    /// proxy.
+    pub(super) build_id: String,
 }
 
 impl<'ctx> TaskCtx<'ctx> {
-    fn new(
+    pub(super) fn new(
         build_dir: &'ctx Mutex<BuildDirectory>,
         config: &'ctx Config,
         experiment: &'ctx Experiment,
         toolchain: &'ctx Toolchain,
         krate: &'ctx Crate,
         quiet: bool,
+        build_cache: Option<&'ctx BuildCache<'ctx>>,
+        report: &'ctx Mutex<BuildReport>,
     ) -> Self {
         TaskCtx {
             build_dir,
@@ -37,28 +49,38 @@ impl<'ctx> TaskCtx<'ctx> {
             toolchain,
             krate,
             quiet,
+            build_cache,
+            env: config.crate_env(krate),
+            report,
+            build_id: Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
         }
     }
 }
 
 pub(super) enum TaskStep {
     BuildAndTest { tc: Toolchain, quiet: bool },
+    BuildTestAndDoctest { tc: Toolchain, quiet: bool },
     BuildOnly { tc: Toolchain, quiet: bool },
     CheckOnly { tc: Toolchain, quiet: bool },
     Clippy { tc: Toolchain, quiet: bool },
     Rustdoc { tc: Toolchain, quiet: bool },
     UnstableFeatures { tc: Toolchain },
+    Reproducibility { tc: Toolchain, quiet: bool },
 }
 
 impl fmt::Debug for TaskStep {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (name, quiet, tc) = match *self {
             TaskStep::BuildAndTest { ref tc, quiet } => ("build and test", quiet, Some(tc)),
+            TaskStep::BuildTestAndDoctest { ref tc, quiet } => {
+                ("build, test and doctest", quiet, Some(tc))
+            }
             TaskStep::BuildOnly { ref tc, quiet } => ("build", quiet, Some(tc)),
             TaskStep::CheckOnly { ref tc, quiet } => ("check", quiet, Some(tc)),
             TaskStep::Clippy { ref tc, quiet } => ("clippy", quiet, Some(tc)),
             TaskStep::Rustdoc { ref tc, quiet } => ("doc", quiet, Some(tc)),
             TaskStep::UnstableFeatures { ref tc } => ("find unstable features on", false, Some(tc)),
+            TaskStep::Reproducibility { ref tc, quiet } => ("build twice", quiet, Some(tc)),
         };
 
         write!(f, "{name}")?;
@@ -89,7 +111,9 @@ impl Task {
         config: &'ctx Config,
         build_dir: &'ctx HashMap<&'ctx crate::toolchain::Toolchain, Mutex<BuildDirectory>>,
         ex: &'ctx Experiment,
-        logs: &LogStorage,
+        build_cache: Option<&'ctx BuildCache<'ctx>>,
+        logs: &BuildLog,
+        report: &'ctx Mutex<BuildReport>,
     ) -> Fallible<TestResult> {
         let (build_dir, action, test, toolchain, quiet): (
             _,
@@ -105,6 +129,13 @@ impl Task {
                 tc,
                 quiet,
             ),
+            TaskStep::BuildTestAndDoctest { ref tc, quiet } => (
+                &build_dir[tc],
+                "testing",
+                test::test_build_test_and_doctest,
+                tc,
+                quiet,
+            ),
             TaskStep::BuildOnly { ref tc, quiet } => {
                 (&build_dir[tc], "building", test::test_build_only, tc, quiet)
             }
@@ -124,9 +155,25 @@ impl Task {
                 tc,
                 false,
             ),
+            TaskStep::Reproducibility { ref tc, quiet } => (
+                &build_dir[tc],
+                "reproducing",
+                test::test_reproducibility,
+                tc,
+                quiet,
+            ),
         };
 
-        let ctx = TaskCtx::new(build_dir, config, ex, toolchain, &self.krate, quiet);
+        let ctx = TaskCtx::new(
+            build_dir,
+            config,
+            ex,
+            toolchain,
+            &self.krate,
+            quiet,
+            build_cache,
+            report,
+        );
         test::run_test(action, &ctx, test, logs)
     }
 }
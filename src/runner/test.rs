@@ -1,19 +1,52 @@
-use crate::crates::Crate;
+use crate::config::NetworkPolicy;
+use crate::crates::{ChecksumMismatch, Crate, MissingTarball, PatchSource};
+use crate::experiments::Mode;
 use crate::prelude::*;
 use crate::results::DiagnosticCode;
-use crate::results::{BrokenReason, FailureReason, TestResult};
+use crate::results::{
+    BrokenReason, BuildDiskUsage, BuildMetrics, BuildWarnings, FailureReason, TestFailures,
+    TestResult, TestRuns,
+};
+use crate::runner::build_cache;
+use crate::runner::network;
+use crate::runner::quarantine::{self, SandboxMonitor};
 use crate::runner::tasks::TaskCtx;
 use crate::runner::OverrideResult;
+use crate::utils::disk_usage::{dir_size, written_since};
+use crate::utils::hex::to_hex;
+use crate::utils::logging::BuildLog;
 use anyhow::Error;
-use cargo_metadata::diagnostic::DiagnosticLevel;
+use cargo_metadata::diagnostic::{Diagnostic, DiagnosticLevel};
 use cargo_metadata::{Message, Metadata, Package, Target};
 use docsrs_metadata::Metadata as DocsrsMetadata;
 use remove_dir_all::remove_dir_all;
 use rustwide::cmd::{CommandError, ProcessLinesActions, SandboxBuilder};
-use rustwide::logging::LogStorage;
 use rustwide::{Build, PrepareError};
-use std::collections::{BTreeSet, HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+// Where rustwide mounts the target directory of the build inside the sandbox
+const CONTAINER_TARGET_DIR: &str = "/opt/rustwide/target";
+// Where rustwide mounts the source of the crate and the cargo home inside the sandbox
+const CONTAINER_SOURCE_DIR: &str = "/opt/rustwide/workdir";
+const CONTAINER_CARGO_HOME: &str = "/opt/rustwide/cargo-home";
+/// Subdirectories of the target directory used by the two builds checking reproducibility. They
+/// differ, so that the artifacts embedding the path of the build without it being remapped differ
+/// too.
+const REPRODUCIBILITY_TARGET_DIRS: [&str; 2] = ["reproducibility-first", "reproducibility-second"];
+
+const FETCH_ATTEMPTS: u32 = 3;
+const FETCH_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct TimingInfo {
+    reason: String,
+    duration: f64,
+    rmeta_time: Option<f64>,
+}
 
 fn failure_reason(err: &Error) -> FailureReason {
     if let Some(reason) = err.downcast_ref::<FailureReason>() {
@@ -65,6 +98,114 @@ fn failure_reason(err: &Error) -> FailureReason {
     }
 }
 
+/// Whether the line of output points to a missing system library or a linker failure, which
+/// depend on the contents of the sandbox image rather than on the toolchain being tested.
+fn is_system_dependency_error(line: &str) -> bool {
+    (line.contains("error: linking with `") && line.contains("` failed"))
+        || line.contains("/usr/bin/ld: cannot find -l")
+        || line.contains("could not find system library")
+        || (line.contains("pkg-config") && line.contains("not be found"))
+        || line.contains("pkg-config: not found")
+        || line.contains("Could not run `PKG_CONFIG_ALLOW_SYSTEM_CFLAGS")
+        || (line.contains("fatal error: ") && line.contains(".h: No such file or directory"))
+}
+
+fn is_registry_error(line: &str) -> bool {
+    line.contains("failed to update registry")
+        || line.contains("Unable to update registry")
+        || line.contains("failed to fetch `https://github.com/rust-lang/crates.io-index`")
+        || line.contains("failed to select a version for the requirement")
+        || line.contains("no matching package named")
+        || (line.contains("failed to get `") && line.contains("` as a dependency of package"))
+}
+
+/// Whether the line of plain text output is part of the error rustc reports when the standard
+/// library is not available for the target the crate is being built for.
+fn is_unsupported_target_error(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("error[E0463]: can't find crate for `std`")
+        || line.starts_with("error[E0463]: can't find crate for `core`")
+        || (line.starts_with("= note: ") && line.ends_with("may not support the standard library"))
+}
+
+/// Whether the diagnostic is the error rustc reports when the standard library is not available
+/// for the target the crate is being built for.
+fn is_unsupported_target_diagnostic(diagnostic: &Diagnostic) -> bool {
+    matches!(diagnostic.level, DiagnosticLevel::Error)
+        && diagnostic
+            .code
+            .as_ref()
+            .is_some_and(|code| code.code == "E0463")
+        && (diagnostic.message == "can't find crate for `std`"
+            || diagnostic.message == "can't find crate for `core`")
+}
+
+/// Whether the line of output is part of the report of a panic of the compiler. Rustdoc prints it
+/// as plain text instead of a JSON diagnostic, followed by the query stack of the panic.
+fn is_compiler_panic(line: &str) -> bool {
+    line.starts_with("thread 'rustc' panicked at")
+        || line.starts_with("thread 'rustdoc' panicked at")
+        || line.contains("error: internal compiler error:")
+        || line.contains("the compiler unexpectedly panicked. this is a bug.")
+        || line.starts_with("query stack during panic:")
+}
+
+/// Whether the diagnostic is a warning turned into an error by `-D warnings`, for example when
+/// the crate or the experiment sets `-Dwarnings` in its rustflags.
+fn is_denied_warning(diagnostic: &Diagnostic) -> bool {
+    diagnostic.children.iter().any(|child| {
+        child.message.contains("implied by `-D warnings`")
+            || child.message.contains("implied by `#[deny(warnings)]`")
+    })
+}
+
+/// Split the codes of the errors into the ones of the warnings denied by `-D warnings` and the
+/// other ones. rustc only notes that a lint is denied by `-D warnings` on its first diagnostic, so
+/// all the diagnostics of the lints denied at least once are counted as denied warnings.
+fn split_denied_lints(
+    error_codes: BTreeSet<DiagnosticCode>,
+    denied_lints: &BTreeSet<DiagnosticCode>,
+) -> (BTreeSet<DiagnosticCode>, BTreeSet<DiagnosticCode>) {
+    error_codes
+        .into_iter()
+        .partition(|code| denied_lints.contains(code))
+}
+
+/// Warnings of the local packages collected across the cargo invocations of a test, when the
+/// experiment tracks them. Cargo replays the warnings of the units it doesn't rebuild, so the
+/// warnings are deduplicated by their rendered message.
+#[derive(Default)]
+struct Warnings {
+    seen: HashSet<String>,
+    collected: BuildWarnings,
+}
+
+impl Warnings {
+    fn new(ctx: &TaskCtx) -> Option<Self> {
+        ctx.experiment.track_warnings.then(Warnings::default)
+    }
+
+    fn record(&mut self, diagnostic: &Diagnostic) {
+        let Some(code) = &diagnostic.code else {
+            return;
+        };
+        let rendered = diagnostic
+            .rendered
+            .as_deref()
+            .unwrap_or(&diagnostic.message);
+        if self.seen.insert(rendered.to_string()) {
+            self.collected.count += 1;
+            *self.collected.lints.entry(code.code.clone()).or_default() += 1;
+        }
+    }
+
+    fn report(ctx: &TaskCtx, warnings: Option<Warnings>) {
+        if let Some(warnings) = warnings {
+            ctx.report.lock().unwrap().warnings = Some(warnings.collected);
+        }
+    }
+}
+
 pub(super) fn detect_broken<T>(res: Result<T, Error>) -> Result<T, Error> {
     match res {
         Ok(ok) => Ok(ok),
@@ -80,6 +221,10 @@ pub(super) fn detect_broken<T>(res: Result<T, Error>) -> Result<T, Error> {
                     PrepareError::PrivateGitRepository => Some(BrokenReason::MissingGitRepository),
                     _ => None,
                 }
+            } else if err.downcast_ref::<ChecksumMismatch>().is_some() {
+                reason = Some(BrokenReason::ChecksumMismatch);
+            } else if err.downcast_ref::<MissingTarball>().is_some() {
+                reason = Some(BrokenReason::Unknown);
             }
 
             if let Some(reason) = reason {
@@ -111,34 +256,68 @@ fn run_cargo(
     check_errors: bool,
     local_packages: &[Package],
     env: HashMap<&'static str, String>,
+    mut metrics: Option<&mut BuildMetrics>,
+    mut warnings: Option<&mut Warnings>,
 ) -> Fallible<()> {
     let local_packages_id: HashSet<_> = local_packages.iter().map(|p| &p.id).collect();
 
+    let build_std = ctx
+        .toolchain
+        .build_std
+        .as_ref()
+        .map(|crates| format!("-Zbuild-std={crates}"));
     let mut args = args.to_vec();
-    if let Some(ref target) = ctx.toolchain.target {
+    // The flags of cargo must come before the arguments of the test harness
+    let harness_args = match args.iter().position(|arg| *arg == "--") {
+        Some(pos) => args.split_off(pos),
+        None => Vec::new(),
+    };
+    if let Some(target) = ctx.toolchain.cargo_target() {
         args.extend(["--target", target]);
     }
+    if let Some(ref build_std) = build_std {
+        args.push(build_std);
+    }
     if let Some(ref tc_cargoflags) = ctx.toolchain.cargoflags {
         args.extend(tc_cargoflags.split(' '));
     }
+    args.extend(harness_args);
 
     let mut rustflags = format!("--cap-lints={}", ctx.experiment.cap_lints.to_str());
     if let Some(ref tc_rustflags) = ctx.toolchain.rustflags {
         rustflags.push(' ');
         rustflags.push_str(tc_rustflags);
     }
+    // The flags configured for the crate are added to the ones of the toolchain
+    if let Some(crate_rustflags) = ctx.env.get("RUSTFLAGS") {
+        rustflags.push(' ');
+        rustflags.push_str(crate_rustflags);
+    }
 
     let mut rustdocflags = format!("--cap-lints={}", ctx.experiment.cap_lints.to_str());
     if let Some(ref tc_rustdocflags) = ctx.toolchain.rustdocflags {
         rustdocflags.push(' ');
         rustdocflags.push_str(tc_rustdocflags);
     }
+    if let Some(crate_rustdocflags) = ctx.env.get("RUSTDOCFLAGS") {
+        rustdocflags.push(' ');
+        rustdocflags.push_str(crate_rustdocflags);
+    }
 
+    let rustdoc = ctx.experiment.mode == Mode::Rustdoc;
     let mut did_ice = false;
+    // Panics of the compiler are only attributed to rustdoc when it fails to document the crate,
+    // as the dependencies are still built by rustc
+    let mut compiler_panicked = false;
+    let mut failed_to_document = false;
     let mut did_network = false;
     let mut did_trybuild = false;
     let mut ran_out_of_space = false;
+    let mut missing_system_dep = false;
+    let mut registry_failure = false;
+    let mut unsupported_target = false;
     let mut error_codes = BTreeSet::new();
+    let mut denied_lints = BTreeSet::new();
     let mut deps = BTreeSet::new();
 
     let mut detect_error = |line: &str, actions: &mut ProcessLinesActions| {
@@ -161,12 +340,39 @@ fn run_cargo(
         if line.contains("the environment variable TRYBUILD=overwrite") {
             did_trybuild = true;
         }
+        if is_system_dependency_error(line) {
+            missing_system_dep = true;
+        }
+        if is_registry_error(line) {
+            registry_failure = true;
+        }
+        if ctx.toolchain.target.is_some() && is_unsupported_target_error(line) {
+            unsupported_target = true;
+        }
+        if rustdoc && is_compiler_panic(line) {
+            compiler_panicked = true;
+        }
+        if rustdoc && line.contains("error: could not document `") {
+            failed_to_document = true;
+        }
 
         // Avoid trying to deserialize non JSON output
         if !line.starts_with('{') {
             return;
         }
 
+        if let Some(metrics) = metrics.as_deref_mut() {
+            if let Ok(timing) = serde_json::from_str::<TimingInfo>(line) {
+                if timing.reason == "timing-info" {
+                    // Everything after the metadata is emitted is spent in codegen
+                    let codegen = timing.duration - timing.rmeta_time.unwrap_or(0.0);
+                    *metrics.codegen_time.get_or_insert(0.0) += codegen.max(0.0);
+                    actions.remove_line();
+                    return;
+                }
+            }
+        }
+
         let message = match serde_json::from_str(line) {
             Ok(message) => message,
             Err(_) => return,
@@ -175,16 +381,29 @@ fn run_cargo(
         match message {
             Message::CompilerMessage(compiler_message) => {
                 let inner_message = compiler_message.message;
+                let missing_std = ctx.toolchain.target.is_some()
+                    && is_unsupported_target_diagnostic(&inner_message);
                 match (inner_message.level, &compiler_message.package_id) {
+                    // Not an error of the crate or of its dependencies, but of the target
+                    _ if missing_std => unsupported_target = true,
                     // the only local crate in a well defined job is the crate currently being tested
                     (DiagnosticLevel::Error, pkgid) if local_packages_id.contains(pkgid) => {
-                        if let Some(code) = inner_message.code {
-                            error_codes.insert(DiagnosticCode::from(code.code));
+                        if let Some(ref code) = inner_message.code {
+                            let code = DiagnosticCode::from(code.code.clone());
+                            if is_denied_warning(&inner_message) {
+                                denied_lints.insert(code.clone());
+                            }
+                            error_codes.insert(code);
                         }
                     }
                     (DiagnosticLevel::Ice, pkgid) if local_packages_id.contains(pkgid) => {
                         did_ice = true
                     }
+                    (DiagnosticLevel::Warning, pkgid) if local_packages_id.contains(pkgid) => {
+                        if let Some(warnings) = warnings.as_deref_mut() {
+                            warnings.record(&inner_message);
+                        }
+                    }
                     // If the error is in a crate that is not local then it's referred to a dependency
                     // of the current crate
                     (DiagnosticLevel::Error, pkgid) => {
@@ -202,40 +421,101 @@ fn run_cargo(
 
                 actions.replace_with_lines(inner_message.rendered.unwrap_or_default().split('\n'));
             }
+            Message::CompilerArtifact(artifact) => {
+                if let Some(metrics) = metrics.as_deref_mut() {
+                    metrics.units += 1;
+                    if local_packages_id.contains(&artifact.package_id) {
+                        metrics.artifact_size += artifact
+                            .filenames
+                            .iter()
+                            .filter_map(|file| host_artifact_size(build_env, file.as_std_path()))
+                            .sum::<u64>();
+                    }
+                }
+                actions.remove_line();
+            }
             _ => actions.remove_line(),
         }
     };
 
-    let mut command = build_env
-        .cargo()
+    // The statistics of the build cache can only be collected by the command running cargo
+    let command = match ctx.build_cache {
+        Some(_) => build_env.cmd(build_cache::CargoWithStats(ctx.toolchain.cargo())),
+        None => build_env.cargo(),
+    };
+    let mut command = command
         .args(&args)
         .env("CARGO_INCREMENTAL", "0")
         .env("RUST_BACKTRACE", "full")
         .env("RUSTFLAGS", rustflags)
         .env("RUSTDOCFLAGS", rustdocflags);
+    if ctx.config.sandbox.network.policy == NetworkPolicy::Allowlist {
+        for (var, data) in network::proxy_env(&ctx.config.sandbox.network, &ctx.build_id) {
+            command = command.env(var, data);
+        }
+    }
+    for (var, data) in &ctx.env {
+        if var != "RUSTFLAGS" && var != "RUSTDOCFLAGS" {
+            command = command.env(var, data);
+        }
+    }
     for (var, data) in env {
         command = command.env(var, data);
     }
+    if let Some(cache) = ctx.build_cache {
+        for (var, data) in cache.env() {
+            command = command.env(var, data);
+        }
+    }
 
-    if check_errors {
-        command = command.process_lines(&mut detect_error);
+    let mut cache_stats = None;
+    let mut process_line = |line: &str, actions: &mut ProcessLinesActions| {
+        if let Some(stats) = build_cache::parse_stats(line) {
+            cache_stats = Some(stats);
+            actions.remove_line();
+        } else if check_errors {
+            detect_error(line, actions);
+        }
+    };
+    if check_errors || ctx.build_cache.is_some() {
+        command = command.process_lines(&mut process_line);
     }
 
     if ctx.quiet {
         command = command.no_output_timeout(None);
     }
 
-    match command.run() {
+    let result = command.run();
+    if let Some(stats) = cache_stats {
+        // Builds run cargo more than once, for example to build and then test the crate
+        let mut report = ctx.report.lock().unwrap();
+        let total = report.cache_stats.get_or_insert_with(Default::default);
+        total.hits += stats.hits;
+        total.misses += stats.misses;
+    }
+    let (new_lints, error_codes) = split_denied_lints(error_codes, &denied_lints);
+    match result {
         Ok(()) => Ok(()),
         e @ Err(_) => {
-            if did_ice {
+            if did_ice || (compiler_panicked && failed_to_document) {
                 e.context(FailureReason::ICE)
             } else if ran_out_of_space {
                 e.context(FailureReason::NoSpace)
+            } else if unsupported_target && error_codes.is_empty() {
+                // Checked before the other reasons, as missing linkers and the errors of the
+                // dependencies are usually caused by the standard library missing for the
+                // target. The errors of the crate itself are still reported first.
+                e.context(FailureReason::UnsupportedTarget)
+            } else if missing_system_dep {
+                e.context(FailureReason::SystemDependency)
+            } else if registry_failure {
+                e.context(FailureReason::RegistryFailure)
             } else if !deps.is_empty() {
                 e.context(FailureReason::DependsOn(deps))
             } else if !error_codes.is_empty() {
                 e.context(FailureReason::CompilerError(error_codes))
+            } else if !new_lints.is_empty() {
+                e.context(FailureReason::NewLint(new_lints))
             } else if did_network {
                 e.context(FailureReason::NetworkAccess)
             } else if did_trybuild {
@@ -247,13 +527,19 @@ fn run_cargo(
     }
 }
 
+fn host_artifact_size(build_env: &Build, path: &Path) -> Option<u64> {
+    let relative = path.strip_prefix(CONTAINER_TARGET_DIR).ok()?;
+    let metadata = std::fs::metadata(build_env.host_target_dir().join(relative)).ok()?;
+    Some(metadata.len())
+}
+
 pub(super) fn run_test(
     action: &str,
     ctx: &TaskCtx,
     test_fn: fn(&TaskCtx, &Build, &[Package]) -> Fallible<TestResult>,
-    logs: &LogStorage,
+    logs: &BuildLog,
 ) -> Fallible<TestResult> {
-    rustwide::logging::capture(logs, || {
+    logs.capture(|| {
         info!(
             "{} {} against {} for {}",
             action,
@@ -261,34 +547,124 @@ pub(super) fn run_test(
             ctx.toolchain.to_string(),
             ctx.experiment.name
         );
-        let sandbox = SandboxBuilder::new()
-            .memory_limit(Some(ctx.config.sandbox.memory_limit.to_bytes()))
-            .enable_networking(false);
+        let start = Instant::now();
+        let res = with_build(ctx, |build| {
+            let build_start = SystemTime::now();
+            let monitor = SandboxMonitor::start(&ctx.build_id, &build.host_target_dir());
+            let local_packages = get_local_packages(build);
+            let res = local_packages.and_then(|packages| test_fn(ctx, build, &packages));
+            let signals = monitor.finish();
+            {
+                let mut report = ctx.report.lock().unwrap();
+                let units = report.metrics.as_ref().map(|metrics| metrics.units);
+                report.quarantine = quarantine::detect(&signals, units);
+                if let Some(quarantine) = &report.quarantine {
+                    warn!("quarantining {}: {}", ctx.krate, quarantine.reason);
+                }
+            }
+            // Failed builds are measured too, as running out of space fails them
+            match disk_usage(build, build_start) {
+                Ok(usage) => ctx.report.lock().unwrap().disk_usage = Some(usage),
+                Err(err) => warn!("failed to measure the disk usage of the build: {:?}", err),
+            }
+            res
+        });
+        // Used by the server to schedule the crates in the next experiments
+        ctx.report.lock().unwrap().build_time = Some(start.elapsed().as_secs_f64());
+        res
+    })
+}
+
+/// Measure the disk space used by the build, to be stored along with the result. The directories
+/// are only walked once, after the build: the target directory is shared with the previous builds
+/// of the worker, so only the files the build wrote are counted.
+fn disk_usage(build: &Build, build_start: SystemTime) -> Fallible<BuildDiskUsage> {
+    Ok(BuildDiskUsage {
+        source_dir: dir_size(&build.host_source_dir())?,
+        target_dir: written_since(&build.host_target_dir(), build_start)?,
+    })
+}
+
+/// Run `f` once rustwide prepared the build of the crate, which copies its source, generates its
+/// lockfile and fetches its dependencies.
+fn with_build<T>(ctx: &TaskCtx, f: impl FnOnce(&Build) -> Fallible<T>) -> Fallible<T> {
+    let mut sandbox = SandboxBuilder::new()
+        .memory_limit(Some(ctx.config.sandbox.memory_limit.to_bytes()))
+        .enable_networking(ctx.config.sandbox.network.policy == NetworkPolicy::Allowlist);
+    if let Some(cache) = ctx.build_cache {
+        sandbox = cache.mount(sandbox)?;
+    }
 
-        let krate = &ctx.krate.to_rustwide();
-        let mut build_dir = ctx.build_dir.lock().unwrap();
-        let mut build = build_dir.build(ctx.toolchain, krate, sandbox);
+    let krate = &ctx.krate.to_rustwide(ctx.config);
+    let mut build_dir = ctx.build_dir.lock().unwrap();
+    let mut build = build_dir.build(ctx.toolchain, krate, sandbox);
 
-        for patch in ctx.toolchain.patches.iter() {
-            build = build.patch_with_git(&patch.name, &patch.repo, &patch.branch);
+    for patch in ctx.toolchain.patches.iter() {
+        build = build.patch_with_git(&patch.name, &patch.repo, &patch.branch);
+    }
+    // The start toolchain builds the crates.io versions, so that the report shows the impact of
+    // the overlay and of the dependency patches
+    let is_end_toolchain = *ctx.toolchain == ctx.experiment.toolchains[1];
+    if is_end_toolchain {
+        for krate in &ctx.experiment.registry_overlay {
+            build = build.patch_with_path(&krate.name, &krate.source_dir().to_string_lossy());
+        }
+        for patch in &ctx.experiment.dependency_patches {
+            build = match &patch.source {
+                PatchSource::Git { repo, branch } => {
+                    build.patch_with_git(&patch.name, repo, branch)
+                }
+                PatchSource::Path { path } => build.patch_with_path(&patch.name, path),
+            };
         }
+    }
 
-        detect_broken(build.run(|build| {
-            let local_packages = get_local_packages(build)?;
-            test_fn(ctx, build, &local_packages)
-        }))
-    })
+    detect_broken(build.run(f))
+}
+
+/// Prepare the build of the crate without running anything in it, which runs `cargo fetch` while
+/// networking is allowed. The dependencies are stored in the cargo home shared by all the build
+/// directories, so the builds of every toolchain reuse them. Failures are retried, as they're
+/// usually caused by network issues rather than by the crate.
+pub(super) fn fetch_dependencies(ctx: &TaskCtx) -> Fallible<()> {
+    let mut attempt = 1;
+    loop {
+        // The build directory is unlocked once the attempt returns, before waiting to retry
+        let err = match with_build(ctx, |_| Ok(())) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        // Retrying won't fix broken crates
+        if attempt == FETCH_ATTEMPTS || err.downcast_ref::<OverrideResult>().is_some() {
+            return Err(err);
+        }
+        warn!(
+            "failed to fetch the dependencies of {} (attempt {}): {:?}",
+            ctx.krate, attempt, err
+        );
+        std::thread::sleep(FETCH_RETRY_INTERVAL * attempt);
+        attempt += 1;
+    }
 }
 
 fn build(ctx: &TaskCtx, build_env: &Build, local_packages: &[Package]) -> Fallible<()> {
+    let mut args = vec!["build", "--frozen", "--message-format=json"];
+    if ctx.toolchain.is_nightly() {
+        args.extend(["--timings=json", "-Zunstable-options"]);
+    }
+    let mut metrics = BuildMetrics::default();
+    let mut warnings = Warnings::new(ctx);
     run_cargo(
         ctx,
         build_env,
-        &["build", "--frozen", "--message-format=json"],
+        &args,
         true,
         local_packages,
         HashMap::default(),
+        Some(&mut metrics),
+        warnings.as_mut(),
     )?;
+    ctx.report.lock().unwrap().metrics = Some(metrics);
     run_cargo(
         ctx,
         build_env,
@@ -296,19 +672,59 @@ fn build(ctx: &TaskCtx, build_env: &Build, local_packages: &[Package]) -> Fallib
         true,
         local_packages,
         HashMap::default(),
+        None,
+        warnings.as_mut(),
     )?;
+    Warnings::report(ctx, warnings);
     Ok(())
 }
 
+/// Append the options of the test harness configured by the experiment to a `cargo test` command.
+fn with_harness_args<'a>(args: &[&'a str], harness_args: &'a [String]) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    if !harness_args.is_empty() {
+        args.push("--");
+        args.extend(harness_args.iter().map(String::as_str));
+    }
+    args
+}
+
 fn test(ctx: &TaskCtx, build_env: &Build) -> Fallible<()> {
-    run_cargo(
-        ctx,
-        build_env,
-        &["test", "--frozen"],
-        false,
-        &[],
-        HashMap::default(),
-    )
+    let harness_args = ctx.experiment.test_options.harness_args();
+    repeat_tests(ctx, || {
+        run_cargo(
+            ctx,
+            build_env,
+            &with_harness_args(&["test", "--frozen"], &harness_args),
+            false,
+            &[],
+            HashMap::default(),
+            None,
+            None,
+        )
+    })
+}
+
+/// Run the test suite as many times as the experiment requires, returning the outcome of the
+/// first failed run. The outcome of all the runs is reported to measure the flakiness of the crate.
+fn repeat_tests(ctx: &TaskCtx, run: impl Fn() -> Fallible<()>) -> Fallible<()> {
+    let total = ctx.experiment.repeat.unwrap_or(1);
+    let mut first_err = None;
+    let mut failed = 0;
+    for _ in 0..total {
+        if let Err(err) = run() {
+            failed += 1;
+            first_err.get_or_insert(err);
+        }
+    }
+
+    if total > 1 {
+        ctx.report.lock().unwrap().test_runs = Some(TestRuns { total, failed });
+    }
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
 pub(super) fn test_build_and_test(
@@ -331,6 +747,60 @@ pub(super) fn test_build_and_test(
     })
 }
 
+pub(super) fn test_build_test_and_doctest(
+    ctx: &TaskCtx,
+    build_env: &Build,
+    local_packages: &[Package],
+) -> Fallible<TestResult> {
+    if let Err(err) = build(ctx, build_env, local_packages) {
+        return Ok(TestResult::BuildFail(failure_reason(&err)));
+    }
+
+    // `--tests` runs the unit and integration tests, but not the doctests.
+    let harness_args = ctx.experiment.test_options.harness_args();
+    let test_r = repeat_tests(ctx, || {
+        run_cargo(
+            ctx,
+            build_env,
+            &with_harness_args(&["test", "--frozen", "--tests"], &harness_args),
+            false,
+            &[],
+            HashMap::default(),
+            None,
+            None,
+        )
+    });
+
+    // `cargo test --doc` errors out when there is no library to run the doctests of.
+    let doctest_r = if local_packages
+        .iter()
+        .any(|p| p.targets.iter().any(is_library))
+    {
+        run_cargo(
+            ctx,
+            build_env,
+            &with_harness_args(&["test", "--frozen", "--doc"], &harness_args),
+            false,
+            &[],
+            HashMap::default(),
+            None,
+            None,
+        )
+    } else {
+        Ok(())
+    };
+
+    Ok(match (test_r, doctest_r) {
+        (Err(err), Err(doctest_err)) => TestResult::TestAndDoctestFail(TestFailures {
+            tests: failure_reason(&err),
+            doctests: failure_reason(&doctest_err),
+        }),
+        (Err(err), Ok(_)) => TestResult::TestFail(failure_reason(&err)),
+        (Ok(_), Err(err)) => TestResult::DoctestFail(failure_reason(&err)),
+        (Ok(_), Ok(_)) => TestResult::TestPass,
+    })
+}
+
 pub(super) fn test_build_only(
     ctx: &TaskCtx,
     build_env: &Build,
@@ -348,6 +818,7 @@ pub(super) fn test_check_only(
     build_env: &Build,
     local_packages_id: &[Package],
 ) -> Fallible<TestResult> {
+    let mut warnings = Warnings::new(ctx);
     if let Err(err) = run_cargo(
         ctx,
         build_env,
@@ -361,9 +832,12 @@ pub(super) fn test_check_only(
         true,
         local_packages_id,
         HashMap::default(),
+        None,
+        warnings.as_mut(),
     ) {
         Ok(TestResult::BuildFail(failure_reason(&err)))
     } else {
+        Warnings::report(ctx, warnings);
         Ok(TestResult::TestPass)
     }
 }
@@ -373,6 +847,7 @@ pub(super) fn test_clippy_only(
     build_env: &Build,
     local_packages: &[Package],
 ) -> Fallible<TestResult> {
+    let mut warnings = Warnings::new(ctx);
     if let Err(err) = run_cargo(
         ctx,
         build_env,
@@ -386,9 +861,12 @@ pub(super) fn test_clippy_only(
         true,
         local_packages,
         HashMap::default(),
+        None,
+        warnings.as_mut(),
     ) {
         Ok(TestResult::BuildFail(failure_reason(&err)))
     } else {
+        Warnings::report(ctx, warnings);
         Ok(TestResult::TestPass)
     }
 }
@@ -398,8 +876,18 @@ pub(super) fn test_rustdoc(
     build_env: &Build,
     local_packages: &[Package],
 ) -> Fallible<TestResult> {
-    let run = |cargo_args, env| {
-        let res = run_cargo(ctx, build_env, cargo_args, true, local_packages, env);
+    let mut warnings = Warnings::new(ctx);
+    let mut run = |cargo_args, env| {
+        let res = run_cargo(
+            ctx,
+            build_env,
+            cargo_args,
+            true,
+            local_packages,
+            env,
+            None,
+            warnings.as_mut(),
+        );
 
         // Make sure to remove the built documentation
         // There is no point in storing it after the build is done
@@ -446,9 +934,170 @@ pub(super) fn test_rustdoc(
         }
     }
 
+    Warnings::report(ctx, warnings);
     Ok(TestResult::TestPass)
 }
 
+/// Context of the builds checking reproducibility, normalized like the reproducible-builds project
+/// does: the paths embedded in the artifacts are remapped, the environment variables commonly
+/// embedded by build scripts are fixed, and the build cache is disabled, as the second build
+/// would otherwise reuse the artifacts of the first one.
+fn reproducible_ctx<'ctx>(ctx: &TaskCtx<'ctx>, target_dir: &str) -> TaskCtx<'ctx> {
+    let remap = [
+        (CONTAINER_SOURCE_DIR, "/build"),
+        (CONTAINER_CARGO_HOME, "/cargo"),
+        (target_dir, "/target"),
+    ]
+    .iter()
+    .map(|(from, to)| format!("--remap-path-prefix={from}={to}"))
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    let mut env = ctx.env.clone();
+    let rustflags = match env.get("RUSTFLAGS") {
+        Some(flags) => format!("{flags} {remap}"),
+        None => remap,
+    };
+    env.insert("RUSTFLAGS".into(), rustflags);
+    env.insert("SOURCE_DATE_EPOCH".into(), "0".into());
+    env.insert("TZ".into(), "UTC".into());
+    env.insert("LC_ALL".into(), "C".into());
+    TaskCtx {
+        env,
+        build_cache: None,
+        ..*ctx
+    }
+}
+
+/// Hash the artifacts of the local packages: the final ones cargo copies at the root of the
+/// directory of the profile, and the ones of all their targets in `deps/`, whose names are
+/// compared without the hash cargo appends to them. `crates` are the names of the targets of the
+/// local packages, as passed to rustc. The dep-info files list absolute paths, and are not
+/// compared.
+fn hash_artifacts(dir: &Path, crates: &HashSet<String>) -> Fallible<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    for (dir, deps) in [(dir.to_path_buf(), false), (dir.join("deps"), true)] {
+        if deps && !dir.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_file() || name.starts_with('.') || name.ends_with(".d") {
+                continue;
+            }
+            let name = if deps {
+                match local_dep_artifact(&name, crates) {
+                    Some(name) => format!("deps/{name}"),
+                    None => continue,
+                }
+            } else {
+                name
+            };
+            let hash = to_hex(&Sha256::digest(std::fs::read(entry.path())?));
+            hashes.insert(name, hash);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Name of an artifact in `deps/` without its hash, like `libfoo.rlib` for `libfoo-1a2b.rlib`,
+/// if it belongs to one of `crates`.
+fn local_dep_artifact(name: &str, crates: &HashSet<String>) -> Option<String> {
+    let (stem, extension) = match name.split_once('.') {
+        Some((stem, extension)) => (stem, Some(extension)),
+        None => (name, None),
+    };
+    let (krate, _hash) = stem.rsplit_once('-')?;
+    if !crates.contains(krate)
+        && !krate
+            .strip_prefix("lib")
+            .is_some_and(|k| crates.contains(k))
+    {
+        return None;
+    }
+    Some(match extension {
+        Some(extension) => format!("{krate}.{extension}"),
+        None => krate.to_string(),
+    })
+}
+
+fn compare_artifacts(
+    first: &BTreeMap<String, String>,
+    second: &BTreeMap<String, String>,
+) -> BuildReproducibility {
+    let names: BTreeSet<_> = first.keys().chain(second.keys()).collect();
+    BuildReproducibility {
+        compared: names.len() as u32,
+        differing: names
+            .into_iter()
+            .filter(|name| first.get(*name) != second.get(*name))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Build the crate twice from scratch, and compare the artifacts of the two builds.
+pub(super) fn test_reproducibility(
+    ctx: &TaskCtx,
+    build_env: &Build,
+    local_packages: &[Package],
+) -> Fallible<TestResult> {
+    let crates = local_packages
+        .iter()
+        .flat_map(|p| &p.targets)
+        .map(|t| t.name.replace('-', "_"))
+        .collect::<HashSet<_>>();
+
+    let mut builds = Vec::new();
+    for dir in REPRODUCIBILITY_TARGET_DIRS {
+        let target_dir = format!("{CONTAINER_TARGET_DIR}/{dir}");
+        let ctx = &reproducible_ctx(ctx, &target_dir);
+        let host_target_dir = build_env.host_target_dir().join(dir);
+        let mut artifacts_dir = host_target_dir.clone();
+        if let Some(target) = ctx.toolchain.cargo_target() {
+            artifacts_dir.push(target);
+        }
+        artifacts_dir.push("debug");
+
+        let res = run_cargo(
+            ctx,
+            build_env,
+            &[
+                "build",
+                "--frozen",
+                "--message-format=json",
+                "--target-dir",
+                &target_dir,
+            ],
+            true,
+            local_packages,
+            HashMap::default(),
+            None,
+            None,
+        )
+        .map(|()| hash_artifacts(&artifacts_dir, &crates));
+
+        // Each build starts from scratch, and the artifacts are not needed once hashed
+        if host_target_dir.exists() {
+            remove_dir_all(&host_target_dir)?;
+        }
+        match res {
+            Ok(artifacts) => builds.push(artifacts?),
+            Err(err) => return Ok(TestResult::BuildFail(failure_reason(&err))),
+        }
+    }
+
+    let reproducibility = compare_artifacts(&builds[0], &builds[1]);
+    let res = if reproducibility.differing.is_empty() {
+        TestResult::TestPass
+    } else {
+        TestResult::TestFail(FailureReason::NonReproducible)
+    };
+    ctx.report.lock().unwrap().reproducibility = Some(reproducibility);
+    Ok(res)
+}
+
 fn is_library(target: &Target) -> bool {
     // Some examples and tests can be libraries (e.g. if they use `cdylib`).
     target.crate_types.iter().any(|ty| ty != "bin")
@@ -467,3 +1116,260 @@ fn test_failure_reason() {
         FailureReason::ICE
     );
 }
+
+#[test]
+fn test_is_system_dependency_error() {
+    assert!(is_system_dependency_error(
+        "error: linking with `cc` failed: exit status: 1"
+    ));
+    assert!(is_system_dependency_error(
+        "          /usr/bin/ld: cannot find -lssl: No such file or directory"
+    ));
+    assert!(is_system_dependency_error(
+        "  The pkg-config command could not be found."
+    ));
+    assert!(is_system_dependency_error(
+        "  src/wrapper.h:1:10: fatal error: alsa/asoundlib.h: No such file or directory"
+    ));
+    assert!(!is_system_dependency_error(
+        "error[E0425]: cannot find value `foo` in this scope"
+    ));
+}
+
+#[test]
+fn test_is_denied_warning() {
+    let diagnostic = |note: &str| -> Diagnostic {
+        serde_json::from_value(serde_json::json!({
+            "message": "unused variable: `x`",
+            "code": { "code": "unused_variables", "explanation": null },
+            "level": "error",
+            "spans": [],
+            "children": [
+                { "message": note, "code": null, "level": "note", "spans": [], "children": [], "rendered": null },
+            ],
+            "rendered": null,
+        }))
+        .unwrap()
+    };
+
+    assert!(is_denied_warning(&diagnostic(
+        "`-D unused-variables` implied by `-D warnings`"
+    )));
+    assert!(is_denied_warning(&diagnostic(
+        "`#[deny(unused_variables)]` implied by `#[deny(warnings)]`"
+    )));
+    assert!(!is_denied_warning(&diagnostic(
+        "`#[deny(unused_variables)]` on by default"
+    )));
+}
+
+#[test]
+fn test_split_denied_lints() {
+    let codes = |codes: &[&str]| -> BTreeSet<DiagnosticCode> {
+        codes
+            .iter()
+            .map(|code| DiagnosticCode::from(code.to_string()))
+            .collect()
+    };
+
+    let (new_lints, error_codes) = split_denied_lints(
+        codes(&["E0308", "unused_variables", "dead_code"]),
+        &codes(&["unused_variables", "dead_code"]),
+    );
+    assert_eq!(new_lints, codes(&["dead_code", "unused_variables"]));
+    assert_eq!(error_codes, codes(&["E0308"]));
+}
+
+#[test]
+fn test_record_warnings() {
+    let diagnostic = |code: Option<&str>, rendered: &str| -> Diagnostic {
+        serde_json::from_value(serde_json::json!({
+            "message": rendered,
+            "code": code.map(|code| serde_json::json!({ "code": code, "explanation": null })),
+            "level": "warning",
+            "spans": [],
+            "children": [],
+            "rendered": rendered,
+        }))
+        .unwrap()
+    };
+
+    let mut warnings = Warnings::default();
+    warnings.record(&diagnostic(
+        Some("dead_code"),
+        "function `foo` is never used",
+    ));
+    warnings.record(&diagnostic(
+        Some("dead_code"),
+        "function `bar` is never used",
+    ));
+    warnings.record(&diagnostic(
+        Some("unused_imports"),
+        "unused import: `std::fs`",
+    ));
+    // Replayed by cargo for the units which were already built
+    warnings.record(&diagnostic(
+        Some("dead_code"),
+        "function `foo` is never used",
+    ));
+    warnings.record(&diagnostic(None, "3 warnings emitted"));
+
+    assert_eq!(warnings.collected.count, 3);
+    assert_eq!(
+        warnings.collected.lints,
+        [
+            ("dead_code".to_string(), 2),
+            ("unused_imports".to_string(), 1)
+        ]
+        .into()
+    );
+}
+
+#[test]
+fn test_is_registry_error() {
+    assert!(is_registry_error(
+        "error: failed to select a version for the requirement `foo = \"^1.2.3\"`"
+    ));
+    assert!(is_registry_error(
+        "    Unable to update registry `crates-io`"
+    ));
+    assert!(is_registry_error(
+        "error: no matching package named `foo` found"
+    ));
+    assert!(!is_registry_error("error[E0432]: unresolved import `foo`"));
+}
+
+#[test]
+fn test_is_compiler_panic() {
+    assert!(is_compiler_panic(
+        "thread 'rustc' panicked at src/librustdoc/clean/mod.rs:1234:5:"
+    ));
+    assert!(is_compiler_panic(
+        "error: the compiler unexpectedly panicked. this is a bug."
+    ));
+    assert!(is_compiler_panic(
+        "error: internal compiler error: compiler/rustc_middle/src/ty/mod.rs:42: unexpected type"
+    ));
+    assert!(is_compiler_panic("query stack during panic:"));
+    assert!(!is_compiler_panic(
+        "thread 'main' panicked at src/main.rs:2:5:"
+    ));
+    assert!(!is_compiler_panic("error: could not document `foo`"));
+}
+
+#[test]
+fn test_is_unsupported_target_error() {
+    assert!(is_unsupported_target_error(
+        "error[E0463]: can't find crate for `std`"
+    ));
+    assert!(is_unsupported_target_error(
+        "  = note: the `wasm32-unknown-unknown` target may not support the standard library"
+    ));
+    // Only the errors of rustc are considered, not what the crate prints
+    assert!(!is_unsupported_target_error(
+        "error: This crate is not supported on this platform"
+    ));
+    assert!(!is_unsupported_target_error(
+        "test parse_unsupported_target ... ok"
+    ));
+    assert!(!is_unsupported_target_error(
+        "println!(\"error[E0463]: can't find crate for `std`\");"
+    ));
+    assert!(!is_unsupported_target_error(
+        "error[E0463]: can't find crate for `foo`"
+    ));
+}
+
+#[test]
+fn test_is_unsupported_target_diagnostic() {
+    let diagnostic = |code: &str, message: &str| -> Diagnostic {
+        serde_json::from_value(serde_json::json!({
+            "message": message,
+            "code": { "code": code, "explanation": null },
+            "level": "error",
+            "spans": [],
+            "children": [],
+            "rendered": null,
+        }))
+        .unwrap()
+    };
+
+    assert!(is_unsupported_target_diagnostic(&diagnostic(
+        "E0463",
+        "can't find crate for `std`"
+    )));
+    assert!(is_unsupported_target_diagnostic(&diagnostic(
+        "E0463",
+        "can't find crate for `core`"
+    )));
+    assert!(!is_unsupported_target_diagnostic(&diagnostic(
+        "E0463",
+        "can't find crate for `foo`"
+    )));
+    assert!(!is_unsupported_target_diagnostic(&diagnostic(
+        "E0425",
+        "cannot find value `std` in this scope"
+    )));
+}
+
+#[test]
+fn test_with_harness_args() {
+    use crate::experiments::TestOptions;
+
+    assert_eq!(with_harness_args(&["test"], &[]), vec!["test"]);
+
+    let options = TestOptions {
+        include_ignored: true,
+        test_threads: Some(1),
+        filters: vec!["net::".into(), "parse".into()],
+    };
+    assert_eq!(
+        with_harness_args(&["test", "--doc"], &options.harness_args()),
+        vec![
+            "test",
+            "--doc",
+            "--",
+            "--include-ignored",
+            "--test-threads=1",
+            "net::",
+            "parse"
+        ]
+    );
+}
+
+#[test]
+fn test_compare_artifacts() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, content: &str| std::fs::write(dir.path().join(name), content).unwrap();
+    write("libfoo.rlib", "foo");
+    write("foo", "binary");
+    write("foo.d", "/opt/rustwide/workdir/src/main.rs");
+    write(".cargo-lock", "");
+    std::fs::create_dir(dir.path().join("deps")).unwrap();
+    // Only the artifacts of the local packages are compared, without their hash
+    write("deps/libfoo-0123abcd.rlib", "foo");
+    write("deps/foo_cli-4567ef01", "binary");
+    write(
+        "deps/foo_cli-4567ef01.d",
+        "/opt/rustwide/workdir/src/main.rs",
+    );
+    write("deps/libserde-89abcdef.rlib", "serde");
+    let crates = HashSet::from(["foo".to_string(), "foo_cli".to_string()]);
+    let first = hash_artifacts(dir.path(), &crates).unwrap();
+    assert_eq!(
+        first.keys().map(String::as_str).collect::<Vec<_>>(),
+        ["deps/foo_cli", "deps/libfoo.rlib", "foo", "libfoo.rlib"]
+    );
+
+    write("foo", "other binary");
+    write("bar", "bar");
+    let second = hash_artifacts(dir.path(), &crates).unwrap();
+    assert_eq!(
+        compare_artifacts(&first, &second),
+        BuildReproducibility {
+            compared: 5,
+            differing: ["bar".to_string(), "foo".to_string()].into(),
+        }
+    );
+    assert!(compare_artifacts(&first, &first).differing.is_empty());
+}
@@ -0,0 +1,215 @@
+//! Installation of the toolchains of an experiment. Downloading the CI artifacts sometimes fails
+//! transiently, which would fail the whole experiment on the agent, so every step is retried with
+//! an exponential backoff, and the CI toolchains are then installed from the configured mirror.
+
+use crate::config::ToolchainsConfig;
+use crate::dirs::WORK_DIR;
+use crate::experiments::Mode;
+use crate::prelude::*;
+use crate::toolchain::Toolchain;
+use rustwide::Workspace;
+use std::process::Command;
+use std::time::Duration;
+
+/// Delay before the first retry, doubled before each following one.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(15);
+/// Tool used by rustwide to install the CI toolchains, in the cargo home of the workspace.
+const INSTALL_MASTER: &str = "rustup-toolchain-install-master";
+
+/// All the attempts to install the toolchain failed, including the one from the mirror if any.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "failed to install {toolchain} after {attempts} attempts{}",
+    .mirror.as_ref().map(|m| format!(" and from the mirror {m}")).unwrap_or_default()
+)]
+pub struct InstallFailed {
+    toolchain: Toolchain,
+    attempts: u32,
+    mirror: Option<String>,
+}
+
+/// Messages of rustup and of the tool installing the CI toolchains when the toolchain, one of its
+/// components or its manifest doesn't exist on the server.
+const MISSING_MESSAGES: &[&str] = &[
+    "404",
+    "not found",
+    "no release found",
+    "is not installable",
+    "is unavailable",
+    "is not available",
+    "missing manifest",
+];
+
+/// Whether the installation failed because the toolchain or one of its components doesn't exist,
+/// rather than because of a problem of the agent or of the network.
+pub(super) fn is_missing(err: &anyhow::Error) -> bool {
+    let message = format!("{err:#}").to_lowercase();
+    MISSING_MESSAGES.iter().any(|m| message.contains(m))
+}
+
+/// Components of the toolchain needed by the experiment, besides the default ones.
+fn components(tc: &Toolchain, mode: Mode) -> Vec<&'static str> {
+    let mut components = Vec::new();
+    if mode == Mode::Clippy {
+        components.push("clippy");
+    }
+    if tc.build_std.is_some() {
+        components.push("rust-src");
+    }
+    components
+}
+
+pub(super) fn install(
+    workspace: &Workspace,
+    tc: &Toolchain,
+    mode: Mode,
+    config: &ToolchainsConfig,
+) -> Fallible<()> {
+    let attempts = config.install_attempts.max(1);
+    let result = retry(attempts, FIRST_RETRY_DELAY, || {
+        tc.install(workspace)?;
+        for component in components(tc, mode) {
+            tc.add_component(workspace, component)?;
+        }
+        if let Some(target) = &tc.target {
+            tc.add_target(workspace, target)?;
+        }
+        Ok(())
+    });
+    let Err(err) = result else {
+        return Ok(());
+    };
+
+    let mirror = match (&config.ci_mirror, tc.source.as_ci()) {
+        (Some(mirror), Some(ci)) => {
+            warn!("installing {} from the mirror {}", tc, mirror);
+            match install_from_mirror(mirror, ci.sha(), ci.alt(), tc, mode) {
+                Ok(()) => return Ok(()),
+                Err(err) => warn!("failed to install {} from the mirror: {:?}", tc, err),
+            }
+            Some(mirror.clone())
+        }
+        _ => None,
+    };
+    Err(err.context(InstallFailed {
+        toolchain: tc.clone(),
+        attempts,
+        mirror,
+    }))
+}
+
+/// Install the CI toolchain with the tool used by rustwide, pointed to the mirror. It installs the
+/// components and the targets at the same time, as adding them later would use the primary
+/// server again.
+fn install_from_mirror(
+    mirror: &str,
+    sha: &str,
+    alt: bool,
+    tc: &Toolchain,
+    mode: Mode,
+) -> Fallible<()> {
+    let mut cmd = Command::new(WORK_DIR.join("cargo-home").join("bin").join(INSTALL_MASTER));
+    cmd.env("CARGO_HOME", WORK_DIR.join("cargo-home"))
+        .env("RUSTUP_HOME", WORK_DIR.join("rustup-home"))
+        .args(["--server", mirror, "--force"]);
+    if alt {
+        cmd.arg("--alt");
+    }
+    for component in components(tc, mode) {
+        cmd.args(["--component", component]);
+    }
+    if let Some(target) = &tc.target {
+        cmd.args(["--targets", target]);
+    }
+    let status = cmd
+        .arg(sha)
+        .status()
+        .with_context(|| format!("failed to run {INSTALL_MASTER}"))?;
+    if !status.success() {
+        bail!("{} exited with {}", INSTALL_MASTER, status);
+    }
+    Ok(())
+}
+
+/// Run the function until it succeeds or all the attempts failed, doubling the delay after each
+/// failure. The error of the last attempt is returned.
+fn retry<T>(attempts: u32, delay: Duration, mut f: impl FnMut() -> Fallible<T>) -> Fallible<T> {
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < attempts => {
+                warn!(
+                    "attempt {} of {} failed, retrying in {:?}: {:?}",
+                    attempt, attempts, delay, err
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_missing, retry, InstallFailed};
+    use crate::toolchain::MAIN_TOOLCHAIN;
+    use std::time::Duration;
+
+    #[test]
+    fn test_retry() {
+        let mut calls = 0;
+        let result = retry(3, Duration::ZERO, || {
+            calls += 1;
+            if calls < 3 {
+                anyhow::bail!("failure {calls}");
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: anyhow::Result<()> = retry(2, Duration::ZERO, || {
+            calls += 1;
+            anyhow::bail!("failure {calls}")
+        });
+        assert_eq!(result.unwrap_err().to_string(), "failure 2");
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_install_failed() {
+        let err = InstallFailed {
+            toolchain: MAIN_TOOLCHAIN.clone(),
+            attempts: 4,
+            mirror: None,
+        };
+        assert_eq!(err.to_string(), "failed to install stable after 4 attempts");
+
+        let err = InstallFailed {
+            mirror: Some("https://mirror.example.com".into()),
+            ..err
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to install stable after 4 attempts and from the mirror \
+             https://mirror.example.com"
+        );
+    }
+
+    #[test]
+    fn test_is_missing() {
+        let missing = anyhow::anyhow!("error: component 'clippy' for target 'x' is unavailable");
+        assert!(is_missing(&missing.context("failed to install stable")));
+        assert!(is_missing(&anyhow::anyhow!(
+            "HTTP status client error (404 Not Found) for url (https://ci-artifacts.example.com)"
+        )));
+
+        let network = anyhow::anyhow!("error: could not download file: connection reset");
+        assert!(!is_missing(&network.context("failed to install stable")));
+        assert!(!is_missing(&anyhow::anyhow!("no space left on device")));
+    }
+}
@@ -3,16 +3,16 @@ use crate::results::TestResult;
 use crate::runner::tasks::TaskCtx;
 use cargo_metadata::Package;
 use rustwide::Build;
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::path::Path;
 use walkdir::{DirEntry, WalkDir};
 
 pub(super) fn find_unstable_features(
-    _ctx: &TaskCtx,
+    ctx: &TaskCtx,
     build: &Build,
     _local_packages_id: &[Package],
 ) -> Fallible<TestResult> {
-    let mut features = HashSet::new();
+    let mut features = BTreeSet::new();
 
     for entry in WalkDir::new(build.host_source_dir())
         .into_iter()
@@ -38,11 +38,10 @@ pub(super) fn find_unstable_features(
         }
     }
 
-    let mut features: Vec<_> = features.into_iter().collect();
-    features.sort();
-    for feature in features {
+    for feature in &features {
         info!("unstable-feature: {}", feature);
     }
+    ctx.report.lock().unwrap().unstable_features = Some(features);
 
     Ok(TestResult::TestPass)
 }
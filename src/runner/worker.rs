@@ -1,16 +1,22 @@
 use crate::agent::AgentApi;
-use crate::crates::Crate;
+use crate::crates::{verify_checksum, Crate, GitHubRepo};
 use crate::experiments::{Experiment, Mode};
 use crate::prelude::*;
-use crate::results::{BrokenReason, TestResult};
-use crate::runner::tasks::{Task, TaskStep};
-use crate::runner::test::detect_broken;
+use crate::results::{BrokenReason, BuildReport, TestResult};
+use crate::runner::build_cache::BuildCache;
+use crate::runner::parallelism::Parallelism;
+use crate::runner::tasks::{Task, TaskCtx, TaskStep};
+use crate::runner::test::{detect_broken, fetch_dependencies};
 use crate::runner::OverrideResult;
-use crate::toolchain::Toolchain;
+use crate::toolchain::{RustcVersion, Toolchain};
 use crate::utils;
-use rustwide::logging::{self, LogStorage};
+use crate::utils::disk_usage::dir_size;
+use crate::utils::logging::BuildLog;
 use rustwide::{BuildDirectory, Workspace};
+use std::any::Any;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::Condvar;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -18,6 +24,10 @@ use std::sync::{
 };
 use std::time::Duration;
 
+/// Number of times a worker is restarted after panicking outside of the tests of a crate, before
+/// giving up.
+const MAX_RESTARTS: u32 = 5;
+
 pub trait RecordProgress: Send + Sync {
     fn record_progress(
         &self,
@@ -26,8 +36,11 @@ pub trait RecordProgress: Send + Sync {
         toolchain: &Toolchain,
         log: &[u8],
         result: &TestResult,
+        report: &BuildReport,
         version: Option<(&Crate, &Crate)>,
     ) -> Fallible<()>;
+
+    fn record_rustc_versions(&self, ex: &Experiment, versions: &[RustcVersion; 2]) -> Fallible<()>;
 }
 
 impl RecordProgress for AgentApi {
@@ -38,48 +51,75 @@ impl RecordProgress for AgentApi {
         toolchain: &Toolchain,
         log: &[u8],
         result: &TestResult,
+        report: &BuildReport,
         version: Option<(&Crate, &Crate)>,
     ) -> Fallible<()> {
-        self.record_progress(ex, krate, toolchain, log, result, version)
+        self.record_progress(ex, krate, toolchain, log, result, report, version)
+    }
+
+    fn record_rustc_versions(&self, ex: &Experiment, versions: &[RustcVersion; 2]) -> Fallible<()> {
+        self.record_rustc_versions(ex, versions)
     }
 }
 
 pub(super) struct Worker<'a> {
     name: String,
+    /// Position of the worker, which only tests crates while it's below the number of workers
+    /// the agent is running.
+    slot: usize,
     workspace: &'a Workspace,
     build_dir: HashMap<&'a crate::toolchain::Toolchain, Mutex<BuildDirectory>>,
+    /// Target directories of the build directories, from the least recently used.
+    target_dirs: Mutex<Vec<(&'a Toolchain, PathBuf)>>,
     ex: &'a Experiment,
+    /// Toolchains of the experiment the crates are tested with.
+    toolchains: &'a [&'a Toolchain],
     config: &'a crate::config::Config,
     api: &'a dyn RecordProgress,
     target_dir_cleanup: AtomicBool,
     next_crate: &'a (dyn Fn() -> Fallible<Option<Crate>> + Send + Sync),
+    parallelism: &'a Parallelism,
+    /// Set when the experiment is aborted, to stop testing crates and discard the results.
+    interrupted: &'a AtomicBool,
+    build_cache: Option<BuildCache<'a>>,
 }
 
 impl<'a> Worker<'a> {
     pub(super) fn new(
         name: String,
+        slot: usize,
         workspace: &'a Workspace,
         ex: &'a Experiment,
+        toolchains: &'a [&'a Toolchain],
         config: &'a crate::config::Config,
         api: &'a dyn RecordProgress,
         next_crate: &'a (dyn Fn() -> Fallible<Option<Crate>> + Send + Sync),
+        parallelism: &'a Parallelism,
+        interrupted: &'a AtomicBool,
     ) -> Self {
         let mut build_dir = HashMap::new();
-        build_dir.insert(
-            &ex.toolchains[0],
-            Mutex::new(workspace.build_dir(&format!("{name}-tc1"))),
-        );
-        build_dir.insert(
-            &ex.toolchains[1],
-            Mutex::new(workspace.build_dir(&format!("{name}-tc2"))),
-        );
+        let mut target_dirs = Vec::new();
+        for (tc, dir_name) in ex
+            .toolchains
+            .iter()
+            .zip([format!("{name}-tc1"), format!("{name}-tc2")])
+        {
+            build_dir.insert(tc, Mutex::new(workspace.build_dir(&dir_name)));
+            target_dirs.push((tc, target_dir_path(&dir_name)));
+        }
         Worker {
             build_dir,
+            target_dirs: Mutex::new(target_dirs),
+            build_cache: BuildCache::new(config, ex, &name),
             name,
+            slot,
             workspace,
             ex,
+            toolchains,
             config,
             next_crate,
+            parallelism,
+            interrupted,
             api,
             target_dir_cleanup: AtomicBool::new(false),
         }
@@ -89,10 +129,15 @@ impl<'a> Worker<'a> {
         &self.name
     }
 
+    fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
     fn run_task(
         &self,
         task: &Task,
-        storage: &LogStorage,
+        storage: &BuildLog,
+        report: &Mutex<BuildReport>,
     ) -> Result<TestResult, (anyhow::Error, TestResult)> {
         info!("running task: {:?}", task);
 
@@ -102,7 +147,16 @@ impl<'a> Worker<'a> {
             // If we're running a task, we call ourselves healthy.
             crate::agent::set_healthy();
 
-            match task.run(self.config, &self.build_dir, self.ex, storage) {
+            // Only the measurements of the last attempt are reported
+            *report.lock().unwrap() = BuildReport::default();
+            match task.run(
+                self.config,
+                &self.build_dir,
+                self.ex,
+                self.build_cache.as_ref(),
+                storage,
+                report,
+            ) {
                 Ok(res) => return Ok(res),
                 Err(e) => {
                     res = Some(e);
@@ -115,28 +169,24 @@ impl<'a> Worker<'a> {
             //
             // For now we make no distinction between build failures and test failures
             // here, but that may change if this proves too slow.
-            let mut should_retry = false;
-            if self.ex.toolchains.len() == 2 {
-                let toolchain = match &task.step {
-                    TaskStep::BuildAndTest { tc, .. }
-                    | TaskStep::BuildOnly { tc, .. }
-                    | TaskStep::CheckOnly { tc, .. }
-                    | TaskStep::Clippy { tc, .. }
-                    | TaskStep::Rustdoc { tc, .. }
-                    | TaskStep::UnstableFeatures { tc } => Some(tc),
-                };
-                if let Some(toolchain) = toolchain {
-                    if toolchain == self.ex.toolchains.last().unwrap() {
-                        should_retry = true;
-                    }
-                }
-            }
+            let toolchain = match &task.step {
+                TaskStep::BuildAndTest { tc, .. }
+                | TaskStep::BuildTestAndDoctest { tc, .. }
+                | TaskStep::BuildOnly { tc, .. }
+                | TaskStep::CheckOnly { tc, .. }
+                | TaskStep::Clippy { tc, .. }
+                | TaskStep::Rustdoc { tc, .. }
+                | TaskStep::UnstableFeatures { tc }
+                | TaskStep::Reproducibility { tc, .. } => tc,
+            };
+            let should_retry = *toolchain == self.ex.toolchains[1];
 
-            if !should_retry {
+            // Retrying would start a new sandbox after the interrupted one was killed
+            if !should_retry || self.is_interrupted() {
                 break;
             }
 
-            log::info!("Retrying task {:?} [{run}/{max_attempts}]", task);
+            info!("Retrying task {:?} [{run}/{max_attempts}]", task);
         }
         // Unreachable unless we failed to succeed above.
         let e = res.unwrap();
@@ -156,8 +206,41 @@ impl<'a> Worker<'a> {
         Err((e, result))
     }
 
+    /// Test crates until none is left. Panics while testing a crate are recorded as its result,
+    /// and the loop is restarted after the other panics, up to `MAX_RESTARTS` times.
     pub(super) fn run(&self) -> Fallible<()> {
+        let mut restarts = 0;
         loop {
+            let payload = match panic::catch_unwind(AssertUnwindSafe(|| self.run_loop())) {
+                Ok(result) => return result,
+                Err(payload) => payload,
+            };
+            let message = utils::panic_message(payload.as_ref()).unwrap_or("unknown panic");
+            if restarts == MAX_RESTARTS {
+                bail!(
+                    "{} panicked {} times, last time: {}",
+                    self.name,
+                    restarts + 1,
+                    message
+                );
+            }
+            restarts += 1;
+            error!("{} panicked, restarting it: {}", self.name, message);
+            crate::agent::record_worker_restart();
+            self.reset_after_panic();
+        }
+    }
+
+    fn run_loop(&self) -> Fallible<()> {
+        loop {
+            if self.is_interrupted() {
+                info!("{} interrupted", self.name);
+                return Ok(());
+            }
+            if !self.parallelism.wait_for_slot(self.slot, self.interrupted) {
+                return Ok(());
+            }
+
             let krate = if let Some(next) = (self.next_crate)()? {
                 next
             } else {
@@ -167,177 +250,363 @@ impl<'a> Worker<'a> {
 
             self.maybe_cleanup_target_dir()?;
 
+            // Created outside of the captured logs, to keep it out of the build logs
+            let _span = info_span!("crate", experiment = %self.ex.name, krate = %krate).entered();
             info!("{} processing crate {}", self.name, krate);
 
-            if !self.ex.ignore_blacklist && self.config.should_skip(&krate) {
-                for tc in &self.ex.toolchains {
-                    // If a skipped crate is somehow sent to the agent (for example, when a crate was
-                    // added to the experiment and *then* blacklisted) report the crate as skipped
-                    // instead of silently ignoring it.
-                    if let Err(e) = self.api.record_progress(
-                        self.ex,
-                        &krate,
-                        tc,
-                        "crate skipped".as_bytes(),
-                        &TestResult::Skipped,
-                        None,
-                    ) {
-                        crate::utils::report_failure(&e);
-                    }
+            let mut recorded = Vec::new();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.process_crate(&krate, &mut recorded)
+            }));
+            match result {
+                Ok(result) => result?,
+                Err(payload) => self.recover_from_panic(&krate, &recorded, payload.as_ref()),
+            }
+        }
+    }
+
+    /// Test the crate with the toolchains of the experiment, recording their results. The
+    /// toolchains whose builds were recorded are added to `recorded`.
+    fn process_crate(&self, krate: &Crate, recorded: &mut Vec<Toolchain>) -> Fallible<()> {
+        if !self.ex.ignore_blacklist && self.config.should_skip(krate) {
+            for &tc in self.toolchains {
+                // If a skipped crate is somehow sent to the agent (for example, when a crate was
+                // added to the experiment and *then* blacklisted) report the crate as skipped
+                // instead of silently ignoring it.
+                if let Err(e) = self.api.record_progress(
+                    self.ex,
+                    krate,
+                    tc,
+                    "crate skipped".as_bytes(),
+                    &TestResult::Skipped,
+                    &BuildReport::default(),
+                    None,
+                ) {
+                    crate::utils::report_failure(&e);
                 }
-                continue;
             }
+            return Ok(());
+        }
 
-            let mut updated_version = None;
-            let logs = LogStorage::from(self.config);
-            let prepare = logging::capture(&logs, || {
-                let rustwide_crate = krate.to_rustwide();
-                for attempt in 1..=15 {
-                    match detect_broken(rustwide_crate.fetch(self.workspace)) {
-                        Ok(()) => break,
-                        Err(e) => {
-                            if logs.to_string().contains("No space left on device") {
-                                if attempt == 15 {
-                                    // If we've failed 15 times, then
-                                    // just give up. It's been at least
-                                    // 45 seconds, which is enough that
-                                    // our disk space check should
-                                    // have run at least once in this
-                                    // time. If that's not helped, then
-                                    // maybe this git repository *is*
-                                    // actually too big.
-                                    //
-                                    // Ideally we'd have some kind of
-                                    // per-worker counter and if we hit
-                                    // this too often we'd replace the
-                                    // machine, but it's not very clear
-                                    // what "too often" means here.
-                                    return Err(e);
-                                } else {
-                                    log::warn!(
-                                        "Retrying crate fetch in 3 seconds (attempt {})",
-                                        attempt
-                                    );
-                                    std::thread::sleep(std::time::Duration::from_secs(3));
-                                }
-                            } else {
+        let result_override = self
+            .config
+            .result_override(krate)
+            .filter(|o| !self.ex.ignore_blacklist && !o.is_expired());
+        if let Some(result_override) = result_override {
+            info!(
+                "result of {} overridden to {}: {}",
+                krate, result_override.result, result_override.reason
+            );
+            let log = format!(
+                "crate not built, its result is overridden to {} by the configuration: {}",
+                result_override.result, result_override.reason
+            );
+            for &tc in self.toolchains {
+                if let Err(e) = self.api.record_progress(
+                    self.ex,
+                    krate,
+                    tc,
+                    log.as_bytes(),
+                    &result_override.result,
+                    &BuildReport {
+                        result_override: Some(result_override.reason.clone()),
+                        ..BuildReport::default()
+                    },
+                    None,
+                ) {
+                    crate::utils::report_failure(&e);
+                }
+            }
+            return Ok(());
+        }
+
+        let mut updated_version = None;
+        let logs = BuildLog::from(self.config);
+        let prepare = logs.capture(|| {
+            if let (Some(cache), Crate::Registry(details)) = (&self.config.crate_cache, krate) {
+                // Fall back to downloading from crates.io if the cache is unavailable
+                if let Err(e) = cache.prefetch(details) {
+                    warn!("failed to fetch {} from the crate cache: {:?}", krate, e);
+                }
+            }
+            krate.checkout_pinned(self.config)?;
+            let rustwide_crate = krate.to_rustwide(self.config);
+            for attempt in 1..=15 {
+                match detect_broken(rustwide_crate.fetch(self.workspace)) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        if logs.to_string().contains("No space left on device") {
+                            if attempt == 15 {
+                                // If we've failed 15 times, then
+                                // just give up. It's been at least
+                                // 45 seconds, which is enough that
+                                // our disk space check should
+                                // have run at least once in this
+                                // time. If that's not helped, then
+                                // maybe this git repository *is*
+                                // actually too big.
+                                //
+                                // Ideally we'd have some kind of
+                                // per-worker counter and if we hit
+                                // this too often we'd replace the
+                                // machine, but it's not very clear
+                                // what "too often" means here.
                                 return Err(e);
+                            } else {
+                                warn!("Retrying crate fetch in 3 seconds (attempt {})", attempt);
+                                std::thread::sleep(std::time::Duration::from_secs(3));
                             }
+                        } else {
+                            return Err(e);
                         }
                     }
                 }
+            }
 
-                if let Crate::GitHub(repo) = &krate {
-                    if let Some(sha) = rustwide_crate.git_commit(self.workspace) {
-                        let updated = crate::crates::GitHubRepo {
-                            sha: Some(sha),
-                            ..repo.clone()
-                        };
-                        updated_version = Some(Crate::GitHub(updated));
-                    } else {
-                        bail!("unable to capture sha for {}", repo.slug());
-                    }
-                }
-                Ok(())
-            });
-            if let Err(err) = prepare {
-                let mut result = if self.config.is_broken(&krate) {
-                    TestResult::BrokenCrate(BrokenReason::Unknown)
-                } else {
-                    TestResult::Error
-                };
+            if let Crate::Registry(details) = krate {
+                detect_broken(verify_checksum(details))?;
+            }
 
-                if let Some(OverrideResult(res)) = err.downcast_ref() {
-                    result = res.clone();
+            // Repositories pinned when the experiment was created are already identified by
+            // their commit
+            if let Crate::GitHub(repo @ GitHubRepo { sha: None, .. }) = krate {
+                if let Some(sha) = rustwide_crate.git_commit(self.workspace) {
+                    let updated = GitHubRepo {
+                        sha: Some(sha),
+                        ..repo.clone()
+                    };
+                    updated_version = Some(Crate::GitHub(updated));
+                } else {
+                    bail!("unable to capture sha for {}", repo.slug());
                 }
+            }
 
-                for tc in &self.ex.toolchains {
-                    if let Err(e) = self.api.record_progress(
-                        self.ex,
-                        &krate,
-                        tc,
-                        format!(
-                            "{}\n\nthis task or one of its parent failed: {:?}",
-                            logs, err
-                        )
-                        .as_bytes(),
-                        &result,
-                        updated_version.as_ref().map(|new| (&krate, new)),
-                    ) {
-                        crate::utils::report_failure(&e);
-                    }
+            // Fetch the dependencies once before the build phase, so that network failures are
+            // reported as prepare errors instead of build failures
+            let quiet = self.config.is_quiet(krate);
+            // Nothing is measured while fetching, so the report is discarded
+            let report = Mutex::default();
+            let tc = self.toolchains[0];
+            let ctx = TaskCtx::new(
+                &self.build_dir[tc],
+                self.config,
+                self.ex,
+                tc,
+                krate,
+                quiet,
+                self.build_cache.as_ref(),
+                &report,
+            );
+            fetch_dependencies(&ctx)
+        });
+        if let Err(err) = prepare {
+            if self.is_interrupted() {
+                self.remove_checkout(krate);
+                return Ok(());
+            }
+
+            let mut result = if self.config.is_broken(krate) {
+                TestResult::BrokenCrate(BrokenReason::Unknown)
+            } else {
+                TestResult::Error
+            };
+
+            if let Some(OverrideResult(res)) = err.downcast_ref() {
+                result = res.clone();
+            }
+
+            for &tc in self.toolchains {
+                if let Err(e) = self.api.record_progress(
+                    self.ex,
+                    krate,
+                    tc,
+                    format!(
+                        "{}\n\nthis task or one of its parent failed: {:?}",
+                        logs, err
+                    )
+                    .as_bytes(),
+                    &result,
+                    &BuildReport::default(),
+                    updated_version.as_ref().map(|new| (krate, new)),
+                ) {
+                    crate::utils::report_failure(&e);
                 }
-                continue;
             }
+            self.remove_checkout(krate);
+            return Ok(());
+        }
 
-            for tc in &self.ex.toolchains {
-                let quiet = self.config.is_quiet(&krate);
-                let task = Task {
-                    krate: krate.clone(),
-                    step: match self.ex.mode {
-                        Mode::BuildOnly => TaskStep::BuildOnly {
+        for &tc in self.toolchains {
+            let _span = info_span!("toolchain", toolchain = %tc).entered();
+            let quiet = self.config.is_quiet(krate);
+            let task = Task {
+                krate: krate.clone(),
+                step: match self.ex.mode {
+                    Mode::BuildOnly => TaskStep::BuildOnly {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::BuildAndTest | Mode::BuildTestAndDoctest
+                        if !self.ex.ignore_blacklist && self.config.should_skip_tests(krate) =>
+                    {
+                        TaskStep::BuildOnly {
                             tc: tc.clone(),
                             quiet,
-                        },
-                        Mode::BuildAndTest
-                            if !self.ex.ignore_blacklist
-                                && self.config.should_skip_tests(&krate) =>
-                        {
-                            TaskStep::BuildOnly {
-                                tc: tc.clone(),
-                                quiet,
-                            }
                         }
-                        Mode::BuildAndTest => TaskStep::BuildAndTest {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        Mode::CheckOnly => TaskStep::CheckOnly {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        Mode::Clippy => TaskStep::Clippy {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        Mode::Rustdoc => TaskStep::Rustdoc {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        Mode::UnstableFeatures => TaskStep::UnstableFeatures { tc: tc.clone() },
-                    },
-                };
-
-                // Fork logs off to distinct branch, so that each toolchain has its own log file,
-                // while keeping the shared prepare step in common.
-                let storage = logs.duplicate();
-                match self.run_task(&task, &storage) {
-                    Ok(res) => {
-                        self.api.record_progress(
-                            self.ex,
-                            &task.krate,
-                            tc,
-                            storage.to_string().as_bytes(),
-                            &res,
-                            updated_version.as_ref().map(|new| (&krate, new)),
-                        )?;
-                    }
-                    Err((err, test_result)) => {
-                        self.api.record_progress(
-                            self.ex,
-                            &task.krate,
-                            tc,
-                            format!("{}\n\n{:?}", storage, err).as_bytes(),
-                            &test_result,
-                            updated_version.as_ref().map(|new| (&krate, new)),
-                        )?;
                     }
+                    Mode::BuildAndTest => TaskStep::BuildAndTest {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::BuildTestAndDoctest => TaskStep::BuildTestAndDoctest {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::CheckOnly => TaskStep::CheckOnly {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::Clippy => TaskStep::Clippy {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::Rustdoc => TaskStep::Rustdoc {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                    Mode::UnstableFeatures => TaskStep::UnstableFeatures { tc: tc.clone() },
+                    Mode::Reproducibility => TaskStep::Reproducibility {
+                        tc: tc.clone(),
+                        quiet,
+                    },
+                },
+            };
+
+            // Fork logs off to distinct branch, so that each toolchain has its own log file,
+            // while keeping the shared prepare step in common.
+            let storage = logs.duplicate();
+            let report = Mutex::default();
+            let result = self.run_task(&task, &storage, &report);
+            self.mark_build_dir_used(tc);
+            // The build was likely killed, its result would be meaningless
+            if self.is_interrupted() {
+                break;
+            }
+            let (log, res) = match result {
+                Ok(res) => (storage.to_string(), res),
+                Err((err, test_result)) => (format!("{}\n\n{:?}", storage, err), test_result),
+            };
+            let report = report.into_inner().unwrap();
+
+            self.api.record_progress(
+                self.ex,
+                &task.krate,
+                tc,
+                log.as_bytes(),
+                &res,
+                &report,
+                updated_version.as_ref().map(|new| (krate, new)),
+            )?;
+            recorded.push(tc.clone());
+        }
+        self.remove_checkout(krate);
+        Ok(())
+    }
+
+    /// Record an error for the toolchains of the crate not tested yet after the worker panicked,
+    /// and prepare the worker to move on to the next crate, instead of taking down the thread.
+    fn recover_from_panic(
+        &self,
+        krate: &Crate,
+        recorded: &[Toolchain],
+        payload: &(dyn Any + Send),
+    ) {
+        let message = utils::panic_message(payload).unwrap_or("unknown panic");
+        error!(
+            "{} panicked while testing {}: {}",
+            self.name, krate, message
+        );
+        crate::agent::record_worker_restart();
+        self.reset_after_panic();
+
+        if !self.is_interrupted() {
+            let log = format!("crater panicked while testing the crate: {message}");
+            for &tc in self.toolchains.iter().filter(|tc| !recorded.contains(tc)) {
+                if let Err(e) = self.api.record_progress(
+                    self.ex,
+                    krate,
+                    tc,
+                    log.as_bytes(),
+                    &TestResult::Error,
+                    &BuildReport::default(),
+                    None,
+                ) {
+                    crate::utils::report_failure(&e);
                 }
             }
         }
+        self.remove_checkout(krate);
+    }
+
+    /// The build directories may have been left locked and half-built by a panic, so they're
+    /// unlocked and purged before the next crate.
+    fn reset_after_panic(&self) {
+        for dir in self.build_dir.values() {
+            dir.clear_poison();
+        }
+        self.target_dirs.clear_poison();
+        self.schedule_target_dir_cleanup();
+    }
+
+    fn remove_checkout(&self, krate: &Crate) {
+        if let Err(err) = krate.remove_checkout() {
+            warn!("failed to remove the checkout of {}: {:?}", krate, err);
+        }
+    }
+
+    fn mark_build_dir_used(&self, tc: &Toolchain) {
+        let mut target_dirs = self.target_dirs.lock().unwrap();
+        if let Some(pos) = target_dirs.iter().position(|(dir_tc, _)| *dir_tc == tc) {
+            let dir = target_dirs.remove(pos);
+            target_dirs.push(dir);
+        }
+    }
+
+    fn target_dirs_size(&self) -> Fallible<u64> {
+        let target_dirs = self.target_dirs.lock().unwrap();
+        target_dirs.iter().map(|(_, path)| dir_size(path)).sum()
+    }
+
+    /// Purge the least recently used build directories until the target directories of this
+    /// worker fit in the configured quota.
+    fn enforce_target_dir_quota(&self) -> Fallible<()> {
+        let quota = match self.config.sandbox.target_dir_quota {
+            Some(quota) => quota.to_bytes() as u64,
+            None => return Ok(()),
+        };
+
+        let target_dirs = self.target_dirs.lock().unwrap();
+        let sizes = target_dirs
+            .iter()
+            .map(|(tc, path)| Ok((*tc, dir_size(path)?)))
+            .collect::<Fallible<Vec<_>>>()?;
+        let mut total: u64 = sizes.iter().map(|(_, size)| size).sum();
+        for (tc, size) in sizes {
+            if total <= quota {
+                break;
+            }
+            info!(
+                "target dirs of {} over the quota ({} bytes), purging the build dir of {}",
+                self.name, total, tc
+            );
+            self.build_dir[tc].lock().unwrap().purge()?;
+            total -= size;
+        }
+        Ok(())
     }
 
     fn maybe_cleanup_target_dir(&self) -> Fallible<()> {
+        self.enforce_target_dir_quota()?;
         if !self.target_dir_cleanup.swap(false, Ordering::SeqCst) {
             return Ok(());
         }
@@ -403,10 +672,41 @@ impl<'a> DiskSpaceWatcher<'a> {
         };
 
         if usage.is_threshold_reached(self.threshold) {
-            warn!("running the scheduled thread cleanup");
-            for worker in self.workers {
-                worker.schedule_target_dir_cleanup();
+            let has_quota = self
+                .workers
+                .first()
+                .is_some_and(|w| w.config.sandbox.target_dir_quota.is_some());
+            if has_quota {
+                // Workers already keep their target dirs small, so only purge the largest ones
+                // instead of making all the workers start from scratch
+                let largest = self.workers.iter().max_by_key(|worker| {
+                    worker.target_dirs_size().unwrap_or_else(|err| {
+                        warn!(
+                            "failed to measure the target dirs of {}: {}",
+                            worker.name, err
+                        );
+                        0
+                    })
+                });
+                if let Some(worker) = largest {
+                    warn!("running the scheduled cleanup of {}", worker.name);
+                    worker.schedule_target_dir_cleanup();
+                }
+            } else {
+                warn!("running the scheduled thread cleanup");
+                for worker in self.workers {
+                    worker.schedule_target_dir_cleanup();
+                }
             }
         }
     }
 }
+
+/// Path of the target directory of a build directory, following the layout of the rustwide
+/// workspace.
+fn target_dir_path(build_dir_name: &str) -> PathBuf {
+    crate::dirs::WORK_DIR
+        .join("builds")
+        .join(build_dir_name)
+        .join("target")
+}
@@ -1,4 +1,5 @@
 use crate::agent::Capabilities;
+use crate::config::Config;
 use crate::db::{Database, QueryUtils};
 use crate::experiments::{Assignee, Experiment};
 use crate::prelude::*;
@@ -81,8 +82,18 @@ pub struct Agents {
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct WorkerInfo {
     id: String,
+    /// Workers of the agent restarted after a panic since its previous heartbeat.
+    #[serde(default)]
+    worker_restarts: u32,
+}
+
+impl WorkerInfo {
+    pub fn worker_restarts(&self) -> u32 {
+        self.worker_restarts
+    }
 }
 
 impl Agents {
@@ -95,6 +106,15 @@ impl Agents {
         Ok(agents)
     }
 
+    /// Load the agents without synchronizing them with the tokens, as that requires writing to
+    /// the database.
+    pub fn read_only(db: Database) -> Self {
+        Agents {
+            db,
+            current_workers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
     pub fn active_worker_count(&self) -> usize {
         let mut guard = self.current_workers.lock().unwrap();
         guard.retain(|_, (_, timestamp)| {
@@ -181,6 +201,28 @@ impl Agents {
             .map_err(Into::into)
     }
 
+    /// Hand the experiments of the agents which didn't send an heartbeat in the last
+    /// `server.agent-failover-after` minutes over to the other agents.
+    pub fn failover(&self, config: &Config) -> Fallible<()> {
+        let threshold = Utc::now() - Duration::minutes(config.server.agent_failover_after as i64);
+
+        for agent in self.all()? {
+            if agent.last_heartbeat.is_some_and(|h| h > threshold) {
+                continue;
+            }
+
+            if let Some(mut ex) = agent.experiment {
+                warn!(
+                    "agent {} stopped responding, handing over experiment {}",
+                    agent.name, ex.name
+                );
+                ex.handle_failure(&self.db, &Assignee::Agent(agent.name.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn record_heartbeat(&self, agent: &str) -> Fallible<()> {
         let changes = self.db.execute(
             "UPDATE agents SET last_heartbeat = ?1 WHERE name = ?2;",
@@ -220,9 +262,10 @@ mod tests {
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::agent::Capabilities;
     use crate::config::Config;
-    use crate::db::Database;
+    use crate::db::{Database, QueryUtils};
     use crate::experiments::{Assignee, Experiment};
     use crate::server::tokens::Tokens;
+    use chrono::{Duration, Utc};
 
     #[test]
     fn test_agents_synchronize() {
@@ -306,13 +349,95 @@ mod tests {
         let (_new, ex) = Experiment::next(&db, &Assignee::Agent("agent".to_string()))
             .unwrap()
             .unwrap();
-        ex.get_uncompleted_crates(&db, None).unwrap();
+        ex.get_uncompleted_crates(&db, &config, None).unwrap();
 
         // After an experiment is assigned to the agent, the agent is working
         let agent = agents.get("agent").unwrap().unwrap();
         assert_eq!(agent.status(), AgentStatus::Working);
     }
 
+    #[test]
+    fn test_agent_failover() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token".into(), "agent".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // Assign an experiment to the agent and start running its crates
+        agents.record_heartbeat("agent").unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let agent = Assignee::Agent("agent".to_string());
+        let (_new, mut ex) = Experiment::next(&db, &agent).unwrap().unwrap();
+        ex.set_assigned_to(&db, Some(&agent)).unwrap();
+        let crates = ex.get_uncompleted_crates(&db, &config, None).unwrap();
+        assert!(!crates.is_empty());
+
+        // Nothing happens while the agent is still responding
+        agents.failover(&config).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.assigned_to, Some(agent.clone()));
+
+        db.execute(
+            "UPDATE agents SET last_heartbeat = ?1 WHERE name = 'agent';",
+            &[&(Utc::now() - Duration::hours(1))],
+        )
+        .unwrap();
+        agents.failover(&config).unwrap();
+
+        // The experiment is now distributed, and its crates are queued again
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.assigned_to, Some(Assignee::Distributed));
+        let agent = agents.get("agent").unwrap().unwrap();
+        assert!(agent.experiment.is_none());
+        assert_eq!(
+            ex.get_uncompleted_crates(&db, &config, None).unwrap().len(),
+            crates.len()
+        );
+    }
+
+    #[test]
+    fn test_agent_failover_distributed() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token".into(), "agent".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // The experiment is distributed, and only one of its crates went to the agent
+        agents.record_heartbeat("agent").unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let agent = Assignee::Agent("agent".to_string());
+        let (_new, ex) = Experiment::next(&db, &agent).unwrap().unwrap();
+        assert_eq!(ex.assigned_to, Some(Assignee::Distributed));
+        let crates = ex.get_uncompleted_crates(&db, &config, None).unwrap();
+        assert!(crates.len() > 1);
+        ex.record_crate_assignment(&db, &crates[0], &agent).unwrap();
+
+        db.execute(
+            "UPDATE agents SET last_heartbeat = ?1 WHERE name = 'agent';",
+            &[&(Utc::now() - Duration::hours(1))],
+        )
+        .unwrap();
+        agents.failover(&config).unwrap();
+
+        // Only the crate of the failed agent is queued again
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.assigned_to, Some(Assignee::Distributed));
+        assert_eq!(
+            ex.get_uncompleted_crates(&db, &config, None).unwrap(),
+            vec![crates[0].clone()]
+        );
+    }
+
     #[test]
     fn test_agent_capabilities() {
         let db = Database::temp().unwrap();
@@ -0,0 +1,349 @@
+//! Encodings of the bodies exchanged by the agents and the server. JSON bodies are large, as they
+//! carry the configuration, the experiments and the logs of the builds, so agents and servers
+//! supporting it exchange MessagePack bodies compressed with zstd (or gzip) instead.
+//!
+//! The agents negotiate the encoding when fetching their configuration: they advertise the
+//! encodings they accept, and only switch to the compact one when the server answers with it.
+//! Both sides decode the bodies according to their `Content-Type` and `Content-Encoding`
+//! headers, so agents and servers not supporting the compact encoding keep using JSON.
+
+use crate::prelude::*;
+use crate::server::uploads::MAX_UPLOAD_SIZE;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, BufReader, Read, Write};
+
+pub const JSON: &str = "application/json";
+pub const MSGPACK: &str = "application/msgpack";
+pub const ZSTD: &str = "zstd";
+pub const GZIP: &str = "gzip";
+/// `Accept` and `Accept-Encoding` headers sent by the agents.
+pub const ACCEPTED_FORMATS: &str = "application/msgpack, application/json";
+pub const ACCEPTED_COMPRESSIONS: &str = "zstd, gzip";
+
+/// Size above which decompressed bodies are rejected, to avoid exhausting the memory. No body
+/// can be larger than the biggest upload accepted by the server.
+pub const MAX_DECODED_SIZE: u64 = MAX_UPLOAD_SIZE;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EncodingError {
+    #[error("unsupported content type `{0}`")]
+    UnsupportedFormat(String),
+    #[error("unsupported content encoding `{0}`")]
+    UnsupportedCompression(String),
+    #[error("the decompressed body is larger than the maximum size")]
+    TooLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MsgPack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+    pub format: Format,
+    pub compression: Option<Compression>,
+}
+
+impl Encoding {
+    /// Plain JSON, understood by every agent and server.
+    pub const JSON: Encoding = Encoding {
+        format: Format::Json,
+        compression: None,
+    };
+
+    /// Encoding of a received body, from its `Content-Type` and `Content-Encoding` headers.
+    /// Bodies without a content type are JSON.
+    pub fn from_headers(
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+    ) -> Result<Encoding, EncodingError> {
+        let format = match content_type.map(media_type) {
+            None | Some(JSON) => Format::Json,
+            Some(MSGPACK) => Format::MsgPack,
+            Some(other) => return Err(EncodingError::UnsupportedFormat(other.into())),
+        };
+        let compression = match content_encoding.map(str::trim) {
+            None | Some("") | Some("identity") => None,
+            Some(ZSTD) => Some(Compression::Zstd),
+            Some(GZIP) => Some(Compression::Gzip),
+            Some(other) => return Err(EncodingError::UnsupportedCompression(other.into())),
+        };
+        Ok(Encoding {
+            format,
+            compression,
+        })
+    }
+
+    /// Most compact encoding accepted by the other side, from its `Accept` and
+    /// `Accept-Encoding` headers.
+    pub fn negotiate(accept: Option<&str>, accept_encoding: Option<&str>) -> Encoding {
+        let accepts = |header: Option<&str>, value: &str| {
+            header
+                .into_iter()
+                .flat_map(|header| header.split(','))
+                .any(|item| media_type(item) == value)
+        };
+        Encoding {
+            format: if accepts(accept, MSGPACK) {
+                Format::MsgPack
+            } else {
+                Format::Json
+            },
+            compression: if accepts(accept_encoding, ZSTD) {
+                Some(Compression::Zstd)
+            } else if accepts(accept_encoding, GZIP) {
+                Some(Compression::Gzip)
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self.format {
+            Format::Json => JSON,
+            Format::MsgPack => MSGPACK,
+        }
+    }
+
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        self.compression.map(|compression| match compression {
+            Compression::Zstd => ZSTD,
+            Compression::Gzip => GZIP,
+        })
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Fallible<Vec<u8>> {
+        let serialized = match self.format {
+            Format::Json => serde_json::to_vec(value)?,
+            // Structs are serialized as maps, as the API relies on flattened and tagged types
+            Format::MsgPack => rmp_serde::to_vec_named(value)?,
+        };
+        self.compress(&serialized)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Fallible<T> {
+        self.decode_limited(body, MAX_DECODED_SIZE)
+    }
+
+    /// Decode the body, rejecting it if it's larger than `max_size` once decompressed.
+    pub fn decode_limited<T: DeserializeOwned>(&self, body: &[u8], max_size: u64) -> Fallible<T> {
+        let body = self.decompress(body, max_size)?;
+        Ok(match self.format {
+            Format::Json => serde_json::from_slice(&body)?,
+            Format::MsgPack => rmp_serde::from_slice(&body)?,
+        })
+    }
+
+    /// Decode the body read from `reader` as it's decompressed, without loading all of it in
+    /// memory, rejecting it if it's larger than `max_size` once decompressed.
+    pub fn decode_from<'a, T: DeserializeOwned, R: Read + 'a>(
+        &self,
+        reader: R,
+        max_size: u64,
+    ) -> Fallible<T> {
+        let reader: Box<dyn Read + 'a> = match self.compression {
+            None => Box::new(reader),
+            Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+            Some(Compression::Gzip) => Box::new(GzDecoder::new(reader)),
+        };
+        let mut limited = LimitedReader {
+            inner: reader,
+            remaining: max_size,
+            exceeded: false,
+        };
+        let decoded = match self.format {
+            Format::Json => {
+                serde_json::from_reader(BufReader::new(&mut limited)).map_err(anyhow::Error::from)
+            }
+            Format::MsgPack => {
+                rmp_serde::from_read(BufReader::new(&mut limited)).map_err(anyhow::Error::from)
+            }
+        };
+        // The deserializers only see the error of the reader as truncated input
+        if limited.exceeded {
+            return Err(EncodingError::TooLarge.into());
+        }
+        decoded
+    }
+
+    /// Compress an already serialized body.
+    pub fn compress(&self, body: &[u8]) -> Fallible<Vec<u8>> {
+        Ok(match self.compression {
+            None => body.to_vec(),
+            Some(Compression::Zstd) => zstd::encode_all(body, ZSTD_LEVEL)?,
+            Some(Compression::Gzip) => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    fn decompress(&self, body: &[u8], max_size: u64) -> Fallible<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self.compression {
+            None if body.len() as u64 > max_size => return Err(EncodingError::TooLarge.into()),
+            None => return Ok(body.to_vec()),
+            Some(Compression::Zstd) => Box::new(zstd::Decoder::new(body)?),
+            Some(Compression::Gzip) => Box::new(GzDecoder::new(body)),
+        };
+        let mut decompressed = Vec::new();
+        reader.take(max_size + 1).read_to_end(&mut decompressed)?;
+        if decompressed.len() as u64 > max_size {
+            return Err(EncodingError::TooLarge.into());
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Reader failing once more than `remaining` bytes are read from it.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // One more byte than allowed is read, to detect the bodies past the limit
+        let max = (buf.len() as u64).min(self.remaining + 1) as usize;
+        let read = self.inner.read(&mut buf[..max])?;
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other(EncodingError::TooLarge));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Media type of a header value, without its parameters (like `; charset=utf-8` or `;q=0.5`).
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, Encoding, EncodingError, Format, MAX_DECODED_SIZE};
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::results::{ProgressData, TaskResult, TestResult};
+    use crate::server::api_types::ApiResponse;
+    use crate::server::routes::agent::ExperimentData;
+    use crate::toolchain::MAIN_TOOLCHAIN;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Encoding::negotiate(None, None), Encoding::JSON);
+        assert_eq!(
+            Encoding::negotiate(
+                Some("application/msgpack, application/json"),
+                Some("gzip, zstd;q=1.0")
+            ),
+            Encoding {
+                format: Format::MsgPack,
+                compression: Some(Compression::Zstd),
+            }
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("*/*"), Some("gzip, deflate")),
+            Encoding {
+                format: Format::Json,
+                compression: Some(Compression::Gzip),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_headers() {
+        assert_eq!(Encoding::from_headers(None, None), Ok(Encoding::JSON));
+        assert_eq!(
+            Encoding::from_headers(Some("application/json; charset=utf-8"), Some("identity")),
+            Ok(Encoding::JSON)
+        );
+        assert_eq!(
+            Encoding::from_headers(Some("application/msgpack"), Some("zstd")),
+            Ok(Encoding {
+                format: Format::MsgPack,
+                compression: Some(Compression::Zstd),
+            })
+        );
+        assert_eq!(
+            Encoding::from_headers(Some("text/plain"), None),
+            Err(EncodingError::UnsupportedFormat("text/plain".into()))
+        );
+        assert_eq!(
+            Encoding::from_headers(None, Some("br")),
+            Err(EncodingError::UnsupportedCompression("br".into()))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let body = serde_json::json!({
+            "experiment-name": "foo",
+            "result": {
+                "crate": Crate::Registry(RegistryCrate {
+                    name: "lazy_static".into(),
+                    version: "1.0.0".into(),
+                }),
+                "toolchain": MAIN_TOOLCHAIN.clone(),
+                "result": TestResult::TestPass,
+                "log": "bG9n",
+            },
+            "version": null,
+        });
+
+        for format in [Format::Json, Format::MsgPack] {
+            for compression in [None, Some(Compression::Zstd), Some(Compression::Gzip)] {
+                let encoding = Encoding {
+                    format,
+                    compression,
+                };
+                let encoded = encoding.encode(&body).unwrap();
+
+                // Flattened fields and tagged enums survive the compact encoding
+                let decoded: ExperimentData<ProgressData> = encoding.decode(&encoded).unwrap();
+                let TaskResult { result, log, .. } = decoded.data.result;
+                assert_eq!(result, TestResult::TestPass);
+                assert_eq!(log, "bG9n");
+
+                let response = encoding
+                    .encode(&ApiResponse::Success { result: Some(42) })
+                    .unwrap();
+                let response: ApiResponse<Option<u32>> = encoding.decode(&response).unwrap();
+                assert!(matches!(
+                    response,
+                    ApiResponse::Success { result: Some(42) }
+                ));
+
+                // Bodies can be decoded as they're read
+                let decoded: ExperimentData<ProgressData> = encoding
+                    .decode_from(encoded.as_slice(), MAX_DECODED_SIZE)
+                    .unwrap();
+                assert_eq!(decoded.data.result.log, "bG9n");
+
+                // Bodies larger than the limit once decompressed are rejected
+                let err = encoding
+                    .decode_limited::<ExperimentData<ProgressData>>(&encoded, 16)
+                    .unwrap_err();
+                assert_eq!(err.downcast_ref(), Some(&EncodingError::TooLarge));
+                let err = encoding
+                    .decode_from::<ExperimentData<ProgressData>, _>(encoded.as_slice(), 16)
+                    .unwrap_err();
+                assert_eq!(err.downcast_ref(), Some(&EncodingError::TooLarge));
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::Config;
 use crate::prelude::*;
-use http::header::{HeaderValue, CONTENT_TYPE};
+use crate::server::api_encoding::Encoding;
+use http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
 use http::Response;
 use http::StatusCode;
 use hyper::Body;
@@ -9,6 +10,27 @@ use std::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
 
+/// Header with the idempotency key of the results uploaded by the agents.
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Headers identifying the chunks of the results uploaded in multiple requests.
+pub const UPLOAD_ID: &str = "upload-id";
+pub const UPLOAD_OFFSET: &str = "upload-offset";
+/// Size of the chunks of the uploads. Results larger than a chunk are uploaded in chunks.
+pub const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Request completing a chunked upload, with the SHA-256 hash of the whole body. The body is
+/// JSON unless the content type and encoding of the assembled body are provided.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CompleteUpload {
+    pub upload_id: String,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AgentConfig {
@@ -24,6 +46,8 @@ pub enum ApiResponse<T> {
     InternalError { error: String },
     Unauthorized,
     NotFound,
+    ReadOnly,
+    BadRequest { error: String },
 }
 
 impl ApiResponse<()> {
@@ -38,6 +62,14 @@ impl ApiResponse<()> {
     pub(in crate::server) fn not_found() -> ApiResponse<()> {
         ApiResponse::NotFound
     }
+
+    pub(in crate::server) fn read_only() -> ApiResponse<()> {
+        ApiResponse::ReadOnly
+    }
+
+    pub(in crate::server) fn bad_request(error: String) -> ApiResponse<()> {
+        ApiResponse::BadRequest { error }
+    }
 }
 
 impl<T> ApiResponse<T> {
@@ -48,17 +80,33 @@ impl<T> ApiResponse<T> {
             ApiResponse::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ApiResponse::Unauthorized => StatusCode::UNAUTHORIZED,
             ApiResponse::NotFound => StatusCode::NOT_FOUND,
+            ApiResponse::ReadOnly => StatusCode::SERVICE_UNAVAILABLE,
+            ApiResponse::BadRequest { .. } => StatusCode::BAD_REQUEST,
         }
     }
 }
 
 impl<T: Serialize> ApiResponse<T> {
     pub(in crate::server) fn into_response(self) -> Fallible<Response<Body>> {
-        let serialized = ::serde_json::to_vec(&self)?;
+        self.into_response_with(Encoding::JSON)
+    }
+
+    /// Response encoded with the encoding negotiated with the agent.
+    pub(in crate::server) fn into_response_with(
+        self,
+        encoding: Encoding,
+    ) -> Fallible<Response<Body>> {
+        let serialized = encoding.encode(&self)?;
 
         let mut resp = Response::new(serialized.into());
-        resp.headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        resp.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(encoding.content_type()),
+        );
+        if let Some(content_encoding) = encoding.content_encoding() {
+            resp.headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+        }
         *resp.status_mut() = self.status_code();
         Ok(resp)
     }
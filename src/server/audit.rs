@@ -0,0 +1,75 @@
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+
+/// Action performed through the admin API, as recorded in the audit log.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub author: String,
+    pub action: String,
+    pub experiment: Option<String>,
+    pub details: Option<String>,
+    pub performed_at: DateTime<Utc>,
+}
+
+/// Record an action in the audit log. Pass the transaction of the action when it changes the
+/// database, so that the entry is only recorded if the action is.
+pub fn record(
+    db: &impl QueryUtils,
+    author: &str,
+    action: &str,
+    experiment: Option<&str>,
+    details: Option<&str>,
+) -> Fallible<()> {
+    info!(
+        "admin {} performed {} (experiment: {:?}, details: {:?})",
+        author, action, experiment, details
+    );
+    db.execute(
+        "INSERT INTO audit_log (author, action, experiment, details, performed_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+        &[&author, &action, &experiment, &details, &Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// Load the most recent entries of the audit log, starting from the newest one.
+pub fn recent(db: &Database, limit: u32) -> Fallible<Vec<AuditEntry>> {
+    db.query(
+        "SELECT * FROM audit_log ORDER BY id DESC LIMIT ?1;",
+        [&limit],
+        |row| {
+            Ok(AuditEntry {
+                author: row.get("author")?,
+                action: row.get("action")?,
+                experiment: row.get("experiment")?,
+                details: row.get("details")?,
+                performed_at: row.get("performed_at")?,
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recent, record};
+    use crate::db::Database;
+
+    #[test]
+    fn test_audit_log() {
+        let db = Database::temp().unwrap();
+        assert!(recent(&db, 10).unwrap().is_empty());
+
+        record(&db, "alice", "reload-acl", None, None).unwrap();
+        record(&db, "bob", "set-priority", Some("foo"), Some("10")).unwrap();
+
+        let entries = recent(&db, 10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].author, "bob");
+        assert_eq!(entries[0].experiment.as_deref(), Some("foo"));
+        assert_eq!(entries[0].details.as_deref(), Some("10"));
+        assert_eq!(entries[1].action, "reload-acl");
+
+        assert_eq!(recent(&db, 1).unwrap().len(), 1);
+    }
+}
@@ -1,7 +1,8 @@
 use crate::config::Config;
 use crate::prelude::*;
-use crate::server::github::{GitHub, GitHubApi};
-use crate::server::{Data, GithubData, HttpError};
+use crate::server::tokens::AdminScope;
+use crate::server::vcs::VcsHost;
+use crate::server::{BotData, Data, HttpError};
 use http::header::{HeaderMap, AUTHORIZATION, USER_AGENT};
 use regex::Regex;
 use rust_team_data::v1 as team_data;
@@ -14,6 +15,13 @@ lazy_static! {
         Regex::new(r"^crater(-agent)?/(?P<sha>[a-f0-9]{7,40})( \(.*\))?$").unwrap();
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenType {
+    Agent,
+    Triager,
+    Admin(AdminScope),
+}
+
 pub struct AuthDetails {
     pub name: String,
     pub git_revision: Option<String>,
@@ -40,7 +48,7 @@ fn git_revision(user_agent: &str) -> Option<String> {
         .map(|cap| cap["sha"].to_string())
 }
 
-fn check_auth(data: &Data, headers: &HeaderMap) -> Option<AuthDetails> {
+fn check_auth(data: &Data, headers: &HeaderMap, token_type: TokenType) -> Option<AuthDetails> {
     // Try to extract the git revision from the User-Agent header
     let git_revision = if let Some(ua_value) = headers.get(USER_AGENT) {
         if let Ok(ua) = ua_value.to_str() {
@@ -55,7 +63,17 @@ fn check_auth(data: &Data, headers: &HeaderMap) -> Option<AuthDetails> {
     if let Some(authorization_value) = headers.get(AUTHORIZATION) {
         if let Ok(authorization) = authorization_value.to_str() {
             if let Some(token) = parse_token(authorization) {
-                if let Some(name) = data.tokens.agents.get(token) {
+                let name = match token_type {
+                    TokenType::Agent => data.tokens.agents.get(token),
+                    TokenType::Triager => data.tokens.triagers.get(token),
+                    TokenType::Admin(scope) => data
+                        .tokens
+                        .admins
+                        .get(token)
+                        .filter(|admin| admin.scopes.contains(&scope))
+                        .map(|admin| &admin.name),
+                };
+                if let Some(name) = name {
                     return Some(AuthDetails {
                         name: name.clone(),
                         git_revision,
@@ -70,11 +88,12 @@ fn check_auth(data: &Data, headers: &HeaderMap) -> Option<AuthDetails> {
 
 pub fn auth_filter(
     data: Arc<Data>,
+    token_type: TokenType,
 ) -> impl Filter<Extract = (AuthDetails,), Error = Rejection> + Clone {
     warp::header::headers_cloned().and_then(move |headers| {
         let data = data.clone();
         async move {
-            match check_auth(&data, &headers) {
+            match check_auth(&data, &headers, token_type) {
                 Some(details) => Ok(details),
                 None => Err(warp::reject::custom(HttpError::Forbidden)),
             }
@@ -91,7 +110,7 @@ pub struct ACL {
 }
 
 impl ACL {
-    pub fn new(config: &Config, github: Option<&GithubData>) -> Fallible<Self> {
+    pub fn new(config: &Config, github: Option<&BotData>) -> Fallible<Self> {
         let mut users = Vec::new();
         let mut teams = Vec::new();
 
@@ -113,12 +132,12 @@ impl ACL {
         };
 
         if let Some(github) = github {
-            acl.refresh_cache(&github.api)?;
+            acl.refresh_cache(&*github.api)?;
         }
         Ok(acl)
     }
 
-    pub fn refresh_cache(&self, github: &GitHubApi) -> Fallible<()> {
+    pub fn refresh_cache(&self, github: &dyn VcsHost) -> Fallible<()> {
         // A new HashSet is created instead of clearing the old one
         // This is done because if an error occurs the old cache is not flushed
         let mut new_cache = HashSet::new();
@@ -147,7 +166,7 @@ impl ACL {
 
     fn load_team(
         &self,
-        github: &GitHubApi,
+        github: &dyn VcsHost,
         new_cache: &mut HashSet<String>,
         orgs: &mut HashMap<String, HashMap<String, usize>>,
         org: &str,
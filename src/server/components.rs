@@ -0,0 +1,179 @@
+//! Availability of the rustup components needed by the experiments. The builds of some commits
+//! of rust-lang/rust don't ship all the components (for example clippy when it fails to build),
+//! which would make the agents fail to prepare the experiment. They're checked when the
+//! experiments are created, suggesting the nearest commit with the components available.
+
+use crate::experiments::Mode;
+use crate::prelude::*;
+use crate::server::vcs::VcsHost;
+use crate::toolchain::{Toolchain, HOST_TARGET};
+use crate::utils;
+use reqwest::{Method, StatusCode};
+
+const CI_ARTIFACTS_URL: &str = "https://ci-artifacts.rust-lang.org";
+const RUST_REPO: &str = "rust-lang/rust";
+/// Number of ancestors of a commit looked at to find one with the component available.
+const MAX_ANCESTORS: usize = 20;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub struct ComponentUnavailable {
+    component: &'static str,
+    toolchain: Toolchain,
+    /// Nearest ancestor of the commit with the component available.
+    suggestion: Option<String>,
+}
+
+impl std::fmt::Display for ComponentUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "the `{}` component is not available for toolchain `{}`",
+            self.component, self.toolchain
+        )?;
+        match &self.suggestion {
+            Some(sha) => write!(
+                f,
+                ", the nearest commit with it available is `master#{sha}`"
+            ),
+            None => write!(
+                f,
+                ", and none of its last {MAX_ANCESTORS} ancestors has it available"
+            ),
+        }
+    }
+}
+
+/// Components needed by the experiments in this mode, besides the ones installed by default.
+fn required_components(mode: Mode) -> &'static [&'static str] {
+    match mode {
+        Mode::Clippy => &["clippy"],
+        _ => &[],
+    }
+}
+
+/// Whether the build of the commit includes the component.
+fn is_available(sha: &str, alt: bool, component: &str) -> Fallible<bool> {
+    let builds = if alt {
+        "rustc-builds-alt"
+    } else {
+        "rustc-builds"
+    };
+    let url = format!("{CI_ARTIFACTS_URL}/{builds}/{sha}/{component}-nightly-{HOST_TARGET}.tar.xz");
+    let resp = utils::http::prepare_sync(Method::HEAD, &url).send()?;
+    match resp.status() {
+        StatusCode::OK => Ok(true),
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Ok(false),
+        status => bail!("request to {} returned status code {}", url, status),
+    }
+}
+
+/// Walk the first parents of the commit, returning the first one for which `available` is true.
+fn nearest_available(
+    sha: &str,
+    mut parent: impl FnMut(&str) -> Fallible<Option<String>>,
+    mut available: impl FnMut(&str) -> Fallible<bool>,
+) -> Fallible<Option<String>> {
+    let mut current = sha.to_string();
+    for _ in 0..MAX_ANCESTORS {
+        let Some(next) = parent(&current)? else {
+            return Ok(None);
+        };
+        if available(&next)? {
+            return Ok(Some(next));
+        }
+        current = next;
+    }
+    Ok(None)
+}
+
+/// Ensure the components needed by the mode are available for the CI toolchains. Release and
+/// nightly toolchains are not checked, as rustup reports their missing components itself.
+pub fn ensure_available(vcs: &dyn VcsHost, toolchains: &[Toolchain], mode: Mode) -> Fallible<()> {
+    for tc in toolchains {
+        let Some(ci) = tc.source.as_ci() else {
+            continue;
+        };
+        for &component in required_components(mode) {
+            if is_available(ci.sha(), ci.alt(), component)? {
+                continue;
+            }
+
+            let parent = |sha: &str| -> Fallible<Option<String>> {
+                let commit = vcs.get_commit(RUST_REPO, sha)?;
+                Ok(commit.parents.into_iter().next().map(|parent| parent.sha))
+            };
+            let available = |sha: &str| is_available(sha, ci.alt(), component);
+            // The suggestion is only a courtesy, failing to find it shouldn't hide the error
+            let suggestion = nearest_available(ci.sha(), parent, available).unwrap_or_else(|e| {
+                warn!(
+                    "failed to find a commit with {} available: {:?}",
+                    component, e
+                );
+                None
+            });
+            return Err(ComponentUnavailable {
+                component,
+                toolchain: tc.clone(),
+                suggestion,
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nearest_available, required_components, ComponentUnavailable, MAX_ANCESTORS};
+    use crate::experiments::Mode;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_required_components() {
+        assert_eq!(required_components(Mode::Clippy), ["clippy"]);
+        assert!(required_components(Mode::BuildAndTest).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_available() {
+        // Each commit is the parent of the next one, and only `c3` has the component
+        let parent = |sha: &str| -> Fallible<Option<String>> {
+            let n: usize = sha[1..].parse()?;
+            Ok((n < 100).then(|| format!("c{}", n + 1)))
+        };
+        let available = |sha: &str| -> Fallible<bool> { Ok(sha == "c3") };
+        assert_eq!(
+            nearest_available("c0", parent, available).unwrap(),
+            Some("c3".into())
+        );
+        assert_eq!(nearest_available("c3", parent, available).unwrap(), None);
+
+        // The search stops after the maximum number of ancestors
+        let available =
+            |sha: &str| -> Fallible<bool> { Ok(sha == format!("c{}", MAX_ANCESTORS + 1)) };
+        assert_eq!(nearest_available("c0", parent, available).unwrap(), None);
+        let available = |sha: &str| -> Fallible<bool> { Ok(sha == format!("c{MAX_ANCESTORS}")) };
+        assert_eq!(
+            nearest_available("c0", parent, available).unwrap(),
+            Some(format!("c{MAX_ANCESTORS}"))
+        );
+    }
+
+    #[test]
+    fn test_message() {
+        let toolchain = "master#0000000000000000000000000000000000000000"
+            .parse()
+            .unwrap();
+        let error = ComponentUnavailable {
+            component: "clippy",
+            toolchain,
+            suggestion: Some("1111111111111111111111111111111111111111".into()),
+        };
+        assert_eq!(
+            error.to_string(),
+            "the `clippy` component is not available for toolchain \
+             `master#0000000000000000000000000000000000000000`, the nearest commit with it \
+             available is `master#1111111111111111111111111111111111111111`"
+        );
+    }
+}
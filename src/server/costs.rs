@@ -0,0 +1,200 @@
+//! Machine time consumed by the experiments, computed from the build times reported by the
+//! agents. It's used to plan the capacity of the agents, and to find which kinds of experiments
+//! are the most expensive to run.
+
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{Duration, NaiveDate, Utc};
+use std::collections::BTreeMap;
+
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+/// Machine time consumed by the builds of an experiment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentCost {
+    pub name: String,
+    pub mode: String,
+    pub builds: u32,
+    pub machine_hours: f64,
+}
+
+/// Average time spent building a crate with a toolchain in an experiment mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeAverage {
+    pub mode: String,
+    pub builds: u32,
+    pub average_seconds: f64,
+}
+
+/// Hours spent building crates by each agent on each of the last days, oldest day first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utilization {
+    pub days: Vec<NaiveDate>,
+    pub agents: BTreeMap<String, Vec<f64>>,
+}
+
+/// Cost of all the experiments with recorded build times, most expensive first.
+pub fn experiment_costs(db: &Database) -> Fallible<Vec<ExperimentCost>> {
+    db.query(
+        "SELECT r.experiment, e.mode, COUNT(*) AS builds, SUM(r.build_time) AS total \
+         FROM results r JOIN experiments e ON e.name = r.experiment \
+         WHERE r.build_time IS NOT NULL \
+         GROUP BY r.experiment ORDER BY total DESC;",
+        [],
+        |row| {
+            Ok(ExperimentCost {
+                name: row.get("experiment")?,
+                mode: row.get("mode")?,
+                builds: row.get("builds")?,
+                machine_hours: row.get::<_, f64>("total")? / SECONDS_PER_HOUR,
+            })
+        },
+    )
+}
+
+pub fn mode_averages(db: &Database) -> Fallible<Vec<ModeAverage>> {
+    db.query(
+        "SELECT e.mode, COUNT(*) AS builds, AVG(r.build_time) AS average \
+         FROM results r JOIN experiments e ON e.name = r.experiment \
+         WHERE r.build_time IS NOT NULL \
+         GROUP BY e.mode ORDER BY e.mode;",
+        [],
+        |row| {
+            Ok(ModeAverage {
+                mode: row.get("mode")?,
+                builds: row.get("builds")?,
+                average_seconds: row.get("average")?,
+            })
+        },
+    )
+}
+
+/// Utilization of the agents over the last days, including the current one.
+pub fn utilization(db: &Database, days: u32) -> Fallible<Utilization> {
+    let today = Utc::now().date_naive();
+    let days = (0..i64::from(days))
+        .rev()
+        .map(|ago| today - Duration::days(ago))
+        .collect::<Vec<_>>();
+    let Some(first) = days.first() else {
+        return Ok(Utilization {
+            days,
+            agents: BTreeMap::new(),
+        });
+    };
+
+    // The timestamps start with their date, so comparing them to the first day as strings lets
+    // the index on the column select the recent results
+    let rows = db.query(
+        "SELECT agent, date(recorded_at) AS day, SUM(build_time) AS total FROM results \
+         WHERE recorded_at >= ?1 AND agent IS NOT NULL AND build_time IS NOT NULL \
+         GROUP BY agent, day;",
+        [first.to_string()],
+        |row| {
+            Ok((
+                row.get::<_, String>("agent")?,
+                row.get::<_, String>("day")?,
+                row.get::<_, f64>("total")?,
+            ))
+        },
+    )?;
+
+    let mut agents: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (agent, day, total) in rows {
+        let day = NaiveDate::parse_from_str(&day, "%Y-%m-%d")?;
+        let Some(idx) = days.iter().position(|d| *d == day) else {
+            continue;
+        };
+        agents.entry(agent).or_insert_with(|| vec![0.0; days.len()])[idx] +=
+            total / SECONDS_PER_HOUR;
+    }
+    Ok(Utilization { days, agents })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{experiment_costs, mode_averages, utilization};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Experiment, Mode};
+    use crate::results::{
+        BuildReport, DatabaseDB, EncodingType, ProgressData, TaskResult, TestResult,
+    };
+    use base64::Engine;
+
+    fn store(db: &Database, ex: &Experiment, agent: &str, krate: usize, time: f64) {
+        let data = ProgressData {
+            result: TaskResult {
+                krate: ex.get_crates(db).unwrap()[krate].clone(),
+                toolchain: ex.toolchains[0].clone(),
+                result: TestResult::TestPass,
+                log: base64::engine::general_purpose::STANDARD.encode("log"),
+                report: BuildReport {
+                    build_time: Some(time),
+                    ..BuildReport::default()
+                },
+            },
+            version: None,
+        };
+        DatabaseDB::new(db)
+            .with_agent(agent)
+            .store(ex, &data, EncodingType::Plain)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_costs() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        CreateExperiment {
+            mode: Mode::CheckOnly,
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let foo = Experiment::get(&db, "foo").unwrap().unwrap();
+        let bar = Experiment::get(&db, "bar").unwrap().unwrap();
+
+        store(&db, &foo, "agent-a", 0, 3600.0);
+        store(&db, &foo, "agent-b", 1, 5400.0);
+        store(&db, &bar, "agent-a", 0, 1800.0);
+        // Results recorded long ago only count towards the costs
+        db.execute(
+            "UPDATE results SET recorded_at = datetime('now', '-30 days') \
+             WHERE experiment = 'foo' AND agent = 'agent-b';",
+            [],
+        )
+        .unwrap();
+
+        let costs = experiment_costs(&db).unwrap();
+        assert_eq!(
+            costs
+                .iter()
+                .map(|c| (c.name.as_str(), c.builds, c.machine_hours))
+                .collect::<Vec<_>>(),
+            [("foo", 2, 2.5), ("bar", 1, 0.5)]
+        );
+
+        let averages = mode_averages(&db).unwrap();
+        assert_eq!(
+            averages
+                .iter()
+                .map(|a| (a.mode.as_str(), a.builds, a.average_seconds))
+                .collect::<Vec<_>>(),
+            [("build-and-test", 2, 4500.0), ("check-only", 1, 1800.0)]
+        );
+
+        let utilization = utilization(&db, 7).unwrap();
+        assert_eq!(utilization.days.len(), 7);
+        assert_eq!(utilization.agents.len(), 1);
+        assert_eq!(
+            utilization.agents["agent-a"],
+            [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Caching proxy for crate tarballs and git repositories.
+//!
+//! Agents pointed to the proxy (with `crater agent --crate-cache` or the `[crate-cache]` section
+//! of `config.toml`) download registry crates from it instead of crates.io, and clone GitHub
+//! repositories from mirrors it keeps up to date, served with git's "dumb" HTTP protocol.
+
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::server::HttpError;
+use crate::utils;
+use bytes::Bytes;
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use hyper::Body;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::path::Tail;
+use warp::Filter;
+
+const CRATES_UPSTREAM: &str = "https://static.crates.io/crates";
+const GIT_UPSTREAM: &str = "https://github.com";
+// Mirrors are refreshed when an agent starts cloning them, at most once per interval
+const GIT_REFRESH_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    static ref CACHE_DIR: PathBuf = WORK_DIR.join("crate-cache");
+    static ref GIT_REFRESHES: Mutex<HashMap<PathBuf, Arc<Mutex<Option<Instant>>>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn is_valid_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && !segment.starts_with('.')
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+}
+
+fn endpoint_crate(name: String, version: String) -> Fallible<Response<Body>> {
+    if !is_valid_segment(&name) || !is_valid_segment(&version) {
+        return Err(HttpError::NotFound.into());
+    }
+
+    let path = CACHE_DIR
+        .join("crates")
+        .join(&name)
+        .join(format!("{name}-{version}.crate"));
+
+    if !path.exists() {
+        info!("downloading crate {} {} from crates.io", name, version);
+        let mut download =
+            utils::http::get_sync(&format!("{CRATES_UPSTREAM}/{name}/{name}-{version}.crate"))
+                .map_err(|_| HttpError::NotFound)?;
+
+        // Every download gets its own temporary file, as agents can request the same crate
+        // concurrently, and only complete downloads are moved to the cache.
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        io::copy(&mut download, &mut tmp)?;
+        tmp.persist(&path)?;
+    }
+
+    file_response(&path, "application/x-tar")
+}
+
+fn git(args: &[&str], cwd: Option<&Path>) -> Fallible<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(args);
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+
+    let status = cmd.status()?;
+    if !status.success() {
+        bail!("command `git {}` failed with {}", args.join(" "), status);
+    }
+    Ok(())
+}
+
+fn refresh_mirror(org: &str, repo: &str, mirror: &Path) -> Fallible<()> {
+    let lock = GIT_REFRESHES
+        .lock()
+        .unwrap()
+        .entry(mirror.to_path_buf())
+        .or_default()
+        .clone();
+    // Hold the lock of this repository for the whole refresh, so that concurrent clones wait
+    // for it instead of fetching the same repository multiple times.
+    let mut last_refresh = lock.lock().unwrap();
+    if last_refresh.is_some_and(|t| t.elapsed() < GIT_REFRESH_INTERVAL) {
+        return Ok(());
+    }
+
+    if mirror.exists() {
+        info!("updating the mirror of {}/{}", org, repo);
+        git(&["remote", "update", "--prune"], Some(mirror))?;
+    } else {
+        info!("creating a mirror of {}/{}", org, repo);
+        fs::create_dir_all(mirror.parent().unwrap())?;
+        git(
+            &[
+                "clone",
+                "--mirror",
+                &format!("{GIT_UPSTREAM}/{org}/{repo}"),
+                mirror.to_str().unwrap(),
+            ],
+            None,
+        )?;
+    }
+    git(&["update-server-info"], Some(mirror))?;
+
+    *last_refresh = Some(Instant::now());
+    Ok(())
+}
+
+fn endpoint_git(org: String, repo: String, tail: String) -> Fallible<Response<Body>> {
+    if !is_valid_segment(&org) || !is_valid_segment(&repo) {
+        return Err(HttpError::NotFound.into());
+    }
+
+    // Only serve files inside the mirror
+    let file = Path::new(&tail);
+    if !file.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(HttpError::NotFound.into());
+    }
+
+    let mirror = CACHE_DIR
+        .join("git")
+        .join("github.com")
+        .join(&org)
+        .join(&repo);
+
+    // `info/refs` is the first file requested by git when cloning or fetching
+    if tail == "info/refs" {
+        refresh_mirror(&org, &repo, &mirror).map_err(|e| {
+            utils::report_failure(&e);
+            HttpError::NotFound
+        })?;
+    }
+
+    file_response(&mirror.join(file), "application/octet-stream")
+}
+
+fn file_response(path: &Path, mime: &'static str) -> Fallible<Response<Body>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(HttpError::NotFound.into()),
+        Err(e) => return Err(e.into()),
+    };
+    if !file.metadata()?.is_file() {
+        return Err(HttpError::NotFound.into());
+    }
+
+    // Stream the file instead of loading it in memory, as packs of big repositories can be
+    // hundreds of megabytes large.
+    let (mut sender, body) = Body::channel();
+    tokio::task::spawn_blocking(move || {
+        let rt = tokio::runtime::Handle::current();
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+        loop {
+            let len = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("failed to read a file of the crate cache: {}", e);
+                    sender.abort();
+                    break;
+                }
+            };
+            let chunk = Bytes::copy_from_slice(&buffer[..len]);
+            if rt.block_on(sender.send_data(chunk)).is_err() {
+                // The client went away
+                break;
+            }
+        }
+    });
+
+    let mut resp = Response::new(body);
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+    Ok(resp)
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => {
+            let status = if let Some(HttpError::NotFound) = err.downcast_ref() {
+                StatusCode::NOT_FOUND
+            } else {
+                utils::report_failure(&err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+
+            let mut resp = Response::new(format!("{status}\n").into());
+            *resp.status_mut() = status;
+            resp
+        }
+    }
+}
+
+/// Runs an endpoint on the blocking thread pool, as they download crates, read files and spawn
+/// git, which would otherwise stall the runtime serving the other requests.
+async fn blocking<F>(endpoint: F) -> Result<Response<Body>, Infallible>
+where
+    F: FnOnce() -> Fallible<Response<Body>> + Send + 'static,
+{
+    let resp = tokio::task::spawn_blocking(endpoint)
+        .await
+        .unwrap_or_else(|e| Err(anyhow!("the endpoint panicked: {}", e)));
+    Ok(handle_results(resp))
+}
+
+pub fn run(bind: SocketAddr) -> Fallible<()> {
+    let crates = warp::get()
+        .and(warp::path("crates"))
+        .and(warp::path::param())
+        .and(warp::path::param())
+        .and(warp::path("download"))
+        .and(warp::path::end())
+        .and_then(|name: String, version: String| blocking(move || endpoint_crate(name, version)));
+
+    let git = warp::get()
+        .and(warp::path("git"))
+        .and(warp::path("github.com"))
+        .and(warp::path::param())
+        .and(warp::path::param())
+        .and(warp::path::tail())
+        .and_then(|org: String, repo: String, tail: Tail| {
+            let tail = tail.as_str().to_string();
+            blocking(move || endpoint_git(org, repo, tail))
+        });
+
+    let routes = crates.or(git).unify();
+
+    info!("running the crate cache on {}...", bind);
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async move {
+        warp::serve(routes).run(bind).await;
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_segment;
+
+    #[test]
+    fn test_is_valid_segment() {
+        assert!(is_valid_segment("lazy_static"));
+        assert!(is_valid_segment("1.4.0+build"));
+        assert!(is_valid_segment("rust-lang"));
+
+        assert!(!is_valid_segment(""));
+        assert!(!is_valid_segment(".."));
+        assert!(!is_valid_segment(".git"));
+        assert!(!is_valid_segment("foo/bar"));
+    }
+}
@@ -1,23 +1,73 @@
 use crate::actions::{Action, ActionsCtx, UpdateLists};
+use crate::config::Config;
+use crate::experiments::{Experiment, Status};
 use crate::prelude::*;
-use crate::server::Data;
+use crate::results::DatabaseDB;
+use crate::server::{idempotency, shadow_runs, Data};
 use crate::utils;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Set while the crate lists are being updated, either by the cron job or from the UI.
+static UPDATING_LISTS: AtomicBool = AtomicBool::new(false);
+
 struct JobDescription {
     name: &'static str,
-    interval: Duration,
+    interval: fn(&Config) -> Duration,
     exec: fn(Arc<Data>) -> Fallible<()>,
 }
 
-static JOBS: &[JobDescription] = &[JobDescription {
-    name: "crates lists update",
-    interval: DAY,
-    exec: update_crates as fn(Arc<Data>) -> Fallible<()>,
-}];
+fn daily(_config: &Config) -> Duration {
+    DAY
+}
+
+fn every_minute(_config: &Config) -> Duration {
+    MINUTE
+}
+
+/// The agents are checked a few times within the failover window, so that the experiments of
+/// the unresponsive ones are handed over soon after it elapses.
+fn failover_interval(config: &Config) -> Duration {
+    (Duration::from_secs(u64::from(config.server.agent_failover_after) * 60) / 4).max(MINUTE)
+}
+
+static JOBS: &[JobDescription] = &[
+    JobDescription {
+        name: "crates lists update",
+        interval: daily,
+        exec: update_crates as fn(Arc<Data>) -> Fallible<()>,
+    },
+    JobDescription {
+        name: "agents failover",
+        interval: failover_interval,
+        exec: agents_failover as fn(Arc<Data>) -> Fallible<()>,
+    },
+    JobDescription {
+        name: "experiment deadlines",
+        interval: every_minute,
+        exec: enforce_deadlines as fn(Arc<Data>) -> Fallible<()>,
+    },
+    JobDescription {
+        name: "shadow runs sampling",
+        interval: every_minute,
+        exec: sample_shadow_runs as fn(Arc<Data>) -> Fallible<()>,
+    },
+    JobDescription {
+        name: "idempotency keys cleanup",
+        interval: daily,
+        exec: purge_idempotency_keys as fn(Arc<Data>) -> Fallible<()>,
+    },
+    JobDescription {
+        name: "abandoned uploads cleanup",
+        interval: daily,
+        exec: purge_uploads as fn(Arc<Data>) -> Fallible<()>,
+    },
+];
 
 pub fn spawn(data: Data) {
     let data = Arc::new(data);
@@ -31,23 +81,98 @@ pub fn spawn(data: Data) {
                 utils::report_failure(&e);
             }
 
+            let interval = (job.interval)(&data.config);
             info!(
                 "the {} thread will be respawned in {}s",
                 job.name,
-                job.interval.as_secs()
+                interval.as_secs()
             );
-            thread::sleep(job.interval);
+            thread::sleep(interval);
         });
     }
 }
 
 fn update_crates(data: Arc<Data>) -> Fallible<()> {
+    if UPDATING_LISTS.swap(true, Ordering::SeqCst) {
+        info!("the crate lists are already being updated");
+        return Ok(());
+    }
+    update_claimed_lists(&data)
+}
+
+/// Update the crate lists once `UPDATING_LISTS` was claimed, releasing it afterwards.
+fn update_claimed_lists(data: &Data) -> Fallible<()> {
     let ctx = ActionsCtx::new(&data.db, &data.config);
 
-    UpdateLists {
+    let result = UpdateLists {
         github: true,
         registry: true,
         local: false,
+        force: false,
+    }
+    .apply(&ctx);
+    UPDATING_LISTS.store(false, Ordering::SeqCst);
+    result
+}
+
+pub fn is_updating_lists() -> bool {
+    UPDATING_LISTS.load(Ordering::SeqCst)
+}
+
+/// Update the crate lists in the background, unless an update is already in progress, calling
+/// `before` once no other update can start anymore. Returns whether the update was started,
+/// which it isn't when `before` fails.
+pub fn spawn_lists_update(
+    data: Arc<Data>,
+    before: impl FnOnce() -> Fallible<()>,
+) -> Fallible<bool> {
+    if UPDATING_LISTS.swap(true, Ordering::SeqCst) {
+        return Ok(false);
     }
-    .apply(&ctx)
+    if let Err(e) = before() {
+        UPDATING_LISTS.store(false, Ordering::SeqCst);
+        return Err(e);
+    }
+
+    thread::spawn(move || {
+        if let Err(e) = update_claimed_lists(&data) {
+            utils::report_failure(&e);
+        }
+    });
+    Ok(true)
+}
+
+fn agents_failover(data: Arc<Data>) -> Fallible<()> {
+    data.agents.failover(&data.config)
+}
+
+fn enforce_deadlines(data: Arc<Data>) -> Fallible<()> {
+    let results = DatabaseDB::new(&data.db).with_log_store(data.log_store.as_deref());
+    for ex in Experiment::unfinished(&data.db)? {
+        if ex.status != Status::Running || !ex.deadline_passed() {
+            continue;
+        }
+
+        let skipped = results.skip_crates_past_deadline(&ex)?;
+        if skipped > 0 {
+            info!(
+                "the deadline of experiment {} passed, skipped {} crates",
+                ex.name, skipped
+            );
+            data.reports_worker.wake();
+        }
+    }
+    Ok(())
+}
+
+fn sample_shadow_runs(data: Arc<Data>) -> Fallible<()> {
+    shadow_runs::sample(&data.db, &data.config)
+}
+
+fn purge_idempotency_keys(data: Arc<Data>) -> Fallible<()> {
+    idempotency::purge_expired(&data.db)
+}
+
+fn purge_uploads(data: Arc<Data>) -> Fallible<()> {
+    data.uploads.purge_stale()
 }
@@ -0,0 +1,72 @@
+use crate::prelude::*;
+use crate::server::tokens::SmtpTokens;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::thread;
+
+/// An email waiting to be sent.
+pub struct OutgoingEmail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(tokens: &SmtpTokens) -> Fallible<Self> {
+        let mut transport = SmtpTransport::relay(&tokens.server)?.credentials(Credentials::new(
+            tokens.username.clone(),
+            tokens.password.clone(),
+        ));
+        if let Some(port) = tokens.port {
+            transport = transport.port(port);
+        }
+
+        Ok(Mailer {
+            transport: transport.build(),
+            from: tokens
+                .from
+                .parse()
+                .with_context(|| format!("invalid sender address: {}", tokens.from))?,
+        })
+    }
+
+    pub fn send(&self, to: &str, subject: &str, body: &str) -> Fallible<()> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(parse_address(to)?)
+            .subject(subject)
+            .body(body.to_string())?;
+        self.transport.send(&email)?;
+        Ok(())
+    }
+
+    /// Sends the emails from a separate thread, so that a slow SMTP server doesn't block the
+    /// caller. Failures are logged.
+    pub fn send_in_background(&self, emails: Vec<OutgoingEmail>) {
+        if emails.is_empty() {
+            return;
+        }
+        let mailer = self.clone();
+        thread::spawn(move || {
+            for email in emails {
+                if let Err(err) = mailer.send(&email.to, &email.subject, &email.body) {
+                    error!("failed to send an email to {}", email.to);
+                    crate::utils::report_failure(&err);
+                }
+            }
+        });
+    }
+}
+
+pub fn parse_address(address: &str) -> Fallible<Mailbox> {
+    address
+        .parse()
+        .with_context(|| format!("invalid email address: {address}"))
+}
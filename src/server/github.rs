@@ -1,39 +1,221 @@
 use crate::prelude::*;
 use crate::server::tokens::BotTokens;
+use crate::server::vcs::{
+    header, CommentEvent, Commit, ComparedCommit, Issue, Label, PullRequest, Repository, User,
+    VcsHost, WebhookEvent,
+};
 use crate::utils;
-use reqwest::blocking::RequestBuilder;
-use reqwest::header::AUTHORIZATION;
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{HeaderMap, AUTHORIZATION, RETRY_AFTER};
 use reqwest::{Method, StatusCode};
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of times a rate limited request is retried before giving up.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+/// Delay used the first time GitHub reports a secondary rate limit without a `Retry-After`
+/// header. It doubles for every consecutive secondary rate limit, as GitHub recommends.
+const SECONDARY_RATE_LIMIT_DELAY: Duration = Duration::from_secs(60);
+/// GitHub recommends waiting at least one second between requests creating content.
+const MUTATION_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of requests kept in reserve before the primary rate limit is exhausted, so that
+/// webhook handlers can still reply while the reports worker is waiting for a reset.
+const REQUEST_BUDGET_RESERVE: u64 = 50;
+/// Upper bound on how long a single request waits for the rate limit to reset.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(15 * 60);
+/// Largest page of commits returned by the compare API.
+const COMPARE_PAGE_SIZE: usize = 100;
+/// Comparisons with more commits than this are truncated, to bound the number of requests.
+const MAX_COMPARED_COMMITS: usize = 10_000;
 
 #[derive(Debug, thiserror::Error)]
 pub enum GitHubError {
     #[error("request to GitHub API failed with status {0}: {1}")]
     RequestFailed(StatusCode, String),
+    #[error("request to GitHub API is still rate limited after {0} retries")]
+    RateLimited(u32),
 }
 
-pub trait GitHub {
-    fn username(&self) -> Fallible<String>;
-    fn post_comment(&self, issue_url: &str, body: &str) -> Fallible<()>;
-    fn list_labels(&self, issue_url: &str) -> Fallible<Vec<Label>>;
-    fn add_label(&self, issue_url: &str, label: &str) -> Fallible<()>;
-    fn remove_label(&self, issue_url: &str, label: &str) -> Fallible<()>;
-    fn list_teams(&self, org: &str) -> Fallible<HashMap<String, usize>>;
-    fn team_members(&self, team: usize) -> Fallible<Vec<String>>;
-    fn get_commit(&self, repo: &str, sha: &str) -> Fallible<Commit>;
-    fn get_pr_head_sha(&self, repo: &str, pr: i32) -> Fallible<String>;
+#[derive(Default)]
+struct RateLimitState {
+    /// Requests left in the current primary rate limit window, as reported by GitHub.
+    remaining: Option<u64>,
+    /// When the current primary rate limit window resets.
+    reset_at: Option<SystemTime>,
+    /// Set after hitting a secondary rate limit: no request is sent before this instant.
+    blocked_until: Option<Instant>,
+    /// Consecutive secondary rate limits reported without a `Retry-After` header.
+    secondary_limits: u32,
+    last_mutation: Option<Instant>,
+}
+
+/// Rate limiter shared by every clone of a `GitHubApi`, so that the reports worker and the
+/// webhook handlers draw from the same request budget.
+#[derive(Default)]
+struct RateLimiter {
+    state: Mutex<RateLimitState>,
+}
+
+impl RateLimiter {
+    /// Returns how long the caller has to wait before sending a request, reserving the slot.
+    fn acquire(&self, method: &Method, low_priority: bool) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut wait = Duration::ZERO;
+
+        if let Some(blocked_until) = state.blocked_until {
+            wait = wait.max(blocked_until.saturating_duration_since(now));
+        }
+
+        let reserve = if low_priority {
+            REQUEST_BUDGET_RESERVE
+        } else {
+            0
+        };
+        if let (Some(remaining), Some(reset_at)) = (state.remaining, state.reset_at) {
+            if remaining <= reserve {
+                let until_reset = reset_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO);
+                wait = wait.max(until_reset.min(MAX_RATE_LIMIT_WAIT));
+            } else {
+                state.remaining = Some(remaining - 1);
+            }
+        }
+
+        if is_mutation(method) {
+            if let Some(last) = state.last_mutation {
+                let next = last + MUTATION_INTERVAL;
+                wait = wait.max(next.saturating_duration_since(now));
+            }
+            state.last_mutation = Some(now + wait);
+        }
+
+        wait
+    }
+
+    fn update(&self, headers: &HeaderMap) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(remaining) = header_u64(headers, "x-ratelimit-remaining") {
+            state.remaining = Some(remaining);
+        }
+        if let Some(reset) = header_u64(headers, "x-ratelimit-reset") {
+            state.reset_at = Some(UNIX_EPOCH + Duration::from_secs(reset));
+        }
+    }
+
+    fn block_for(&self, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + delay;
+        state.blocked_until = Some(state.blocked_until.map_or(until, |b| b.max(until)));
+    }
+
+    /// Blocks all requests after a secondary rate limit GitHub didn't say how long lasts,
+    /// doubling the delay every time it's hit again before a request goes through.
+    fn back_off(&self) -> Duration {
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            let delay = secondary_backoff(state.secondary_limits);
+            state.secondary_limits = state.secondary_limits.saturating_add(1);
+            delay
+        };
+        self.block_for(delay);
+        delay
+    }
+
+    fn reset_backoff(&self) {
+        self.state.lock().unwrap().secondary_limits = 0;
+    }
+}
+
+fn secondary_backoff(previous: u32) -> Duration {
+    SECONDARY_RATE_LIMIT_DELAY
+        .saturating_mul(2u32.saturating_pow(previous))
+        .min(MAX_RATE_LIMIT_WAIT)
+}
+
+fn is_mutation(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PATCH | Method::PUT | Method::DELETE
+    )
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// How a response was affected by the rate limits of GitHub.
+#[derive(Debug, PartialEq)]
+enum RateLimit {
+    /// GitHub said how long to wait before retrying.
+    Wait(Duration),
+    /// Rate limited without saying for how long: back off exponentially.
+    Backoff,
+    /// A 403 without any rate limit header, which is either a secondary rate limit or a
+    /// permission error depending on its message.
+    MaybeSecondary,
+}
+
+/// Returns how the response was rate limited, or `None` if it wasn't.
+fn rate_limit(status: StatusCode, headers: &HeaderMap, now: SystemTime) -> Option<RateLimit> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    if let Some(retry_after) = header_u64(headers, RETRY_AFTER.as_str()) {
+        return Some(RateLimit::Wait(
+            Duration::from_secs(retry_after).min(MAX_RATE_LIMIT_WAIT),
+        ));
+    }
+
+    if header_u64(headers, "x-ratelimit-remaining") == Some(0) {
+        if let Some(reset) = header_u64(headers, "x-ratelimit-reset") {
+            let wait = (UNIX_EPOCH + Duration::from_secs(reset))
+                .duration_since(now)
+                .unwrap_or(Duration::ZERO);
+            return Some(RateLimit::Wait(wait.min(MAX_RATE_LIMIT_WAIT)));
+        }
+    }
+
+    // A plain 429 is always a rate limit, while a 403 can also be a permission error.
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        Some(RateLimit::Backoff)
+    } else {
+        Some(RateLimit::MaybeSecondary)
+    }
+}
+
+fn is_secondary_rate_limit(message: &str) -> bool {
+    message.to_lowercase().contains("secondary rate limit")
 }
 
 #[derive(Clone)]
 pub struct GitHubApi {
     token: String,
+    limiter: Arc<RateLimiter>,
+    low_priority: bool,
 }
 
 impl GitHubApi {
     pub fn new(tokens: &BotTokens) -> Self {
         GitHubApi {
             token: tokens.api_token.clone(),
+            limiter: Arc::new(RateLimiter::default()),
+            low_priority: false,
+        }
+    }
+
+    /// A client sharing the rate limiter of this one, whose requests leave part of the budget
+    /// to the replies to the bot commands. Used by the background workers.
+    pub fn background(&self) -> Self {
+        GitHubApi {
+            low_priority: true,
+            ..self.clone()
         }
     }
 
@@ -47,21 +229,111 @@ impl GitHubApi {
         utils::http::prepare_sync(method, &url)
             .header(AUTHORIZATION, format!("token {}", self.token))
     }
+
+    /// Sends a request through the shared rate limiter, retrying it when GitHub rejects it
+    /// because of a primary or secondary rate limit.
+    fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Fallible<Response> {
+        for _ in 0..=MAX_RATE_LIMIT_RETRIES {
+            let wait = self.limiter.acquire(&method, self.low_priority);
+            if !wait.is_zero() {
+                debug!("waiting {:?} before sending a request to GitHub", wait);
+                thread::sleep(wait);
+            }
+
+            let mut request = self.build_request(method.clone(), url);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send()?;
+            self.limiter.update(response.headers());
+
+            let status = response.status();
+            let delay = match rate_limit(status, response.headers(), SystemTime::now()) {
+                Some(RateLimit::Wait(delay)) => {
+                    self.limiter.block_for(delay);
+                    delay
+                }
+                Some(RateLimit::Backoff) => self.limiter.back_off(),
+                Some(RateLimit::MaybeSecondary) => {
+                    // Telling the two apart needs the body, so permission errors are reported
+                    // here rather than by the caller.
+                    let error: Error = response.json()?;
+                    if !is_secondary_rate_limit(&error.message) {
+                        return Err(GitHubError::RequestFailed(status, error.message).into());
+                    }
+                    self.limiter.back_off()
+                }
+                None => {
+                    self.limiter.reset_backoff();
+                    return Ok(response);
+                }
+            };
+            warn!(
+                "hit GitHub rate limit on {} {}, retrying in {:?}",
+                method, url, delay
+            );
+        }
+
+        Err(GitHubError::RateLimited(MAX_RATE_LIMIT_RETRIES).into())
+    }
+}
+
+fn verify_signature(secret: &str, payload: &[u8], raw_signature: &str) -> bool {
+    type HmacSha1 = Hmac<sha1::Sha1>;
+
+    // The signature must have a =
+    if !raw_signature.contains('=') {
+        return false;
+    }
+
+    // Split the raw signature to get the algorithm and the signature
+    let splitted: Vec<&str> = raw_signature.split('=').collect();
+    let algorithm = &splitted[0];
+    let hex_signature = splitted
+        .iter()
+        .skip(1)
+        .cloned()
+        .collect::<Vec<&str>>()
+        .join("=");
+
+    // Convert the signature from hex
+    let signature = if let Ok(converted) = crate::utils::hex::from_hex(&hex_signature) {
+        converted
+    } else {
+        // This is not hex
+        return false;
+    };
+
+    // Only SHA-1 is supported
+    if *algorithm != "sha1" {
+        return false;
+    }
+
+    // Verify the HMAC signature
+    let mut mac = HmacSha1::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload);
+    mac.verify_slice(&signature).is_ok()
 }
 
-impl GitHub for GitHubApi {
+impl VcsHost for GitHubApi {
     fn username(&self) -> Fallible<String> {
-        let response: User = self.build_request(Method::GET, "user").send()?.json()?;
+        let response: User = self.send(Method::GET, "user", None)?.json()?;
         Ok(response.login)
     }
 
     fn post_comment(&self, issue_url: &str, body: &str) -> Fallible<()> {
-        let response = self
-            .build_request(Method::POST, &format!("{issue_url}/comments"))
-            .json(&json!({
+        let response = self.send(
+            Method::POST,
+            &format!("{issue_url}/comments"),
+            Some(&json!({
                 "body": body,
-            }))
-            .send()?;
+            })),
+        )?;
 
         let status = response.status();
         if status == StatusCode::CREATED {
@@ -73,9 +345,7 @@ impl GitHub for GitHubApi {
     }
 
     fn list_labels(&self, issue_url: &str) -> Fallible<Vec<Label>> {
-        let response = self
-            .build_request(Method::GET, &format!("{issue_url}/labels"))
-            .send()?;
+        let response = self.send(Method::GET, &format!("{issue_url}/labels"), None)?;
 
         let status = response.status();
         if status == StatusCode::OK {
@@ -87,10 +357,11 @@ impl GitHub for GitHubApi {
     }
 
     fn add_label(&self, issue_url: &str, label: &str) -> Fallible<()> {
-        let response = self
-            .build_request(Method::POST, &format!("{issue_url}/labels"))
-            .json(&json!([label]))
-            .send()?;
+        let response = self.send(
+            Method::POST,
+            &format!("{issue_url}/labels"),
+            Some(&json!([label])),
+        )?;
 
         let status = response.status();
         if status == StatusCode::OK {
@@ -102,9 +373,7 @@ impl GitHub for GitHubApi {
     }
 
     fn remove_label(&self, issue_url: &str, label: &str) -> Fallible<()> {
-        let response = self
-            .build_request(Method::DELETE, &format!("{issue_url}/labels/{label}"))
-            .send()?;
+        let response = self.send(Method::DELETE, &format!("{issue_url}/labels/{label}"), None)?;
 
         let status = response.status();
         if status == StatusCode::OK {
@@ -116,9 +385,7 @@ impl GitHub for GitHubApi {
     }
 
     fn list_teams(&self, org: &str) -> Fallible<HashMap<String, usize>> {
-        let response = self
-            .build_request(Method::GET, &format!("orgs/{org}/teams"))
-            .send()?;
+        let response = self.send(Method::GET, &format!("orgs/{org}/teams"), None)?;
 
         let status = response.status();
         if status == StatusCode::OK {
@@ -131,9 +398,7 @@ impl GitHub for GitHubApi {
     }
 
     fn team_members(&self, team: usize) -> Fallible<Vec<String>> {
-        let response = self
-            .build_request(Method::GET, &format!("teams/{team}/members"))
-            .send()?;
+        let response = self.send(Method::GET, &format!("teams/{team}/members"), None)?;
 
         let status = response.status();
         if status == StatusCode::OK {
@@ -147,8 +412,7 @@ impl GitHub for GitHubApi {
 
     fn get_commit(&self, repo: &str, sha: &str) -> Fallible<Commit> {
         let commit = self
-            .build_request(Method::GET, &format!("repos/{repo}/commits/{sha}"))
-            .send()?
+            .send(Method::GET, &format!("repos/{repo}/commits/{sha}"), None)?
             .error_for_status()?
             .json()?;
         Ok(commit)
@@ -156,12 +420,155 @@ impl GitHub for GitHubApi {
 
     fn get_pr_head_sha(&self, repo: &str, pr: i32) -> Fallible<String> {
         let pr: PullRequestData = self
-            .build_request(Method::GET, &format!("repos/{repo}/pulls/{pr}"))
-            .send()?
+            .send(Method::GET, &format!("repos/{repo}/pulls/{pr}"), None)?
             .error_for_status()?
             .json()?;
         Ok(pr.head.sha)
     }
+
+    fn compare_commits(&self, repo: &str, base: &str, head: &str) -> Fallible<Vec<ComparedCommit>> {
+        let mut commits = Vec::new();
+        for page in 1.. {
+            let comparison: Comparison = self
+                .send(
+                    Method::GET,
+                    &format!(
+                        "repos/{repo}/compare/{base}...{head}?per_page={COMPARE_PAGE_SIZE}\
+                         &page={page}"
+                    ),
+                    None,
+                )?
+                .error_for_status()?
+                .json()?;
+            let done = comparison.commits.len() < COMPARE_PAGE_SIZE;
+            commits.extend(comparison.commits.into_iter().map(|c| ComparedCommit {
+                sha: c.sha,
+                message: c.commit.message,
+            }));
+            if done || commits.len() >= comparison.total_commits {
+                break;
+            }
+            if commits.len() >= MAX_COMPARED_COMMITS {
+                warn!(
+                    "only fetched {} of the {} commits between {} and {}",
+                    commits.len(),
+                    comparison.total_commits,
+                    base,
+                    head
+                );
+                break;
+            }
+        }
+        Ok(commits)
+    }
+
+    fn create_issue(
+        &self,
+        repo: &str,
+        title: &str,
+        body: &str,
+        labels: &[String],
+    ) -> Fallible<String> {
+        let response = self.send(
+            Method::POST,
+            &format!("repos/{repo}/issues"),
+            Some(&json!({
+                "title": title,
+                "body": body,
+                "labels": labels,
+            })),
+        )?;
+
+        let status = response.status();
+        if status == StatusCode::CREATED {
+            let issue: Issue = response.json()?;
+            Ok(issue.html_url)
+        } else {
+            let error: Error = response.json()?;
+            Err(GitHubError::RequestFailed(status, error.message).into())
+        }
+    }
+
+    fn parse_webhook(
+        &self,
+        secret: &str,
+        headers: &http::HeaderMap,
+        payload: &[u8],
+    ) -> Fallible<WebhookEvent> {
+        let signature = header(headers, "X-Hub-Signature")?;
+        let event = header(headers, "X-GitHub-Event")?;
+        if !verify_signature(secret, payload, signature) {
+            bail!("invalid signature for the webhook!");
+        }
+
+        parse_event(event, payload)
+    }
+}
+
+/// Parse the payload of a webhook. Commands are accepted in new comments on issues and pull
+/// requests, in new review comments and reviews, and in the body of newly opened issues and pull
+/// requests. Edits and deletions are ignored, to avoid running the same command twice.
+fn parse_event(event: &str, payload: &[u8]) -> Fallible<WebhookEvent> {
+    let (action, expected, comment) = match event {
+        "ping" => return Ok(WebhookEvent::Ping),
+        "issue_comment" => {
+            let p: EventIssueComment = serde_json::from_slice(payload)?;
+            let comment = CommentEvent {
+                sender: p.sender,
+                body: p.comment.body,
+                repository: p.repository,
+                issue: p.issue,
+            };
+            (p.action, "created", Some(comment))
+        }
+        "issues" => {
+            let p: EventIssue = serde_json::from_slice(payload)?;
+            let comment = p.issue.body.map(|body| CommentEvent {
+                sender: p.sender,
+                body,
+                repository: p.repository,
+                issue: p.issue.issue,
+            });
+            (p.action, "opened", comment)
+        }
+        "pull_request" => {
+            let p: EventPullRequest = serde_json::from_slice(payload)?;
+            let (issue, body) = p.pull_request.into_issue();
+            let comment = body.map(|body| CommentEvent {
+                sender: p.sender,
+                body,
+                repository: p.repository,
+                issue,
+            });
+            (p.action, "opened", comment)
+        }
+        "pull_request_review_comment" => {
+            let p: EventPullRequestReviewComment = serde_json::from_slice(payload)?;
+            let comment = CommentEvent {
+                sender: p.sender,
+                body: p.comment.body,
+                repository: p.repository,
+                issue: p.pull_request.into_issue().0,
+            };
+            (p.action, "created", Some(comment))
+        }
+        "pull_request_review" => {
+            let p: EventPullRequestReview = serde_json::from_slice(payload)?;
+            let comment = p.review.body.map(|body| CommentEvent {
+                sender: p.sender,
+                body,
+                repository: p.repository,
+                issue: p.pull_request.into_issue().0,
+            });
+            (p.action, "submitted", comment)
+        }
+        e => bail!("invalid event received: {}", e),
+    };
+
+    match comment {
+        Some(comment) if action == expected => Ok(WebhookEvent::Comment(comment)),
+        _ => Ok(WebhookEvent::Ignored),
+    }
 }
 
 #[derive(Deserialize)]
@@ -170,36 +577,86 @@ pub struct Error {
 }
 
 #[derive(Deserialize)]
-pub struct User {
-    pub id: u64,
-    pub login: String,
+pub struct EventIssueComment {
+    pub action: String,
+    pub issue: Issue,
+    pub comment: Comment,
+    pub sender: User,
+    pub repository: Repository,
 }
 
 #[derive(Deserialize)]
-pub struct EventIssueComment {
+pub struct EventIssue {
     pub action: String,
+    pub issue: IssueWithBody,
+    pub sender: User,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize)]
+pub struct IssueWithBody {
+    #[serde(flatten)]
     pub issue: Issue,
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EventPullRequest {
+    pub action: String,
+    pub pull_request: EventPullRequestData,
+    pub sender: User,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize)]
+pub struct EventPullRequestReviewComment {
+    pub action: String,
     pub comment: Comment,
+    pub pull_request: EventPullRequestData,
     pub sender: User,
     pub repository: Repository,
 }
 
 #[derive(Deserialize)]
-pub struct Issue {
+pub struct EventPullRequestReview {
+    pub action: String,
+    pub review: Review,
+    pub pull_request: EventPullRequestData,
+    pub sender: User,
+    pub repository: Repository,
+}
+
+/// Pull request included in the pull request events, which use a different representation than
+/// the issue included in the comment events.
+#[derive(Deserialize)]
+pub struct EventPullRequestData {
     pub number: i32,
-    pub url: String,
+    pub issue_url: String,
     pub html_url: String,
-    // used in some targets
-    #[allow(unused)]
+    #[serde(default)]
     pub labels: Vec<Label>,
-    pub pull_request: Option<PullRequest>,
+    pub body: Option<String>,
+}
+
+impl EventPullRequestData {
+    /// Convert the pull request to the issue it's backed by, returning its body too.
+    fn into_issue(self) -> (Issue, Option<String>) {
+        let issue = Issue {
+            number: self.number,
+            url: self.issue_url,
+            html_url: self.html_url.clone(),
+            labels: self.labels,
+            pull_request: Some(PullRequest {
+                html_url: self.html_url,
+            }),
+        };
+        (issue, self.body)
+    }
 }
 
 #[derive(Deserialize)]
-pub struct PullRequest {
-    // used in some targets
-    #[allow(unused)]
-    pub html_url: String,
+pub struct Review {
+    pub body: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -213,18 +670,25 @@ pub struct PullRequestHead {
 }
 
 #[derive(Deserialize)]
-pub struct Repository {
-    pub full_name: String,
+pub struct Comment {
+    pub body: String,
 }
 
 #[derive(Deserialize)]
-pub struct Label {
-    pub name: String,
+pub struct Comparison {
+    pub total_commits: usize,
+    pub commits: Vec<ComparisonCommit>,
 }
 
 #[derive(Deserialize)]
-pub struct Comment {
-    pub body: String,
+pub struct ComparisonCommit {
+    pub sha: String,
+    pub commit: CommitDetails,
+}
+
+#[derive(Deserialize)]
+pub struct CommitDetails {
+    pub message: String,
 }
 
 #[derive(Deserialize)]
@@ -233,15 +697,259 @@ pub struct Team {
     pub slug: String,
 }
 
-#[derive(Deserialize)]
-pub struct Commit {
-    // used in some targets
-    #[allow(unused)]
-    pub sha: String,
-    pub parents: Vec<CommitParent>,
-}
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_secondary_rate_limit, parse_event, rate_limit, secondary_backoff, verify_signature,
+        RateLimit, RateLimiter, MAX_RATE_LIMIT_WAIT, MUTATION_INTERVAL, SECONDARY_RATE_LIMIT_DELAY,
+    };
+    use crate::server::vcs::{CommentEvent, WebhookEvent};
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use reqwest::{Method, StatusCode};
+    use serde_json::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn headers(list: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in list {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
 
-#[derive(Deserialize)]
-pub struct CommitParent {
-    pub sha: String,
+    #[test]
+    fn test_verify_signature() {
+        // Signature generated with `echo -n "payload" | openssl sha1 -hmac secret`
+        let signature = "sha1=f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d";
+        assert!(verify_signature("secret", b"payload", signature));
+        assert!(!verify_signature("other", b"payload", signature));
+        assert!(!verify_signature("secret", b"other", signature));
+        assert!(!verify_signature("secret", b"payload", "sha1"));
+        assert!(!verify_signature("secret", b"payload", "sha1=nothex"));
+        assert!(!verify_signature("secret", b"payload", "md5=00"));
+    }
+
+    fn comment(event: &str, payload: serde_json::Value) -> Option<CommentEvent> {
+        match parse_event(event, payload.to_string().as_bytes()).unwrap() {
+            WebhookEvent::Comment(comment) => Some(comment),
+            WebhookEvent::Ignored => None,
+            WebhookEvent::Ping => panic!("unexpected ping"),
+        }
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let sender = json!({"id": 42, "login": "pietroalbini"});
+        let repository = json!({"full_name": "rust-lang/rust"});
+        let issue = json!({
+            "number": 1,
+            "url": "https://api.github.com/repos/rust-lang/rust/issues/1",
+            "html_url": "https://github.com/rust-lang/rust/issues/1",
+            "labels": [],
+            "body": "@craterbot check",
+        });
+        let pull_request = json!({
+            "number": 2,
+            "url": "https://api.github.com/repos/rust-lang/rust/pulls/2",
+            "issue_url": "https://api.github.com/repos/rust-lang/rust/issues/2",
+            "html_url": "https://github.com/rust-lang/rust/pull/2",
+            "labels": [{"name": "S-waiting-on-crater"}],
+            "body": "@craterbot run",
+        });
+
+        // Comments on issues
+        let c = comment(
+            "issue_comment",
+            json!({
+                "action": "created",
+                "issue": issue,
+                "comment": {"body": "@craterbot ping"},
+                "sender": sender,
+                "repository": repository,
+            }),
+        )
+        .unwrap();
+        assert_eq!(c.body, "@craterbot ping");
+        assert_eq!(c.issue.number, 1);
+        assert_eq!(c.sender.login, "pietroalbini");
+        assert_eq!(c.repository.full_name, "rust-lang/rust");
+
+        // Bodies of new issues, but not of edited ones
+        let opened = |action: &str| {
+            json!({
+                "action": action,
+                "issue": issue,
+                "sender": sender,
+                "repository": repository,
+            })
+        };
+        let c = comment("issues", opened("opened")).unwrap();
+        assert_eq!(c.body, "@craterbot check");
+        assert!(c.issue.pull_request.is_none());
+        assert!(comment("issues", opened("edited")).is_none());
+
+        // Bodies of new pull requests
+        let c = comment(
+            "pull_request",
+            json!({
+                "action": "opened",
+                "number": 2,
+                "pull_request": pull_request,
+                "sender": sender,
+                "repository": repository,
+            }),
+        )
+        .unwrap();
+        assert_eq!(c.body, "@craterbot run");
+        assert_eq!(c.issue.number, 2);
+        assert_eq!(
+            c.issue.url,
+            "https://api.github.com/repos/rust-lang/rust/issues/2"
+        );
+        assert_eq!(
+            c.issue.pull_request.unwrap().html_url,
+            "https://github.com/rust-lang/rust/pull/2"
+        );
+        assert_eq!(c.issue.labels[0].name, "S-waiting-on-crater");
+
+        // Review comments and reviews
+        let c = comment(
+            "pull_request_review_comment",
+            json!({
+                "action": "created",
+                "comment": {"body": "@craterbot abort"},
+                "pull_request": pull_request,
+                "sender": sender,
+                "repository": repository,
+            }),
+        )
+        .unwrap();
+        assert_eq!(c.body, "@craterbot abort");
+        assert_eq!(c.issue.number, 2);
+        let review = |body: serde_json::Value| {
+            json!({
+                "action": "submitted",
+                "review": {"body": body},
+                "pull_request": pull_request,
+                "sender": sender,
+                "repository": repository,
+            })
+        };
+        let c = comment("pull_request_review", review(json!("@craterbot p=1"))).unwrap();
+        assert_eq!(c.body, "@craterbot p=1");
+        // Reviews without a body have nothing to parse
+        assert!(comment("pull_request_review", review(json!(null))).is_none());
+
+        assert!(parse_event("push", b"{}").is_err());
+        assert!(matches!(
+            parse_event("ping", b"{}").unwrap(),
+            WebhookEvent::Ping
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        // Successful responses and unrelated errors are never retried
+        assert_eq!(rate_limit(StatusCode::OK, &headers(&[]), now), None);
+        assert_eq!(rate_limit(StatusCode::NOT_FOUND, &headers(&[]), now), None);
+
+        // Secondary rate limits can include a Retry-After header
+        assert_eq!(
+            rate_limit(
+                StatusCode::FORBIDDEN,
+                &headers(&[("retry-after", "30")]),
+                now
+            ),
+            Some(RateLimit::Wait(Duration::from_secs(30)))
+        );
+
+        // Primary rate limits wait until the reset
+        assert_eq!(
+            rate_limit(
+                StatusCode::FORBIDDEN,
+                &headers(&[
+                    ("x-ratelimit-remaining", "0"),
+                    ("x-ratelimit-reset", "1042"),
+                ]),
+                now
+            ),
+            Some(RateLimit::Wait(Duration::from_secs(42)))
+        );
+
+        // Without any hint the message of a 403 tells whether it's a rate limit
+        assert_eq!(
+            rate_limit(
+                StatusCode::FORBIDDEN,
+                &headers(&[
+                    ("x-ratelimit-remaining", "12"),
+                    ("x-ratelimit-reset", "1042"),
+                ]),
+                now
+            ),
+            Some(RateLimit::MaybeSecondary)
+        );
+        assert_eq!(
+            rate_limit(StatusCode::FORBIDDEN, &headers(&[]), now),
+            Some(RateLimit::MaybeSecondary)
+        );
+        assert!(is_secondary_rate_limit(
+            "You have exceeded a secondary rate limit. Please wait a few minutes before you try \
+             again."
+        ));
+        assert!(!is_secondary_rate_limit(
+            "Resource not accessible by integration"
+        ));
+
+        assert_eq!(
+            rate_limit(StatusCode::TOO_MANY_REQUESTS, &headers(&[]), now),
+            Some(RateLimit::Backoff)
+        );
+    }
+
+    #[test]
+    fn test_secondary_backoff() {
+        assert_eq!(secondary_backoff(0), SECONDARY_RATE_LIMIT_DELAY);
+        assert_eq!(secondary_backoff(1), SECONDARY_RATE_LIMIT_DELAY * 2);
+        assert_eq!(secondary_backoff(2), SECONDARY_RATE_LIMIT_DELAY * 4);
+        assert_eq!(secondary_backoff(100), MAX_RATE_LIMIT_WAIT);
+
+        let limiter = RateLimiter::default();
+        assert_eq!(limiter.back_off(), SECONDARY_RATE_LIMIT_DELAY);
+        assert_eq!(limiter.back_off(), SECONDARY_RATE_LIMIT_DELAY * 2);
+        assert!(limiter.acquire(&Method::GET, false) > SECONDARY_RATE_LIMIT_DELAY);
+        limiter.reset_backoff();
+        assert_eq!(limiter.back_off(), SECONDARY_RATE_LIMIT_DELAY);
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_mutations() {
+        let limiter = RateLimiter::default();
+
+        assert_eq!(limiter.acquire(&Method::GET, false), Duration::ZERO);
+        assert_eq!(limiter.acquire(&Method::POST, true), Duration::ZERO);
+        assert_eq!(limiter.acquire(&Method::GET, false), Duration::ZERO);
+
+        let wait = limiter.acquire(&Method::POST, true);
+        assert!(wait > Duration::ZERO && wait <= MUTATION_INTERVAL);
+    }
+
+    #[test]
+    fn test_rate_limiter_keeps_reserve() {
+        let limiter = RateLimiter::default();
+        let reset = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        limiter.update(&headers(&[
+            ("x-ratelimit-remaining", "10"),
+            ("x-ratelimit-reset", &reset.to_string()),
+        ]));
+
+        // High priority requests can use the reserve, low priority ones have to wait
+        assert_eq!(limiter.acquire(&Method::GET, false), Duration::ZERO);
+        assert!(limiter.acquire(&Method::POST, true) > Duration::ZERO);
+    }
 }
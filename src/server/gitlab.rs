@@ -0,0 +1,500 @@
+use crate::prelude::*;
+use crate::server::tokens::BotTokens;
+use crate::server::vcs::{
+    header, CommentEvent, Commit, CommitParent, ComparedCommit, Issue, Label, PullRequest,
+    Repository, User, VcsHost, WebhookEvent,
+};
+use crate::utils;
+use http::HeaderMap;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::blocking::Response;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Largest page of a listing returned by GitLab.
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GitLabError {
+    #[error("request to GitLab API failed with status {0}: {1}")]
+    RequestFailed(reqwest::StatusCode, String),
+}
+
+/// Client of the API of a (possibly self-hosted) GitLab instance, where the bot is driven from
+/// comments on merge requests. GitLab groups are used in place of GitHub teams in the ACL.
+#[derive(Clone)]
+pub struct GitLabApi {
+    base_url: String,
+    token: String,
+}
+
+impl GitLabApi {
+    pub fn new(base_url: &str, tokens: &BotTokens) -> Self {
+        GitLabApi {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: tokens.api_token.clone(),
+        }
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        if path.starts_with("https://") || path.starts_with("http://") {
+            path.to_string()
+        } else {
+            format!("{}/api/v4/{}", self.base_url, path)
+        }
+    }
+
+    fn project_url(&self, repo: &str) -> String {
+        format!("projects/{}", utf8_percent_encode(repo, NON_ALPHANUMERIC))
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Fallible<Response> {
+        let mut request = utils::http::prepare_sync(method, &self.api_url(url))
+            .header("PRIVATE-TOKEN", &self.token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = request.send()?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            Err(GitLabError::RequestFailed(status, response.text()?).into())
+        }
+    }
+
+    /// Fetch every page of a listing.
+    fn get_all<T: DeserializeOwned>(&self, url: &str) -> Fallible<Vec<T>> {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let url = format!("{url}{separator}per_page={PAGE_SIZE}");
+        let mut items = Vec::new();
+        let mut page = Some(url.clone());
+        while let Some(current) = page {
+            let response = self.send(Method::GET, &current, None)?;
+            page = next_page(&url, response.headers());
+            items.extend(response.json::<Vec<T>>()?);
+        }
+        Ok(items)
+    }
+}
+
+/// URL of the page of the listing following the one the headers were returned with. GitLab sets
+/// `X-Next-Page` on every page but the last one, except on large listings where it only provides
+/// the `Link` header.
+fn next_page(url: &str, headers: &HeaderMap) -> Option<String> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(page) = header("X-Next-Page").map(str::trim) {
+        return (!page.is_empty()).then(|| format!("{url}&page={page}"));
+    }
+    header("Link")?
+        .split(',')
+        .find(|link| link.contains("rel=\"next\""))
+        .and_then(|link| link.trim().strip_prefix('<')?.split_once('>'))
+        .map(|(next, _)| next.to_string())
+}
+
+impl VcsHost for GitLabApi {
+    fn username(&self) -> Fallible<String> {
+        let user: GitLabUser = self.send(Method::GET, "user", None)?.json()?;
+        Ok(user.username)
+    }
+
+    fn post_comment(&self, issue_url: &str, body: &str) -> Fallible<()> {
+        self.send(
+            Method::POST,
+            &format!("{issue_url}/notes"),
+            Some(&json!({
+                "body": body,
+            })),
+        )?;
+        Ok(())
+    }
+
+    fn list_labels(&self, issue_url: &str) -> Fallible<Vec<Label>> {
+        let target: NoteTargetData = self.send(Method::GET, issue_url, None)?.json()?;
+        Ok(target
+            .labels
+            .into_iter()
+            .map(|name| Label { name })
+            .collect())
+    }
+
+    fn add_label(&self, issue_url: &str, label: &str) -> Fallible<()> {
+        self.send(
+            Method::PUT,
+            issue_url,
+            Some(&json!({
+                "add_labels": label,
+            })),
+        )?;
+        Ok(())
+    }
+
+    fn remove_label(&self, issue_url: &str, label: &str) -> Fallible<()> {
+        self.send(
+            Method::PUT,
+            issue_url,
+            Some(&json!({
+                "remove_labels": label,
+            })),
+        )?;
+        Ok(())
+    }
+
+    fn list_teams(&self, org: &str) -> Fallible<HashMap<String, usize>> {
+        let groups: Vec<Group> = self.get_all(&format!(
+            "groups/{}/subgroups",
+            utf8_percent_encode(org, NON_ALPHANUMERIC)
+        ))?;
+        Ok(groups.into_iter().map(|g| (g.path, g.id)).collect())
+    }
+
+    fn team_members(&self, team: usize) -> Fallible<Vec<String>> {
+        let members: Vec<GitLabUser> = self.get_all(&format!("groups/{team}/members/all"))?;
+        Ok(members.into_iter().map(|m| m.username).collect())
+    }
+
+    fn get_commit(&self, repo: &str, sha: &str) -> Fallible<Commit> {
+        let commit: GitLabCommit = self
+            .send(
+                Method::GET,
+                &format!("{}/repository/commits/{sha}", self.project_url(repo)),
+                None,
+            )?
+            .json()?;
+        Ok(Commit {
+            sha: commit.id,
+            parents: commit
+                .parent_ids
+                .into_iter()
+                .map(|sha| CommitParent { sha })
+                .collect(),
+        })
+    }
+
+    fn get_pr_head_sha(&self, repo: &str, pr: i32) -> Fallible<String> {
+        let mr: MergeRequestData = self
+            .send(
+                Method::GET,
+                &format!("{}/merge_requests/{pr}", self.project_url(repo)),
+                None,
+            )?
+            .json()?;
+        Ok(mr.sha)
+    }
+
+    fn compare_commits(&self, repo: &str, base: &str, head: &str) -> Fallible<Vec<ComparedCommit>> {
+        let comparison: GitLabComparison = self
+            .send(
+                Method::GET,
+                &format!(
+                    "{}/repository/compare?from={base}&to={head}",
+                    self.project_url(repo)
+                ),
+                None,
+            )?
+            .json()?;
+        Ok(comparison
+            .commits
+            .into_iter()
+            .map(|commit| ComparedCommit {
+                sha: commit.id,
+                message: commit.message,
+            })
+            .collect())
+    }
+
+    fn create_issue(
+        &self,
+        repo: &str,
+        title: &str,
+        body: &str,
+        labels: &[String],
+    ) -> Fallible<String> {
+        let issue: GitLabIssue = self
+            .send(
+                Method::POST,
+                &format!("{}/issues", self.project_url(repo)),
+                Some(&json!({
+                    "title": title,
+                    "description": body,
+                    "labels": labels.join(","),
+                })),
+            )?
+            .json()?;
+        Ok(issue.web_url)
+    }
+
+    fn parse_webhook(
+        &self,
+        secret: &str,
+        headers: &HeaderMap,
+        payload: &[u8],
+    ) -> Fallible<WebhookEvent> {
+        let token = header(headers, "X-Gitlab-Token")?;
+        let event = header(headers, "X-Gitlab-Event")?;
+        if !constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+            bail!("invalid token for the webhook!");
+        }
+
+        match event {
+            "Note Hook" => {
+                let p: EventNote = serde_json::from_slice(payload)?;
+                Ok(self.note_to_event(p))
+            }
+            e => bail!("invalid event received: {}", e),
+        }
+    }
+}
+
+impl GitLabApi {
+    fn note_to_event(&self, p: EventNote) -> WebhookEvent {
+        // Only process new comments, and ignore when a comment is edited
+        if p.object_attributes.action.as_deref().unwrap_or("create") != "create" {
+            return WebhookEvent::Ignored;
+        }
+
+        let project = format!("projects/{}", p.project.id);
+        let (target, kind, is_mr) = match p.object_attributes.noteable_type.as_str() {
+            "MergeRequest" => (p.merge_request, "merge_requests", true),
+            "Issue" => (p.issue, "issues", false),
+            // Comments on commits and snippets can't be used to control the bot
+            _ => return WebhookEvent::Ignored,
+        };
+        let Some(target) = target else {
+            return WebhookEvent::Ignored;
+        };
+
+        WebhookEvent::Comment(CommentEvent {
+            sender: User {
+                id: p.user.id,
+                login: p.user.username,
+            },
+            body: p.object_attributes.note,
+            repository: Repository {
+                full_name: p.project.path_with_namespace,
+            },
+            issue: Issue {
+                number: target.iid,
+                url: self.api_url(&format!("{project}/{kind}/{}", target.iid)),
+                pull_request: if is_mr {
+                    Some(PullRequest {
+                        html_url: target.url.clone(),
+                    })
+                } else {
+                    None
+                },
+                html_url: target.url,
+                labels: Vec::new(),
+            },
+        })
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[derive(Deserialize)]
+struct GitLabUser {
+    id: u64,
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct Group {
+    id: usize,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct GitLabCommit {
+    id: String,
+    parent_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct GitLabComparison {
+    commits: Vec<GitLabComparedCommit>,
+}
+
+#[derive(Deserialize)]
+struct GitLabComparedCommit {
+    id: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestData {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct GitLabIssue {
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct NoteTargetData {
+    labels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct EventNote {
+    user: GitLabUser,
+    project: Project,
+    object_attributes: NoteAttributes,
+    merge_request: Option<NoteTarget>,
+    issue: Option<NoteTarget>,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    id: u64,
+    path_with_namespace: String,
+}
+
+#[derive(Deserialize)]
+struct NoteAttributes {
+    note: String,
+    noteable_type: String,
+    action: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NoteTarget {
+    iid: i32,
+    url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, next_page, GitLabApi};
+    use crate::server::tokens::BotTokens;
+    use crate::server::vcs::{VcsHost, WebhookEvent};
+    use http::{HeaderMap, HeaderValue};
+
+    fn api() -> GitLabApi {
+        GitLabApi::new(
+            "https://gitlab.example.com/",
+            &BotTokens {
+                webhooks_secret: "secret".into(),
+                api_token: "token".into(),
+                gitlab_url: None,
+            },
+        )
+    }
+
+    fn headers(token: &'static str, event: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Gitlab-Token", HeaderValue::from_static(token));
+        headers.insert("X-Gitlab-Event", HeaderValue::from_static(event));
+        headers
+    }
+
+    const MR_NOTE: &str = r#"{
+        "object_kind": "note",
+        "user": {"id": 42, "username": "pietroalbini"},
+        "project": {"id": 7, "path_with_namespace": "compilers/rust"},
+        "object_attributes": {
+            "note": "@craterbot run",
+            "noteable_type": "MergeRequest",
+            "action": "create"
+        },
+        "merge_request": {
+            "iid": 12,
+            "url": "https://gitlab.example.com/compilers/rust/-/merge_requests/12"
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_note_webhook() {
+        let api = api();
+        let event = api
+            .parse_webhook(
+                "secret",
+                &headers("secret", "Note Hook"),
+                MR_NOTE.as_bytes(),
+            )
+            .unwrap();
+        let WebhookEvent::Comment(comment) = event else {
+            panic!("expected a comment");
+        };
+        assert_eq!(comment.sender.id, 42);
+        assert_eq!(comment.sender.login, "pietroalbini");
+        assert_eq!(comment.body, "@craterbot run");
+        assert_eq!(comment.repository.full_name, "compilers/rust");
+        assert_eq!(comment.issue.number, 12);
+        assert_eq!(
+            comment.issue.url,
+            "https://gitlab.example.com/api/v4/projects/7/merge_requests/12"
+        );
+        assert!(comment.issue.pull_request.is_some());
+
+        // Edited comments are ignored
+        let edited = MR_NOTE.replace("\"create\"", "\"update\"");
+        assert!(matches!(
+            api.parse_webhook("secret", &headers("secret", "Note Hook"), edited.as_bytes())
+                .unwrap(),
+            WebhookEvent::Ignored
+        ));
+
+        // Invalid tokens and unknown events are rejected
+        assert!(api
+            .parse_webhook("secret", &headers("wrong", "Note Hook"), MR_NOTE.as_bytes())
+            .is_err());
+        assert!(api
+            .parse_webhook("secret", &headers("secret", "Push Hook"), b"{}")
+            .is_err());
+    }
+
+    #[test]
+    fn test_next_page() {
+        let url = "groups/1/members/all?per_page=100";
+        let page = |name, value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            next_page(url, &headers)
+        };
+
+        assert_eq!(
+            page("X-Next-Page", "3").as_deref(),
+            Some("groups/1/members/all?per_page=100&page=3")
+        );
+        // The last page has an empty header
+        assert_eq!(page("X-Next-Page", ""), None);
+        assert_eq!(
+            page(
+                "Link",
+                "<https://gitlab.example.com/api/v4/groups/1/members/all?id_after=42>; \
+                 rel=\"next\", <https://gitlab.example.com/api/v4/groups/1/members/all>; \
+                 rel=\"first\""
+            )
+            .as_deref(),
+            Some("https://gitlab.example.com/api/v4/groups/1/members/all?id_after=42")
+        );
+        assert_eq!(
+            page(
+                "Link",
+                "<https://gitlab.example.com/api/v4/groups/1/members/all>; rel=\"first\""
+            ),
+            None
+        );
+        assert_eq!(next_page(url, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
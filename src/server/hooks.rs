@@ -0,0 +1,330 @@
+//! Webhooks registered by external services, like triagebot, to be notified of the events of the
+//! experiments instead of polling the UI. Hooks are either global or scoped to an experiment, and
+//! every delivery is signed with the secret of the hook, like the GitHub webhooks are.
+//!
+//! Deliveries are queued and sent by a background thread, which retries the failed ones with an
+//! exponential backoff, so that unreachable receivers don't slow down the experiments.
+
+use crate::db::{Database, QueryUtils};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::utils;
+use crate::utils::hex::to_hex;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+use sha2::Sha256;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Headers of the deliveries, with the name of the event and the HMAC-SHA256 signature of the
+/// body.
+pub const EVENT_HEADER: &str = "x-crater-event";
+pub const SIGNATURE_HEADER: &str = "x-crater-signature-256";
+/// Slow receivers are cut short, to avoid delaying the deliveries queued after theirs.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts made to deliver an event to a webhook before giving up.
+const DELIVERY_ATTEMPTS: u32 = 5;
+/// Delay before the first retry of a failed delivery, doubled after each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum HookError {
+    #[error("invalid webhook URL `{0}`: only http and https URLs are supported")]
+    InvalidUrl(String),
+    #[error("the secret of the webhook can't be empty")]
+    EmptySecret,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hook {
+    pub id: i64,
+    pub url: String,
+    /// The hook receives the events of all the experiments when unscoped.
+    pub experiment: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    secret: String,
+}
+
+/// An event waiting to be delivered to a webhook.
+struct PendingDelivery {
+    hook: i64,
+    url: String,
+    secret: String,
+    event: String,
+    body: Vec<u8>,
+    /// Number of failed attempts so far.
+    failures: u32,
+}
+
+#[derive(Serialize)]
+struct Delivery<'a> {
+    event: &'a str,
+    experiment: &'a Experiment,
+    message: &'a str,
+    sent_at: DateTime<Utc>,
+}
+
+/// Register a new webhook, returning its id.
+pub fn register(
+    db: &impl QueryUtils,
+    url: &str,
+    secret: &str,
+    experiment: Option<&str>,
+    created_by: &str,
+) -> Fallible<i64> {
+    match url::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+        _ => return Err(HookError::InvalidUrl(url.into()).into()),
+    }
+    if secret.is_empty() {
+        return Err(HookError::EmptySecret.into());
+    }
+
+    db.with_conn(|conn| {
+        conn.execute(
+            "INSERT INTO hooks (url, secret, experiment, created_by, created_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5);",
+            rusqlite::params![url, secret, experiment, created_by, Utc::now()],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+}
+
+/// Remove a webhook, returning whether it existed.
+pub fn remove(db: &impl QueryUtils, id: i64) -> Fallible<bool> {
+    Ok(db.execute("DELETE FROM hooks WHERE id = ?1;", &[&id])? != 0)
+}
+
+/// Load all the registered webhooks, oldest first.
+pub fn list(db: &Database) -> Fallible<Vec<Hook>> {
+    db.query("SELECT * FROM hooks ORDER BY id;", [], hook_from_row)
+}
+
+/// Load the webhooks receiving the events of the experiment.
+fn for_experiment(db: &Database, experiment: &str) -> Fallible<Vec<Hook>> {
+    db.query(
+        "SELECT * FROM hooks WHERE experiment IS NULL OR experiment = ?1 ORDER BY id;",
+        [experiment],
+        hook_from_row,
+    )
+}
+
+fn hook_from_row(row: &rusqlite::Row) -> rusqlite::Result<Hook> {
+    Ok(Hook {
+        id: row.get("id")?,
+        url: row.get("url")?,
+        experiment: row.get("experiment")?,
+        created_by: row.get("created_by")?,
+        created_at: row.get("created_at")?,
+        secret: row.get("secret")?,
+    })
+}
+
+/// Signature of a delivery, as sent in the signature header.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    format!("sha256={}", to_hex(&mac.finalize().into_bytes()))
+}
+
+/// Queue of the deliveries to the webhooks, processed by a background thread.
+#[derive(Clone)]
+pub struct HookQueue {
+    sender: Sender<PendingDelivery>,
+    receiver: Receiver<PendingDelivery>,
+}
+
+impl HookQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE_SIZE);
+        HookQueue { sender, receiver }
+    }
+
+    pub fn spawn(&self) {
+        let receiver = self.receiver.clone();
+        thread::Builder::new()
+            .name(String::from("hooks-crater"))
+            .spawn(move || process_deliveries(receiver))
+            .expect("failed to spawn the webhook deliveries thread");
+    }
+
+    /// Queue the delivery of the event to all the webhooks of the experiment. An error is
+    /// returned if the queue has no room left for some of them.
+    pub fn deliver(
+        &self,
+        db: &Database,
+        ex: &Experiment,
+        event: &str,
+        message: &str,
+    ) -> Fallible<()> {
+        let body = serde_json::to_vec(&Delivery {
+            event,
+            experiment: ex,
+            message,
+            sent_at: Utc::now(),
+        })?;
+
+        let mut dropped = 0;
+        for hook in for_experiment(db, &ex.name)? {
+            let delivery = PendingDelivery {
+                hook: hook.id,
+                url: hook.url,
+                secret: hook.secret,
+                event: event.into(),
+                body: body.clone(),
+                failures: 0,
+            };
+            match self.sender.try_send(delivery) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => dropped += 1,
+                Err(TrySendError::Disconnected(_)) => unreachable!("the queue owns a receiver"),
+            }
+        }
+        if dropped > 0 {
+            bail!("the webhook queue is full, {dropped} deliveries of {event} were dropped");
+        }
+        Ok(())
+    }
+}
+
+/// Send the queued deliveries, retrying the failed ones once their backoff expires.
+fn process_deliveries(receiver: Receiver<PendingDelivery>) {
+    let mut retries: Vec<(Instant, PendingDelivery)> = Vec::new();
+    loop {
+        let received = match retries.iter().map(|(at, _)| *at).min() {
+            Some(next_retry) => receiver.recv_deadline(next_retry),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(delivery) => attempt(delivery, &mut retries),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut retries)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        retries = pending;
+        for (_, delivery) in due {
+            attempt(delivery, &mut retries);
+        }
+    }
+}
+
+/// Delay before retrying a delivery which failed `failures` times.
+fn backoff(failures: u32) -> Duration {
+    RETRY_BACKOFF * 2u32.pow(failures.saturating_sub(1))
+}
+
+fn attempt(mut delivery: PendingDelivery, retries: &mut Vec<(Instant, PendingDelivery)>) {
+    let Err(err) = send(&delivery) else {
+        return;
+    };
+    delivery.failures += 1;
+    if delivery.failures < DELIVERY_ATTEMPTS {
+        let delay = backoff(delivery.failures);
+        warn!("{:?} (retrying in {}s)", err, delay.as_secs());
+        retries.push((Instant::now() + delay, delivery));
+    } else {
+        utils::report_failure(
+            &err.context(format!("giving up after {DELIVERY_ATTEMPTS} attempts")),
+        );
+    }
+}
+
+fn send(delivery: &PendingDelivery) -> Fallible<()> {
+    utils::http::prepare_sync(Method::POST, &delivery.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(SIGNATURE_HEADER, sign(&delivery.secret, &delivery.body))
+        .body(delivery.body.clone())
+        .send()
+        .map_err(anyhow::Error::from)
+        .and_then(|resp| Ok(resp.error_for_status()?))
+        .with_context(|| {
+            format!(
+                "failed to deliver {} to webhook {}",
+                delivery.event, delivery.hook
+            )
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, for_experiment, list, register, remove, sign, HookError};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+
+    #[test]
+    fn test_hooks() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        CreateExperiment::dummy("bar").apply(&ctx).unwrap();
+
+        let global = register(&db, "https://example.com/all", "s1", None, "alice").unwrap();
+        let scoped = register(&db, "http://example.com/foo", "s2", Some("foo"), "bob").unwrap();
+
+        let ids = |hooks: Vec<super::Hook>| hooks.iter().map(|h| h.id).collect::<Vec<_>>();
+        assert_eq!(ids(list(&db).unwrap()), [global, scoped]);
+        assert_eq!(ids(for_experiment(&db, "foo").unwrap()), [global, scoped]);
+        assert_eq!(ids(for_experiment(&db, "bar").unwrap()), [global]);
+
+        // Secrets are never exposed by the API
+        let listed = serde_json::to_value(list(&db).unwrap()).unwrap();
+        assert!(listed[0].get("secret").is_none());
+        assert_eq!(listed[1]["experiment"], "foo");
+
+        assert!(remove(&db, global).unwrap());
+        assert!(!remove(&db, global).unwrap());
+        assert!(for_experiment(&db, "bar").unwrap().is_empty());
+
+        // Scoped hooks are removed along with their experiment
+        crate::actions::DeleteExperiment { name: "foo".into() }
+            .apply(&ctx)
+            .unwrap();
+        assert!(list(&db).unwrap().is_empty());
+
+        for (url, secret, error) in [
+            (
+                "ftp://example.com",
+                "s",
+                HookError::InvalidUrl("ftp://example.com".into()),
+            ),
+            ("not a url", "s", HookError::InvalidUrl("not a url".into())),
+            ("https://example.com", "", HookError::EmptySecret),
+        ] {
+            let err = register(&db, url, secret, None, "alice").unwrap_err();
+            assert_eq!(err.downcast_ref::<HookError>(), Some(&error));
+        }
+        assert!(register(&db, "https://example.com", "s", Some("missing"), "alice").is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1).as_secs(), 30);
+        assert_eq!(backoff(2).as_secs(), 60);
+        assert_eq!(backoff(4).as_secs(), 240);
+    }
+
+    #[test]
+    fn test_sign() {
+        // Signature generated with `echo -n "payload" | openssl sha256 -hmac secret`
+        assert_eq!(
+            sign("secret", b"payload"),
+            "sha256=b82fcb791acec57859b989b430a826488ce2e479fdf92326bd0a2e8375a42ba4"
+        );
+    }
+}
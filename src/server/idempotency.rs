@@ -0,0 +1,80 @@
+//! Idempotency keys of the results uploaded by the agents. Agents retry the uploads whose outcome
+//! they don't know (for example when the connection drops before the response is received), and
+//! the keys allow the server to acknowledge those retries without recording the results twice.
+
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::Utc;
+
+/// Number of days the keys are remembered for, well beyond the time agents keep retrying.
+const RETENTION_DAYS: u32 = 7;
+
+/// Record the key of an upload, returning `false` if it was already recorded. Keys are recorded
+/// in the same transaction as the results of the upload.
+pub fn claim(db: &impl QueryUtils, agent: &str, key: &str) -> Fallible<bool> {
+    let inserted = db.execute(
+        "INSERT OR IGNORE INTO result_uploads (agent, idempotency_key, received_at) \
+         VALUES (?1, ?2, ?3);",
+        rusqlite::params![agent, key, Utc::now()],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Whether the results of an upload were already recorded.
+pub fn is_recorded(db: &Database, agent: &str, key: &str) -> Fallible<bool> {
+    db.exists(
+        "SELECT 1 FROM result_uploads WHERE agent = ?1 AND idempotency_key = ?2;",
+        &[&agent, &key],
+    )
+}
+
+/// Remove the keys older than the retention period.
+pub fn purge_expired(db: &Database) -> Fallible<()> {
+    let purged = db.execute(
+        &format!(
+            "DELETE FROM result_uploads \
+             WHERE received_at <= datetime('now', '-{RETENTION_DAYS} days');"
+        ),
+        [],
+    )?;
+    if purged > 0 {
+        info!("purged {} expired idempotency keys", purged);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{claim, is_recorded, purge_expired};
+    use crate::db::{Database, QueryUtils};
+
+    #[test]
+    fn test_claim() {
+        let db = Database::temp().unwrap();
+
+        assert!(!is_recorded(&db, "agent-a", "key").unwrap());
+        assert!(claim(&db, "agent-a", "key").unwrap());
+        assert!(is_recorded(&db, "agent-a", "key").unwrap());
+        assert!(!claim(&db, "agent-a", "key").unwrap());
+        // Keys are scoped to the agent uploading the results
+        assert!(!is_recorded(&db, "agent-b", "key").unwrap());
+        assert!(claim(&db, "agent-b", "key").unwrap());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let db = Database::temp().unwrap();
+        assert!(claim(&db, "agent", "new").unwrap());
+        assert!(claim(&db, "agent", "old").unwrap());
+        db.execute(
+            "UPDATE result_uploads SET received_at = datetime('now', '-8 days') \
+             WHERE idempotency_key = 'old';",
+            [],
+        )
+        .unwrap();
+
+        purge_expired(&db).unwrap();
+        assert!(!claim(&db, "agent", "new").unwrap());
+        assert!(claim(&db, "agent", "old").unwrap());
+    }
+}
@@ -0,0 +1,265 @@
+//! Filing issues about the root regressions found by an experiment, once its report is
+//! generated. Each crate is reported only once for the same failure, across all experiments.
+
+use crate::crates::Crate;
+use crate::db::{Database, QueryUtils};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{self, ResultName, RootRegression, TestResults};
+use crate::results::{DatabaseDB, ReadResults};
+use crate::server::{BotData, Data};
+use chrono::Utc;
+use std::fmt::Write;
+
+/// Number of lines of the log included in the issues, starting from the first error.
+const EXCERPT_LINES: usize = 25;
+
+/// An issue about a root regression, ready to be filed.
+struct DraftIssue {
+    krate: String,
+    reason: String,
+    title: String,
+    body: String,
+}
+
+/// File (or draft, depending on the configuration) the issues about the root regressions of the
+/// experiment, skipping the ones already reported.
+pub(super) fn file_issues(
+    data: &Data,
+    bot_data: &BotData,
+    ex: &Experiment,
+    results: &DatabaseDB,
+    res: &TestResults,
+    report_url: &str,
+) -> Fallible<()> {
+    let config = &data.config.server.issues;
+    let repo = match &config.repo {
+        Some(repo) if config.experiments.is_match(&ex.name) => repo,
+        _ => return Ok(()),
+    };
+
+    let mut drafts = Vec::new();
+    for root in report::root_regressions(res) {
+        if drafts.len() >= config.max_issues {
+            warn!(
+                "{} has more than {} root regressions, not reporting the other ones",
+                ex.name, config.max_issues
+            );
+            break;
+        }
+        if let Some(draft) = draft_issue(&data.db, ex, results, &root, report_url)? {
+            drafts.push(draft);
+        }
+    }
+    if drafts.is_empty() {
+        return Ok(());
+    }
+
+    if config.draft {
+        let Some(github_issue) = &ex.github_issue else {
+            return Ok(());
+        };
+        let mut comment = format!(
+            ":memo: Drafts of the issues about the {} root regressions of **`{}`**, to file \
+             in {repo}:\n",
+            drafts.len(),
+            ex.name,
+        );
+        for draft in &drafts {
+            write!(
+                comment,
+                "\n<details>\n<summary>{}</summary>\n\n{}\n</details>\n",
+                draft.title, draft.body
+            )?;
+        }
+        bot_data.api.post_comment(&github_issue.api_url, &comment)?;
+        return Ok(());
+    }
+
+    for draft in drafts {
+        let url = bot_data
+            .api
+            .create_issue(repo, &draft.title, &draft.body, &config.labels)?;
+        info!(
+            "filed {} about {} regressing in {}",
+            url, draft.krate, ex.name
+        );
+        record_filed(&data.db, &draft.krate, &draft.reason, &ex.name, &url)?;
+    }
+    Ok(())
+}
+
+fn draft_issue(
+    db: &Database,
+    ex: &Experiment,
+    results: &DatabaseDB,
+    root: &RootRegression,
+    report_url: &str,
+) -> Fallible<Option<DraftIssue>> {
+    // Local crates are only used to test Crater itself
+    let Some(name) = report::retry_list_name(&root.krate) else {
+        return Ok(None);
+    };
+    let reason = match &root.result {
+        Some(result) => result.to_string(),
+        None => "breaks-dependents".into(),
+    };
+    if let Some(url) = previously_filed(db, &name, &reason)? {
+        debug!("{} was already reported in {}", name, url);
+        return Ok(None);
+    }
+
+    // When the root crate was not tested by the experiment, its failure is only visible in the
+    // logs of its dependents
+    let log_crate = if root.result.is_some() {
+        &root.krate
+    } else if let Some(dependent) = root.dependents.first() {
+        dependent
+    } else {
+        return Ok(None);
+    };
+    let excerpt = match results.load_log(ex, &ex.toolchains[1], log_crate)? {
+        Some(log) => error_excerpt(&String::from_utf8_lossy(&log.to_plain()?)),
+        None => String::new(),
+    };
+
+    let [start, end] = &ex.toolchains;
+    let title = format!("`{name}` regressed from {start} to {end}");
+    let mut body = format!(
+        "The crate [{}]({}) regressed in the Crater experiment [{}]({report_url})",
+        root.krate,
+        crate_url(&root.krate),
+        ex.name,
+    );
+    match &root.result {
+        Some(result) => write!(body, ": {}.", result.long_name())?,
+        None => write!(body, ", breaking the crates depending on it.")?,
+    }
+    write!(
+        body,
+        "\n\n* [Log with {start}]({})\n* [Log with {end}]({})\n",
+        report::log_url(report_url, start, log_crate),
+        report::log_url(report_url, end, log_crate),
+    )?;
+    for (tc, version) in ex.toolchains.iter().zip(ex.rustc_versions.iter()) {
+        if let Some(version) = version {
+            write!(body, "* {tc}: `{}`", version.version)?;
+            if let Some(hash) = &version.commit_hash {
+                write!(body, " (commit {hash})")?;
+            }
+            body.push('\n');
+        }
+    }
+    if !root.dependents.is_empty() {
+        write!(
+            body,
+            "\n{} crates failing because they depend on it also regressed.\n",
+            root.dependents.len()
+        )?;
+    }
+    if !excerpt.is_empty() {
+        write!(body, "\n```\n{excerpt}\n```\n")?;
+    }
+
+    Ok(Some(DraftIssue {
+        krate: name,
+        reason,
+        title,
+        body,
+    }))
+}
+
+fn crate_url(krate: &Crate) -> String {
+    match krate {
+        Crate::Registry(details) => format!("https://crates.io/crates/{}", details.name),
+        Crate::GitHub(repo) => format!("https://github.com/{}/{}", repo.org, repo.name),
+        Crate::Local(_) | Crate::Git(_) | Crate::Path(_) => String::new(),
+    }
+}
+
+/// Extract the lines of the log starting from the first error, or the end of the log if no
+/// error was found.
+fn error_excerpt(log: &str) -> String {
+    let lines = log.lines().collect::<Vec<_>>();
+    let start = lines
+        .iter()
+        .position(|line| line.contains("error[") || line.contains("error:"))
+        .unwrap_or_else(|| lines.len().saturating_sub(EXCERPT_LINES));
+    lines[start..]
+        .iter()
+        .take(EXCERPT_LINES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn previously_filed(db: &Database, krate: &str, reason: &str) -> Fallible<Option<String>> {
+    db.get_row(
+        "SELECT issue_url FROM filed_issues WHERE crate = ?1 AND reason = ?2;",
+        [&krate, &reason],
+        |row| row.get("issue_url"),
+    )
+}
+
+fn record_filed(
+    db: &Database,
+    krate: &str,
+    reason: &str,
+    ex: &str,
+    issue_url: &str,
+) -> Fallible<()> {
+    db.execute(
+        "INSERT INTO filed_issues (crate, reason, experiment, issue_url, filed_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+        &[&krate, &reason, &ex, &issue_url, &Utc::now()],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{error_excerpt, previously_filed, record_filed};
+    use crate::db::Database;
+
+    #[test]
+    fn test_error_excerpt() {
+        let log = "[INFO] [stderr]    Compiling foo v1.0.0\n\
+                   [INFO] [stderr] error[E0308]: mismatched types\n\
+                   [INFO] [stderr]  --> src/lib.rs:1:1";
+        assert_eq!(
+            error_excerpt(log),
+            "[INFO] [stderr] error[E0308]: mismatched types\n\
+             [INFO] [stderr]  --> src/lib.rs:1:1"
+        );
+
+        let log = (0..100).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(error_excerpt(&log.join("\n")), log[75..].join("\n"));
+    }
+
+    #[test]
+    fn test_deduplication() {
+        let db = Database::temp().unwrap();
+        assert_eq!(
+            previously_filed(&db, "foo", "build-fail:ice").unwrap(),
+            None
+        );
+
+        record_filed(
+            &db,
+            "foo",
+            "build-fail:ice",
+            "beta-1",
+            "https://example.com/1",
+        )
+        .unwrap();
+        assert_eq!(
+            previously_filed(&db, "foo", "build-fail:ice").unwrap(),
+            Some("https://example.com/1".into())
+        );
+        // The same crate failing differently is reported again
+        assert_eq!(
+            previously_filed(&db, "foo", "build-fail:unknown").unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+
+/// While the server is in maintenance mode no new experiments or crates are assigned to the
+/// agents, which finish the builds they're running and then wait for the maintenance to end.
+#[derive(Debug, Serialize)]
+pub struct Maintenance {
+    pub message: String,
+    pub enabled_by: String,
+    pub enabled_at: DateTime<Utc>,
+}
+
+pub fn current(db: &Database) -> Fallible<Option<Maintenance>> {
+    db.get_row(
+        "SELECT message, enabled_by, enabled_at FROM maintenance WHERE id = 0;",
+        [],
+        |row| {
+            Ok(Maintenance {
+                message: row.get("message")?,
+                enabled_by: row.get("enabled_by")?,
+                enabled_at: row.get("enabled_at")?,
+            })
+        },
+    )
+}
+
+pub fn is_enabled(db: &Database) -> Fallible<bool> {
+    Ok(current(db)?.is_some())
+}
+
+/// Enable the maintenance mode, or replace the message of the current maintenance.
+pub fn enable(db: &impl QueryUtils, message: &str, author: &str) -> Fallible<()> {
+    info!("{} enabled the maintenance mode: {}", author, message);
+    db.execute(
+        "INSERT OR REPLACE INTO maintenance (id, message, enabled_by, enabled_at) \
+         VALUES (0, ?1, ?2, ?3);",
+        &[&message, &author, &Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// Disable the maintenance mode, returning whether it was enabled.
+pub fn disable(db: &impl QueryUtils, author: &str) -> Fallible<bool> {
+    let changes = db.execute("DELETE FROM maintenance;", &[])?;
+    if changes != 0 {
+        info!("{} disabled the maintenance mode", author);
+    }
+    Ok(changes != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current, disable, enable, is_enabled};
+    use crate::db::Database;
+
+    #[test]
+    fn test_maintenance() {
+        let db = Database::temp().unwrap();
+        assert!(!is_enabled(&db).unwrap());
+        assert!(!disable(&db, "alice").unwrap());
+
+        enable(&db, "deploying", "alice").unwrap();
+        enable(&db, "deploying the new agents", "bob").unwrap();
+        let maintenance = current(&db).unwrap().unwrap();
+        assert_eq!(maintenance.message, "deploying the new agents");
+        assert_eq!(maintenance.enabled_by, "bob");
+
+        assert!(disable(&db, "alice").unwrap());
+        assert!(current(&db).unwrap().is_none());
+    }
+}
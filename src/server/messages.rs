@@ -1,7 +1,19 @@
+use crate::config::ZulipConfig;
+use crate::db::Database;
+use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::server::github::GitHub;
-use crate::server::{Data, GithubData};
+use crate::server::email::{Mailer, OutgoingEmail};
+use crate::server::hooks;
+use crate::server::maintenance;
+use crate::server::subscriptions;
+use crate::server::zulip::ZulipApi;
+use crate::server::{BotData, Data};
+use crate::utils;
 use std::fmt::Write;
+use std::thread;
+
+/// Maximum size of the body of a GitHub comment, with some room for the continuation headers.
+const MAX_COMMENT_SIZE: usize = 65536 - 256;
 
 pub enum Label {
     ExperimentQueued,
@@ -15,6 +27,7 @@ struct Line {
 
 pub struct Message {
     lines: Vec<Line>,
+    sections: Vec<String>,
     notes: Vec<Line>,
     new_label: Option<Label>,
 }
@@ -23,6 +36,7 @@ impl Message {
     pub fn new() -> Message {
         Message {
             lines: Vec::new(),
+            sections: Vec::new(),
             notes: Vec::new(),
             new_label: None,
         }
@@ -36,6 +50,13 @@ impl Message {
         self
     }
 
+    /// Block of markdown added after the lines, like a collapsed `<details>` section. Messages
+    /// with too many sections are split into multiple comments.
+    pub fn section<S: Into<String>>(mut self, content: S) -> Self {
+        self.sections.push(content.into());
+        self
+    }
+
     pub fn note<S1: Into<String>, S2: Into<String>>(mut self, emoji: S1, content: S2) -> Self {
         self.notes.push(Line {
             emoji: emoji.into(),
@@ -49,7 +70,17 @@ impl Message {
         self
     }
 
-    pub fn send(mut self, issue_url: &str, data: &Data, github_data: &GithubData) -> Fallible<()> {
+    pub fn send(mut self, issue_url: &str, data: &Data, bot_data: &BotData) -> Fallible<()> {
+        if let Some(maintenance) = maintenance::current(&data.db)? {
+            self = self.note(
+                "construction",
+                format!(
+                    "Crater is in maintenance mode, no new builds are started until it ends: {}",
+                    maintenance.message
+                ),
+            );
+        }
+
         // Always add a note at the bottom explaining what this is
         self = self.note(
             "information_source",
@@ -60,15 +91,18 @@ impl Message {
             ),
         );
 
-        let mut message = String::new();
+        let mut head = String::new();
         for line in self.lines {
-            writeln!(&mut message, ":{}: {}", line.emoji, line.content).unwrap();
+            writeln!(&mut head, ":{}: {}", line.emoji, line.content).unwrap();
         }
+        let mut tail = String::new();
         for line in self.notes {
-            write!(&mut message, "\n:{}: {}", line.emoji, line.content).unwrap();
+            write!(&mut tail, "\n:{}: {}", line.emoji, line.content).unwrap();
         }
 
-        github_data.api.post_comment(issue_url, &message)?;
+        for comment in split_comments(head, &self.sections, &tail, MAX_COMMENT_SIZE) {
+            bot_data.api.post_comment(issue_url, &comment)?;
+        }
 
         if let Some(label) = self.new_label {
             let label = match label {
@@ -79,23 +113,351 @@ impl Message {
             // Remove all the labels matching the provided regex
             // If the label is already present don't reapply it though
             let regex = &data.config.server.labels.remove;
-            let current_labels = github_data.api.list_labels(issue_url)?;
+            let current_labels = bot_data.api.list_labels(issue_url)?;
             let mut label_already_present = false;
             for current_label in &current_labels {
                 if current_label.name == *label {
                     label_already_present = true;
                 } else if regex.is_match(&current_label.name) {
-                    github_data
-                        .api
-                        .remove_label(issue_url, &current_label.name)?;
+                    bot_data.api.remove_label(issue_url, &current_label.name)?;
                 }
             }
 
             if !label_already_present {
-                github_data.api.add_label(issue_url, label)?;
+                bot_data.api.add_label(issue_url, label)?;
             }
         }
 
         Ok(())
     }
 }
+
+/// Split a message into comments smaller than `max_size`. The lines start the first comment, the
+/// notes end the last one, and the sections are only split across comments when a single one
+/// doesn't fit in a comment.
+fn split_comments(head: String, sections: &[String], tail: &str, max_size: usize) -> Vec<String> {
+    let mut comments = vec![head];
+    for section in sections.iter().flat_map(|s| split_section(s, max_size)) {
+        let current = comments.last().unwrap();
+        if !current.is_empty() && current.len() + section.len() + 1 > max_size {
+            comments.push(String::new());
+        }
+        let current = comments.last_mut().unwrap();
+        current.push('\n');
+        current.push_str(&section);
+        current.push('\n');
+    }
+    if !sections.is_empty() && comments.last().unwrap().len() + tail.len() > max_size {
+        comments.push(String::new());
+    }
+    comments.last_mut().unwrap().push_str(tail);
+
+    let count = comments.len();
+    if count > 1 {
+        for (i, comment) in comments.iter_mut().enumerate().skip(1) {
+            comment.insert_str(0, &format!("*(continued, part {} of {count})*\n", i + 1));
+        }
+    }
+    comments
+}
+
+/// Split a section too large to fit in a comment at line boundaries. A `<details>` section is
+/// closed at the end of each part, and reopened with the same summary at the start of the next.
+fn split_section(section: &str, max_size: usize) -> Vec<String> {
+    // The section is surrounded by newlines in the comment
+    if section.len() + 2 <= max_size {
+        return vec![section.to_string()];
+    }
+
+    const SUMMARY_END: &str = "</summary>\n";
+    const DETAILS_END: &str = "</details>";
+    let (header, body, footer) =
+        match (section.find(SUMMARY_END), section.strip_suffix(DETAILS_END)) {
+            (Some(pos), Some(inner)) if section.starts_with("<details>") => {
+                // The empty line after the summary is needed to render the markdown of the content
+                let mut end = pos + SUMMARY_END.len();
+                if section[end..].starts_with('\n') {
+                    end += 1;
+                }
+                (&section[..end], &inner[end..], DETAILS_END)
+            }
+            _ => ("", section, ""),
+        };
+
+    let budget = max_size
+        .saturating_sub(header.len() + footer.len() + 2)
+        .max(1);
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for mut line in body.split_inclusive('\n') {
+        while !line.is_empty() {
+            if !current.is_empty() && current.len() + line.len() > budget {
+                parts.push(std::mem::take(&mut current));
+            }
+            // Lines longer than a whole part are cut
+            let mut end = line.len().min(budget);
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                end = line.chars().next().unwrap().len_utf8();
+            }
+            current.push_str(&line[..end]);
+            line = &line[end..];
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+        .into_iter()
+        .map(|part| format!("{header}{part}{footer}"))
+        .collect()
+}
+
+/// Events in the lifecycle of an experiment which are mirrored to the notifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    ExperimentQueued,
+    ExperimentStarted,
+    ExperimentCompleted,
+    ReportReady,
+    ReportFailed,
+    ToolchainUnavailable,
+}
+
+impl Event {
+    /// Name of the event in the webhook deliveries.
+    fn name(self) -> &'static str {
+        match self {
+            Event::ExperimentQueued => "experiment-queued",
+            Event::ExperimentStarted => "experiment-started",
+            Event::ExperimentCompleted => "experiment-completed",
+            Event::ReportReady => "report-ready",
+            Event::ReportFailed => "report-failed",
+            Event::ToolchainUnavailable => "toolchain-unavailable",
+        }
+    }
+
+    fn render(self, ex: &Experiment) -> String {
+        let name = &ex.name;
+        let mut message = match self {
+            Event::ExperimentQueued => format!("Experiment **`{name}`** created and queued."),
+            Event::ExperimentStarted => format!("Experiment **`{name}`** is now **running**."),
+            Event::ExperimentCompleted => {
+                format!("Experiment **`{name}`** finished running, generating the report.")
+            }
+            Event::ReportReady => match ex.report_url {
+                Some(ref url) => format!("The [report]({url}) of **`{name}`** is ready."),
+                None => format!("The report of **`{name}`** is ready."),
+            },
+            Event::ReportFailed => format!("Report generation of **`{name}`** failed."),
+            Event::ToolchainUnavailable => {
+                format!(
+                    "Experiment **`{name}`** can't run, one of its toolchains is not available."
+                )
+            }
+        };
+        if let Some(ref issue) = ex.github_issue {
+            write!(&mut message, " ([GitHub thread]({}))", issue.html_url).unwrap();
+        }
+        message
+    }
+}
+
+/// A destination experiment events are sent to, in addition to the GitHub comments.
+pub trait Notifier {
+    fn notify(&self, ex: &Experiment, event: Event) -> Fallible<()>;
+}
+
+pub struct Zulip<'a> {
+    api: &'a ZulipApi,
+    config: &'a ZulipConfig,
+}
+
+impl Notifier for Zulip<'_> {
+    fn notify(&self, ex: &Experiment, event: Event) -> Fallible<()> {
+        let stream = match ex.zulip_stream.as_ref().or(self.config.stream.as_ref()) {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        let topic = self.config.topic.replace("{name}", &ex.name);
+        self.api
+            .send_stream_message(stream, &topic, &event.render(ex))
+    }
+}
+
+/// Emails the subscribers of the experiment once its report is ready or failed to generate. The
+/// emails are sent in the background, without waiting for the SMTP server.
+pub struct Email<'a> {
+    mailer: &'a Mailer,
+    db: &'a Database,
+    public_url: &'a str,
+}
+
+impl Notifier for Email<'_> {
+    fn notify(&self, ex: &Experiment, event: Event) -> Fallible<()> {
+        if event != Event::ReportReady && event != Event::ReportFailed {
+            return Ok(());
+        }
+
+        let subject = format!("[crater] {}", ex.name);
+        let body = event.render(ex);
+        let emails = subscriptions::email_subscribers(self.db, &ex.name)?
+            .into_iter()
+            .map(|(to, token)| OutgoingEmail {
+                to,
+                subject: subject.clone(),
+                body: format!(
+                    "{body}\n\nTo stop receiving these emails: \
+                     {}/subscriptions/unsubscribe/{token}",
+                    self.public_url
+                ),
+            })
+            .collect();
+        self.mailer.send_in_background(emails);
+        Ok(())
+    }
+}
+
+/// Delivers all the events to the webhooks registered through the admin API, in the background.
+pub struct Webhooks<'a> {
+    db: &'a Database,
+    queue: &'a hooks::HookQueue,
+}
+
+impl Notifier for Webhooks<'_> {
+    fn notify(&self, ex: &Experiment, event: Event) -> Fallible<()> {
+        self.queue
+            .deliver(self.db, ex, event.name(), &event.render(ex))
+    }
+}
+
+fn notifiers(data: &Data) -> Vec<Box<dyn Notifier + '_>> {
+    let mut notifiers: Vec<Box<dyn Notifier + '_>> = vec![Box::new(Webhooks {
+        db: &data.db,
+        queue: &data.hook_queue,
+    })];
+    if let Some(ref api) = data.zulip {
+        notifiers.push(Box::new(Zulip {
+            api,
+            config: &data.config.server.zulip,
+        }));
+    }
+    if let Some(ref mailer) = data.mailer {
+        notifiers.push(Box::new(Email {
+            mailer,
+            db: &data.db,
+            public_url: data.config.server.public_url.trim_end_matches('/'),
+        }));
+    }
+    notifiers
+}
+
+/// Sends the event to all the configured notifiers. Notifications are best effort: failures are
+/// logged without interrupting the caller.
+pub fn notify(data: &Data, ex: &Experiment, event: Event) {
+    for notifier in notifiers(data) {
+        if let Err(err) = notifier.notify(ex, event) {
+            error!("failed to send the {:?} notification of {}", event, ex.name);
+            utils::report_failure(&err);
+        }
+    }
+}
+
+/// Sends the event from a separate thread, for the callers which can't wait for the notifiers,
+/// like the agent endpoints holding the lock on the server state.
+pub fn notify_in_background(data: &Data, ex: &Experiment, event: Event) {
+    let data = data.clone();
+    let ex = ex.clone();
+    thread::spawn(move || notify(&data, &ex, event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_comments, Event};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+
+    #[test]
+    fn test_render_event() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        assert_eq!(
+            Event::ExperimentStarted.render(&ex),
+            "Experiment **`foo`** is now **running**."
+        );
+        assert_eq!(
+            Event::ReportReady.render(&ex),
+            "The report of **`foo`** is ready."
+        );
+
+        ex.set_report_url(&db, "https://example.com/foo/index.html")
+            .unwrap();
+        assert_eq!(
+            Event::ReportReady.render(&ex),
+            "The [report](https://example.com/foo/index.html) of **`foo`** is ready."
+        );
+    }
+
+    #[test]
+    fn test_split_comments() {
+        let sections = ["a".repeat(40), "b".repeat(40), "c".repeat(40)];
+
+        let comments = split_comments("head\n".into(), &sections, "\ntail", 1000);
+        assert_eq!(
+            comments,
+            [format!(
+                "head\n\n{}\n\n{}\n\n{}\n\ntail",
+                sections[0], sections[1], sections[2]
+            )]
+        );
+
+        let comments = split_comments("head\n".into(), &sections, "\ntail", 100);
+        assert_eq!(
+            comments,
+            [
+                format!("head\n\n{}\n\n{}\n", sections[0], sections[1]),
+                format!("*(continued, part 2 of 2)*\n\n{}\n\ntail", sections[2]),
+            ]
+        );
+
+        // Messages without sections are never split
+        assert_eq!(
+            split_comments("head\n".into(), &[], "\ntail", 5),
+            ["head\n\ntail"]
+        );
+
+        // Sections larger than a comment are split, reopening their details
+        let lines = (0..6)
+            .map(|i| format!("* {i}{}\n", "x".repeat(20)))
+            .collect::<String>();
+        let section = format!("<details>\n<summary>big</summary>\n\n{lines}</details>");
+        let comments = split_comments("head\n".into(), &[section], "\ntail", 120);
+        assert_eq!(comments.len(), 4);
+        for comment in &comments[1..3] {
+            assert!(comment.len() <= 120 + "*(continued, part 2 of 4)*\n".len());
+            assert!(comment.contains("\n<details>\n<summary>big</summary>\n\n* "));
+            assert!(comment.ends_with("</details>\n"));
+        }
+        for i in 0..6 {
+            assert_eq!(
+                comments
+                    .iter()
+                    .filter(|c| c.contains(&format!("* {i}x")))
+                    .count(),
+                1
+            );
+        }
+        assert_eq!(comments[3], "*(continued, part 4 of 4)*\n\ntail");
+    }
+}
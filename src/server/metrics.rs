@@ -1,9 +1,16 @@
 use crate::db::{Database, QueryUtils};
-use crate::experiments::{Assignee, Experiment};
+use crate::experiments::{Assignee, Experiment, Mode};
 use crate::prelude::*;
+use crate::report::Comparison;
+use crate::results::{BuildCacheStats, BuildDiskUsage, TestFailures, TestResult};
 use crate::server::agents::Agent;
+use crate::toolchain::Toolchain;
 use chrono::{DateTime, Utc};
-use prometheus::{Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
+use prometheus::core::Collector;
+use prometheus::{
+    CounterVec, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use std::collections::HashMap;
 
 const JOBS_METRIC: &str = "crater_completed_jobs_total";
 const AGENT_WORK_METRIC: &str = "crater_agent_supposed_to_work";
@@ -12,6 +19,14 @@ const PROGRESS_REPORT: &str = "crater_progress_report";
 const LAST_CRATES_UPDATE_METRIC: &str = "crater_last_crates_update";
 const ENDPOINT_TIME: &str = "crater_endpoint_time_seconds";
 const WORKER_COUNT: &str = "crater_worker_count";
+const DISK_USAGE: &str = "crater_build_disk_usage_bytes";
+const SHADOW_RUNS: &str = "crater_shadow_run_results_total";
+const BUILD_TIME: &str = "crater_build_time_seconds";
+const AGENT_BUILD_TIME: &str = "crater_agent_build_seconds_total";
+const FAILURE_REASONS: &str = "crater_failure_reasons_total";
+const COMPARISONS: &str = "crater_comparisons_total";
+const BUILD_CACHE: &str = "crater_build_cache_compilations_total";
+const WORKER_RESTARTS: &str = "crater_worker_restarts_total";
 
 #[derive(Clone)]
 pub struct Metrics {
@@ -24,6 +39,14 @@ pub struct Metrics {
     crater_worker_count: IntGauge,
     pub result_log_size: Histogram,
     pub crater_progress_report: IntCounterVec,
+    crater_build_disk_usage: HistogramVec,
+    crater_shadow_run_results: IntCounterVec,
+    crater_build_time: HistogramVec,
+    crater_agent_build_seconds: CounterVec,
+    crater_failure_reasons: IntCounterVec,
+    crater_comparisons: IntCounterVec,
+    crater_build_cache: IntCounterVec,
+    crater_worker_restarts: IntCounterVec,
 }
 
 impl Metrics {
@@ -61,6 +84,62 @@ impl Metrics {
             prometheus::exponential_buckets(4096.0, 1.28, 30)?
         )?;
 
+        let crater_build_disk_usage = prometheus::register_histogram_vec!(
+            prometheus::HistogramOpts::new(DISK_USAGE, "disk space used by the builds")
+                // Exponential buckets, from 1MiB to 32GiB.
+                .buckets(prometheus::exponential_buckets(1048576.0, 2.0, 16)?),
+            &["dir"]
+        )?;
+
+        let shadow_runs_opts = prometheus::opts!(
+            SHADOW_RUNS,
+            "results of the shadow runs involving each agent"
+        );
+        let crater_shadow_run_results =
+            prometheus::register_int_counter_vec!(shadow_runs_opts, &["agent", "outcome"])?;
+
+        let crater_build_time = prometheus::register_histogram_vec!(
+            prometheus::HistogramOpts::new(BUILD_TIME, "time spent building each crate")
+                // Exponential buckets, from 1 second to approximately 4.5 hours.
+                .buckets(prometheus::exponential_buckets(1.0, 2.0, 15)?),
+            &["mode"]
+        )?;
+        let agent_build_opts = prometheus::opts!(
+            AGENT_BUILD_TIME,
+            "total time spent building crates by each agent"
+        );
+        let crater_agent_build_seconds =
+            prometheus::register_counter_vec!(agent_build_opts, &["agent"])?;
+
+        let failure_reasons_opts = prometheus::opts!(
+            FAILURE_REASONS,
+            "failed builds of the running experiments by failure reason"
+        );
+        let crater_failure_reasons = prometheus::register_int_counter_vec!(
+            failure_reasons_opts,
+            &["experiment", "toolchain", "reason"]
+        )?;
+        let comparisons_opts = prometheus::opts!(
+            COMPARISONS,
+            "crates of the running experiments by comparison of their results"
+        );
+        let crater_comparisons =
+            prometheus::register_int_counter_vec!(comparisons_opts, &["experiment", "comparison"])?;
+
+        let build_cache_opts = prometheus::opts!(
+            BUILD_CACHE,
+            "compilations served from the build cache of each agent, or missing from it"
+        );
+        let crater_build_cache =
+            prometheus::register_int_counter_vec!(build_cache_opts, &["agent", "outcome"])?;
+
+        let worker_restarts_opts = prometheus::opts!(
+            WORKER_RESTARTS,
+            "workers of each agent restarted after a panic"
+        );
+        let crater_worker_restarts =
+            prometheus::register_int_counter_vec!(worker_restarts_opts, &["agent"])?;
+
         let crater_worker_count = prometheus::opts!(WORKER_COUNT, "number of active workers");
         let crater_worker_count = prometheus::register_int_gauge!(crater_worker_count)?;
 
@@ -74,9 +153,92 @@ impl Metrics {
             crater_endpoint_time,
             crater_worker_count,
             result_log_size,
+            crater_build_disk_usage,
+            crater_shadow_run_results,
+            crater_build_time,
+            crater_agent_build_seconds,
+            crater_failure_reasons,
+            crater_comparisons,
+            crater_build_cache,
+            crater_worker_restarts,
         })
     }
 
+    pub fn record_disk_usage(&self, usage: &BuildDiskUsage) {
+        self.crater_build_disk_usage
+            .with_label_values(&["source"])
+            .observe(usage.source_dir as f64);
+        self.crater_build_disk_usage
+            .with_label_values(&["target"])
+            .observe(usage.target_dir as f64);
+    }
+
+    /// Count the result of a shadow run for both the agent which originally ran the crate and
+    /// the one which re-ran it.
+    pub fn record_shadow_run(&self, original_agent: &str, shadow_agent: &str, matches: bool) {
+        let outcome = if matches { "match" } else { "mismatch" };
+        for agent in [original_agent, shadow_agent] {
+            self.crater_shadow_run_results
+                .with_label_values(&[agent, outcome])
+                .inc();
+        }
+    }
+
+    /// Record the time spent building a crate, used to track the cost of the experiments and the
+    /// utilization of the agents.
+    pub fn record_build_time(&self, agent: &str, mode: Mode, seconds: f64) {
+        self.crater_build_time
+            .with_label_values(&[mode.to_str()])
+            .observe(seconds);
+        self.crater_agent_build_seconds
+            .with_label_values(&[agent])
+            .inc_by(seconds);
+    }
+
+    pub fn record_build_cache(&self, agent: &str, stats: &BuildCacheStats) {
+        self.crater_build_cache
+            .with_label_values(&[agent, "hit"])
+            .inc_by(stats.hits.into());
+        self.crater_build_cache
+            .with_label_values(&[agent, "miss"])
+            .inc_by(stats.misses.into());
+    }
+
+    /// Count the failure reason of a result as soon as it's recorded, allowing to alert on
+    /// failures like ICEs while the experiment is still running.
+    pub fn record_failure_reason(
+        &self,
+        experiment: &str,
+        toolchain: &Toolchain,
+        result: &TestResult,
+    ) {
+        let reason = match result {
+            TestResult::BuildFail(reason)
+            | TestResult::TestFail(reason)
+            | TestResult::DoctestFail(reason)
+            | TestResult::TestAndDoctestFail(TestFailures { tests: reason, .. }) => reason,
+            _ => return,
+        };
+        self.crater_failure_reasons
+            .with_label_values(&[experiment, &toolchain.to_string(), reason.kind()])
+            .inc();
+    }
+
+    /// Count the comparison of the results of a crate, once both toolchains have been run.
+    pub fn record_comparison(&self, experiment: &str, comparison: Comparison) {
+        self.crater_comparisons
+            .with_label_values(&[experiment, comparison.to_str()])
+            .inc();
+    }
+
+    pub fn record_worker_restarts(&self, agent: &str, restarts: u32) {
+        if restarts > 0 {
+            self.crater_worker_restarts
+                .with_label_values(&[agent])
+                .inc_by(restarts.into());
+        }
+    }
+
     pub fn record_worker_count(&self, count: usize) {
         self.crater_worker_count.set(count as i64);
     }
@@ -129,26 +291,54 @@ impl Metrics {
     }
 
     pub fn on_complete_experiment(&self, experiment: &str) -> Fallible<()> {
-        self.remove_experiment_jobs(experiment)
+        self.remove_experiment_jobs(experiment)?;
+        remove_experiment_series(&self.crater_failure_reasons, experiment)?;
+        remove_experiment_series(&self.crater_comparisons, experiment)?;
+        Ok(())
     }
 }
 
+/// Remove all the series of the metric labelled with the experiment, whatever their other labels.
+fn remove_experiment_series(metric: &IntCounterVec, experiment: &str) -> Fallible<()> {
+    for family in metric.collect() {
+        for series in family.get_metric() {
+            let labels = series
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .collect::<HashMap<_, _>>();
+            if labels.get("experiment") == Some(&experiment) {
+                metric.remove(&labels)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+lazy_static! {
+    /// The metrics are registered globally, so all the tests share the same instance.
+    pub(super) static ref TEST_METRICS: Metrics = Metrics::new().unwrap();
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Metrics, AGENT_WORK_METRIC, JOBS_METRIC, LAST_CRATES_UPDATE_METRIC};
+    use super::{
+        Metrics, AGENT_WORK_METRIC, COMPARISONS, FAILURE_REASONS, JOBS_METRIC,
+        LAST_CRATES_UPDATE_METRIC, WORKER_RESTARTS,
+    };
     use crate::actions::{Action, ActionsCtx, CreateExperiment, EditExperiment};
     use crate::config::Config;
     use crate::db::Database;
     use crate::experiments::{Assignee, Experiment};
+    use crate::report::Comparison;
+    use crate::results::{FailureReason, TestResult};
     use crate::server::agents::{Agent, Agents};
     use crate::server::tokens::Tokens;
     use chrono::Utc;
-    use lazy_static::lazy_static;
     use prometheus::proto::{Metric, MetricFamily};
 
-    lazy_static! {
-        static ref METRICS: Metrics = Metrics::new().unwrap();
-    }
+    use super::TEST_METRICS as METRICS;
 
     impl Metrics {
         fn get_metric_by_name(name: &str) -> Option<MetricFamily> {
@@ -197,6 +387,70 @@ mod tests {
         METRICS.on_complete_experiment(ex2).unwrap();
     }
 
+    fn counter_value(name: &str, experiment: &str, label: &str, value: &str) -> Option<u64> {
+        let family = Metrics::get_metric_by_name(name)?;
+        family
+            .get_metric()
+            .iter()
+            .find(|met| {
+                Metrics::get_label_by_name(met, "experiment") == Some(experiment)
+                    && Metrics::get_label_by_name(met, label) == Some(value)
+            })
+            .map(|met| met.get_counter().get_value() as u64)
+    }
+
+    #[test]
+    fn test_result_categories() {
+        let ex = "pr-categories";
+        let toolchain = "stable".parse().unwrap();
+
+        let ice = TestResult::BuildFail(FailureReason::ICE);
+        METRICS.record_failure_reason(ex, &toolchain, &ice);
+        METRICS.record_failure_reason(ex, &toolchain, &ice);
+        METRICS.record_failure_reason(ex, &toolchain, &TestResult::TestPass);
+        let lint = "new-lint(unused_imports)".parse::<FailureReason>().unwrap();
+        METRICS.record_failure_reason(ex, &toolchain, &TestResult::TestFail(lint));
+        METRICS.record_comparison(ex, Comparison::Regressed);
+
+        assert_eq!(counter_value(FAILURE_REASONS, ex, "reason", "ice"), Some(2));
+        assert_eq!(
+            counter_value(FAILURE_REASONS, ex, "reason", "new-lint"),
+            Some(1)
+        );
+        assert_eq!(
+            counter_value(COMPARISONS, ex, "comparison", "regressed"),
+            Some(1)
+        );
+
+        METRICS.on_complete_experiment(ex).unwrap();
+        assert_eq!(counter_value(FAILURE_REASONS, ex, "reason", "ice"), None);
+        assert_eq!(
+            counter_value(COMPARISONS, ex, "comparison", "regressed"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_worker_restarts() {
+        let agent = "agent-restarts";
+        METRICS.record_worker_restarts(agent, 0);
+        let restarts = || {
+            Metrics::get_metric_by_name(WORKER_RESTARTS).and_then(|family| {
+                family
+                    .get_metric()
+                    .iter()
+                    .find(|met| Metrics::get_label_by_name(met, "agent") == Some(agent))
+                    .map(|met| met.get_counter().get_value() as u64)
+            })
+        };
+        // Heartbeats without restarts don't create the series
+        assert_eq!(restarts(), None);
+
+        METRICS.record_worker_restarts(agent, 2);
+        METRICS.record_worker_restarts(agent, 1);
+        assert_eq!(restarts(), Some(3));
+    }
+
     fn supposed_to_work(metric: &MetricFamily, agent_filter: Option<&str>) -> bool {
         metric
             .get_metric()
@@ -255,7 +509,7 @@ mod tests {
         .apply(&ctx)
         .unwrap();
         let ex = Experiment::next(&db, &assignee).unwrap().unwrap().1;
-        ex.get_uncompleted_crates(&db, None).unwrap();
+        ex.get_uncompleted_crates(&db, &config, None).unwrap();
         METRICS.update_agent_status(&db, &agent_list_ref).unwrap();
 
         // There are no experiments in the queue but agent1 is still executing the
@@ -1,28 +1,51 @@
 pub mod agents;
+pub mod api_encoding;
 pub mod api_types;
+pub(crate) mod audit;
 mod auth;
+mod components;
+mod costs;
+pub mod crate_cache;
 mod cronjobs;
+mod email;
 mod github;
+mod gitlab;
+mod hooks;
+pub(crate) mod idempotency;
+mod issues;
+mod maintenance;
 mod messages;
 mod metrics;
 mod reports;
 mod routes;
+mod shadow_runs;
+mod subscriptions;
 pub mod tokens;
 mod try_builds;
+mod uploads;
+mod vcs;
+mod zulip;
 
 use crate::config::Config;
 use crate::crates::Crate;
 use crate::db::Database;
 use crate::prelude::*;
+use crate::results::LogStore;
 use crate::server::agents::Agents;
 use crate::server::auth::ACL;
-use crate::server::github::{GitHub, GitHubApi};
+use crate::server::email::Mailer;
+use crate::server::github::GitHubApi;
+use crate::server::gitlab::GitLabApi;
 use crate::server::tokens::{BotTokens, Tokens};
+use crate::server::vcs::VcsHost;
+use crate::server::zulip::ZulipApi;
 use http::{header::HeaderValue, Response};
 use hyper::Body;
 use metrics::Metrics;
 use std::collections::VecDeque;
+use std::fmt;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use warp::Filter;
@@ -38,10 +61,24 @@ pub enum HttpError {
     NotFound,
     #[error("forbidden")]
     Forbidden,
+    #[error("the server is in read-only mode")]
+    ReadOnly,
+    #[error("bad request")]
+    BadRequest,
 }
 
 impl warp::reject::Reject for HttpError {}
 
+impl HttpError {
+    /// Error rejecting an invalid request, with the reason shown to the client.
+    pub fn bad_request<R>(reason: R) -> anyhow::Error
+    where
+        R: fmt::Display + Send + Sync + 'static,
+    {
+        anyhow::Error::new(HttpError::BadRequest).context(reason)
+    }
+}
+
 #[derive(Clone)]
 pub struct Data {
     pub config: Config,
@@ -50,67 +87,188 @@ pub struct Data {
     pub db: Database,
     pub reports_worker: reports::ReportsWorker,
     pub record_progress_worker: routes::agent::RecordProgressThread,
+    pub webhook_queue: routes::webhooks::WebhookQueue,
+    pub hook_queue: hooks::HookQueue,
     pub uncompleted_cache: Arc<Mutex<VecDeque<(Instant, Crate)>>>,
     pub acl: ACL,
     pub metrics: Metrics,
+    pub zulip: Option<ZulipApi>,
+    pub mailer: Option<Mailer>,
+    pub log_store: Option<Arc<dyn LogStore>>,
+    pub uploads: uploads::Uploads,
+    /// Handle to the database allowed to change the state of the server, missing in read-only
+    /// mode.
+    writable_db: Option<Database>,
+}
+
+impl Data {
+    /// Handle to the database to change the state of the server through, which is refused when
+    /// the server is in read-only mode.
+    pub fn writable_db(&self) -> Fallible<&Database> {
+        self.writable_db
+            .as_ref()
+            .ok_or_else(|| HttpError::ReadOnly.into())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.writable_db.is_none()
+    }
+}
+
+#[cfg(test)]
+impl Data {
+    /// Server state backed by a temporary database, without any background worker running.
+    pub(crate) fn temp(tokens: Tokens) -> Fallible<Self> {
+        let db = Database::temp()?;
+        let config = Config::default();
+        let metrics = metrics::TEST_METRICS.clone();
+        Ok(Data {
+            record_progress_worker: routes::agent::RecordProgressThread::new(
+                db.clone(),
+                config.clone(),
+                metrics.clone(),
+                None,
+            ),
+            agents: Agents::new(db.clone(), &tokens)?,
+            acl: ACL::new(&config, None)?,
+            writable_db: Some(db.clone()),
+            config,
+            tokens,
+            db,
+            reports_worker: reports::ReportsWorker::new(),
+            webhook_queue: routes::webhooks::WebhookQueue::new(),
+            hook_queue: hooks::HookQueue::new(),
+            metrics,
+            zulip: None,
+            mailer: None,
+            log_store: None,
+            uploads: uploads::Uploads::new(&std::env::temp_dir().join("crater-test-uploads")),
+            uncompleted_cache: Arc::new(Mutex::new(VecDeque::new())),
+        })
+    }
 }
 
+/// State of the bot, which can be hosted either on GitHub or on a GitLab instance.
 #[derive(Clone)]
-pub struct GithubData {
+pub struct BotData {
     pub bot_username: String,
-    pub api: GitHubApi,
+    pub api: Arc<dyn VcsHost>,
+    /// Client used by the background workers, which can't use the part of the rate limit kept
+    /// for the replies to the bot commands.
+    background_api: Arc<dyn VcsHost>,
     pub tokens: BotTokens,
 }
 
-pub fn run(config: Config, bind: SocketAddr) -> Fallible<()> {
-    let db = Database::open()?;
+impl BotData {
+    /// The bot as used by the background workers, like the reports generator.
+    pub fn background(&self) -> BotData {
+        BotData {
+            api: self.background_api.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Start the server. In read-only mode the server only serves the UI and the read-only APIs from
+/// the provided replica of the database, refusing all the requests that would change its state.
+pub fn run(
+    config: Config,
+    bind: SocketAddr,
+    read_only: bool,
+    replica: Option<&Path>,
+) -> Fallible<()> {
+    let db = match replica {
+        Some(path) if read_only => {
+            info!("serving the read-only replica {}", path.display());
+            Database::open_read_only(path)?
+        }
+        Some(_) => bail!("a replica of the database can only be served in read-only mode"),
+        None if read_only => bail!("the read-only mode needs a replica of the database"),
+        None => Database::open()?,
+    };
     let tokens = tokens::Tokens::load()?;
-    let github_data = tokens
+    let bot_data = tokens
         .bot
         .as_ref()
         .cloned()
         .map(|tokens| {
-            let github = GitHubApi::new(&tokens);
-            let bot_username = github.username()?;
+            let (api, background_api): (Arc<dyn VcsHost>, Arc<dyn VcsHost>) =
+                match tokens.gitlab_url {
+                    Some(ref url) => {
+                        info!("using the GitLab instance at {}", url);
+                        let api: Arc<dyn VcsHost> = Arc::new(GitLabApi::new(url, &tokens));
+                        (api.clone(), api)
+                    }
+                    None => {
+                        let api = GitHubApi::new(&tokens);
+                        let background: Arc<dyn VcsHost> = Arc::new(api.background());
+                        (Arc::new(api), background)
+                    }
+                };
+            let bot_username = api.username()?;
             info!("bot username: {}", bot_username);
-            Ok::<_, anyhow::Error>(GithubData {
-                api: github,
+            Ok::<_, anyhow::Error>(BotData {
+                api,
+                background_api,
                 bot_username,
                 tokens,
             })
         })
         .transpose()?;
-    let agents = Agents::new(db.clone(), &tokens)?;
+    let agents = if read_only {
+        Agents::read_only(db.clone())
+    } else {
+        Agents::new(db.clone(), &tokens)?
+    };
     info!("loaded agents...");
-    let acl = ACL::new(&config, github_data.as_ref())?;
+    let acl = ACL::new(&config, bot_data.as_ref())?;
     let metrics = Metrics::new()?;
     info!("initialized metrics...");
+    let zulip = tokens.zulip.as_ref().map(ZulipApi::new);
+    let mailer = tokens.smtp.as_ref().map(Mailer::new).transpose()?;
+    let log_store = tokens.log_store()?;
 
     let data = Data {
+        writable_db: if read_only { None } else { Some(db.clone()) },
         record_progress_worker: routes::agent::RecordProgressThread::new(
             db.clone(),
+            config.clone(),
             metrics.clone(),
+            log_store.clone(),
         ),
         config,
         tokens,
         agents,
         db,
         reports_worker: reports::ReportsWorker::new(),
+        webhook_queue: routes::webhooks::WebhookQueue::new(),
+        hook_queue: hooks::HookQueue::new(),
         acl,
         metrics,
+        zulip,
+        mailer,
+        log_store,
+        uploads: uploads::Uploads::new(&crate::dirs::WORK_DIR.join("uploads")),
         uncompleted_cache: Arc::new(Mutex::new(VecDeque::new())),
     };
 
     let mutex = Arc::new(Mutex::new(data.clone()));
 
-    data.reports_worker.spawn(data.clone(), github_data.clone());
-    info!("spawned reports worker...");
-    cronjobs::spawn(data.clone());
+    if !read_only {
+        data.reports_worker
+            .spawn(data.clone(), bot_data.as_ref().map(BotData::background));
+        info!("spawned reports worker...");
+        data.webhook_queue.spawn(data.clone(), bot_data.clone());
+        info!("spawned webhooks worker...");
+        data.hook_queue.spawn();
+        info!("spawned webhook deliveries worker...");
+        cronjobs::spawn(data.clone());
+    }
 
     info!("running server on {}...", bind);
 
     let data = Arc::new(data);
-    let github_data = github_data.map(Arc::new);
+    let bot_data = bot_data.map(Arc::new);
 
     let record_progress_worker = data.record_progress_worker.clone();
     let routes = warp::any()
@@ -119,16 +277,24 @@ pub fn run(config: Config, bind: SocketAddr) -> Fallible<()> {
             warp::any()
                 .and(
                     warp::path("webhooks")
-                        .and(routes::webhooks::routes(data.clone(), github_data.clone())),
+                        .and(routes::webhooks::routes(data.clone(), bot_data.clone())),
                 )
                 .or(warp::path("agent-api").and(routes::agent::routes(
                     data.clone(),
                     mutex,
-                    github_data,
+                    bot_data.clone(),
                 )))
                 .unify()
                 .or(warp::path("metrics").and(routes::metrics::routes(data.clone())))
                 .unify()
+                .or(warp::path("triage-api").and(routes::triage::routes(data.clone())))
+                .unify()
+                .or(warp::path("crates-api").and(routes::crates::routes(data.clone())))
+                .unify()
+                .or(warp::path("experiments-api").and(routes::experiments::routes(data.clone())))
+                .unify()
+                .or(warp::path("admin-api").and(routes::admin::routes(data.clone(), bot_data)))
+                .unify()
                 .or(routes::ui::routes(data))
                 .unify(),
         )
@@ -1,44 +1,103 @@
-use crate::experiments::{Experiment, Status};
+use crate::actions::{Action, ActionsCtx, CreateExperiment};
+use crate::experiments::{CrateSelect, Experiment, FollowUp, FollowUpKind, Status};
 use crate::prelude::*;
-use crate::report::{self, Comparison, TestResults};
+use crate::report::{self, Comparison, MergedPr, ReportJob, TestResults};
 use crate::results::DatabaseDB;
-use crate::server::messages::{Label, Message};
-use crate::server::{Data, GithubData};
+use crate::server::issues;
+use crate::server::messages::{self, Event, Label, Message};
+use crate::server::subscriptions::{self, SubscriberKind};
+use crate::server::{BotData, Data};
 use crate::utils;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, Thread};
 use std::time::Duration;
 
-use super::tokens::BucketRegion;
-
 // Automatically wake up the reports generator thread every 10 minutes to check for new jobs
 const AUTOMATIC_THREAD_WAKEUP: u64 = 600;
+const RUST_REPO: &str = "rust-lang/rust";
 
-fn generate_report(data: &Data, ex: &Experiment, results: &DatabaseDB) -> Fallible<TestResults> {
-    let mut config = aws_config::from_env();
-    match &data.tokens.reports_bucket.region {
-        BucketRegion::S3 { region } => {
-            config = config.region(aws_sdk_s3::config::Region::new(region.to_owned()));
-        }
-        BucketRegion::Custom { url } => {
-            config = config.region(aws_sdk_s3::config::Region::from_static("us-east-1"));
-            config = config.endpoint_url(url.clone());
+/// Pull requests merged into the beta since the stable release it's compared to, used to point
+/// at the likely causes of the regressions. They're only a hint, so failing to fetch them
+/// doesn't prevent the report from being generated.
+fn beta_changeset(ex: &Experiment, bot_data: Option<&BotData>) -> Option<Vec<MergedPr>> {
+    let bot_data = bot_data?;
+    let (stable, beta) = report::beta_changeset(&ex.rustc_versions)?;
+    match bot_data.api.compare_commits(RUST_REPO, stable, beta) {
+        Ok(commits) => Some(
+            commits
+                .iter()
+                .filter_map(|commit| MergedPr::from_merge_commit(&commit.message))
+                .collect(),
+        ),
+        Err(err) => {
+            warn!(
+                "failed to fetch the changes between {} and {}: {:?}",
+                stable, beta, err
+            );
+            None
         }
     }
-    config = config.credentials_provider(data.tokens.reports_bucket.to_aws_credentials());
-    let config = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?
-        .block_on(config.load());
-    let client = aws_sdk_s3::Client::new(&config);
+}
+
+fn generate_report(
+    data: &Data,
+    bot_data: Option<&BotData>,
+    ex: &Experiment,
+    resume: bool,
+) -> Fallible<TestResults> {
+    let client = data.tokens.reports_bucket.to_s3_client()?;
     let writer = report::S3Writer::create(
         client,
         data.tokens.reports_bucket.bucket.clone(),
         ex.name.clone(),
     )?;
 
-    let crates = ex.get_crates(&data.db)?;
-    let res = report::gen(results, ex, &crates, &writer, &data.config, false)?;
+    // Mirrors are optional, don't fail the report when one of them can't be set up
+    let mut mirrors = Vec::new();
+    for mirror in &data.tokens.report_mirrors {
+        match mirror.writer(&ex.name) {
+            Ok(writer) => mirrors.push(writer),
+            Err(err) => warn!("failed to set up a mirror of the report: {:?}", err),
+        }
+    }
+    let writer = report::MirroredWriter::new(writer, mirrors);
+
+    let job = if resume {
+        ReportJob::resume(&data.db, ex)?
+    } else {
+        ReportJob::start(&data.db, ex)?
+    };
+
+    // Agents may still be recording results of the experiment (for example when the report is
+    // generated before the experiment completes), read them all from a consistent snapshot. The
+    // snapshot is released before uploading the report, to let the WAL be checkpointed meanwhile.
+    let changeset = beta_changeset(ex, bot_data);
+    let (crates, report_data) = {
+        let snapshot = data.db.snapshot()?;
+        let results = DatabaseDB::new(&snapshot).with_log_store(data.log_store.as_deref());
+        let crates = ex.get_crates(&snapshot)?;
+        let report_data = report::read_report(
+            &results,
+            ex,
+            &crates,
+            &data.config,
+            Some(&job),
+            changeset.as_deref(),
+        )?;
+        (crates, report_data)
+    };
+    let results = DatabaseDB::new(&data.db).with_log_store(data.log_store.as_deref());
+    let res = report::write_report(
+        &results,
+        report_data,
+        ex,
+        &crates,
+        &writer,
+        &data.config,
+        false,
+        Some(&job),
+    )?;
+    job.finish()?;
 
     //remove metrics about completed experiments
     data.metrics.on_complete_experiment(&ex.name)?;
@@ -46,9 +105,92 @@ fn generate_report(data: &Data, ex: &Experiment, results: &DatabaseDB) -> Fallib
     Ok(res)
 }
 
-fn reports_thread(data: &Data, github_data: Option<&GithubData>) -> Fallible<()> {
+/// Definition of the follow-up experiment of `ex`, testing `crates` with the settings requested
+/// when `ex` was defined and inheriting the other ones.
+fn follow_up_definition(
+    ex: &Experiment,
+    follow_up: &FollowUp,
+    name: String,
+    crates: CrateSelect,
+) -> CreateExperiment {
+    let mut create = CreateExperiment::copy_of(ex, &name);
+    create.crates = crates;
+    create.follow_up = None;
+    create.parent = Some(ex.name.clone());
+    create.mode = follow_up.mode.unwrap_or(ex.mode);
+    create.cap_lints = follow_up.cap_lints.unwrap_or(ex.cap_lints);
+    create.priority = follow_up.priority.unwrap_or(ex.priority);
+    create.sandbox_image = follow_up
+        .sandbox_image
+        .clone()
+        .or_else(|| ex.sandbox_image.clone());
+    create
+}
+
+/// Creates the follow-up experiment requested when `ex` was defined, returning its name and
+/// the number of crates it will test.
+fn create_follow_up(
+    data: &Data,
+    ex: &Experiment,
+    res: &TestResults,
+) -> Fallible<Option<(String, usize)>> {
+    let follow_up = match &ex.follow_up {
+        Some(follow_up) => follow_up,
+        None => return Ok(None),
+    };
+    match follow_up.kind {
+        FollowUpKind::RetryRegressed => {}
+    }
+
+    // Experiments selecting exact versions retry the versions which regressed, instead of the
+    // latest ones
+    let (crates, count) = if ex.exact_versions {
+        let versions = report::regressed_crate_versions(res);
+        let count = versions.len();
+        (CrateSelect::Versions(versions), count)
+    } else {
+        let names = report::regressed_crate_names(res);
+        let count = names.len();
+        (CrateSelect::List(names), count)
+    };
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let mut name = format!("{}-retry", ex.name);
+    let mut counter = 1;
+    while Experiment::exists(&data.db, &name)? {
+        counter += 1;
+        name = format!("{}-retry-{counter}", ex.name);
+    }
+
+    follow_up_definition(ex, follow_up, name.clone(), crates)
+        .apply(&ActionsCtx::new(&data.db, &data.config))?;
+
+    if let Some(follow_up) = Experiment::get(&data.db, &name)? {
+        messages::notify(data, &follow_up, Event::ExperimentQueued);
+    }
+
+    Ok(Some((name, count)))
+}
+
+/// Mentions the GitHub users who subscribed to the experiment with the `notify` command.
+fn mention_subscribers(data: &Data, ex: &Experiment, message: Message) -> Fallible<Message> {
+    let users = subscriptions::subscribers(&data.db, &ex.name, SubscriberKind::GitHub)?;
+    if users.is_empty() {
+        return Ok(message);
+    }
+
+    let mentions = users
+        .iter()
+        .map(|user| format!("@{user}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok(message.line("bell", format!("cc {mentions}")))
+}
+
+fn reports_thread(data: &Data, bot_data: Option<&BotData>) -> Fallible<()> {
     let timeout = Duration::from_secs(AUTOMATIC_THREAD_WAKEUP);
-    let results = DatabaseDB::new(&data.db);
 
     loop {
         let mut ex = match Experiment::ready_for_report(&data.db)? {
@@ -62,31 +204,41 @@ fn reports_thread(data: &Data, github_data: Option<&GithubData>) -> Fallible<()>
         };
         let name = ex.name.clone();
 
-        info!("generating report for experiment {}...", name);
-        ex.set_status(&data.db, Status::GeneratingReport)?;
+        // Experiments still generating their report were interrupted by a restart of the server
+        let resume = ex.status == Status::GeneratingReport;
+        if resume {
+            info!("resuming the report generation of experiment {}...", name);
+        } else {
+            info!("generating report for experiment {}...", name);
+            ex.set_status(&data.db, Status::GeneratingReport)?;
+            messages::notify(data, &ex, Event::ExperimentCompleted);
+        }
 
-        match generate_report(data, &ex, &results) {
+        match generate_report(data, bot_data, &ex, resume) {
             Err(err) => {
                 ex.set_status(&data.db, Status::ReportFailed)?;
                 error!("failed to generate the report of {}", name);
                 utils::report_failure(&err);
+                messages::notify(data, &ex, Event::ReportFailed);
 
-                if let Some(github_data) = github_data {
+                if let Some(bot_data) = bot_data {
                     if let Some(ref github_issue) = ex.github_issue {
-                        Message::new()
-                        .line(
-                            "rotating_light",
-                            format!("Report generation of **`{name}`** failed: {err}"),
-                        )
-                        .line(
-                            "hammer_and_wrench",
-                            "If the error is fixed use the `retry-report` command.",
-                        )
-                        .note(
-                            "sos",
-                            "Can someone from the infra team check in on this? @rust-lang/infra",
-                        )
-                        .send(&github_issue.api_url, data, github_data)?;
+                        let message = Message::new()
+                            .line(
+                                "rotating_light",
+                                format!("Report generation of **`{name}`** failed: {err}"),
+                            )
+                            .line(
+                                "hammer_and_wrench",
+                                "If the error is fixed use the `retry-report` command.",
+                            );
+                        mention_subscribers(data, &ex, message)?
+                            .note(
+                                "sos",
+                                "Can someone from the infra team check in on this? \
+                                 @rust-lang/infra",
+                            )
+                            .send(&github_issue.api_url, data, bot_data)?;
                     }
                 }
 
@@ -99,19 +251,30 @@ fn reports_thread(data: &Data, github_data: Option<&GithubData>) -> Fallible<()>
                     .public_url
                     .replace("{bucket}", &data.tokens.reports_bucket.bucket);
                 let report_url = format!("{base_url}/{name}/index.html");
+                let results = DatabaseDB::new(&data.db).with_log_store(data.log_store.as_deref());
 
                 ex.set_status(&data.db, Status::Completed)?;
                 ex.set_report_url(&data.db, &report_url)?;
                 info!("report for the experiment {} generated successfully!", name);
+                messages::notify(data, &ex, Event::ReportReady);
 
-                let (regressed, fixed) = (
-                    res.info.get(&Comparison::Regressed).unwrap_or(&0),
-                    res.info.get(&Comparison::Fixed).unwrap_or(&0),
-                );
+                let follow_up = match create_follow_up(data, &ex, &res) {
+                    Ok(follow_up) => follow_up,
+                    Err(err) => {
+                        error!("failed to create the follow-up experiment of {}", name);
+                        utils::report_failure(&err);
+                        None
+                    }
+                };
 
-                if let Some(github_data) = github_data {
+                if let Some(bot_data) = bot_data {
                     if let Some(ref github_issue) = ex.github_issue {
-                        Message::new()
+                        let (regressed, fixed) = (
+                            res.info.get(&Comparison::Regressed).unwrap_or(&0),
+                            res.info.get(&Comparison::Fixed).unwrap_or(&0),
+                        );
+                        // The first lines keep the same format, for the bots parsing them
+                        let mut message = Message::new()
                             .line("tada", format!("Experiment **`{name}`** is completed!"))
                             .line(
                                 "bar_chart",
@@ -122,10 +285,37 @@ fn reports_thread(data: &Data, github_data: Option<&GithubData>) -> Fallible<()>
                                     res.info.values().sum::<u32>(),
                                 ),
                             )
+                            .line(
+                                "memo",
+                                format!("**TL;DR:** {}.", report::summary_tldr(&res)),
+                            )
                             .line(
                                 "newspaper",
                                 format!("[Open the full report]({report_url})."),
-                            )
+                            );
+                        let skipped = results.count_skipped_past_deadline(&ex)?;
+                        if skipped > 0 {
+                            message = message.line(
+                                "hourglass",
+                                format!(
+                                    "The deadline of the experiment passed before {skipped} \
+                                     crates were tested, this report is partial."
+                                ),
+                            );
+                        }
+                        if let Some((follow_up, count)) = follow_up {
+                            message = message.line(
+                                "repeat",
+                                format!(
+                                    "Follow-up experiment **`{follow_up}`** created and queued \
+                                     to retry the {count} regressed crates."
+                                ),
+                            );
+                        }
+                        for section in report::summary_sections(&res, &report_url) {
+                            message = message.section(section);
+                        }
+                        mention_subscribers(data, &ex, message)?
                             .note(
                                 "warning",
                                 format!(
@@ -135,7 +325,17 @@ fn reports_thread(data: &Data, github_data: Option<&GithubData>) -> Fallible<()>
                                 ),
                             )
                             .set_label(Label::ExperimentCompleted)
-                            .send(&github_issue.api_url, data, github_data)?;
+                            .send(&github_issue.api_url, data, bot_data)?;
+                    }
+
+                    if let Err(err) =
+                        issues::file_issues(data, bot_data, &ex, &results, &res, &report_url)
+                    {
+                        error!(
+                            "failed to file the issues about the regressions of {}",
+                            name
+                        );
+                        utils::report_failure(&err);
                     }
                 }
             }
@@ -151,9 +351,9 @@ impl ReportsWorker {
         ReportsWorker(Arc::new(Mutex::new(None)))
     }
 
-    pub fn spawn(&self, data: Data, github_data: Option<GithubData>) {
+    pub fn spawn(&self, data: Data, bot_data: Option<BotData>) {
         let joiner = thread::spawn(move || loop {
-            let result = reports_thread(&data.clone(), github_data.as_ref())
+            let result = reports_thread(&data.clone(), bot_data.as_ref())
                 .with_context(|| "the reports generator thread crashed");
             if let Err(e) = result {
                 utils::report_failure(&e);
@@ -171,3 +371,51 @@ impl ReportsWorker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::follow_up_definition;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{CapLints, CrateSelect, Experiment, Mode};
+
+    #[test]
+    fn test_follow_up_settings() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut create = CreateExperiment::dummy("foo");
+        create.priority = 5;
+        create.follow_up = Some(
+            "retry-regressed:mode=check-only,cap-lints=warn"
+                .parse()
+                .unwrap(),
+        );
+        create.apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        let crates = CrateSelect::List(std::iter::once("lazy_static".to_string()).collect());
+        let follow_up = follow_up_definition(
+            &ex,
+            ex.follow_up.as_ref().unwrap(),
+            "foo-retry".into(),
+            crates,
+        );
+
+        // The requested settings are overridden, the other ones are inherited
+        assert_eq!(follow_up.name, "foo-retry");
+        assert_eq!(follow_up.mode, Mode::CheckOnly);
+        assert_eq!(follow_up.cap_lints, CapLints::Warn);
+        assert_eq!(follow_up.priority, 5);
+        assert_eq!(follow_up.toolchains, ex.toolchains);
+        assert_eq!(follow_up.parent.as_deref(), Some("foo"));
+        assert!(follow_up.follow_up.is_none());
+        match follow_up.crates {
+            CrateSelect::List(list) => assert!(list.contains("lazy_static")),
+            _ => panic!("the follow-up should test the regressed crates"),
+        }
+    }
+}
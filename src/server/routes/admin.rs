@@ -0,0 +1,662 @@
+use crate::actions::{Action, ActionsCtx, DeleteExperiment, ExperimentError};
+use crate::crates::quarantine::{self, QuarantinedCrate};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::server::api_types::ApiResponse;
+use crate::server::audit::{self, AuditEntry};
+use crate::server::auth::{auth_filter, AuthDetails, TokenType};
+use crate::server::cronjobs;
+use crate::server::hooks::{self, Hook};
+use crate::server::maintenance;
+use crate::server::tokens::AdminScope;
+use crate::server::{BotData, Data, HttpError};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+use warp::{Filter, Rejection};
+
+/// Number of audit log entries returned when no limit is provided.
+const DEFAULT_AUDIT_LOG_LIMIT: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct PriorityUpdate {
+    priority: i32,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceUpdate {
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct QuarantineReview {
+    #[serde(rename = "crate")]
+    krate: String,
+}
+
+#[derive(Deserialize)]
+pub struct HookRegistration {
+    url: String,
+    secret: String,
+    experiment: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditLogQuery {
+    limit: Option<u32>,
+}
+
+pub fn routes(
+    data: Arc<Data>,
+    bot_data: Option<Arc<BotData>>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_cloned = data.clone();
+    let data_filter = warp::any().map(move || data_cloned.clone());
+    let bot_data_filter = warp::any().map(move || bot_data.clone());
+
+    let delete_experiment = warp::delete()
+        .and(warp::path("ex"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::DeleteExperiments),
+        ))
+        .map(endpoint_delete_experiment);
+
+    let set_priority = warp::post()
+        .and(warp::path("ex"))
+        .and(warp::path::param())
+        .and(warp::path("priority"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::EditExperiments),
+        ))
+        .map(endpoint_set_priority);
+
+    let reload_acl = warp::post()
+        .and(warp::path("reload-acl"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(bot_data_filter)
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::ReloadAcl),
+        ))
+        .map(endpoint_reload_acl);
+
+    let enable_maintenance = warp::put()
+        .and(warp::path("maintenance"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Maintenance),
+        ))
+        .map(endpoint_enable_maintenance);
+
+    let disable_maintenance = warp::delete()
+        .and(warp::path("maintenance"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Maintenance),
+        ))
+        .map(endpoint_disable_maintenance);
+
+    let list_quarantine = warp::get()
+        .and(warp::path("quarantine"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Quarantine),
+        ))
+        .map(endpoint_list_quarantine);
+
+    let confirm_quarantine = warp::post()
+        .and(warp::path("quarantine"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Quarantine),
+        ))
+        .map(endpoint_confirm_quarantine);
+
+    let release_quarantine = warp::delete()
+        .and(warp::path("quarantine"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Quarantine),
+        ))
+        .map(endpoint_release_quarantine);
+
+    let list_webhooks = warp::get()
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Webhooks),
+        ))
+        .map(endpoint_list_webhooks);
+
+    let register_webhook = warp::post()
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Webhooks),
+        ))
+        .map(endpoint_register_webhook);
+
+    let remove_webhook = warp::delete()
+        .and(warp::path("webhooks"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::Webhooks),
+        ))
+        .map(endpoint_remove_webhook);
+
+    let refresh_lists = warp::post()
+        .and(warp::path("crate-lists"))
+        .and(warp::path("refresh"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(
+            data.clone(),
+            TokenType::Admin(AdminScope::RefreshLists),
+        ))
+        .map(endpoint_refresh_lists);
+
+    let audit_log = warp::get()
+        .and(warp::path("audit-log"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter)
+        .and(auth_filter(
+            data,
+            TokenType::Admin(AdminScope::ReadAuditLog),
+        ))
+        .map(endpoint_audit_log);
+
+    warp::any()
+        .and(
+            delete_experiment
+                .or(set_priority)
+                .unify()
+                .or(reload_acl)
+                .unify()
+                .or(enable_maintenance)
+                .unify()
+                .or(disable_maintenance)
+                .unify()
+                .or(list_quarantine)
+                .unify()
+                .or(confirm_quarantine)
+                .unify()
+                .or(release_quarantine)
+                .unify()
+                .or(list_webhooks)
+                .unify()
+                .or(register_webhook)
+                .unify()
+                .or(remove_webhook)
+                .unify()
+                .or(refresh_lists)
+                .unify()
+                .or(audit_log)
+                .unify(),
+        )
+        .map(handle_results)
+        .recover(handle_errors)
+        .unify()
+}
+
+fn endpoint_delete_experiment(
+    name: String,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    DeleteExperiment { name }.apply(
+        &ActionsCtx::new(db, &data.config)
+            .with_log_store(data.log_store.as_deref())
+            .with_audit(&auth.name),
+    )?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_set_priority(
+    name: String,
+    update: PriorityUpdate,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let mut ex = Experiment::get(db, &name)?.ok_or(HttpError::NotFound)?;
+    let old = ex.priority;
+    db.transaction(true, |t| {
+        ex.set_priority(t, update.priority)?;
+        audit::record(
+            t,
+            &auth.name,
+            "set-priority",
+            Some(name.as_str()),
+            Some(&format!("{old} -> {}", update.priority)),
+        )
+    })?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_reload_acl(
+    data: Arc<Data>,
+    bot_data: Option<Arc<BotData>>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let bot_data = bot_data.ok_or_else(|| anyhow!("the GitHub bot is not configured"))?;
+    data.acl.refresh_cache(&*bot_data.api)?;
+    audit::record(db, &auth.name, "reload-acl", None, None)?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_refresh_lists(data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let result = cronjobs::spawn_lists_update(data.clone(), || {
+        audit::record(db, &auth.name, "refresh-lists", None, None)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_enable_maintenance(
+    update: MaintenanceUpdate,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    db.transaction(true, |t| {
+        maintenance::enable(t, &update.message, &auth.name)?;
+        audit::record(
+            t,
+            &auth.name,
+            "enable-maintenance",
+            None,
+            Some(&update.message),
+        )
+    })?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_disable_maintenance(data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let result = db.transaction(true, |t| {
+        let result = maintenance::disable(t, &auth.name)?;
+        audit::record(t, &auth.name, "disable-maintenance", None, None)?;
+        Ok(result)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_list_quarantine(data: Arc<Data>, _auth: AuthDetails) -> Fallible<Response<Body>> {
+    let result: Vec<QuarantinedCrate> = quarantine::list(&data.db)?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_confirm_quarantine(
+    review: QuarantineReview,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let result = db.transaction(true, |t| {
+        let result = quarantine::confirm(t, &review.krate)?;
+        audit::record(
+            t,
+            &auth.name,
+            "confirm-quarantine",
+            None,
+            Some(&review.krate),
+        )?;
+        Ok(result)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_release_quarantine(
+    review: QuarantineReview,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let result = db.transaction(true, |t| {
+        let result = quarantine::release(t, &data.config, &review.krate)?;
+        audit::record(
+            t,
+            &auth.name,
+            "release-quarantine",
+            None,
+            Some(&review.krate),
+        )?;
+        Ok(result)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_list_webhooks(data: Arc<Data>, _auth: AuthDetails) -> Fallible<Response<Body>> {
+    let result: Vec<Hook> = hooks::list(&data.db)?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_register_webhook(
+    registration: HookRegistration,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    if let Some(name) = &registration.experiment {
+        if !Experiment::exists(db, name)? {
+            return Err(HttpError::NotFound.into());
+        }
+    }
+    let result = db.transaction(true, |t| {
+        let result = hooks::register(
+            t,
+            &registration.url,
+            &registration.secret,
+            registration.experiment.as_deref(),
+            &auth.name,
+        )?;
+        audit::record(
+            t,
+            &auth.name,
+            "register-webhook",
+            registration.experiment.as_deref(),
+            Some(&format!("{result}: {}", registration.url)),
+        )?;
+        Ok(result)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_remove_webhook(
+    id: i64,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let result = db.transaction(true, |t| {
+        let result = hooks::remove(t, id)?;
+        audit::record(t, &auth.name, "remove-webhook", None, Some(&id.to_string()))?;
+        Ok(result)
+    })?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_audit_log(
+    query: AuditLogQuery,
+    data: Arc<Data>,
+    _auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let result: Vec<AuditEntry> =
+        audit::recent(&data.db, query.limit.unwrap_or(DEFAULT_AUDIT_LOG_LIMIT))?;
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => {
+            if let Some(HttpError::NotFound) = err.downcast_ref() {
+                return ApiResponse::not_found().into_response().unwrap();
+            }
+            if let Some(HttpError::ReadOnly) = err.downcast_ref() {
+                return ApiResponse::read_only().into_response().unwrap();
+            }
+            if let Some(HttpError::BadRequest) = err.downcast_ref() {
+                return ApiResponse::bad_request(err.to_string())
+                    .into_response()
+                    .unwrap();
+            }
+            if let Some(ExperimentError::NotFound(_)) = err.downcast_ref() {
+                return ApiResponse::not_found().into_response().unwrap();
+            }
+            ApiResponse::internal_error(err.to_string())
+                .into_response()
+                .unwrap()
+        }
+    }
+}
+
+async fn handle_errors(err: Rejection) -> Result<Response<Body>, Rejection> {
+    match err.find::<HttpError>() {
+        Some(HttpError::NotFound) => Ok(ApiResponse::not_found().into_response().unwrap()),
+        Some(HttpError::Forbidden) => Ok(ApiResponse::unauthorized().into_response().unwrap()),
+        Some(HttpError::ReadOnly) => Ok(ApiResponse::read_only().into_response().unwrap()),
+        Some(HttpError::BadRequest) => Ok(ApiResponse::bad_request("bad request".into())
+            .into_response()
+            .unwrap()),
+        None => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::experiments::Experiment;
+    use crate::server::audit;
+    use crate::server::maintenance;
+    use crate::server::tokens::{AdminScope, AdminToken, Tokens};
+    use crate::server::Data;
+    use http::{Response, StatusCode};
+    use hyper::body::Bytes;
+    use std::sync::Arc;
+
+    fn data() -> Arc<Data> {
+        let mut tokens = Tokens::default();
+        for (token, name, scopes) in [
+            (
+                "editor",
+                "alice",
+                vec![AdminScope::EditExperiments, AdminScope::Maintenance],
+            ),
+            (
+                "auditor",
+                "bob",
+                vec![AdminScope::ReadAuditLog, AdminScope::Quarantine],
+            ),
+        ] {
+            tokens.admins.insert(
+                token.to_string(),
+                AdminToken {
+                    name: name.to_string(),
+                    scopes,
+                },
+            );
+        }
+        let data = Data::temp(tokens).unwrap();
+
+        crate::crates::lists::setup_test_lists(&data.db, &data.config).unwrap();
+        CreateExperiment::dummy("foo")
+            .apply(&ActionsCtx::new(&data.db, &data.config))
+            .unwrap();
+        Arc::new(data)
+    }
+
+    fn request(
+        data: &Arc<Data>,
+        method: &str,
+        path: &str,
+        token: &str,
+        body: Option<&str>,
+    ) -> Response<Bytes> {
+        let mut request = warp::test::request()
+            .method(method)
+            .path(path)
+            .header("Authorization", format!("CraterToken {token}"));
+        if let Some(body) = body {
+            request = request
+                .header("Content-Type", "application/json")
+                .body(body);
+        }
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(request.reply(&routes(data.clone(), None)))
+    }
+
+    #[test]
+    fn test_set_priority() {
+        let data = data();
+        let body = Some(r#"{"priority": 10}"#);
+
+        let resp = request(&data, "POST", "/ex/foo/priority", "auditor", body);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = request(&data, "POST", "/ex/foo/priority", "unknown", body);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(audit::recent(&data.db, 10).unwrap().is_empty());
+
+        let resp = request(&data, "POST", "/ex/foo/priority", "editor", body);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let ex = Experiment::get(&data.db, "foo").unwrap().unwrap();
+        assert_eq!(ex.priority, 10);
+
+        let log = audit::recent(&data.db, 10).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].author, "alice");
+        assert_eq!(log[0].action, "set-priority");
+        assert_eq!(log[0].experiment.as_deref(), Some("foo"));
+        assert_eq!(log[0].details.as_deref(), Some("0 -> 10"));
+
+        let resp = request(&data, "POST", "/ex/missing/priority", "editor", body);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(audit::recent(&data.db, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut data = Arc::try_unwrap(data()).ok().unwrap();
+        data.writable_db = None;
+        let data = Arc::new(data);
+
+        let resp = request(
+            &data,
+            "POST",
+            "/ex/foo/priority",
+            "editor",
+            Some(r#"{"priority": 10}"#),
+        );
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let ex = Experiment::get(&data.db, "foo").unwrap().unwrap();
+        assert_eq!(ex.priority, 0);
+        assert!(audit::recent(&data.db, 10).unwrap().is_empty());
+
+        // Reading is still allowed
+        let resp = request(&data, "GET", "/audit-log", "auditor", None);
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let data = data();
+        let body = Some(r#"{"message": "upgrading the server"}"#);
+
+        let resp = request(&data, "PUT", "/maintenance", "auditor", body);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(!maintenance::is_enabled(&data.db).unwrap());
+
+        let resp = request(&data, "PUT", "/maintenance", "editor", body);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(maintenance::is_enabled(&data.db).unwrap());
+
+        let resp = request(&data, "DELETE", "/maintenance", "auditor", None);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(maintenance::is_enabled(&data.db).unwrap());
+
+        let resp = request(&data, "DELETE", "/maintenance", "editor", None);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!maintenance::is_enabled(&data.db).unwrap());
+
+        let log = audit::recent(&data.db, 10).unwrap();
+        let actions: Vec<_> = log.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, ["disable-maintenance", "enable-maintenance"]);
+        assert!(log.iter().all(|e| e.author == "alice"));
+        assert_eq!(log[1].details.as_deref(), Some("upgrading the server"));
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let data = data();
+        let body = Some(r#"{"crate": "reg/lazy_static/0.1.0"}"#);
+
+        let resp = request(&data, "GET", "/quarantine", "editor", None);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = request(&data, "GET", "/quarantine", "auditor", None);
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = request(&data, "POST", "/quarantine", "editor", body);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = request(&data, "DELETE", "/quarantine", "editor", body);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(audit::recent(&data.db, 10).unwrap().is_empty());
+
+        let resp = request(&data, "POST", "/quarantine", "auditor", body);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = request(&data, "DELETE", "/quarantine", "auditor", body);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let log = audit::recent(&data.db, 10).unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|e| e.author == "bob"));
+        assert_eq!(log[0].action, "release-quarantine");
+        assert_eq!(log[1].action, "confirm-quarantine");
+        assert_eq!(log[0].details.as_deref(), Some("reg/lazy_static/0.1.0"));
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let data = data();
+        audit::record(&data.db, "alice", "set-priority", Some("foo"), None).unwrap();
+        audit::record(&data.db, "alice", "enable-maintenance", None, None).unwrap();
+
+        let resp = request(&data, "GET", "/audit-log", "editor", None);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = request(&data, "GET", "/audit-log?limit=1", "auditor", None);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        let result = body["result"].as_array().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0]["action"], "enable-maintenance");
+    }
+}
@@ -1,84 +1,185 @@
 use crate::agent::Capabilities;
-use crate::experiments::{Assignee, Experiment};
+use crate::config::Config;
+use crate::experiments::{Assignee, Experiment, Status};
 use crate::prelude::*;
-use crate::results::{DatabaseDB, EncodingType, ProgressData};
+use crate::report::compare;
+use crate::results::{DatabaseDB, EncodingType, LogStore, ProgressData, ReadResults};
 use crate::server::agents::WorkerInfo;
-use crate::server::api_types::{AgentConfig, ApiResponse};
-use crate::server::auth::{auth_filter, AuthDetails};
-use crate::server::messages::Message;
-use crate::server::{Data, GithubData, HttpError};
-use crossbeam_channel::Sender;
+use crate::server::api_encoding::{Encoding, MAX_DECODED_SIZE};
+use crate::server::api_types::{
+    AgentConfig, ApiResponse, CompleteUpload, IDEMPOTENCY_KEY, UPLOAD_CHUNK_SIZE, UPLOAD_ID,
+    UPLOAD_OFFSET,
+};
+use crate::server::auth::{auth_filter, AuthDetails, TokenType};
+use crate::server::messages::{self, Event, Message};
+use crate::server::uploads::UploadError;
+use crate::server::{idempotency, maintenance};
+use crate::server::{shadow_runs, BotData, Data, HttpError};
+use crate::toolchain::RustcVersion;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
 use http::Response;
 use hyper::Body;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::sync::{Arc, Condvar, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use warp::{Filter, Rejection};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct ExperimentData<T> {
-    experiment_name: String,
+    pub(in crate::server) experiment_name: String,
     #[serde(flatten)]
-    data: T,
+    pub(in crate::server) data: T,
+}
+
+/// Rejection of the request bodies which couldn't be decoded.
+#[derive(Debug)]
+struct InvalidBody(String);
+
+impl warp::reject::Reject for InvalidBody {}
+
+/// Maximum decompressed size of the request bodies, except for the results of the builds.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+/// Time the uploads wait for their result to be recorded before asking the agent to retry,
+/// shorter than the 30 seconds after which the HTTP client of the agents gives up.
+const RECORD_PROGRESS_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Request body, decoded according to its `Content-Type` and `Content-Encoding` headers and
+/// rejected if it's larger than `max_size` once decompressed. The filter has to come after the
+/// authentication, so that only agents can make the server decompress bodies.
+fn body<T: DeserializeOwned + Send>(
+    max_size: u64,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(CONTENT_TYPE.as_str())
+        .and(warp::header::optional::<String>(CONTENT_ENCODING.as_str()))
+        .and(warp::body::bytes())
+        .and_then(move |content_type, content_encoding, body| {
+            decode_body(content_type, content_encoding, body, max_size)
+        })
+}
+
+async fn decode_body<T: DeserializeOwned>(
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    body: Bytes,
+    max_size: u64,
+) -> Result<T, Rejection> {
+    Encoding::from_headers(content_type.as_deref(), content_encoding.as_deref())
+        .map_err(anyhow::Error::from)
+        .and_then(|encoding| encoding.decode_limited(&body, max_size))
+        .map_err(|err| warp::reject::custom(InvalidBody(format!("{err:#}"))))
+}
+
+/// Encoding of the responses, negotiated from the `Accept` and `Accept-Encoding` headers.
+fn accepted_encoding() -> impl Filter<Extract = (Encoding,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(ACCEPT.as_str())
+        .and(warp::header::optional::<String>(ACCEPT_ENCODING.as_str()))
+        .map(|accept: Option<String>, accept_encoding: Option<String>| {
+            Encoding::negotiate(accept.as_deref(), accept_encoding.as_deref())
+        })
 }
 
 pub fn routes(
     data: Arc<Data>,
     mutex: Arc<Mutex<Data>>,
-    github_data: Option<Arc<GithubData>>,
+    bot_data: Option<Arc<BotData>>,
 ) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
     let data_cloned = data.clone();
     let data_filter = warp::any().map(move || data_cloned.clone());
     let mutex_filter = warp::any().map(move || mutex.clone());
-    let github_data_filter = warp::any().map(move || github_data.clone());
+    let bot_data_filter = warp::any().map(move || bot_data.clone());
 
     let config = warp::post()
         .and(warp::path("config"))
         .and(warp::path::end())
-        .and(warp::body::json())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
+        .and(accepted_encoding())
         .and(data_filter.clone())
-        .and(auth_filter(data.clone()))
         .map(endpoint_config);
 
     let next_experiment = warp::post()
         .and(warp::path("next-experiment"))
         .and(warp::path::end())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(accepted_encoding())
         .and(mutex_filter.clone())
-        .and(github_data_filter)
-        .and(auth_filter(data.clone()))
+        .and(bot_data_filter.clone())
         .map(endpoint_next_experiment);
 
     let next_crate = warp::post()
         .and(warp::path("next-crate"))
         .and(warp::path::end())
-        .and(warp::body::json())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
+        .and(accepted_encoding())
         .and(data_filter.clone())
-        .and(auth_filter(data.clone()))
         .map(endpoint_next_crate);
 
     let record_progress = warp::post()
         .and(warp::path("record-progress"))
         .and(warp::path::end())
-        .and(warp::body::json())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_DECODED_SIZE))
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY))
         .and(data_filter.clone())
-        .and(auth_filter(data.clone()))
         .map(endpoint_record_progress);
 
+    let upload_chunk = warp::post()
+        .and(warp::path("upload-chunk"))
+        .and(warp::path::end())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(warp::header::<String>(UPLOAD_ID))
+        .and(warp::header::<u64>(UPLOAD_OFFSET))
+        .and(warp::body::content_length_limit(UPLOAD_CHUNK_SIZE as u64))
+        .and(warp::body::bytes())
+        .and(data_filter.clone())
+        .map(endpoint_upload_chunk);
+
+    let complete_upload = warp::post()
+        .and(warp::path("complete-upload"))
+        .and(warp::path::end())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        // Only the metadata of the upload is in the body, the results were sent in chunks
+        .and(body(MAX_BODY_SIZE))
+        .and(data_filter.clone())
+        .map(endpoint_complete_upload);
+
+    let rustc_versions = warp::post()
+        .and(warp::path("rustc-versions"))
+        .and(warp::path::end())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
+        .and(data_filter.clone())
+        .map(endpoint_rustc_versions);
+
+    let experiment_aborted = warp::post()
+        .and(warp::path("experiment-aborted"))
+        .and(warp::path::end())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
+        .and(data_filter.clone())
+        .map(endpoint_experiment_aborted);
+
     let heartbeat = warp::post()
         .and(warp::path("heartbeat"))
         .and(warp::path::end())
-        .and(warp::body::json())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
         .and(data_filter)
-        .and(auth_filter(data.clone()))
         .map(endpoint_heartbeat);
 
     let error = warp::post()
         .and(warp::path("error"))
         .and(warp::path::end())
-        .and(warp::body::json())
+        .and(auth_filter(data, TokenType::Agent))
+        .and(body(MAX_BODY_SIZE))
         .and(mutex_filter)
-        .and(auth_filter(data))
+        .and(bot_data_filter)
         .map(endpoint_error);
 
     warp::any()
@@ -90,6 +191,14 @@ pub fn routes(
                 .unify()
                 .or(record_progress)
                 .unify()
+                .or(upload_chunk)
+                .unify()
+                .or(complete_upload)
+                .unify()
+                .or(rustc_versions)
+                .unify()
+                .or(experiment_aborted)
+                .unify()
                 .or(heartbeat)
                 .unify()
                 .or(error)
@@ -101,10 +210,12 @@ pub fn routes(
 }
 
 fn endpoint_config(
+    auth: AuthDetails,
     caps: Capabilities,
+    encoding: Encoding,
     data: Arc<Data>,
-    auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
+    data.writable_db()?;
     data.agents.add_capabilities(&auth.name, &caps)?;
 
     Ok(ApiResponse::Success {
@@ -113,27 +224,37 @@ fn endpoint_config(
             crater_config: data.config.clone(),
         },
     }
-    .into_response()?)
+    .into_response_with(encoding)?)
 }
 
 fn endpoint_next_experiment(
-    mutex: Arc<Mutex<Data>>,
-    github_data: Option<Arc<GithubData>>,
     auth: AuthDetails,
+    encoding: Encoding,
+    mutex: Arc<Mutex<Data>>,
+    bot_data: Option<Arc<BotData>>,
 ) -> Fallible<Response<Body>> {
     //we need to make sure that Experiment::next executes uninterrupted
     let data = mutex.lock().unwrap();
-    let next = Experiment::next(&data.db, &Assignee::Agent(auth.name))?;
+    let db = data.writable_db()?;
+    // Agents keep waiting for new experiments until the maintenance ends
+    if maintenance::is_enabled(db)? {
+        return Ok(ApiResponse::Success {
+            result: None::<Experiment>,
+        }
+        .into_response_with(encoding)?);
+    }
+    let next = Experiment::next(db, &Assignee::Agent(auth.name))?;
     let result = if let Some((new, ex)) = next {
         if new {
-            if let Some(github_data) = github_data.as_ref() {
+            messages::notify_in_background(&data, &ex, Event::ExperimentStarted);
+            if let Some(bot_data) = bot_data.as_ref() {
                 if let Some(ref github_issue) = ex.github_issue {
                     Message::new()
                         .line(
                             "construction",
                             format!("Experiment **`{}`** is now **running**", ex.name,),
                         )
-                        .send(&github_issue.api_url, &data, github_data)?;
+                        .send(&github_issue.api_url, &data, bot_data)?;
                 }
             }
         }
@@ -143,62 +264,91 @@ fn endpoint_next_experiment(
         None
     };
 
-    Ok(ApiResponse::Success { result }.into_response()?)
+    Ok(ApiResponse::Success { result }.into_response_with(encoding)?)
+}
+
+/// Next crate of the experiment never handed out, or not reported within the timeout.
+fn next_queued_crate(ex: &Experiment, data: &Data) -> Fallible<Option<crate::crates::Crate>> {
+    while let Some(next) = data.uncompleted_cache.lock().unwrap().pop_front() {
+        if next.0.elapsed() <= std::time::Duration::from_secs(120) {
+            return Ok(Some(next.1));
+        }
+    }
+
+    // The slowest crates of the batch come first
+    let mut crates = ex.next_batch(&data.db, &data.config)?.into_iter();
+    let ret = crates.next();
+    let now = std::time::Instant::now();
+    data.uncompleted_cache
+        .lock()
+        .unwrap()
+        .extend(crates.map(|c| (now, c)));
+    Ok(ret)
 }
 
 fn endpoint_next_crate_inner(
     experiment: String,
     data: Arc<Data>,
+    agent: &str,
 ) -> Fallible<Option<crate::crates::Crate>> {
-    let result: Option<crate::crates::Crate> =
-        if let Some(ex) = Experiment::get(&data.db, &experiment)? {
-            while let Some(next) = data.uncompleted_cache.lock().unwrap().pop_front() {
-                if next.0.elapsed() <= std::time::Duration::from_secs(120) {
-                    return Ok(Some(next.1));
-                }
-            }
+    let db = data.writable_db()?;
+    // Without new crates the workers stop once their current build is done
+    if maintenance::is_enabled(db)? {
+        return Ok(None);
+    }
+    let Some(ex) = Experiment::get(db, &experiment)? else {
+        return Ok(None);
+    };
 
-            let mut crates = ex.get_uncompleted_crates(&data.db, Some(1000))?;
-            if crates.is_empty() {
-                None
-            } else {
-                let now = std::time::Instant::now();
-                let ret = crates.pop().unwrap();
-                data.uncompleted_cache
-                    .lock()
-                    .unwrap()
-                    .extend(crates.into_iter().map(|c| (now, c)));
-                Some(ret)
-            }
-        } else {
-            None
-        };
+    // The remaining crates are skipped by the deadlines cronjob
+    if ex.deadline_passed() {
+        return Ok(None);
+    }
+
+    // Shadow runs are handed out first, as they're only useful while the experiment runs
+    if let Some(krate) = shadow_runs::next_crate(db, &data.config, &ex, agent)? {
+        return Ok(Some(krate));
+    }
+
+    let result = next_queued_crate(&ex, &data)?;
+    if let Some(krate) = &result {
+        ex.record_crate_assignment(db, krate, &Assignee::Agent(agent.to_string()))?;
+    }
 
     Ok(result)
 }
 
 fn endpoint_next_crate(
+    auth: AuthDetails,
     experiment: String,
+    encoding: Encoding,
     data: Arc<Data>,
-    _auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
     Ok(ApiResponse::Success {
-        result: endpoint_next_crate_inner(experiment, data)?,
+        result: endpoint_next_crate_inner(experiment, data, &auth.name)?,
     }
-    .into_response()?)
+    .into_response_with(encoding)?)
 }
 
 #[derive(Clone)]
 pub struct RecordProgressThread {
-    // String is the worker name
-    queue: Sender<ExperimentData<ProgressData>>,
+    // String is the worker name, followed by the idempotency key of the upload and the channel
+    // notified once the result is recorded
+    queue: Sender<(
+        ExperimentData<ProgressData>,
+        String,
+        Option<String>,
+        Sender<Fallible<()>>,
+    )>,
     in_flight_requests: Arc<(Mutex<usize>, Condvar)>,
 }
 
 impl RecordProgressThread {
     pub fn new(
         db: crate::db::Database,
+        config: Config,
         metrics: crate::server::metrics::Metrics,
+        log_store: Option<Arc<dyn LogStore>>,
     ) -> RecordProgressThread {
         // 64 message queue, after which we start load shedding automatically.
         let (tx, rx) = crossbeam_channel::bounded(64);
@@ -215,26 +365,82 @@ impl RecordProgressThread {
                 // Panics should already be logged and otherwise there's not much we
                 // can/should do.
                 let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    let result = rx.recv().unwrap();
+                    let (result, agent, idempotency_key, recorded) = rx.recv().unwrap();
                     this.block_until_idle();
 
                     let start = std::time::Instant::now();
+                    let _span = info_span!(
+                        "record_progress",
+                        experiment = %result.experiment_name,
+                        krate = %result.data.result.krate,
+                        toolchain = %result.data.result.toolchain,
+                        agent = %agent,
+                    )
+                    .entered();
 
                     if let Some(ex) = Experiment::get(&db, &result.experiment_name).unwrap() {
-                        let db = DatabaseDB::new(&db);
-                        if let Err(e) = db.store(&ex, &result.data, EncodingType::Plain) {
-                            // Failing to record a result is basically fine -- this
-                            // just means that we'll have to re-try this job.
-                            log::error!("Failed to store result into database: {:?}", e);
-                            crate::utils::report_failure(&e);
-                        }
+                        // Results which can't be identified as shadow runs are stored as usual
+                        let shadow = shadow_runs::record(&db, &ex, &result.data, &agent)
+                            .unwrap_or_else(|e| {
+                                error!("Failed to record the shadow run: {:?}", e);
+                                crate::utils::report_failure(&e);
+                                None
+                            });
+                        let db = DatabaseDB::new(&db)
+                            .with_log_store(log_store.as_deref())
+                            .with_agent(&agent)
+                            .with_idempotency_key(idempotency_key.as_deref());
+                        let stored = if let Some(shadow) = shadow {
+                            // The results of shadow runs are only compared with the original ones
+                            if !shadow.matches {
+                                warn!(
+                                    "shadow run of {} on {} by {} doesn't match the result of {}",
+                                    result.data.result.krate,
+                                    result.data.result.toolchain,
+                                    agent,
+                                    shadow.original_agent
+                                );
+                            }
+                            metrics.record_shadow_run(
+                                &shadow.original_agent,
+                                &agent,
+                                shadow.matches,
+                            );
+                            Ok(())
+                        } else {
+                            match db.store(&ex, &result.data, EncodingType::Plain) {
+                                // Retried uploads don't change the recorded results, and
+                                // aren't counted twice
+                                Ok(true) => {
+                                    record_result_metrics(
+                                        &metrics,
+                                        &config,
+                                        &db,
+                                        &ex,
+                                        &result.data,
+                                        &agent,
+                                    );
+                                    Ok(())
+                                }
+                                Ok(false) => Ok(()),
+                                // Failing to record a result is basically fine -- this
+                                // just means that the agent will upload it again.
+                                Err(e) => {
+                                    error!("Failed to store result into database: {:?}", e);
+                                    crate::utils::report_failure(&e);
+                                    Err(e)
+                                }
+                            }
+                        };
+                        // The request may have given up waiting for it
+                        let _ = recorded.send(stored);
 
                         metrics.record_completed_jobs(&ex.name, 1);
 
                         if let Err(e) = db.clear_stale_records() {
                             // Not a hard failure. We can continue even if we failed
                             // to clear records from already completed runs...
-                            log::error!("Failed to clear stale records: {:?}", e);
+                            error!("Failed to clear stale records: {:?}", e);
                             crate::utils::report_failure(&e);
                         }
 
@@ -250,6 +456,9 @@ impl RecordProgressThread {
                                 &result.data.result.result.to_string(),
                             ])
                             .inc();
+                    } else {
+                        // Nothing is left to record for deleted experiments
+                        let _ = recorded.send(Ok(()));
                     }
                 }));
             })
@@ -300,6 +509,89 @@ impl RecordProgressThread {
             thread: self.clone(),
         }
     }
+
+    fn end_request(&self) {
+        *self
+            .in_flight_requests
+            .0
+            .lock()
+            .unwrap_or_else(|l| l.into_inner()) -= 1;
+        self.in_flight_requests.1.notify_one();
+    }
+
+    /// Wait until the result queued by the current request is recorded. The request doesn't
+    /// count as in flight in the meantime, as the thread only records results when the server
+    /// is idle.
+    fn wait_recorded(
+        &self,
+        recorded: &Receiver<Fallible<()>>,
+    ) -> Result<Fallible<()>, RecvTimeoutError> {
+        self.end_request();
+        let result = recorded.recv_timeout(RECORD_PROGRESS_TIMEOUT);
+        *self
+            .in_flight_requests
+            .0
+            .lock()
+            .unwrap_or_else(|l| l.into_inner()) += 1;
+        result
+    }
+}
+
+/// Record the metrics derived from a result which was just stored.
+fn record_result_metrics(
+    metrics: &crate::server::metrics::Metrics,
+    config: &Config,
+    db: &DatabaseDB<'_>,
+    ex: &Experiment,
+    data: &ProgressData,
+    agent: &str,
+) {
+    let krate = match &data.version {
+        Some((_, new)) => new,
+        None => &data.result.krate,
+    };
+    match db.load_disk_usage(ex, &data.result.toolchain, krate) {
+        Ok(Some(usage)) => metrics.record_disk_usage(&usage),
+        Ok(None) => {}
+        Err(e) => error!("Failed to load the disk usage: {:?}", e),
+    }
+    match db.load_build_time(ex, &data.result.toolchain, krate) {
+        Ok(Some(time)) => metrics.record_build_time(agent, ex.mode, time),
+        Ok(None) => {}
+        Err(e) => error!("Failed to load the build time: {:?}", e),
+    }
+    match db.load_build_cache_stats(ex, &data.result.toolchain, krate) {
+        Ok(Some(stats)) => metrics.record_build_cache(agent, &stats),
+        Ok(None) => {}
+        Err(e) => error!("Failed to load the cache stats: {:?}", e),
+    }
+    metrics.record_failure_reason(&ex.name, &data.result.toolchain, &data.result.result);
+    // The crate is compared once the results of both toolchains are in
+    let results = ex
+        .toolchains
+        .iter()
+        .map(|tc| {
+            Ok((
+                db.load_test_result(ex, tc, krate)?,
+                db.load_overridden(ex, tc, krate)?,
+            ))
+        })
+        .collect::<Fallible<Vec<_>>>();
+    match results.as_deref() {
+        Ok([(Some(r1), o1), (Some(r2), o2)]) => metrics.record_comparison(
+            &ex.name,
+            compare(
+                config,
+                ex.comparison_policy,
+                krate,
+                Some(r1),
+                Some(r2),
+                *o1 && *o2,
+            ),
+        ),
+        Ok(_) => {}
+        Err(e) => error!("Failed to load the results: {:?}", e),
+    }
 }
 
 pub struct RequestGuard {
@@ -308,13 +600,7 @@ pub struct RequestGuard {
 
 impl Drop for RequestGuard {
     fn drop(&mut self) {
-        *self
-            .thread
-            .in_flight_requests
-            .0
-            .lock()
-            .unwrap_or_else(|l| l.into_inner()) -= 1;
-        self.thread.in_flight_requests.1.notify_one();
+        self.thread.end_request();
     }
 }
 
@@ -328,39 +614,176 @@ impl Drop for RequestGuard {
 // In practice it's pretty likely that we won't fully run in parallel anyway,
 // but this lets some of the work proceed without the lock being held, which is
 // generally positive.
+//
+// Uploads carrying an idempotency key are ignored once their results are recorded: agents retry
+// uploads when the connection drops, even if the server already received them. The upload is
+// only acknowledged once its result is committed, as the agent drops it from its spool then.
 fn endpoint_record_progress(
+    auth: AuthDetails,
     result: ExperimentData<ProgressData>,
+    idempotency_key: Option<String>,
     data: Arc<Data>,
-    _auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
     let start = Instant::now();
 
+    if let Some(key) = &idempotency_key {
+        if idempotency::is_recorded(db, &auth.name, key)? {
+            debug!("ignoring duplicate upload {} from {}", key, auth.name);
+            return Ok(ApiResponse::Success { result: true }.into_response()?);
+        }
+    }
+
+    let ret = if record_progress(&data, result, idempotency_key, auth.name)? {
+        Ok(ApiResponse::Success { result: true }.into_response()?)
+    } else {
+        Ok(ApiResponse::<()>::SlowDown.into_response()?)
+    };
+
+    data.metrics
+        .crater_endpoint_time
+        .with_label_values(&["record_progress_endpoint"])
+        .observe(start.elapsed().as_secs_f64());
+
+    ret
+}
+
+/// Queue the result to be recorded, along with the idempotency key of its upload, and wait
+/// until it's committed. Returns `false` if the queue is full or the result took too long to be
+/// recorded: the agent then uploads it again, which is ignored if it was recorded in the end.
+fn record_progress(
+    data: &Data,
+    result: ExperimentData<ProgressData>,
+    idempotency_key: Option<String>,
+    agent: String,
+) -> Fallible<bool> {
     data.metrics
         .result_log_size
         .observe(result.data.result.log.len() as f64);
 
-    let ret = match data.record_progress_worker.queue.try_send(result) {
-        Ok(()) => Ok(ApiResponse::Success { result: true }.into_response()?),
+    let worker = &data.record_progress_worker;
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    match worker.queue.try_send((result, agent, idempotency_key, tx)) {
+        Ok(()) => {}
         Err(crossbeam_channel::TrySendError::Full(_)) => {
             data.metrics.crater_bounced_record_progress.inc_by(1);
-            Ok(ApiResponse::<()>::SlowDown.into_response()?)
+            return Ok(false);
         }
         Err(crossbeam_channel::TrySendError::Disconnected(_)) => unreachable!(),
+    }
+
+    match worker.wait_recorded(&rx) {
+        Ok(recorded) => recorded.map(|()| true),
+        Err(RecvTimeoutError::Timeout) => Ok(false),
+        // The thread panicked while recording it
+        Err(RecvTimeoutError::Disconnected) => bail!("failed to record the result"),
+    }
+}
+
+fn endpoint_upload_chunk(
+    auth: AuthDetails,
+    id: String,
+    offset: u64,
+    chunk: Bytes,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    data.writable_db()?;
+    let received = data.uploads.append(&auth.name, &id, offset, &chunk)?;
+    Ok(ApiResponse::Success { result: received }.into_response()?)
+}
+
+// The id of the upload doubles as its idempotency key. The response is `false` when the upload
+// was corrupted or abandoned, and the agent has to upload it again from the start.
+fn endpoint_complete_upload(
+    auth: AuthDetails,
+    upload: CompleteUpload,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let id = &upload.upload_id;
+    let _lock = data.uploads.lock(&auth.name, id)?;
+    if idempotency::is_recorded(db, &auth.name, id)? {
+        debug!("ignoring duplicate upload {} from {}", id, auth.name);
+        data.uploads.remove(&auth.name, id)?;
+        return Ok(ApiResponse::Success { result: true }.into_response()?);
+    }
+
+    let result = data
+        .uploads
+        .assemble(&auth.name, id, &upload.sha256)
+        .and_then(|file| {
+            Encoding::from_headers(
+                upload.content_type.as_deref(),
+                upload.content_encoding.as_deref(),
+            )?
+            .decode_from(file, MAX_DECODED_SIZE)
+        });
+    let result: ExperimentData<ProgressData> = match result {
+        Ok(result) => result,
+        Err(err) => {
+            if let Some(UploadError::HashMismatch { .. } | UploadError::Unknown(_)) =
+                err.downcast_ref()
+            {
+                warn!("{}", err);
+                return Ok(ApiResponse::Success { result: false }.into_response()?);
+            }
+            return Err(err);
+        }
     };
 
-    data.metrics
-        .crater_endpoint_time
-        .with_label_values(&["record_progress_endpoint"])
-        .observe(start.elapsed().as_secs_f64());
+    if record_progress(&data, result, Some(id.clone()), auth.name.clone())? {
+        data.uploads.remove(&auth.name, id)?;
+        Ok(ApiResponse::Success { result: true }.into_response()?)
+    } else {
+        Ok(ApiResponse::<()>::SlowDown.into_response()?)
+    }
+}
 
-    ret
+#[derive(Deserialize)]
+pub struct RustcVersionsData {
+    versions: [RustcVersion; 2],
+}
+
+fn endpoint_rustc_versions(
+    _auth: AuthDetails,
+    versions: ExperimentData<RustcVersionsData>,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let mut ex = Experiment::get(db, &versions.experiment_name)?
+        .ok_or_else(|| anyhow!("no experiment run by this agent"))?;
+    ex.set_rustc_versions(db, &versions.data.versions)?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AbortedQuery {
+    created_at: DateTime<Utc>,
+}
+
+fn endpoint_experiment_aborted(
+    _auth: AuthDetails,
+    query: ExperimentData<AbortedQuery>,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    // Aborting deletes the experiment, and a new one with the same name might have been created
+    let aborted = match Experiment::get(&data.db, &query.experiment_name)? {
+        Some(ex) => ex.created_at != query.data.created_at,
+        None => true,
+    };
+    Ok(ApiResponse::Success { result: aborted }.into_response()?)
 }
 
 fn endpoint_heartbeat(
+    auth: AuthDetails,
     id: WorkerInfo,
     data: Arc<Data>,
-    auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
+    data.writable_db()?;
+    data.metrics
+        .record_worker_restarts(&auth.name, id.worker_restarts());
     data.agents.add_worker(id);
     if let Some(rev) = auth.git_revision {
         data.agents.set_git_revision(&auth.name, &rev)?;
@@ -373,11 +796,12 @@ fn endpoint_heartbeat(
 }
 
 fn endpoint_error(
+    auth: AuthDetails,
     error: ExperimentData<HashMap<String, String>>,
     mutex: Arc<Mutex<Data>>,
-    auth: AuthDetails,
+    bot_data: Option<Arc<BotData>>,
 ) -> Fallible<Response<Body>> {
-    log::error!(
+    error!(
         "agent {} failed while running {}: {:?}",
         auth.name,
         error.experiment_name,
@@ -385,9 +809,44 @@ fn endpoint_error(
     );
 
     let data = mutex.lock().unwrap();
-    let ex = Experiment::get(&data.db, &error.experiment_name)?
+    let db = data.writable_db()?;
+    let mut ex = Experiment::get(db, &error.experiment_name)?
         .ok_or_else(|| anyhow!("no experiment run by this agent"))?;
 
+    // A missing toolchain is not a problem of the agent, and no other agent will be able to run
+    // the experiment either
+    if let Some(toolchain) = error.data.get("toolchain") {
+        // Every agent running the experiment reports the failure, only the first one is notified
+        if ex.status == Status::Running {
+            ex.set_status(db, Status::ToolchainUnavailable)?;
+            messages::notify_in_background(&data, &ex, Event::ToolchainUnavailable);
+            if let (Some(bot_data), Some(github_issue)) = (&bot_data, &ex.github_issue) {
+                Message::new()
+                    .line(
+                        "warning",
+                        format!(
+                            "Experiment **`{}`** can't run: toolchain `{toolchain}` is not \
+                             available.",
+                            ex.name
+                        ),
+                    )
+                    .line(
+                        "information_source",
+                        format!(
+                            "The error was: `{}`",
+                            error.data.get("error").map(String::as_str).unwrap_or("")
+                        ),
+                    )
+                    .line(
+                        "hammer_and_wrench",
+                        "Edit the toolchains of the experiment to queue it again.",
+                    )
+                    .send(&github_issue.api_url, &data, bot_data)?;
+            }
+        }
+        return Ok(ApiResponse::Success { result: true }.into_response()?);
+    }
+
     data.metrics.record_error(&auth.name, &ex.name);
 
     Ok(ApiResponse::Success { result: true }.into_response()?)
@@ -396,13 +855,28 @@ fn endpoint_error(
 fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
     match resp {
         Ok(resp) => resp,
-        Err(err) => ApiResponse::internal_error(err.to_string())
-            .into_response()
-            .unwrap(),
+        Err(err) => {
+            if let Some(HttpError::ReadOnly) = err.downcast_ref() {
+                return ApiResponse::read_only().into_response().unwrap();
+            }
+            if let Some(HttpError::BadRequest) = err.downcast_ref() {
+                return ApiResponse::bad_request(err.to_string())
+                    .into_response()
+                    .unwrap();
+            }
+            ApiResponse::internal_error(err.to_string())
+                .into_response()
+                .unwrap()
+        }
     }
 }
 
 async fn handle_errors(err: Rejection) -> Result<Response<Body>, Rejection> {
+    if let Some(InvalidBody(error)) = err.find() {
+        let error = format!("invalid request body: {error}");
+        return Ok(ApiResponse::bad_request(error).into_response().unwrap());
+    }
+
     let error = if let Some(compat) = err.find::<HttpError>() {
         Some(*compat)
     } else if err.is_not_found() {
@@ -414,6 +888,10 @@ async fn handle_errors(err: Rejection) -> Result<Response<Body>, Rejection> {
     match error {
         Some(HttpError::NotFound) => Ok(ApiResponse::not_found().into_response().unwrap()),
         Some(HttpError::Forbidden) => Ok(ApiResponse::unauthorized().into_response().unwrap()),
+        Some(HttpError::ReadOnly) => Ok(ApiResponse::read_only().into_response().unwrap()),
+        Some(HttpError::BadRequest) => Ok(ApiResponse::bad_request("bad request".into())
+            .into_response()
+            .unwrap()),
         None => Err(err),
     }
 }
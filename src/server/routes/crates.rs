@@ -0,0 +1,147 @@
+use crate::crates::lists::{self, ListSummary};
+use crate::prelude::*;
+use crate::report::{compare, Comparison};
+use crate::results::{failing_since, DatabaseDB};
+use crate::server::api_types::ApiResponse;
+use crate::server::{Data, HttpError};
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+use warp::{Filter, Rejection};
+
+/// Number of experiments returned by the trend endpoint when no limit is provided.
+const DEFAULT_TREND_LIMIT: u32 = 10;
+const MAX_TREND_LIMIT: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct TrendQuery {
+    #[serde(rename = "crate")]
+    krate: String,
+    tag: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct TrendResult {
+    toolchain: String,
+    result: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TrendEntry {
+    experiment: String,
+    created_at: DateTime<Utc>,
+    #[serde(rename = "crate")]
+    krate: String,
+    start: TrendResult,
+    end: TrendResult,
+    comparison: Comparison,
+}
+
+#[derive(Serialize)]
+struct FailingSinceEntry {
+    experiment: String,
+    toolchain: String,
+}
+
+#[derive(Serialize)]
+struct Trend {
+    failing_since: Option<FailingSinceEntry>,
+    experiments: Vec<TrendEntry>,
+}
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_filter = warp::any().map(move || data.clone());
+
+    let trend = warp::get()
+        .and(warp::path("trend"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter.clone())
+        .map(endpoint_trend);
+
+    let lists = warp::get()
+        .and(warp::path("lists"))
+        .and(warp::path::end())
+        .and(data_filter)
+        .map(endpoint_lists);
+
+    warp::any().and(trend.or(lists).unify()).map(handle_results)
+}
+
+fn endpoint_trend(query: TrendQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let name = query.krate.trim();
+    if name.is_empty() {
+        return Err(HttpError::NotFound.into());
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_TREND_LIMIT)
+        .min(MAX_TREND_LIMIT);
+
+    let tag = query.tag.as_deref().filter(|tag| !tag.is_empty());
+    let trend = DatabaseDB::new(&data.db).crate_trend(name, tag, limit)?;
+    let failing_since = failing_since(&trend).map(|since| FailingSinceEntry {
+        experiment: since.experiment,
+        toolchain: since.toolchain.to_string(),
+    });
+
+    let experiments = trend
+        .into_iter()
+        .map(|entry| {
+            let [start_tc, end_tc] = entry.toolchains;
+            let [start_res, end_res] = entry.results;
+            TrendEntry {
+                comparison: compare(
+                    &data.config,
+                    entry.comparison_policy,
+                    &entry.krate,
+                    start_res.as_ref(),
+                    end_res.as_ref(),
+                    entry.overridden,
+                ),
+                experiment: entry.experiment,
+                created_at: entry.created_at,
+                krate: entry.krate.id(),
+                start: TrendResult {
+                    toolchain: start_tc.to_string(),
+                    result: start_res.map(|r| r.to_string()),
+                },
+                end: TrendResult {
+                    toolchain: end_tc.to_string(),
+                    result: end_res.map(|r| r.to_string()),
+                },
+            }
+        })
+        .collect();
+
+    Ok(ApiResponse::Success {
+        result: Trend {
+            failing_since,
+            experiments,
+        },
+    }
+    .into_response()?)
+}
+
+fn endpoint_lists(data: Arc<Data>) -> Fallible<Response<Body>> {
+    let result: Vec<ListSummary> = lists::summaries(&data.db)?;
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => {
+            if let Some(HttpError::NotFound) = err.downcast_ref() {
+                return ApiResponse::not_found().into_response().unwrap();
+            }
+            ApiResponse::internal_error(err.to_string())
+                .into_response()
+                .unwrap()
+        }
+    }
+}
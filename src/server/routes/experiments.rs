@@ -0,0 +1,69 @@
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::server::api_types::ApiResponse;
+use crate::server::Data;
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+use warp::{Filter, Rejection};
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExperimentEntry {
+    name: String,
+    status: &'static str,
+    mode: &'static str,
+    tags: Vec<String>,
+    created_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_filter = warp::any().map(move || data.clone());
+
+    let list = warp::get()
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter)
+        .map(endpoint_list);
+
+    warp::any().and(list).map(handle_results)
+}
+
+fn endpoint_list(query: ListQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let tag = query
+        .tag
+        .as_deref()
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty());
+
+    let result = Experiment::all(&data.db)?
+        .into_iter()
+        .filter(|ex| tag.is_none_or(|tag| ex.has_tag(tag)))
+        .map(|ex| ExperimentEntry {
+            status: ex.status.to_str(),
+            mode: ex.mode.to_str(),
+            created_at: ex.created_at,
+            completed_at: ex.completed_at,
+            name: ex.name,
+            tags: ex.tags,
+        })
+        .collect::<Vec<_>>();
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => ApiResponse::internal_error(err.to_string())
+            .into_response()
+            .unwrap(),
+    }
+}
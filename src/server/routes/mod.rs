@@ -1,4 +1,8 @@
+pub mod admin;
 pub mod agent;
+pub mod crates;
+pub mod experiments;
 pub mod metrics;
+pub mod triage;
 pub mod ui;
 pub mod webhooks;
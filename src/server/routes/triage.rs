@@ -0,0 +1,196 @@
+use crate::actions::{spurious_crates, ActionsCtx, RetrySpurious};
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::results::{DatabaseDB, TriageAnnotation, TriageStatus};
+use crate::server::api_types::ApiResponse;
+use crate::server::auth::{auth_filter, AuthDetails, TokenType};
+use crate::server::{Data, HttpError};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+use warp::{Filter, Rejection};
+
+#[derive(Deserialize)]
+pub struct TriageUpdate {
+    #[serde(rename = "crate")]
+    krate: String,
+    annotation: Option<TriageUpdateAnnotation>,
+}
+
+#[derive(Deserialize)]
+pub struct TriageUpdateAnnotation {
+    status: TriageStatus,
+    issue: Option<String>,
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TriageEntry {
+    #[serde(rename = "crate")]
+    krate: String,
+    #[serde(flatten)]
+    annotation: TriageAnnotation,
+}
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_cloned = data.clone();
+    let data_filter = warp::any().map(move || data_cloned.clone());
+
+    let list = warp::get()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(endpoint_list);
+
+    let update = warp::post()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Triager))
+        .map(endpoint_update);
+
+    let spurious = warp::get()
+        .and(warp::path::param())
+        .and(warp::path("spurious"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(endpoint_spurious);
+
+    let retry_spurious = warp::post()
+        .and(warp::path::param())
+        .and(warp::path("spurious"))
+        .and(warp::path::end())
+        .and(data_filter)
+        .and(auth_filter(data, TokenType::Triager))
+        .map(endpoint_retry_spurious);
+
+    warp::any()
+        .and(
+            list.or(update)
+                .unify()
+                .or(spurious)
+                .unify()
+                .or(retry_spurious)
+                .unify(),
+        )
+        .map(handle_results)
+        .recover(handle_errors)
+        .unify()
+}
+
+fn endpoint_list(name: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let ex = Experiment::get(&data.db, &name)?.ok_or(HttpError::NotFound)?;
+    let result = DatabaseDB::new(&data.db)
+        .load_all_triage(&ex)?
+        .into_iter()
+        .map(|(krate, annotation)| TriageEntry { krate, annotation })
+        .collect::<Vec<_>>();
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_update(
+    name: String,
+    update: TriageUpdate,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let ex = Experiment::get(db, &name)?.ok_or(HttpError::NotFound)?;
+    let krate: Crate = update
+        .krate
+        .parse()
+        .map_err(|e| HttpError::bad_request(format!("invalid crate {}: {}", update.krate, e)))?;
+
+    let annotation = update.annotation.map(|a| TriageAnnotation {
+        status: a.status,
+        issue: a.issue.filter(|i| !i.trim().is_empty()),
+        note: a.note.filter(|n| !n.trim().is_empty()),
+        author: auth.name.clone(),
+    });
+
+    info!(
+        "{} triaged {} in {}: {}",
+        auth.name,
+        krate.id(),
+        ex.name,
+        annotation
+            .as_ref()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|| "removed".into())
+    );
+    DatabaseDB::new(db).store_triage(&ex, &krate, annotation.as_ref())?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_spurious(name: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let ex = Experiment::get(&data.db, &name)?.ok_or(HttpError::NotFound)?;
+    let result = spurious_crates(&data.db, &data.config, &ex)?
+        .into_iter()
+        .map(|krate| krate.id())
+        .collect::<Vec<_>>();
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_retry_spurious(
+    name: String,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    if !Experiment::exists(db, &name)? {
+        return Err(HttpError::NotFound.into());
+    }
+
+    info!(
+        "{} requested to retry the spurious results of {}",
+        auth.name, name
+    );
+    let result = RetrySpurious { name }
+        .requeue(&ActionsCtx::new(db, &data.config).with_log_store(data.log_store.as_deref()))?
+        .into_iter()
+        .map(|krate| krate.id())
+        .collect::<Vec<_>>();
+
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => {
+            if let Some(HttpError::NotFound) = err.downcast_ref() {
+                return ApiResponse::not_found().into_response().unwrap();
+            }
+            if let Some(HttpError::ReadOnly) = err.downcast_ref() {
+                return ApiResponse::read_only().into_response().unwrap();
+            }
+            if let Some(HttpError::BadRequest) = err.downcast_ref() {
+                return ApiResponse::bad_request(err.to_string())
+                    .into_response()
+                    .unwrap();
+            }
+            ApiResponse::internal_error(err.to_string())
+                .into_response()
+                .unwrap()
+        }
+    }
+}
+
+async fn handle_errors(err: Rejection) -> Result<Response<Body>, Rejection> {
+    match err.find::<HttpError>() {
+        Some(HttpError::NotFound) => Ok(ApiResponse::not_found().into_response().unwrap()),
+        Some(HttpError::Forbidden) => Ok(ApiResponse::unauthorized().into_response().unwrap()),
+        Some(HttpError::ReadOnly) => Ok(ApiResponse::read_only().into_response().unwrap()),
+        Some(HttpError::BadRequest) => Ok(ApiResponse::bad_request("bad request".into())
+            .into_response()
+            .unwrap()),
+        None => Err(err),
+    }
+}
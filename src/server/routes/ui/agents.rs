@@ -1,7 +1,7 @@
 use crate::prelude::*;
 use crate::server::agents::AgentStatus;
 use crate::server::routes::ui::{render_template, LayoutContext};
-use crate::server::Data;
+use crate::server::{shadow_runs, Data};
 use chrono::SecondsFormat;
 use http::Response;
 use hyper::Body;
@@ -16,6 +16,7 @@ struct AgentData {
     assigned_experiment: Option<String>,
     git_revision: Option<String>,
     capabilities: Vec<String>,
+    reproducibility: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -25,6 +26,7 @@ struct ListContext {
 }
 
 pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
+    let reproducibility = shadow_runs::reproducibility(&data.db)?;
     let mut agents = Vec::new();
     for agent in &data.agents.all()? {
         let (status_class, status_pretty, show_assigned) = match agent.status() {
@@ -54,13 +56,21 @@ pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
             },
             git_revision: agent.git_revision().cloned(),
             capabilities,
+            reproducibility: reproducibility.get(agent.name()).map(|r| {
+                format!(
+                    "{:.1}% ({}/{})",
+                    f64::from(r.matching) * 100.0 / f64::from(r.compared),
+                    r.matching,
+                    r.compared
+                )
+            }),
         });
     }
 
     render_template(
         "ui/agents.html",
         &ListContext {
-            layout: LayoutContext::new(),
+            layout: LayoutContext::with_data(&data)?,
             agents,
         },
     )
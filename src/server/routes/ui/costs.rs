@@ -0,0 +1,84 @@
+use crate::prelude::*;
+use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::{costs, Data};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+
+/// Number of days the utilization of the agents is shown for.
+const UTILIZATION_DAYS: u32 = 14;
+
+#[derive(Serialize)]
+struct ExperimentCostData {
+    name: String,
+    mode: String,
+    builds: u32,
+    machine_hours: String,
+}
+
+#[derive(Serialize)]
+struct ModeAverageData {
+    mode: String,
+    builds: u32,
+    average: String,
+}
+
+#[derive(Serialize)]
+struct AgentUtilizationData {
+    name: String,
+    hours: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CostsContext {
+    layout: LayoutContext,
+    total_machine_hours: String,
+    experiments: Vec<ExperimentCostData>,
+    modes: Vec<ModeAverageData>,
+    days: Vec<String>,
+    agents: Vec<AgentUtilizationData>,
+}
+
+pub fn endpoint_costs(data: Arc<Data>) -> Fallible<Response<Body>> {
+    let experiments = costs::experiment_costs(&data.db)?;
+    let total_machine_hours = experiments.iter().map(|ex| ex.machine_hours).sum::<f64>();
+    let utilization = costs::utilization(&data.db, UTILIZATION_DAYS)?;
+
+    render_template(
+        "ui/costs.html",
+        &CostsContext {
+            layout: LayoutContext::with_data(&data)?,
+            total_machine_hours: format!("{total_machine_hours:.1}"),
+            experiments: experiments
+                .into_iter()
+                .map(|ex| ExperimentCostData {
+                    name: ex.name,
+                    mode: ex.mode,
+                    builds: ex.builds,
+                    machine_hours: format!("{:.1}", ex.machine_hours),
+                })
+                .collect(),
+            modes: costs::mode_averages(&data.db)?
+                .into_iter()
+                .map(|mode| ModeAverageData {
+                    mode: mode.mode,
+                    builds: mode.builds,
+                    average: format!("{:.1}s", mode.average_seconds),
+                })
+                .collect(),
+            days: utilization
+                .days
+                .iter()
+                .map(|day| day.format("%m-%d").to_string())
+                .collect(),
+            agents: utilization
+                .agents
+                .into_iter()
+                .map(|(name, hours)| AgentUtilizationData {
+                    name,
+                    hours: hours.iter().map(|h| format!("{h:.1}")).collect(),
+                })
+                .collect(),
+        },
+    )
+}
@@ -0,0 +1,48 @@
+use crate::crates::lists;
+use crate::prelude::*;
+use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::{cronjobs, Data};
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct ListData {
+    name: &'static str,
+    crates: u32,
+    loaded_at: Option<String>,
+    fetched_at: Option<String>,
+    revision: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CrateListsContext {
+    layout: LayoutContext,
+    lists: Vec<ListData>,
+    updating: bool,
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn endpoint_lists(data: Arc<Data>) -> Fallible<Response<Body>> {
+    render_template(
+        "ui/crate-lists.html",
+        &CrateListsContext {
+            layout: LayoutContext::with_data(&data)?,
+            lists: lists::summaries(&data.db)?
+                .into_iter()
+                .map(|list| ListData {
+                    name: list.name,
+                    crates: list.crates,
+                    loaded_at: list.loaded_at.map(format_time),
+                    fetched_at: list.fetched_at.map(format_time),
+                    revision: list.revision,
+                })
+                .collect(),
+            updating: cronjobs::is_updating_lists(),
+        },
+    )
+}
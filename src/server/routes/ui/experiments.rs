@@ -1,7 +1,14 @@
+use crate::actions::spurious_crates;
+use crate::crates::{CratePattern, DependencyPatch, OverlayCrate};
 use crate::experiments::{Experiment, Mode, Status};
 use crate::prelude::*;
+use crate::report::{compare, diff_experiments, Comparison, ReportDiff, ReportProgress};
+use crate::results::{DatabaseDB, TriageAnnotation};
+use crate::server::email::{self, OutgoingEmail};
 use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::subscriptions::{self, PendingSubscription};
 use crate::server::{Data, HttpError};
+use crate::toolchain::RustcVersion;
 use chrono::{Duration, SecondsFormat, Utc};
 use http::Response;
 use hyper::Body;
@@ -13,10 +20,12 @@ struct ExperimentData {
     status_class: &'static str,
     status_pretty: &'static str,
     mode: &'static str,
+    tags: Vec<String>,
     assigned_to: Option<String>,
     requirement: Option<String>,
     progress: u8,
     priority: i32,
+    queue_position: Option<u32>,
 }
 
 impl ExperimentData {
@@ -28,6 +37,7 @@ impl ExperimentData {
             Status::GeneratingReport => ("orange", "Generating report", false),
             Status::ReportFailed => ("red", "Report failed", false),
             Status::Completed => ("green", "Completed", false),
+            Status::ToolchainUnavailable => ("red", "Toolchain unavailable", false),
         };
 
         Ok(ExperimentData {
@@ -36,14 +46,18 @@ impl ExperimentData {
             status_pretty,
             mode: match experiment.mode {
                 Mode::BuildAndTest => "cargo test",
+                Mode::BuildTestAndDoctest => "cargo test + cargo test --doc",
                 Mode::BuildOnly => "cargo build",
                 Mode::CheckOnly => "cargo check",
                 Mode::Clippy => "cargo clippy",
                 Mode::Rustdoc => "cargo doc",
                 Mode::UnstableFeatures => "unstable features",
+                Mode::Reproducibility => "cargo build (twice)",
             },
+            tags: experiment.tags.clone(),
             assigned_to: experiment.assigned_to.as_ref().map(|a| a.to_string()),
             priority: experiment.priority,
+            queue_position: None,
             requirement: experiment.requirement.clone(),
             progress: if show_progress {
                 experiment.progress(&data.db)?
@@ -57,35 +71,63 @@ impl ExperimentData {
 #[derive(Serialize)]
 struct ListContext {
     layout: LayoutContext,
+    tag: Option<String>,
     experiments: Vec<ExperimentData>,
 }
 
-pub fn endpoint_queue(data: Arc<Data>) -> Fallible<Response<Body>> {
+#[derive(Deserialize)]
+pub struct QueueQuery {
+    tag: Option<String>,
+}
+
+pub fn endpoint_queue(query: QueueQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let tag = query
+        .tag
+        .as_deref()
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty());
+
     let mut queued = Vec::new();
     let mut running = Vec::new();
     let mut needs_report = Vec::new();
     let mut generating_report = Vec::new();
     let mut report_failed = Vec::new();
+    let mut toolchain_unavailable = Vec::new();
 
+    let mut queue_position = 0;
     for experiment in &Experiment::unfinished(&data.db)? {
         // Don't include completed experiments in the queue
         if experiment.status == Status::Completed {
             continue;
         }
 
-        let ex = ExperimentData::new(&data, experiment)?;
+        // The position in the queue counts the experiments without the tag too
+        if experiment.status == Status::Queued {
+            queue_position += 1;
+        }
+        if tag.is_some_and(|tag| !experiment.has_tag(tag)) {
+            continue;
+        }
+
+        let mut ex = ExperimentData::new(&data, experiment)?;
 
         match experiment.status {
-            Status::Queued => queued.push(ex),
+            Status::Queued => {
+                // Experiments are listed in the order they're picked up by the agents
+                ex.queue_position = Some(queue_position);
+                queued.push(ex);
+            }
             Status::Running => running.push(ex),
             Status::NeedsReport => needs_report.push(ex),
             Status::GeneratingReport => generating_report.push(ex),
             Status::ReportFailed => report_failed.push(ex),
+            Status::ToolchainUnavailable => toolchain_unavailable.push(ex),
             Status::Completed => unreachable!(),
         };
     }
 
     let mut experiments = Vec::new();
+    experiments.append(&mut toolchain_unavailable);
     experiments.append(&mut report_failed);
     experiments.append(&mut generating_report);
     experiments.append(&mut needs_report);
@@ -95,7 +137,8 @@ pub fn endpoint_queue(data: Arc<Data>) -> Fallible<Response<Body>> {
     render_template(
         "ui/queue.html",
         &ListContext {
-            layout: LayoutContext::new(),
+            layout: LayoutContext::with_data(&data)?,
+            tag: tag.map(String::from),
             experiments,
         },
     )
@@ -108,6 +151,21 @@ struct ExperimentExt {
 
     github_url: Option<String>,
     report_url: Option<String>,
+    report_progress: Option<String>,
+    sandbox_image: Option<String>,
+    rustc_versions: [Option<RustcVersion>; 2],
+    test_args: Option<String>,
+    deadline: Option<String>,
+    follow_up: Option<String>,
+    comparison_policy: &'static str,
+    registry_overlay: Vec<OverlayCrate>,
+    dependency_patches: Vec<DependencyPatch>,
+    exclude: Vec<CratePattern>,
+    build_cache: bool,
+    track_warnings: bool,
+    client_side_report: bool,
+    parent: Option<String>,
+    follow_ups: Vec<String>,
 
     created_at: String,
     started_at: Option<String>,
@@ -123,6 +181,7 @@ struct ExperimentExt {
 #[derive(Serialize)]
 struct ExperimentContext {
     experiment: ExperimentExt,
+    email_subscriptions: bool,
     layout: LayoutContext,
 }
 
@@ -174,10 +233,40 @@ pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<B
             };
 
         let experiment = ExperimentExt {
-            common: ExperimentData::new(&data, &ex)?,
+            common: ExperimentData {
+                queue_position: ex.queue_position(&data.db)?,
+                ..ExperimentData::new(&data, &ex)?
+            },
 
-            github_url: ex.github_issue.map(|i| i.html_url),
+            github_url: ex.github_issue.clone().map(|i| i.html_url),
             report_url: ex.report_url.clone(),
+            report_progress: if ex.status == Status::GeneratingReport {
+                ReportProgress::get(&data.db, &ex)?.map(|p| p.to_string())
+            } else {
+                None
+            },
+            sandbox_image: ex.sandbox_image.clone(),
+            rustc_versions: ex.rustc_versions.clone(),
+            test_args: if ex.test_options.is_default() {
+                None
+            } else {
+                Some(ex.test_options.harness_args().join(" "))
+            },
+            deadline: ex.deadline.map(|d| d.to_string()),
+            follow_up: ex.follow_up.as_ref().map(|f| f.to_string()),
+            comparison_policy: ex.comparison_policy.to_str(),
+            registry_overlay: ex.registry_overlay.clone(),
+            dependency_patches: ex.dependency_patches.clone(),
+            exclude: ex.exclude.clone(),
+            build_cache: ex.build_cache,
+            track_warnings: ex.track_warnings,
+            client_side_report: ex.client_side_report,
+            parent: ex.parent.clone(),
+            follow_ups: ex
+                .follow_ups(&data.db)?
+                .into_iter()
+                .map(|f| f.name)
+                .collect(),
 
             created_at: ex.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
             started_at: ex
@@ -197,11 +286,328 @@ pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<B
         render_template(
             "ui/experiment.html",
             &ExperimentContext {
-                layout: LayoutContext::new(),
+                layout: LayoutContext::with_data(&data)?,
                 experiment,
+                email_subscriptions: data.mailer.is_some() && !data.is_read_only(),
+            },
+        )
+    } else {
+        Err(HttpError::NotFound.into())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SubscribeForm {
+    email: String,
+}
+
+#[derive(Serialize)]
+struct SubscriptionContext {
+    name: String,
+    message: &'static str,
+    /// Token of the subscription to remove when the form of the page is submitted.
+    unsubscribe_token: Option<String>,
+    layout: LayoutContext,
+}
+
+fn render_subscription(
+    data: &Data,
+    name: String,
+    message: &'static str,
+) -> Fallible<Response<Body>> {
+    render_template(
+        "ui/subscription.html",
+        &SubscriptionContext {
+            name,
+            message,
+            unsubscribe_token: None,
+            layout: LayoutContext::with_data(data)?,
+        },
+    )
+}
+
+/// Subscribes an email address to the experiment once it's confirmed through the link emailed
+/// to it, so that the form can't be used to send notifications to someone else.
+pub fn endpoint_subscribe(
+    name: String,
+    form: SubscribeForm,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    let db = data.writable_db()?;
+    let mailer = match data.mailer {
+        Some(ref mailer) if Experiment::exists(db, &name)? => mailer,
+        _ => return Err(HttpError::NotFound.into()),
+    };
+
+    let address = email::parse_address(form.email.trim())
+        .map_err(HttpError::bad_request)?
+        .email
+        .to_string();
+    match subscriptions::subscribe_email(db, &name, &address)? {
+        PendingSubscription::Created(token) => {
+            mailer.send_in_background(vec![OutgoingEmail {
+                to: address,
+                subject: format!("[crater] Confirm your subscription to {name}"),
+                body: format!(
+                    "Someone asked to be emailed at this address when the report of the \
+                     Crater experiment {name} is ready. To confirm the subscription, open \
+                     {}/subscriptions/confirm/{token}\n\n\
+                     If you didn't ask for it, you can ignore this email.",
+                    data.config.server.public_url.trim_end_matches('/'),
+                ),
+            }]);
+        }
+        PendingSubscription::Exists => {}
+        PendingSubscription::RateLimited => {
+            return Err(HttpError::bad_request(
+                "too many subscriptions were requested recently, please try again later",
+            ));
+        }
+    }
+
+    render_subscription(&data, name, "Check your inbox to confirm the subscription.")
+}
+
+pub fn endpoint_confirm_subscription(token: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    match subscriptions::confirm(data.writable_db()?, &token)? {
+        Some(name) => {
+            render_subscription(&data, name, "You'll be emailed when the report is ready.")
+        }
+        None => Err(HttpError::NotFound.into()),
+    }
+}
+
+/// The links in the emails only show the form to unsubscribe, so that they can be opened by
+/// the link scanners of the mail servers without removing the subscription.
+pub fn endpoint_confirm_unsubscribe(token: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    match subscriptions::experiment(&data.db, &token)? {
+        Some(name) => render_template(
+            "ui/subscription.html",
+            &SubscriptionContext {
+                name,
+                message: "Do you want to stop being emailed about this experiment?",
+                unsubscribe_token: Some(token),
+                layout: LayoutContext::with_data(&data)?,
+            },
+        ),
+        None => Err(HttpError::NotFound.into()),
+    }
+}
+
+pub fn endpoint_unsubscribe(token: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    match subscriptions::unsubscribe(data.writable_db()?, &token)? {
+        Some(name) => render_subscription(&data, name, "You won't be emailed anymore."),
+        None => Err(HttpError::NotFound.into()),
+    }
+}
+
+#[derive(Serialize)]
+struct TriageContext {
+    name: String,
+    annotations: Vec<(String, TriageAnnotation)>,
+    spurious: Vec<String>,
+    layout: LayoutContext,
+}
+
+pub fn endpoint_triage(name: String, data: Arc<Data>) -> Fallible<Response<Body>> {
+    if let Some(ex) = Experiment::get(&data.db, &name)? {
+        render_template(
+            "ui/triage.html",
+            &TriageContext {
+                annotations: DatabaseDB::new(&data.db).load_all_triage(&ex)?,
+                spurious: spurious_crates(&data.db, &data.config, &ex)?
+                    .into_iter()
+                    .map(|krate| krate.id())
+                    .collect(),
+                name: ex.name,
+                layout: LayoutContext::with_data(&data)?,
             },
         )
     } else {
         Err(HttpError::NotFound.into())
     }
 }
+
+/// Maximum number of results shown in the results page, to keep it fast to render.
+const MAX_RESULTS: usize = 1000;
+/// Orders the results page can be sorted by.
+const RESULTS_SORTS: &[&str] = &["crate", "comparison"];
+
+fn invalid_filter(key: &str, value: &str, accepted: &[&str]) -> anyhow::Error {
+    HttpError::bad_request(format!(
+        "invalid {key} `{value}` (accepted values: {})",
+        accepted.join(", ")
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResultsFilter {
+    comparison: Option<String>,
+    reason: Option<String>,
+    #[serde(rename = "crate")]
+    krate: Option<String>,
+    sort: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ResultRow {
+    #[serde(rename = "crate")]
+    krate: String,
+    comparison: Comparison,
+    results: Vec<Option<String>>,
+}
+
+#[derive(Serialize)]
+struct ResultsContext {
+    name: String,
+    toolchains: Vec<String>,
+    comparisons: Vec<&'static str>,
+    filter: ResultsFilter,
+    results: Vec<ResultRow>,
+    matching: usize,
+    layout: LayoutContext,
+}
+
+pub fn endpoint_results(
+    name: String,
+    filter: ResultsFilter,
+    data: Arc<Data>,
+) -> Fallible<Response<Body>> {
+    let Some(ex) = Experiment::get(&data.db, &name)? else {
+        return Err(HttpError::NotFound.into());
+    };
+
+    // Empty form fields are submitted as empty strings
+    let non_empty = |value: &Option<String>| value.clone().filter(|v| !v.is_empty());
+    let comparison = non_empty(&filter.comparison)
+        .map(|c| {
+            c.parse::<Comparison>()
+                .map_err(|_| invalid_filter("comparison", &c, Comparison::possible_values()))
+        })
+        .transpose()?;
+    if let Some(sort) = non_empty(&filter.sort) {
+        if !RESULTS_SORTS.contains(&sort.as_str()) {
+            return Err(invalid_filter("sort", &sort, RESULTS_SORTS));
+        }
+    }
+    let reason = non_empty(&filter.reason);
+    let krate = non_empty(&filter.krate);
+
+    let results_db = DatabaseDB::new(&data.db);
+    let all_results = results_db.load_all_results(&ex)?;
+    let overridden = results_db.load_overridden_crates(&ex)?;
+    let mut results = Vec::new();
+    for krate_info in ex.get_crates(&data.db)? {
+        let id = krate_info.id();
+        if let Some(krate) = &krate {
+            if !id.contains(krate.as_str()) {
+                continue;
+            }
+        }
+
+        let crate_results = ex
+            .toolchains
+            .iter()
+            .map(|tc| all_results.get(&(id.clone(), tc.to_string())))
+            .collect::<Vec<_>>();
+        // Skip the crates which haven't been tested yet
+        if crate_results.iter().all(|r| r.is_none()) {
+            continue;
+        }
+
+        let crate_comparison = compare(
+            &data.config,
+            ex.comparison_policy,
+            &krate_info,
+            crate_results[0],
+            crate_results[1],
+            overridden.contains(&id),
+        );
+        if comparison.is_some_and(|c| c != crate_comparison) {
+            continue;
+        }
+
+        let crate_results = crate_results
+            .into_iter()
+            .map(|r| r.map(|r| r.to_string()))
+            .collect::<Vec<_>>();
+        if let Some(reason) = &reason {
+            if !crate_results
+                .iter()
+                .flatten()
+                .any(|r| r.contains(reason.as_str()))
+            {
+                continue;
+            }
+        }
+
+        results.push(ResultRow {
+            krate: id,
+            comparison: crate_comparison,
+            results: crate_results,
+        });
+    }
+
+    match filter.sort.as_deref() {
+        Some("comparison") => results.sort_by(|a, b| {
+            a.comparison
+                .to_str()
+                .cmp(b.comparison.to_str())
+                .then_with(|| a.krate.cmp(&b.krate))
+        }),
+        _ => results.sort_by(|a, b| a.krate.cmp(&b.krate)),
+    }
+
+    let matching = results.len();
+    results.truncate(MAX_RESULTS);
+
+    render_template(
+        "ui/results.html",
+        &ResultsContext {
+            toolchains: ex.toolchains.iter().map(|tc| tc.to_string()).collect(),
+            comparisons: Comparison::possible_values().to_vec(),
+            name: ex.name,
+            filter,
+            results,
+            matching,
+            layout: LayoutContext::with_data(&data)?,
+        },
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DiffQuery {
+    before: Option<String>,
+    after: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiffContext {
+    query: DiffQuery,
+    diff: Option<ReportDiff>,
+    layout: LayoutContext,
+}
+
+pub fn endpoint_diff(query: DiffQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let diff = match (query.before.as_deref(), query.after.as_deref()) {
+        (Some(before), Some(after)) if !before.is_empty() && !after.is_empty() => {
+            let (Some(before), Some(after)) = (
+                Experiment::get(&data.db, before)?,
+                Experiment::get(&data.db, after)?,
+            ) else {
+                return Err(HttpError::NotFound.into());
+            };
+            Some(diff_experiments(&data.db, &data.config, &before, &after)?)
+        }
+        _ => None,
+    };
+
+    render_template(
+        "ui/diff.html",
+        &DiffContext {
+            query,
+            diff,
+            layout: LayoutContext::with_data(&data)?,
+        },
+    )
+}
@@ -1,6 +1,6 @@
 use crate::assets;
 use crate::prelude::*;
-use crate::server::{Data, HttpError};
+use crate::server::{maintenance, Data, HttpError};
 use http::header::{HeaderValue, CONTENT_TYPE};
 use http::{Response, StatusCode};
 use hyper::Body;
@@ -9,19 +9,32 @@ use std::sync::Arc;
 use warp::{Filter, Rejection};
 
 mod agents;
+mod costs;
+mod crate_lists;
 mod experiments;
+mod search;
 
 #[derive(Serialize)]
 struct LayoutContext {
     git_revision: Option<&'static str>,
+    maintenance: Option<String>,
 }
 
 impl LayoutContext {
     fn new() -> Self {
         LayoutContext {
             git_revision: crate::GIT_REVISION,
+            maintenance: None,
         }
     }
+
+    /// Context of the pages showing the banner of the maintenance mode, when it's enabled.
+    fn with_data(data: &Data) -> Fallible<Self> {
+        Ok(LayoutContext {
+            maintenance: maintenance::current(&data.db)?.map(|m| m.message),
+            ..LayoutContext::new()
+        })
+    }
 }
 
 pub fn routes(
@@ -31,6 +44,7 @@ pub fn routes(
 
     let queue = warp::get()
         .and(warp::path::end())
+        .and(warp::query())
         .and(data_filter.clone())
         .map(experiments::endpoint_queue);
 
@@ -41,12 +55,88 @@ pub fn routes(
         .and(data_filter.clone())
         .map(experiments::endpoint_experiment);
 
+    let subscribe = warp::post()
+        .and(warp::path("ex"))
+        .and(warp::path::param())
+        .and(warp::path("subscribe"))
+        .and(warp::path::end())
+        .and(warp::body::form())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_subscribe);
+
+    let confirm_subscription = warp::get()
+        .and(warp::path("subscriptions"))
+        .and(warp::path("confirm"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_confirm_subscription);
+
+    let confirm_unsubscribe = warp::get()
+        .and(warp::path("subscriptions"))
+        .and(warp::path("unsubscribe"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_confirm_unsubscribe);
+
+    let unsubscribe = warp::post()
+        .and(warp::path("subscriptions"))
+        .and(warp::path("unsubscribe"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_unsubscribe);
+
+    let triage = warp::get()
+        .and(warp::path("ex"))
+        .and(warp::path::param())
+        .and(warp::path("triage"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_triage);
+
+    let results = warp::get()
+        .and(warp::path("ex"))
+        .and(warp::path::param())
+        .and(warp::path("results"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_results);
+
+    let diff = warp::get()
+        .and(warp::path("diff"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter.clone())
+        .map(experiments::endpoint_diff);
+
+    let search = warp::get()
+        .and(warp::path("search"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(data_filter.clone())
+        .map(search::endpoint_search);
+
     let agents = warp::get()
         .and(warp::path("agents"))
         .and(warp::path::end())
-        .and(data_filter)
+        .and(data_filter.clone())
         .map(agents::endpoint_list);
 
+    let costs = warp::get()
+        .and(warp::path("costs"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(costs::endpoint_costs);
+
+    let crate_lists = warp::get()
+        .and(warp::path("crate-lists"))
+        .and(warp::path::end())
+        .and(data_filter)
+        .map(crate_lists::endpoint_lists);
+
     let assets = warp::get()
         .and(warp::path("assets"))
         .and(warp::path::param())
@@ -58,8 +148,28 @@ pub fn routes(
             queue
                 .or(experiment)
                 .unify()
+                .or(subscribe)
+                .unify()
+                .or(confirm_subscription)
+                .unify()
+                .or(confirm_unsubscribe)
+                .unify()
+                .or(unsubscribe)
+                .unify()
+                .or(triage)
+                .unify()
+                .or(results)
+                .unify()
+                .or(diff)
+                .unify()
+                .or(search)
+                .unify()
                 .or(agents)
                 .unify()
+                .or(costs)
+                .unify()
+                .or(crate_lists)
+                .unify()
                 .or(assets)
                 .unify(),
         )
@@ -100,6 +210,25 @@ fn error_404() -> Fallible<Response<Body>> {
     Ok(resp)
 }
 
+#[derive(Serialize)]
+struct BadRequestContext {
+    message: String,
+    layout: LayoutContext,
+}
+
+fn error_400(message: String) -> Fallible<Response<Body>> {
+    let mut resp = render_template(
+        "ui/400.html",
+        &BadRequestContext {
+            message,
+            layout: LayoutContext::new(),
+        },
+    )?;
+
+    *resp.status_mut() = StatusCode::BAD_REQUEST;
+    Ok(resp)
+}
+
 fn error_500() -> Response<Body> {
     // Ensure the 500 error page always renders
     let mut resp = match render_template(
@@ -137,6 +266,15 @@ fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
                     }
                 }
             }
+            if let Some(HttpError::BadRequest) = err.downcast_ref() {
+                match error_400(err.to_string()) {
+                    Ok(content) => return content,
+                    Err(err400) => {
+                        crate::utils::report_failure(&err400);
+                        return error_500();
+                    }
+                }
+            }
 
             crate::utils::report_failure(&err);
             error_500()
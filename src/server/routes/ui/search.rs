@@ -0,0 +1,105 @@
+use crate::prelude::*;
+use crate::report::log_url;
+use crate::results::{failing_since, DatabaseDB, TestResult};
+use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::Data;
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+
+const MAX_RESULTS: u32 = 500;
+/// Number of release experiments considered to find since when the crate is failing.
+const TREND_EXPERIMENTS: u32 = 50;
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchQuery {
+    #[serde(rename = "crate")]
+    krate: Option<String>,
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchRow {
+    experiment: String,
+    #[serde(rename = "crate")]
+    krate: String,
+    toolchain: String,
+    result: String,
+    log_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchContext {
+    query: SearchQuery,
+    results: Option<Vec<SearchRow>>,
+    last_built_in: Option<String>,
+    failing_since: Option<String>,
+    layout: LayoutContext,
+}
+
+/// Whether the crate was built successfully, regardless of the outcome of its tests.
+fn has_built(result: &TestResult) -> bool {
+    matches!(
+        result,
+        TestResult::TestPass
+            | TestResult::TestSkipped
+            | TestResult::TestFail(_)
+            | TestResult::DoctestFail(_)
+            | TestResult::TestAndDoctestFail(_)
+    )
+}
+
+pub fn endpoint_search(query: SearchQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let name = query
+        .krate
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+
+    let tag = query
+        .tag
+        .as_deref()
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty());
+
+    let (results, last_built_in, failing_since) = if let Some(name) = name {
+        let db = DatabaseDB::new(&data.db);
+        let history = db.crate_history(name, tag, MAX_RESULTS)?;
+        // The history is sorted from the most recent experiment
+        let last_built_in = history
+            .iter()
+            .find(|entry| has_built(&entry.result))
+            .map(|entry| entry.experiment.clone());
+
+        let rows = history
+            .into_iter()
+            .map(|entry| SearchRow {
+                log_url: entry
+                    .report_url
+                    .as_deref()
+                    .map(|url| log_url(url, &entry.toolchain, &entry.krate)),
+                experiment: entry.experiment,
+                krate: entry.krate.id(),
+                toolchain: entry.toolchain.to_string(),
+                result: entry.result.to_string(),
+            })
+            .collect();
+
+        let failing_since = failing_since(&db.crate_trend(name, tag, TREND_EXPERIMENTS)?)
+            .map(|since| since.toolchain.to_string());
+        (Some(rows), last_built_in, failing_since)
+    } else {
+        (None, None, None)
+    };
+
+    render_template(
+        "ui/search.html",
+        &SearchContext {
+            query,
+            results,
+            last_built_in,
+            failing_since,
+            layout: LayoutContext::with_data(&data)?,
+        },
+    )
+}
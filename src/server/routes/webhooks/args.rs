@@ -1,28 +1,141 @@
-use crate::experiments::{Assignee, CapLints, DeferredCrateSelect, Mode};
+use crate::experiments::{
+    Assignee, CapLints, ComparisonPolicy, Deadline, DeferredCrateSelect, FollowUp, FollowUpKind,
+    Mode,
+};
 use crate::toolchain::Toolchain;
+use crate::utils::string::closest_match;
+use std::fmt::Display;
+use std::str::FromStr;
 
 #[derive(Debug, thiserror::Error)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub enum CommandParseError {
     #[error("missing command")]
     MissingCommand,
-    #[error("invalid argument: {0}")]
+    #[error("unknown command `{command}`{}", hint(.suggestion, &[], "commands"))]
+    UnknownCommand {
+        command: String,
+        suggestion: Option<String>,
+    },
+    #[error("invalid argument `{0}`, arguments must be written as `key=value`")]
     InvalidArgument(String),
-    #[error("duplicate key: {0}")]
+    #[error("duplicate key `{0}`")]
     DuplicateKey(String),
-    #[error("unknown key: {0}")]
-    UnknownKey(String),
+    #[error("unknown key `{key}`{}", hint(.suggestion, .accepted, "keys"))]
+    UnknownKey {
+        key: String,
+        suggestion: Option<String>,
+        accepted: Vec<String>,
+    },
+    #[error("invalid value `{value}` for `{key}`: {reason}{}", hint(&None, .accepted, "values"))]
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+        accepted: Vec<String>,
+    },
+}
+
+impl CommandParseError {
+    fn unknown_command(command: &str, commands: &[&str]) -> Self {
+        CommandParseError::UnknownCommand {
+            command: command.into(),
+            suggestion: closest_match(command, commands.iter().copied()).map(Into::into),
+        }
+    }
+
+    fn unknown_key(key: &str, keys: &[&str]) -> Self {
+        CommandParseError::UnknownKey {
+            key: key.into(),
+            suggestion: closest_match(key, keys.iter().copied()).map(Into::into),
+            accepted: keys.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+}
+
+/// Explain how to fix the error, either suggesting the closest alternative or listing the
+/// accepted ones.
+fn hint(suggestion: &Option<String>, accepted: &[String], kind: &str) -> String {
+    if let Some(suggestion) = suggestion {
+        format!(", did you mean `{suggestion}`?")
+    } else if accepted.is_empty() {
+        String::new()
+    } else {
+        let accepted = accepted
+            .iter()
+            .map(|value| format!("`{value}`"))
+            .collect::<Vec<_>>();
+        format!(" (accepted {kind}: {})", accepted.join(", "))
+    }
+}
+
+/// Values accepted by the type of an argument, listed when an invalid one is provided. Types
+/// accepting free-form values list none.
+trait AcceptedValues {
+    fn accepted_values() -> &'static [&'static str] {
+        &[]
+    }
+}
+
+macro_rules! accepted_values {
+    ($($ty:ty),* $(,)?) => {$(
+        impl AcceptedValues for $ty {
+            fn accepted_values() -> &'static [&'static str] {
+                <$ty>::possible_values()
+            }
+        }
+    )*};
+}
+
+accepted_values!(Mode, CapLints, ComparisonPolicy);
+
+impl AcceptedValues for FollowUp {
+    fn accepted_values() -> &'static [&'static str] {
+        FollowUpKind::possible_values()
+    }
+}
+
+impl AcceptedValues for bool {
+    fn accepted_values() -> &'static [&'static str] {
+        &["true", "false"]
+    }
+}
+
+impl AcceptedValues for String {}
+impl AcceptedValues for Toolchain {}
+impl AcceptedValues for DeferredCrateSelect {}
+impl AcceptedValues for Assignee {}
+impl AcceptedValues for Deadline {}
+impl AcceptedValues for i32 {}
+impl AcceptedValues for u32 {}
+
+fn parse_value<T>(key: &str, value: &str) -> Result<T, CommandParseError>
+where
+    T: FromStr + AcceptedValues,
+    T::Err: Display,
+{
+    let invalid = |reason: String| CommandParseError::InvalidValue {
+        key: key.into(),
+        value: value.into(),
+        reason,
+        accepted: T::accepted_values().iter().map(|v| v.to_string()).collect(),
+    };
+    if value.is_empty() {
+        return Err(invalid("the value is empty".into()));
+    }
+    value
+        .parse()
+        .map_err(|err: T::Err| invalid(err.to_string()))
 }
 
 macro_rules! generate_parser {
     (pub enum $enum:ident {
-        $($command:pat => $variant:ident($var_struct:ident {
+        $($($command:literal)|+ => $variant:ident($var_struct:ident {
             $($flag:ident: $type:ty = $name:expr,)*
         }))*
         => $d_variant:ident($d_var_struct:ident {$($d_flag:ident: $d_type:ty = $d_name:expr,)*})
     }) => {
         use crate::prelude::*;
-        use std::str::FromStr;
         use crate::utils::string::split_quoted;
 
         $(
@@ -45,18 +158,26 @@ macro_rules! generate_parser {
         }
 
         #[allow(unused_variables, unused_mut)]
-        impl FromStr for $enum {
+        impl ::std::str::FromStr for $enum {
             type Err = ::anyhow::Error;
 
             fn from_str(input: &str) -> Fallible<$enum> {
                 let mut parts = split_quoted(input)?.into_iter().peekable();
                 Ok(match parts.peek().map(|s| s.as_str()) {
                     $(
-                        Some($command) => generate_parser!(@parser
+                        Some($($command)|+) => generate_parser!(@parser
                             parts.skip(1), $enum, $variant, $var_struct,
                             $($flag, $type, $name),*
                         ),
                     )*
+                    // Only the default command can be used without a name, so a first word not
+                    // looking like an argument is a misspelled command
+                    Some(command) if !command.is_empty() && !command.contains('=') => {
+                        return Err(CommandParseError::unknown_command(
+                            command,
+                            &[$($($command),+),*],
+                        ).into());
+                    }
                     Some(_) => generate_parser!(@parser
                         parts, $enum, $d_variant, $d_var_struct,
                         $($d_flag, $d_type, $d_name),*
@@ -87,13 +208,13 @@ macro_rules! generate_parser {
             if false {}
             $(else if key == $name {
                 if args.$flag.is_none() {
-                    args.$flag = Some(value.parse()?)
+                    args.$flag = Some(parse_value(key, value)?)
                 } else {
                     return Err(CommandParseError::DuplicateKey(key.to_string()).into());
                 }
             })*
             else {
-                return Err(CommandParseError::UnknownKey(key.to_string()).into());
+                return Err(CommandParseError::unknown_key(key, &[$($name),*]).into());
             }
         }
 
@@ -113,6 +234,22 @@ generate_parser!(pub enum Command {
         ignore_blacklist: Option<bool> = "ignore-blacklist",
         assign: Option<Assignee> = "assign",
         requirement: Option<String> = "requirement",
+        follow_up: Option<FollowUp> = "follow-up",
+        sandbox_image: Option<String> = "sandbox-image",
+        zulip_stream: Option<String> = "zulip-stream",
+        repeat: Option<u32> = "repeat",
+        include_ignored: Option<bool> = "include-ignored",
+        test_threads: Option<u32> = "test-threads",
+        test_filters: Option<String> = "test-filters",
+        deadline: Option<Deadline> = "deadline",
+        comparison_policy: Option<ComparisonPolicy> = "comparison",
+        overlay: Option<String> = "overlay",
+        build_cache: Option<bool> = "build-cache",
+        track_warnings: Option<bool> = "track-warnings",
+        client_side_report: Option<bool> = "client-side-report",
+        patches: Option<String> = "patches",
+        exclude: Option<String> = "exclude",
+        tags: Option<String> = "tags",
     })
 
     "check" => Check(CheckArgs {
@@ -125,12 +262,58 @@ generate_parser!(pub enum Command {
         ignore_blacklist: Option<bool> = "ignore-blacklist",
         assign: Option<Assignee> = "assign",
         requirement: Option<String> = "requirement",
+        follow_up: Option<FollowUp> = "follow-up",
+        sandbox_image: Option<String> = "sandbox-image",
+        zulip_stream: Option<String> = "zulip-stream",
+        deadline: Option<Deadline> = "deadline",
+        comparison_policy: Option<ComparisonPolicy> = "comparison",
+        overlay: Option<String> = "overlay",
+        build_cache: Option<bool> = "build-cache",
+        track_warnings: Option<bool> = "track-warnings",
+        client_side_report: Option<bool> = "client-side-report",
+        patches: Option<String> = "patches",
+        exclude: Option<String> = "exclude",
+        tags: Option<String> = "tags",
+    })
+
+    "clone" => Clone(CloneArgs {
+        ex: Option<String> = "ex",
+        name: Option<String> = "name",
+        start: Option<Toolchain> = "start",
+        end: Option<Toolchain> = "end",
+        mode: Option<Mode> = "mode",
+        crates: Option<DeferredCrateSelect> = "crates",
+        cap_lints: Option<CapLints> = "cap-lints",
+        priority: Option<i32> = "p",
+        ignore_blacklist: Option<bool> = "ignore-blacklist",
+        assign: Option<Assignee> = "assign",
+        requirement: Option<String> = "requirement",
+        follow_up: Option<FollowUp> = "follow-up",
+        sandbox_image: Option<String> = "sandbox-image",
+        zulip_stream: Option<String> = "zulip-stream",
+        repeat: Option<u32> = "repeat",
+        include_ignored: Option<bool> = "include-ignored",
+        test_threads: Option<u32> = "test-threads",
+        test_filters: Option<String> = "test-filters",
+        deadline: Option<Deadline> = "deadline",
+        comparison_policy: Option<ComparisonPolicy> = "comparison",
+        build_cache: Option<bool> = "build-cache",
+        track_warnings: Option<bool> = "track-warnings",
+        client_side_report: Option<bool> = "client-side-report",
+        patches: Option<String> = "patches",
+        exclude: Option<String> = "exclude",
+        tags: Option<String> = "tags",
     })
 
     "abort" | "cancel" => Abort(AbortArgs {
         name: Option<String> = "name",
     })
 
+    "priority" => Priority(PriorityArgs {
+        ex: Option<String> = "ex",
+        priority: Option<i32> = "p",
+    })
+
     "ping" => Ping(PingArgs {})
 
     "retry-report" => RetryReport(RetryReportArgs {
@@ -141,6 +324,18 @@ generate_parser!(pub enum Command {
         name: Option<String> = "name",
     })
 
+    "retry-spurious" => RetrySpurious(RetrySpuriousArgs {
+        name: Option<String> = "name",
+    })
+
+    "notify" => Notify(NotifyArgs {
+        name: Option<String> = "name",
+    })
+
+    "status" => Status(StatusArgs {
+        name: Option<String> = "name",
+    })
+
     "reload-acl" => ReloadACL(ReloadACLArgs {})
 
     => Edit(EditArgs {
@@ -154,12 +349,28 @@ generate_parser!(pub enum Command {
         ignore_blacklist: Option<bool> = "ignore-blacklist",
         assign: Option<Assignee> = "assign",
         requirement: Option<String> = "requirement",
+        follow_up: Option<FollowUp> = "follow-up",
+        sandbox_image: Option<String> = "sandbox-image",
+        zulip_stream: Option<String> = "zulip-stream",
+        repeat: Option<u32> = "repeat",
+        include_ignored: Option<bool> = "include-ignored",
+        test_threads: Option<u32> = "test-threads",
+        test_filters: Option<String> = "test-filters",
+        deadline: Option<Deadline> = "deadline",
+        comparison_policy: Option<ComparisonPolicy> = "comparison",
+        build_cache: Option<bool> = "build-cache",
+        track_warnings: Option<bool> = "track-warnings",
+        client_side_report: Option<bool> = "client-side-report",
+        patches: Option<String> = "patches",
+        exclude: Option<String> = "exclude",
+        tags: Option<String> = "tags",
     })
 });
 
 #[cfg(test)]
 mod tests {
-    use super::CommandParseError;
+    use super::{parse_value, CommandParseError};
+    use crate::experiments::Mode;
 
     // Use a simpler parser for tests
     generate_parser!(pub enum TestCommand {
@@ -240,8 +451,62 @@ mod tests {
 
         // Test if invalid args are rejected
         test!(fail "foo arg1=98 arg1=42", CommandParseError::DuplicateKey("arg1".into()));
-        test!(fail "bar arg1=98", CommandParseError::UnknownKey("arg1".into()));
-        test!(fail "foo arg4=42", CommandParseError::UnknownKey("arg4".into()));
+        test!(fail "bar arg1=98", CommandParseError::UnknownKey {
+            key: "arg1".into(),
+            suggestion: Some("arg3".into()),
+            accepted: vec!["arg3".into()],
+        });
+        test!(fail "foo argument=42", CommandParseError::UnknownKey {
+            key: "argument".into(),
+            suggestion: None,
+            accepted: vec!["arg1".into(), "arg2".into()],
+        });
         test!(fail "foo bar", CommandParseError::InvalidArgument("bar".into()));
+        test!(fail "fo arg1=98", CommandParseError::UnknownCommand {
+            command: "fo".into(),
+            suggestion: Some("foo".into()),
+        });
+        test!(fail "qux", CommandParseError::UnknownCommand {
+            command: "qux".into(),
+            suggestion: None,
+        });
+        test!(fail "foo arg1=abc", CommandParseError::InvalidValue {
+            key: "arg1".into(),
+            value: "abc".into(),
+            reason: "invalid digit found in string".into(),
+            accepted: Vec::new(),
+        });
+        test!(fail "foo arg2=", CommandParseError::InvalidValue {
+            key: "arg2".into(),
+            value: "".into(),
+            reason: "the value is empty".into(),
+            accepted: Vec::new(),
+        });
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            "bar arg_3=1"
+                .parse::<TestCommand>()
+                .unwrap_err()
+                .to_string(),
+            "unknown key `arg_3`, did you mean `arg3`?"
+        );
+        assert_eq!(
+            "foo argument=1"
+                .parse::<TestCommand>()
+                .unwrap_err()
+                .to_string(),
+            "unknown key `argument` (accepted keys: `arg1`, `arg2`)"
+        );
+        assert_eq!(
+            parse_value::<Mode>("mode", "build")
+                .unwrap_err()
+                .to_string(),
+            "invalid value `build` for `mode`: invalid Mode: build (accepted values: \
+             `build-and-test`, `build-test-and-doctest`, `build-only`, `check-only`, `clippy`, \
+             `rustdoc`, `unstable-features`, `reproducibility`)"
+        );
     }
 }
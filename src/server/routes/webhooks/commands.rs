@@ -1,20 +1,27 @@
 use crate::actions::{self, Action, ActionsCtx};
+use crate::crates::{CratePattern, DependencyPatch, PatchSource};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::{CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status};
+use crate::experiments::{
+    CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status, TestOptions,
+};
 use crate::prelude::*;
-use crate::server::github::{GitHub, Issue, Repository};
-use crate::server::messages::{Label, Message};
+use crate::report::ReportProgress;
+use crate::server::audit;
+use crate::server::messages::{self, Event, Label, Message};
 use crate::server::routes::webhooks::args::{
-    AbortArgs, CheckArgs, EditArgs, RetryArgs, RetryReportArgs, RunArgs,
+    AbortArgs, CheckArgs, CloneArgs, EditArgs, NotifyArgs, PriorityArgs, RetryArgs,
+    RetryReportArgs, RetrySpuriousArgs, RunArgs, StatusArgs,
 };
-use crate::server::{Data, GithubData};
+use crate::server::subscriptions::{self, SubscriberKind};
+use crate::server::vcs::{Issue, Repository};
+use crate::server::{BotData, Data};
 use crate::toolchain::Toolchain;
 use rustwide::Toolchain as RustwideToolchain;
 
-pub fn ping(data: &Data, github_data: &GithubData, issue: &Issue) -> Fallible<()> {
+pub fn ping(data: &Data, bot_data: &BotData, issue: &Issue) -> Fallible<()> {
     Message::new()
         .line("ping_pong", "**Pong!**")
-        .send(&issue.url, data, github_data)?;
+        .send(&issue.url, data, bot_data)?;
 
     Ok(())
 }
@@ -22,7 +29,7 @@ pub fn ping(data: &Data, github_data: &GithubData, issue: &Issue) -> Fallible<()
 pub fn check(
     host: &str,
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
     repo: &Repository,
     issue: &Issue,
     args: CheckArgs,
@@ -30,7 +37,7 @@ pub fn check(
     run(
         host,
         data,
-        github_data,
+        bot_data,
         repo,
         issue,
         RunArgs {
@@ -44,6 +51,22 @@ pub fn check(
             ignore_blacklist: args.ignore_blacklist,
             assign: args.assign,
             requirement: args.requirement,
+            follow_up: args.follow_up,
+            sandbox_image: args.sandbox_image,
+            zulip_stream: args.zulip_stream,
+            repeat: None,
+            include_ignored: None,
+            test_threads: None,
+            test_filters: None,
+            deadline: args.deadline,
+            comparison_policy: args.comparison_policy,
+            overlay: args.overlay,
+            build_cache: args.build_cache,
+            track_warnings: args.track_warnings,
+            client_side_report: args.client_side_report,
+            patches: args.patches,
+            exclude: args.exclude,
+            tags: args.tags,
         },
     )
 }
@@ -51,7 +74,7 @@ pub fn check(
 pub fn run(
     host: &str,
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
     repo: &Repository,
     issue: &Issue,
     args: RunArgs,
@@ -75,6 +98,7 @@ pub fn run(
                 rustflags: None,
                 rustdocflags: None,
                 cargoflags: None,
+                build_std: None,
                 ci_try: false,
                 patches: Vec::new(),
             });
@@ -84,6 +108,7 @@ pub fn run(
                 rustflags: None,
                 rustdocflags: None,
                 cargoflags: None,
+                build_std: None,
                 ci_try: true,
                 patches: Vec::new(),
             });
@@ -91,12 +116,10 @@ pub fn run(
                 "robot",
                 format!("Automatically detected try build {}", build.merge_sha),
             );
-            let pr_head = github_data
+            let pr_head = bot_data
                 .api
                 .get_pr_head_sha(&repo.full_name, issue.number)?;
-            let mut merge_commit = github_data
-                .api
-                .get_commit(&repo.full_name, &build.merge_sha)?;
+            let mut merge_commit = bot_data.api.get_commit(&repo.full_name, &build.merge_sha)?;
             if merge_commit.parents.len() == 2 {
                 // The first parent is the rust-lang/rust commit, and the second
                 // parent (index 1) is the PR commit
@@ -126,7 +149,7 @@ pub fn run(
         .transpose()
         .map_err(|e| e.context("Failed to resolve crate list"))?;
 
-    actions::CreateExperiment {
+    let create = actions::CreateExperiment {
         name: name.clone(),
         toolchains: [
             args.start
@@ -148,22 +171,99 @@ pub fn run(
         ignore_blacklist: args.ignore_blacklist.unwrap_or(false),
         assign: args.assign,
         requirement: Some(requirement),
+        follow_up: args.follow_up,
+        parent: None,
+        sandbox_image: args.sandbox_image,
+        zulip_stream: args.zulip_stream,
+        repeat: args.repeat,
+        test_options: TestOptions {
+            include_ignored: args.include_ignored.unwrap_or(false),
+            test_threads: args.test_threads,
+            filters: args
+                .test_filters
+                .as_deref()
+                .map(parse_list)
+                .unwrap_or_default(),
+        },
+        deadline: args.deadline,
+        comparison_policy: args.comparison_policy.unwrap_or_default(),
+        // Downloading the overlaid crates can take a while, so they're resolved in the background
+        registry_overlay: Vec::new(),
+        dependency_patches: args
+            .patches
+            .as_deref()
+            .map(parse_patches)
+            .transpose()?
+            .unwrap_or_default(),
+        exclude: args
+            .exclude
+            .as_deref()
+            .map(parse_patterns)
+            .transpose()?
+            .unwrap_or_default(),
+        tags: args.tags.as_deref().map(parse_list).unwrap_or_default(),
+        build_cache: args.build_cache.unwrap_or(false),
+        track_warnings: args.track_warnings.unwrap_or(false),
+        client_side_report: args.client_side_report.unwrap_or(false),
+    };
+    let overlay_urls = args.overlay.as_deref().map(parse_list).unwrap_or_default();
+    crate::server::components::ensure_available(&*bot_data.api, &create.toolchains, create.mode)?;
+
+    if let CrateSelect::Full | CrateSelect::FullActive = create.crates {
+        Message::new()
+            .line(
+                "hourglass",
+                format!(
+                    "Creating experiment **`{name}`** with all the crates, this can take a few \
+                     minutes..."
+                ),
+            )
+            .send(&issue.url, data, bot_data)?;
     }
-    .apply(&ActionsCtx::new(&data.db, &data.config))?;
 
-    message
-        .line(
-            "mag",
+    // Inserting the crates of large experiments takes a while, which only delays the next commands
+    // in the webhook queue: the webhook itself was already answered
+    let message = message.line(
+        "mag",
+        format!(
+            "You can check out [the queue](https://{host}) and [this experiment's details](https://{host}/ex/{name})."
+        ),
+    );
+    create_experiment(data, bot_data, &issue.url, create, &overlay_urls, message)
+}
+
+/// Create the experiment, posting the message once it's queued.
+fn create_experiment(
+    data: &Data,
+    bot_data: &BotData,
+    issue_url: &str,
+    mut create: actions::CreateExperiment,
+    overlay_urls: &[String],
+    mut message: Message,
+) -> Fallible<()> {
+    let name = create.name.clone();
+    if !overlay_urls.is_empty() {
+        create.registry_overlay = crate::crates::resolve_overlay(overlay_urls)?;
+        message = message.line(
+            "package",
             format!(
-                "You can check out [the queue](https://{host}) and [this experiment's details](https://{host}/ex/{name})."
+                "The builds will use {} instead of the crates.io versions.",
+                crate::crates::describe_overlay(&create.registry_overlay)
             ),
-        ).set_label(Label::ExperimentQueued)
-        .send(&issue.url, data,github_data)?;
+        );
+    }
+    create.apply(&ActionsCtx::new(&data.db, &data.config))?;
 
-    Ok(())
+    if let Some(ex) = Experiment::get(&data.db, &name)? {
+        messages::notify(data, &ex, Event::ExperimentQueued);
+    }
+
+    message
+        .set_label(Label::ExperimentQueued)
+        .send(issue_url, data, bot_data)
 }
 
-pub fn edit(data: &Data, github_data: &GithubData, issue: &Issue, args: EditArgs) -> Fallible<()> {
+pub fn edit(data: &Data, bot_data: &BotData, issue: &Issue, args: EditArgs) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
 
     let crates = args
@@ -172,6 +272,20 @@ pub fn edit(data: &Data, github_data: &GithubData, issue: &Issue, args: EditArgs
         .transpose()
         .map_err(|e| e.context("Failed to resolve crate list"))?;
 
+    if let Some(ex) = Experiment::get(&data.db, &name)? {
+        let toolchains = [
+            args.start
+                .clone()
+                .unwrap_or_else(|| ex.toolchains[0].clone()),
+            args.end.clone().unwrap_or_else(|| ex.toolchains[1].clone()),
+        ];
+        crate::server::components::ensure_available(
+            &*bot_data.api,
+            &toolchains,
+            args.mode.unwrap_or(ex.mode),
+        )?;
+    }
+
     actions::EditExperiment {
         name: name.clone(),
         toolchains: [args.start, args.end],
@@ -182,6 +296,21 @@ pub fn edit(data: &Data, github_data: &GithubData, issue: &Issue, args: EditArgs
         ignore_blacklist: args.ignore_blacklist,
         assign: args.assign,
         requirement: args.requirement,
+        follow_up: args.follow_up,
+        sandbox_image: args.sandbox_image,
+        zulip_stream: args.zulip_stream,
+        repeat: args.repeat,
+        include_ignored: args.include_ignored,
+        test_threads: args.test_threads,
+        test_filters: args.test_filters.as_deref().map(parse_list),
+        deadline: args.deadline,
+        comparison_policy: args.comparison_policy,
+        build_cache: args.build_cache,
+        track_warnings: args.track_warnings,
+        client_side_report: args.client_side_report,
+        dependency_patches: args.patches.as_deref().map(parse_patches).transpose()?,
+        exclude: args.exclude.as_deref().map(parse_patterns).transpose()?,
+        tags: args.tags.as_deref().map(parse_list),
     }
     .apply(&ActionsCtx::new(&data.db, &data.config))?;
 
@@ -190,14 +319,100 @@ pub fn edit(data: &Data, github_data: &GithubData, issue: &Issue, args: EditArgs
             "memo",
             format!("Configuration of the **`{name}`** experiment changed."),
         )
-        .send(&issue.url, data, github_data)?;
+        .send(&issue.url, data, bot_data)?;
 
     Ok(())
 }
 
+pub fn clone(
+    host: &str,
+    data: &Data,
+    bot_data: &BotData,
+    issue: &Issue,
+    args: CloneArgs,
+) -> Fallible<()> {
+    // The experiment being cloned defaults to the last one of the issue, like the other commands
+    let source = match args.ex {
+        Some(ex) => ex,
+        None => default_experiment_name(&data.db, issue)?
+            .ok_or_else(|| anyhow!("missing experiment to clone"))?,
+    };
+    let Some(ex) = Experiment::get(&data.db, &source)? else {
+        bail!("an experiment named **`{}`** doesn't exist!", source);
+    };
+    let name = setup_run_name(&data.db, issue, args.name)?;
+
+    let mut create = actions::CreateExperiment::copy_of(&ex, &name);
+    create.github_issue = Some(GitHubIssue {
+        api_url: issue.url.clone(),
+        html_url: issue.html_url.clone(),
+        number: issue.number,
+    });
+    if let Some(start) = args.start {
+        create.toolchains[0] = start;
+    }
+    if let Some(end) = args.end {
+        create.toolchains[1] = end;
+    }
+    if let Some(crates) = args.crates {
+        create.crates = crates
+            .resolve()
+            .map_err(|e| e.context("Failed to resolve crate list"))?;
+    }
+    create.mode = args.mode.unwrap_or(create.mode);
+    create.cap_lints = args.cap_lints.unwrap_or(create.cap_lints);
+    create.priority = args.priority.unwrap_or(create.priority);
+    create.ignore_blacklist = args.ignore_blacklist.unwrap_or(create.ignore_blacklist);
+    create.assign = args.assign;
+    create.requirement = args.requirement.or(create.requirement);
+    create.follow_up = args.follow_up.or(create.follow_up);
+    create.sandbox_image = args.sandbox_image.or(create.sandbox_image);
+    create.zulip_stream = args.zulip_stream.or(create.zulip_stream);
+    create.repeat = args.repeat.or(create.repeat);
+    if let Some(include_ignored) = args.include_ignored {
+        create.test_options.include_ignored = include_ignored;
+    }
+    if let Some(test_threads) = args.test_threads {
+        create.test_options.test_threads = Some(test_threads);
+    }
+    if let Some(filters) = args.test_filters {
+        create.test_options.filters = parse_list(&filters);
+    }
+    create.deadline = args.deadline.or(create.deadline);
+    create.comparison_policy = args.comparison_policy.unwrap_or(create.comparison_policy);
+    create.build_cache = args.build_cache.unwrap_or(create.build_cache);
+    create.track_warnings = args.track_warnings.unwrap_or(create.track_warnings);
+    create.client_side_report = args.client_side_report.unwrap_or(create.client_side_report);
+    if let Some(patches) = args.patches {
+        create.dependency_patches = parse_patches(&patches)?;
+    }
+    if let Some(exclude) = args.exclude {
+        create.exclude = parse_patterns(&exclude)?;
+    }
+    if let Some(tags) = args.tags {
+        create.tags = parse_list(&tags);
+    }
+    crate::server::components::ensure_available(&*bot_data.api, &create.toolchains, create.mode)?;
+
+    // Copying the crates of large experiments takes a while, which only delays the next commands
+    // in the webhook queue like the experiments defined with `run`
+    let message = Message::new()
+        .line(
+            "ok_hand",
+            format!("Experiment **`{name}`** created and queued, as a copy of **`{source}`**."),
+        )
+        .line(
+            "mag",
+            format!(
+                "You can check out [the queue](https://{host}) and [this experiment's details](https://{host}/ex/{name})."
+            ),
+        );
+    create_experiment(data, bot_data, &issue.url, create, &[], message)
+}
+
 pub fn retry_report(
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
     issue: &Issue,
     args: RetryReportArgs,
 ) -> Fallible<()> {
@@ -222,7 +437,7 @@ pub fn retry_report(
                 format!("Generation of the report for **`{name}`** queued again."),
             )
             .set_label(Label::ExperimentQueued)
-            .send(&issue.url, data, github_data)?;
+            .send(&issue.url, data, bot_data)?;
 
         Ok(())
     } else {
@@ -230,12 +445,7 @@ pub fn retry_report(
     }
 }
 
-pub fn retry(
-    data: &Data,
-    github_data: &GithubData,
-    issue: &Issue,
-    args: RetryArgs,
-) -> Fallible<()> {
+pub fn retry(data: &Data, bot_data: &BotData, issue: &Issue, args: RetryArgs) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
 
     if let Some(mut experiment) = Experiment::get(&data.db, &name)? {
@@ -248,7 +458,7 @@ pub fn retry(
                 format!("Experiment **`{name}`** queued again."),
             )
             .set_label(Label::ExperimentQueued)
-            .send(&issue.url, data, github_data)?;
+            .send(&issue.url, data, bot_data)?;
 
         Ok(())
     } else {
@@ -256,31 +466,187 @@ pub fn retry(
     }
 }
 
-pub fn abort(
+pub fn retry_spurious(
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
     issue: &Issue,
-    args: AbortArgs,
+    args: RetrySpuriousArgs,
 ) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
 
-    actions::DeleteExperiment { name: name.clone() }
-        .apply(&ActionsCtx::new(&data.db, &data.config))?;
+    let crates = actions::RetrySpurious { name: name.clone() }.requeue(
+        &ActionsCtx::new(&data.db, &data.config).with_log_store(data.log_store.as_deref()),
+    )?;
+
+    let mut message = Message::new();
+    if crates.is_empty() {
+        message = message.line(
+            "information_source",
+            format!("Experiment **`{name}`** has no spurious results to retry."),
+        );
+    } else {
+        message = message
+            .line(
+                "hammer_and_wrench",
+                format!(
+                    "{} crates with spurious results in **`{name}`** queued again.",
+                    crates.len()
+                ),
+            )
+            .set_label(Label::ExperimentQueued);
+    }
+    message.send(&issue.url, data, bot_data)?;
+
+    Ok(())
+}
+
+pub fn abort(data: &Data, bot_data: &BotData, issue: &Issue, args: AbortArgs) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+
+    actions::DeleteExperiment { name: name.clone() }.apply(
+        &ActionsCtx::new(&data.db, &data.config).with_log_store(data.log_store.as_deref()),
+    )?;
 
     Message::new()
         .line("wastebasket", format!("Experiment **`{name}`** deleted!"))
         .set_label(Label::ExperimentCompleted)
-        .send(&issue.url, data, github_data)?;
+        .send(&issue.url, data, bot_data)?;
+
+    Ok(())
+}
+
+pub fn priority(
+    data: &Data,
+    bot_data: &BotData,
+    issue: &Issue,
+    sender: &str,
+    args: PriorityArgs,
+) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.ex)?;
+    let priority = args
+        .priority
+        .ok_or_else(|| anyhow!("missing the new priority (`p=<n>`)"))?;
+    let mut experiment = match Experiment::get(&data.db, &name)? {
+        Some(experiment) => experiment,
+        None => bail!("an experiment named **`{}`** doesn't exist!", name),
+    };
+    // Agents only pick up queued and running experiments, so it wouldn't have any effect
+    if !matches!(experiment.status, Status::Queued | Status::Running) {
+        bail!(
+            "the priority of **`{}`** can only be changed while it's queued or running",
+            name
+        );
+    }
+
+    let old = experiment.priority;
+    data.db.transaction(true, |t| {
+        experiment.set_priority(t, priority)?;
+        audit::record(
+            t,
+            sender,
+            "set-priority",
+            Some(name.as_str()),
+            Some(&format!("{old} -> {priority}")),
+        )
+    })?;
+
+    let mut message = Message::new().line(
+        "arrows_counterclockwise",
+        format!("Priority of **`{name}`** changed from {old} to {priority}."),
+    );
+    if let Some(position) = experiment.queue_position(&data.db)? {
+        message = message.line(
+            "bar_chart",
+            format!("It's now number {position} in the queue."),
+        );
+    }
+    message.send(&issue.url, data, bot_data)?;
+
+    Ok(())
+}
+
+pub fn notify(
+    data: &Data,
+    bot_data: &BotData,
+    issue: &Issue,
+    sender: &str,
+    args: NotifyArgs,
+) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+    if !Experiment::exists(&data.db, &name)? {
+        bail!("an experiment named **`{}`** doesn't exist!", name);
+    }
+
+    let message = if subscriptions::subscribe(&data.db, &name, SubscriberKind::GitHub, sender)? {
+        format!("**{sender}** will be mentioned when the report of **`{name}`** is ready.")
+    } else {
+        format!("**{sender}** is already subscribed to **`{name}`**.")
+    };
+    Message::new()
+        .line("bell", message)
+        .send(&issue.url, data, bot_data)?;
+
+    Ok(())
+}
+
+pub fn status(data: &Data, bot_data: &BotData, issue: &Issue, args: StatusArgs) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+    let experiment = match Experiment::get(&data.db, &name)? {
+        Some(experiment) => experiment,
+        None => bail!("an experiment named **`{}`** doesn't exist!", name),
+    };
+
+    let mut message = Message::new().line(
+        "information_source",
+        format!(
+            "Experiment **`{name}`** is `{}`.",
+            experiment.status.to_str()
+        ),
+    );
+    match experiment.status {
+        Status::Queued | Status::Running => {
+            let (completed, all) = experiment.raw_progress(&data.db)?;
+            message = message.line(
+                "bar_chart",
+                format!(
+                    "{completed} out of {all} jobs are done ({}%).",
+                    experiment.progress(&data.db)?
+                ),
+            );
+            if let Some(position) = experiment.queue_position(&data.db)? {
+                message =
+                    message.line("hourglass", format!("It's number {position} in the queue."));
+            }
+        }
+        Status::GeneratingReport => {
+            if let Some(progress) = ReportProgress::get(&data.db, &experiment)? {
+                message = message.line("memo", format!("Generating the report: {progress}."));
+            }
+        }
+        Status::Completed => {
+            if let Some(url) = &experiment.report_url {
+                message = message.line("newspaper", format!("[Open the full report]({url})."));
+            }
+        }
+        Status::ToolchainUnavailable => {
+            message = message.line(
+                "hammer_and_wrench",
+                "One of its toolchains is not available, edit them to queue it again.",
+            );
+        }
+        Status::NeedsReport | Status::ReportFailed => {}
+    }
+    message.send(&issue.url, data, bot_data)?;
 
     Ok(())
 }
 
-pub fn reload_acl(data: &Data, github_data: &GithubData, issue: &Issue) -> Fallible<()> {
-    data.acl.refresh_cache(&github_data.api)?;
+pub fn reload_acl(data: &Data, bot_data: &BotData, issue: &Issue) -> Fallible<()> {
+    data.acl.refresh_cache(&*bot_data.api)?;
 
     Message::new()
         .line("hammer_and_wrench", "List of authorized users reloaded!")
-        .send(&issue.url, data, github_data)?;
+        .send(&issue.url, data, bot_data)?;
 
     Ok(())
 }
@@ -350,20 +716,68 @@ fn generate_new_experiment_name(db: &Database, issue: &Issue) -> Fallible<String
     Ok(name)
 }
 
+/// Split the comma-separated lists of arguments like `test-filters`.
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parse a comma-separated list of dependency patches. Directories only exist on the machine
+/// running local experiments, so the bot only accepts git patches.
+fn parse_patches(list: &str) -> Fallible<Vec<DependencyPatch>> {
+    parse_list(list)
+        .iter()
+        .map(|item| {
+            let patch: DependencyPatch = item.parse()?;
+            if let PatchSource::Path { .. } = patch.source {
+                bail!("`{}` is not a git repository, which patches must be", item);
+            }
+            Ok(patch)
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of crate exclusion patterns. Regular expressions can contain
+/// commas, like `/^a{1,3}$/`, so the list is not split between the slashes enclosing them.
+fn parse_patterns(list: &str) -> Fallible<Vec<CratePattern>> {
+    let mut items: Vec<String> = Vec::new();
+    let mut in_regex = false;
+    for piece in list.split(',') {
+        match items.last_mut() {
+            Some(item) if in_regex => {
+                item.push(',');
+                item.push_str(piece);
+            }
+            _ => items.push(piece.to_string()),
+        }
+        let item = items.last().unwrap().trim();
+        in_regex = item.starts_with('/') && (item.len() == 1 || !item.ends_with('/'));
+    }
+    items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| Ok(item.parse()?))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        default_experiment_name, generate_new_experiment_name, get_name, setup_run_name,
-        store_experiment_name,
+        default_experiment_name, generate_new_experiment_name, get_name, parse_patches,
+        parse_patterns, setup_run_name, store_experiment_name,
     };
     use crate::actions::{self, Action, ActionsCtx};
     use crate::config::Config;
     use crate::db::Database;
     use crate::prelude::*;
-    use crate::server::github;
+    use crate::server::vcs;
 
     /// Simulate to the `run` command, and return experiment name
-    fn dummy_run(db: &Database, issue: &github::Issue, name: Option<String>) -> Fallible<String> {
+    fn dummy_run(db: &Database, issue: &vcs::Issue, name: Option<String>) -> Fallible<String> {
         let config = Config::default();
         let ctx = ActionsCtx::new(db, &config);
         let name = setup_run_name(db, issue, name)?;
@@ -372,7 +786,7 @@ mod tests {
     }
 
     /// Simulate to the `edit` command, and return experiment name
-    fn dummy_edit(db: &Database, issue: &github::Issue, name: Option<String>) -> Fallible<String> {
+    fn dummy_edit(db: &Database, issue: &vcs::Issue, name: Option<String>) -> Fallible<String> {
         let config = Config::default();
         let ctx = ActionsCtx::new(db, &config);
         let name = get_name(db, issue, name)?;
@@ -385,7 +799,7 @@ mod tests {
         let db = Database::temp().unwrap();
 
         // With simple issues no default should be used
-        let issue = github::Issue {
+        let issue = vcs::Issue {
             number: 1,
             url: String::new(),
             html_url: String::new(),
@@ -395,12 +809,12 @@ mod tests {
         assert!(default_experiment_name(&db, &issue).unwrap().is_none());
 
         // With pull requests pr-{number} should be used
-        let pr = github::Issue {
+        let pr = vcs::Issue {
             number: 2,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -421,12 +835,12 @@ mod tests {
     fn test_run() {
         let db = Database::temp().unwrap();
 
-        let pr1 = github::Issue {
+        let pr1 = vcs::Issue {
             number: 1,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -438,12 +852,12 @@ mod tests {
         // make sure it fails the second time
         assert!(dummy_run(&db, &pr1, Some("pr-1".to_owned())).is_err(),);
 
-        let pr2 = github::Issue {
+        let pr2 = vcs::Issue {
             number: 2,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -478,12 +892,12 @@ mod tests {
         let db = Database::temp().unwrap();
 
         // test retrieval of name generated in a supplied-name run
-        let pr1 = github::Issue {
+        let pr1 = vcs::Issue {
             number: 1,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -497,12 +911,12 @@ mod tests {
         );
 
         // test retrieval of name generated in an auto-generated run
-        let pr2 = github::Issue {
+        let pr2 = vcs::Issue {
             number: 2,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -537,12 +951,12 @@ mod tests {
         let config = Config::default();
         let ctx = ActionsCtx::new(&db, &config);
 
-        let pr = github::Issue {
+        let pr = vcs::Issue {
             number: 12345,
             url: String::new(),
             html_url: String::new(),
             labels: Vec::new(),
-            pull_request: Some(github::PullRequest {
+            pull_request: Some(vcs::PullRequest {
                 html_url: String::new(),
             }),
         };
@@ -560,4 +974,29 @@ mod tests {
             "pr-12345-2"
         );
     }
+
+    #[test]
+    fn test_parse_patches() {
+        let patches = parse_patches(
+            "serde=https://github.com/serde-rs/serde=fix, syn=https://github.com/dtolnay/syn=v2",
+        )
+        .unwrap();
+        assert_eq!(
+            patches.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            ["serde", "syn"]
+        );
+
+        assert!(parse_patches("serde=/opt/serde").is_err());
+        assert!(parse_patches("serde").is_err());
+    }
+
+    #[test]
+    fn test_parse_patterns() {
+        let patterns = parse_patterns("win-*, /^a{1,3}$/,*-sys,").unwrap();
+        assert_eq!(
+            patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            ["win-*", "/^a{1,3}$/", "*-sys"]
+        );
+        assert!(parse_patterns("").unwrap().is_empty());
+    }
 }
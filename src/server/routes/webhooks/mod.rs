@@ -2,73 +2,155 @@ mod args;
 mod commands;
 
 use crate::prelude::*;
-use crate::server::github::{EventIssueComment, Issue, Repository};
 use crate::server::messages::Message;
 use crate::server::routes::webhooks::args::Command;
-use crate::server::{Data, GithubData};
+use crate::server::vcs::{CommentEvent, Issue, Repository, WebhookEvent};
+use crate::server::{BotData, Data, HttpError};
+use anyhow::Error;
 use bytes::Bytes;
-use hmac::{Hmac, Mac};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
 use http::{HeaderMap, Response, StatusCode};
 use hyper::Body;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::thread;
 use warp::{Filter, Rejection};
 
+/// Number of comments waiting to be processed, after which new webhooks are refused.
+const QUEUE_SIZE: usize = 128;
+
+/// Queue of the comments received through webhooks. Processing a command can take a while (for
+/// example creating an experiment or calling the VCS host API), so the webhook endpoint only
+/// verifies the event and queues it, and the commands are executed by a background thread.
+#[derive(Clone)]
+pub struct WebhookQueue {
+    // String is the host the webhook was received on
+    sender: Sender<(String, CommentEvent)>,
+    receiver: Receiver<(String, CommentEvent)>,
+}
+
+impl WebhookQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE_SIZE);
+        WebhookQueue { sender, receiver }
+    }
+
+    pub fn spawn(&self, data: Data, bot_data: Option<BotData>) {
+        let Some(bot_data) = bot_data else {
+            return;
+        };
+        let receiver = self.receiver.clone();
+        thread::Builder::new()
+            .name(String::from("webhooks-crater"))
+            .spawn(move || {
+                for (host, event) in receiver.iter() {
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                        process_comment(&host, &event, &data, &bot_data)
+                    }))
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!("the bot crashed while processing the command"))
+                    });
+                    if let Err(e) = result {
+                        error!(
+                            "error while processing the comment on {}",
+                            event.issue.html_url
+                        );
+                        crate::utils::report_failure(&e);
+                        // The sender of the comment is not waiting for the webhook response anymore
+                        if let Err(e) = send_error(&e, &event.issue.url, &data, &bot_data) {
+                            crate::utils::report_failure(&e);
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn the webhooks thread");
+    }
+
+    /// Queue the comment, replying on its issue if it contains a command the queue has no room
+    /// for. Comments are refused in read-only mode, as the queue isn't processed then.
+    fn enqueue(
+        &self,
+        host: &str,
+        event: CommentEvent,
+        data: &Data,
+        bot_data: &BotData,
+    ) -> Fallible<()> {
+        data.writable_db()?;
+        match self.sender.try_send((host.to_string(), event)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full((_, event))) => {
+                let start = format!("@{} ", bot_data.bot_username);
+                if event.body.lines().any(|line| line.starts_with(&start)) {
+                    Message::new()
+                        .line(
+                            "hourglass",
+                            "**Error:** too many commands are waiting to be processed, please \
+                             send the command again later.",
+                        )
+                        .send(&event.issue.url, data, bot_data)?;
+                }
+                bail!("too many webhooks are waiting to be processed");
+            }
+            Err(TrySendError::Disconnected(_)) => unreachable!(),
+        }
+    }
+}
+
 fn process_webhook(
     payload: &[u8],
     host: &str,
-    signature: &str,
-    event: &str,
+    headers: &HeaderMap,
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
 ) -> Fallible<()> {
-    if !verify_signature(&github_data.tokens.webhooks_secret, payload, signature) {
-        bail!("invalid signature for the webhook!");
-    }
+    let event = bot_data
+        .api
+        .parse_webhook(&bot_data.tokens.webhooks_secret, headers, payload)?;
 
     match event {
-        "ping" => info!("the webhook is configured correctly!"),
-        "issue_comment" => {
-            let p: EventIssueComment = serde_json::from_slice(payload)?;
+        WebhookEvent::Ping => info!("the webhook is configured correctly!"),
+        WebhookEvent::Ignored => {}
+        WebhookEvent::Comment(p) => data.webhook_queue.enqueue(host, p, data, bot_data)?,
+    }
 
-            // Only process "created" events, and ignore when a comment is edited or deleted
-            if p.action != "created" {
-                return Ok(());
-            }
+    Ok(())
+}
 
-            crate::server::try_builds::detect(
-                &data.db,
-                &github_data.api,
-                &p.repository.full_name,
-                p.issue.number,
-                &p.comment.body,
-            )?;
-
-            if let Err(e) = process_command(
-                host,
-                &p.sender.login,
-                p.sender.id,
-                &p.comment.body,
-                &p.repository,
-                &p.issue,
-                data,
-                github_data,
-            ) {
-                Message::new()
-                    .line("rotating_light", format!("**Error:** {e}"))
-                    .note(
-                        "sos",
-                        "If you have any trouble with Crater please ping **`@rust-lang/infra`**!",
-                    )
-                    .send(&p.issue.url, data, github_data)?;
-            }
-        }
-        e => bail!("invalid event received: {}", e),
+fn process_comment(host: &str, p: &CommentEvent, data: &Data, bot_data: &BotData) -> Fallible<()> {
+    crate::server::try_builds::detect(
+        &data.db,
+        &*bot_data.api,
+        &p.repository.full_name,
+        p.issue.number,
+        &p.body,
+    )?;
+
+    if let Err(e) = process_command(
+        host,
+        &p.sender.login,
+        p.sender.id,
+        &p.body,
+        &p.repository,
+        &p.issue,
+        data,
+        bot_data,
+    ) {
+        send_error(&e, &p.issue.url, data, bot_data)?;
     }
 
     Ok(())
 }
 
+fn send_error(err: &Error, issue_url: &str, data: &Data, bot_data: &BotData) -> Fallible<()> {
+    Message::new()
+        .line("rotating_light", format!("**Error:** {err}"))
+        .note(
+            "sos",
+            "If you have any trouble with Crater please ping **`@rust-lang/infra`**!",
+        )
+        .send(issue_url, data, bot_data)
+}
+
 fn process_command(
     host: &str,
     sender: &str,
@@ -77,9 +159,9 @@ fn process_command(
     repo: &Repository,
     issue: &Issue,
     data: &Data,
-    github_data: &GithubData,
+    bot_data: &BotData,
 ) -> Fallible<()> {
-    let start = format!("@{} ", github_data.bot_username);
+    let start = format!("@{} ", bot_data.bot_username);
     for line in body.lines() {
         if !line.starts_with(&start) {
             continue;
@@ -104,46 +186,66 @@ fn process_command(
                         crate::CRATER_REPO_URL,
                     ),
                 )
-                .send(&issue.url, data, github_data)?;
+                .send(&issue.url, data, bot_data)?;
             return Ok(());
         }
 
         info!("user @{} sent command: {}", sender, command);
 
-        let args: Command =
-            Command::from_str(command).with_context(|| "failed to parse the command")?;
+        // The parse errors explain what's wrong with the command, and are posted as-is
+        let args = Command::from_str(command)?;
 
         match args {
             Command::Ping(_) => {
-                commands::ping(data, github_data, issue)?;
+                commands::ping(data, bot_data, issue)?;
             }
 
             Command::Run(args) => {
-                commands::run(host, data, github_data, repo, issue, args)?;
+                commands::run(host, data, bot_data, repo, issue, args)?;
             }
 
             Command::Check(args) => {
-                commands::check(host, data, github_data, repo, issue, args)?;
+                commands::check(host, data, bot_data, repo, issue, args)?;
             }
 
             Command::Edit(args) => {
-                commands::edit(data, github_data, issue, args)?;
+                commands::edit(data, bot_data, issue, args)?;
+            }
+
+            Command::Clone(args) => {
+                commands::clone(host, data, bot_data, issue, args)?;
             }
 
             Command::RetryReport(args) => {
-                commands::retry_report(data, github_data, issue, args)?;
+                commands::retry_report(data, bot_data, issue, args)?;
             }
 
             Command::Retry(args) => {
-                commands::retry(data, github_data, issue, args)?;
+                commands::retry(data, bot_data, issue, args)?;
+            }
+
+            Command::RetrySpurious(args) => {
+                commands::retry_spurious(data, bot_data, issue, args)?;
             }
 
             Command::Abort(args) => {
-                commands::abort(data, github_data, issue, args)?;
+                commands::abort(data, bot_data, issue, args)?;
+            }
+
+            Command::Priority(args) => {
+                commands::priority(data, bot_data, issue, sender, args)?;
+            }
+
+            Command::Notify(args) => {
+                commands::notify(data, bot_data, issue, sender, args)?;
+            }
+
+            Command::Status(args) => {
+                commands::status(data, bot_data, issue, args)?;
             }
 
             Command::ReloadACL(_) => {
-                commands::reload_acl(data, github_data, issue)?;
+                commands::reload_acl(data, bot_data, issue)?;
             }
         }
 
@@ -153,75 +255,30 @@ fn process_command(
     Ok(())
 }
 
-fn verify_signature(secret: &str, payload: &[u8], raw_signature: &str) -> bool {
-    type HmacSha1 = Hmac<sha1::Sha1>;
-
-    // The signature must have a =
-    if !raw_signature.contains('=') {
-        return false;
-    }
-
-    // Split the raw signature to get the algorithm and the signature
-    let splitted: Vec<&str> = raw_signature.split('=').collect();
-    let algorithm = &splitted[0];
-    let hex_signature = splitted
-        .iter()
-        .skip(1)
-        .cloned()
-        .collect::<Vec<&str>>()
-        .join("=");
-
-    // Convert the signature from hex
-    let signature = if let Ok(converted) = crate::utils::hex::from_hex(&hex_signature) {
-        converted
-    } else {
-        // This is not hex
-        return false;
-    };
-
-    // Only SHA-1 is supported
-    if *algorithm != "sha1" {
-        return false;
-    }
-
-    // Verify the HMAC signature
-    let mut mac = HmacSha1::new_from_slice(secret.as_bytes()).unwrap();
-    mac.update(payload);
-    mac.verify_slice(&signature).is_ok()
-}
-
 fn receive_endpoint(
     data: Arc<Data>,
-    github_data: Arc<GithubData>,
+    bot_data: Arc<BotData>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Fallible<()> {
-    let signature = headers
-        .get("X-Hub-Signature")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| anyhow!("missing header X-Hub-Signature\n"))?;
-    let event = headers
-        .get("X-GitHub-Event")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| anyhow!("missing header X-GitHub-Event\n"))?;
     let host = headers
         .get("Host")
         .and_then(|h| h.to_str().ok())
         .ok_or_else(|| anyhow!("missing header Host\n"))?;
 
-    process_webhook(&body[..], host, signature, event, &data, &github_data)
+    process_webhook(&body[..], host, &headers, &data, &bot_data)
 }
 
 pub fn routes(
     data: Arc<Data>,
-    github_data: Option<Arc<GithubData>>,
+    bot_data: Option<Arc<BotData>>,
 ) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
     let data_filter = warp::any().map(move || data.clone());
-    let github_data_filter = warp::any().and_then(move || {
-        let g = github_data.clone();
+    let bot_data_filter = warp::any().and_then(move || {
+        let g = bot_data.clone();
         async move {
             match g {
-                Some(github_data) => Ok(github_data),
+                Some(bot_data) => Ok(bot_data),
                 None => Err(warp::reject::not_found()),
             }
         }
@@ -230,14 +287,18 @@ pub fn routes(
     warp::post()
         .and(warp::path::end())
         .and(data_filter)
-        .and(github_data_filter)
+        .and(bot_data_filter)
         .and(warp::header::headers_cloned())
         .and(warp::body::bytes())
         .map(
-            |data: Arc<Data>, github_data: Arc<GithubData>, headers: HeaderMap, body: Bytes| {
+            |data: Arc<Data>, bot_data: Arc<BotData>, headers: HeaderMap, body: Bytes| {
                 let mut resp: Response<Body>;
-                match receive_endpoint(data, github_data, headers, body) {
+                match receive_endpoint(data, bot_data, headers, body) {
                     Ok(()) => resp = Response::new("OK\n".into()),
+                    Err(err) if err.downcast_ref::<HttpError>() == Some(&HttpError::ReadOnly) => {
+                        resp = Response::new(format!("Error: {err}\n").into());
+                        *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    }
                     Err(err) => {
                         error!("error while processing webhook");
                         crate::utils::report_failure(&err);
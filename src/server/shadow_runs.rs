@@ -0,0 +1,301 @@
+//! Shadow runs re-build a random sample of the crates completed by an experiment on a different
+//! agent than the one which originally ran them, comparing the two results. Agents whose results
+//! often don't match the ones of the other agents are likely to be misconfigured or broken.
+
+use crate::config::Config;
+use crate::crates::Crate;
+use crate::db::{Database, QueryUtils};
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use crate::results::{DatabaseDB, ProgressData, ReadResults};
+use chrono::Utc;
+use std::collections::HashMap;
+
+/// Sample the crates to re-run for all the running experiments, keeping the number of shadow
+/// runs of each experiment proportional to the number of crates it completed.
+pub fn sample(db: &Database, config: &Config) -> Fallible<()> {
+    let rate = config.server.shadow_runs.sample_rate.clamp(0.0, 1.0);
+    if rate == 0.0 {
+        return Ok(());
+    }
+
+    for ex in Experiment::unfinished(db)? {
+        if ex.status != Status::Running {
+            continue;
+        }
+
+        let completed: u32 = db
+            .get_row(
+                "SELECT COUNT(*) AS count FROM experiment_crates \
+                 WHERE experiment = ?1 AND status = 'completed' AND skipped = 0;",
+                [&ex.name],
+                |row| row.get("count"),
+            )?
+            .unwrap_or(0);
+        let sampled: u32 = db
+            .get_row(
+                "SELECT COUNT(*) AS count FROM shadow_runs WHERE experiment = ?1;",
+                [&ex.name],
+                |row| row.get("count"),
+            )?
+            .unwrap_or(0);
+        let missing = (f64::from(completed) * rate) as u32;
+        if missing <= sampled {
+            continue;
+        }
+
+        // Only the crates whose results were all reported by the same agent can be compared
+        let added = db.execute(
+            "INSERT INTO shadow_runs (experiment, crate, original_agent, created_at) \
+             SELECT ec.experiment, ec.crate, MIN(r.agent), ?3 \
+             FROM experiment_crates ec \
+             JOIN results r ON r.experiment = ec.experiment AND r.crate = ec.crate \
+             WHERE ec.experiment = ?1 AND ec.status = 'completed' AND ec.skipped = 0 \
+             AND NOT EXISTS (SELECT 1 FROM shadow_runs s \
+                 WHERE s.experiment = ec.experiment AND s.crate = ec.crate) \
+             GROUP BY ec.crate \
+             HAVING COUNT(r.agent) = COUNT(*) AND COUNT(DISTINCT r.agent) = 1 \
+             ORDER BY RANDOM() LIMIT ?2;",
+            rusqlite::params![ex.name, missing - sampled, Utc::now()],
+        )?;
+        if added > 0 {
+            info!("sampled {} crates of {} for shadow runs", added, ex.name);
+        }
+    }
+    Ok(())
+}
+
+/// Hand out a crate to re-run to the agent, marking it as assigned. Crates are never re-run by
+/// the agent which originally ran them, and crates assigned more than
+/// `server.reassign-crates-after` minutes ago without their results being reported are handed
+/// out again.
+pub fn next_crate(
+    db: &Database,
+    config: &Config,
+    ex: &Experiment,
+    agent: &str,
+) -> Fallible<Option<Crate>> {
+    let timeout = config.server.reassign_crates_after;
+    db.transaction(true, |t| {
+        let krate: Option<String> = t.get_row(
+            &format!(
+                "SELECT crate FROM shadow_runs \
+                 WHERE experiment = ?1 AND original_agent != ?2 AND completed_at IS NULL \
+                 AND (assigned_at IS NULL \
+                     OR assigned_at <= datetime('now', '-{timeout} minutes')) \
+                 ORDER BY assigned_at IS NOT NULL, created_at \
+                 LIMIT 1;"
+            ),
+            rusqlite::params![ex.name, agent],
+            |row| row.get("crate"),
+        )?;
+        let Some(krate) = krate else {
+            return Ok(None);
+        };
+
+        t.execute(
+            "UPDATE shadow_runs SET shadow_agent = ?3, assigned_at = datetime('now') \
+             WHERE experiment = ?1 AND crate = ?2;",
+            &[&ex.name, &krate, &agent],
+        )?;
+        Ok(Some(krate.parse()?))
+    })
+}
+
+/// A result reported by a shadow run, compared with the original one.
+pub struct ShadowResult {
+    pub original_agent: String,
+    pub matches: bool,
+}
+
+/// Record the result reported by the agent if it's part of a shadow run assigned to it,
+/// returning how it compares with the original result. Results recorded here must not be stored
+/// as the results of the experiment. Crates which are not completed anymore were queued again,
+/// and the results of their new runs are never shadow runs, even when they were handed out to
+/// the agent assigned their shadow run.
+pub fn record(
+    db: &Database,
+    ex: &Experiment,
+    data: &ProgressData,
+    agent: &str,
+) -> Fallible<Option<ShadowResult>> {
+    let krate = data.result.krate.id();
+    let original_agent: Option<String> = db.get_row(
+        "SELECT s.original_agent FROM shadow_runs s \
+         JOIN experiment_crates ec ON ec.experiment = s.experiment AND ec.crate = s.crate \
+         WHERE s.experiment = ?1 AND s.crate = ?2 AND s.shadow_agent = ?3 \
+         AND s.completed_at IS NULL AND ec.status = 'completed';",
+        rusqlite::params![ex.name, krate, agent],
+        |row| row.get("original_agent"),
+    )?;
+    let Some(original_agent) = original_agent else {
+        return Ok(None);
+    };
+    let Some(original) =
+        DatabaseDB::new(db).load_test_result(ex, &data.result.toolchain, &data.result.krate)?
+    else {
+        return Ok(None);
+    };
+
+    let (original, shadow) = (original.to_string(), data.result.result.to_string());
+    let now = Utc::now();
+    db.execute(
+        "INSERT INTO shadow_results (experiment, crate, toolchain, original_agent, \
+         original_result, shadow_agent, shadow_result, recorded_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+        &[
+            &ex.name,
+            &krate,
+            &data.result.toolchain.to_string(),
+            &original_agent,
+            &original,
+            &agent,
+            &shadow,
+            &now,
+        ],
+    )?;
+    db.execute(
+        "UPDATE shadow_runs SET completed_at = ?3 WHERE experiment = ?1 AND crate = ?2 \
+         AND (SELECT COUNT(*) FROM shadow_results WHERE experiment = ?1 AND crate = ?2) > 1;",
+        &[&ex.name, &krate, &now],
+    )?;
+
+    Ok(Some(ShadowResult {
+        original_agent,
+        matches: original == shadow,
+    }))
+}
+
+/// How many of the results involving an agent matched the result of the other agent.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Reproducibility {
+    pub matching: u32,
+    pub compared: u32,
+}
+
+/// Reproducibility of the results of each agent which took part in shadow runs.
+pub fn reproducibility(db: &Database) -> Fallible<HashMap<String, Reproducibility>> {
+    let rows = db.query(
+        "SELECT original_agent, shadow_agent, original_result = shadow_result AS matches \
+         FROM shadow_results;",
+        [],
+        |row| {
+            Ok((
+                row.get::<_, String>("original_agent")?,
+                row.get::<_, String>("shadow_agent")?,
+                row.get::<_, bool>("matches")?,
+            ))
+        },
+    )?;
+
+    let mut agents: HashMap<String, Reproducibility> = HashMap::new();
+    for (original, shadow, matches) in rows {
+        for agent in [original, shadow] {
+            let entry = agents.entry(agent).or_default();
+            entry.compared += 1;
+            entry.matching += u32::from(matches);
+        }
+    }
+    Ok(agents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_crate, record, reproducibility, sample, Reproducibility};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::Crate;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Experiment, Status};
+    use crate::results::{
+        BuildReport, DatabaseDB, EncodingType, ProgressData, TaskResult, TestResult,
+    };
+    use base64::Engine;
+
+    fn progress(ex: &Experiment, krate: &Crate, tc: usize, result: TestResult) -> ProgressData {
+        ProgressData {
+            result: TaskResult {
+                krate: krate.clone(),
+                toolchain: ex.toolchains[tc].clone(),
+                result,
+                log: base64::engine::general_purpose::STANDARD.encode("log"),
+                report: BuildReport::default(),
+            },
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_shadow_runs() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        config.server.shadow_runs.sample_rate = 1.0;
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        ex.set_status(&db, Status::Running).unwrap();
+
+        let crates = ex.get_crates(&db).unwrap();
+        let krate = &crates[0];
+        for tc in 0..2 {
+            DatabaseDB::new(&db)
+                .with_agent("agent-a")
+                .store(
+                    &ex,
+                    &progress(&ex, krate, tc, TestResult::TestPass),
+                    EncodingType::Plain,
+                )
+                .unwrap();
+        }
+
+        // Nothing is sampled while the sampling is disabled
+        sample(&db, &Config::default()).unwrap();
+        assert!(next_crate(&db, &config, &ex, "agent-b").unwrap().is_none());
+
+        sample(&db, &config).unwrap();
+        // The crate is never re-run by the agent which originally ran it
+        assert!(next_crate(&db, &config, &ex, "agent-a").unwrap().is_none());
+        assert_eq!(
+            next_crate(&db, &config, &ex, "agent-b").unwrap().as_ref(),
+            Some(krate)
+        );
+        assert!(next_crate(&db, &config, &ex, "agent-c").unwrap().is_none());
+
+        // Results of other agents aren't part of the shadow run
+        let data = progress(&ex, krate, 0, TestResult::TestPass);
+        assert!(record(&db, &ex, &data, "agent-c").unwrap().is_none());
+
+        // Neither are the results of the crate once it's queued again
+        let set_status = |status: &str| {
+            db.execute(
+                "UPDATE experiment_crates SET status = ?1 WHERE experiment = 'foo' AND crate = ?2;",
+                &[&status, &krate.id().as_str()],
+            )
+            .unwrap();
+        };
+        set_status("queued");
+        assert!(record(&db, &ex, &data, "agent-b").unwrap().is_none());
+        set_status("completed");
+
+        let shadow = record(&db, &ex, &data, "agent-b").unwrap().unwrap();
+        assert_eq!(shadow.original_agent, "agent-a");
+        assert!(shadow.matches);
+        let data = progress(&ex, krate, 1, TestResult::TestSkipped);
+        assert!(!record(&db, &ex, &data, "agent-b").unwrap().unwrap().matches);
+
+        // The shadow run is over once both toolchains are recorded
+        assert!(record(&db, &ex, &data, "agent-b").unwrap().is_none());
+        sample(&db, &config).unwrap();
+        assert!(next_crate(&db, &config, &ex, "agent-c").unwrap().is_none());
+
+        let agents = reproducibility(&db).unwrap();
+        let expected = Reproducibility {
+            matching: 1,
+            compared: 2,
+        };
+        assert_eq!(agents.len(), 2);
+        assert_eq!(agents["agent-a"], expected);
+        assert_eq!(agents["agent-b"], expected);
+    }
+}
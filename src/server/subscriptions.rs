@@ -0,0 +1,231 @@
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+
+/// Confirmation emails sent to the same address within an hour, across all the experiments.
+const MAX_CONFIRMATIONS_PER_ADDRESS: u32 = 3;
+/// Confirmation emails sent by the server within an hour.
+const MAX_CONFIRMATIONS: u32 = 100;
+
+string_enum!(pub enum SubscriberKind {
+    GitHub => "github",
+    Email => "email",
+});
+
+/// Subscribe someone to the completion of an experiment, returning whether they weren't
+/// subscribed already. GitHub subscribers are identified by their username.
+pub fn subscribe(
+    db: &Database,
+    experiment: &str,
+    kind: SubscriberKind,
+    target: &str,
+) -> Fallible<bool> {
+    let now = Utc::now();
+    let changes = db.execute(
+        "INSERT OR IGNORE INTO subscriptions \
+         (experiment, kind, target, token, created_at, confirmed_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?5);",
+        &[&experiment, &kind.to_str(), &target, &new_token(), &now],
+    )?;
+    Ok(changes != 0)
+}
+
+/// Outcome of the subscription of an email address, which has to be confirmed through the link
+/// sent to it before any notification is sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PendingSubscription {
+    /// Token of the confirmation link to send to the address.
+    Created(String),
+    /// The address is already subscribed, or waiting for its confirmation.
+    Exists,
+    /// Too many confirmation emails were sent recently.
+    RateLimited,
+}
+
+/// Subscribe an email address to the completion of an experiment, pending its confirmation.
+pub fn subscribe_email(
+    db: &Database,
+    experiment: &str,
+    address: &str,
+) -> Fallible<PendingSubscription> {
+    let kind = SubscriberKind::Email.to_str();
+    let since = Utc::now() - Duration::hours(1);
+    // Concurrent requests would all pass the rate limit if they counted the recent subscriptions
+    // before any of them was recorded
+    db.transaction(true, |t| {
+        let sent_to_address: u32 = t
+            .get_row(
+                "SELECT COUNT(*) FROM subscriptions \
+                 WHERE kind = ?1 AND target = ?2 AND created_at > ?3;",
+                rusqlite::params![kind, address, since],
+                |row| row.get(0),
+            )?
+            .unwrap_or(0);
+        let sent: u32 = t
+            .get_row(
+                "SELECT COUNT(*) FROM subscriptions WHERE kind = ?1 AND created_at > ?2;",
+                rusqlite::params![kind, since],
+                |row| row.get(0),
+            )?
+            .unwrap_or(0);
+        if sent_to_address >= MAX_CONFIRMATIONS_PER_ADDRESS || sent >= MAX_CONFIRMATIONS {
+            return Ok(PendingSubscription::RateLimited);
+        }
+
+        let token = new_token();
+        let changes = t.execute(
+            "INSERT OR IGNORE INTO subscriptions (experiment, kind, target, token, created_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5);",
+            &[&experiment, &kind, &address, &token, &Utc::now()],
+        )?;
+        Ok(if changes != 0 {
+            PendingSubscription::Created(token)
+        } else {
+            PendingSubscription::Exists
+        })
+    })
+}
+
+/// Load the name of the experiment of the subscription the token was sent for.
+pub fn experiment(db: &impl QueryUtils, token: &str) -> Fallible<Option<String>> {
+    db.get_row(
+        "SELECT experiment FROM subscriptions WHERE token = ?1;",
+        [&token],
+        |row| row.get("experiment"),
+    )
+}
+
+/// Confirm the subscription the token was sent for, returning the name of its experiment.
+pub fn confirm(db: &Database, token: &str) -> Fallible<Option<String>> {
+    db.transaction(true, |t| {
+        let experiment = experiment(t, token)?;
+        t.execute(
+            "UPDATE subscriptions SET confirmed_at = ?2 \
+             WHERE token = ?1 AND confirmed_at IS NULL;",
+            &[&token, &Utc::now()],
+        )?;
+        Ok(experiment)
+    })
+}
+
+/// Remove the subscription the token was sent for, returning the name of its experiment.
+pub fn unsubscribe(db: &Database, token: &str) -> Fallible<Option<String>> {
+    db.transaction(true, |t| {
+        let experiment = experiment(t, token)?;
+        t.execute("DELETE FROM subscriptions WHERE token = ?1;", &[&token])?;
+        Ok(experiment)
+    })
+}
+
+/// Load the confirmed subscribers of a kind to an experiment, in the order they subscribed.
+pub fn subscribers(db: &Database, experiment: &str, kind: SubscriberKind) -> Fallible<Vec<String>> {
+    Ok(confirmed(db, experiment, kind)?
+        .into_iter()
+        .map(|(target, _)| target)
+        .collect())
+}
+
+/// Load the confirmed email subscribers to an experiment, along with the token of the link to
+/// unsubscribe them.
+pub fn email_subscribers(db: &Database, experiment: &str) -> Fallible<Vec<(String, String)>> {
+    confirmed(db, experiment, SubscriberKind::Email)
+}
+
+fn confirmed(
+    db: &Database,
+    experiment: &str,
+    kind: SubscriberKind,
+) -> Fallible<Vec<(String, String)>> {
+    db.query(
+        "SELECT target, token FROM subscriptions \
+         WHERE experiment = ?1 AND kind = ?2 AND confirmed_at IS NOT NULL \
+         ORDER BY created_at, rowid;",
+        [&experiment, &kind.to_str()],
+        |row| Ok((row.get("target")?, row.get("token")?)),
+    )
+}
+
+fn new_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        confirm, email_subscribers, experiment, subscribe, subscribe_email, subscribers,
+        unsubscribe, PendingSubscription, SubscriberKind, MAX_CONFIRMATIONS_PER_ADDRESS,
+    };
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+
+    #[test]
+    fn test_subscriptions() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+
+        assert!(subscribers(&db, "foo", SubscriberKind::GitHub)
+            .unwrap()
+            .is_empty());
+
+        assert!(subscribe(&db, "foo", SubscriberKind::GitHub, "alice").unwrap());
+        assert!(subscribe(&db, "foo", SubscriberKind::GitHub, "bob").unwrap());
+        assert!(!subscribe(&db, "foo", SubscriberKind::GitHub, "alice").unwrap());
+
+        assert_eq!(
+            subscribers(&db, "foo", SubscriberKind::GitHub).unwrap(),
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_email_subscriptions() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        CreateExperiment::dummy("bar").apply(&ctx).unwrap();
+
+        // Nobody is notified until the address is confirmed
+        let token = match subscribe_email(&db, "foo", "alice@example.com").unwrap() {
+            PendingSubscription::Created(token) => token,
+            other => panic!("unexpected subscription: {other:?}"),
+        };
+        assert_eq!(
+            subscribe_email(&db, "foo", "alice@example.com").unwrap(),
+            PendingSubscription::Exists
+        );
+        assert!(email_subscribers(&db, "foo").unwrap().is_empty());
+
+        assert_eq!(confirm(&db, &token).unwrap(), Some("foo".into()));
+        assert_eq!(confirm(&db, "invalid").unwrap(), None);
+        assert_eq!(
+            email_subscribers(&db, "foo").unwrap(),
+            vec![("alice@example.com".to_string(), token.clone())]
+        );
+
+        assert_eq!(experiment(&db, &token).unwrap(), Some("foo".into()));
+        assert_eq!(unsubscribe(&db, &token).unwrap(), Some("foo".into()));
+        assert!(email_subscribers(&db, "foo").unwrap().is_empty());
+        assert_eq!(experiment(&db, &token).unwrap(), None);
+
+        // The confirmation emails sent to each address are limited
+        for i in 0..MAX_CONFIRMATIONS_PER_ADDRESS {
+            let name = format!("ex-{i}");
+            CreateExperiment::dummy(&name).apply(&ctx).unwrap();
+            assert!(matches!(
+                subscribe_email(&db, &name, "bob@example.com").unwrap(),
+                PendingSubscription::Created(_)
+            ));
+        }
+        assert_eq!(
+            subscribe_email(&db, "bar", "bob@example.com").unwrap(),
+            PendingSubscription::RateLimited
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use crate::prelude::*;
+use crate::report::{FileWriter, MirrorWriter, S3Writer};
+use crate::results::{LocalLogStore, LogStore, S3LogStore};
 use serde_derive::Deserialize;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 static TOKENS_PATH: &str = "tokens.toml";
 
@@ -17,6 +20,9 @@ pub enum BucketRegion {
 pub struct BotTokens {
     pub webhooks_secret: String,
     pub api_token: String,
+    /// URL of the GitLab instance the bot is used on. The bot talks to GitHub when it's not set.
+    #[serde(default)]
+    pub gitlab_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -29,18 +35,132 @@ pub struct ReportsBucket {
     pub secret_key: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ZulipTokens {
+    pub site: String,
+    pub bot_email: String,
+    pub api_key: String,
+}
+
+/// Server used to send the email notifications to the subscribers of the experiments.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmtpTokens {
+    pub server: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+}
+
+/// Destination the reports are copied to in addition to the reports bucket, to keep them
+/// available when the bucket isn't.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ReportMirror {
+    /// Any S3-compatible object store, like a bucket in another region or Google Cloud Storage.
+    #[serde(rename_all = "kebab-case")]
+    S3 {
+        region: BucketRegion,
+        bucket: String,
+        access_key: String,
+        secret_key: String,
+    },
+    Local {
+        path: PathBuf,
+    },
+}
+
+fn s3_client(
+    region: &BucketRegion,
+    access_key: &str,
+    secret_key: &str,
+) -> Fallible<aws_sdk_s3::Client> {
+    let mut config = aws_config::from_env();
+    match region {
+        BucketRegion::S3 { region } => {
+            config = config.region(aws_sdk_s3::config::Region::new(region.to_owned()));
+        }
+        BucketRegion::Custom { url } => {
+            config = config.region(aws_sdk_s3::config::Region::from_static("us-east-1"));
+            config = config.endpoint_url(url.clone());
+        }
+    }
+    config = config.credentials_provider(aws_sdk_s3::config::Credentials::new(
+        access_key.to_string(),
+        secret_key.to_string(),
+        None,
+        None,
+        "crater-credentials",
+    ));
+    let config = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(config.load());
+    Ok(aws_sdk_s3::Client::new(&config))
+}
+
 impl ReportsBucket {
-    pub(crate) fn to_aws_credentials(&self) -> aws_sdk_s3::config::Credentials {
-        aws_sdk_s3::config::Credentials::new(
-            self.access_key.clone(),
-            self.secret_key.clone(),
-            None,
-            None,
-            "crater-credentials",
-        )
+    pub(crate) fn to_s3_client(&self) -> Fallible<aws_sdk_s3::Client> {
+        s3_client(&self.region, &self.access_key, &self.secret_key)
+    }
+}
+
+impl ReportMirror {
+    /// Build the writer copying the report of the experiment `name` to this mirror.
+    pub(crate) fn writer(&self, name: &str) -> Fallible<MirrorWriter> {
+        Ok(match self {
+            ReportMirror::S3 {
+                region,
+                bucket,
+                access_key,
+                secret_key,
+            } => MirrorWriter::S3(S3Writer::create(
+                s3_client(region, access_key, secret_key)?,
+                bucket.clone(),
+                name.to_string(),
+            )?),
+            ReportMirror::Local { path } => {
+                MirrorWriter::File(FileWriter::create(path.join(name))?)
+            }
+        })
     }
 }
 
+/// Where the logs of the results are stored when they're not kept in the database.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum LogsStorage {
+    Local {
+        path: PathBuf,
+    },
+    /// Bucket accessed with the credentials of the reports bucket.
+    S3 {
+        bucket: String,
+        prefix: String,
+    },
+}
+
+string_enum!(pub enum AdminScope {
+    DeleteExperiments => "delete-experiments",
+    EditExperiments => "edit-experiments",
+    ReloadAcl => "reload-acl",
+    ReadAuditLog => "read-audit-log",
+    Maintenance => "maintenance",
+    Quarantine => "quarantine",
+    Webhooks => "webhooks",
+    RefreshLists => "refresh-lists",
+});
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AdminToken {
+    pub name: String,
+    pub scopes: Vec<AdminScope>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Tokens {
@@ -48,6 +168,18 @@ pub struct Tokens {
     pub bot: Option<BotTokens>,
     pub reports_bucket: ReportsBucket,
     pub agents: HashMap<String, String>,
+    #[serde(default)]
+    pub triagers: HashMap<String, String>,
+    #[serde(default)]
+    pub admins: HashMap<String, AdminToken>,
+    #[serde(default)]
+    pub zulip: Option<ZulipTokens>,
+    #[serde(default)]
+    pub smtp: Option<SmtpTokens>,
+    #[serde(default)]
+    pub logs_storage: Option<LogsStorage>,
+    #[serde(default)]
+    pub report_mirrors: Vec<ReportMirror>,
 }
 
 #[cfg(test)]
@@ -65,6 +197,12 @@ impl Default for Tokens {
                 secret_key: String::new(),
             },
             agents: HashMap::new(),
+            triagers: HashMap::new(),
+            admins: HashMap::new(),
+            zulip: None,
+            smtp: None,
+            logs_storage: None,
+            report_mirrors: Vec::new(),
         }
     }
 }
@@ -76,4 +214,26 @@ impl Tokens {
         let res = ::toml::from_str(&content)?;
         Ok(res)
     }
+
+    /// Build the log store configured in the tokens file, if the file exists. This allows the CLI
+    /// to read the logs stored outside the database when it runs on the server.
+    pub fn load_log_store() -> Fallible<Option<Arc<dyn LogStore>>> {
+        if Path::new(TOKENS_PATH).exists() {
+            Tokens::load()?.log_store()
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn log_store(&self) -> Fallible<Option<Arc<dyn LogStore>>> {
+        Ok(match &self.logs_storage {
+            None => None,
+            Some(LogsStorage::Local { path }) => Some(Arc::new(LocalLogStore::new(path.clone()))),
+            Some(LogsStorage::S3 { bucket, prefix }) => Some(Arc::new(S3LogStore::create(
+                self.reports_bucket.to_s3_client()?,
+                bucket.clone(),
+                prefix.clone(),
+            )?)),
+        })
+    }
 }
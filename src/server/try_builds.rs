@@ -1,6 +1,6 @@
 use crate::db::{Database, QueryUtils};
 use crate::prelude::*;
-use crate::server::github::GitHub;
+use crate::server::vcs::VcsHost;
 use regex::Regex;
 
 lazy_static! {
@@ -18,7 +18,7 @@ pub(crate) struct TryBuild {
     pub(crate) merge_sha: String,
 }
 
-fn base_commit(gh: &dyn GitHub, repo: &str, merge_sha: &str) -> Fallible<Option<String>> {
+fn base_commit(gh: &dyn VcsHost, repo: &str, merge_sha: &str) -> Fallible<Option<String>> {
     let mut commit = gh.get_commit(repo, merge_sha)?;
     if commit.parents.len() != 2 {
         return Ok(None);
@@ -28,7 +28,7 @@ fn base_commit(gh: &dyn GitHub, repo: &str, merge_sha: &str) -> Fallible<Option<
 
 pub(crate) fn detect(
     db: &Database,
-    gh: &dyn GitHub,
+    gh: &dyn VcsHost,
     repo: &str,
     pr: i32,
     comment: &str,
@@ -66,9 +66,10 @@ mod tests {
     use super::{detect, get_sha};
     use crate::db::Database;
     use crate::prelude::*;
-    use crate::server::github::{Commit, CommitParent, GitHub, Label};
-    use std::cell::RefCell;
+    use crate::server::vcs::{Commit, CommitParent, ComparedCommit, Label, VcsHost, WebhookEvent};
+    use http::HeaderMap;
     use std::collections::HashMap;
+    use std::sync::Mutex;
 
     static COMMIT_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
     static COMMIT_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
@@ -118,18 +119,19 @@ mod tests {
 
     #[derive(Default)]
     struct DummyGitHub {
-        commits: RefCell<HashMap<(String, String), Commit>>,
+        commits: Mutex<HashMap<(String, String), Commit>>,
     }
 
     impl DummyGitHub {
         fn set_commit(&self, repo: &str, sha: &str, commit: Commit) {
             self.commits
-                .borrow_mut()
+                .lock()
+                .unwrap()
                 .insert((repo.to_string(), sha.to_string()), commit);
         }
     }
 
-    impl GitHub for DummyGitHub {
+    impl VcsHost for DummyGitHub {
         fn username(&self) -> Fallible<String> {
             unimplemented!();
         }
@@ -161,7 +163,8 @@ mod tests {
         fn get_commit(&self, repo: &str, sha: &str) -> Fallible<Commit> {
             Ok(self
                 .commits
-                .borrow_mut()
+                .lock()
+                .unwrap()
                 .remove(&(repo.into(), sha.into()))
                 .unwrap())
         }
@@ -169,5 +172,33 @@ mod tests {
         fn get_pr_head_sha(&self, _repo: &str, _pr: i32) -> Fallible<String> {
             unimplemented!();
         }
+
+        fn compare_commits(
+            &self,
+            _repo: &str,
+            _base: &str,
+            _head: &str,
+        ) -> Fallible<Vec<ComparedCommit>> {
+            unimplemented!();
+        }
+
+        fn create_issue(
+            &self,
+            _repo: &str,
+            _title: &str,
+            _body: &str,
+            _labels: &[String],
+        ) -> Fallible<String> {
+            unimplemented!();
+        }
+
+        fn parse_webhook(
+            &self,
+            _secret: &str,
+            _headers: &HeaderMap,
+            _payload: &[u8],
+        ) -> Fallible<WebhookEvent> {
+            unimplemented!();
+        }
     }
 }
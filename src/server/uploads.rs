@@ -0,0 +1,236 @@
+//! Results uploaded by the agents in chunks. The logs of some crates weigh hundreds of megabytes,
+//! and uploading them in a single request keeps failing on unreliable links: agents upload the
+//! large results a chunk at a time instead, resuming from the last chunk the server received, and
+//! the server reassembles them and verifies their hash before recording them.
+
+use crate::prelude::*;
+use crate::utils::hex::to_hex;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Uploads not completed within this time are abandoned, and removed.
+const RETENTION: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+/// Size above which uploads are refused, to avoid filling the disk of the server.
+pub(crate) const MAX_UPLOAD_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum UploadError {
+    #[error("invalid upload id `{0}`")]
+    InvalidId(String),
+    #[error("upload `{0}` is larger than the maximum size")]
+    TooLarge(String),
+    #[error("unknown upload `{0}`")]
+    Unknown(String),
+    #[error("upload `{id}` is corrupted: expected hash {expected}, found {found}")]
+    HashMismatch {
+        id: String,
+        expected: String,
+        found: String,
+    },
+}
+
+#[derive(Clone)]
+pub struct Uploads {
+    dir: PathBuf,
+    locked: Arc<(Mutex<HashSet<PathBuf>>, Condvar)>,
+}
+
+/// Lock on an upload, released when dropped.
+pub struct UploadLock<'a> {
+    uploads: &'a Uploads,
+    path: PathBuf,
+}
+
+impl Drop for UploadLock<'_> {
+    fn drop(&mut self) {
+        let (locked, released) = &*self.uploads.locked;
+        locked
+            .lock()
+            .unwrap_or_else(|l| l.into_inner())
+            .remove(&self.path);
+        released.notify_all();
+    }
+}
+
+impl Uploads {
+    pub fn new(dir: &Path) -> Self {
+        Uploads {
+            dir: dir.to_path_buf(),
+            locked: Arc::new((Mutex::new(HashSet::new()), Condvar::new())),
+        }
+    }
+
+    /// Lock the upload until the returned guard is dropped, waiting for the requests already
+    /// handling it. Agents retry their requests when the connection drops, so the retries of a
+    /// chunk or of the completion of an upload can be received while the first one is still
+    /// being handled.
+    pub fn lock(&self, agent: &str, id: &str) -> Result<UploadLock<'_>, UploadError> {
+        let path = self.path(agent, id)?;
+        let (locked, released) = &*self.locked;
+        let mut locked = locked.lock().unwrap_or_else(|l| l.into_inner());
+        while locked.contains(&path) {
+            locked = released.wait(locked).unwrap_or_else(|l| l.into_inner());
+        }
+        locked.insert(path.clone());
+        Ok(UploadLock {
+            uploads: self,
+            path,
+        })
+    }
+
+    fn path(&self, agent: &str, id: &str) -> Result<PathBuf, UploadError> {
+        // The id is chosen by the agent, and must not escape the directory of the uploads
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(UploadError::InvalidId(id.to_string()));
+        }
+        Ok(self.dir.join(agent).join(id))
+    }
+
+    /// Append a chunk starting at the given offset, returning the number of bytes received so
+    /// far. Chunks not starting where the received bytes end are ignored, which makes retrying
+    /// them harmless: the agent continues from the returned offset.
+    pub fn append(&self, agent: &str, id: &str, offset: u64, chunk: &[u8]) -> Fallible<u64> {
+        let _lock = self.lock(agent, id)?;
+        let path = self.path(agent, id)?;
+        fs::create_dir_all(self.dir.join(agent))?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let received = file.metadata()?.len();
+        if offset != received {
+            return Ok(received);
+        }
+        if received + chunk.len() as u64 > MAX_UPLOAD_SIZE {
+            return Err(UploadError::TooLarge(id.to_string()).into());
+        }
+        file.write_all(chunk)?;
+        Ok(received + chunk.len() as u64)
+    }
+
+    /// Reassemble the upload, which must match the SHA-256 hash computed by the agent, returning
+    /// the file to read it from. Corrupted uploads are removed, to be uploaded again from the
+    /// start. The caller has to hold the lock of the upload.
+    pub fn assemble(&self, agent: &str, id: &str, sha256: &str) -> Fallible<File> {
+        let path = self.path(agent, id)?;
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(UploadError::Unknown(id.to_string()).into());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        let found = to_hex(&hasher.finalize());
+        if !found.eq_ignore_ascii_case(sha256) {
+            self.remove(agent, id)?;
+            return Err(UploadError::HashMismatch {
+                id: id.to_string(),
+                expected: sha256.to_string(),
+                found,
+            }
+            .into());
+        }
+        file.rewind()?;
+        Ok(file)
+    }
+
+    pub fn remove(&self, agent: &str, id: &str) -> Fallible<()> {
+        match fs::remove_file(self.path(agent, id)?) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the uploads abandoned by the agents.
+    pub fn purge_stale(&self) -> Fallible<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        let now = SystemTime::now();
+        let mut purged = 0;
+        for agent in fs::read_dir(&self.dir)? {
+            for upload in fs::read_dir(agent?.path())? {
+                let upload = upload?;
+                let modified = upload.metadata()?.modified()?;
+                if now.duration_since(modified).unwrap_or_default() > RETENTION {
+                    fs::remove_file(upload.path())?;
+                    purged += 1;
+                }
+            }
+        }
+        if purged > 0 {
+            info!("purged {} abandoned uploads", purged);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UploadError, Uploads};
+    use crate::utils::hex::to_hex;
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+
+    #[test]
+    fn test_chunked_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = Uploads::new(dir.path());
+        let hash = to_hex(&Sha256::digest(b"hello world"));
+
+        assert_eq!(uploads.append("agent", "key", 0, b"hello").unwrap(), 5);
+        // Retrying a chunk the server already received doesn't duplicate it
+        assert_eq!(uploads.append("agent", "key", 0, b"hello").unwrap(), 5);
+        // Chunks past the received bytes are ignored as well
+        assert_eq!(uploads.append("agent", "key", 8, b"rld").unwrap(), 5);
+        assert_eq!(uploads.append("agent", "key", 5, b" world").unwrap(), 11);
+        let mut body = Vec::new();
+        uploads
+            .assemble("agent", "key", &hash)
+            .unwrap()
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body, b"hello world");
+
+        // Uploads are scoped to the agent
+        let err = uploads.assemble("other", "key", &hash).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&UploadError::Unknown("key".into()))
+        );
+
+        uploads.remove("agent", "key").unwrap();
+        assert!(uploads.assemble("agent", "key", &hash).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = Uploads::new(dir.path());
+        let hash = to_hex(&Sha256::digest(b"hello world"));
+
+        uploads.append("agent", "key", 0, b"hello there").unwrap();
+        let err = uploads.assemble("agent", "key", &hash).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(UploadError::HashMismatch { .. })
+        ));
+        // The corrupted upload starts again from scratch
+        assert_eq!(uploads.append("agent", "key", 0, b"hello").unwrap(), 5);
+    }
+
+    #[test]
+    fn test_invalid_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = Uploads::new(dir.path());
+        for id in ["", "../escape", "a/b"] {
+            let err = uploads.append("agent", id, 0, b"data").unwrap_err();
+            assert_eq!(err.downcast_ref(), Some(&UploadError::InvalidId(id.into())));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::prelude::*;
+use http::HeaderMap;
+use std::collections::HashMap;
+
+/// Service hosting the repositories the bot is used in, like GitHub or a GitLab instance.
+pub trait VcsHost: Send + Sync {
+    fn username(&self) -> Fallible<String>;
+    fn post_comment(&self, issue_url: &str, body: &str) -> Fallible<()>;
+    fn list_labels(&self, issue_url: &str) -> Fallible<Vec<Label>>;
+    fn add_label(&self, issue_url: &str, label: &str) -> Fallible<()>;
+    fn remove_label(&self, issue_url: &str, label: &str) -> Fallible<()>;
+    fn list_teams(&self, org: &str) -> Fallible<HashMap<String, usize>>;
+    fn team_members(&self, team: usize) -> Fallible<Vec<String>>;
+    fn get_commit(&self, repo: &str, sha: &str) -> Fallible<Commit>;
+    fn get_pr_head_sha(&self, repo: &str, pr: i32) -> Fallible<String>;
+    /// Commits reachable from `head` but not from `base`, oldest first.
+    fn compare_commits(&self, repo: &str, base: &str, head: &str) -> Fallible<Vec<ComparedCommit>>;
+    /// Open a new issue in the repository, returning its URL.
+    fn create_issue(
+        &self,
+        repo: &str,
+        title: &str,
+        body: &str,
+        labels: &[String],
+    ) -> Fallible<String>;
+
+    /// Authenticate and parse a webhook sent by the host.
+    fn parse_webhook(
+        &self,
+        secret: &str,
+        headers: &HeaderMap,
+        payload: &[u8],
+    ) -> Fallible<WebhookEvent>;
+}
+
+pub enum WebhookEvent {
+    Ping,
+    Comment(CommentEvent),
+    /// Events the bot doesn't have to act on, like edited comments.
+    Ignored,
+}
+
+/// A new comment posted on an issue or a pull request.
+pub struct CommentEvent {
+    pub sender: User,
+    pub body: String,
+    pub repository: Repository,
+    pub issue: Issue,
+}
+
+pub(super) fn header<'a>(headers: &'a HeaderMap, name: &str) -> Fallible<&'a str> {
+    headers
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .ok_or_else(|| anyhow!("missing header {}\n", name))
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub id: u64,
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+pub struct Issue {
+    pub number: i32,
+    pub url: String,
+    pub html_url: String,
+    // used in some targets
+    #[allow(unused)]
+    pub labels: Vec<Label>,
+    pub pull_request: Option<PullRequest>,
+}
+
+#[derive(Deserialize)]
+pub struct PullRequest {
+    // used in some targets
+    #[allow(unused)]
+    pub html_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct Repository {
+    pub full_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct Commit {
+    // used in some targets
+    #[allow(unused)]
+    pub sha: String,
+    pub parents: Vec<CommitParent>,
+}
+
+#[derive(Deserialize)]
+pub struct CommitParent {
+    pub sha: String,
+}
+
+pub struct ComparedCommit {
+    // used in some targets
+    #[allow(unused)]
+    pub sha: String,
+    pub message: String,
+}
@@ -0,0 +1,47 @@
+use crate::prelude::*;
+use crate::server::tokens::ZulipTokens;
+use crate::utils;
+use reqwest::Method;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ZulipError {
+    #[error("request to Zulip API failed with status {0}: {1}")]
+    RequestFailed(reqwest::StatusCode, String),
+}
+
+#[derive(Clone)]
+pub struct ZulipApi {
+    site: String,
+    bot_email: String,
+    api_key: String,
+}
+
+impl ZulipApi {
+    pub fn new(tokens: &ZulipTokens) -> Self {
+        ZulipApi {
+            site: tokens.site.trim_end_matches('/').to_string(),
+            bot_email: tokens.bot_email.clone(),
+            api_key: tokens.api_key.clone(),
+        }
+    }
+
+    pub fn send_stream_message(&self, stream: &str, topic: &str, content: &str) -> Fallible<()> {
+        let response =
+            utils::http::prepare_sync(Method::POST, &format!("{}/api/v1/messages", self.site))
+                .basic_auth(&self.bot_email, Some(&self.api_key))
+                .form(&[
+                    ("type", "stream"),
+                    ("to", stream),
+                    ("topic", topic),
+                    ("content", content),
+                ])
+                .send()?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(ZulipError::RequestFailed(status, response.text()?).into())
+        }
+    }
+}
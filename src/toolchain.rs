@@ -5,6 +5,9 @@ use rustwide::Toolchain as RustwideToolchain;
 use std::fmt;
 use std::str::FromStr;
 
+/// Target of the agents, which the crates are built for unless the toolchain specifies another one.
+pub(crate) const HOST_TARGET: &str = "x86_64-unknown-linux-gnu";
+
 #[cfg(test)]
 lazy_static! {
     /// This toolchain is used during internal tests, and must be different than TEST_TOOLCHAIN
@@ -14,6 +17,7 @@ lazy_static! {
         rustflags: None,
         rustdocflags: None,
         cargoflags: None,
+        build_std: None,
         ci_try: false,
         patches: Vec::new(),
     };
@@ -25,6 +29,7 @@ lazy_static! {
         rustflags: None,
         rustdocflags: None,
         cargoflags: None,
+        build_std: None,
         ci_try: false,
         patches: Vec::new(),
     };
@@ -37,6 +42,8 @@ pub struct Toolchain {
     pub rustflags: Option<String>,
     pub rustdocflags: Option<String>,
     pub cargoflags: Option<String>,
+    /// Crates of the standard library rebuilt with `-Zbuild-std`, like `std` or `core,alloc`.
+    pub build_std: Option<String>,
     pub ci_try: bool,
     pub patches: Vec<CratePatch>,
 }
@@ -47,6 +54,38 @@ impl Toolchain {
 
         encode(&self.to_string(), &utils::FILENAME_ENCODE_SET).to_string()
     }
+
+    /// Whether unstable cargo and rustc flags can be used with this toolchain.
+    pub fn is_nightly(&self) -> bool {
+        if let Some(dist) = self.source.as_dist() {
+            dist.name().starts_with("nightly")
+        } else {
+            // CI artifacts are built from the master branch, on the nightly channel
+            self.source.as_ci().is_some()
+        }
+    }
+
+    /// Whether the binaries built with this toolchain can be executed by the agents, which run
+    /// on x86_64 Linux. Crates built for other targets can only be checked or built.
+    pub fn can_run_binaries(&self) -> bool {
+        match self.target {
+            Some(ref target) => {
+                let arch = target.split('-').next().unwrap_or_default();
+                ["x86_64", "i686", "i586"].contains(&arch) && target.contains("-linux-")
+            }
+            None => true,
+        }
+    }
+
+    /// Target passed explicitly to cargo, if any. `-Zbuild-std` requires an explicit target, so
+    /// the host one is used when the toolchain doesn't specify it.
+    pub fn cargo_target(&self) -> Option<&str> {
+        match (&self.target, &self.build_std) {
+            (Some(target), _) => Some(target),
+            (None, Some(_)) => Some(HOST_TARGET),
+            (None, None) => None,
+        }
+    }
 }
 
 impl std::ops::Deref for Toolchain {
@@ -87,6 +126,10 @@ impl fmt::Display for Toolchain {
             write!(f, "+cargoflags={flag}")?;
         }
 
+        if let Some(ref crates) = self.build_std {
+            write!(f, "+build-std={crates}")?;
+        }
+
         for patch in self.patches.iter() {
             write!(f, "+patch={patch}")?;
         }
@@ -148,6 +191,7 @@ impl FromStr for Toolchain {
         let mut rustflags = None;
         let mut rustdocflags = None;
         let mut cargoflags = None;
+        let mut build_std = None;
         let mut patches: Vec<CratePatch> = vec![];
         let mut target = None;
         for part in parts {
@@ -163,6 +207,7 @@ impl FromStr for Toolchain {
                     "rustflags" => rustflags = Some(value),
                     "rustdocflags" => rustdocflags = Some(value),
                     "cargoflags" => cargoflags = Some(value),
+                    "build-std" => build_std = Some(value),
                     "patch" => patches.push(value.parse()?),
                     "target" => target = Some(value),
                     unknown => return Err(ToolchainParseError::InvalidFlag(unknown.to_string())),
@@ -178,6 +223,7 @@ impl FromStr for Toolchain {
             rustflags,
             rustdocflags,
             cargoflags,
+            build_std,
             ci_try,
             patches,
         })
@@ -215,9 +261,50 @@ impl fmt::Display for CratePatch {
     }
 }
 
+/// Exact compiler of a toolchain, resolved when the experiment starts since channels like `beta`
+/// point to different compilers over time.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RustcVersion {
+    /// First line of `rustc -vV`, like `rustc 1.76.0-beta.1 (0e09125c6 2023-12-21)`.
+    pub version: String,
+    pub commit_hash: Option<String>,
+}
+
+impl RustcVersion {
+    /// Parse the output of `rustc -vV`.
+    pub fn from_verbose_output(lines: &[String]) -> Option<Self> {
+        let version = lines.first()?.trim();
+        if !version.starts_with("rustc ") {
+            return None;
+        }
+        let commit_hash = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("commit-hash: "))
+            .map(|hash| hash.trim())
+            .find(|hash| *hash != "unknown")
+            .map(String::from);
+
+        Some(RustcVersion {
+            version: version.to_string(),
+            commit_hash,
+        })
+    }
+
+    /// Release number of the version, like `1.76.0-beta.1`.
+    pub fn release(&self) -> Option<&str> {
+        self.version.split_whitespace().nth(1)
+    }
+}
+
+impl fmt::Display for RustcVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.version)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{CratePatch, Toolchain};
+    use super::{CratePatch, RustcVersion, Toolchain};
     use rustwide::Toolchain as RustwideToolchain;
     use std::str::FromStr;
 
@@ -233,6 +320,7 @@ mod tests {
                         rustflags: None,
                         rustdocflags: None,
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: Vec::new(),
                     });
@@ -244,6 +332,7 @@ mod tests {
                         rustflags: None,
                         rustdocflags: None,
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: Vec::new(),
                     });
@@ -255,6 +344,7 @@ mod tests {
                         rustflags: Some("foo bar".to_string()),
                         rustdocflags: None,
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: Vec::new(),
                     });
@@ -266,6 +356,7 @@ mod tests {
                         rustflags: None,
                         rustdocflags: Some("-Zunstable-options -wjson".to_string()),
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: Vec::new(),
                     });
@@ -277,6 +368,19 @@ mod tests {
                         rustflags: None,
                         rustdocflags: None,
                         cargoflags: Some("foo bar".to_string()),
+                        build_std: None,
+                        ci_try: $ci_try,
+                        patches: Vec::new(),
+                    });
+
+                    // Test parsing with build-std
+                    test_from_str!(concat!($str, "+build-std=core,alloc") => Toolchain {
+                        source: $source,
+                        target: None,
+                        rustflags: None,
+                        rustdocflags: None,
+                        cargoflags: None,
+                        build_std: Some("core,alloc".to_string()),
                         ci_try: $ci_try,
                         patches: Vec::new(),
                     });
@@ -288,6 +392,7 @@ mod tests {
                         rustflags: None,
                         rustdocflags: None,
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: vec![CratePatch {
                             name: "example".to_string(),
@@ -303,6 +408,7 @@ mod tests {
                         rustflags: Some("foo bar".to_string()),
                         rustdocflags: None,
                         cargoflags: None,
+                        build_std: None,
                         ci_try: $ci_try,
                         patches: vec![CratePatch {
                             name: "example".to_string(),
@@ -359,6 +465,59 @@ mod tests {
         assert!(Toolchain::from_str("stable+donotusethisflag=ever").is_err());
         assert!(Toolchain::from_str("stable+patch=").is_err());
         assert!(Toolchain::from_str("try#1234+target=").is_err());
+        assert!(Toolchain::from_str("nightly+build-std").is_err());
         assert!(Toolchain::from_str("0000000000000000000000000000000000000000").is_err());
     }
+    #[test]
+    fn test_cargo_target() {
+        let tc = |name: &str| Toolchain::from_str(name).unwrap();
+
+        assert_eq!(tc("nightly").cargo_target(), None);
+        assert_eq!(
+            tc("nightly+target=wasm32-unknown-unknown").cargo_target(),
+            Some("wasm32-unknown-unknown")
+        );
+        assert_eq!(
+            tc("nightly+build-std=std").cargo_target(),
+            Some("x86_64-unknown-linux-gnu")
+        );
+        assert_eq!(
+            tc("nightly+target=i686-unknown-linux-musl+build-std=std").cargo_target(),
+            Some("i686-unknown-linux-musl")
+        );
+    }
+
+    #[test]
+    fn test_rustc_version_from_verbose_output() {
+        let lines = |output: &str| output.lines().map(String::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            RustcVersion::from_verbose_output(&lines(
+                "rustc 1.76.0-beta.1 (0e09125c6 2023-12-21)\n\
+                 binary: rustc\n\
+                 commit-hash: 0e09125c6c3c2fd70d7de961bcf0e51575235fad\n\
+                 commit-date: 2023-12-21\n\
+                 host: x86_64-unknown-linux-gnu\n\
+                 release: 1.76.0-beta.1\n\
+                 LLVM version: 17.0.6"
+            )),
+            Some(RustcVersion {
+                version: "rustc 1.76.0-beta.1 (0e09125c6 2023-12-21)".into(),
+                commit_hash: Some("0e09125c6c3c2fd70d7de961bcf0e51575235fad".into()),
+            })
+        );
+        assert_eq!(
+            RustcVersion::from_verbose_output(&lines(
+                "rustc 1.77.0-dev\nbinary: rustc\ncommit-hash: unknown"
+            )),
+            Some(RustcVersion {
+                version: "rustc 1.77.0-dev".into(),
+                commit_hash: None,
+            })
+        );
+        assert!(
+            RustcVersion::from_verbose_output(&lines("error: toolchain not installed")).is_none()
+        );
+        assert!(RustcVersion::from_verbose_output(&[]).is_none());
+    }
 }
@@ -1,6 +1,8 @@
 use crate::prelude::*;
 use std::path::Path;
+use std::time::SystemTime;
 use systemstat::{Filesystem, Platform, System};
+use walkdir::WalkDir;
 
 pub(crate) struct DiskUsage {
     mount_point: String,
@@ -51,3 +53,58 @@ fn current_mount() -> Fallible<Filesystem> {
     }
     found.ok_or_else(|| anyhow!("failed to find the current mount"))
 }
+
+/// Total size of the files inside a directory, or zero if the directory doesn't exist.
+pub(crate) fn dir_size(path: &Path) -> Fallible<u64> {
+    size_of_files(path, None)
+}
+
+/// Total size of the files inside a directory modified since the provided time, or zero if the
+/// directory doesn't exist.
+pub(crate) fn written_since(path: &Path, since: SystemTime) -> Fallible<u64> {
+    size_of_files(path, Some(since))
+}
+
+fn size_of_files(path: &Path, since: Option<SystemTime>) -> Fallible<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let mut size = 0;
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let metadata = entry.metadata()?;
+            if since.is_none_or(|since| metadata.modified().is_ok_and(|m| m >= since)) {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dir_size, written_since};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_dir_size() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(dir_size(&dir.path().join("missing")).unwrap(), 0);
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a"), [0; 10]).unwrap();
+        std::fs::write(dir.path().join("sub").join("b"), [0; 32]).unwrap();
+        assert_eq!(dir_size(dir.path()).unwrap(), 42);
+
+        assert_eq!(
+            written_since(dir.path(), SystemTime::now() - Duration::from_secs(60)).unwrap(),
+            42
+        );
+        assert_eq!(
+            written_since(dir.path(), SystemTime::now() + Duration::from_secs(60)).unwrap(),
+            0
+        );
+    }
+}
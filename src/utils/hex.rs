@@ -42,9 +42,13 @@ pub(crate) fn from_hex(input: &str) -> Result<Vec<u8>, HexError> {
     }
 }
 
+pub(crate) fn to_hex(input: &[u8]) -> String {
+    input.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{from_hex, HexError};
+    use super::{from_hex, to_hex, HexError};
 
     #[test]
     fn test_from_hex() {
@@ -60,4 +64,10 @@ mod tests {
         // Invalid length
         assert_eq!(from_hex("000").unwrap_err(), HexError::InvalidLength);
     }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x01, 0x02, 0x10, 0xFF]), "00010210ff");
+        assert_eq!(from_hex(&to_hex(b"crater")).unwrap(), b"crater");
+    }
 }
@@ -1,7 +1,10 @@
 use crate::prelude::*;
 use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
 use reqwest::redirect::Policy;
-use reqwest::{header::USER_AGENT, Method, StatusCode};
+use reqwest::{
+    header::{IF_NONE_MATCH, USER_AGENT},
+    Method, StatusCode,
+};
 
 const MAX_REDIRECTS: usize = 4;
 
@@ -29,6 +32,26 @@ pub(crate) fn prepare_sync(method: Method, url: &str) -> RequestBuilder {
         .header(USER_AGENT, crate::USER_AGENT.clone())
 }
 
+/// Send a conditional request, returning `None` if the resource still has the `etag` it had when
+/// it was last fetched.
+pub(crate) fn get_sync_if_changed(url: &str, etag: Option<&str>) -> Fallible<Option<Response>> {
+    let mut req = prepare_sync(Method::GET, url);
+    if let Some(etag) = etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    let resp = req.send()?;
+
+    match resp.status() {
+        StatusCode::OK => Ok(Some(resp)),
+        StatusCode::NOT_MODIFIED => Ok(None),
+        status => Err(InvalidStatusCode {
+            url: url.to_string(),
+            status,
+        }
+        .into()),
+    }
+}
+
 pub(crate) fn get_sync(url: &str) -> Fallible<Response> {
     let resp = prepare_sync(Method::GET, url).send()?;
 
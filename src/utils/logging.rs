@@ -0,0 +1,305 @@
+//! Logging of the crater binaries. Events are recorded with `tracing`, and the spans entered by
+//! the agents and the server carry the experiment, crate and toolchain being processed, so that
+//! the logs of the whole fleet can be filtered by crate. Setting `CRATER_LOG_FORMAT=json` outputs
+//! one JSON object per event, including the fields of its spans.
+//!
+//! The logs of the builds are captured by rustwide through the `log` crate: the events of crater
+//! are forwarded to it, while the records of rustwide and of the other dependencies are forwarded
+//! to `tracing`, to be output in the context of the current spans. Rustwide only keeps the start
+//! of the logs, so their end is captured separately by [`LogTail`].
+
+use log::{Level, Log, Metadata, Record};
+use rustwide::logging::LogStorage;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing_log::LogTracer;
+use tracing_subscriber::EnvFilter;
+
+const FORMAT_ENV: &str = "CRATER_LOG_FORMAT";
+
+thread_local! {
+    /// Tails of the build logs being captured by this thread.
+    static TAILS: RefCell<Vec<LogTail>> = RefCell::new(Vec::new());
+}
+
+/// Forwards the records of the dependencies to `tracing`. The records of the crates using
+/// `tracing` already reached the subscriber, and forwarding them again would duplicate them.
+struct DependenciesLogger {
+    tracer: LogTracer,
+    crates: Vec<String>,
+}
+
+impl DependenciesLogger {
+    fn is_forwarded(&self, target: &str) -> bool {
+        !self
+            .crates
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once("tracing"))
+            .any(|name| {
+                target == name
+                    || target
+                        .strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+    }
+}
+
+impl Log for DependenciesLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.is_forwarded(metadata.target()) && self.tracer.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        TAILS.with(|tails| {
+            for tail in tails.borrow().iter() {
+                tail.record(record);
+            }
+        });
+        if self.is_forwarded(record.target()) {
+            self.tracer.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.tracer.flush();
+    }
+}
+
+/// Install the global logger, showing the events of the given crates and of rustwide at the
+/// info level unless `RUST_LOG` says otherwise.
+pub fn init(crates: &[&str]) {
+    let mut directives = crates
+        .iter()
+        .chain(std::iter::once(&"rustwide"))
+        .map(|name| format!("{name}=info"))
+        .collect::<Vec<_>>();
+    // Appended last, to override the defaults
+    if let Ok(content) = std::env::var("RUST_LOG") {
+        directives.push(content);
+    }
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(directives.join(",")));
+
+    let result = match std::env::var(FORMAT_ENV).as_deref() {
+        Ok("json") => tracing::subscriber::set_global_default(
+            builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .finish(),
+        ),
+        _ => tracing::subscriber::set_global_default(builder.finish()),
+    };
+    result.expect("the logger was already initialized");
+
+    rustwide::logging::init_with(DependenciesLogger {
+        tracer: LogTracer::new(),
+        crates: crates.iter().map(|name| name.to_string()).collect(),
+    });
+}
+
+#[derive(Clone, Default)]
+struct TailState {
+    head_lines: usize,
+    head_size: usize,
+    head_full: bool,
+    lines: VecDeque<String>,
+    size: usize,
+    dropped: usize,
+}
+
+/// End of a build log, kept in a ring buffer once the start of the log stored by rustwide reached
+/// its limits. The records are counted like rustwide does to know when its storage is full.
+#[derive(Clone)]
+pub(crate) struct LogTail {
+    head_max_size: usize,
+    head_max_lines: usize,
+    max_size: usize,
+    max_lines: usize,
+    state: Arc<Mutex<TailState>>,
+}
+
+impl LogTail {
+    pub(crate) fn new(
+        head_max_size: usize,
+        head_max_lines: usize,
+        max_size: usize,
+        max_lines: usize,
+    ) -> Self {
+        LogTail {
+            head_max_size,
+            head_max_lines,
+            max_size,
+            max_lines,
+            state: Arc::default(),
+        }
+    }
+
+    /// Copy of the tail, recording the following lines independently of this one.
+    pub(crate) fn duplicate(&self) -> Self {
+        LogTail {
+            state: Arc::new(Mutex::new(self.state.lock().unwrap().clone())),
+            ..*self
+        }
+    }
+
+    /// Record the end of the logs emitted by this thread while `f` runs.
+    pub(crate) fn capture<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Guard;
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                TAILS.with(|tails| tails.borrow_mut().pop());
+            }
+        }
+
+        TAILS.with(|tails| tails.borrow_mut().push(self.clone()));
+        let _guard = Guard;
+        f()
+    }
+
+    fn record(&self, record: &Record) {
+        if record.level() > Level::Info {
+            return;
+        }
+        let message = record.args().to_string();
+        let mut state = self.state.lock().unwrap();
+        if !state.head_full {
+            if state.head_lines < self.head_max_lines
+                && state.head_size + message.len() < self.head_max_size
+            {
+                state.head_lines += 1;
+                state.head_size += message.len();
+                return;
+            }
+            state.head_full = true;
+        }
+
+        let line = format!("[{}] {}", record.level(), message);
+        state.size += line.len() + 1;
+        state.lines.push_back(line);
+        while state.size > self.max_size || state.lines.len() > self.max_lines {
+            let Some(dropped) = state.lines.pop_front() else {
+                break;
+            };
+            state.size -= dropped.len() + 1;
+            state.dropped += 1;
+        }
+    }
+}
+
+impl fmt::Display for LogTail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        if state.dropped > 0 {
+            writeln!(f, "[... {} lines truncated ...]", state.dropped)?;
+        }
+        for line in &state.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Log of a build, made of its start stored by rustwide and optionally of its end.
+pub(crate) struct BuildLog {
+    storage: LogStorage,
+    tail: Option<LogTail>,
+}
+
+impl BuildLog {
+    pub(crate) fn new(storage: LogStorage, tail: Option<LogTail>) -> Self {
+        BuildLog { storage, tail }
+    }
+
+    pub(crate) fn duplicate(&self) -> Self {
+        BuildLog {
+            storage: self.storage.duplicate(),
+            tail: self.tail.as_ref().map(LogTail::duplicate),
+        }
+    }
+
+    /// Record the logs emitted by this thread while `f` runs.
+    pub(crate) fn capture<R>(&self, f: impl FnOnce() -> R) -> R {
+        rustwide::logging::capture(&self.storage, || match &self.tail {
+            Some(tail) => tail.capture(f),
+            None => f(),
+        })
+    }
+}
+
+impl fmt::Display for BuildLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.storage)?;
+        if let Some(tail) = &self.tail {
+            write!(f, "{tail}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependenciesLogger, LogTail};
+    use log::{Level, Log, Record};
+    use tracing_log::LogTracer;
+
+    #[test]
+    fn test_is_forwarded() {
+        let logger = DependenciesLogger {
+            tracer: LogTracer::new(),
+            crates: vec!["crater".into()],
+        };
+        assert!(logger.is_forwarded("rustwide::cmd"));
+        assert!(logger.is_forwarded("crater_dependency"));
+        assert!(!logger.is_forwarded("crater"));
+        assert!(!logger.is_forwarded("crater::runner::worker"));
+        assert!(!logger.is_forwarded("tracing::span"));
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let logger = DependenciesLogger {
+            tracer: LogTracer::new(),
+            crates: vec!["crater".into()],
+        };
+        let tail = LogTail::new(100, 2, 1000, 2);
+        let log = |level, i| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("line {i}"))
+                    .level(level)
+                    .target("crater")
+                    .build(),
+            )
+        };
+
+        // Only the lines emitted while capturing are recorded
+        log(Level::Info, 0);
+        tail.capture(|| {
+            for i in 1..=6 {
+                log(Level::Info, i);
+                log(Level::Debug, i);
+            }
+        });
+        log(Level::Info, 7);
+        assert_eq!(
+            tail.to_string(),
+            "[... 2 lines truncated ...]\n[INFO] line 5\n[INFO] line 6\n"
+        );
+
+        // Nothing is recorded until the start of the log is full
+        let tail = LogTail::new(100, 10, 1000, 2);
+        tail.capture(|| log(Level::Info, 1));
+        assert_eq!(tail.to_string(), "");
+
+        // The duplicated tails are independent
+        let tail = LogTail::new(100, 1, 1000, 10);
+        tail.capture(|| log(Level::Info, 1));
+        let duplicate = tail.duplicate();
+        duplicate.capture(|| log(Level::Warn, 2));
+        assert_eq!(tail.to_string(), "");
+        assert_eq!(duplicate.to_string(), "[WARN] line 2\n");
+    }
+}
@@ -1,7 +1,8 @@
 macro_rules! string_enum {
-    ($vis:vis enum $name:ident { $($item:ident => $str:expr,)* }) => {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($item:ident => $str:expr,)* }) => {
         #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
         #[serde(try_from = "String", into = "String")]
+        $(#[$meta])*
         $vis enum $name {
             $($item,)*
         }
@@ -5,6 +5,7 @@ use std::str::FromStr;
 
 pub(crate) mod hex;
 pub(crate) mod http;
+pub mod logging;
 #[macro_use]
 mod macros;
 pub(crate) mod disk_usage;
@@ -27,11 +28,18 @@ pub(crate) const FILENAME_ENCODE_SET: AsciiSet = CONTROLS
     .add(b'?')
     .add(b'*');
 
-pub fn report_panic(e: &dyn Any) {
+/// Message of a panic payload, when the panic was raised with one.
+pub fn panic_message(e: &dyn Any) -> Option<&str> {
     if let Some(e) = e.downcast_ref::<String>() {
-        error!("panicked: {}", e)
-    } else if let Some(e) = e.downcast_ref::<&'static str>() {
-        error!("panicked: {}", e)
+        Some(e)
+    } else {
+        e.downcast_ref::<&'static str>().copied()
+    }
+}
+
+pub fn report_panic(e: &dyn Any) {
+    if let Some(message) = panic_message(e) {
+        error!("panicked: {}", message)
     } else {
         error!("panicked")
     }
@@ -67,3 +75,20 @@ fn is_backtrace_runtime_enabled() -> bool {
         .and_then(|s| i32::from_str(&s).ok())
         .is_some_and(|val| val != 0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::panic_message;
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("static message")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), Some("static message"));
+
+        let payload = std::panic::catch_unwind(|| panic!("formatted {}", 42)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), Some("formatted 42"));
+
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), None);
+    }
+}
@@ -1,5 +1,5 @@
-use log::warn;
 use std::path::{Component, Path, PathBuf, Prefix, PrefixComponent};
+use tracing::warn;
 
 pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     let mut p = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
@@ -41,9 +41,51 @@ pub(crate) fn split_quoted(input: &str) -> Result<Vec<String>, SplitQuotedError>
     }
 }
 
+/// Number of single character insertions, deletions or substitutions turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Find the candidate closest to the input, if it's close enough to likely be a typo of it.
+pub(crate) fn closest_match<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (input.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(input, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::split_quoted;
+    use super::{closest_match, edit_distance, split_quoted};
+
+    #[test]
+    fn test_closest_match() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("cap_lints", "cap-lints"), 1);
+
+        let keys = ["name", "cap-lints", "crates", "mode"];
+        assert_eq!(closest_match("cap_lints", keys), Some("cap-lints"));
+        assert_eq!(closest_match("crate", keys), Some("crates"));
+        assert_eq!(closest_match("nmae", keys), None);
+        assert_eq!(closest_match("toolchain", keys), None);
+    }
 
     #[test]
     fn test_split_quoted() {
@@ -50,10 +50,10 @@ fn expand_file_names(input: &str, add: &str) -> String {
 }
 
 trait Compare {
-    fn file_names(&self) -> Vec<String>;
+    fn file_names(&self, file_dir: &Path) -> Vec<String>;
     fn format(&self, input: Vec<u8>) -> Vec<u8>;
     fn compare(&self, ex_dir: &Path, file_dir: &Path) -> bool {
-        let file_names = self.file_names();
+        let file_names = self.file_names(file_dir);
         let mut failed = false;
         for file in &file_names {
             let actual_file = ex_dir.join(expand_file_names(file, ".actual"));
@@ -103,14 +103,32 @@ enum Reports {
 }
 
 impl Compare for Reports {
-    fn file_names(&self) -> Vec<String> {
+    fn file_names(&self, file_dir: &Path) -> Vec<String> {
         match *self {
             Self::Raw => vec!["results.json".into()],
-            Self::HTMLContext => vec![
-                "index.html.context.json".into(),
-                "downloads.html.context.json".into(),
-                "full.html.context.json".into(),
-            ],
+            Self::HTMLContext => {
+                let mut files = vec![
+                    "index.html.context.json".into(),
+                    "downloads.html.context.json".into(),
+                ];
+                // Every category linked from the landing page has its own page
+                let index: Value = serde_json::from_slice(
+                    &::std::fs::read(file_dir.join("index.html.context.json"))
+                        .expect("failed to read index.html.context.json"),
+                )
+                .expect("invalid json report");
+                for link in ["summary", "others"]
+                    .iter()
+                    .flat_map(|list| index[list].as_array().expect("invalid json report"))
+                {
+                    let url = link["url"].as_str().expect("invalid json report");
+                    files.push(format!("{url}.context.json"));
+                }
+                if index["excluded"].as_u64() != Some(0) {
+                    files.push("excluded.html.context.json".into());
+                }
+                files
+            }
             Self::MarkdownContext => vec!["markdown.md.context.json".into()],
         }
     }
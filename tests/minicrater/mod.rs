@@ -55,6 +55,13 @@ minicrater! {
         ..Default::default()
     },
 
+    #[cfg(target_os = "linux")] // The sandboxes are isolated with iptables
+    network_allowlist {
+        ex: "network-allowlist",
+        crate_select: "demo",
+        ..Default::default()
+    },
+
     #[cfg(not(windows))] // `State.OOMKilled` is not set on Windows
     resource_exhaustion {
         ex: "resource-exhaustion",